@@ -0,0 +1,45 @@
+//! Transcribes a WAV file while boosting the tokens of a keyword through a logits processor.
+//!
+//! ```sh
+//! cargo run --release --example keyword_boost -- \
+//!     model.safetensors tokenizer.json config.json audio.wav "Candle" 2.0
+//! ```
+
+use candle_whisper::logic::{DecodeOptions, Decoder, LogitsContext};
+use std::path::Path;
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [weights, tokenizer, config, wav, keyword, rest @ ..] = &args[..] else {
+        anyhow::bail!("usage: keyword_boost WEIGHTS TOKENIZER CONFIG WAV KEYWORD [BOOST]");
+    };
+    let boost: f32 = rest.first().map_or(Ok(2.), |boost| boost.parse())?;
+    let mut decoder = Decoder::load_from_paths(
+        Path::new(weights),
+        Path::new(tokenizer),
+        Path::new(config),
+        None,
+        DecodeOptions::default(),
+    )?;
+    // Words are preceded by a space in the whisper vocabulary.
+    let keyword_tokens = decoder.encode_text(&format!(" {keyword}"))?;
+    println!("boosting {keyword_tokens:?} by {boost}");
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            // Boost the start of the keyword, or its next token once it has started.
+            let started = (1..keyword_tokens.len())
+                .rev()
+                .find(|&n| context.tokens.ends_with(&keyword_tokens[..n]))
+                .unwrap_or(0);
+            if let Some(logit) = logits.get_mut(keyword_tokens[started] as usize) {
+                *logit += boost;
+            }
+        },
+    )));
+    let output = decoder.convert_and_run(&std::fs::read(wav)?)?;
+    for segment in output.segments.iter() {
+        let end = segment.start + segment.duration;
+        println!("[{:.2} - {end:.2}] {}", segment.start, segment.dr.text);
+    }
+    Ok(())
+}
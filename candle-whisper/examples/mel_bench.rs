@@ -0,0 +1,38 @@
+//! Times the log-mel spectrogram of a minute of audio and the FFT of sizes that are not powers
+//! of two.
+//!
+//! ```sh
+//! cargo run --release --example mel_bench
+//! ```
+
+use candle_whisper::{
+    audio::{self, fft, MelNormalization},
+    fixtures::tiny_config,
+    logic::m,
+};
+use std::time::Instant;
+
+fn main() -> anyhow::Result<()> {
+    let config = tiny_config();
+    let pcm: Vec<f32> = (0..60 * m::SAMPLE_RATE)
+        .map(|i| (i as f32 * 440. * std::f32::consts::TAU / m::SAMPLE_RATE as f32).sin())
+        .collect();
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, config.num_mel_bins);
+    let start = Instant::now();
+    audio::pcm_to_mel(&config, &pcm, &filters, MelNormalization::GlobalMax)?;
+    println!("60s log-mel: {:?}", start.elapsed());
+    let start = Instant::now();
+    audio::pcm_to_mel_precise(&config, &pcm, &filters, MelNormalization::GlobalMax)?;
+    println!("60s log-mel in f64: {:?}", start.elapsed());
+
+    for n in [400, 401, 509, 512, 1000, 1009] {
+        let frame: Vec<f32> = pcm[..n].to_vec();
+        let iterations = 1000;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            std::hint::black_box(fft(std::hint::black_box(&frame)));
+        }
+        println!("fft({n}): {:?}", start.elapsed() / iterations);
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+#ifndef CANDLE_WHISPER_H
+#define CANDLE_WHISPER_H
+
+/* Generated with cbindgen:0.26.0 */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The call succeeded.
+#define WHISPER_OK 0
+
+// The decoder failed, the error JSON is the one of the `WhisperError`.
+#define WHISPER_ERROR 1
+
+// A pointer is null or a string is not UTF-8, the error code is `invalid_argument`.
+#define WHISPER_INVALID_ARGUMENT 2
+
+// A panic was caught, the error code is `panic`.
+#define WHISPER_PANIC 3
+
+typedef struct Decoder Decoder;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Loads a decoder from the JSON of a `ModelData`, returns null on failure.
+//
+// # Safety
+//
+// `model_data` must be valid for reads of `len` bytes and `out_err` must be null or valid for
+// writes.
+Decoder *whisper_decoder_new(const uint8_t *model_data, size_t len, char **out_err);
+
+// Transcribes a 16kHz WAV file, storing the JSON of the `TranscriptionOutput` in `out_json`.
+//
+// # Safety
+//
+// `decoder` must come from [`whisper_decoder_new`], `wav` must be valid for reads of `len`
+// bytes and `out_json` and `out_err` must be null or valid for writes.
+int32_t whisper_decoder_transcribe_wav(Decoder *decoder,
+                                       const uint8_t *wav,
+                                       size_t len,
+                                       char **out_json,
+                                       char **out_err);
+
+// Sets one of the `DecodeOptions` by the dot separated path of its JSON field, e.g.
+// `("temperatures", "[0.0, 0.2]")`, `("use_vad", "true")` or `("vad.min_rms", "0.01")`.
+//
+// # Safety
+//
+// `decoder` must come from [`whisper_decoder_new`], `key` and `value` must be NUL-terminated
+// strings and `out_err` must be null or valid for writes.
+int32_t whisper_decoder_set_option(Decoder *decoder,
+                                   const char *key,
+                                   const char *value,
+                                   char **out_err);
+
+// Frees a decoder, null is ignored.
+//
+// # Safety
+//
+// `decoder` must be null or come from [`whisper_decoder_new`] and not be used afterwards.
+void whisper_decoder_free(Decoder *decoder);
+
+// Frees a string returned by the other functions, null is ignored.
+//
+// # Safety
+//
+// `s` must be null or come from an `out_json` or `out_err` argument and not be used
+// afterwards.
+void whisper_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CANDLE_WHISPER_H */
@@ -0,0 +1,500 @@
+use crate::{logic::SpecialTokens, paragraphs::is_cjk};
+
+use anyhow::Error as E;
+use candle_core::{DType, IndexOp, Module, Result, Tensor};
+use candle_nn::{embedding, linear, linear_no_bias, Embedding, LayerNorm, Linear, VarBuilder};
+use candle_transformers::models::whisper::{self as m, Config};
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+/// Width of the median filter smoothing the attention weights along the time.
+const MEDIAN_FILTER_WIDTH: usize = 7;
+
+/// Time in seconds covered by one encoder output frame, also the step of the timestamp
+/// tokens.
+const FRAME_DURATION: f64 = (2 * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+
+/// Lowest weight of a token in the heuristic timings, so that unlikely tokens still take time.
+const MIN_TOKEN_WEIGHT: f64 = 0.1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WordTiming {
+    pub word: String,
+    /// Start time in seconds.
+    pub start: f64,
+    /// End time in seconds.
+    pub end: f64,
+    #[serde(default)]
+    pub timing_source: TimingSource,
+    /// Mean probability of the tokens of the word, `None` when the probabilities of the tokens
+    /// are not known, e.g. after a vote.
+    #[serde(default)]
+    pub probability: Option<f32>,
+}
+
+/// How the times of a word were obtained, from the most to the least precise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimingSource {
+    /// Dynamic time warping of the cross-attention weights.
+    #[default]
+    Attention,
+    /// The timestamp tokens around the word, which is alone between them.
+    TimestampTokens,
+    /// Estimated from the token counts and probabilities, see [`heuristic_word_timings`].
+    Heuristic,
+}
+
+fn layer_norm(size: usize, vb: VarBuilder) -> Result<LayerNorm> {
+    let weight = vb.get(size, "weight")?;
+    let bias = vb.get(size, "bias")?;
+    Ok(LayerNorm::new(weight, bias, 1e-5))
+}
+
+struct Attention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    out: Linear,
+    n_head: usize,
+}
+
+impl Attention {
+    fn load(n_state: usize, n_head: usize, vb: VarBuilder) -> Result<Self> {
+        Ok(Self {
+            query: linear(n_state, n_state, vb.pp("q_proj"))?,
+            key: linear_no_bias(n_state, n_state, vb.pp("k_proj"))?,
+            value: linear(n_state, n_state, vb.pp("v_proj"))?,
+            out: linear(n_state, n_state, vb.pp("out_proj"))?,
+            n_head,
+        })
+    }
+
+    fn reshape_head(&self, x: &Tensor) -> Result<Tensor> {
+        let (n_batch, n_ctx, n_state) = x.dims3()?;
+        let target_dims = &[n_batch, n_ctx, self.n_head, n_state / self.n_head];
+        x.reshape(target_dims)?.transpose(1, 2)
+    }
+
+    /// Output of the attention and its weights before the softmax, of shape
+    /// `(batch, heads, queries, keys)`.
+    fn forward(
+        &self,
+        x: &Tensor,
+        xa: Option<&Tensor>,
+        mask: Option<&Tensor>,
+    ) -> Result<(Tensor, Tensor)> {
+        let kv = xa.unwrap_or(x);
+        let q = self.query.forward(x)?;
+        let k = self.key.forward(kv)?;
+        let v = self.value.forward(kv)?;
+        let (_, n_ctx, n_state) = q.dims3()?;
+        let scale = ((n_state / self.n_head) as f64).powf(-0.25);
+        let q = (self.reshape_head(&q)? * scale)?;
+        let k = (self.reshape_head(&k)?.transpose(2, 3)? * scale)?;
+        let v = self.reshape_head(&v)?.contiguous()?;
+        let mut qk = q.matmul(&k)?;
+        if let Some(mask) = mask {
+            qk = qk.broadcast_add(&mask.i((0..n_ctx, 0..n_ctx))?)?
+        }
+        let w = candle_nn::ops::softmax_last_dim(&qk)?;
+        let wv = w.matmul(&v)?.transpose(1, 2)?.flatten_from(2)?;
+        Ok((self.out.forward(&wv)?, qk))
+    }
+}
+
+struct Block {
+    attn: Attention,
+    attn_ln: LayerNorm,
+    cross_attn: Attention,
+    cross_attn_ln: LayerNorm,
+    mlp_linear1: Linear,
+    mlp_linear2: Linear,
+    mlp_ln: LayerNorm,
+}
+
+impl Block {
+    fn load(n_state: usize, n_head: usize, vb: VarBuilder) -> Result<Self> {
+        let n_mlp = n_state * 4;
+        Ok(Self {
+            attn: Attention::load(n_state, n_head, vb.pp("self_attn"))?,
+            attn_ln: layer_norm(n_state, vb.pp("self_attn_layer_norm"))?,
+            cross_attn: Attention::load(n_state, n_head, vb.pp("encoder_attn"))?,
+            cross_attn_ln: layer_norm(n_state, vb.pp("encoder_attn_layer_norm"))?,
+            mlp_linear1: linear(n_state, n_mlp, vb.pp("fc1"))?,
+            mlp_linear2: linear(n_mlp, n_state, vb.pp("fc2"))?,
+            mlp_ln: layer_norm(n_state, vb.pp("final_layer_norm"))?,
+        })
+    }
+
+    /// Output of the block and the cross-attention weights before the softmax.
+    fn forward(&self, x: &Tensor, xa: &Tensor, mask: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (attn, _) = self
+            .attn
+            .forward(&self.attn_ln.forward(x)?, None, Some(mask))?;
+        let x = (x + attn)?;
+        let (cross_attn, qk) =
+            self.cross_attn
+                .forward(&self.cross_attn_ln.forward(&x)?, Some(xa), None)?;
+        let x = (&x + cross_attn)?;
+        let mlp = self.mlp_linear2.forward(
+            &self
+                .mlp_linear1
+                .forward(&self.mlp_ln.forward(&x)?)?
+                .gelu()?,
+        )?;
+        Ok(((x + mlp)?, qk))
+    }
+}
+
+/// Text decoder of the non-quantized model exposing the cross-attention weights, which the
+/// candle decoder keeps private. It is loaded from the same tensors as the model so that the
+/// weights are shared.
+pub struct AlignmentDecoder {
+    token_embedding: Embedding,
+    positional_embedding: Tensor,
+    blocks: Vec<Block>,
+    mask: Tensor,
+}
+
+impl AlignmentDecoder {
+    pub fn load(vb: VarBuilder, cfg: &Config) -> Result<Self> {
+        let n_state = cfg.d_model;
+        let n_head = cfg.decoder_attention_heads;
+        let n_ctx = cfg.max_target_positions;
+        let blocks = (0..cfg.decoder_layers)
+            .map(|i| Block::load(n_state, n_head, vb.pp(format!("layers.{i}"))))
+            .collect::<Result<Vec<_>>>()?;
+        let mask: Vec<_> = (0..n_ctx)
+            .flat_map(|i| (0..n_ctx).map(move |j| if j > i { f32::NEG_INFINITY } else { 0f32 }))
+            .collect();
+        let mask = Tensor::from_vec(mask, (n_ctx, n_ctx), vb.device())?.to_dtype(vb.dtype())?;
+        Ok(Self {
+            token_embedding: embedding(cfg.vocab_size, n_state, vb.pp("embed_tokens"))?,
+            positional_embedding: vb.get((n_ctx, n_state), "embed_positions.weight")?,
+            blocks,
+            mask,
+        })
+    }
+
+    /// Cross-attention weights before the softmax of the heads of the second half of the
+    /// layers, which openai/whisper uses as alignment heads when no model specific heads are
+    /// known, as a `(heads, tokens, audio frames)` f32 tensor.
+    pub fn cross_attention(&self, tokens: &[u32], audio_features: &Tensor) -> Result<Tensor> {
+        let tokens = Tensor::new(tokens, audio_features.device())?.unsqueeze(0)?;
+        let token_embedding = self.token_embedding.forward(&tokens)?;
+        let positional_embedding = self.positional_embedding.narrow(0, 0, tokens.dim(1)?)?;
+        let mut x = token_embedding.broadcast_add(&positional_embedding)?;
+        let mut weights = vec![];
+        for (i, block) in self.blocks.iter().enumerate() {
+            let (output, qk) = block.forward(&x, audio_features, &self.mask)?;
+            x = output;
+            if i >= self.blocks.len() / 2 {
+                weights.push(qk.i(0)?)
+            }
+        }
+        Tensor::cat(&weights, 0)?.to_dtype(DType::F32)
+    }
+
+    /// Times of the words of `tokens`, the decoded tokens of a window of `n_frames` mel
+    /// frames, relative to the start of the window, with their probabilities from `probs`
+    /// aligned with `tokens`.
+    ///
+    /// The cross-attention weights of the alignment heads are normalized over the tokens,
+    /// median filtered along the time and averaged over the heads, and a dynamic time warping
+    /// over this token by frame matrix gives the time each token starts at.
+    pub fn word_timings(
+        &self,
+        tokenizer: &Tokenizer,
+        special_tokens: &SpecialTokens,
+        tokens: &[u32],
+        probs: &[f32],
+        audio_features: &Tensor,
+        n_frames: usize,
+    ) -> anyhow::Result<Vec<WordTiming>> {
+        let eot = special_tokens.eot;
+        let text_tokens: Vec<u32> = tokens.iter().copied().filter(|t| *t < eot).collect();
+        if text_tokens.is_empty() {
+            return Ok(vec![]);
+        }
+        // The alignment is computed without the timestamp tokens, as openai/whisper does.
+        let mut sequence: Vec<u32> = tokens
+            .iter()
+            .copied()
+            .take_while(|t| *t > eot && *t < special_tokens.no_timestamps)
+            .collect();
+        sequence.push(special_tokens.no_timestamps);
+        let first_row = sequence.len() - 1;
+        sequence.extend_from_slice(&text_tokens);
+        sequence.push(eot);
+
+        let weights = self.cross_attention(&sequence, audio_features)?;
+        let (_, _, audio_frames) = weights.dims3()?;
+        let weights = weights.narrow(2, 0, usize::clamp(n_frames / 2, 1, audio_frames))?;
+        let weights = candle_nn::ops::softmax_last_dim(&weights)?;
+        let mean = weights.mean_keepdim(1)?;
+        let centered = weights.broadcast_sub(&mean)?;
+        let std = (centered.sqr()?.mean_keepdim(1)?.sqrt()? + 1e-10)?;
+        let weights = centered.broadcast_div(&std)?.to_vec3::<f32>()?;
+
+        // Rows of the no timestamps token and of the text tokens.
+        let rows = first_row..sequence.len() - 1;
+        let n_heads = weights.len() as f32;
+        let mut cost = vec![vec![0f32; weights[0][0].len()]; rows.len()];
+        for head in weights.iter() {
+            for (row, weights) in cost.iter_mut().zip(head[rows.clone()].iter()) {
+                let filtered = median_filter(weights, MEDIAN_FILTER_WIDTH);
+                for (c, w) in row.iter_mut().zip(filtered) {
+                    *c -= w / n_heads
+                }
+            }
+        }
+        let mut jump_times = vec![];
+        let mut previous_row = None;
+        for (row, frame) in dtw(&cost) {
+            if previous_row != Some(row) {
+                jump_times.push(frame as f64 * FRAME_DURATION);
+                previous_row = Some(row);
+            }
+        }
+
+        let text_probs = text_token_probs(tokens, probs, eot);
+        group_words(tokenizer, &text_tokens)?
+            .into_iter()
+            .map(|(word, tokens)| {
+                Ok(WordTiming {
+                    word,
+                    start: jump_times[tokens.start],
+                    end: jump_times[tokens.end],
+                    timing_source: TimingSource::Attention,
+                    probability: mean_probability(text_probs.as_deref(), &tokens),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Probabilities of the text tokens of `tokens`, `None` when `probs` is not aligned with them.
+fn text_token_probs(tokens: &[u32], probs: &[f32], eot: u32) -> Option<Vec<f32>> {
+    (probs.len() == tokens.len()).then(|| {
+        tokens
+            .iter()
+            .zip(probs)
+            .filter(|(token, _)| **token < eot)
+            .map(|(_, prob)| *prob)
+            .collect()
+    })
+}
+
+fn mean_probability(probs: Option<&[f32]>, range: &std::ops::Range<usize>) -> Option<f32> {
+    let probs = probs?.get(range.clone())?;
+    (!probs.is_empty()).then(|| probs.iter().sum::<f32>() / probs.len() as f32)
+}
+
+/// Longest run of tokens a character can be split over, the bytes of a UTF-8 character.
+const MAX_CHARACTER_TOKENS: usize = 4;
+
+/// The words of `text_tokens` with their token ranges, a word starting at each token that
+/// starts with a space. The CJK characters, written without spaces, are words of their own as
+/// far as the tokens allow, a token holding several of them making a single word.
+fn group_words(
+    tokenizer: &Tokenizer,
+    text_tokens: &[u32],
+) -> anyhow::Result<Vec<(String, std::ops::Range<usize>)>> {
+    let decode = |tokens: &[u32]| tokenizer.decode(tokens, false).map_err(E::msg);
+    // The shortest runs of tokens decoding to whole characters, the byte-level tokens splitting
+    // the CJK characters.
+    let mut units = vec![];
+    let mut start = 0;
+    for end in 1..=text_tokens.len() {
+        let text = decode(&text_tokens[start..end])?;
+        if !text.contains(char::REPLACEMENT_CHARACTER)
+            || end - start >= MAX_CHARACTER_TOKENS
+            || end == text_tokens.len()
+        {
+            units.push((start..end, text));
+            start = end;
+        }
+    }
+    let mut ranges: Vec<std::ops::Range<usize>> = vec![];
+    let mut previous = String::new();
+    for (range, text) in units {
+        let starts_word =
+            text.starts_with(' ') || text.starts_with(is_cjk) || previous.ends_with(is_cjk);
+        match ranges.last_mut() {
+            Some(last) if !starts_word => last.end = range.end,
+            _ => ranges.push(range),
+        }
+        previous = text;
+    }
+    ranges
+        .into_iter()
+        .map(|range| {
+            let word = tokenizer
+                .decode(&text_tokens[range.clone()], false)
+                .map_err(E::msg)?;
+            Ok((word, range))
+        })
+        .collect()
+}
+
+/// Text tokens between two timestamps, with their weights.
+#[derive(Debug, Default)]
+struct TokenSpan {
+    tokens: Vec<(u32, f64)>,
+    start: f64,
+    end: f64,
+    /// Whether the ends come from timestamp tokens rather than the edges of the window.
+    anchored_start: bool,
+    anchored_end: bool,
+}
+
+/// Times of the words of `tokens`, the decoded tokens of a window of `duration` seconds,
+/// relative to the start of the window, for models without cross-attention weights such as
+/// the quantized ones.
+///
+/// The time between consecutive timestamp tokens, or the edges of the window, is distributed
+/// over the text tokens in between proportionally to their probabilities in `probs`, aligned
+/// with `tokens`, or evenly when `probs` is not aligned. Timestamps going backwards or beyond
+/// the window are clamped, so that the words are in order and within the window.
+pub fn heuristic_word_timings(
+    tokenizer: &Tokenizer,
+    special_tokens: &SpecialTokens,
+    tokens: &[u32],
+    probs: &[f32],
+    duration: f64,
+) -> anyhow::Result<Vec<WordTiming>> {
+    let eot = special_tokens.eot;
+    let weight = |i: usize| match probs.len() == tokens.len() {
+        true => f64::max(probs[i] as f64, MIN_TOKEN_WEIGHT),
+        false => 1.,
+    };
+    // The text tokens are split in spans by the timestamp tokens.
+    let mut spans: Vec<TokenSpan> = vec![];
+    let mut span = TokenSpan::default();
+    for (i, &token) in tokens.iter().enumerate() {
+        if token < eot {
+            span.tokens.push((token, weight(i)));
+        } else if token >= special_tokens.timestamp_begin {
+            let time = (token - special_tokens.timestamp_begin) as f64 * FRAME_DURATION;
+            let end = time.clamp(span.start, f64::max(span.start, duration));
+            spans.push(TokenSpan {
+                end,
+                anchored_end: true,
+                ..std::mem::take(&mut span)
+            });
+            span = TokenSpan {
+                start: end,
+                anchored_start: true,
+                ..Default::default()
+            };
+        }
+    }
+    spans.push(TokenSpan {
+        end: f64::max(span.start, duration),
+        ..span
+    });
+
+    // Times of the text tokens, with the index of their span.
+    let mut text_tokens = vec![];
+    let mut times: Vec<(f64, f64, usize)> = vec![];
+    for (index, span) in spans.iter().enumerate() {
+        let total: f64 = span.tokens.iter().map(|(_, w)| w).sum();
+        let mut time = span.start;
+        for &(token, w) in span.tokens.iter() {
+            let next = f64::min(span.end, time + (span.end - span.start) * w / total);
+            text_tokens.push(token);
+            times.push((time, next, index));
+            time = next;
+        }
+    }
+    if text_tokens.is_empty() {
+        return Ok(vec![]);
+    }
+    let text_probs = text_token_probs(tokens, probs, eot);
+    group_words(tokenizer, &text_tokens)?
+        .into_iter()
+        .map(|(word, range)| {
+            let (start, _, index) = times[range.start];
+            let end = times[range.end - 1].1;
+            let span = &spans[index];
+            let alone = span.anchored_start
+                && span.anchored_end
+                && range.len() == span.tokens.len()
+                && times[range.end - 1].2 == index;
+            Ok(WordTiming {
+                word,
+                start,
+                end,
+                timing_source: match alone {
+                    true => TimingSource::TimestampTokens,
+                    false => TimingSource::Heuristic,
+                },
+                probability: mean_probability(text_probs.as_deref(), &range),
+            })
+        })
+        .collect()
+}
+
+/// Median filter of the given odd width with reflected edges, rows not longer than half the
+/// width are returned as is.
+pub fn median_filter(row: &[f32], width: usize) -> Vec<f32> {
+    let pad = width / 2;
+    let len = row.len();
+    if len <= pad {
+        return row.to_vec();
+    }
+    let mut window = Vec::with_capacity(width);
+    (0..len)
+        .map(|i| {
+            window.clear();
+            window.extend((i as isize - pad as isize..=(i + pad) as isize).map(|j| {
+                let j = j.unsigned_abs();
+                row[if j >= len { 2 * (len - 1) - j } else { j }]
+            }));
+            window.sort_by(f32::total_cmp);
+            window[pad]
+        })
+        .collect()
+}
+
+/// Monotonic path of minimal cost through a `(rows, columns)` cost matrix from its first to
+/// its last cell, moving one row, one column or both at each step.
+pub fn dtw(cost: &[Vec<f32>]) -> Vec<(usize, usize)> {
+    let n = cost.len();
+    let m = cost.first().map_or(0, |row| row.len());
+    if n == 0 || m == 0 {
+        return vec![];
+    }
+    let mut total = vec![vec![f32::INFINITY; m + 1]; n + 1];
+    // 0 for a diagonal step, 1 for a row step and 2 for a column step.
+    let mut trace = vec![vec![0u8; m + 1]; n + 1];
+    total[0][0] = 0.;
+    for j in 1..=m {
+        for i in 1..=n {
+            let steps = [total[i - 1][j - 1], total[i - 1][j], total[i][j - 1]];
+            let (step, best) = steps
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap();
+            total[i][j] = cost[i - 1][j - 1] + best;
+            trace[i][j] = step as u8;
+        }
+    }
+    let mut path = vec![];
+    let (mut i, mut j) = (n, m);
+    while i > 0 && j > 0 {
+        path.push((i - 1, j - 1));
+        match trace[i][j] {
+            0 => {
+                i -= 1;
+                j -= 1
+            }
+            1 => i -= 1,
+            _ => j -= 1,
+        }
+    }
+    path.reverse();
+    path
+}
@@ -1,4 +1,7 @@
-use crate::logic;
+use crate::{error::WhisperError, logic};
+
+use candle_core::{Device, Tensor};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 pub trait Float: num_traits::Float + num_traits::FloatConst + num_traits::NumAssign {}
 
@@ -30,64 +33,193 @@ fn dft<T: Float>(inp: &[T]) -> Vec<T> {
     out
 }
 
-fn fft<T: Float>(inp: &[T]) -> Vec<T> {
-    let n = inp.len();
-    let zero = T::zero();
-    if n == 1 {
-        return vec![inp[0], zero];
-    }
-    if n % 2 == 1 {
-        return dft(inp);
-    }
-    let mut out = vec![zero; n * 2];
+/// Sizes up to this that are not powers of two are transformed with the direct DFT.
+const MAX_DFT_SIZE: usize = 16;
 
-    let mut even = Vec::with_capacity(n / 2);
-    let mut odd = Vec::with_capacity(n / 2);
+/// Complex number as `(re, im)`.
+type Complex<T> = (T, T);
 
-    for (i, &inp) in inp.iter().enumerate() {
-        if i % 2 == 0 {
-            even.push(inp)
-        } else {
-            odd.push(inp);
+fn mul<T: Float>(a: Complex<T>, b: Complex<T>) -> Complex<T> {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+/// `exp(-i * angle)`.
+fn unit<T: Float>(angle: T) -> Complex<T> {
+    (angle.cos(), -angle.sin())
+}
+
+/// In-place radix-2 FFT of a power of two size, `twiddles` being `exp(-2iπk/n)` for `k < n/2`.
+fn radix2<T: Float>(data: &mut [Complex<T>], twiddles: &[Complex<T>], inverse: bool) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
         }
     }
+    let mut len = 2;
+    while len <= n {
+        let (half, stride) = (len / 2, n / len);
+        for block in data.chunks_exact_mut(len) {
+            let (lo, hi) = block.split_at_mut(half);
+            for (k, (a, b)) in lo.iter_mut().zip(hi.iter_mut()).enumerate() {
+                let (re, im) = twiddles[k * stride];
+                let t = mul(*b, (re, if inverse { -im } else { im }));
+                *b = (a.0 - t.0, a.1 - t.1);
+                *a = (a.0 + t.0, a.1 + t.1);
+            }
+        }
+        len <<= 1;
+    }
+}
 
-    let even_fft = fft(&even);
-    let odd_fft = fft(&odd);
+enum FftKind<T> {
+    Dft,
+    Radix2,
+    /// Bluestein's algorithm: the DFT is the convolution of the input multiplied by the `chirp`
+    /// with the conjugated chirp, computed with radix-2 FFTs of the zero padded sequences.
+    Bluestein {
+        /// `exp(-iπk²/n)`.
+        chirp: Vec<Complex<T>>,
+        /// FFT of the conjugated chirp, laid out for a circular convolution.
+        kernel: Vec<Complex<T>>,
+    },
+}
 
-    let two_pi = T::PI() + T::PI();
-    let n_t = T::from(n).unwrap();
-    for k in 0..n / 2 {
-        let k_t = T::from(k).unwrap();
-        let theta = two_pi * k_t / n_t;
-        let re = theta.cos();
-        let im = -theta.sin();
+/// FFT of real inputs of a given size, with the twiddle factors computed once. The powers of two
+/// use the radix-2 algorithm and the other sizes are zero padded to a power of two at least
+/// twice as large by Bluestein's algorithm, so every size runs in `O(n log n)` and yields the
+/// exact `n` bins.
+pub struct FftPlan<T> {
+    n: usize,
+    twiddles: Vec<Complex<T>>,
+    kind: FftKind<T>,
+}
 
-        let re_odd = odd_fft[2 * k];
-        let im_odd = odd_fft[2 * k + 1];
+impl<T: Float> FftPlan<T> {
+    pub fn new(n: usize) -> Self {
+        let pi = T::PI();
+        let twiddles = |m: usize| -> Vec<Complex<T>> {
+            let m_t = T::from(m).unwrap();
+            (0..m / 2)
+                .map(|k| unit((pi + pi) * T::from(k).unwrap() / m_t))
+                .collect()
+        };
+        if n.is_power_of_two() {
+            return Self {
+                n,
+                twiddles: twiddles(n),
+                kind: FftKind::Radix2,
+            };
+        }
+        if n <= MAX_DFT_SIZE {
+            return Self {
+                n,
+                twiddles: vec![],
+                kind: FftKind::Dft,
+            };
+        }
+        let m = (2 * n - 1).next_power_of_two();
+        let twiddles = twiddles(m);
+        let n_t = T::from(n).unwrap();
+        // k² is reduced modulo 2n, the period of the chirp, to keep the angles accurate.
+        let chirp: Vec<Complex<T>> = (0..n)
+            .map(|k| unit(pi * T::from(k * k % (2 * n)).unwrap() / n_t))
+            .collect();
+        let mut kernel = vec![(T::zero(), T::zero()); m];
+        for (k, &(re, im)) in chirp.iter().enumerate() {
+            kernel[k] = (re, -im);
+            kernel[(m - k) % m] = (re, -im);
+        }
+        radix2(&mut kernel, &twiddles, false);
+        Self {
+            n,
+            twiddles,
+            kind: FftKind::Bluestein { chirp, kernel },
+        }
+    }
 
-        out[2 * k] = even_fft[2 * k] + re * re_odd - im * im_odd;
-        out[2 * k + 1] = even_fft[2 * k + 1] + re * im_odd + im * re_odd;
+    /// DFT of `inp`, of the size of the plan, as interleaved real and imaginary parts.
+    pub fn transform(&self, inp: &[T]) -> Vec<T> {
+        let mut out = vec![];
+        self.transform_into(inp, &mut vec![], &mut out);
+        out
+    }
 
-        out[2 * (k + n / 2)] = even_fft[2 * k] - re * re_odd + im * im_odd;
-        out[2 * (k + n / 2) + 1] = even_fft[2 * k + 1] - re * im_odd - im * re_odd;
+    /// [`FftPlan::transform`] into `out`, with `scratch` as working buffer, neither being
+    /// reallocated once large enough.
+    fn transform_into(&self, inp: &[T], scratch: &mut Vec<Complex<T>>, out: &mut Vec<T>) {
+        assert_eq!(
+            inp.len(),
+            self.n,
+            "input of the wrong size for the FFT plan"
+        );
+        let zero = T::zero();
+        out.clear();
+        match &self.kind {
+            FftKind::Dft => out.extend(dft(inp)),
+            FftKind::Radix2 => {
+                scratch.clear();
+                scratch.extend(inp.iter().map(|&x| (x, zero)));
+                radix2(scratch, &self.twiddles, false);
+                out.extend(scratch.iter().flat_map(|&(re, im)| [re, im]));
+            }
+            FftKind::Bluestein { chirp, kernel } => {
+                let m = kernel.len();
+                scratch.clear();
+                scratch.resize(m, (zero, zero));
+                for ((d, &x), &(re, im)) in scratch.iter_mut().zip(inp).zip(chirp) {
+                    *d = (x * re, x * im);
+                }
+                radix2(scratch, &self.twiddles, false);
+                for (d, &k) in scratch.iter_mut().zip(kernel) {
+                    *d = mul(*d, k);
+                }
+                radix2(scratch, &self.twiddles, true);
+                let scale = T::one() / T::from(m).unwrap();
+                out.extend(scratch.iter().zip(chirp).flat_map(|(&d, &w)| {
+                    let (re, im) = mul(d, w);
+                    [re * scale, im * scale]
+                }));
+            }
+        }
     }
-    out
+}
+
+/// DFT of `inp` as interleaved real and imaginary parts, see [`FftPlan`] to transform several
+/// inputs of the same size.
+pub fn fft<T: Float>(inp: &[T]) -> Vec<T> {
+    FftPlan::new(inp.len()).transform(inp)
+}
+
+/// Input, output and working buffers of the FFT of the frames.
+struct FftBuffers<T> {
+    fft_in: Vec<T>,
+    fft_out: Vec<T>,
+    scratch: Vec<Complex<T>>,
 }
 
 #[allow(clippy::too_many_arguments)]
 fn log_mel_spectrogram_w<T: Float>(
     ith: usize,
+    plan: &FftPlan<T>,
+    buffers: &mut FftBuffers<T>,
     hann: &[T],
     samples: &[T],
     filters: &[T],
-    fft_size: usize,
     fft_step: usize,
     speed_up: bool,
     n_len: usize,
     n_mel: usize,
     n_threads: usize,
 ) -> Vec<T> {
+    let fft_size = hann.len();
     let n_fft = if speed_up {
         1 + fft_size / 4
     } else {
@@ -96,7 +228,12 @@ fn log_mel_spectrogram_w<T: Float>(
 
     let zero = T::zero();
     let half = T::from(0.5).unwrap();
-    let mut fft_in = vec![zero; fft_size];
+    let FftBuffers {
+        fft_in,
+        fft_out,
+        scratch,
+    } = buffers;
+    fft_in.resize(fft_size, zero);
     let mut mel = vec![zero; n_len * n_mel];
 
     for i in (ith..n_len).step_by(n_threads) {
@@ -110,7 +247,7 @@ fn log_mel_spectrogram_w<T: Float>(
             }
         }
 
-        let mut fft_out: Vec<T> = fft(&fft_in);
+        plan.transform_into(fft_in, scratch, fft_out);
 
         for j in 0..fft_size {
             fft_out[j] = fft_out[2 * j] * fft_out[2 * j] + fft_out[2 * j + 1] * fft_out[2 * j + 1];
@@ -127,62 +264,217 @@ fn log_mel_spectrogram_w<T: Float>(
         }
 
         for j in 0..n_mel {
-            let mut sum = zero;
-            for k in 0..n_fft {
-                sum += fft_out[k] * filters[j * n_fft + k];
-            }
+            let sum = kahan_dot(&fft_out[..n_fft], &filters[j * n_fft..(j + 1) * n_fft]);
             mel[j * n_len + i] = T::max(sum, T::from(1e-10).unwrap()).log10();
         }
     }
     mel
 }
 
+/// Dot product with the Kahan compensated summation, whose error does not grow with the length,
+/// so that the result barely depends on the order of the additions.
+fn kahan_dot<T: Float>(a: &[T], b: &[T]) -> T {
+    let mut sum = T::zero();
+    let mut compensation = T::zero();
+    for (&x, &y) in a.iter().zip(b) {
+        let term = x * y - compensation;
+        let next = sum + term;
+        compensation = (next - sum) - term;
+        sum = next;
+    }
+    sum
+}
+
+/// Reference level of the dynamic range clamp of the log-mel spectrogram, the values more than
+/// 8 (80dB) below the reference being raised to it.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MelNormalization {
+    /// The maximum of the whole spectrogram, as in openai/whisper. A single loud spike pushes
+    /// the rest of the audio into the floor.
+    #[default]
+    GlobalMax,
+    /// This percentile in `(0, 100]` of the maxima of the audio frames, e.g. 99.5, robust to
+    /// short spikes.
+    Percentile(f64),
+    /// The maximum of every 30 seconds of frames, for long files whose level drifts.
+    PerWindow,
+}
+
+/// Raises the `values` more than 8 below `reference` to `reference - 8` and scales them.
+fn clamp_dynamic_range<'a, T: Float + 'a>(values: impl Iterator<Item = &'a mut T>, reference: T) {
+    let floor = reference - T::from(8).unwrap();
+    let one = T::from(1.0).unwrap();
+    let four = T::from(4.0).unwrap();
+    for m in values {
+        let v = T::max(*m, floor);
+        *m = v / four + one
+    }
+}
+
+fn max_value<'a, T: Float + 'a>(values: impl Iterator<Item = &'a T>) -> T {
+    values
+        .max_by(|&u, &v| u.partial_cmp(v).unwrap_or(std::cmp::Ordering::Greater))
+        .copied()
+        .unwrap_or(T::zero())
+}
+
+/// Variance of the samples below which the audio is taken as silence, that of a signal smaller
+/// than one 16-bit quantization step. Relative to its own maximum, the noise of such a signal
+/// would fill the 80dB of the spectrogram as if it were loud.
+const MIN_SAMPLE_VARIANCE: f64 = 1. / (32768. * 32768.);
+
+/// Value of every bin of the spectrogram of silence, the floor of the log of the energy scaled.
+const SILENCE_MEL: f64 = -10. / 4. + 1.;
+
+fn sample_variance<T: Float>(samples: &[T]) -> f64 {
+    if samples.is_empty() {
+        return 0.;
+    }
+    let n = samples.len() as f64;
+    let mean = samples
+        .iter()
+        .map(|v| v.to_f64().unwrap_or(0.))
+        .sum::<f64>()
+        / n;
+    samples
+        .iter()
+        .map(|v| (v.to_f64().unwrap_or(0.) - mean).powi(2))
+        .sum::<f64>()
+        / n
+}
+
+/// FFT plan, window and buffers of the log-mel spectrograms, kept from one spectrogram to the
+/// next so that transcribing many files only sets them up once.
+pub struct MelContext<T> {
+    plan: FftPlan<T>,
+    hann: Vec<T>,
+    /// Samples padded with silence to the frames of the spectrogram.
+    padded: Vec<T>,
+    buffers: FftBuffers<T>,
+}
+
+impl<T: Float + std::fmt::Display> Default for MelContext<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Float + std::fmt::Display> MelContext<T> {
+    pub fn new() -> Self {
+        let fft_size = logic::m::N_FFT;
+        let two_pi = T::PI() + T::PI();
+        let half = T::from(0.5).unwrap();
+        let one = T::from(1.0).unwrap();
+        let fft_size_t = T::from(fft_size).unwrap();
+        let hann = (0..fft_size)
+            .map(|i| half * (one - ((two_pi * T::from(i).unwrap()) / fft_size_t).cos()))
+            .collect();
+        Self {
+            plan: FftPlan::new(fft_size),
+            hann,
+            padded: vec![],
+            buffers: FftBuffers {
+                fft_in: vec![],
+                fft_out: vec![],
+                scratch: vec![],
+            },
+        }
+    }
+
+    /// Same as [`pcm_to_mel`], with the plan and the buffers of the context.
+    pub fn pcm_to_mel(
+        &mut self,
+        cfg: &logic::m::Config,
+        samples: &[T],
+        filters: &[T],
+        normalization: MelNormalization,
+    ) -> anyhow::Result<Vec<T>> {
+        Ok(log_mel_spectrogram_(
+            self,
+            samples,
+            filters,
+            logic::m::HOP_LENGTH,
+            cfg.num_mel_bins,
+            false,
+            normalization,
+        ))
+    }
+}
+
+impl MelContext<f64> {
+    /// Same as [`pcm_to_mel_precise`], with the plan and the buffers of the context.
+    pub fn pcm_to_mel_precise(
+        &mut self,
+        cfg: &logic::m::Config,
+        samples: &[f32],
+        filters: &[f32],
+        normalization: MelNormalization,
+    ) -> anyhow::Result<Vec<f32>> {
+        let samples: Vec<f64> = samples.iter().map(|&v| v as f64).collect();
+        let filters: Vec<f64> = filters.iter().map(|&v| v as f64).collect();
+        let mel = self.pcm_to_mel(cfg, &samples, &filters, normalization)?;
+        Ok(mel.into_iter().map(|v| v as f32).collect())
+    }
+}
+
 fn log_mel_spectrogram_<T: Float + std::fmt::Display>(
+    context: &mut MelContext<T>,
     samples: &[T],
     filters: &[T],
-    fft_size: usize,
     fft_step: usize,
     n_mel: usize,
     speed_up: bool,
+    normalization: MelNormalization,
 ) -> Vec<T> {
-    let zero = T::zero();
-    let two_pi = T::PI() + T::PI();
-    let half = T::from(0.5).unwrap();
-    let one = T::from(1.0).unwrap();
-    let four = T::from(4.0).unwrap();
-    let fft_size_t = T::from(fft_size).unwrap();
-
-    let hann: Vec<T> = (0..fft_size)
-        .map(|i| half * (one - ((two_pi * T::from(i).unwrap()) / fft_size_t).cos()))
-        .collect();
-    let n_len = samples.len() / fft_step;
-
-    let pad = 100 * logic::m::CHUNK_LENGTH / 2;
-    let n_len = if n_len % pad != 0 {
-        (n_len / pad + 1) * pad
-    } else {
-        n_len
-    };
-    let n_len = n_len + pad;
-    let samples = {
-        let mut samples_padded = samples.to_vec();
-        let to_add = n_len * fft_step - samples.len();
-        samples_padded.extend(std::iter::repeat(zero).take(to_add));
-        samples_padded
-    };
+    // The frames are padded once, with silence up to the next 30-second window, an empty audio
+    // having a single silent window.
+    let n_audio = usize::max(samples.len().div_ceil(fft_step), 1);
+    let n_len = n_audio.next_multiple_of(logic::m::N_FRAMES);
+    // A constant or nearly constant signal, e.g. digital silence with a DC offset, gives the
+    // spectrogram of silence rather than its noise raised to the level of speech.
+    if sample_variance(samples) < MIN_SAMPLE_VARIANCE {
+        return vec![T::from(SILENCE_MEL).unwrap(); n_len * n_mel];
+    }
+    let MelContext {
+        plan,
+        hann,
+        padded,
+        buffers,
+    } = context;
+    padded.clear();
+    padded.extend_from_slice(samples);
+    padded.resize(n_len * fft_step, T::zero());
 
     let mut mel = log_mel_spectrogram_w(
-        0, &hann, &samples, filters, fft_size, fft_step, speed_up, n_len, n_mel, 1,
+        0, plan, buffers, hann, padded, filters, fft_step, speed_up, n_len, n_mel, 1,
     );
-    let mmax = mel
-        .iter()
-        .max_by(|&u, &v| u.partial_cmp(v).unwrap_or(std::cmp::Ordering::Greater))
-        .copied()
-        .unwrap_or(zero)
-        - T::from(8).unwrap();
-    for m in mel.iter_mut() {
-        let v = T::max(*m, mmax);
-        *m = v / four + one
+    match normalization {
+        MelNormalization::GlobalMax => {
+            let reference = max_value(mel.iter());
+            clamp_dynamic_range(mel.iter_mut(), reference);
+        }
+        MelNormalization::Percentile(percentile) => {
+            // The padding frames are silent and left out.
+            let mut frame_maxima: Vec<T> = (0..n_audio)
+                .map(|i| max_value((0..n_mel).map(|j| &mel[j * n_len + i])))
+                .collect();
+            frame_maxima.sort_by(|u, v| u.partial_cmp(v).unwrap_or(std::cmp::Ordering::Greater));
+            let rank = ((percentile / 100. * n_audio as f64).ceil() as usize).clamp(1, n_audio);
+            clamp_dynamic_range(mel.iter_mut(), frame_maxima[rank - 1]);
+        }
+        MelNormalization::PerWindow => {
+            for start in (0..n_len).step_by(logic::m::N_FRAMES) {
+                // The frames of the window in every mel bin.
+                let rows: Vec<std::ops::Range<usize>> = (0..n_mel)
+                    .map(|j| j * n_len + start..j * n_len + start + logic::m::N_FRAMES)
+                    .collect();
+                let reference = max_value(rows.iter().flat_map(|row| mel[row.clone()].iter()));
+                for row in rows {
+                    clamp_dynamic_range(mel[row].iter_mut(), reference);
+                }
+            }
+        }
     }
     mel
 }
@@ -191,14 +483,1257 @@ pub fn pcm_to_mel<T: Float + std::fmt::Display>(
     cfg: &logic::m::Config,
     samples: &[T],
     filters: &[T],
+    normalization: MelNormalization,
 ) -> anyhow::Result<Vec<T>> {
-    let mel = log_mel_spectrogram_(
-        samples,
-        filters,
-        logic::m::N_FFT,
-        logic::m::HOP_LENGTH,
-        cfg.num_mel_bins,
-        false,
+    MelContext::new().pcm_to_mel(cfg, samples, filters, normalization)
+}
+
+/// [`pcm_to_mel`] computed in `f64`, the FFT and the filter sums included, and only converted
+/// to `f32` at the end, so that the result does not depend on the rounding of the `f32`
+/// operations, which differs between the native and the wasm builds.
+pub fn pcm_to_mel_precise(
+    cfg: &logic::m::Config,
+    samples: &[f32],
+    filters: &[f32],
+    normalization: MelNormalization,
+) -> anyhow::Result<Vec<f32>> {
+    MelContext::new().pcm_to_mel_precise(cfg, samples, filters, normalization)
+}
+
+const MEL_MAGIC: &[u8; 4] = b"WMEL";
+const MEL_VERSION: u32 = 1;
+const MEL_HEADER_LEN: usize = 24;
+
+/// Log-mel spectrogram of an audio as fed to the encoder, computed once it can be run with
+/// different settings.
+#[derive(Debug, Clone)]
+pub struct MelSpectrogram {
+    /// `(1, n_mels, n_frames)` tensor of `f32`.
+    tensor: Tensor,
+    n_mels: usize,
+    n_frames: usize,
+    duration: f64,
+}
+
+impl MelSpectrogram {
+    /// `data` holds `n_mels` rows of frames, `duration` is the duration in seconds of the
+    /// audio the spectrogram was computed from.
+    pub fn new(
+        data: Vec<f32>,
+        n_mels: usize,
+        duration: f64,
+        device: &Device,
+    ) -> Result<Self, WhisperError> {
+        if n_mels == 0 || !data.len().is_multiple_of(n_mels) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!(
+                    "{} mel values cannot be split into {n_mels} bins",
+                    data.len()
+                ),
+            });
+        }
+        let n_frames = data.len() / n_mels;
+        let tensor = Tensor::from_vec(data, (1, n_mels, n_frames), device)?;
+        Ok(Self {
+            tensor,
+            n_mels,
+            n_frames,
+            duration,
+        })
+    }
+
+    pub fn tensor(&self) -> &Tensor {
+        &self.tensor
+    }
+
+    pub fn n_mels(&self) -> usize {
+        self.n_mels
+    }
+
+    pub fn n_frames(&self) -> usize {
+        self.n_frames
+    }
+
+    /// Duration in seconds of the audio.
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Serializes the spectrogram as a 24 bytes header (`WMEL` magic, version, `n_mels`,
+    /// `n_frames` as little-endian `u32`, duration as little-endian `f64`) followed by the
+    /// little-endian `f32` values.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WhisperError> {
+        let data = self.tensor.flatten_all()?.to_vec1::<f32>()?;
+        let mut bytes = Vec::with_capacity(MEL_HEADER_LEN + data.len() * 4);
+        bytes.extend_from_slice(MEL_MAGIC);
+        bytes.extend_from_slice(&MEL_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.n_mels as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.n_frames as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.duration.to_le_bytes());
+        for v in data {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    /// Reads a spectrogram serialized by [`MelSpectrogram::to_bytes`].
+    pub fn from_bytes(bytes: &[u8], device: &Device) -> Result<Self, WhisperError> {
+        let invalid = |reason: String| WhisperError::InvalidConfig {
+            reason: format!("invalid mel spectrogram: {reason}"),
+        };
+        if bytes.len() < MEL_HEADER_LEN || &bytes[..4] != MEL_MAGIC {
+            return Err(invalid("bad magic".to_string()));
+        }
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let version = u32_at(4);
+        if version != MEL_VERSION {
+            return Err(invalid(format!("unsupported version {version}")));
+        }
+        let n_mels = u32_at(8) as usize;
+        let n_frames = u32_at(12) as usize;
+        let duration = f64::from_le_bytes(bytes[16..24].try_into().expect("8 bytes"));
+        let payload = &bytes[MEL_HEADER_LEN..];
+        let expected = n_mels
+            .checked_mul(n_frames)
+            .and_then(|values| values.checked_mul(4))
+            .ok_or_else(|| invalid(format!("{n_mels}x{n_frames} values overflow")))?;
+        if payload.len() != expected {
+            return Err(invalid(format!(
+                "expected {expected} bytes of data for {n_mels}x{n_frames} values, got {}",
+                payload.len()
+            )));
+        }
+        let data = payload
+            .chunks_exact(4)
+            .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect();
+        Self::new(data, n_mels, duration, device)
+    }
+}
+
+/// Properties of a WAV file, obtained without computing its mel spectrogram.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AudioInfo {
+    pub duration_secs: f64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    /// `int` or `float`.
+    pub sample_format: String,
+    /// Number of samples per channel.
+    pub frames: u32,
+    /// Highest absolute sample value in dBFS, `-inf` for digital silence.
+    pub peak_dbfs: f32,
+    /// Fraction of the samples at full scale, a high value hints at a clipped recording.
+    pub clipped_fraction: f32,
+}
+
+/// Reads the header of a WAV file and scans its samples for the peak level.
+pub fn probe_wav(bytes: &[u8]) -> Result<AudioInfo, WhisperError> {
+    if bytes.is_empty() {
+        return Err(WhisperError::unsupported_audio("the file is empty"));
+    }
+    let mut reader = match hound::WavReader::new(std::io::Cursor::new(bytes)) {
+        Ok(reader) => reader,
+        Err(_) => {
+            let (spec, samples) = read_riff(bytes)?;
+            let frames = (samples.len() / spec.channels as usize) as u32;
+            let levels = scan_levels(samples.into_iter().map(Ok))?;
+            return audio_info(spec, frames, levels);
+        }
+    };
+    let spec = reader.spec();
+    let frames = reader.duration();
+    let levels = match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            scan_levels(
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / full_scale)),
+            )?
+        }
+        hound::SampleFormat::Float => scan_levels(reader.samples::<f32>())?,
+    };
+    audio_info(spec, frames, levels)
+}
+
+fn audio_info(
+    spec: hound::WavSpec,
+    frames: u32,
+    (peak, clipped): (f32, usize),
+) -> Result<AudioInfo, WhisperError> {
+    if frames == 0 {
+        return Err(WhisperError::unsupported_audio("the file has no samples"));
+    }
+    let samples = frames as f32 * spec.channels as f32;
+    Ok(AudioInfo {
+        duration_secs: frames as f64 / spec.sample_rate as f64,
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+        bits_per_sample: spec.bits_per_sample,
+        sample_format: match spec.sample_format {
+            hound::SampleFormat::Int => "int",
+            hound::SampleFormat::Float => "float",
+        }
+        .to_string(),
+        frames,
+        peak_dbfs: 20. * peak.log10(),
+        clipped_fraction: clipped as f32 / samples,
+    })
+}
+
+/// Peak absolute value and number of samples at full scale.
+fn scan_levels(
+    samples: impl Iterator<Item = hound::Result<f32>>,
+) -> Result<(f32, usize), WhisperError> {
+    let mut peak = 0f32;
+    let mut clipped = 0;
+    for sample in samples {
+        let sample = sample?.abs();
+        peak = f32::max(peak, sample);
+        if sample >= CLIPPING_LEVEL {
+            clipped += 1
+        }
+    }
+    Ok((peak, clipped))
+}
+
+/// Level from which a normalized sample is considered at full scale.
+const CLIPPING_LEVEL: f32 = 0.999;
+
+/// Format tag of the extensible format, the actual format is in the sub-format GUID.
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Decodes a WAV file into interleaved samples in `[-1, 1]`.
+///
+/// hound is used first, files it rejects go through a more tolerant RIFF reader.
+pub fn decode_wav(bytes: &[u8]) -> Result<(hound::WavSpec, Vec<f32>), WhisperError> {
+    let reader = match hound::WavReader::new(std::io::Cursor::new(bytes)) {
+        Ok(reader) => reader,
+        Err(_) => return read_riff(bytes),
+    };
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / full_scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        hound::SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+    };
+    Ok((spec, samples))
+}
+
+/// Channel of a multi-channel recording to transcribe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelSelect {
+    /// Average of all the channels.
+    #[default]
+    Mix,
+    /// The first channel.
+    Left,
+    /// The second channel, a mono file has none.
+    Right,
+    /// The channel of the given 0-based index.
+    Index(usize),
+}
+
+impl ChannelSelect {
+    /// Mono samples of the selected channel of the interleaved `samples` of `channels`
+    /// channels, failing when the audio does not have that channel.
+    pub fn extract(self, samples: &[f32], channels: u16) -> Result<Vec<f32>, WhisperError> {
+        let channels = channels as usize;
+        if channels == 0 {
+            return Err(WhisperError::unsupported_audio("the file has no channels"));
+        }
+        let frames = samples.chunks_exact(channels);
+        let index = match self {
+            Self::Mix if channels == 1 => return Ok(samples.to_vec()),
+            Self::Mix => {
+                let scale = 1. / channels as f32;
+                return Ok(frames.map(|f| f.iter().sum::<f32>() * scale).collect());
+            }
+            Self::Left => 0,
+            Self::Right => 1,
+            Self::Index(index) => index,
+        };
+        if index >= channels {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!(
+                    "channel {index} is out of range, the audio has {channels} channel{}",
+                    if channels == 1 { "" } else { "s" }
+                ),
+            });
+        }
+        Ok(frames.map(|f| f[index]).collect())
+    }
+}
+
+fn is_chunk_id(bytes: &[u8]) -> bool {
+    bytes.len() >= 4
+        && bytes[..4]
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || *b == b' ')
+}
+
+/// RIFF chunk walker accepting what hound rejects: `fmt ` and `data` chunks anywhere among
+/// unknown chunks, extensible formats, odd-sized chunks missing their padding byte and a
+/// `data` chunk declaring more bytes than the file holds.
+pub fn read_riff(bytes: &[u8]) -> Result<(hound::WavSpec, Vec<f32>), WhisperError> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(WhisperError::unsupported_audio("not a RIFF/WAVE file"));
+    }
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+    let mut format = None;
+    let mut data = None;
+    let mut pos: usize = 12;
+    while pos.saturating_add(8) <= bytes.len() && (format.is_none() || data.is_none()) {
+        let id = &bytes[pos..pos + 4];
+        let size = u32_at(bytes, pos + 4) as usize;
+        let start = pos + 8;
+        let end = usize::min(start.saturating_add(size), bytes.len());
+        let body = &bytes[start..end];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let mut tag = u16_at(body, 0);
+                if tag == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+                    // The sub-format GUID starts with the format tag.
+                    tag = u16_at(body, 24);
+                }
+                format = Some((
+                    tag,
+                    u16_at(body, 2),
+                    u32_at(body, 4),
+                    u16_at(body, 12),
+                    u16_at(body, 14),
+                ));
+            }
+            b"fmt " => return Err(WhisperError::unsupported_audio("truncated fmt chunk")),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        pos = start.saturating_add(size);
+        // Chunks are padded to an even size, some writers forget the padding byte.
+        if size % 2 == 1 {
+            let at = |i: usize| &bytes[usize::min(i, bytes.len())..];
+            let unpadded = is_chunk_id(at(pos)) && !is_chunk_id(at(pos.saturating_add(1)));
+            if !unpadded {
+                pos = pos.saturating_add(1)
+            }
+        }
+    }
+    let (tag, channels, sample_rate, block_align, bits_per_sample) =
+        format.ok_or_else(|| WhisperError::unsupported_audio("no fmt chunk"))?;
+    let data = data.ok_or_else(|| WhisperError::unsupported_audio("no data chunk"))?;
+    if channels == 0 || sample_rate == 0 {
+        return Err(WhisperError::unsupported_audio("invalid fmt chunk"));
+    }
+    let sample_bytes = match block_align as usize / channels as usize {
+        0 => (bits_per_sample as usize).div_ceil(8),
+        n => n,
+    };
+    if sample_bytes == 0 {
+        return Err(WhisperError::unsupported_audio("invalid fmt chunk"));
+    }
+    let frame_bytes = sample_bytes * channels as usize;
+    let data = &data[..data.len() - data.len() % frame_bytes];
+    let (sample_format, samples): (_, Vec<f32>) = match (tag, sample_bytes) {
+        (WAVE_FORMAT_PCM, 1) => (
+            hound::SampleFormat::Int,
+            data.iter().map(|b| (*b as f32 - 128.) / 128.).collect(),
+        ),
+        (WAVE_FORMAT_PCM, 2..=4) => {
+            // Samples are left-aligned in their container.
+            let full_scale = (1i64 << (8 * sample_bytes - 1)) as f32;
+            let samples = data
+                .chunks_exact(sample_bytes)
+                .map(|s| {
+                    let mut v = [0u8; 4];
+                    v[4 - sample_bytes..].copy_from_slice(s);
+                    (i32::from_le_bytes(v) >> (8 * (4 - sample_bytes))) as f32 / full_scale
+                })
+                .collect();
+            (hound::SampleFormat::Int, samples)
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 4) => (
+            hound::SampleFormat::Float,
+            data.chunks_exact(4)
+                .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                .collect(),
+        ),
+        (WAVE_FORMAT_IEEE_FLOAT, 8) => (
+            hound::SampleFormat::Float,
+            data.chunks_exact(8)
+                .map(|s| f64::from_le_bytes(s.try_into().expect("8 bytes")) as f32)
+                .collect(),
+        ),
+        _ => {
+            return Err(WhisperError::unsupported_audio(format!(
+                "unsupported format {tag:#x} with {bits_per_sample} bits per sample"
+            )))
+        }
+    };
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format,
+    };
+    Ok((spec, samples))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// Scale the signal so that its peak reaches the target level.
+    Peak,
+    /// Scale the signal so that its RMS reaches the target level, limited by the ceiling.
+    Rms,
+}
+
+/// Preprocessing applied to the samples before computing the mel spectrogram, everything is
+/// disabled by default.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AudioPreprocess {
+    /// Amplitude of the triangular dither added to the samples, e.g. [`DEFAULT_DITHER`], which
+    /// breaks up the few quantization levels of very quiet recordings.
+    pub dither: Option<f32>,
+    /// Subtract the mean of the signal.
+    pub remove_dc_offset: bool,
+    /// Cutoff in Hz of a high-pass filter removing rumble and hum.
+    pub highpass_hz: Option<f32>,
+    pub normalization: Option<Normalization>,
+    /// Target level of the normalization in dBFS.
+    pub target_dbfs: f32,
+    /// Maximum peak level in dBFS reached by the normalization, avoids clipping when
+    /// normalizing the RMS.
+    pub ceiling_dbfs: f32,
+    /// Coefficient `a` of the pre-emphasis filter `y[n] = x[n] - a * x[n - 1]`.
+    pub pre_emphasis: Option<f32>,
+    /// Gain following the short-term level, for recordings whose level varies over time.
+    pub agc: Option<AgcOptions>,
+}
+
+impl Default for AudioPreprocess {
+    fn default() -> Self {
+        Self {
+            dither: None,
+            remove_dc_offset: false,
+            highpass_hz: None,
+            normalization: None,
+            target_dbfs: -3.0,
+            ceiling_dbfs: -1.0,
+            pre_emphasis: None,
+            agc: None,
+        }
+    }
+}
+
+impl AudioPreprocess {
+    pub fn is_enabled(&self) -> bool {
+        self.dither.is_some()
+            || self.remove_dc_offset
+            || self.highpass_hz.is_some()
+            || self.normalization.is_some()
+            || self.pre_emphasis.is_some()
+            || self.agc.is_some()
+    }
+
+    /// Applies the enabled steps in order: dither, DC offset removal, high-pass, pre-emphasis,
+    /// automatic gain control, normalization. The filters start from a clean state on every
+    /// call, the dither is drawn from a generator seeded with 0.
+    pub fn apply<'a>(&self, pcm: &'a [f32]) -> std::borrow::Cow<'a, [f32]> {
+        self.apply_with_rng(pcm, &mut StdRng::seed_from_u64(0))
+    }
+
+    /// [`AudioPreprocess::apply`] with the dither drawn from `rng`.
+    pub fn apply_with_rng<'a>(
+        &self,
+        pcm: &'a [f32],
+        rng: &mut impl Rng,
+    ) -> std::borrow::Cow<'a, [f32]> {
+        if !self.is_enabled() {
+            return std::borrow::Cow::Borrowed(pcm);
+        }
+        let mut pcm = pcm.to_vec();
+        if let Some(amplitude) = self.dither {
+            dither(&mut pcm, amplitude, rng)
+        }
+        if self.remove_dc_offset {
+            remove_dc_offset(&mut pcm)
+        }
+        if let Some(cutoff_hz) = self.highpass_hz {
+            Biquad::highpass(
+                logic::m::SAMPLE_RATE,
+                cutoff_hz,
+                std::f32::consts::FRAC_1_SQRT_2,
+            )
+            .process(&mut pcm)
+        }
+        if let Some(coeff) = self.pre_emphasis {
+            pre_emphasis(&mut pcm, coeff)
+        }
+        if let Some(agc) = &self.agc {
+            automatic_gain_control(&mut pcm, agc)
+        }
+        match self.normalization {
+            Some(Normalization::Peak) => {
+                normalize_peak(&mut pcm, f32::min(self.target_dbfs, self.ceiling_dbfs))
+            }
+            Some(Normalization::Rms) => {
+                normalize_rms(&mut pcm, self.target_dbfs, self.ceiling_dbfs)
+            }
+            None => {}
+        }
+        std::borrow::Cow::Owned(pcm)
+    }
+}
+
+/// Dither of one 16-bit quantization step.
+pub const DEFAULT_DITHER: f32 = 1. / 32768.;
+
+/// Adds triangular noise in `(-amplitude, amplitude)`, the difference of two uniform draws,
+/// whose error is independent of the signal.
+fn dither(pcm: &mut [f32], amplitude: f32, rng: &mut impl Rng) {
+    for v in pcm {
+        *v += amplitude * (rng.gen::<f32>() - rng.gen::<f32>())
+    }
+}
+
+fn db_to_amplitude(dbfs: f32) -> f32 {
+    10f32.powf(dbfs / 20.)
+}
+
+fn peak(pcm: &[f32]) -> f32 {
+    pcm.iter().fold(0f32, |max, v| f32::max(max, v.abs()))
+}
+
+fn rms(pcm: &[f32]) -> f32 {
+    if pcm.is_empty() {
+        return 0.;
+    }
+    (pcm.iter().map(|v| v * v).sum::<f32>() / pcm.len() as f32).sqrt()
+}
+
+/// Subtracts the mean of the signal.
+pub fn remove_dc_offset(pcm: &mut [f32]) {
+    if pcm.is_empty() {
+        return;
+    }
+    let mean = pcm.iter().map(|v| *v as f64).sum::<f64>() / pcm.len() as f64;
+    for v in pcm.iter_mut() {
+        *v -= mean as f32
+    }
+}
+
+/// Scales the signal so that its peak is at `target_dbfs`, silent signals are left as is.
+pub fn normalize_peak(pcm: &mut [f32], target_dbfs: f32) {
+    let peak = peak(pcm);
+    if peak > 0. {
+        let gain = db_to_amplitude(target_dbfs) / peak;
+        pcm.iter_mut().for_each(|v| *v *= gain)
+    }
+}
+
+/// Scales the signal so that its RMS is at `target_dbfs`, the gain being limited so that the
+/// peak stays under `ceiling_dbfs`. Silent signals are left as is.
+pub fn normalize_rms(pcm: &mut [f32], target_dbfs: f32, ceiling_dbfs: f32) {
+    let (rms, peak) = (rms(pcm), peak(pcm));
+    if rms > 0. {
+        let gain = f32::min(
+            db_to_amplitude(target_dbfs) / rms,
+            db_to_amplitude(ceiling_dbfs) / peak,
+        );
+        pcm.iter_mut().for_each(|v| *v *= gain)
+    }
+}
+
+/// Automatic gain control, see [`automatic_gain_control`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AgcOptions {
+    /// Length in seconds of the windows the level is measured on.
+    pub window_seconds: f32,
+    /// RMS level of the windows in dBFS.
+    pub target_dbfs: f32,
+    /// Time constant in seconds of the gain decreasing when the level rises.
+    pub attack_seconds: f32,
+    /// Time constant in seconds of the gain increasing when the level falls.
+    pub release_seconds: f32,
+    /// Maximum gain in dB, so that the noise floor of the pauses is not raised to the target.
+    pub max_gain_db: f32,
+}
+
+impl Default for AgcOptions {
+    fn default() -> Self {
+        Self {
+            window_seconds: 1.,
+            target_dbfs: -20.,
+            attack_seconds: 0.5,
+            release_seconds: 2.,
+            max_gain_db: 30.,
+        }
+    }
+}
+
+impl AgcOptions {
+    pub fn validate(&self) -> Result<(), WhisperError> {
+        let times = [
+            self.window_seconds,
+            self.attack_seconds,
+            self.release_seconds,
+        ];
+        if times.iter().any(|t| t.is_nan() || *t <= 0.) {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the AGC window and time constants must be positive".to_string(),
+            });
+        }
+        if self.max_gain_db.is_nan() || self.max_gain_db < 0. || !self.target_dbfs.is_finite() {
+            return Err(WhisperError::InvalidConfig {
+                reason: "invalid AGC target or maximum gain".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Scales the signal by a gain following its level: the RMS of each window is brought towards
+/// `target_dbfs` by a gain limited to `max_gain_db`. The gain is smoothed from window to window
+/// with the attack and release time constants, and interpolated linearly between the window
+/// centers so that no step is audible at the boundaries. The gain at a center is limited so
+/// that the peaks of the window and of its neighbours stay under full scale.
+pub fn automatic_gain_control(pcm: &mut [f32], options: &AgcOptions) {
+    let window = usize::max(
+        1,
+        (options.window_seconds * logic::m::SAMPLE_RATE as f32) as usize,
     );
-    Ok(mel)
+    if pcm.is_empty() {
+        return;
+    }
+    let max_gain_db = options.max_gain_db;
+    let attack = (-options.window_seconds / options.attack_seconds).exp();
+    let release = (-options.window_seconds / options.release_seconds).exp();
+    let mut gains_db: Vec<f32> = Vec::with_capacity(pcm.len().div_ceil(window));
+    for chunk in pcm.chunks(window) {
+        let rms = rms(chunk);
+        let target_db = if rms > 0. {
+            f32::min(options.target_dbfs - 20. * rms.log10(), max_gain_db)
+        } else {
+            max_gain_db
+        };
+        let gain_db = match gains_db.last() {
+            Some(&previous) => {
+                let coeff = if target_db < previous {
+                    attack
+                } else {
+                    release
+                };
+                target_db + coeff * (previous - target_db)
+            }
+            None => target_db,
+        };
+        gains_db.push(gain_db);
+    }
+    let full_scale_db: Vec<f32> = pcm
+        .chunks(window)
+        .map(|chunk| -20. * peak(chunk).log10())
+        .collect();
+    let gains: Vec<f32> = gains_db
+        .iter()
+        .enumerate()
+        .map(|(k, gain_db)| {
+            let neighbours = k.saturating_sub(1)..usize::min(k + 2, full_scale_db.len());
+            let limit_db = full_scale_db[neighbours]
+                .iter()
+                .copied()
+                .fold(f32::INFINITY, f32::min);
+            db_to_amplitude(f32::min(*gain_db, limit_db))
+        })
+        .collect();
+    let first_center = (window as f32 - 1.) / 2.;
+    for (i, v) in pcm.iter_mut().enumerate() {
+        // Position relative to the window centers.
+        let position = (i as f32 - first_center) / window as f32;
+        let k = position.max(0.) as usize;
+        let gain = match (gains.get(k), gains.get(k + 1)) {
+            (Some(a), Some(b)) if position > 0. => {
+                let t = position - k as f32;
+                a + (b - a) * t
+            }
+            (Some(a), _) => *a,
+            (None, _) => gains[gains.len() - 1],
+        };
+        *v = (*v * gain).clamp(-1., 1.)
+    }
+}
+
+/// Second order IIR filter with coefficients from the Audio EQ Cookbook, processed in direct
+/// form II transposed.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b0: (b[0] / a[0]) as f32,
+            b1: (b[1] / a[0]) as f32,
+            b2: (b[2] / a[0]) as f32,
+            a1: (a[1] / a[0]) as f32,
+            a2: (a[2] / a[0]) as f32,
+            z1: 0.,
+            z2: 0.,
+        }
+    }
+
+    /// Returns `(cos(w0), alpha)`.
+    fn params(sample_rate: usize, freq_hz: f32, q: f32) -> (f64, f64) {
+        let w0 = 2. * std::f64::consts::PI * freq_hz as f64 / sample_rate as f64;
+        (w0.cos(), w0.sin() / (2. * q as f64))
+    }
+
+    pub fn highpass(sample_rate: usize, cutoff_hz: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::params(sample_rate, cutoff_hz, q);
+        Self::new(
+            [(1. + cos) / 2., -(1. + cos), (1. + cos) / 2.],
+            [1. + alpha, -2. * cos, 1. - alpha],
+        )
+    }
+
+    pub fn lowpass(sample_rate: usize, cutoff_hz: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::params(sample_rate, cutoff_hz, q);
+        Self::new(
+            [(1. - cos) / 2., 1. - cos, (1. - cos) / 2.],
+            [1. + alpha, -2. * cos, 1. - alpha],
+        )
+    }
+
+    /// Band-pass with a 0 dB gain at `center_hz`.
+    pub fn bandpass(sample_rate: usize, center_hz: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::params(sample_rate, center_hz, q);
+        Self::new([alpha, 0., -alpha], [1. + alpha, -2. * cos, 1. - alpha])
+    }
+
+    /// Clears the filter state.
+    pub fn reset(&mut self) {
+        self.z1 = 0.;
+        self.z2 = 0.;
+    }
+
+    /// Filters `samples` in place, the state is kept so that a signal can be processed in
+    /// chunks.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for v in samples.iter_mut() {
+            let x = *v;
+            let y = self.b0 * x + self.z1;
+            self.z1 = self.b1 * x - self.a1 * y + self.z2;
+            self.z2 = self.b2 * x - self.a2 * y;
+            *v = y;
+        }
+    }
+}
+
+/// First order high-pass filter `y[n] = x[n] - coeff * x[n - 1]`.
+pub fn pre_emphasis(pcm: &mut [f32], coeff: f32) {
+    let mut previous = 0f32;
+    for v in pcm.iter_mut() {
+        let x = *v;
+        *v = x - coeff * previous;
+        previous = x;
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct VadOptions {
+    /// Length of the analysis frames in seconds.
+    pub frame_secs: f64,
+    /// Percentile (in `[0, 1]`) of the frame energies used as the noise floor estimate.
+    pub noise_floor_percentile: f64,
+    /// A frame is considered as speech when its RMS exceeds the noise floor by this factor.
+    pub energy_ratio: f32,
+    /// Absolute RMS under which a frame is always silent, guards digital silence.
+    pub min_rms: f32,
+    /// Frames with a zero-crossing rate above this value are treated as noise.
+    pub max_zero_crossing_rate: f32,
+    /// Time during which a region stays open after its last speech frame.
+    pub hangover_secs: f64,
+    /// Regions shorter than this are discarded.
+    pub min_speech_secs: f64,
+    /// Margin added on both sides of each region so that words straddling the region edges
+    /// are kept.
+    pub margin_secs: f64,
+}
+
+impl Default for VadOptions {
+    fn default() -> Self {
+        Self {
+            frame_secs: 0.03,
+            noise_floor_percentile: 0.1,
+            energy_ratio: 3.0,
+            min_rms: 1e-4,
+            max_zero_crossing_rate: 0.35,
+            hangover_secs: 0.3,
+            min_speech_secs: 0.1,
+            margin_secs: 0.3,
+        }
+    }
+}
+
+/// Returns the `(start, end)` ranges in seconds of the regions of `pcm` that likely contain
+/// speech, using the framed RMS energy against an adaptive noise floor and the zero-crossing
+/// rate.
+pub fn detect_speech_regions(
+    pcm: &[f32],
+    sample_rate: usize,
+    opts: &VadOptions,
+) -> Vec<(f64, f64)> {
+    let frame_len = usize::max(1, (opts.frame_secs * sample_rate as f64) as usize);
+    let frames: Vec<(f32, f32)> = pcm
+        .chunks(frame_len)
+        .map(|frame| {
+            let energy = frame.iter().map(|v| v * v).sum::<f32>() / frame.len() as f32;
+            let crossings = frame
+                .windows(2)
+                .filter(|w| (w[0] >= 0.) != (w[1] >= 0.))
+                .count();
+            (energy.sqrt(), crossings as f32 / frame.len() as f32)
+        })
+        .collect();
+    if frames.is_empty() {
+        return vec![];
+    }
+
+    let mut energies: Vec<f32> = frames.iter().map(|(rms, _)| *rms).collect();
+    energies.sort_by(|a, b| a.total_cmp(b));
+    let idx = (opts.noise_floor_percentile.clamp(0., 1.) * (energies.len() - 1) as f64) as usize;
+    let threshold = f32::max(opts.min_rms, energies[idx] * opts.energy_ratio);
+
+    let frame_secs = frame_len as f64 / sample_rate as f64;
+    let hangover = (opts.hangover_secs / frame_secs).ceil() as usize;
+    let mut regions: Vec<(f64, f64)> = vec![];
+    let mut current: Option<(usize, usize)> = None;
+    for (i, &(rms, zcr)) in frames.iter().enumerate() {
+        let is_speech = rms > threshold && zcr <= opts.max_zero_crossing_rate;
+        current = match (current, is_speech) {
+            (None, true) => Some((i, i)),
+            (Some((start, _)), true) => Some((start, i)),
+            (Some((start, last)), false) if i - last > hangover => {
+                regions.push((start as f64 * frame_secs, (last + 1) as f64 * frame_secs));
+                None
+            }
+            (current, _) => current,
+        }
+    }
+    if let Some((start, last)) = current {
+        regions.push((start as f64 * frame_secs, (last + 1) as f64 * frame_secs));
+    }
+
+    let duration = pcm.len() as f64 / sample_rate as f64;
+    let mut merged: Vec<(f64, f64)> = vec![];
+    for (start, end) in regions.into_iter() {
+        if end - start < opts.min_speech_secs {
+            continue;
+        }
+        let start = f64::max(0., start - opts.margin_secs);
+        let end = f64::min(duration, end + opts.margin_secs);
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = f64::max(last.1, end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Samples of `pcm` from the first to the last speech found by [`detect_speech_regions`],
+/// without its margin, widened by `padding_secs` on both sides. `None` when there is no speech.
+pub fn speech_range(
+    pcm: &[f32],
+    sample_rate: usize,
+    opts: &VadOptions,
+    padding_secs: f64,
+) -> Option<std::ops::Range<usize>> {
+    let opts = VadOptions {
+        margin_secs: 0.,
+        ..opts.clone()
+    };
+    let regions = detect_speech_regions(pcm, sample_rate, &opts);
+    let (first, last) = (regions.first()?.0, regions.last()?.1);
+    let to_index = |t: f64| usize::min(pcm.len(), (t.max(0.) * sample_rate as f64) as usize);
+    Some(to_index(first - padding_secs)..to_index(last + padding_secs))
+}
+
+/// The parts of `regions` within `[start, end)`.
+pub fn clip_regions(regions: &[(f64, f64)], start: f64, end: f64) -> Vec<(f64, f64)> {
+    regions
+        .iter()
+        .map(|&(s, e)| (f64::max(s, start), f64::min(e, end)))
+        .filter(|(s, e)| s < e)
+        .collect()
+}
+
+/// Seconds of audio per value of the envelopes compared by [`align_tracks`], the resolution of
+/// the estimated offset.
+const ENVELOPE_SECS: f64 = 0.01;
+
+/// Peak in dBFS below which [`align_tracks`] treats a track as silent.
+const SILENT_TRACK_DBFS: f32 = -60.;
+
+/// RMS of the consecutive blocks of `hop` samples, minus its mean.
+fn envelope(pcm: &[f32], hop: usize) -> Vec<f64> {
+    let mut envelope: Vec<f64> = pcm.chunks(hop).map(|block| rms(block) as f64).collect();
+    let mean = envelope.iter().sum::<f64>() / envelope.len().max(1) as f64;
+    envelope.iter_mut().for_each(|v| *v -= mean);
+    envelope
+}
+
+/// Circular cross-correlation `c[k] = Σ a[t] b[t + k]` of the sequences zero padded to `n`, a
+/// power of two, the negative lags `k` being at `n + k`.
+fn cross_correlation(a: &[f64], b: &[f64], n: usize) -> Vec<f64> {
+    let plan = FftPlan::<f64>::new(n);
+    let spectrum = |x: &[f64]| {
+        let mut padded = x.to_vec();
+        padded.resize(n, 0.);
+        plan.transform(&padded)
+    };
+    let (fa, fb) = (spectrum(a), spectrum(b));
+    let mut data: Vec<Complex<f64>> = fa
+        .chunks_exact(2)
+        .zip(fb.chunks_exact(2))
+        .map(|(a, b)| mul((a[0], -a[1]), (b[0], b[1])))
+        .collect();
+    radix2(&mut data, &plan.twiddles, true);
+    data.iter().map(|(re, _)| re / n as f64).collect()
+}
+
+/// Offset in seconds of track `b` relative to track `a`, two recordings of the same
+/// conversation on separate devices: the sound at `t` in `a` is at `t + offset` in `b`.
+///
+/// The offset is the lag maximizing the cross-correlation of the RMS envelopes of the tracks,
+/// at a resolution of 10 ms, among the lags where the tracks overlap over at least half of the
+/// shorter one. It is `0` when a track is silent, and an error when larger than
+/// `max_offset_secs`.
+pub fn align_tracks(
+    a: &[f32],
+    b: &[f32],
+    sample_rate: u32,
+    max_offset_secs: f64,
+) -> Result<f64, WhisperError> {
+    let silence = db_to_amplitude(SILENT_TRACK_DBFS);
+    if peak(a) < silence || peak(b) < silence {
+        return Ok(0.);
+    }
+    let hop = usize::max(1, (ENVELOPE_SECS * sample_rate as f64).round() as usize);
+    let (a, b) = (envelope(a, hop), envelope(b, hop));
+    let n = (a.len() + b.len()).next_power_of_two();
+    let correlation = cross_correlation(&a, &b, n);
+    let (len_a, len_b) = (a.len() as isize, b.len() as isize);
+    let min_overlap = isize::max(1, isize::min(len_a, len_b) / 2);
+    let best = (1 - len_a..len_b)
+        .filter_map(|lag| {
+            let overlap = isize::min(len_a, len_b - lag) - isize::max(0, -lag);
+            let c = correlation[lag.rem_euclid(n as isize) as usize];
+            (overlap >= min_overlap).then_some((lag, c / overlap as f64))
+        })
+        .max_by(|(_, u), (_, v)| u.total_cmp(v))
+        .map_or(0, |(lag, _)| lag);
+    let offset = (best * hop as isize) as f64 / sample_rate as f64;
+    if offset.abs() > max_offset_secs {
+        return Err(WhisperError::unsupported_audio(format!(
+            "the tracks are {offset:.2}s apart, more than {max_offset_secs}s"
+        )));
+    }
+    Ok(offset)
+}
+
+/// Samples per block of the power spectrum of [`check_format`].
+const FORMAT_FFT_SIZE: usize = 512;
+
+/// Blocks of the power spectrum of [`check_format`] at most, spread over the audio.
+const FORMAT_MAX_BLOCKS: usize = 256;
+
+/// Peak in dBFS below which [`check_format`] has nothing to analyze.
+const FORMAT_MIN_DBFS: f32 = -60.;
+
+/// Fraction of the energy below which the top third of the spectrum counts as empty.
+const EMPTY_BAND_ENERGY: f64 = 1e-4;
+
+/// Fraction of the energy below the rolloff frequency.
+const ROLLOFF_ENERGY: f64 = 0.99;
+
+/// Rolloff frequency of speech whatever the rate it was recorded at.
+const SPEECH_ROLLOFF_HZ: f64 = 4000.;
+
+/// Power relative to the strongest bin from which a bin below the rolloff carries sound.
+const OCCUPIED_BIN_POWER: f64 = 1e-3;
+
+/// Fraction of the bins below the rolloff carrying sound under which the audio is tonal: a few
+/// partials, as a test tone, rather than the continuous spectrum of speech.
+const MIN_OCCUPIED_BINS: f64 = 0.5;
+
+/// Rates audio is commonly recorded at, the candidates for the true rate of a mislabeled file.
+const COMMON_SAMPLE_RATES: [u32; 5] = [22050, 24000, 32000, 44100, 48000];
+
+/// Spectral flatness from which the audio is noise-like, white noise being close to 1 and
+/// speech below 0.1.
+const NOISE_FLATNESS: f64 = 0.5;
+
+/// Fraction of the samples above half scale from which the audio is noise-like, samples
+/// spread uniformly over the full scale having 0.5 and speech close to 0.
+const NOISE_LOUD_FRACTION: f64 = 0.3;
+
+/// Zero crossings of the sinc on each side of the interpolated sample of [`resample`].
+const RESAMPLE_ZEROS: f64 = 16.;
+
+/// How the samples of a WAV file look wrong next to what its header declares, see
+/// [`check_format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FormatIssue {
+    /// The top third of the spectrum is empty, as in audio recorded at `estimated_rate` under
+    /// a header declaring `declared_rate`, the speech being slowed down.
+    MislabeledRate {
+        declared_rate: u32,
+        estimated_rate: u32,
+    },
+    /// Noise-like samples that turn into audio once their bytes are swapped: 16-bit PCM of the
+    /// other byte order.
+    ByteSwapped,
+    /// Flat spectrum and samples spread over the full scale, which swapping the bytes does not
+    /// fix, e.g. raw data of another format behind a WAV header.
+    NoiseLike,
+}
+
+impl std::fmt::Display for FormatIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MislabeledRate {
+                declared_rate,
+                estimated_rate,
+            } => write!(
+                f,
+                "the header declares {declared_rate} Hz but the spectrum is that of \
+                 {estimated_rate} Hz audio"
+            ),
+            Self::ByteSwapped => write!(f, "the samples are 16-bit PCM of the wrong byte order"),
+            Self::NoiseLike => write!(f, "the samples are noise, not audio of the declared format"),
+        }
+    }
+}
+
+/// [`FormatIssue`] of a decoded WAV file, with whether its samples were reinterpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FormatCheck {
+    pub issue: FormatIssue,
+    pub corrected: bool,
+}
+
+/// Power spectrum of `samples` averaged over Hann windowed blocks of [`FORMAT_FFT_SIZE`], from
+/// the first bin to the Nyquist frequency.
+fn average_power_spectrum(samples: &[f32]) -> Vec<f64> {
+    let n = FORMAT_FFT_SIZE;
+    let plan = FftPlan::<f64>::new(n);
+    let hann: Vec<f64> = (0..n)
+        .map(|i| 0.5 - 0.5 * (2. * std::f64::consts::PI * i as f64 / n as f64).cos())
+        .collect();
+    let blocks = samples.len() / n;
+    let mut power = vec![0.; n / 2];
+    for block in samples
+        .chunks_exact(n)
+        .step_by(usize::max(1, blocks / FORMAT_MAX_BLOCKS))
+    {
+        let windowed: Vec<f64> = block
+            .iter()
+            .zip(&hann)
+            .map(|(&x, w)| x as f64 * w)
+            .collect();
+        let spectrum = plan.transform(&windowed);
+        for (p, bin) in power.iter_mut().zip(spectrum[2..].chunks_exact(2)) {
+            *p += bin[0] * bin[0] + bin[1] * bin[1];
+        }
+    }
+    power
+}
+
+/// Whether `samples` of spectrum `power` look like white noise: a flat spectrum and samples
+/// spread over the full scale.
+fn is_noise_like(samples: &[f32], power: &[f64]) -> bool {
+    let mean = power.iter().sum::<f64>() / power.len() as f64;
+    let log_mean = power.iter().map(|p| p.max(1e-30).ln()).sum::<f64>() / power.len() as f64;
+    let flatness = log_mean.exp() / mean;
+    let loud = samples.iter().filter(|x| x.abs() > 0.5).count();
+    flatness > NOISE_FLATNESS && loud as f64 > NOISE_LOUD_FRACTION * samples.len() as f64
+}
+
+/// Samples of 16-bit PCM in `[-1, 1]` with the bytes of every sample swapped.
+pub fn swap_bytes_16(samples: &[f32]) -> Vec<f32> {
+    samples
+        .iter()
+        .map(|&x| {
+            let sample = (x * 32768.).round().clamp(-32768., 32767.) as i16;
+            sample.swap_bytes() as f32 / 32768.
+        })
+        .collect()
+}
+
+/// Checks mono `samples` decoded from a WAV file declaring `declared_rate` for the common
+/// mistakes of the export pipelines, which otherwise give garbage transcripts without errors.
+///
+/// Noise-like samples, with a flat spectrum and spread over the full scale, are
+/// [`FormatIssue::ByteSwapped`] when swapping their bytes turns them into audio and
+/// [`FormatIssue::NoiseLike`] otherwise. The samples whose top third of the spectrum is empty
+/// are [`FormatIssue::MislabeledRate`], the true rate being estimated from the 99% rolloff
+/// frequency, assumed to be 4 kHz for speech, and rounded to a common rate at least 1.5 times
+/// the declared one, below which the top third would not be empty. Tonal audio, e.g. a test
+/// tone, whose rolloff says nothing of the rate, is not reported.
+///
+/// `None` when the samples look right, or are too short or too quiet to tell.
+pub fn check_format(samples: &[f32], declared_rate: u32) -> Option<FormatIssue> {
+    if samples.len() < FORMAT_FFT_SIZE || peak(samples) < db_to_amplitude(FORMAT_MIN_DBFS) {
+        return None;
+    }
+    let power = average_power_spectrum(samples);
+    if is_noise_like(samples, &power) {
+        let swapped = swap_bytes_16(samples);
+        return Some(
+            if is_noise_like(&swapped, &average_power_spectrum(&swapped)) {
+                FormatIssue::NoiseLike
+            } else {
+                FormatIssue::ByteSwapped
+            },
+        );
+    }
+    let total: f64 = power.iter().sum();
+    let top_third: f64 = power[power.len() * 2 / 3..].iter().sum();
+    if top_third >= EMPTY_BAND_ENERGY * total {
+        return None;
+    }
+    let mut cumulative = 0.;
+    let rolloff_bin = power
+        .iter()
+        .position(|p| {
+            cumulative += p;
+            cumulative >= ROLLOFF_ENERGY * total
+        })
+        .unwrap_or(power.len() - 1)
+        + 1;
+    let below_rolloff = &power[..rolloff_bin];
+    let strongest = below_rolloff.iter().copied().fold(0., f64::max);
+    let occupied = below_rolloff
+        .iter()
+        .filter(|&&p| p >= OCCUPIED_BIN_POWER * strongest)
+        .count();
+    if (occupied as f64) < MIN_OCCUPIED_BINS * rolloff_bin as f64 {
+        return None;
+    }
+    let rolloff_hz = rolloff_bin as f64 * declared_rate as f64 / FORMAT_FFT_SIZE as f64;
+    // Speech recorded at `rate` has its rolloff at `SPEECH_ROLLOFF_HZ * declared / rate`.
+    let estimate = SPEECH_ROLLOFF_HZ * declared_rate as f64 / rolloff_hz;
+    let estimated_rate = COMMON_SAMPLE_RATES
+        .into_iter()
+        .filter(|&rate| 2 * rate >= 3 * declared_rate)
+        .min_by(|&a, &b| {
+            let distance = |rate: u32| (rate as f64 / estimate).ln().abs();
+            distance(a).total_cmp(&distance(b))
+        })?;
+    Some(FormatIssue::MislabeledRate {
+        declared_rate,
+        estimated_rate,
+    })
+}
+
+/// Resamples `samples` from `from` Hz to `to` Hz by windowed sinc interpolation, filtered
+/// below the lower of the two Nyquist frequencies.
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = to as f64 / from as f64;
+    // Of the Nyquist frequency of the input.
+    let cutoff = f64::min(1., ratio);
+    let half_width = RESAMPLE_ZEROS / cutoff;
+    let len = (samples.len() as f64 * ratio).round() as usize;
+    (0..len)
+        .map(|i| {
+            let t = i as f64 / ratio;
+            let first = (t - half_width).ceil().max(0.) as usize;
+            let last = usize::min(samples.len() - 1, (t + half_width).floor() as usize);
+            (first..=last)
+                .map(|k| {
+                    let d = t - k as f64;
+                    let x = std::f64::consts::PI * d * cutoff;
+                    let sinc = if x == 0. { 1. } else { x.sin() / x };
+                    let window = 0.5 + 0.5 * (std::f64::consts::PI * d / half_width).cos();
+                    samples[k] as f64 * cutoff * sinc * window
+                })
+                .sum::<f64>() as f32
+        })
+        .collect()
+}
+
+fn hz_to_mel(f: f64) -> f64 {
+    // Slaney scale: linear below 1kHz, logarithmic above.
+    let f_sp = 200.0 / 3.0;
+    let min_log_hz = 1000.0;
+    let min_log_mel = min_log_hz / f_sp;
+    let logstep = 6.4f64.ln() / 27.0;
+    if f >= min_log_hz {
+        min_log_mel + (f / min_log_hz).ln() / logstep
+    } else {
+        f / f_sp
+    }
+}
+
+fn mel_to_hz(m: f64) -> f64 {
+    let f_sp = 200.0 / 3.0;
+    let min_log_hz = 1000.0;
+    let min_log_mel = min_log_hz / f_sp;
+    let logstep = 6.4f64.ln() / 27.0;
+    if m >= min_log_mel {
+        min_log_hz * (logstep * (m - min_log_mel)).exp()
+    } else {
+        f_sp * m
+    }
+}
+
+/// Slaney-normalized mel filterbank of shape `(n_mels, 1 + n_fft / 2)`, laid out row-major,
+/// matching `librosa.filters.mel` which produced the filters shipped with whisper.
+pub fn mel_filters(sample_rate: usize, n_fft: usize, n_mels: usize) -> Vec<f32> {
+    let n_freqs = 1 + n_fft / 2;
+    let fft_freqs: Vec<f64> = (0..n_freqs)
+        .map(|i| i as f64 * sample_rate as f64 / n_fft as f64)
+        .collect();
+    let max_mel = hz_to_mel(sample_rate as f64 / 2.0);
+    let mel_freqs: Vec<f64> = (0..n_mels + 2)
+        .map(|i| mel_to_hz(max_mel * i as f64 / (n_mels + 1) as f64))
+        .collect();
+
+    let mut filters = vec![0f32; n_mels * n_freqs];
+    for i in 0..n_mels {
+        let (lower, center, upper) = (mel_freqs[i], mel_freqs[i + 1], mel_freqs[i + 2]);
+        let enorm = 2.0 / (upper - lower);
+        for (j, &f) in fft_freqs.iter().enumerate() {
+            let rising = (f - lower) / (center - lower);
+            let falling = (upper - f) / (upper - center);
+            let weight = f64::max(0.0, f64::min(rising, falling));
+            filters[i * n_freqs + j] = (weight * enorm) as f32;
+        }
+    }
+    filters
 }
@@ -0,0 +1,150 @@
+//! Soft boosting of user vocabulary, e.g. product names the model does not know: the logits of
+//! the tokens that start a phrase, or continue the part of it just sampled, are raised.
+//!
+//! The phrases are tokenized once into a trie, which every decoding step walks from the last
+//! tokens sampled, without allocating.
+
+use crate::error::WhisperError;
+
+use tokenizers::Tokenizer;
+
+struct Edge {
+    token: u32,
+    node: usize,
+    /// Strongest boost of the phrases going through the edge.
+    boost: f32,
+}
+
+/// Node of the trie, its edges sorted by token.
+#[derive(Default)]
+struct Node {
+    edges: Vec<Edge>,
+}
+
+/// Trie of the token sequences of the boosted phrases.
+pub struct PhraseBooster {
+    /// The root first.
+    nodes: Vec<Node>,
+    /// Tokens of the longest sequence.
+    max_depth: usize,
+    /// Token, match depth and boost of the current step, reused across the steps.
+    scratch: Vec<(u32, usize, f32)>,
+}
+
+impl Default for PhraseBooster {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+            max_depth: 0,
+            scratch: vec![],
+        }
+    }
+}
+
+impl PhraseBooster {
+    /// Trie of the token sequences with their additive logit boosts. The empty sequences and
+    /// the zero boosts are left out.
+    pub fn new(sequences: &[(Vec<u32>, f32)]) -> Self {
+        let mut booster = Self::default();
+        for (tokens, boost) in sequences {
+            if tokens.is_empty() || *boost == 0. {
+                continue;
+            }
+            let mut node = 0;
+            for &token in tokens {
+                let edges = &booster.nodes[node].edges;
+                node = match edges.binary_search_by_key(&token, |edge| edge.token) {
+                    Ok(i) => {
+                        let edge = &mut booster.nodes[node].edges[i];
+                        // The strongest boost wins, positive or negative.
+                        if boost.abs() > edge.boost.abs() {
+                            edge.boost = *boost;
+                        }
+                        edge.node
+                    }
+                    Err(i) => {
+                        let child = booster.nodes.len();
+                        booster.nodes[node].edges.insert(
+                            i,
+                            Edge {
+                                token,
+                                node: child,
+                                boost: *boost,
+                            },
+                        );
+                        booster.nodes.push(Node::default());
+                        child
+                    }
+                };
+            }
+            booster.max_depth = booster.max_depth.max(tokens.len());
+        }
+        booster
+    }
+
+    /// Trie of the `phrases` tokenized with and without a leading space, the spelling inside a
+    /// sentence and at the start of a segment. The sequences with special tokens, from `eot`
+    /// up, are left out.
+    pub fn for_tokenizer(
+        phrases: &[(String, f32)],
+        tokenizer: &Tokenizer,
+        eot: u32,
+    ) -> Result<Self, WhisperError> {
+        let mut sequences: Vec<(Vec<u32>, f32)> = vec![];
+        for (phrase, boost) in phrases {
+            let phrase = phrase.trim();
+            for text in [phrase.to_string(), format!(" {phrase}")] {
+                let tokens = tokenizer.encode(text, false)?.get_ids().to_vec();
+                if tokens.iter().all(|&token| token < eot) {
+                    sequences.push((tokens, *boost));
+                }
+            }
+        }
+        Ok(Self::new(&sequences))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes[0].edges.is_empty()
+    }
+
+    /// Node reached from the root by `tokens`.
+    fn walk(&self, tokens: &[u32]) -> Option<usize> {
+        tokens.iter().try_fold(0, |node, token| {
+            let edges = &self.nodes[node].edges;
+            let i = edges.binary_search_by_key(token, |edge| edge.token).ok()?;
+            Some(edges[i].node)
+        })
+    }
+
+    /// Boosts of the tokens that can follow the `sampled` tokens, sorted by token. A token
+    /// continuing a match of `depth` tokens gets the boost of its phrase divided by
+    /// `depth + 1`, so that a wrong phrase is not completed at all costs. The deepest match
+    /// wins when a token continues several.
+    pub fn boosts(&mut self, sampled: &[u32]) -> impl Iterator<Item = (u32, f32)> + '_ {
+        self.scratch.clear();
+        let longest = usize::min(self.max_depth.saturating_sub(1), sampled.len());
+        for depth in 0..=longest {
+            let Some(node) = self.walk(&sampled[sampled.len() - depth..]) else {
+                continue;
+            };
+            let scale = 1. / (depth + 1) as f32;
+            for edge in &self.nodes[node].edges {
+                self.scratch.push((edge.token, depth, edge.boost * scale));
+            }
+        }
+        self.scratch
+            .sort_unstable_by_key(|&(token, depth, _)| (token, std::cmp::Reverse(depth)));
+        self.scratch.dedup_by_key(|(token, _, _)| *token);
+        self.scratch.iter().map(|&(token, _, boost)| (token, boost))
+    }
+
+    /// Adds the [`PhraseBooster::boosts`] following `sampled` to `logits`, the suppressed
+    /// tokens staying at `-inf`.
+    pub fn apply(&mut self, sampled: &[u32], logits: &mut [f32]) {
+        for (token, boost) in self.boosts(sampled) {
+            if let Some(logit) = logits.get_mut(token as usize) {
+                *logit += boost;
+            }
+        }
+    }
+}
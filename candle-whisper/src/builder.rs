@@ -0,0 +1,794 @@
+use crate::{
+    alignment::AlignmentDecoder,
+    audio,
+    error::WhisperError,
+    half_model, languages,
+    logging::{DefaultLogger, Logger},
+    logic::{
+        m, Config, DecodeOptions, Decoder, LoadProgress, LoadStage, Model, ModelData, Task,
+        MULTILINGUAL_VOCAB_SIZE,
+    },
+    model_info::ModelInfo,
+    model_profile::ModelProfile,
+    weights::{verify_weights, WeightManifest},
+};
+
+use anyhow::Context;
+use candle_core::{
+    quantized::{gguf_file, GgmlDType, QStorage, QTensor},
+    safetensors::Load,
+    DType, Device,
+};
+use candle_nn::VarBuilder;
+use std::{rc::Rc, sync::Arc};
+use tokenizers::Tokenizer;
+
+/// Number of timestamp tokens, from `<|0.00|>` to `<|30.00|>`.
+const N_TIMESTAMP_TOKENS: usize = 1501;
+
+/// Amount of consumed weight bytes after which the weights buffer is shrunk while loading.
+const RELEASE_CHUNK_BYTES: usize = 64 << 20;
+
+/// Default seed of the sampling RNG used at non-zero temperatures.
+pub const DEFAULT_SEED: u64 = 299792458;
+
+/// Where the model weights are read from.
+enum Weights {
+    Safetensors(Vec<u8>),
+    Gguf(Vec<u8>),
+    /// Safetensors or GGUF file, memory-mapped when possible.
+    #[cfg(not(target_arch = "wasm32"))]
+    File(std::path::PathBuf),
+}
+
+/// Where the tokenizer comes from.
+enum TokenizerSource {
+    Bytes(Vec<u8>),
+    /// Parsed once with [`parse_tokenizer`] and shared by several decoders.
+    Shared(Arc<Tokenizer>),
+}
+
+/// Fluent construction of a [`Decoder`], validating all the provided pieces at once in
+/// [`DecoderBuilder::build`].
+pub struct DecoderBuilder {
+    weights: Vec<Weights>,
+    tokenizer: Option<TokenizerSource>,
+    config: Option<Vec<u8>>,
+    mel_filters: Option<Vec<u8>>,
+    language: Option<String>,
+    task: Task,
+    timestamps: bool,
+    is_multilingual: Option<bool>,
+    seed: u64,
+    device: Device,
+    dtype: Option<DType>,
+    logger: Rc<dyn Logger>,
+    warm_up_on_load: bool,
+    quantize_on_load: Option<GgmlDType>,
+    manifest: Option<WeightManifest>,
+    options: Option<DecodeOptions>,
+    errors: Vec<String>,
+}
+
+impl Default for DecoderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DecoderBuilder {
+    pub fn new() -> Self {
+        Self {
+            weights: vec![],
+            tokenizer: None,
+            config: None,
+            mel_filters: None,
+            language: None,
+            task: Task::Transcribe,
+            timestamps: false,
+            is_multilingual: None,
+            seed: DEFAULT_SEED,
+            device: Device::Cpu,
+            dtype: None,
+            logger: Rc::new(DefaultLogger),
+            warm_up_on_load: false,
+            quantize_on_load: None,
+            manifest: None,
+            options: None,
+            errors: vec![],
+        }
+    }
+
+    pub fn weights_safetensors(mut self, weights: Vec<u8>) -> Self {
+        self.weights.push(Weights::Safetensors(weights));
+        self
+    }
+
+    pub fn weights_gguf(mut self, weights: Vec<u8>) -> Self {
+        self.weights.push(Weights::Gguf(weights));
+        self
+    }
+
+    /// Safetensors or GGUF weights file, the format is detected from the extension or the
+    /// file header. The file is memory-mapped rather than read.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn weights_file(mut self, path: impl AsRef<std::path::Path>) -> Self {
+        self.weights
+            .push(Weights::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tokenizer_file(mut self, path: impl AsRef<std::path::Path>) -> Self {
+        self.tokenizer = self
+            .read_file("tokenizer", path.as_ref())
+            .map(TokenizerSource::Bytes);
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn config_file(mut self, path: impl AsRef<std::path::Path>) -> Self {
+        self.config = self.read_file("config", path.as_ref());
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn mel_filters_file(mut self, path: impl AsRef<std::path::Path>) -> Self {
+        self.mel_filters = self.read_file("mel filters", path.as_ref());
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_file(&mut self, what: &str, path: &std::path::Path) -> Option<Vec<u8>> {
+        match std::fs::read(path) {
+            Ok(bytes) => Some(bytes),
+            Err(err) => {
+                self.errors
+                    .push(format!("unable to read {what} {}: {err}", path.display()));
+                None
+            }
+        }
+    }
+
+    pub fn tokenizer(mut self, tokenizer: Vec<u8>) -> Self {
+        self.tokenizer = Some(TokenizerSource::Bytes(tokenizer));
+        self
+    }
+
+    /// Tokenizer already parsed with [`parse_tokenizer`], shared with the other decoders built
+    /// from it rather than parsed again.
+    pub fn tokenizer_shared(mut self, tokenizer: Arc<Tokenizer>) -> Self {
+        self.tokenizer = Some(TokenizerSource::Shared(tokenizer));
+        self
+    }
+
+    pub fn config(mut self, config: Vec<u8>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Safetensors file holding the `mel_{num_mel_bins}` filterbank. When not provided the
+    /// filterbank is computed.
+    pub fn mel_filters(mut self, mel_filters: Option<Vec<u8>>) -> Self {
+        self.mel_filters = mel_filters;
+        self
+    }
+
+    /// Language to decode in, a code, a name or an alias, see `languages::canonicalize`.
+    pub fn language(mut self, language: Option<&str>) -> Self {
+        self.language = language.map(|l| languages::canonicalize(l).unwrap_or(l).to_string());
+        self
+    }
+
+    pub fn task(mut self, task: Task) -> Self {
+        self.task = task;
+        self
+    }
+
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Overrides the multilingual detection, which otherwise relies on the vocabulary size of
+    /// the config.
+    pub fn multilingual(mut self, is_multilingual: bool) -> Self {
+        self.is_multilingual = Some(is_multilingual);
+        self
+    }
+
+    /// Calls [`Decoder::warm_up`] once the model is loaded, its report is then available with
+    /// [`Decoder::warmup_report`].
+    pub fn warm_up_on_load(mut self, warm_up: bool) -> Self {
+        self.warm_up_on_load = warm_up;
+        self
+    }
+
+    /// Quantizes the weight matrices of safetensors weights to `dtype` while loading, the
+    /// model then runs as a quantized one. The full-precision tensors are released one by one
+    /// so that the weights and their quantized copy never coexist in full.
+    pub fn quantize_on_load(mut self, dtype: Option<GgmlDType>) -> Self {
+        self.quantize_on_load = dtype;
+        self
+    }
+
+    /// Digests the safetensors weights provided in memory are verified against while loading,
+    /// see [`verify_weights`]. Their structure is checked in any case.
+    pub fn manifest(mut self, manifest: Option<WeightManifest>) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Options of the built decoder, instead of the defaults of its [`ModelProfile`].
+    pub fn options(mut self, options: Option<DecodeOptions>) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Device the model runs on, the CPU by default. Quantized models only run on the CPU.
+    pub fn device(mut self, device: Device) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Runs the model on the given CUDA GPU when available, on the CPU otherwise.
+    #[cfg(feature = "cuda")]
+    pub fn cuda_if_available(mut self, ordinal: usize) -> Self {
+        match Device::cuda_if_available(ordinal) {
+            Ok(device) => self.device = device,
+            Err(err) => self
+                .errors
+                .push(format!("unable to use CUDA device {ordinal}: {err}")),
+        }
+        self
+    }
+
+    /// Dtype of the normal model, `m::DTYPE` by default. The weights are converted on load.
+    ///
+    /// `f32` is supported everywhere and is the fastest on CPU, `f16` halves the memory used
+    /// on GPU, `bf16` is only supported on GPU. Quantized models always compute in `f32`.
+    pub fn dtype(mut self, dtype: DType) -> Self {
+        self.dtype = Some(dtype);
+        self
+    }
+
+    /// Destination of the log messages of the loading and of the built decoder,
+    /// [`DefaultLogger`] by default.
+    pub fn logger(mut self, logger: Rc<dyn Logger>) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    fn validate(&self) -> Result<(), WhisperError> {
+        let mut errors = self.errors.clone();
+        match self.weights.len() {
+            0 => errors.push("missing model weights".to_string()),
+            1 => {}
+            _ => errors.push("several model weights were provided".to_string()),
+        }
+        if self.tokenizer.is_none() {
+            errors.push("missing tokenizer".to_string())
+        }
+        if self.config.is_none() {
+            errors.push("missing config".to_string())
+        }
+        if self.manifest.is_some() && !matches!(self.weights.as_slice(), [Weights::Safetensors(_)])
+        {
+            errors.push("a manifest requires safetensors weights provided in memory".to_string())
+        }
+        if let Some(language) = &self.language {
+            if !languages::is_supported(language) {
+                errors.push(format!("unsupported language {language}"))
+            }
+        }
+        if self.is_multilingual == Some(false) {
+            if self.language.is_some() {
+                errors.push("a language cannot be set for non-multilingual models".to_string())
+            }
+            if matches!(self.task, Task::Translate | Task::Both) {
+                errors.push("the translate task requires a multilingual model".to_string())
+            }
+        }
+        match self.dtype {
+            None | Some(DType::F32) | Some(DType::F16) => {}
+            Some(DType::BF16) if !self.device.is_cpu() => {}
+            Some(DType::BF16) => {
+                errors.push(format!("unsupported dtype BF16 on {:?}", self.device))
+            }
+            Some(dtype) => errors.push(format!("unsupported dtype {dtype:?}")),
+        }
+        if let Some(dtype) = self.quantize_on_load {
+            if !is_block_quantized(dtype) {
+                errors.push(format!("cannot quantize on load to {dtype:?}"))
+            }
+        }
+        if !errors.is_empty() {
+            return Err(WhisperError::InvalidConfig {
+                reason: errors.join(", "),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<Decoder, WhisperError> {
+        self.build_with_progress(&mut |_| {})
+    }
+
+    pub fn build_with_progress(
+        self,
+        progress: &mut dyn FnMut(&LoadProgress),
+    ) -> Result<Decoder, WhisperError> {
+        self.validate()?;
+        self.load(progress).map_err(WhisperError::model_load)
+    }
+
+    fn load(self, progress: &mut dyn FnMut(&LoadProgress)) -> anyhow::Result<Decoder> {
+        let mut bytes_reported = 0;
+        let mut report = |stage, tensors_loaded, tensors_total, bytes_allocated: usize| {
+            // The quantized tensors are counted as they are quantized then as they are loaded.
+            bytes_reported = bytes_reported.max(bytes_allocated);
+            progress(&LoadProgress {
+                stage,
+                tensors_loaded,
+                tensors_total,
+                bytes_allocated: bytes_reported,
+            })
+        };
+        let device = self.device;
+        let logger = self.logger;
+        let dtype = self.dtype.unwrap_or(m::DTYPE);
+        // The buffers of the tokenizer, the config and the mel filters are dropped once parsed,
+        // before the weights are loaded.
+        let tokenizer = match self.tokenizer {
+            Some(TokenizerSource::Shared(tokenizer)) => tokenizer,
+            Some(TokenizerSource::Bytes(bytes)) => parse_tokenizer(&bytes)?,
+            None => unreachable!("validated tokenizer"),
+        };
+        report(LoadStage::Tokenizer, 0, 0, 0);
+        let config: Config = serde_json::from_slice(&self.config.unwrap_or_default())?;
+        report(LoadStage::Config, 0, 0, 0);
+        let mel_filters = match self.mel_filters {
+            Some(mel_filters) => {
+                let mel_filters = safetensors::tensor::SafeTensors::deserialize(&mel_filters)?;
+                let name = format!("mel_{}", config.num_mel_bins);
+                let view = mel_filters.tensor(&name)?;
+                log_at!(logger, Debug, "mel_filters shape '{:?}'", view.shape());
+                if view.dtype() == safetensors::Dtype::F32 {
+                    view.data()
+                        .chunks_exact(4)
+                        .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+                        .collect()
+                } else {
+                    view.load(&device)?
+                        .flatten_all()?
+                        .to_dtype(DType::F32)?
+                        .to_vec1::<f32>()?
+                }
+            }
+            None => audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, config.num_mel_bins),
+        };
+        report(LoadStage::MelFilters, 0, 0, 0);
+        let is_multilingual = self
+            .is_multilingual
+            .unwrap_or(config.vocab_size >= MULTILINGUAL_VOCAB_SIZE);
+
+        let weights = self.weights.into_iter().next().expect("validated weights");
+        let mut model_info = match &weights {
+            Weights::Gguf(weights) => ModelInfo::from_gguf(weights)?,
+            Weights::Safetensors(weights) => {
+                let report = verify_weights(weights, self.manifest.as_ref())?;
+                log_at!(logger, Debug, "verified weights: {report:?}");
+                ModelInfo {
+                    weights_sha256: self.manifest.as_ref().map(WeightManifest::digest),
+                    ..ModelInfo::from_safetensors(weights)?
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Weights::File(path) => ModelInfo::from_file(path)
+                .with_context(|| format!("invalid weights file {}", path.display()))?,
+        };
+        if self.quantize_on_load.is_some() && model_info.quantized {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the weights are already quantized".to_string(),
+            }
+            .into());
+        }
+        if model_info.quantized || self.quantize_on_load.is_some() {
+            if !device.is_cpu() {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("quantized models only run on the CPU, not on {device:?}"),
+                }
+                .into());
+            }
+            if dtype != DType::F32 {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("quantized models compute in F32, not in {dtype:?}"),
+                }
+                .into());
+            }
+        }
+        let mut issues = model_info.config_mismatches(&config);
+        issues.extend(tokenizer_mismatches(&tokenizer, &config, is_multilingual));
+        if !issues.is_empty() {
+            return Err(WhisperError::ModelMismatch { issues }.into());
+        }
+        log_at!(
+            logger,
+            Debug,
+            "{} tensors, quantization {:?}",
+            model_info.tensor_count,
+            model_info.quantization
+        );
+        let tensors_total = model_info.tensor_count;
+        report(LoadStage::Weights, 0, tensors_total, 0);
+        let weights = match (self.quantize_on_load, weights) {
+            (None, weights) => weights,
+            (Some(qdtype), weights) => {
+                let weights = match weights {
+                    Weights::Safetensors(weights) => weights,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    Weights::File(path) => std::fs::read(&path)
+                        .with_context(|| format!("unable to read {}", path.display()))?,
+                    Weights::Gguf(_) => unreachable!("GGUF weights are quantized"),
+                };
+                let gguf = quantize_safetensors(weights, qdtype, &mut |loaded, allocated| {
+                    report(LoadStage::Weights, loaded, tensors_total, allocated)
+                })?;
+                model_info = ModelInfo {
+                    source_parameter_bytes: Some(model_info.parameter_bytes),
+                    weights_sha256: model_info.weights_sha256.take(),
+                    ..ModelInfo::from_gguf(&gguf)?
+                };
+                log_at!(
+                    logger,
+                    Debug,
+                    "quantized {} bytes of weights to {}",
+                    model_info.source_parameter_bytes.unwrap_or_default(),
+                    model_info.parameter_bytes
+                );
+                Weights::Gguf(gguf)
+            }
+        };
+        model_info.profile = ModelProfile::classify(&config);
+        log_at!(logger, Debug, "model profile {}", model_info.profile.name());
+        let options = self.options.unwrap_or_else(|| {
+            model_info
+                .profile
+                .options(is_multilingual, model_info.quantized)
+        });
+        let (model, alignment) = match weights {
+            Weights::Gguf(weights) => {
+                let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf_buffer(
+                    &weights, &device,
+                )?;
+                // The quantized tensors own a copy of their data.
+                drop(weights);
+                report(
+                    LoadStage::Weights,
+                    tensors_total,
+                    tensors_total,
+                    model_info.materialized_bytes(dtype),
+                );
+                (
+                    Model::Quantized(m::quantized_model::Whisper::load(&vb, config)?),
+                    None,
+                )
+            }
+            Weights::Safetensors(mut weights) => {
+                let (header_len, metadata) =
+                    safetensors::tensor::SafeTensors::read_metadata(&weights)?;
+                let data_start = 8 + header_len;
+                let mut infos: Vec<_> = metadata
+                    .tensors()
+                    .into_iter()
+                    .map(|(name, info)| (name, info.clone()))
+                    .collect();
+                // The tensors are materialized from the end of the buffer so that the consumed
+                // bytes can be released as the loading goes, the buffer and the tensors never
+                // coexist in full.
+                infos.sort_by_key(|(_, info)| std::cmp::Reverse(info.data_offsets.0));
+                let mut tensors = std::collections::HashMap::new();
+                let mut bytes_allocated = 0;
+                let mut bytes_released = 0;
+                // The buffer of the small models is released by chunks as well.
+                let release_chunk = RELEASE_CHUNK_BYTES.min(weights.len() / 8);
+                for (name, info) in infos {
+                    let (start, end) = info.data_offsets;
+                    let data = &weights[data_start + start..data_start + end];
+                    let view = safetensors::tensor::TensorView::new(info.dtype, info.shape, data)?;
+                    let tensor = view.load(&device)?.to_dtype(dtype)?;
+                    bytes_allocated += tensor.elem_count() * dtype.size_in_bytes();
+                    tensors.insert(name, tensor);
+                    bytes_released += weights.len() - (data_start + start);
+                    weights.truncate(data_start + start);
+                    if bytes_released > release_chunk {
+                        weights.shrink_to_fit();
+                        bytes_released = 0;
+                    }
+                    report(
+                        LoadStage::Weights,
+                        tensors.len(),
+                        tensors_total,
+                        bytes_allocated,
+                    );
+                }
+                drop(weights);
+                let vb = VarBuilder::from_tensors(tensors, dtype, &device);
+                let alignment = AlignmentDecoder::load(vb.pp("model.decoder"), &config)?;
+                (normal_model(&vb, config)?, Some(alignment))
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Weights::File(path) if model_info.quantized => {
+                let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(
+                    &path, &device,
+                )
+                .with_context(|| format!("unable to load {}", path.display()))?;
+                report(
+                    LoadStage::Weights,
+                    tensors_total,
+                    tensors_total,
+                    model_info.materialized_bytes(dtype),
+                );
+                (
+                    Model::Quantized(m::quantized_model::Whisper::load(&vb, config)?),
+                    None,
+                )
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Weights::File(path) => {
+                // Safety: the file is expected not to be modified while the model is alive.
+                let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[&path], dtype, &device) }
+                    .with_context(|| format!("unable to map {}", path.display()))?;
+                report(
+                    LoadStage::Weights,
+                    tensors_total,
+                    tensors_total,
+                    model_info.materialized_bytes(dtype),
+                );
+                let alignment = AlignmentDecoder::load(vb.pp("model.decoder"), &config)?;
+                (normal_model(&vb, config)?, Some(alignment))
+            }
+        };
+        log_at!(logger, Info, "model loaded");
+        report(
+            LoadStage::Done,
+            tensors_total,
+            tensors_total,
+            model_info.materialized_bytes(dtype),
+        );
+
+        let mut decoder = Decoder::new(
+            model,
+            alignment,
+            model_info,
+            tokenizer,
+            mel_filters,
+            device,
+            dtype,
+            Some(self.task),
+            self.language,
+            is_multilingual,
+            self.timestamps,
+            self.seed,
+            logger,
+        )?;
+        decoder.set_options(options)?;
+        if self.warm_up_on_load {
+            decoder.warm_up()?;
+        }
+        Ok(decoder)
+    }
+}
+
+/// Model of the non-quantized weights, the candle model in `f32`, [`half_model`] otherwise.
+fn normal_model(vb: &VarBuilder, config: Config) -> candle_core::Result<Model> {
+    Ok(match vb.dtype() {
+        DType::F32 => Model::Normal(m::model::Whisper::load(vb, config)?),
+        _ => Model::Half(half_model::Whisper::load(vb, config)?),
+    })
+}
+
+/// Whether `dtype` is a block quantization the quantized model can run, as opposed to the
+/// float dtypes and the `Q8_1` and `Q8K` dtypes used for the activations only.
+fn is_block_quantized(dtype: GgmlDType) -> bool {
+    use GgmlDType::*;
+    matches!(
+        dtype,
+        Q4_0 | Q4_1 | Q5_0 | Q5_1 | Q8_0 | Q2K | Q3K | Q4K | Q5K | Q6K
+    )
+}
+
+/// Parses a `tokenizer.json`, to be shared by several decoders with
+/// [`DecoderBuilder::tokenizer_shared`].
+pub fn parse_tokenizer(bytes: &[u8]) -> Result<Arc<Tokenizer>, WhisperError> {
+    Ok(Arc::new(Tokenizer::from_bytes(bytes)?))
+}
+
+/// Parses a quantization name such as `q8_0` or `Q4K`.
+pub fn parse_ggml_dtype(name: &str) -> Option<GgmlDType> {
+    use GgmlDType::*;
+    let dtype = match name.to_ascii_lowercase().replace('_', "").as_str() {
+        "f32" => F32,
+        "f16" => F16,
+        "q40" => Q4_0,
+        "q41" => Q4_1,
+        "q50" => Q5_0,
+        "q51" => Q5_1,
+        "q80" => Q8_0,
+        "q81" => Q8_1,
+        "q2k" => Q2K,
+        "q3k" => Q3K,
+        "q4k" => Q4K,
+        "q5k" => Q5K,
+        "q6k" => Q6K,
+        "q8k" => Q8K,
+        _ => return None,
+    };
+    Some(dtype)
+}
+
+/// Whether the tensor is the weight matrix of a linear layer, which the quantized model
+/// multiplies without dequantizing it. The other tensors are dequantized when the model is
+/// loaded and are kept in `f32`.
+fn is_linear_weight(name: &str) -> bool {
+    const LINEARS: [&str; 6] = ["q_proj", "k_proj", "v_proj", "out_proj", "fc1", "fc2"];
+    name.strip_suffix(".weight")
+        .and_then(|name| name.rsplit('.').next())
+        .is_some_and(|layer| LINEARS.contains(&layer))
+}
+
+/// Converts safetensors weights to an in-memory GGUF file, quantizing the linear weight
+/// matrices to `qdtype`. As when loading the normal model, the tensors are taken from the end
+/// of the buffer which is truncated as the quantization goes. `progress` receives the number of
+/// tensors quantized and the bytes they take.
+fn quantize_safetensors(
+    mut weights: Vec<u8>,
+    qdtype: GgmlDType,
+    progress: &mut dyn FnMut(usize, usize),
+) -> anyhow::Result<Vec<u8>> {
+    let (header_len, metadata) = safetensors::tensor::SafeTensors::read_metadata(&weights)?;
+    let data_start = 8 + header_len;
+    let mut infos: Vec<_> = metadata
+        .tensors()
+        .into_iter()
+        .map(|(name, info)| (name, info.clone()))
+        .collect();
+    infos.sort_by_key(|(_, info)| std::cmp::Reverse(info.data_offsets.0));
+    let mut tensors = Vec::with_capacity(infos.len());
+    let mut bytes_allocated = 0;
+    let mut bytes_released = 0;
+    // The quantized tensors are not much smaller than the weights for the small models, whose
+    // buffer is also released by chunks.
+    let release_chunk = RELEASE_CHUNK_BYTES.min(weights.len() / 8);
+    for (name, info) in infos {
+        let (start, end) = info.data_offsets;
+        let data = &weights[data_start + start..data_start + end];
+        let view = safetensors::tensor::TensorView::new(info.dtype, info.shape, data)?;
+        // The blocks run along the last dimension.
+        let qtensor = match view.shape().last() {
+            Some(&n) if is_linear_weight(&name) && n % qdtype.block_size() == 0 => {
+                QTensor::quantize(&view.load(&Device::Cpu)?, qdtype)
+                    .with_context(|| format!("unable to quantize {name}"))?
+            }
+            _ => {
+                // Converted directly, `QTensor::quantize` would copy the tensor once more.
+                let values: Vec<f32> = if view.dtype() == safetensors::Dtype::F32 {
+                    data.chunks_exact(4)
+                        .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+                        .collect()
+                } else {
+                    view.load(&Device::Cpu)?
+                        .to_dtype(DType::F32)?
+                        .flatten_all()?
+                        .to_vec1()?
+                };
+                QTensor::new(QStorage::Cpu(Box::new(values)), view.shape())?
+            }
+        };
+        bytes_allocated += qtensor.storage_size_in_bytes();
+        tensors.push((name, qtensor));
+        bytes_released += weights.len() - (data_start + start);
+        weights.truncate(data_start + start);
+        if bytes_released > release_chunk {
+            weights.shrink_to_fit();
+            bytes_released = 0;
+        }
+        progress(tensors.len(), bytes_allocated);
+    }
+    drop(weights);
+    let refs: Vec<_> = tensors
+        .iter()
+        .map(|(name, tensor)| (name.as_str(), tensor))
+        .collect();
+    // The header and the padding of each tensor to 32 bytes, a buffer growing past its capacity
+    // would be reallocated to twice the size.
+    let header_bytes: usize = tensors
+        .iter()
+        .map(|(name, tensor)| 8 + name.len() + 4 + 8 * tensor.rank() + 12 + 31 + 31)
+        .sum();
+    let capacity = bytes_allocated + 24 + header_bytes + 31;
+    let mut gguf = std::io::Cursor::new(Vec::with_capacity(capacity));
+    gguf_file::write(&mut gguf, &[], &refs)?;
+    Ok(gguf.into_inner())
+}
+
+/// Inconsistencies between the tokenizer and the config.
+fn tokenizer_mismatches(
+    tokenizer: &Tokenizer,
+    config: &Config,
+    is_multilingual: bool,
+) -> Vec<String> {
+    let mut issues = vec![];
+    let tokenizer_size = tokenizer.get_vocab_size(true);
+    if tokenizer_size > config.vocab_size {
+        issues.push(format!(
+            "tokenizer has {tokenizer_size} tokens but config.json expects {}",
+            config.vocab_size
+        ))
+    }
+    // The timestamp tokens follow `<|notimestamps|>` up to the end of the vocabulary.
+    match tokenizer.token_to_id(m::NO_TIMESTAMPS_TOKEN) {
+        Some(id) if id as usize + 1 + N_TIMESTAMP_TOKENS != config.vocab_size => {
+            issues.push(format!(
+                "tokenizer implies a vocab_size of {} but config.json expects {}",
+                id as usize + 1 + N_TIMESTAMP_TOKENS,
+                config.vocab_size
+            ))
+        }
+        Some(_) => {}
+        None => issues.push(format!(
+            "tokenizer does not define {}",
+            m::NO_TIMESTAMPS_TOKEN
+        )),
+    }
+    let multilingual_model = config.vocab_size >= MULTILINGUAL_VOCAB_SIZE;
+    if is_multilingual && multilingual_model && tokenizer.token_to_id("<|en|>").is_none() {
+        issues
+            .push("the model is multilingual but the tokenizer has no language tokens".to_string())
+    }
+    issues
+}
+
+impl From<ModelData> for DecoderBuilder {
+    fn from(md: ModelData) -> Self {
+        let builder = Self::new()
+            .tokenizer(md.tokenizer)
+            .config(md.config)
+            .mel_filters((!md.mel_filters.is_empty()).then_some(md.mel_filters))
+            .language(md.language.as_deref())
+            .timestamps(md.timestamps)
+            .multilingual(md.is_multilingual)
+            .manifest(md.manifest);
+        let mut builder = if md.quantized {
+            builder.weights_gguf(md.weights)
+        } else {
+            builder.weights_safetensors(md.weights)
+        };
+        match md.dtype.as_deref() {
+            Some("f32") => builder.dtype = Some(DType::F32),
+            Some("f16") => builder.dtype = Some(DType::F16),
+            Some("bf16") => builder.dtype = Some(DType::BF16),
+            Some(dtype) => builder.errors.push(format!("unknown dtype {dtype}")),
+            None => {}
+        }
+        match md.quantize_on_load.as_deref().map(parse_ggml_dtype) {
+            Some(Some(dtype)) => builder.quantize_on_load = Some(dtype),
+            Some(None) => builder.errors.push(format!(
+                "unknown quantization {}",
+                md.quantize_on_load.unwrap_or_default()
+            )),
+            None => {}
+        }
+        match md.task.as_deref().map(str::parse::<Task>) {
+            Some(Ok(task)) => builder.task = task,
+            Some(Err(err)) => builder.errors.push(err.to_string()),
+            None => {}
+        }
+        builder
+    }
+}
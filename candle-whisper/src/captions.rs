@@ -0,0 +1,262 @@
+//! Incremental SRT and WebVTT writers, formatting the cues as the segments are decoded for the
+//! live captions rather than once the transcript is complete.
+
+use crate::{
+    alignment::WordTiming,
+    logic::Segment,
+    segments::{is_speech, start_order},
+};
+use std::collections::HashMap;
+
+/// Segments held back by default to put an out-of-order pair back in order.
+pub const DEFAULT_MAX_REORDER: usize = 1;
+
+/// Cue ready to be formatted.
+struct Cue {
+    number: usize,
+    start: f64,
+    end: f64,
+    text: String,
+    words: Vec<WordTiming>,
+}
+
+/// Orders the pushed segments into numbered cues, see [`SrtWriter`].
+struct CueStream {
+    max_reorder: usize,
+    /// Segments not emitted yet, in start order.
+    pending: Vec<Segment>,
+    /// Cue numbers of the emitted segments by id.
+    numbers: HashMap<usize, usize>,
+}
+
+impl CueStream {
+    fn new(max_reorder: usize) -> Self {
+        Self {
+            max_reorder,
+            pending: vec![],
+            numbers: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, segment: &Segment) -> Vec<Cue> {
+        self.pending.retain(|pending| pending.id != segment.id);
+        if cue_text(segment).is_empty() {
+            return vec![];
+        }
+        if let Some(&number) = self.numbers.get(&segment.id) {
+            return vec![cue(number, segment)];
+        }
+        let index = self
+            .pending
+            .partition_point(|pending| start_order(pending, segment).is_lt());
+        self.pending.insert(index, segment.clone());
+        let excess = self.pending.len().saturating_sub(self.max_reorder);
+        self.emit(excess)
+    }
+
+    fn finish(&mut self) -> Vec<Cue> {
+        self.emit(self.pending.len())
+    }
+
+    /// Numbers and returns the first `count` pending segments.
+    fn emit(&mut self, count: usize) -> Vec<Cue> {
+        self.pending
+            .drain(..count)
+            .map(|segment| {
+                let number = self.numbers.len() + 1;
+                self.numbers.insert(segment.id, number);
+                cue(number, &segment)
+            })
+            .collect()
+    }
+}
+
+/// Text of the cue of a speech segment, empty for the other segments. The cleaned text is used
+/// when available, trimmed and without the blank lines that would end the cue.
+fn cue_text(segment: &Segment) -> String {
+    if !is_speech(segment) {
+        return String::new();
+    }
+    let text = segment.dr.text_clean.as_deref().unwrap_or(&segment.dr.text);
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+fn cue(number: usize, segment: &Segment) -> Cue {
+    Cue {
+        number,
+        start: segment.start,
+        end: segment.start + segment.duration,
+        text: cue_text(segment),
+        words: segment.words.clone(),
+    }
+}
+
+fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Words of the cue each preceded by its start time, clamped to the cue, as a WebVTT
+/// timestamp tag. The words starting with the cue have no tag, the tags having to be after
+/// the start of the cue.
+fn karaoke_text(cue: &Cue) -> String {
+    let mut text = String::new();
+    for word in &cue.words {
+        let core = word.word.trim();
+        if core.is_empty() {
+            continue;
+        }
+        if !text.is_empty() && word.word.starts_with(char::is_whitespace) {
+            text.push(' ');
+        }
+        let start = word.start.clamp(cue.start, cue.end);
+        if start > cue.start && start < cue.end {
+            text.push_str(&format!("<{}>", timestamp(start, '.')));
+        }
+        text.push_str(&escape_vtt(core));
+    }
+    text
+}
+
+/// `HH:MM:SS` followed by `separator` and the milliseconds.
+fn timestamp(seconds: f64, separator: char) -> String {
+    let ms = (seconds.max(0.) * 1000.).round() as u64;
+    let (h, m, s) = (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60);
+    format!("{h:02}:{m:02}:{s:02}{separator}{:03}", ms % 1000)
+}
+
+/// Writes the segments as SRT cues as they are decoded.
+///
+/// Segments pushed out of order are put back in order as long as at most `max_reorder`
+/// segments come before an earlier one, the cues being held back by as many segments; a
+/// segment arriving later than that is emitted out of order. A segment with the id of an
+/// emitted one revises it: its cue is emitted again with the same number, the consumers
+/// keeping the cues by number replace the previous one. The segments without speech are
+/// skipped.
+pub struct SrtWriter {
+    cues: CueStream,
+}
+
+impl Default for SrtWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SrtWriter {
+    /// Writer holding back [`DEFAULT_MAX_REORDER`] segments.
+    pub fn new() -> Self {
+        Self::with_max_reorder(DEFAULT_MAX_REORDER)
+    }
+
+    /// Writer holding back `max_reorder` segments, 0 emitting every cue as soon as pushed.
+    pub fn with_max_reorder(max_reorder: usize) -> Self {
+        Self {
+            cues: CueStream::new(max_reorder),
+        }
+    }
+
+    /// Text of the cues the segment makes ready, possibly empty.
+    pub fn push(&mut self, segment: &Segment) -> String {
+        format_srt(self.cues.push(segment))
+    }
+
+    /// Text of the cues held back, once the last segment was pushed.
+    pub fn finish(&mut self) -> String {
+        format_srt(self.cues.finish())
+    }
+}
+
+fn format_srt(cues: Vec<Cue>) -> String {
+    cues.into_iter()
+        .map(|cue| {
+            format!(
+                "{}\n{} --> {}\n{}\n\n",
+                cue.number,
+                timestamp(cue.start, ','),
+                timestamp(cue.end, ','),
+                cue.text
+            )
+        })
+        .collect()
+}
+
+/// Writes the segments as WebVTT cues as they are decoded, the `WEBVTT` header being part of
+/// the text of the first push. The cues are ordered and revised as with [`SrtWriter`], their
+/// identifier being their number.
+pub struct VttWriter {
+    cues: CueStream,
+    header_written: bool,
+    word_timestamps: bool,
+}
+
+impl Default for VttWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VttWriter {
+    /// Writer holding back [`DEFAULT_MAX_REORDER`] segments.
+    pub fn new() -> Self {
+        Self::with_max_reorder(DEFAULT_MAX_REORDER)
+    }
+
+    /// Writer holding back `max_reorder` segments, 0 emitting every cue as soon as pushed.
+    pub fn with_max_reorder(max_reorder: usize) -> Self {
+        Self {
+            cues: CueStream::new(max_reorder),
+            header_written: false,
+            word_timestamps: false,
+        }
+    }
+
+    /// Karaoke cues for the players highlighting the word being spoken: the words of the
+    /// segments with word timings are preceded by their start time, as
+    /// `<00:00:01.200>word`. The text of these cues is that of the words, without the text
+    /// post-processing; the segments without word timings keep their text.
+    pub fn with_word_timestamps(mut self, word_timestamps: bool) -> Self {
+        self.word_timestamps = word_timestamps;
+        self
+    }
+
+    /// Text of the cues the segment makes ready, preceded by the header on the first push.
+    pub fn push(&mut self, segment: &Segment) -> String {
+        let cues = self.cues.push(segment);
+        self.format(cues)
+    }
+
+    /// Text of the cues held back, and the header when nothing was pushed so that the file is
+    /// valid.
+    pub fn finish(&mut self) -> String {
+        let cues = self.cues.finish();
+        self.format(cues)
+    }
+
+    fn format(&mut self, cues: Vec<Cue>) -> String {
+        let mut out = String::new();
+        if !self.header_written {
+            out.push_str("WEBVTT\n\n");
+            self.header_written = true;
+        }
+        for cue in cues {
+            let text = match self.word_timestamps && !cue.words.is_empty() {
+                true => karaoke_text(&cue),
+                false => escape_vtt(&cue.text),
+            };
+            out.push_str(&format!(
+                "{}\n{} --> {}\n{text}\n\n",
+                cue.number,
+                timestamp(cue.start, '.'),
+                timestamp(cue.end, '.'),
+            ));
+        }
+        out
+    }
+}
@@ -0,0 +1,86 @@
+//! Chunked long-form transcription, the strategy distil-whisper models are trained for: the
+//! audio is split in overlapping chunks decoded independently, and the token sequences of
+//! consecutive chunks are merged on their longest common sequence as in the transformers
+//! pipeline.
+
+use crate::logic::Config;
+
+use serde::{Deserialize, Serialize};
+
+/// Decoder depth up to which a model with a deep encoder is considered distilled.
+const MAX_DISTILLED_DECODER_LAYERS: usize = 4;
+
+/// Encoder depth from which a model with a shallow decoder is considered distilled.
+const MIN_DISTILLED_ENCODER_LAYERS: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkOptions {
+    /// Length of the chunks, at most the 30 seconds of a window.
+    pub chunk_seconds: f64,
+    /// Seconds by which consecutive chunks overlap, less than half a chunk.
+    pub overlap_seconds: f64,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self {
+            chunk_seconds: 30.,
+            overlap_seconds: 5.,
+        }
+    }
+}
+
+/// Whether the model looks like a distil-whisper checkpoint, i.e. has a shallow decoder and a
+/// deep encoder, e.g. 2 decoder layers for 32 encoder layers for distil-large-v3 while the
+/// tiny whisper model has 4 of each.
+pub fn is_distilled(config: &Config) -> bool {
+    config.decoder_layers <= MAX_DISTILLED_DECODER_LAYERS
+        && config.encoder_layers >= MIN_DISTILLED_ENCODER_LAYERS
+}
+
+/// Where the token sequences of two overlapping chunks are merged.
+///
+/// The sequences are slid over each other and the alignment matching the most tokens relative
+/// to its length, with at least two matches and longer alignments winning ties, is cut in its
+/// middle. Returns the number of tokens kept from `left` and the index of the first token kept
+/// from `right`, `(left.len(), 0)` when no alignment matches.
+pub fn merge_point(left: &[u32], right: &[u32]) -> (usize, usize) {
+    let (n, m) = (left.len(), right.len());
+    let mut best = 0.;
+    let mut best_ranges = (n, n, 0, 0);
+    for i in 1..n + m {
+        let left_start = n.saturating_sub(i);
+        let left_stop = usize::min(n, n + m - i);
+        let right_start = i.saturating_sub(n);
+        let right_stop = usize::min(m, i);
+        let matches = left[left_start..left_stop]
+            .iter()
+            .zip(&right[right_start..right_stop])
+            .filter(|(a, b)| a == b)
+            .count();
+        let matching = matches as f64 / i as f64 + i as f64 / 10000.;
+        if matches > 1 && matching > best {
+            best = matching;
+            best_ranges = (left_start, left_stop, right_start, right_stop);
+        }
+    }
+    let (left_start, left_stop, right_start, right_stop) = best_ranges;
+    ((left_start + left_stop) / 2, (right_start + right_stop) / 2)
+}
+
+/// Token sequences of consecutive chunks merged into one.
+pub fn merge_sequences(sequences: &[Vec<u32>]) -> Vec<u32> {
+    let Some((first, rest)) = sequences.split_first() else {
+        return vec![];
+    };
+    let mut merged = vec![];
+    let mut left = first.as_slice();
+    for right in rest {
+        let (left_end, right_start) = merge_point(left, right);
+        merged.extend_from_slice(&left[..left_end]);
+        left = &right[right_start..];
+    }
+    merged.extend_from_slice(left);
+    merged
+}
@@ -0,0 +1,89 @@
+use crate::logic::{m, DecodingResult, Segment};
+
+use serde::{Deserialize, Serialize};
+
+/// Weights of the decoding statistics in the confidence score:
+///
+/// `score = clamp(1 + logprob * avg_logprob - no_speech * no_speech_prob
+///     - compression_ratio * max(0, compression_ratio - 2.4), 0, 1)`
+///
+/// With the default weights a segment with an average log probability of -0.2 scores 0.8 and
+/// one at -1 scores 0. A compression ratio that was not computed does not lower the score.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfidenceWeights {
+    pub logprob: f32,
+    pub no_speech: f32,
+    pub compression_ratio: f32,
+}
+
+impl Default for ConfidenceWeights {
+    fn default() -> Self {
+        Self {
+            logprob: 1.0,
+            no_speech: 0.5,
+            compression_ratio: 0.5,
+        }
+    }
+}
+
+impl ConfidenceWeights {
+    pub fn score(&self, dr: &DecodingResult) -> f32 {
+        let excess_compression = match dr.compression_ratio() {
+            ratio if ratio.is_nan() => 0.,
+            ratio => f64::max(0., ratio - m::COMPRESSION_RATIO_THRESHOLD) as f32,
+        };
+        let score = 1. + self.logprob * dr.avg_logprob as f32
+            - self.no_speech * dr.no_speech_prob as f32
+            - self.compression_ratio * excess_compression;
+        score.clamp(0., 1.)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Band {
+    High,
+    Medium,
+    Low,
+}
+
+/// Lowest confidence scores of the high and medium bands.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ConfidenceThresholds {
+    pub high: f32,
+    pub medium: f32,
+}
+
+pub const DEFAULT_CONFIDENCE_THRESHOLDS: ConfidenceThresholds = ConfidenceThresholds {
+    high: 0.7,
+    medium: 0.4,
+};
+
+impl Default for ConfidenceThresholds {
+    fn default() -> Self {
+        DEFAULT_CONFIDENCE_THRESHOLDS
+    }
+}
+
+impl Segment {
+    /// Band of the `confidence` of the segment. A segment accepted after a temperature
+    /// fallback is at most medium, no-speech and placeholder segments are low.
+    pub fn confidence_band(&self, thresholds: &ConfidenceThresholds) -> Band {
+        if self.no_speech || self.error.is_some() {
+            return Band::Low;
+        }
+        let band = if self.confidence >= thresholds.high {
+            Band::High
+        } else if self.confidence >= thresholds.medium {
+            Band::Medium
+        } else {
+            Band::Low
+        };
+        if band == Band::High && self.dr.attempts > 1 {
+            Band::Medium
+        } else {
+            band
+        }
+    }
+}
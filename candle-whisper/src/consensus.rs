@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+/// Multi-pass decoding: every window is decoded again at each of `temperatures` on top of the
+/// result accepted by the temperature fallback, and the tokens are voted between the passes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighAccuracyOptions {
+    /// Temperatures of the additional passes, a pass at temperature 0 repeats the greedy
+    /// decoding and only adds weight to it.
+    pub temperatures: Vec<f64>,
+    /// Tokens agreed on by a smaller fraction of the passes are reported as low agreement
+    /// spans.
+    pub min_agreement: f64,
+}
+
+impl Default for HighAccuracyOptions {
+    fn default() -> Self {
+        Self {
+            temperatures: vec![0.2, 0.4],
+            min_agreement: 1.0,
+        }
+    }
+}
+
+/// Tokens of a segment the decoding passes disagreed on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LowAgreementSpan {
+    /// Index of the first token of the span in the tokens of the segment.
+    pub start_token: usize,
+    /// Index past the last token of the span.
+    pub end_token: usize,
+    pub text: String,
+    /// Lowest fraction of the passes agreeing on a token of the span.
+    pub agreement: f64,
+}
+
+/// Result of the vote between several token sequences.
+#[derive(Debug, Clone, Default)]
+pub struct Consensus {
+    pub tokens: Vec<u32>,
+    /// Fraction of the passes agreeing on each token, lowered on the token following a
+    /// disagreement that was voted out.
+    pub agreement: Vec<f64>,
+}
+
+impl Consensus {
+    fn push(&mut self, token: u32, agreement: f64, pending: &mut Option<f64>) {
+        let agreement = pending.take().map_or(agreement, |p| f64::min(p, agreement));
+        self.tokens.push(token);
+        self.agreement.push(agreement);
+    }
+
+    /// Ranges of consecutive tokens with an agreement below `min_agreement`, with the lowest
+    /// agreement of each range.
+    pub fn low_agreement(&self, min_agreement: f64) -> Vec<(std::ops::Range<usize>, f64)> {
+        let mut spans: Vec<(std::ops::Range<usize>, f64)> = vec![];
+        for (i, &agreement) in self.agreement.iter().enumerate() {
+            if agreement >= min_agreement {
+                continue;
+            }
+            match spans.last_mut() {
+                Some((range, lowest)) if range.end == i => {
+                    range.end = i + 1;
+                    *lowest = f64::min(*lowest, agreement);
+                }
+                _ => spans.push((i..i + 1, agreement)),
+            }
+        }
+        spans
+    }
+}
+
+fn lower(agreement: &mut Option<f64>, value: f64) {
+    *agreement = Some(agreement.map_or(value, |a| f64::min(a, value)))
+}
+
+/// Alignment of a hypothesis on a reference: the hypothesis token aligned with each reference
+/// token, `None` when deleted, and the tokens inserted before each reference token and at the
+/// end.
+struct Alignment {
+    aligned: Vec<Option<u32>>,
+    inserted: Vec<Vec<u32>>,
+}
+
+/// Levenshtein alignment of `hypothesis` on `reference`.
+fn align(reference: &[u32], hypothesis: &[u32]) -> Alignment {
+    let (n, m) = (reference.len(), hypothesis.len());
+    let mut cost = vec![vec![0usize; m + 1]; n + 1];
+    for (i, row) in cost.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, c) in cost[0].iter_mut().enumerate() {
+        *c = j;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let substitution =
+                cost[i - 1][j - 1] + usize::from(reference[i - 1] != hypothesis[j - 1]);
+            cost[i][j] = substitution.min(cost[i - 1][j] + 1).min(cost[i][j - 1] + 1);
+        }
+    }
+    let mut aligned = vec![None; n];
+    let mut inserted = vec![vec![]; n + 1];
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0
+            && j > 0
+            && cost[i][j] == cost[i - 1][j - 1] + usize::from(reference[i - 1] != hypothesis[j - 1])
+        {
+            aligned[i - 1] = Some(hypothesis[j - 1]);
+            i -= 1;
+            j -= 1;
+        } else if i > 0 && cost[i][j] == cost[i - 1][j] + 1 {
+            i -= 1;
+        } else {
+            inserted[i].insert(0, hypothesis[j - 1]);
+            j -= 1;
+        }
+    }
+    Alignment { aligned, inserted }
+}
+
+/// Most voted candidate and its number of votes, ties go to the first candidate.
+fn majority<T: PartialEq + Clone>(candidates: &[T]) -> (T, usize) {
+    let mut best = (candidates[0].clone(), 0);
+    for candidate in candidates.iter() {
+        let votes = candidates.iter().filter(|c| *c == candidate).count();
+        if votes > best.1 {
+            best = (candidate.clone(), votes)
+        }
+    }
+    best
+}
+
+/// Votes the tokens of several decoding passes, the first pass being the reference the others
+/// are aligned on.
+///
+/// Each reference token is kept, replaced or dropped by majority between the passes, the
+/// reference winning ties. Tokens inserted by the other passes are kept when a strict majority
+/// of the passes inserted the same tokens at the same place.
+pub fn vote(passes: &[&[u32]]) -> Consensus {
+    let Some((reference, others)) = passes.split_first() else {
+        return Consensus::default();
+    };
+    let n_passes = passes.len() as f64;
+    let alignments: Vec<Alignment> = others.iter().map(|p| align(reference, p)).collect();
+    let mut consensus = Consensus::default();
+    // Agreement of the last disagreement that was voted out, reported on the next token.
+    let mut pending: Option<f64> = None;
+    for i in 0..=reference.len() {
+        let insertions: Vec<&[u32]> = std::iter::once(&[][..])
+            .chain(alignments.iter().map(|a| a.inserted[i].as_slice()))
+            .collect();
+        let (inserted, votes) = majority(&insertions);
+        if votes < insertions.len() {
+            if 2 * votes > passes.len() && !inserted.is_empty() {
+                for &token in inserted {
+                    consensus.push(token, votes as f64 / n_passes, &mut pending)
+                }
+            } else {
+                lower(&mut pending, votes as f64 / n_passes)
+            }
+        }
+        let Some(&token) = reference.get(i) else {
+            break;
+        };
+        let candidates: Vec<Option<u32>> = std::iter::once(Some(token))
+            .chain(alignments.iter().map(|a| a.aligned[i]))
+            .collect();
+        let (winner, votes) = majority(&candidates);
+        let agreement = votes as f64 / n_passes;
+        match winner {
+            Some(token) => consensus.push(token, agreement, &mut pending),
+            None => lower(&mut pending, agreement),
+        }
+    }
+    if let (Some(p), Some(last)) = (pending, consensus.agreement.last_mut()) {
+        *last = f64::min(*last, p)
+    }
+    consensus
+}
@@ -0,0 +1,129 @@
+//! Text of the sampled tokens. Sampling at a high temperature can produce byte sequences that
+//! are not valid UTF-8, the text is then decoded token by token with the invalid bytes replaced
+//! by U+FFFD rather than failing the segment.
+
+use crate::prompt::PrefixIndex;
+
+use regex::Regex;
+use std::{borrow::Cow, sync::OnceLock};
+use tokenizers::Tokenizer;
+
+/// Text of a token sequence.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenText {
+    pub text: String,
+    /// Indices of the tokens that could not be decoded, whose bytes are replaced by U+FFFD.
+    pub invalid_tokens: Vec<usize>,
+}
+
+/// Decodes `tokens`, the tokens from `eot` being special. `index` has the bytes of the text
+/// tokens of the byte-level tokenizers, which locates the invalid bytes exactly; the other
+/// tokenizers are decoded a token at a time to find the invalid tokens.
+pub fn decode_tokens(
+    tokenizer: &Tokenizer,
+    index: Option<&PrefixIndex>,
+    tokens: &[u32],
+    eot: u32,
+) -> TokenText {
+    if let Ok(text) = tokenizer.decode(tokens, true) {
+        if !text.contains(char::REPLACEMENT_CHARACTER) {
+            return TokenText {
+                text: strip_special_token_text(&text).into_owned(),
+                invalid_tokens: vec![],
+            };
+        }
+    }
+    let TokenText {
+        text,
+        invalid_tokens,
+    } = match index {
+        Some(index) => decode_bytes(index, tokens, eot),
+        None => decode_each(tokenizer, tokens, eot),
+    };
+    TokenText {
+        text: strip_special_token_text(&text).into_owned(),
+        invalid_tokens,
+    }
+}
+
+/// Decodes the bytes of the text tokens, each invalid byte run becoming a U+FFFD.
+fn decode_bytes(index: &PrefixIndex, tokens: &[u32], eot: u32) -> TokenText {
+    let mut bytes = vec![];
+    // Index in `tokens` of the token of each byte.
+    let mut owners = vec![];
+    for (i, &token) in tokens.iter().enumerate().filter(|(_, &t)| t < eot) {
+        let token_bytes = index.token_bytes(token);
+        bytes.extend_from_slice(token_bytes);
+        owners.extend(std::iter::repeat_n(i, token_bytes.len()));
+    }
+    let mut text = String::with_capacity(bytes.len());
+    let mut invalid_tokens: Vec<usize> = vec![];
+    let mut start = 0;
+    while start < bytes.len() {
+        match std::str::from_utf8(&bytes[start..]) {
+            Ok(valid) => {
+                text.push_str(valid);
+                break;
+            }
+            Err(err) => {
+                let valid_end = start + err.valid_up_to();
+                // Valid up to `valid_end` by definition.
+                text.push_str(std::str::from_utf8(&bytes[start..valid_end]).unwrap_or_default());
+                text.push(char::REPLACEMENT_CHARACTER);
+                // `None` when the bytes end inside a character.
+                let invalid_end = err.error_len().map_or(bytes.len(), |len| valid_end + len);
+                for &owner in &owners[valid_end..invalid_end] {
+                    if invalid_tokens.last() != Some(&owner) {
+                        invalid_tokens.push(owner);
+                    }
+                }
+                start = invalid_end;
+            }
+        }
+    }
+    TokenText {
+        text,
+        invalid_tokens,
+    }
+}
+
+/// Decodes the runs of text tokens that decode on their own, each run of the other tokens
+/// becoming a U+FFFD.
+fn decode_each(tokenizer: &Tokenizer, tokens: &[u32], eot: u32) -> TokenText {
+    let text_tokens: Vec<(usize, u32)> = tokens
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(_, t)| t < eot)
+        .collect();
+    let is_valid = |token: u32| {
+        tokenizer
+            .decode(&[token], true)
+            .is_ok_and(|text| !text.contains(char::REPLACEMENT_CHARACTER))
+    };
+    let mut pieces = vec![];
+    let mut invalid_tokens = vec![];
+    for run in text_tokens.chunk_by(|a, b| is_valid(a.1) == is_valid(b.1)) {
+        let ids: Vec<u32> = run.iter().map(|&(_, t)| t).collect();
+        match tokenizer.decode(&ids, true) {
+            Ok(piece) if is_valid(ids[0]) => pieces.push(piece),
+            _ => {
+                pieces.push(char::REPLACEMENT_CHARACTER.to_string());
+                invalid_tokens.extend(run.iter().map(|&(i, _)| i));
+            }
+        }
+    }
+    TokenText {
+        text: pieces.concat(),
+        invalid_tokens,
+    }
+}
+
+/// Removes the text of the special tokens, such as `<|en|>` or `<|0.00|>`, that the decoding
+/// kept because the tokenizer does not mark them as special, with the whitespace before them
+/// that a word-level decoder puts between the tokens.
+pub fn strip_special_token_text(text: &str) -> Cow<'_, str> {
+    static SPECIAL: OnceLock<Regex> = OnceLock::new();
+    let special = SPECIAL.get_or_init(|| Regex::new(r"\s*<\|[^|<>\s]*\|>").expect("valid regex"));
+    special.replace_all(text, "")
+}
@@ -0,0 +1,117 @@
+//! Structured record of what happened to every window of a run, returned to the callers for
+//! their telemetry instead of being only logged.
+
+use crate::{
+    audio::{FormatCheck, MelNormalization},
+    model_profile::ModelProfile,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Windows of a run in decoding order, collected with `DecodeOptions::collect_diagnostics`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunDiagnostics {
+    pub windows: Vec<WindowDiagnostics>,
+    /// Normalization of the mel spectrograms computed from the samples; the spectrograms passed
+    /// to `Decoder::run_mel` keep the one they were computed with.
+    #[serde(default)]
+    pub mel_normalization: MelNormalization,
+    /// Family the model was recognized as, whose defaults the options start from.
+    #[serde(default)]
+    pub model_profile: ModelProfile,
+    /// Mismatch between the samples of the transcribed WAV file and its header, see
+    /// `audio::check_format`.
+    #[serde(default)]
+    pub format_check: Option<FormatCheck>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WindowDiagnostics {
+    /// Start in seconds of the window, relative to the decoded range.
+    pub start: f64,
+    pub seek_frame: usize,
+    /// Mel frames of the window fed to the encoder, padding included.
+    #[serde(default)]
+    pub encoder_frames: usize,
+    /// Language the window was decoded in, `None` for the English-only models.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Thresholds in effect for the window, those of the language profile when one matches.
+    #[serde(default)]
+    pub no_speech_threshold: Option<f64>,
+    #[serde(default)]
+    pub logprob_threshold: Option<f64>,
+    #[serde(default)]
+    pub compression_ratio_threshold: Option<f64>,
+    pub outcome: WindowOutcome,
+    /// Decoding attempts of the temperature schedule in order, followed by the ones of the
+    /// speech recovered in a window treated as silence.
+    pub attempts: Vec<AttemptDiagnostics>,
+    /// Whether an attempt was rejected and a higher temperature tried.
+    pub fallback: bool,
+    /// Of the kept result, `NaN` serialized as `null` when the window was not decoded.
+    #[serde(deserialize_with = "crate::logic::f64_or_nan")]
+    pub no_speech_prob: f64,
+    #[serde(deserialize_with = "crate::logic::f64_or_nan")]
+    pub avg_logprob: f64,
+    /// Error of a window skipped or replaced by a placeholder after failing to decode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Cosine distance between the last seconds before the window and its first seconds, with
+    /// `speaker_change_hints`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker_change_distance: Option<f32>,
+    /// Cosine distances between the consecutive blocks of seconds of the window, the baseline
+    /// of the speaker change scores.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub within_window_distances: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowOutcome {
+    #[default]
+    Decoded,
+    /// Skipped without decoding, the VAD found no speech in it.
+    NoSpeechRegion,
+    /// Skipped, the result was treated as silence.
+    Silence,
+    /// Treated as silence, the speech found by the VAD in it was decoded on its own.
+    RecoveredSpeech,
+    /// Failed to decode.
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttemptDiagnostics {
+    pub temperature: f64,
+    /// Tokens sampled, the prompt excluded.
+    pub decode_steps: usize,
+    /// `NaN` serialized as `null` for the failed attempts.
+    #[serde(deserialize_with = "crate::logic::f64_or_nan")]
+    pub avg_logprob: f64,
+    #[serde(deserialize_with = "crate::logic::f64_or_nan")]
+    pub no_speech_prob: f64,
+    #[serde(deserialize_with = "crate::logic::f64_or_nan")]
+    pub compression_ratio: f64,
+    /// Thresholds the result failed, which trigger a fallback unless the attempt is the last
+    /// of the schedule.
+    pub fallback_reasons: Vec<FallbackReason>,
+    /// Whether the result was kept.
+    pub accepted: bool,
+    /// `DecodingResult::sampling_fallbacks` of the result.
+    #[serde(default)]
+    pub sampling_fallbacks: u32,
+    /// Error of a failed attempt, recovered from when a higher temperature was tried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackReason {
+    /// Above `DecodeOptions::compression_ratio_threshold`.
+    CompressionRatio,
+    /// Below `DecodeOptions::logprob_threshold`.
+    Logprob,
+}
@@ -0,0 +1,346 @@
+//! Word-level comparison of two transcripts of the same audio, e.g. from two models or two
+//! option sets. The segments of the two runs are grouped by overlapping times, and the words of
+//! each group are compared with the Myers diff, `a` being the reference of the statistics.
+
+use crate::{
+    logic::Segment,
+    segments::{is_speech, start_order},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiffOptions {
+    /// Compare the words lowercased.
+    pub ignore_case: bool,
+    /// Compare the alphanumeric characters of the words only, the words without any being
+    /// left out of the comparison.
+    pub ignore_punctuation: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            ignore_case: true,
+            ignore_punctuation: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EditKind {
+    /// Words of `b` missing from `a`.
+    Insert,
+    /// Words of `a` missing from `b`.
+    Delete,
+    /// Words of `a` replaced by other words in `b`.
+    Replace,
+}
+
+/// Consecutive words differing between the two transcripts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffSpan {
+    pub kind: EditKind,
+    /// Words of `a`, as written, empty for an insertion.
+    pub a_words: Vec<String>,
+    /// Words of `b`, as written, empty for a deletion.
+    pub b_words: Vec<String>,
+    /// Start time in seconds, the words being spread evenly over their segment.
+    pub start: f64,
+    /// End time in seconds.
+    pub end: f64,
+}
+
+/// Word error counts of `b` against the reference `a`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiffStats {
+    /// Compared words of `a`.
+    pub reference_words: usize,
+    pub substitutions: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Errors per reference word, the number of errors when `a` has no words.
+    pub wer: f64,
+}
+
+impl DiffStats {
+    fn add(&mut self, other: &DiffStats) {
+        self.reference_words += other.reference_words;
+        self.substitutions += other.substitutions;
+        self.insertions += other.insertions;
+        self.deletions += other.deletions;
+    }
+
+    fn with_wer(mut self) -> Self {
+        let errors = self.substitutions + self.insertions + self.deletions;
+        self.wer = errors as f64 / self.reference_words.max(1) as f64;
+        self
+    }
+}
+
+/// Segments of the two runs overlapping in time, compared as a whole so that the segments do
+/// not need the same boundaries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlignedSegments {
+    /// Indices of the segments of `a`, empty when `b` has speech where `a` has none.
+    pub a_segments: Vec<usize>,
+    /// Indices of the segments of `b`.
+    pub b_segments: Vec<usize>,
+    /// Start time in seconds of the first segment of the group.
+    pub start: f64,
+    /// End time in seconds of the last segment of the group.
+    pub end: f64,
+    pub spans: Vec<DiffSpan>,
+    pub stats: DiffStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptDiff {
+    pub groups: Vec<AlignedSegments>,
+    /// Totals of the groups.
+    pub stats: DiffStats,
+}
+
+impl TranscriptDiff {
+    /// Whether the two transcripts have the same compared words.
+    pub fn is_identical(&self) -> bool {
+        self.groups.iter().all(|group| group.spans.is_empty())
+    }
+}
+
+/// Compares the speech segments of `b` with the ones of `a`.
+pub fn diff_segments(a: &[Segment], b: &[Segment], opts: &DiffOptions) -> TranscriptDiff {
+    let groups: Vec<AlignedSegments> = overlapping_groups(a, b)
+        .into_iter()
+        .map(|(a_segments, b_segments)| diff_group(a, b, a_segments, b_segments, opts))
+        .collect();
+    let mut stats = DiffStats::default();
+    for group in &groups {
+        stats.add(&group.stats);
+    }
+    TranscriptDiff {
+        groups,
+        stats: stats.with_wer(),
+    }
+}
+
+fn end(segment: &Segment) -> f64 {
+    segment.start + segment.duration
+}
+
+/// Indices of the speech segments of `a` and `b` grouped by overlapping times, in time order.
+/// Segments only touching each other are in different groups.
+fn overlapping_groups(a: &[Segment], b: &[Segment]) -> Vec<(Vec<usize>, Vec<usize>)> {
+    // (segment, index, whether from `a`) in start order.
+    let mut segments: Vec<(&Segment, usize, bool)> = a
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| (segment, i, true))
+        .chain(b.iter().enumerate().map(|(i, segment)| (segment, i, false)))
+        .filter(|(segment, _, _)| is_speech(segment))
+        .collect();
+    segments.sort_by(|x, y| start_order(x.0, y.0).then(y.2.cmp(&x.2)));
+    let mut groups: Vec<(Vec<usize>, Vec<usize>)> = vec![];
+    let mut group_end = f64::NEG_INFINITY;
+    for (segment, i, from_a) in segments {
+        if groups.is_empty() || segment.start >= group_end {
+            groups.push((vec![], vec![]));
+            group_end = end(segment);
+        }
+        group_end = group_end.max(end(segment));
+        let (a_segments, b_segments) = groups.last_mut().expect("a group was pushed");
+        if from_a {
+            a_segments.push(i);
+        } else {
+            b_segments.push(i);
+        }
+    }
+    groups
+}
+
+/// Word of a segment with the key it is compared by.
+struct Word<'a> {
+    text: &'a str,
+    key: String,
+    start: f64,
+    end: f64,
+}
+
+fn words<'a>(segments: &'a [Segment], indices: &[usize], opts: &DiffOptions) -> Vec<Word<'a>> {
+    let mut words = vec![];
+    for segment in indices.iter().map(|&i| &segments[i]) {
+        let text = segment.dr.text_clean.as_deref().unwrap_or(&segment.dr.text);
+        let texts: Vec<&str> = text.split_whitespace().collect();
+        let word_duration = segment.duration / texts.len().max(1) as f64;
+        for (k, text) in texts.into_iter().enumerate() {
+            let key = normalize(text, opts);
+            if key.is_empty() {
+                continue;
+            }
+            let start = segment.start + k as f64 * word_duration;
+            words.push(Word {
+                text,
+                key,
+                start,
+                end: start + word_duration,
+            });
+        }
+    }
+    words
+}
+
+fn normalize(word: &str, opts: &DiffOptions) -> String {
+    let kept = word
+        .chars()
+        .filter(|c| !opts.ignore_punctuation || c.is_alphanumeric());
+    if opts.ignore_case {
+        kept.flat_map(char::to_lowercase).collect()
+    } else {
+        kept.collect()
+    }
+}
+
+fn diff_group(
+    a: &[Segment],
+    b: &[Segment],
+    a_segments: Vec<usize>,
+    b_segments: Vec<usize>,
+    opts: &DiffOptions,
+) -> AlignedSegments {
+    let a_words = words(a, &a_segments, opts);
+    let b_words = words(b, &b_segments, opts);
+    let a_keys: Vec<&str> = a_words.iter().map(|w| w.key.as_str()).collect();
+    let b_keys: Vec<&str> = b_words.iter().map(|w| w.key.as_str()).collect();
+    let mut spans = vec![];
+    let mut stats = DiffStats {
+        reference_words: a_words.len(),
+        ..Default::default()
+    };
+    // Words of the edit being gathered, until the next equal word.
+    let (mut deleted, mut inserted): (Vec<&Word>, Vec<&Word>) = (vec![], vec![]);
+    let (mut i, mut j) = (0, 0);
+    for op in myers(&a_keys, &b_keys).into_iter().chain([Op::Equal]) {
+        match op {
+            Op::Delete => {
+                deleted.push(&a_words[i]);
+                i += 1;
+            }
+            Op::Insert => {
+                inserted.push(&b_words[j]);
+                j += 1;
+            }
+            Op::Equal => {
+                if !deleted.is_empty() || !inserted.is_empty() {
+                    let substitutions = deleted.len().min(inserted.len());
+                    stats.substitutions += substitutions;
+                    stats.deletions += deleted.len() - substitutions;
+                    stats.insertions += inserted.len() - substitutions;
+                    spans.push(span(&deleted, &inserted));
+                    deleted.clear();
+                    inserted.clear();
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let times = a_segments
+        .iter()
+        .map(|&i| &a[i])
+        .chain(b_segments.iter().map(|&i| &b[i]));
+    let start = times.clone().map(|s| s.start).fold(f64::INFINITY, f64::min);
+    let last_end = times.map(end).fold(f64::NEG_INFINITY, f64::max);
+    AlignedSegments {
+        a_segments,
+        b_segments,
+        start,
+        end: last_end,
+        spans,
+        stats: stats.with_wer(),
+    }
+}
+
+fn span(deleted: &[&Word], inserted: &[&Word]) -> DiffSpan {
+    let kind = match (deleted.is_empty(), inserted.is_empty()) {
+        (false, false) => EditKind::Replace,
+        (false, true) => EditKind::Delete,
+        _ => EditKind::Insert,
+    };
+    let words = || deleted.iter().chain(inserted.iter());
+    DiffSpan {
+        kind,
+        a_words: deleted.iter().map(|w| w.text.to_string()).collect(),
+        b_words: inserted.iter().map(|w| w.text.to_string()).collect(),
+        start: words().map(|w| w.start).fold(f64::INFINITY, f64::min),
+        end: words().map(|w| w.end).fold(f64::NEG_INFINITY, f64::max),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Shortest edit script turning `a` into `b`, by the O(ND) algorithm of Myers.
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Op> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    // `v[k + offset]` is the furthest `x` reached on the diagonal `k = x - y`.
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // `v` at the start of every round, to walk the edits back.
+    let mut trace = vec![];
+    'rounds: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let at = |k: isize| (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'rounds;
+            }
+        }
+    }
+    let mut ops = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| (k + offset) as usize;
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[at(previous_k)];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == previous_x {
+                Op::Insert
+            } else {
+                Op::Delete
+            });
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    ops.reverse();
+    ops
+}
@@ -0,0 +1,113 @@
+use candle_core::Tensor;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+/// Bounds of the encoder output cache, the least recently used entries are evicted first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderCacheOptions {
+    pub max_entries: usize,
+    /// Total size of the cached encoder outputs.
+    pub max_bytes: usize,
+}
+
+impl Default for EncoderCacheOptions {
+    fn default() -> Self {
+        Self {
+            max_entries: 64,
+            max_bytes: 256 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct EncoderCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: usize,
+    /// Lookups that ran the encoder.
+    pub misses: usize,
+}
+
+/// Encoder outputs keyed by a hash of the mel window and of the model identity, so that
+/// decoding the same audio again, e.g. with another task, skips the encoder.
+#[derive(Debug, Default)]
+pub struct EncoderCache {
+    options: Option<EncoderCacheOptions>,
+    /// Entries from the least to the most recently used.
+    entries: VecDeque<(u64, Tensor, usize)>,
+    stats: EncoderCacheStats,
+}
+
+impl EncoderCache {
+    pub fn is_enabled(&self) -> bool {
+        self.options.is_some()
+    }
+
+    /// Changes the bounds, `None` disables the cache and drops its entries.
+    pub fn set_options(&mut self, options: Option<EncoderCacheOptions>) {
+        self.options = options;
+        self.evict();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.stats.entries = 0;
+        self.stats.bytes = 0;
+    }
+
+    pub fn stats(&self) -> EncoderCacheStats {
+        self.stats
+    }
+
+    /// Key of a mel window of the model identified by `model`.
+    pub fn key(mel_window: &[f32], model: impl Hash) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        model.hash(&mut hasher);
+        mel_window.len().hash(&mut hasher);
+        for v in mel_window {
+            v.to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    pub fn get(&mut self, key: u64) -> Option<Tensor> {
+        match self.entries.iter().position(|(k, _, _)| *k == key) {
+            Some(index) => {
+                let entry = self.entries.remove(index)?;
+                let tensor = entry.1.clone();
+                self.entries.push_back(entry);
+                self.stats.hits += 1;
+                Some(tensor)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: u64, tensor: Tensor) {
+        let bytes = tensor.elem_count() * tensor.dtype().size_in_bytes();
+        self.entries.retain(|(k, _, _)| *k != key);
+        self.entries.push_back((key, tensor, bytes));
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        let (max_entries, max_bytes) = match &self.options {
+            Some(options) => (options.max_entries, options.max_bytes),
+            None => (0, 0),
+        };
+        let mut bytes: usize = self.entries.iter().map(|(_, _, b)| b).sum();
+        while self.entries.len() > max_entries || bytes > max_bytes {
+            match self.entries.pop_front() {
+                Some((_, _, b)) => bytes -= b,
+                None => break,
+            }
+        }
+        self.stats.entries = self.entries.len();
+        self.stats.bytes = bytes;
+    }
+}
@@ -0,0 +1,281 @@
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+
+/// Error type of the public API.
+///
+/// Internal helpers keep using `anyhow`, a `WhisperError` raised inside an `anyhow::Error` is
+/// recovered as is when converting back at the API boundary.
+#[derive(Debug)]
+pub enum WhisperError {
+    UnsupportedAudio {
+        reason: String,
+    },
+    InvalidConfig {
+        reason: String,
+    },
+    ModelLoad {
+        source: anyhow::Error,
+    },
+    Tokenizer {
+        source: anyhow::Error,
+    },
+    LanguageNotSupported {
+        lang: String,
+    },
+    /// The weights, config and tokenizer do not belong to the same model.
+    ModelMismatch {
+        issues: Vec<String>,
+    },
+    /// The safetensors weights are truncated or corrupted, see `weights::verify_weights`.
+    CorruptWeights {
+        /// First offending tensor, `None` when the header itself is invalid.
+        tensor: Option<String>,
+        /// Offset in the weights at which the problem lies.
+        offset: usize,
+        reason: String,
+    },
+    Decode {
+        context: Box<DecodeContext>,
+        source: anyhow::Error,
+    },
+    /// The model produced NaN or infinite logits, e.g. a half precision overflow.
+    NonFiniteLogits {
+        context: Box<DecodeContext>,
+    },
+    Cancelled,
+    Internal {
+        source: anyhow::Error,
+    },
+}
+
+impl WhisperError {
+    /// Stable identifier of the variant, meant to be matched on by JS callers.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnsupportedAudio { .. } => "unsupported_audio",
+            Self::InvalidConfig { .. } => "invalid_config",
+            Self::ModelLoad { .. } => "model_load",
+            Self::Tokenizer { .. } => "tokenizer",
+            Self::LanguageNotSupported { .. } => "language_not_supported",
+            Self::ModelMismatch { .. } => "model_mismatch",
+            Self::CorruptWeights { .. } => "corrupt_weights",
+            Self::Decode { .. } => "decode",
+            Self::NonFiniteLogits { .. } => "non_finite_logits",
+            Self::Cancelled => "cancelled",
+            Self::Internal { .. } => "internal",
+        }
+    }
+
+    pub(crate) fn unsupported_audio(reason: impl ToString) -> Self {
+        Self::UnsupportedAudio {
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Classifies an error raised while loading a model, errors that are not already a
+    /// `WhisperError` are reported as `ModelLoad`.
+    pub(crate) fn model_load(source: anyhow::Error) -> Self {
+        match source.downcast::<Self>() {
+            Ok(err) => err,
+            Err(source) => Self::ModelLoad { source },
+        }
+    }
+
+    /// Attaches the window being decoded to an error, completed with the decoding step when the
+    /// error was raised while sampling. Errors that are already a `WhisperError` are kept as is.
+    pub(crate) fn decode(mut context: DecodeContext, source: anyhow::Error) -> Self {
+        let source = match source.downcast::<Self>() {
+            Ok(err) => return err,
+            Err(source) => source,
+        };
+        let source = match source.downcast::<StepFailure>() {
+            Ok(failure) => {
+                context.step = Some(failure.step);
+                context.temperature = Some(failure.temperature);
+                context.tokens_generated = failure.tokens.len();
+                let last = failure.tokens.len().saturating_sub(LAST_TOKENS);
+                context.last_tokens = failure.tokens[last..].to_vec();
+                match failure.source {
+                    Some(source) => source,
+                    None => {
+                        return Self::NonFiniteLogits {
+                            context: Box::new(context),
+                        }
+                    }
+                }
+            }
+            Err(source) => source,
+        };
+        Self::Decode {
+            context: Box::new(context),
+            source,
+        }
+    }
+
+    /// Context of a decoding error, `None` for the other variants.
+    pub fn decode_context(&self) -> Option<&DecodeContext> {
+        match self {
+            Self::Decode { context, .. } | Self::NonFiniteLogits { context } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+/// Number of sampled tokens kept in a [`DecodeContext`].
+const LAST_TOKENS: usize = 10;
+
+/// Where the decoding of a window failed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DecodeContext {
+    /// Start of the window, in seconds.
+    pub segment_start: f64,
+    /// First mel frame of the window.
+    pub seek_frame: usize,
+    /// Index of the token being sampled, `None` when the failure happened before sampling,
+    /// e.g. in the encoder.
+    pub step: Option<usize>,
+    pub temperature: Option<f64>,
+    /// Number of tokens sampled before the failure, the prompt excluded.
+    pub tokens_generated: usize,
+    /// The last sampled tokens, at most 10 of them.
+    pub last_tokens: Vec<u32>,
+    pub quantized: bool,
+}
+
+impl std::fmt::Display for DecodeContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "segment at {}s", self.segment_start)?;
+        if let (Some(step), Some(temperature)) = (self.step, self.temperature) {
+            write!(f, ", step {step} at temperature {temperature}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Failure of a decoding step, raised by the sampling loop and turned into a `Decode` or
+/// `NonFiniteLogits` error once the window is known.
+#[derive(Debug)]
+pub(crate) struct StepFailure {
+    pub step: usize,
+    pub temperature: f64,
+    /// Tokens sampled before the failure.
+    pub tokens: Vec<u32>,
+    /// `None` when the logits were not finite.
+    pub source: Option<anyhow::Error>,
+}
+
+impl std::fmt::Display for StepFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "step {} at temperature {}: ",
+            self.step, self.temperature
+        )?;
+        match &self.source {
+            Some(source) => write!(f, "{source}"),
+            None => write!(f, "non finite logits"),
+        }
+    }
+}
+
+impl std::error::Error for StepFailure {}
+
+impl std::fmt::Display for WhisperError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedAudio { reason } => write!(f, "unsupported audio: {reason}"),
+            Self::InvalidConfig { reason } => write!(f, "invalid configuration: {reason}"),
+            Self::ModelLoad { source } => write!(f, "unable to load the model: {source}"),
+            Self::Tokenizer { source } => write!(f, "tokenizer error: {source}"),
+            Self::LanguageNotSupported { lang } => write!(f, "language {lang} is not supported"),
+            Self::ModelMismatch { issues } => {
+                write!(f, "the model files do not match: {}", issues.join(", "))
+            }
+            Self::CorruptWeights {
+                tensor,
+                offset,
+                reason,
+            } => {
+                write!(f, "corrupt weights at byte {offset}")?;
+                if let Some(tensor) = tensor {
+                    write!(f, " in tensor {tensor}")?;
+                }
+                write!(f, ": {reason}")
+            }
+            Self::Decode { context, source } => {
+                write!(f, "decoding failed for {context}: {source}")
+            }
+            Self::NonFiniteLogits { context } => {
+                write!(f, "the model produced non finite logits for {context}")
+            }
+            Self::Cancelled => write!(f, "transcription cancelled"),
+            Self::Internal { source } => write!(f, "{source}"),
+        }
+    }
+}
+
+impl std::error::Error for WhisperError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ModelLoad { source }
+            | Self::Tokenizer { source }
+            | Self::Decode { source, .. }
+            | Self::Internal { source } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for WhisperError {
+    fn from(source: anyhow::Error) -> Self {
+        match source.downcast::<Self>() {
+            Ok(err) => err,
+            Err(source) => Self::Internal { source },
+        }
+    }
+}
+
+impl From<candle_core::Error> for WhisperError {
+    fn from(source: candle_core::Error) -> Self {
+        Self::Internal {
+            source: source.into(),
+        }
+    }
+}
+
+impl From<hound::Error> for WhisperError {
+    fn from(err: hound::Error) -> Self {
+        Self::unsupported_audio(err)
+    }
+}
+
+impl From<tokenizers::Error> for WhisperError {
+    fn from(source: tokenizers::Error) -> Self {
+        Self::Tokenizer {
+            source: anyhow::Error::msg(source),
+        }
+    }
+}
+
+/// Serialized as `{ "code": ..., "message": ... }` plus the variant specific fields, so that the
+/// worker can forward errors to the main thread.
+impl Serialize for WhisperError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("WhisperError", 3)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        match self {
+            Self::LanguageNotSupported { lang } => s.serialize_field("lang", lang)?,
+            Self::ModelMismatch { issues } => s.serialize_field("issues", issues)?,
+            Self::CorruptWeights { tensor, offset, .. } => {
+                s.serialize_field("tensor", tensor)?;
+                s.serialize_field("offset", offset)?
+            }
+            Self::Decode { context, .. } | Self::NonFiniteLogits { context } => {
+                s.serialize_field("segment_start", &context.segment_start)?;
+                s.serialize_field("context", context)?
+            }
+            _ => {}
+        }
+        s.end()
+    }
+}
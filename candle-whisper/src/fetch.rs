@@ -0,0 +1,322 @@
+//! Download of the files of a model from a Hugging Face repo, for the native programs that
+//! are pointed at a repo rather than at local files. The web app fetches its files itself.
+//!
+//! The files are cached under their ETag, the SHA-256 of their content for the LFS files, so
+//! that a file is only downloaded again when it changes upstream. An interrupted download is
+//! resumed with a ranged request.
+
+use crate::{error::WhisperError, fingerprint::sha256, logic::ModelData};
+
+use reqwest::{header, Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Quantizations of the GGUF files, in the spelling of their names.
+const GGUF_QUANTIZATIONS: &[&str] = &[
+    "q40", "q41", "q50", "q51", "q80", "q2k", "q3k", "q4k", "q5k", "q6k", "q8k",
+];
+
+/// Mel filters of the model, in the layout of `DecoderBuilder::mel_filters`. The transcription
+/// repos do not have them, they are then computed on load.
+const MEL_FILTERS_FILE: &str = "mel_filters.safetensors";
+
+/// Layout of the files of a repo.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelVariant {
+    /// `model.safetensors`, `config.json` and `tokenizer.json`, as in openai/whisper-tiny.
+    Safetensors,
+    /// `model-{model}-{quantization}.gguf`, `config-{model}.json` and `tokenizer-{model}.json`,
+    /// the dots of the model name written as dashes, as in lmz/candle-whisper: `tiny.en` in
+    /// `q80` is `model-tiny-en-q80.gguf`.
+    Gguf { model: String, quantization: String },
+}
+
+impl ModelVariant {
+    /// Names of the weights, config and tokenizer files.
+    pub fn file_names(&self) -> Result<[String; 3], WhisperError> {
+        match self {
+            Self::Safetensors => Ok([
+                "model.safetensors".to_string(),
+                "config.json".to_string(),
+                "tokenizer.json".to_string(),
+            ]),
+            Self::Gguf {
+                model,
+                quantization,
+            } => {
+                let valid_name = !model.is_empty()
+                    && model
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+                if !valid_name {
+                    return Err(WhisperError::InvalidConfig {
+                        reason: format!("invalid model name {model:?}"),
+                    });
+                }
+                if !GGUF_QUANTIZATIONS.contains(&quantization.as_str()) {
+                    return Err(WhisperError::InvalidConfig {
+                        reason: format!(
+                            "unknown quantization {quantization}, expected one of {}",
+                            GGUF_QUANTIZATIONS.join(", ")
+                        ),
+                    });
+                }
+                let model = model.replace('.', "-");
+                Ok([
+                    format!("model-{model}-{quantization}.gguf"),
+                    format!("config-{model}.json"),
+                    format!("tokenizer-{model}.json"),
+                ])
+            }
+        }
+    }
+}
+
+/// Reported by [`fetch_model_with_progress`] as a file downloads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchProgress {
+    /// Name of the file in the repo.
+    pub file: String,
+    /// Bytes of the file in the cache, the part of an interrupted download included.
+    pub bytes_downloaded: u64,
+    pub bytes_total: u64,
+    /// Whether the file was found in the cache, without a download.
+    pub cached: bool,
+}
+
+/// URL of `file` in `repo`, a repo id such as `openai/whisper-tiny` on huggingface.co or the
+/// URL of a repo, possibly of one of its revisions (`.../resolve/<revision>`).
+fn file_url(repo: &str, file: &str) -> String {
+    let repo = repo.trim_end_matches('/');
+    if !(repo.starts_with("http://") || repo.starts_with("https://")) {
+        format!("https://huggingface.co/{repo}/resolve/main/{file}")
+    } else if repo.contains("/resolve/") {
+        format!("{repo}/{file}")
+    } else {
+        format!("{repo}/resolve/main/{file}")
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Size and version of a file of the repo.
+struct RemoteFile {
+    url: String,
+    size: u64,
+    /// ETag as sent, quotes included, for the `If-Range` of a resumed download.
+    etag: Option<String>,
+}
+
+impl RemoteFile {
+    /// ETag without its quotes and weak prefix.
+    fn etag_value(&self) -> Option<&str> {
+        let etag = self.etag.as_deref()?;
+        Some(etag.trim_start_matches("W/").trim_matches('"'))
+    }
+
+    /// SHA-256 of the content when the ETag is one, as for the LFS files.
+    fn sha256(&self) -> Option<&str> {
+        self.etag_value()
+            .filter(|etag| etag.len() == 64 && etag.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+
+    /// Name of the file in the cache: its ETag, or the digest of its URL and its size when the
+    /// server sends none.
+    fn cache_key(&self) -> String {
+        match self.etag_value() {
+            Some(etag) if !etag.is_empty() => etag
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                .collect(),
+            _ => format!("{}-{}", hex(&sha256(self.url.as_bytes())), self.size),
+        }
+    }
+}
+
+fn header_value(response: &Response, name: &str) -> Option<String> {
+    let value = response.headers().get(name)?.to_str().ok()?;
+    Some(value.to_string())
+}
+
+/// Size and ETag of `file`, `None` when the repo does not have it. The `X-Linked-*` headers of
+/// the LFS files, which describe the content rather than the pointer, are preferred.
+async fn head(client: &Client, repo: &str, file: &str) -> anyhow::Result<Option<RemoteFile>> {
+    let url = file_url(repo, file);
+    let response = client.head(&url).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    // The length of a HEAD response is only in its headers, it has no body.
+    let size = header_value(&response, "x-linked-size")
+        .or_else(|| header_value(&response, header::CONTENT_LENGTH.as_str()))
+        .and_then(|size| size.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("{url} has no content length"))?;
+    let etag = header_value(&response, "x-linked-etag")
+        .or_else(|| header_value(&response, header::ETAG.as_str()));
+    Ok(Some(RemoteFile { url, size, etag }))
+}
+
+/// Downloads `remote` into `part`, after the bytes already there when the server supports
+/// ranged requests and the file did not change since.
+async fn download(
+    client: &Client,
+    remote: &RemoteFile,
+    part: &Path,
+    report: &mut (dyn FnMut(u64) + Send),
+) -> anyhow::Result<()> {
+    let mut offset = match tokio::fs::metadata(part).await {
+        Ok(metadata) if metadata.len() <= remote.size => metadata.len(),
+        _ => 0,
+    };
+    if offset == remote.size && offset > 0 {
+        return Ok(());
+    }
+    let mut request = client.get(&remote.url);
+    if offset > 0 {
+        request = request.header(header::RANGE, format!("bytes={offset}-"));
+        if let Some(etag) = &remote.etag {
+            request = request.header(header::IF_RANGE, etag);
+        }
+    }
+    let mut response = request.send().await?.error_for_status()?;
+    let mut file = if offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(part)
+            .await?
+    } else {
+        // The whole file, the server ignoring the range or the file having changed.
+        offset = 0;
+        tokio::fs::File::create(part).await?
+    };
+    report(offset);
+    let written: anyhow::Result<()> = async {
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+            offset += chunk.len() as u64;
+            report(offset);
+        }
+        Ok(())
+    }
+    .await;
+    // Completes the pending write, so that an interrupted download is resumed from its end.
+    file.flush().await?;
+    written
+}
+
+/// Path of `file` in the cache, downloaded unless it is already there.
+async fn fetch_file(
+    client: &Client,
+    remote: &RemoteFile,
+    file: &str,
+    blobs: &Path,
+    progress: &mut (dyn FnMut(&FetchProgress) + Send),
+) -> anyhow::Result<PathBuf> {
+    let key = remote.cache_key();
+    let path = blobs.join(&key);
+    let cached = tokio::fs::metadata(&path)
+        .await
+        .is_ok_and(|metadata| metadata.len() == remote.size);
+    if cached {
+        progress(&FetchProgress {
+            file: file.to_string(),
+            bytes_downloaded: remote.size,
+            bytes_total: remote.size,
+            cached: true,
+        });
+        return Ok(path);
+    }
+    let part = blobs.join(format!("{key}.part"));
+    download(client, remote, &part, &mut |bytes_downloaded| {
+        progress(&FetchProgress {
+            file: file.to_string(),
+            bytes_downloaded,
+            bytes_total: remote.size,
+            cached: false,
+        })
+    })
+    .await?;
+    // A part that cannot be completed is dropped, the next fetch starting over.
+    let data = tokio::fs::read(&part).await?;
+    if data.len() as u64 != remote.size {
+        tokio::fs::remove_file(&part).await?;
+        anyhow::bail!(
+            "{file} is {} bytes, the server announced {}",
+            data.len(),
+            remote.size
+        );
+    }
+    if let Some(expected) = remote.sha256() {
+        let actual = hex(&sha256(&data));
+        if !actual.eq_ignore_ascii_case(expected) {
+            tokio::fs::remove_file(&part).await?;
+            anyhow::bail!("{file} has the digest {actual}, its ETag is {expected}");
+        }
+    }
+    tokio::fs::rename(&part, &path).await?;
+    Ok(path)
+}
+
+/// [`fetch_model_with_progress`] without progress.
+pub async fn fetch_model(
+    repo: &str,
+    variant: ModelVariant,
+    cache_dir: &Path,
+) -> Result<ModelData, WhisperError> {
+    fetch_model_with_progress(repo, variant, cache_dir, &mut |_| {}).await
+}
+
+/// Model data of the files of `repo`, a repo id on huggingface.co or the URL of a repo, laid
+/// out as `variant`. The files are cached in `cache_dir`, the ones already there are not
+/// downloaded again unless they changed. The mel filters are fetched when the repo has
+/// `mel_filters.safetensors`, they are computed on load otherwise. `progress` receives the
+/// bytes downloaded of each file.
+pub async fn fetch_model_with_progress(
+    repo: &str,
+    variant: ModelVariant,
+    cache_dir: &Path,
+    progress: &mut (dyn FnMut(&FetchProgress) + Send),
+) -> Result<ModelData, WhisperError> {
+    let [weights, config, tokenizer] = variant.file_names()?;
+    fetch(repo, [&weights, &config, &tokenizer], cache_dir, progress)
+        .await
+        .map_err(WhisperError::model_load)
+}
+
+async fn fetch(
+    repo: &str,
+    files: [&str; 3],
+    cache_dir: &Path,
+    progress: &mut (dyn FnMut(&FetchProgress) + Send),
+) -> anyhow::Result<ModelData> {
+    let client = Client::new();
+    let blobs = cache_dir.join("blobs");
+    tokio::fs::create_dir_all(&blobs).await?;
+    let mut data = vec![];
+    for file in files {
+        let remote = head(&client, repo, file)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{} not found", file_url(repo, file)))?;
+        let path = fetch_file(&client, &remote, file, &blobs, progress).await?;
+        data.push(tokio::fs::read(path).await?);
+    }
+    let mel_filters = match head(&client, repo, MEL_FILTERS_FILE).await? {
+        Some(remote) => {
+            let path = fetch_file(&client, &remote, MEL_FILTERS_FILE, &blobs, progress).await?;
+            tokio::fs::read(path).await?
+        }
+        None => vec![],
+    };
+    let [weights, config, tokenizer]: [Vec<u8>; 3] = data.try_into().expect("one buffer per file");
+    Ok(ModelData::from_parts(
+        weights,
+        tokenizer,
+        mel_filters,
+        config,
+    ))
+}
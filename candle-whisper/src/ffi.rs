@@ -0,0 +1,251 @@
+//! C ABI of the decoder for the native hosts that cannot link Rust, behind the `ffi` feature.
+//!
+//! The library is built with `cargo rustc --release --features ffi --crate-type cdylib` and the
+//! `include/candle_whisper.h` header is generated with
+//! `cbindgen --config cbindgen.toml --output include/candle_whisper.h`.
+//!
+//! The fallible functions return a status code and, when their `out_err` argument is not null,
+//! store the JSON of the error, `{"code": ..., "message": ...}` as thrown to the JS callers.
+//! The strings returned through the `out_*` arguments are owned by the caller and released with
+//! [`whisper_string_free`]. Panics are caught at the boundary and reported as
+//! [`WHISPER_PANIC`], the decoder should then be freed.
+//!
+//! A decoder is not thread safe: it must only be used from the thread that created it, hosts
+//! decoding in parallel create one decoder per thread.
+
+use crate::{
+    error::WhisperError,
+    logic::{Decoder, ModelData},
+};
+
+use serde_json::{json, Value};
+use std::{
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+/// The call succeeded.
+pub const WHISPER_OK: i32 = 0;
+/// The decoder failed, the error JSON is the one of the `WhisperError`.
+pub const WHISPER_ERROR: i32 = 1;
+/// A pointer is null or a string is not UTF-8, the error code is `invalid_argument`.
+pub const WHISPER_INVALID_ARGUMENT: i32 = 2;
+/// A panic was caught, the error code is `panic`.
+pub const WHISPER_PANIC: i32 = 3;
+
+/// Status and error JSON of a failed call.
+struct Failure {
+    status: i32,
+    json: String,
+}
+
+impl Failure {
+    fn new(status: i32, code: &str, message: impl ToString) -> Self {
+        let json = json!({ "code": code, "message": message.to_string() }).to_string();
+        Self { status, json }
+    }
+
+    fn invalid_argument(message: impl ToString) -> Self {
+        Self::new(WHISPER_INVALID_ARGUMENT, "invalid_argument", message)
+    }
+}
+
+impl From<WhisperError> for Failure {
+    fn from(e: WhisperError) -> Self {
+        Self {
+            status: WHISPER_ERROR,
+            json: serde_json::to_string(&e).unwrap_or_else(|_| e.to_string()),
+        }
+    }
+}
+
+/// Runs `f` with the panics caught, storing the error JSON of a failure in `out_err`.
+///
+/// # Safety
+///
+/// `out_err` must be null or valid for writes.
+unsafe fn guard<T>(
+    out_err: *mut *mut c_char,
+    f: impl FnOnce() -> Result<T, Failure>,
+) -> Result<T, i32> {
+    write_string(out_err, None);
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(Failure::new(WHISPER_PANIC, "panic", message))
+    });
+    result.map_err(|failure| {
+        write_string(out_err, Some(failure.json));
+        failure.status
+    })
+}
+
+/// Stores `s` as a C string owned by the caller in `out`, or null for `None`.
+///
+/// # Safety
+///
+/// `out` must be null or valid for writes.
+unsafe fn write_string(out: *mut *mut c_char, s: Option<String>) {
+    if out.is_null() {
+        return;
+    }
+    *out = s.map_or(ptr::null_mut(), |s| {
+        // The JSON escapes the NUL characters, only the plain messages can contain one.
+        CString::new(s.replace('\0', " ")).unwrap().into_raw()
+    });
+}
+
+/// # Safety
+///
+/// `bytes` must be null or valid for reads of `len` bytes.
+unsafe fn byte_slice<'a>(bytes: *const u8, len: usize) -> Result<&'a [u8], Failure> {
+    match (bytes.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(Failure::invalid_argument("null buffer")),
+        (false, _) => Ok(slice::from_raw_parts(bytes, len)),
+    }
+}
+
+/// # Safety
+///
+/// `s` must be null or a NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    if s.is_null() {
+        return Err(Failure::invalid_argument(format!("null {name}")));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| Failure::invalid_argument(format!("{name} is not UTF-8")))
+}
+
+/// # Safety
+///
+/// `decoder` must be null or returned by [`whisper_decoder_new`] and not freed.
+unsafe fn decoder_arg<'a>(decoder: *mut Decoder) -> Result<&'a mut Decoder, Failure> {
+    decoder
+        .as_mut()
+        .ok_or_else(|| Failure::invalid_argument("null decoder"))
+}
+
+/// Sets the option at the dot separated `key` of the JSON of the `DecodeOptions`, e.g.
+/// `temperatures` or `vad.min_rms`. Values that do not parse as JSON are taken as strings.
+fn set_option(decoder: &mut Decoder, key: &str, value: &str) -> Result<(), WhisperError> {
+    let invalid = |reason: String| WhisperError::InvalidConfig { reason };
+    let mut options = serde_json::to_value(decoder.options()).map_err(anyhow::Error::from)?;
+    let field = key
+        .split('.')
+        .try_fold(&mut options, |object, name| {
+            object.as_object_mut()?.get_mut(name)
+        })
+        .ok_or_else(|| invalid(format!("unknown option {key}")))?;
+    *field = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    let options = serde_json::from_value(options)
+        .map_err(|e| invalid(format!("invalid value for option {key}: {e}")))?;
+    decoder.set_options(options)
+}
+
+/// Loads a decoder from the JSON of a `ModelData`, returns null on failure.
+///
+/// # Safety
+///
+/// `model_data` must be valid for reads of `len` bytes and `out_err` must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn whisper_decoder_new(
+    model_data: *const u8,
+    len: usize,
+    out_err: *mut *mut c_char,
+) -> *mut Decoder {
+    guard(out_err, || {
+        let bytes = byte_slice(model_data, len)?;
+        let md: ModelData = serde_json::from_slice(bytes).map_err(|e| {
+            Failure::from(WhisperError::InvalidConfig {
+                reason: format!("invalid model data: {e}"),
+            })
+        })?;
+        Ok(Box::into_raw(Box::new(Decoder::load(md)?)))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Transcribes a 16kHz WAV file, storing the JSON of the `TranscriptionOutput` in `out_json`.
+///
+/// # Safety
+///
+/// `decoder` must come from [`whisper_decoder_new`], `wav` must be valid for reads of `len`
+/// bytes and `out_json` and `out_err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn whisper_decoder_transcribe_wav(
+    decoder: *mut Decoder,
+    wav: *const u8,
+    len: usize,
+    out_json: *mut *mut c_char,
+    out_err: *mut *mut c_char,
+) -> i32 {
+    write_string(out_json, None);
+    let json = guard(out_err, || {
+        let decoder = decoder_arg(decoder)?;
+        let output = decoder.convert_and_run(byte_slice(wav, len)?)?;
+        serde_json::to_string(&output)
+            .map_err(|e| Failure::from(WhisperError::from(anyhow::Error::from(e))))
+    });
+    match json {
+        Ok(json) => {
+            write_string(out_json, Some(json));
+            WHISPER_OK
+        }
+        Err(status) => status,
+    }
+}
+
+/// Sets one of the `DecodeOptions` by the dot separated path of its JSON field, e.g.
+/// `("temperatures", "[0.0, 0.2]")`, `("use_vad", "true")` or `("vad.min_rms", "0.01")`.
+///
+/// # Safety
+///
+/// `decoder` must come from [`whisper_decoder_new`], `key` and `value` must be NUL-terminated
+/// strings and `out_err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn whisper_decoder_set_option(
+    decoder: *mut Decoder,
+    key: *const c_char,
+    value: *const c_char,
+    out_err: *mut *mut c_char,
+) -> i32 {
+    guard(out_err, || {
+        let decoder = decoder_arg(decoder)?;
+        let (key, value) = (str_arg(key, "key")?, str_arg(value, "value")?);
+        Ok(set_option(decoder, key, value)?)
+    })
+    .err()
+    .unwrap_or(WHISPER_OK)
+}
+
+/// Frees a decoder, null is ignored.
+///
+/// # Safety
+///
+/// `decoder` must be null or come from [`whisper_decoder_new`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn whisper_decoder_free(decoder: *mut Decoder) {
+    if !decoder.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(decoder))));
+    }
+}
+
+/// Frees a string returned by the other functions, null is ignored.
+///
+/// # Safety
+///
+/// `s` must be null or come from an `out_json` or `out_err` argument and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn whisper_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
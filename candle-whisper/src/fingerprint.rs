@@ -0,0 +1,173 @@
+//! Cheap comparisons of the uploads on their mel spectrogram, to catch the files uploaded twice
+//! and the files without speech before spending decode time on them.
+
+use crate::{audio::MelSpectrogram, error::WhisperError, logic::m};
+
+/// Mel bands of the perceptual hash grid.
+const GRID_BANDS: usize = 16;
+
+/// Time bins of the perceptual hash grid, the hash has `GRID_BANDS * GRID_BINS` bits.
+const GRID_BINS: usize = 16;
+
+/// Percentile of the frame energies taken as the noise floor.
+const NOISE_FLOOR_PERCENTILE: f64 = 0.1;
+
+/// Normalized log-mel units by which a frame must exceed the noise floor to count as speech,
+/// the values being a quarter of a decade of power this is 20dB.
+const NOISE_MARGIN: f32 = 0.5;
+
+/// Frame energy from which a frame counts as speech whatever the noise floor, so that audio
+/// without pauses is not taken for noise, 0 being a power of 1e-4 in every band.
+const ACTIVE_LEVEL: f32 = 0.;
+
+impl MelSpectrogram {
+    /// Coarse perceptual hash of the spectrogram: the mel of the audio, the padding excluded,
+    /// is averaged on a 16 bands by 16 time bins grid whose cells are set when above the
+    /// median cell. The hash does not depend on the gain, and close audio have close hashes.
+    pub fn perceptual_hash(&self) -> Result<[u8; 32], WhisperError> {
+        let (data, frames) = self.audio_frames()?;
+        let mut hash = [0u8; 32];
+        if frames == 0 {
+            return Ok(hash);
+        }
+        let ranges = |n: usize, parts: usize| {
+            (0..parts).map(move |i| {
+                let start = usize::min(i * n / parts, n - 1);
+                start..usize::max((i + 1) * n / parts, start + 1)
+            })
+        };
+        let n_frames = self.n_frames();
+        let mut cells = Vec::with_capacity(GRID_BANDS * GRID_BINS);
+        for bands in ranges(self.n_mels(), GRID_BANDS) {
+            for bin in ranges(frames, GRID_BINS) {
+                let sum: f32 = bands
+                    .clone()
+                    .flat_map(|row| &data[row * n_frames + bin.start..row * n_frames + bin.end])
+                    .sum();
+                cells.push(sum / (bands.len() * bin.len()) as f32);
+            }
+        }
+        let mut sorted = cells.clone();
+        sorted.sort_by(f32::total_cmp);
+        let half = sorted.len() / 2;
+        let median = (sorted[half - 1] + sorted[half]) / 2.;
+        for (i, _) in cells.iter().enumerate().filter(|(_, &v)| v > median) {
+            hash[i / 8] |= 0x80 >> (i % 8);
+        }
+        Ok(hash)
+    }
+
+    /// SHA-256 of the [`MelSpectrogram::perceptual_hash`], identical for the same audio.
+    ///
+    /// Hashing loses the closeness of the perceptual hashes, use [`similarity`] to compare
+    /// audio that may have been re-encoded.
+    pub fn fingerprint(&self) -> Result<[u8; 32], WhisperError> {
+        Ok(sha256(&self.perceptual_hash()?))
+    }
+
+    /// Fraction of the frames of the audio whose mean mel energy exceeds an adaptive floor:
+    /// 20dB above the 10th percentile of the frame energies, or a fixed level for the audio
+    /// without quiet frames. 0 for digital silence.
+    pub fn speech_energy_ratio(&self) -> Result<f32, WhisperError> {
+        let (data, frames) = self.audio_frames()?;
+        if frames == 0 {
+            return Ok(0.);
+        }
+        let (n_mels, n_frames) = (self.n_mels(), self.n_frames());
+        let energies: Vec<f32> = (0..frames)
+            .map(|i| (0..n_mels).map(|row| data[row * n_frames + i]).sum::<f32>() / n_mels as f32)
+            .collect();
+        let mut sorted = energies.clone();
+        sorted.sort_by(f32::total_cmp);
+        let noise_floor = sorted[((frames - 1) as f64 * NOISE_FLOOR_PERCENTILE) as usize];
+        let floor = f32::min(noise_floor + NOISE_MARGIN, ACTIVE_LEVEL);
+        let active = energies.iter().filter(|&&e| e > floor).count();
+        Ok(active as f32 / frames as f32)
+    }
+
+    /// Values of the spectrogram and number of frames of the audio, the padding excluded.
+    fn audio_frames(&self) -> Result<(Vec<f32>, usize), WhisperError> {
+        let data = self.tensor().flatten_all()?.to_vec1::<f32>()?;
+        let frames = (self.duration() * m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64).ceil();
+        Ok((data, usize::min(frames as usize, self.n_frames())))
+    }
+}
+
+/// Similarity in `[0, 1]` of two spectrograms, the fraction of equal bits of their
+/// [`MelSpectrogram::perceptual_hash`]. Unrelated audio are around 0.5.
+pub fn similarity(a: &MelSpectrogram, b: &MelSpectrogram) -> Result<f32, WhisperError> {
+    let (a, b) = (a.perceptual_hash()?, b.perceptual_hash()?);
+    let distance: u32 = a.iter().zip(&b).map(|(a, b)| (a ^ b).count_ones()).sum();
+    Ok(1. - distance as f32 / (a.len() * 8) as f32)
+}
+
+/// SHA-256 digest of `data`.
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    // Only the last partial block is copied to be padded, the weights being hashed as well.
+    let blocks = data.chunks_exact(64);
+    let mut tail = blocks.remainder().to_vec();
+    tail.push(0x80);
+    while tail.len() % 64 != 56 {
+        tail.push(0);
+    }
+    tail.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in blocks.chain(tail.chunks_exact(64)) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0u8; 32];
+    for (bytes, h) in digest.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
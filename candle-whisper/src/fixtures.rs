@@ -0,0 +1,405 @@
+//! Tiny deterministic model and generated audio, for the tests of this crate and of its users.
+//!
+//! The model is a 2-layer whisper with a `d_model` of 64 and a toy English-only vocabulary.
+//! Its weights are pseudo-random, the transcripts are meaningless but the whole pipeline runs
+//! on it in seconds.
+
+use crate::{
+    audio,
+    logging::{Level, Logger},
+    logic::{m, Config, ModelData, Segment, MULTILINGUAL_VOCAB_SIZE},
+};
+
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use safetensors::tensor::{Dtype, TensorView};
+use serde_json::json;
+use std::{cell::RefCell, rc::Rc};
+
+/// Text tokens of the toy vocabulary, their ids follow this order.
+pub const TEXT_TOKENS: &[&str] = &[
+    "<unk>", "hello", "world", "the", "sound", "of", "a", "sine", "wave", ",", ".",
+];
+
+/// Special tokens following the text tokens, the timestamp tokens come after
+/// `<|notimestamps|>` and are only counted in the vocabulary size of the config.
+pub const SPECIAL_TOKENS: &[&str] = &[
+    m::EOT_TOKEN,
+    m::SOT_TOKEN,
+    m::TRANSLATE_TOKEN,
+    m::TRANSCRIBE_TOKEN,
+    "<|nospeech|>",
+    m::NO_TIMESTAMPS_TOKEN,
+];
+
+const N_TIMESTAMP_TOKENS: usize = 1501;
+
+/// Language tokens of the tokenizer of the multilingual whisper models, in their order.
+pub const LANGUAGE_TOKENS: &[&str] = &[
+    "<|en|>", "<|zh|>", "<|de|>", "<|es|>", "<|ru|>", "<|ko|>", "<|fr|>", "<|ja|>", "<|pt|>",
+    "<|tr|>", "<|pl|>", "<|ca|>", "<|nl|>", "<|ar|>", "<|sv|>", "<|it|>", "<|id|>", "<|hi|>",
+    "<|fi|>", "<|vi|>", "<|he|>", "<|uk|>", "<|el|>", "<|ms|>", "<|cs|>", "<|ro|>", "<|da|>",
+    "<|hu|>", "<|ta|>", "<|no|>", "<|th|>", "<|ur|>", "<|hr|>", "<|bg|>", "<|lt|>", "<|la|>",
+    "<|mi|>", "<|ml|>", "<|cy|>", "<|sk|>", "<|te|>", "<|fa|>", "<|lv|>", "<|bn|>", "<|sr|>",
+    "<|az|>", "<|sl|>", "<|kn|>", "<|et|>", "<|mk|>", "<|br|>", "<|eu|>", "<|is|>", "<|hy|>",
+    "<|ne|>", "<|mn|>", "<|bs|>", "<|kk|>", "<|sq|>", "<|sw|>", "<|gl|>", "<|mr|>", "<|pa|>",
+    "<|si|>", "<|km|>", "<|sn|>", "<|yo|>", "<|so|>", "<|af|>", "<|oc|>", "<|ka|>", "<|be|>",
+    "<|tg|>", "<|sd|>", "<|gu|>", "<|am|>", "<|yi|>", "<|lo|>", "<|uz|>", "<|fo|>", "<|ht|>",
+    "<|ps|>", "<|tk|>", "<|nn|>", "<|mt|>", "<|sa|>", "<|lb|>", "<|my|>", "<|bo|>", "<|tl|>",
+    "<|mg|>", "<|as|>", "<|tt|>", "<|haw|>", "<|ln|>", "<|ha|>", "<|ba|>", "<|jw|>", "<|su|>",
+];
+
+const D_MODEL: usize = 64;
+
+/// Scale of the pseudo-random weights.
+const WEIGHT_SCALE: f32 = 0.1;
+
+pub fn tiny_config() -> Config {
+    serde_json::from_slice(&tiny_config_json()).expect("valid tiny config")
+}
+
+pub fn tiny_config_json() -> Vec<u8> {
+    config_json(TEXT_TOKENS.len() + SPECIAL_TOKENS.len() + N_TIMESTAMP_TOKENS)
+}
+
+fn config_json(vocab_size: usize) -> Vec<u8> {
+    let config = json!({
+        "num_mel_bins": 80,
+        "max_source_positions": m::N_FRAMES / 2,
+        "d_model": D_MODEL,
+        "encoder_attention_heads": 4,
+        "encoder_layers": 2,
+        "vocab_size": vocab_size,
+        "max_target_positions": 24,
+        "decoder_attention_heads": 4,
+        "decoder_layers": 2,
+        "suppress_tokens": [],
+    });
+    serde_json::to_vec(&config).expect("serializable config")
+}
+
+/// Word-level tokenizer over [`TEXT_TOKENS`] with the whisper special tokens.
+pub fn tiny_tokenizer_json() -> Vec<u8> {
+    tokenizer_json(SPECIAL_TOKENS)
+}
+
+/// Word-level tokenizer over [`TEXT_TOKENS`] with the special tokens of the multilingual
+/// models, the language tokens following `<|startoftranscript|>`.
+pub fn multilingual_tokenizer_json() -> Vec<u8> {
+    tokenizer_json(&multilingual_special_tokens())
+}
+
+fn multilingual_special_tokens() -> Vec<&'static str> {
+    [m::EOT_TOKEN, m::SOT_TOKEN]
+        .into_iter()
+        .chain(LANGUAGE_TOKENS.iter().copied())
+        .chain([
+            m::TRANSLATE_TOKEN,
+            m::TRANSCRIBE_TOKEN,
+            "<|startoflm|>",
+            "<|startofprev|>",
+            "<|nospeech|>",
+            m::NO_TIMESTAMPS_TOKEN,
+        ])
+        .collect()
+}
+
+fn tokenizer_json(special_tokens: &[&str]) -> Vec<u8> {
+    tokenizer_json_with(TEXT_TOKENS, special_tokens)
+}
+
+fn tokenizer_json_with<T: AsRef<str>>(text_tokens: &[T], special_tokens: &[&str]) -> Vec<u8> {
+    let vocab: serde_json::Map<String, serde_json::Value> = text_tokens
+        .iter()
+        .map(AsRef::as_ref)
+        .chain(special_tokens.iter().copied())
+        .enumerate()
+        .map(|(id, token)| (token.to_string(), json!(id)))
+        .collect();
+    let added_tokens: Vec<_> = special_tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            json!({
+                "id": text_tokens.len() + i,
+                "content": token,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            })
+        })
+        .collect();
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": { "type": "WhitespaceSplit" },
+        "post_processor": null,
+        "decoder": null,
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "<unk>" },
+    });
+    serde_json::to_vec(&tokenizer).expect("serializable tokenizer")
+}
+
+/// Pseudo-random values in `[-WEIGHT_SCALE / 2, WEIGHT_SCALE / 2)` seeded by `name`.
+fn pseudo_random(name: &str, len: usize) -> Vec<f32> {
+    let mut state = name.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    (0..len)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            ((state >> 40) as f32 / (1u64 << 24) as f32 - 0.5) * WEIGHT_SCALE
+        })
+        .collect()
+}
+
+/// Safetensors weights of a model with the given config. The layer norms are initialized to
+/// the identity and the other tensors to pseudo-random values that only depend on their name.
+pub fn tiny_weights(config: &Config) -> anyhow::Result<Vec<u8>> {
+    tiny_weights_with(config, |_, _| {})
+}
+
+/// [`tiny_weights`] with the values of each tensor passed through `edit` with its name, e.g.
+/// to inject NaN weights.
+pub fn tiny_weights_with(
+    config: &Config,
+    edit: impl Fn(&str, &mut [f32]),
+) -> anyhow::Result<Vec<u8>> {
+    // Loading the model from an empty var map lists the names and shapes of its tensors.
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    m::model::Whisper::load(&vb, config.clone())?;
+    let mut shapes: Vec<(String, Vec<usize>)> = varmap
+        .data()
+        .lock()
+        .map_err(|_| anyhow::anyhow!("poisoned var map"))?
+        .iter()
+        .map(|(name, var)| (name.clone(), var.dims().to_vec()))
+        .collect();
+    shapes.sort();
+    let data: Vec<Vec<u8>> = shapes
+        .iter()
+        .map(|(name, shape)| {
+            let len = shape.iter().product();
+            let mut values = if name.ends_with("norm.weight") {
+                vec![1.; len]
+            } else if name.ends_with("norm.bias") {
+                vec![0.; len]
+            } else {
+                pseudo_random(name, len)
+            };
+            edit(name, &mut values);
+            values.iter().flat_map(|v| v.to_le_bytes()).collect()
+        })
+        .collect();
+    let views = shapes
+        .iter()
+        .zip(data.iter())
+        .map(|((name, shape), data)| {
+            Ok((
+                name.as_str(),
+                TensorView::new(Dtype::F32, shape.clone(), data)?,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(safetensors::tensor::serialize(views, &None)?)
+}
+
+/// Safetensors file holding the `mel_80` filterbank.
+pub fn tiny_mel_filters() -> anyhow::Result<Vec<u8>> {
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, 80);
+    let data: Vec<u8> = filters.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let view = TensorView::new(Dtype::F32, vec![80, m::N_FFT / 2 + 1], &data)?;
+    Ok(safetensors::tensor::serialize([("mel_80", view)], &None)?)
+}
+
+/// Model data of the tiny model, transcribing without timestamps.
+pub fn tiny_model_data() -> ModelData {
+    ModelData {
+        weights: tiny_weights(&tiny_config()).expect("tiny weights"),
+        tokenizer: tiny_tokenizer_json(),
+        mel_filters: tiny_mel_filters().expect("tiny mel filters"),
+        config: tiny_config_json(),
+        quantized: false,
+        timestamps: false,
+        is_multilingual: false,
+        language: None,
+        task: None,
+        dtype: None,
+        quantize_on_load: None,
+        manifest: None,
+    }
+}
+
+/// [`tiny_model_data`] with a tokenizer without `<|translate|>` nor `<|transcribe|>`, which an
+/// English-only model does not need, `<|startoflm|>` and `<|startofprev|>` taking their ids.
+pub fn english_only_model_data() -> ModelData {
+    let special_tokens = [
+        m::EOT_TOKEN,
+        m::SOT_TOKEN,
+        "<|startoflm|>",
+        "<|startofprev|>",
+        "<|nospeech|>",
+        m::NO_TIMESTAMPS_TOKEN,
+    ];
+    ModelData {
+        tokenizer: tokenizer_json(&special_tokens),
+        ..tiny_model_data()
+    }
+}
+
+/// [`tiny_model_data`] with the tokenizer of [`multilingual_tokenizer_json`], which has all the
+/// special tokens. The vocabulary is too small for the model to be considered multilingual.
+pub fn multilingual_model_data() -> ModelData {
+    let vocab_size = TEXT_TOKENS.len() + multilingual_special_tokens().len() + N_TIMESTAMP_TOKENS;
+    let config = config_json(vocab_size);
+    let weights = serde_json::from_slice(&config)
+        .map_err(anyhow::Error::from)
+        .and_then(|config| tiny_weights(&config))
+        .expect("tiny weights");
+    ModelData {
+        weights,
+        tokenizer: multilingual_tokenizer_json(),
+        config,
+        ..tiny_model_data()
+    }
+}
+
+/// [`multilingual_model_data`] with the vocabulary size of the multilingual whisper models,
+/// [`TEXT_TOKENS`] being followed by unused filler words, so that the model is considered
+/// multilingual: it detects the language and translates. Its embedding makes it about ten
+/// times as large as the tiny model.
+pub fn large_vocab_model_data() -> ModelData {
+    let special_tokens = multilingual_special_tokens();
+    let fillers = MULTILINGUAL_VOCAB_SIZE - N_TIMESTAMP_TOKENS - special_tokens.len();
+    let text_tokens: Vec<String> = TEXT_TOKENS
+        .iter()
+        .map(|token| token.to_string())
+        .chain((TEXT_TOKENS.len()..fillers).map(|i| format!("filler{i}")))
+        .collect();
+    let config = config_json(MULTILINGUAL_VOCAB_SIZE);
+    let weights = serde_json::from_slice(&config)
+        .map_err(anyhow::Error::from)
+        .and_then(|config| tiny_weights(&config))
+        .expect("tiny weights");
+    ModelData {
+        weights,
+        tokenizer: tokenizer_json_with(&text_tokens, &special_tokens),
+        config,
+        is_multilingual: true,
+        ..tiny_model_data()
+    }
+}
+
+/// Logger keeping the messages, clones share the messages.
+#[derive(Debug, Clone, Default)]
+pub struct CapturingLogger {
+    messages: Rc<RefCell<Vec<(Level, String)>>>,
+}
+
+impl CapturingLogger {
+    pub fn messages(&self) -> Vec<(Level, String)> {
+        self.messages.borrow().clone()
+    }
+
+    /// Whether a message at `level` contains `text`.
+    pub fn contains(&self, level: Level, text: &str) -> bool {
+        self.messages
+            .borrow()
+            .iter()
+            .any(|(l, msg)| *l == level && msg.contains(text))
+    }
+}
+
+impl Logger for CapturingLogger {
+    fn log(&self, level: Level, msg: &str) {
+        self.messages.borrow_mut().push((level, msg.to_string()))
+    }
+}
+
+/// Segment of `text` without tokens, with neutral decoding statistics, for the tests of the
+/// functions working on transcripts. The other fields are set with the struct update syntax.
+pub fn segment(id: usize, start: f64, duration: f64, text: &str) -> Segment {
+    serde_json::from_value(json!({
+        "id": id,
+        "start": start,
+        "duration": duration,
+        "dr": {
+            "tokens": [],
+            "text": text,
+            "avg_logprob": 0.,
+            "no_speech_prob": 0.,
+            "temperature": 0.,
+            "compression_ratio": null,
+        },
+    }))
+    .expect("valid segment")
+}
+
+/// 16kHz mono samples of a sine wave at half the full scale.
+pub fn sine_pcm(seconds: f64, freq: f64) -> Vec<f32> {
+    let len = (seconds * m::SAMPLE_RATE as f64) as usize;
+    (0..len)
+        .map(|i| {
+            let t = i as f64 / m::SAMPLE_RATE as f64;
+            (0.5 * (2. * std::f64::consts::PI * freq * t).sin()) as f32
+        })
+        .collect()
+}
+
+/// 16-bit 16kHz mono WAV file of [`sine_pcm`].
+pub fn sine_wav(seconds: f64, freq: f64) -> Vec<u8> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: m::SAMPLE_RATE as u32,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = std::io::Cursor::new(vec![]);
+    let mut writer = hound::WavWriter::new(&mut bytes, spec).expect("in-memory writer");
+    for sample in sine_pcm(seconds, freq) {
+        let sample = (sample * i16::MAX as f32) as i16;
+        writer.write_sample(sample).expect("in-memory write");
+    }
+    writer.finalize().expect("in-memory finalize");
+    bytes.into_inner()
+}
+
+/// RIFF/WAVE file made of `chunks` in their order, the odd-sized ones padded.
+pub fn riff_wav(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut body = b"WAVE".to_vec();
+    for (id, data) in chunks {
+        body.extend_from_slice(*id);
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            body.push(0)
+        }
+    }
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+/// Body of a `fmt ` chunk of interleaved samples of `bits_per_sample` bits in the format of
+/// `tag`, 1 for integers and 3 for floats.
+pub fn fmt_chunk(tag: u16, channels: u16, sample_rate: u32, bits_per_sample: u16) -> Vec<u8> {
+    let block_align = channels * bits_per_sample.div_ceil(8);
+    let mut fmt = vec![];
+    fmt.extend_from_slice(&tag.to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+    fmt
+}
@@ -0,0 +1,5 @@
+//! Machine-readable output formats of the transcripts, the captions being in
+//! [`crate::captions`].
+
+pub mod jsonl;
+pub mod verbose_json;
@@ -0,0 +1,260 @@
+//! JSON Lines stream of a transcription, one record per line written as the segments are
+//! pushed, for piping into `jq` or indexers without waiting for the whole transcript.
+//!
+//! Every line is an object with the schema version `"v"` and a `"type"` discriminator, see
+//! [`JsonlRecord`]. Readers reject the major versions they do not know.
+
+use crate::{
+    diagnostics::WindowDiagnostics,
+    logic::{Segment, TranscriptionOutput},
+    timings::Timings,
+};
+
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+/// Major version of the schema written in the `"v"` field of every line.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Line of the stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonlRecord {
+    /// Segment with speech, or a placeholder of a window that failed to decode.
+    Segment(Box<Segment>),
+    /// Range of the audio without speech.
+    NoSpeech {
+        id: usize,
+        start: f64,
+        duration: f64,
+    },
+    /// What happened to a window, with `DecodeOptions::collect_diagnostics`.
+    Diagnostics(WindowDiagnostics),
+    /// Last line of a complete stream.
+    Summary(JsonlSummary),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonlSummary {
+    /// Seconds of audio transcribed.
+    pub duration: f64,
+    /// Language of the transcript, detected or pinned.
+    pub language: Option<String>,
+    /// Number of segment and no-speech lines of the stream, to tell a complete stream.
+    pub segments: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+/// Line as written, the record with the schema version.
+#[derive(Serialize)]
+struct VersionedRecord<'a> {
+    v: u64,
+    #[serde(flatten)]
+    record: &'a JsonlRecord,
+}
+
+/// Writes the records of a transcription as JSON Lines, flushing after every line so that the
+/// consumers see each segment as soon as it is pushed.
+pub struct SegmentJsonlWriter<W: Write> {
+    writer: W,
+    segments: usize,
+}
+
+impl<W: Write> SegmentJsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            segments: 0,
+        }
+    }
+
+    /// Writes one line.
+    pub fn write_record(&mut self, record: &JsonlRecord) -> std::io::Result<()> {
+        if matches!(
+            record,
+            JsonlRecord::Segment(_) | JsonlRecord::NoSpeech { .. }
+        ) {
+            self.segments += 1;
+        }
+        serde_json::to_writer(
+            &mut self.writer,
+            &VersionedRecord {
+                v: SCHEMA_VERSION,
+                record,
+            },
+        )?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    /// Writes a segment, as a no-speech line when it has no speech.
+    pub fn push(&mut self, segment: &Segment) -> std::io::Result<()> {
+        let record = if segment.no_speech {
+            JsonlRecord::NoSpeech {
+                id: segment.id,
+                start: segment.start,
+                duration: segment.duration,
+            }
+        } else {
+            JsonlRecord::Segment(Box::new(segment.clone()))
+        };
+        self.write_record(&record)
+    }
+
+    pub fn push_diagnostics(&mut self, window: &WindowDiagnostics) -> std::io::Result<()> {
+        self.write_record(&JsonlRecord::Diagnostics(window.clone()))
+    }
+
+    /// Writes the summary line of the transcription of `duration` seconds of audio and returns
+    /// the underlying writer.
+    pub fn finish(mut self, output: &TranscriptionOutput, duration: f64) -> std::io::Result<W> {
+        let summary = JsonlSummary {
+            duration,
+            language: output.language.clone(),
+            segments: self.segments,
+            timings: output.timings.clone(),
+        };
+        self.write_record(&JsonlRecord::Summary(summary))?;
+        Ok(self.writer)
+    }
+
+    /// Writes a complete transcription: its segments, the diagnostics of its windows and the
+    /// summary.
+    pub fn write_output(
+        mut self,
+        output: &TranscriptionOutput,
+        duration: f64,
+    ) -> std::io::Result<W> {
+        for segment in &output.segments {
+            self.push(segment)?;
+        }
+        for window in output.diagnostics.iter().flat_map(|d| &d.windows) {
+            self.push_diagnostics(window)?;
+        }
+        self.finish(output, duration)
+    }
+
+    /// The underlying writer, without a summary line.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonlErrorKind {
+    /// The reader failed, no more lines are read.
+    Io,
+    /// The last line ends before its record, e.g. a stream still being written or a
+    /// transcription that was interrupted.
+    Truncated,
+    /// The line is not a JSON object.
+    Syntax,
+    /// The line has no `"v"` field or a major version other than [`SCHEMA_VERSION`].
+    UnsupportedVersion,
+    /// The line is not a record of the schema.
+    InvalidRecord,
+}
+
+/// Line that could not be read. The lines after it are still read, except after an `Io`
+/// error.
+#[derive(Debug)]
+pub struct JsonlError {
+    pub kind: JsonlErrorKind,
+    /// Line number, from 1.
+    pub line: usize,
+    /// Offset in bytes of the start of the line in the stream.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for JsonlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {} at byte {}: {}",
+            self.line, self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for JsonlError {}
+
+/// Records of a JSON Lines stream, the blank lines being skipped.
+pub fn from_reader<R: BufRead>(reader: R) -> JsonlRecords<R> {
+    JsonlRecords {
+        reader: Some(reader),
+        line: 0,
+        offset: 0,
+    }
+}
+
+/// Iterator returned by [`from_reader`].
+pub struct JsonlRecords<R: BufRead> {
+    /// `None` once the reader failed.
+    reader: Option<R>,
+    line: usize,
+    offset: usize,
+}
+
+impl<R: BufRead> JsonlRecords<R> {
+    fn parse(&self, bytes: &[u8], offset: usize) -> Result<JsonlRecord, JsonlError> {
+        let value: serde_json::Value = match serde_json::from_slice(bytes) {
+            Ok(value) => value,
+            Err(err) => {
+                let kind = if err.is_eof() && !bytes.ends_with(b"\n") {
+                    JsonlErrorKind::Truncated
+                } else {
+                    JsonlErrorKind::Syntax
+                };
+                return Err(self.error(kind, offset, err));
+            }
+        };
+        let version = value.get("v");
+        if version.and_then(serde_json::Value::as_u64) != Some(SCHEMA_VERSION) {
+            let version = version.map_or("none".to_string(), ToString::to_string);
+            return Err(self.error(
+                JsonlErrorKind::UnsupportedVersion,
+                offset,
+                format!("unsupported schema version {version}"),
+            ));
+        }
+        serde_json::from_value(value)
+            .map_err(|err| self.error(JsonlErrorKind::InvalidRecord, offset, err))
+    }
+
+    fn error(&self, kind: JsonlErrorKind, offset: usize, message: impl ToString) -> JsonlError {
+        JsonlError {
+            kind,
+            line: self.line,
+            offset,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlRecords<R> {
+    type Item = Result<JsonlRecord, JsonlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut bytes = vec![];
+            let read = self.reader.as_mut()?.read_until(b'\n', &mut bytes);
+            let offset = self.offset;
+            self.line += 1;
+            let read = match read {
+                Ok(0) => return None,
+                Ok(read) => read,
+                Err(err) => {
+                    self.reader = None;
+                    return Some(Err(self.error(JsonlErrorKind::Io, offset, err)));
+                }
+            };
+            self.offset += read;
+            if bytes.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            return Some(self.parse(&bytes, offset));
+        }
+    }
+}
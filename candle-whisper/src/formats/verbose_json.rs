@@ -0,0 +1,115 @@
+//! The `verbose_json` transcript of the OpenAI transcription API, which openai/whisper and
+//! whisper.cpp write too, for the tools consuming it, e.g. the karaoke caption editors reading
+//! the `words` of the segments.
+
+use crate::{
+    logic::{Segment, Task, TranscriptionOutput},
+    segments::is_speech,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerboseTranscript {
+    pub task: Task,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Seconds of audio transcribed.
+    pub duration: f64,
+    /// Text of the segments, concatenated.
+    pub text: String,
+    pub segments: Vec<VerboseSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerboseSegment {
+    pub id: usize,
+    /// Mel frame the segment starts at.
+    pub seek: usize,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    /// Tokens of the decoding result, its prompt and timestamp tokens included.
+    pub tokens: Vec<u32>,
+    pub temperature: f64,
+    pub avg_logprob: f64,
+    /// `null` when not computed.
+    pub compression_ratio: Option<f64>,
+    pub no_speech_prob: f64,
+    /// Left out when the segment has no word timings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub words: Option<Vec<VerboseWord>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerboseWord {
+    /// The word with its leading space, none for the CJK characters.
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+    /// Left out when the probabilities of the tokens are not known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probability: Option<f32>,
+}
+
+/// Mel frames per second, the unit of `VerboseSegment::seek`.
+const FRAMES_PER_SECOND: f64 = 100.;
+
+fn verbose_segment(segment: &Segment) -> VerboseSegment {
+    let start = segment.start;
+    let end = segment.start + segment.duration;
+    let words = (!segment.words.is_empty()).then(|| {
+        segment
+            .words
+            .iter()
+            .map(|word| {
+                let word_start = word.start.clamp(start, end);
+                VerboseWord {
+                    word: word.word.clone(),
+                    start: word_start,
+                    end: word.end.clamp(word_start, end),
+                    probability: word.probability,
+                }
+            })
+            .collect()
+    });
+    let compression_ratio = segment.dr.compression_ratio();
+    VerboseSegment {
+        id: segment.id,
+        seek: (start * FRAMES_PER_SECOND).round() as usize,
+        start,
+        end,
+        text: segment
+            .dr
+            .text_clean
+            .clone()
+            .unwrap_or_else(|| segment.dr.text.clone()),
+        tokens: segment.dr.tokens.clone(),
+        temperature: segment.dr.temperature,
+        avg_logprob: segment.dr.avg_logprob,
+        compression_ratio: (!compression_ratio.is_nan()).then_some(compression_ratio),
+        no_speech_prob: segment.dr.no_speech_prob,
+        words,
+    }
+}
+
+/// Verbose transcript of the speech segments of `output`, a transcription of `duration`
+/// seconds of audio. The times of the words are clamped to their segment.
+pub fn to_verbose_json(output: &TranscriptionOutput, duration: f64) -> VerboseTranscript {
+    let segments: Vec<VerboseSegment> = output
+        .segments
+        .iter()
+        .filter(|segment| is_speech(segment))
+        .map(verbose_segment)
+        .collect();
+    VerboseTranscript {
+        task: output.task,
+        language: output.language.clone(),
+        duration,
+        text: segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect(),
+        segments,
+    }
+}
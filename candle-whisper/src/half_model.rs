@@ -0,0 +1,289 @@
+//! Whisper model computing in the dtype of its weights. The candle model builds the sinusoidal
+//! positional embedding of its encoder and the causal mask of its decoder in `f32` whatever
+//! the dtype of its weights, so it only runs in `f32`, this one runs in `f16` and `bf16` too.
+//! It mirrors the candle model, the weights have the same names.
+
+use candle_core::{DType, Device, IndexOp, Module, Result, Tensor, D};
+use candle_nn::{
+    embedding, linear, linear_no_bias, Conv1d, Conv1dConfig, Embedding, LayerNorm, Linear,
+    VarBuilder,
+};
+use candle_transformers::models::whisper::Config;
+
+fn conv1d(
+    in_channels: usize,
+    out_channels: usize,
+    stride: usize,
+    vb: VarBuilder,
+) -> Result<Conv1d> {
+    let weight = vb.get((out_channels, in_channels, 3), "weight")?;
+    let bias = vb.get(out_channels, "bias")?;
+    let config = Conv1dConfig {
+        padding: 1,
+        stride,
+        dilation: 1,
+        groups: 1,
+    };
+    Ok(Conv1d::new(weight, Some(bias), config))
+}
+
+fn layer_norm(size: usize, vb: VarBuilder) -> Result<LayerNorm> {
+    let weight = vb.get(size, "weight")?;
+    let bias = vb.get(size, "bias")?;
+    Ok(LayerNorm::new(weight, bias, 1e-5))
+}
+
+fn sinusoids(length: usize, channels: usize, dtype: DType, device: &Device) -> Result<Tensor> {
+    let log_timescale_increment = 10000f32.ln() / (channels / 2 - 1) as f32;
+    let inv_timescales: Vec<_> = (0..channels / 2)
+        .map(|i| (i as f32 * (-log_timescale_increment)).exp())
+        .collect();
+    let inv_timescales = Tensor::new(inv_timescales.as_slice(), device)?.unsqueeze(0)?;
+    let arange = Tensor::arange(0, length as u32, device)?
+        .to_dtype(DType::F32)?
+        .unsqueeze(1)?;
+    let shape = (length, channels / 2);
+    let scaled_time = (arange.broadcast_as(shape)? * inv_timescales.broadcast_as(shape)?)?;
+    Tensor::cat(&[scaled_time.sin()?, scaled_time.cos()?], 1)?.to_dtype(dtype)
+}
+
+struct MultiHeadAttention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    out: Linear,
+    n_head: usize,
+    /// Keys and values of the audio features of the cross-attention.
+    kv_cache: Option<(Tensor, Tensor)>,
+}
+
+impl MultiHeadAttention {
+    fn load(n_state: usize, n_head: usize, vb: VarBuilder) -> Result<Self> {
+        Ok(Self {
+            query: linear(n_state, n_state, vb.pp("q_proj"))?,
+            key: linear_no_bias(n_state, n_state, vb.pp("k_proj"))?,
+            value: linear(n_state, n_state, vb.pp("v_proj"))?,
+            out: linear(n_state, n_state, vb.pp("out_proj"))?,
+            n_head,
+            kv_cache: None,
+        })
+    }
+
+    fn forward(
+        &mut self,
+        x: &Tensor,
+        xa: Option<&Tensor>,
+        mask: Option<&Tensor>,
+        flush_cache: bool,
+    ) -> Result<Tensor> {
+        let q = self.query.forward(x)?;
+        let (k, v) = match xa {
+            None => (self.key.forward(x)?, self.value.forward(x)?),
+            Some(xa) => match &self.kv_cache {
+                Some((k, v)) if !flush_cache => (k.clone(), v.clone()),
+                _ => {
+                    let kv = (self.key.forward(xa)?, self.value.forward(xa)?);
+                    self.kv_cache = Some(kv.clone());
+                    kv
+                }
+            },
+        };
+        let (_, n_ctx, n_state) = q.dims3()?;
+        let scale = ((n_state / self.n_head) as f64).powf(-0.25);
+        let q = (self.reshape_head(&q)? * scale)?;
+        let k = (self.reshape_head(&k)?.transpose(2, 3)? * scale)?;
+        let v = self.reshape_head(&v)?.contiguous()?;
+        let mut qk = q.matmul(&k)?;
+        if let Some(mask) = mask {
+            qk = qk.broadcast_add(&mask.i((0..n_ctx, 0..n_ctx))?)?
+        }
+        let w = candle_nn::ops::softmax_last_dim(&qk)?;
+        let wv = w.matmul(&v)?.transpose(1, 2)?.flatten_from(2)?;
+        self.out.forward(&wv)
+    }
+
+    fn reshape_head(&self, x: &Tensor) -> Result<Tensor> {
+        let (n_batch, n_ctx, n_state) = x.dims3()?;
+        let target_dims = &[n_batch, n_ctx, self.n_head, n_state / self.n_head];
+        x.reshape(target_dims)?.transpose(1, 2)
+    }
+}
+
+struct ResidualAttentionBlock {
+    attn: MultiHeadAttention,
+    attn_ln: LayerNorm,
+    cross_attn: Option<(MultiHeadAttention, LayerNorm)>,
+    mlp_linear1: Linear,
+    mlp_linear2: Linear,
+    mlp_ln: LayerNorm,
+}
+
+impl ResidualAttentionBlock {
+    fn load(n_state: usize, n_head: usize, cross_attn: bool, vb: VarBuilder) -> Result<Self> {
+        let cross_attn = if cross_attn {
+            Some((
+                MultiHeadAttention::load(n_state, n_head, vb.pp("encoder_attn"))?,
+                layer_norm(n_state, vb.pp("encoder_attn_layer_norm"))?,
+            ))
+        } else {
+            None
+        };
+        let n_mlp = n_state * 4;
+        Ok(Self {
+            attn: MultiHeadAttention::load(n_state, n_head, vb.pp("self_attn"))?,
+            attn_ln: layer_norm(n_state, vb.pp("self_attn_layer_norm"))?,
+            cross_attn,
+            mlp_linear1: linear(n_state, n_mlp, vb.pp("fc1"))?,
+            mlp_linear2: linear(n_mlp, n_state, vb.pp("fc2"))?,
+            mlp_ln: layer_norm(n_state, vb.pp("final_layer_norm"))?,
+        })
+    }
+
+    fn forward(
+        &mut self,
+        x: &Tensor,
+        xa: Option<&Tensor>,
+        mask: Option<&Tensor>,
+        flush_kv_cache: bool,
+    ) -> Result<Tensor> {
+        let attn = self
+            .attn
+            .forward(&self.attn_ln.forward(x)?, None, mask, flush_kv_cache)?;
+        let mut x = (x + attn)?;
+        if let Some((attn, ln)) = &mut self.cross_attn {
+            x = (&x + attn.forward(&ln.forward(&x)?, xa, None, flush_kv_cache)?)?;
+        }
+        let mlp = self.mlp_linear2.forward(
+            &self
+                .mlp_linear1
+                .forward(&self.mlp_ln.forward(&x)?)?
+                .gelu()?,
+        )?;
+        x + mlp
+    }
+
+    fn reset_kv_cache(&mut self) {
+        if let Some((attn, _)) = &mut self.cross_attn {
+            attn.kv_cache = None;
+        }
+    }
+}
+
+pub struct AudioEncoder {
+    conv1: Conv1d,
+    conv2: Conv1d,
+    positional_embedding: Tensor,
+    blocks: Vec<ResidualAttentionBlock>,
+    ln_post: LayerNorm,
+}
+
+impl AudioEncoder {
+    fn load(vb: VarBuilder, cfg: &Config) -> Result<Self> {
+        let n_state = cfg.d_model;
+        let n_head = cfg.encoder_attention_heads;
+        let blocks = (0..cfg.encoder_layers)
+            .map(|i| {
+                ResidualAttentionBlock::load(n_state, n_head, false, vb.pp(format!("layers.{i}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            conv1: conv1d(cfg.num_mel_bins, n_state, 1, vb.pp("conv1"))?,
+            conv2: conv1d(n_state, n_state, 2, vb.pp("conv2"))?,
+            positional_embedding: sinusoids(
+                cfg.max_source_positions,
+                n_state,
+                vb.dtype(),
+                vb.device(),
+            )?,
+            blocks,
+            ln_post: layer_norm(n_state, vb.pp("layer_norm"))?,
+        })
+    }
+
+    pub fn forward(&mut self, x: &Tensor, flush_kv_cache: bool) -> Result<Tensor> {
+        let x = self.conv1.forward(x)?.gelu()?;
+        let x = self.conv2.forward(&x)?.gelu()?.transpose(1, 2)?;
+        let (_, seq_len, _) = x.dims3()?;
+        let mut x = x.broadcast_add(&self.positional_embedding.narrow(0, 0, seq_len)?)?;
+        for block in self.blocks.iter_mut() {
+            x = block.forward(&x, None, None, flush_kv_cache)?
+        }
+        self.ln_post.forward(&x)
+    }
+}
+
+pub struct TextDecoder {
+    token_embedding: Embedding,
+    positional_embedding: Tensor,
+    blocks: Vec<ResidualAttentionBlock>,
+    ln: LayerNorm,
+    mask: Tensor,
+}
+
+impl TextDecoder {
+    fn load(vb: VarBuilder, cfg: &Config) -> Result<Self> {
+        let n_state = cfg.d_model;
+        let n_head = cfg.decoder_attention_heads;
+        let n_ctx = cfg.max_target_positions;
+        let blocks = (0..cfg.decoder_layers)
+            .map(|i| {
+                ResidualAttentionBlock::load(n_state, n_head, true, vb.pp(format!("layers.{i}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mask: Vec<_> = (0..n_ctx)
+            .flat_map(|i| (0..n_ctx).map(move |j| if j > i { f32::NEG_INFINITY } else { 0f32 }))
+            .collect();
+        let mask = Tensor::from_vec(mask, (n_ctx, n_ctx), vb.device())?.to_dtype(vb.dtype())?;
+        Ok(Self {
+            token_embedding: embedding(cfg.vocab_size, n_state, vb.pp("embed_tokens"))?,
+            positional_embedding: vb.get((n_ctx, n_state), "embed_positions.weight")?,
+            blocks,
+            ln: layer_norm(n_state, vb.pp("layer_norm"))?,
+            mask,
+        })
+    }
+
+    pub fn forward(&mut self, x: &Tensor, xa: &Tensor, flush_kv_cache: bool) -> Result<Tensor> {
+        let token_embedding = self.token_embedding.forward(x)?;
+        let positional_embedding = self.positional_embedding.narrow(0, 0, x.dim(D::Minus1)?)?;
+        let mut x = token_embedding.broadcast_add(&positional_embedding)?;
+        for block in self.blocks.iter_mut() {
+            x = block.forward(&x, Some(xa), Some(&self.mask), flush_kv_cache)?;
+        }
+        self.ln.forward(&x)
+    }
+
+    pub fn final_linear(&self, x: &Tensor) -> Result<Tensor> {
+        let w = self
+            .token_embedding
+            .embeddings()
+            .broadcast_left(x.dim(0)?)?;
+        x.matmul(&w.t()?)
+    }
+
+    pub fn reset_kv_cache(&mut self) {
+        self.blocks
+            .iter_mut()
+            .for_each(ResidualAttentionBlock::reset_kv_cache);
+    }
+}
+
+pub struct Whisper {
+    pub encoder: AudioEncoder,
+    pub decoder: TextDecoder,
+    pub config: Config,
+}
+
+impl Whisper {
+    pub fn load(vb: &VarBuilder, config: Config) -> Result<Self> {
+        Ok(Self {
+            encoder: AudioEncoder::load(vb.pp("model.encoder"), &config)?,
+            decoder: TextDecoder::load(vb.pp("model.decoder"), &config)?,
+            config,
+        })
+    }
+
+    pub fn reset_kv_cache(&mut self) {
+        self.decoder.reset_kv_cache();
+    }
+}
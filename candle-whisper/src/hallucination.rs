@@ -0,0 +1,144 @@
+use crate::logic::Segment;
+
+use serde::{Deserialize, Serialize};
+
+/// Outputs whisper is known to produce on silence or music.
+pub const DEFAULT_BLOCKLIST: [&str; 6] = [
+    "thanks for watching",
+    "thank you for watching",
+    "please subscribe",
+    "subtitles by the amara.org community",
+    "you",
+    "♪",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HallucinationOptions {
+    /// Normalized Levenshtein similarity with the previous segment above which a segment is
+    /// flagged as a repetition.
+    pub similarity_threshold: f64,
+    /// Size in words of the n-grams used by the repetition check.
+    pub ngram_size: usize,
+    /// Fraction of the words of a segment covered by a single repeated n-gram above which the
+    /// segment is flagged.
+    pub max_ngram_coverage: f64,
+    /// Phrases that flag a segment when its whole normalized text matches one of them.
+    pub blocklist: Vec<String>,
+    /// Remove flagged segments instead of only scoring them.
+    pub drop: bool,
+}
+
+impl Default for HallucinationOptions {
+    fn default() -> Self {
+        Self {
+            similarity_threshold: 0.95,
+            ngram_size: 3,
+            max_ngram_coverage: 0.8,
+            blocklist: DEFAULT_BLOCKLIST.iter().map(|s| s.to_string()).collect(),
+            drop: false,
+        }
+    }
+}
+
+fn normalize(text: &str) -> String {
+    let text: String = text
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_punctuation() || matches!(c, '，' | '。' | '！' | '？' | '、') {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// Normalized Levenshtein similarity in `[0, 1]`, 1 meaning identical texts.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = normalize(a).chars().collect();
+    let b: Vec<char> = normalize(b).chars().collect();
+    let max_len = usize::max(a.len(), b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(&a, &b) as f64 / max_len as f64
+}
+
+/// Fraction of the words of `text` covered by its most frequent repeated n-gram.
+pub fn ngram_coverage(text: &str, n: usize) -> f64 {
+    let text = normalize(text);
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if n == 0 || words.len() < 2 * n {
+        return 0.0;
+    }
+    let mut counts = std::collections::HashMap::new();
+    for ngram in words.windows(n) {
+        *counts.entry(ngram).or_insert(0usize) += 1;
+    }
+    let max_count = counts.values().copied().max().unwrap_or(0);
+    if max_count < 2 {
+        return 0.0;
+    }
+    f64::min(1.0, (max_count * n) as f64 / words.len() as f64)
+}
+
+fn is_blocklisted(text: &str, blocklist: &[String]) -> bool {
+    let text = normalize(text);
+    !text.is_empty() && blocklist.iter().any(|phrase| normalize(phrase) == text)
+}
+
+/// Hallucination score in `[0, 1]` of `text` following the text `previous`, and whether one of
+/// the checks flags it.
+pub fn score(previous: Option<&str>, text: &str, opts: &HallucinationOptions) -> (f64, bool) {
+    let repetition = previous.map_or(0.0, |previous| similarity(previous, text));
+    let coverage = ngram_coverage(text, opts.ngram_size);
+    let blocklisted = is_blocklisted(text, &opts.blocklist);
+    let flagged = repetition >= opts.similarity_threshold
+        || coverage > opts.max_ngram_coverage
+        || blocklisted;
+    let score = if blocklisted {
+        1.0
+    } else {
+        f64::max(repetition, coverage)
+    };
+    (score, flagged)
+}
+
+/// Scores every segment with a `hallucination_score` in `[0, 1]` and, when `opts.drop` is set,
+/// removes the segments flagged by one of the checks.
+pub fn filter_hallucinations(segments: Vec<Segment>, opts: &HallucinationOptions) -> Vec<Segment> {
+    let mut output = Vec::with_capacity(segments.len());
+    let mut previous_text: Option<String> = None;
+    for mut segment in segments.into_iter() {
+        if segment.no_speech || segment.error.is_some() {
+            output.push(segment);
+            continue;
+        }
+        let text = segment.dr.text.clone();
+        let (score, flagged) = score(previous_text.as_deref(), &text, opts);
+        segment.hallucination_score = score;
+        previous_text = Some(text);
+        if flagged && opts.drop {
+            continue;
+        }
+        output.push(segment)
+    }
+    output
+}
@@ -0,0 +1,569 @@
+//! Inverse text normalization: spoken forms of numbers, amounts, percentages and times written
+//! the way they are read, e.g. `twenty three dollars and fifty cents` becomes `$23.50`.
+//!
+//! The rules only rewrite the patterns they fully recognize, any other word is left untouched.
+
+use crate::alignment::WordTiming;
+
+use std::ops::Range;
+
+/// Run of words forming an entity, with its written form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entity {
+    /// Indices of the words of the entity.
+    pub words: Range<usize>,
+    /// Written form, including the punctuation before the first word and after the last one.
+    pub text: String,
+}
+
+/// Rules of a language.
+pub trait InverseNormalizer {
+    /// Entities of `words`, sorted and not overlapping. The words are separated by whitespace
+    /// in the text and keep their punctuation.
+    fn entities(&self, words: &[&str]) -> Vec<Entity>;
+}
+
+/// Rules of `language`, `None` when the language is not supported.
+pub fn normalizer(language: &str) -> Option<&'static dyn InverseNormalizer> {
+    match language {
+        "en" => Some(&English),
+        _ => None,
+    }
+}
+
+/// `text` with its entities replaced by their written form, the whitespace between the other
+/// words is kept as is.
+pub fn normalize_text(normalizer: &dyn InverseNormalizer, text: &str) -> String {
+    let mut spans = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                spans.push(s..i);
+                start = None
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push(s..text.len());
+    }
+    let words: Vec<&str> = spans.iter().map(|span| &text[span.clone()]).collect();
+    let mut output = String::with_capacity(text.len());
+    let mut copied = 0;
+    for entity in normalizer.entities(&words) {
+        let start = spans[entity.words.start].start;
+        output.push_str(&text[copied..start]);
+        output.push_str(&entity.text);
+        copied = spans[entity.words.end - 1].end;
+    }
+    output.push_str(&text[copied..]);
+    output
+}
+
+/// Words with the words of each entity merged into a single word spanning their times.
+pub fn merge_words(normalizer: &dyn InverseNormalizer, words: &[WordTiming]) -> Vec<WordTiming> {
+    let texts: Vec<&str> = words.iter().map(|w| w.word.trim()).collect();
+    let mut merged = Vec::with_capacity(words.len());
+    let mut next = 0;
+    for entity in normalizer.entities(&texts) {
+        merged.extend_from_slice(&words[next..entity.words.start]);
+        let first = &words[entity.words.start];
+        let last = &words[entity.words.end - 1];
+        let leading = &first.word[..first.word.len() - first.word.trim_start().len()];
+        let probs: Option<Vec<f32>> = words[entity.words.clone()]
+            .iter()
+            .map(|word| word.probability)
+            .collect();
+        merged.push(WordTiming {
+            word: format!("{leading}{}", entity.text),
+            start: first.start,
+            end: last.end,
+            timing_source: first.timing_source,
+            probability: probs.map(|probs| probs.iter().sum::<f32>() / probs.len() as f32),
+        });
+        next = entity.words.end;
+    }
+    merged.extend_from_slice(&words[next..]);
+    merged
+}
+
+/// Word split into its punctuation and its lowercase core.
+#[derive(Debug)]
+struct Word<'a> {
+    prefix: &'a str,
+    core: String,
+    suffix: &'a str,
+}
+
+impl<'a> Word<'a> {
+    fn new(word: &'a str) -> Self {
+        let trimmed = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+        let prefix = &word[..word.len() - trimmed.len()];
+        let core = trimmed.trim_end_matches(|c: char| !c.is_alphanumeric());
+        Self {
+            prefix,
+            core: core.to_lowercase().replace('’', "'"),
+            suffix: &trimmed[core.len()..],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// `zero` to `nine`.
+    Unit,
+    /// `ten` to `nineteen`.
+    Teen,
+    /// `twenty` to `ninety`, and their compounds with a unit such as `twenty-three`.
+    Tens,
+    Hundred,
+    Scale,
+}
+
+const UNITS: [(&str, &str); 20] = [
+    ("zero", "zeroth"),
+    ("one", "first"),
+    ("two", "second"),
+    ("three", "third"),
+    ("four", "fourth"),
+    ("five", "fifth"),
+    ("six", "sixth"),
+    ("seven", "seventh"),
+    ("eight", "eighth"),
+    ("nine", "ninth"),
+    ("ten", "tenth"),
+    ("eleven", "eleventh"),
+    ("twelve", "twelfth"),
+    ("thirteen", "thirteenth"),
+    ("fourteen", "fourteenth"),
+    ("fifteen", "fifteenth"),
+    ("sixteen", "sixteenth"),
+    ("seventeen", "seventeenth"),
+    ("eighteen", "eighteenth"),
+    ("nineteen", "nineteenth"),
+];
+
+const TENS: [(&str, &str); 8] = [
+    ("twenty", "twentieth"),
+    ("thirty", "thirtieth"),
+    ("forty", "fortieth"),
+    ("fifty", "fiftieth"),
+    ("sixty", "sixtieth"),
+    ("seventy", "seventieth"),
+    ("eighty", "eightieth"),
+    ("ninety", "ninetieth"),
+];
+
+const SCALES: [(&str, &str, u64); 4] = [
+    ("hundred", "hundredth", 100),
+    ("thousand", "thousandth", 1_000),
+    ("million", "millionth", 1_000_000),
+    ("billion", "billionth", 1_000_000_000),
+];
+
+/// Number word, with its value and whether it is an ordinal.
+fn number_word(core: &str) -> Option<(Kind, u64, bool)> {
+    if let Some((tens, unit)) = core.split_once('-') {
+        let (Kind::Tens, tens, false) = number_word(tens)? else {
+            return None;
+        };
+        return match number_word(unit)? {
+            (Kind::Unit, unit, ordinal) if unit > 0 => Some((Kind::Tens, tens + unit, ordinal)),
+            _ => None,
+        };
+    }
+    for (value, (cardinal, ordinal)) in UNITS.iter().enumerate() {
+        let kind = if value < 10 { Kind::Unit } else { Kind::Teen };
+        if core == *cardinal || core == *ordinal {
+            return Some((kind, value as u64, core == *ordinal));
+        }
+    }
+    for (i, (cardinal, ordinal)) in TENS.iter().enumerate() {
+        if core == *cardinal || core == *ordinal {
+            return Some((Kind::Tens, 20 + 10 * i as u64, core == *ordinal));
+        }
+    }
+    for (cardinal, ordinal, value) in SCALES {
+        if core == cardinal || core == ordinal {
+            let kind = if value == 100 {
+                Kind::Hundred
+            } else {
+                Kind::Scale
+            };
+            return Some((kind, value, core == ordinal));
+        }
+    }
+    None
+}
+
+/// Number read from consecutive words.
+#[derive(Debug, Clone)]
+struct Number {
+    value: u64,
+    /// Digits after the decimal point.
+    decimals: String,
+    ordinal: bool,
+    /// Number of words.
+    len: usize,
+    /// Whether the number is made of units, teens and tens only.
+    simple: bool,
+}
+
+impl Number {
+    /// Digits of the number, grouped by thousands from 10,000.
+    fn digits(&self) -> String {
+        let digits = self.value.to_string();
+        let mut grouped = if self.value >= 10_000 {
+            let mut grouped = String::new();
+            for (i, c) in digits.chars().enumerate() {
+                if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                    grouped.push(',');
+                }
+                grouped.push(c);
+            }
+            grouped
+        } else {
+            digits
+        };
+        if !self.decimals.is_empty() {
+            grouped.push('.');
+            grouped.push_str(&self.decimals);
+        }
+        grouped
+    }
+
+    fn ordinal_suffix(&self) -> &'static str {
+        match (self.value % 10, self.value % 100) {
+            (_, 11..=13) => "th",
+            (1, _) => "st",
+            (2, _) => "nd",
+            (3, _) => "rd",
+            _ => "th",
+        }
+    }
+}
+
+/// English rules for cardinals, ordinals, dollar, euro and cent amounts, percentages and times.
+///
+/// Cardinals and ordinals below ten are left as words unless they are part of another entity,
+/// e.g. `one of the two` stays as is while `five percent` becomes `5%`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct English;
+
+impl InverseNormalizer for English {
+    fn entities(&self, words: &[&str]) -> Vec<Entity> {
+        let words: Vec<Word> = words.iter().map(|w| Word::new(w)).collect();
+        let mut entities = vec![];
+        let mut i = 0;
+        while i < words.len() {
+            let found = [time, money, percent, number]
+                .iter()
+                .find_map(|rule| rule(&words, i));
+            match found {
+                Some((len, text)) => {
+                    let mut suffix = words[i + len - 1].suffix;
+                    // The dot of a `p.m.` ending the entity also ends the sentence only at the
+                    // end of the text.
+                    if words[i + len - 1].core.contains('.') && i + len < words.len() {
+                        suffix = suffix.strip_prefix('.').unwrap_or(suffix);
+                    }
+                    entities.push(Entity {
+                        words: i..i + len,
+                        text: format!("{}{text}{suffix}", words[i].prefix),
+                    });
+                    i += len;
+                }
+                // A run of numbers no rule recognized is left untouched as a whole, so
+                // that no part of `twelve thirty` is rewritten.
+                None => {
+                    i += 1;
+                    while i < words.len()
+                        && joined(&words, i - 1)
+                        && number_word(&words[i - 1].core).is_some()
+                        && number_word(&words[i].core).is_some()
+                    {
+                        i += 1;
+                    }
+                }
+            }
+        }
+        entities
+    }
+}
+
+/// Whether the words `i` and `i + 1` can belong to the same entity, i.e. no punctuation
+/// separates them.
+fn joined(words: &[Word], i: usize) -> bool {
+    i + 1 < words.len() && words[i].suffix.is_empty() && words[i + 1].prefix.is_empty()
+}
+
+/// Core of the word following `i` in the same entity.
+fn next_core<'w>(words: &'w [Word], i: usize) -> Option<&'w str> {
+    joined(words, i).then(|| words[i + 1].core.as_str())
+}
+
+/// Number written in digits, possibly with thousands separators and decimals.
+fn digits(core: &str) -> Option<Number> {
+    let (integer, decimals) = core.split_once('.').unwrap_or((core, ""));
+    let integer = integer.replace(',', "");
+    let valid = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if integer.is_empty() || !valid(&integer) || !valid(decimals) {
+        return None;
+    }
+    Some(Number {
+        value: integer.parse().ok()?,
+        decimals: decimals.to_string(),
+        ordinal: false,
+        len: 1,
+        simple: false,
+    })
+}
+
+/// Spoken cardinal or ordinal starting at word `i`, e.g. `two hundred and five`.
+fn spoken(words: &[Word], i: usize) -> Option<Number> {
+    let (mut total, mut current, mut last_scale) = (0u64, 0u64, u64::MAX);
+    let mut previous: Option<Kind> = None;
+    let mut ordinal = false;
+    let mut simple = true;
+    let mut j = i;
+    while j < words.len() && (j == i || joined(words, j - 1)) {
+        let core = words[j].core.as_str();
+        let scale_follows = next_core(words, j)
+            .and_then(number_word)
+            .is_some_and(|(kind, _, _)| matches!(kind, Kind::Hundred | Kind::Scale));
+        if core == "a" && j == i && scale_follows {
+            current = 1;
+            previous = Some(Kind::Unit);
+            j += 1;
+            continue;
+        }
+        if core == "and" && matches!(previous, Some(Kind::Hundred | Kind::Scale)) {
+            let number_follows = next_core(words, j)
+                .and_then(number_word)
+                .is_some_and(|(kind, _, _)| matches!(kind, Kind::Unit | Kind::Teen | Kind::Tens));
+            if number_follows {
+                j += 1;
+                continue;
+            }
+            break;
+        }
+        let Some((kind, value, is_ordinal)) = number_word(core) else {
+            break;
+        };
+        let after_scale = matches!(previous, None | Some(Kind::Hundred | Kind::Scale));
+        let accepted = match kind {
+            Kind::Unit if value == 0 => previous.is_none(),
+            Kind::Unit => after_scale || previous == Some(Kind::Tens) && current % 10 == 0,
+            Kind::Teen | Kind::Tens => after_scale,
+            Kind::Hundred => {
+                matches!(previous, Some(Kind::Unit | Kind::Teen | Kind::Tens))
+                    && (1..100).contains(&current)
+            }
+            Kind::Scale => current > 0 && value < last_scale,
+        };
+        if !accepted {
+            break;
+        }
+        match kind {
+            Kind::Hundred => current *= value,
+            Kind::Scale => {
+                total += current * value;
+                current = 0;
+                last_scale = value;
+            }
+            _ => current += value,
+        }
+        if matches!(kind, Kind::Hundred | Kind::Scale) {
+            simple = false;
+        }
+        previous = Some(kind);
+        j += 1;
+        if is_ordinal {
+            ordinal = true;
+            break;
+        }
+    }
+    previous?;
+    let mut number = Number {
+        value: total + current,
+        decimals: String::new(),
+        ordinal,
+        len: j - i,
+        simple,
+    };
+    // Decimals are read digit by digit: `three point one four`.
+    if !ordinal && next_core(words, j - 1) == Some("point") {
+        let mut k = j + 1;
+        let mut decimals = String::new();
+        while k < words.len() && joined(words, k - 1) {
+            match number_word(&words[k].core) {
+                Some((Kind::Unit, digit, false)) => decimals.push_str(&digit.to_string()),
+                _ if words[k].core == "oh" => decimals.push('0'),
+                _ => break,
+            }
+            k += 1;
+        }
+        if !decimals.is_empty() {
+            number.decimals = decimals;
+            number.len = k - i;
+            number.simple = false;
+        }
+    }
+    Some(number)
+}
+
+/// Cardinal starting at word `i`, spoken or in digits.
+fn cardinal(words: &[Word], i: usize) -> Option<Number> {
+    digits(&words[i].core)
+        .or_else(|| spoken(words, i))
+        .filter(|n| !n.ordinal)
+}
+
+/// Years read as two pairs of digits, from `fifteen oh one` to `twenty ninety nine`.
+fn year(words: &[Word], i: usize) -> Option<(usize, String)> {
+    let century = spoken(words, i).filter(|n| n.simple && !n.ordinal && n.decimals.is_empty())?;
+    if !(15..=20).contains(&century.value) {
+        return None;
+    }
+    let j = i + century.len;
+    let rest = next_core(words, j - 1)?;
+    let (len, value) = if rest == "oh" {
+        let unit = next_core(words, j).and_then(number_word);
+        match unit {
+            Some((Kind::Unit, value, _)) if value > 0 => (2, value),
+            _ => return None,
+        }
+    } else {
+        let n = spoken(words, j).filter(|n| n.simple && !n.ordinal && n.decimals.is_empty())?;
+        if n.value < 10 {
+            return None;
+        }
+        (n.len, n.value)
+    };
+    Some((century.len + len, (century.value * 100 + value).to_string()))
+}
+
+/// Cardinal or ordinal, left alone below ten or when followed by another number, e.g.
+/// `twelve thirty`.
+fn number(words: &[Word], i: usize) -> Option<(usize, String)> {
+    if let Some(year) = year(words, i) {
+        return Some(year);
+    }
+    let n = spoken(words, i)?;
+    if n.value < 10 && n.decimals.is_empty() && n.len == 1 {
+        return None;
+    }
+    if next_core(words, i + n.len - 1).is_some_and(|core| number_word(core).is_some()) {
+        return None;
+    }
+    if n.ordinal {
+        Some((n.len, format!("{}{}", n.digits(), n.ordinal_suffix())))
+    } else {
+        Some((n.len, n.digits()))
+    }
+}
+
+fn percent(words: &[Word], i: usize) -> Option<(usize, String)> {
+    let n = cardinal(words, i)?;
+    let j = i + n.len - 1;
+    let len = match next_core(words, j)? {
+        "percent" => 1,
+        "per" if next_core(words, j + 1) == Some("cent") => 2,
+        _ => return None,
+    };
+    Some((n.len + len, format!("{}%", n.digits())))
+}
+
+/// Amount in dollars or euros with optional cents, or in cents alone.
+fn money(words: &[Word], i: usize) -> Option<(usize, String)> {
+    let n = cardinal(words, i)?;
+    let mut j = i + n.len - 1;
+    let symbol = match next_core(words, j)? {
+        "dollar" | "dollars" => "$",
+        "euro" | "euros" => "€",
+        "cent" | "cents" if n.value < 100 && n.decimals.is_empty() => {
+            return Some((n.len + 1, format!("{}¢", n.value)));
+        }
+        _ => return None,
+    };
+    j += 1;
+    let mut amount = format!("{symbol}{}", n.digits());
+    if n.decimals.is_empty() {
+        let cents_start = match next_core(words, j) {
+            Some("and") => j + 2,
+            _ => j + 1,
+        };
+        let cents = (cents_start < words.len() && joined(words, cents_start - 1))
+            .then(|| cardinal(words, cents_start))
+            .flatten()
+            .filter(|c| c.value < 100 && c.decimals.is_empty());
+        if let Some(cents) = cents {
+            let end = cents_start + cents.len - 1;
+            if matches!(next_core(words, end), Some("cent" | "cents")) {
+                amount.push_str(&format!(".{:02}", cents.value));
+                j = end + 1;
+            }
+        }
+    }
+    Some((j - i + 1, amount))
+}
+
+/// `am` or `pm`, possibly written `a.m.` or in two words, with its number of words.
+fn meridiem(words: &[Word], i: usize) -> Option<(usize, &'static str)> {
+    let core = words.get(i)?.core.replace('.', "");
+    match core.as_str() {
+        "am" => Some((1, "AM")),
+        "pm" => Some((1, "PM")),
+        "a" | "p" if next_core(words, i) == Some("m") => {
+            Some((2, if core == "a" { "AM" } else { "PM" }))
+        }
+        _ => None,
+    }
+}
+
+/// Time of a 12-hour clock: `three pm`, `three thirty pm`, `seven oh five am` or
+/// `ten o'clock`. Hours followed by minutes need `am` or `pm`, `three thirty` alone could be
+/// two numbers.
+fn time(words: &[Word], i: usize) -> Option<(usize, String)> {
+    let (kind, hour, false) = number_word(&words[i].core)? else {
+        return None;
+    };
+    if !matches!(kind, Kind::Unit | Kind::Teen) || !(1..=12).contains(&hour) {
+        return None;
+    }
+    if !joined(words, i) {
+        return None;
+    }
+    let j = i + 1;
+    if words[j].core == "o'clock" {
+        return match joined(words, j).then(|| meridiem(words, j + 1)).flatten() {
+            Some((len, meridiem)) => Some((2 + len, format!("{hour}:00 {meridiem}"))),
+            None => Some((2, format!("{hour}:00"))),
+        };
+    }
+    if let Some((len, meridiem)) = meridiem(words, j) {
+        return Some((1 + len, format!("{hour} {meridiem}")));
+    }
+    let (minutes_len, minutes) = if words[j].core == "oh" {
+        match next_core(words, j).and_then(number_word) {
+            Some((Kind::Unit, value, false)) if value > 0 => (2, value),
+            _ => return None,
+        }
+    } else {
+        let n = spoken(words, j).filter(|n| n.simple && !n.ordinal && n.decimals.is_empty())?;
+        if !(10..60).contains(&n.value) {
+            return None;
+        }
+        (n.len, n.value)
+    };
+    let end = j + minutes_len - 1;
+    let (len, meridiem) = joined(words, end)
+        .then(|| meridiem(words, end + 1))
+        .flatten()?;
+    Some((
+        1 + minutes_len + len,
+        format!("{hour}:{minutes:02} {meridiem}"),
+    ))
+}
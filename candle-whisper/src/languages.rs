@@ -1,101 +1,188 @@
-pub const LANGUAGES: [(&str, &str); 99] = [
-    ("en", "english"),
-    ("zh", "chinese"),
-    ("de", "german"),
-    ("es", "spanish"),
-    ("ru", "russian"),
-    ("ko", "korean"),
-    ("fr", "french"),
-    ("ja", "japanese"),
-    ("pt", "portuguese"),
-    ("tr", "turkish"),
-    ("pl", "polish"),
-    ("ca", "catalan"),
-    ("nl", "dutch"),
-    ("ar", "arabic"),
-    ("sv", "swedish"),
-    ("it", "italian"),
-    ("id", "indonesian"),
-    ("hi", "hindi"),
-    ("fi", "finnish"),
-    ("vi", "vietnamese"),
-    ("he", "hebrew"),
-    ("uk", "ukrainian"),
-    ("el", "greek"),
-    ("ms", "malay"),
-    ("cs", "czech"),
-    ("ro", "romanian"),
-    ("da", "danish"),
-    ("hu", "hungarian"),
-    ("ta", "tamil"),
-    ("no", "norwegian"),
-    ("th", "thai"),
-    ("ur", "urdu"),
-    ("hr", "croatian"),
-    ("bg", "bulgarian"),
-    ("lt", "lithuanian"),
-    ("la", "latin"),
-    ("mi", "maori"),
-    ("ml", "malayalam"),
-    ("cy", "welsh"),
-    ("sk", "slovak"),
-    ("te", "telugu"),
-    ("fa", "persian"),
-    ("lv", "latvian"),
-    ("bn", "bengali"),
-    ("sr", "serbian"),
-    ("az", "azerbaijani"),
-    ("sl", "slovenian"),
-    ("kn", "kannada"),
-    ("et", "estonian"),
-    ("mk", "macedonian"),
-    ("br", "breton"),
-    ("eu", "basque"),
-    ("is", "icelandic"),
-    ("hy", "armenian"),
-    ("ne", "nepali"),
-    ("mn", "mongolian"),
-    ("bs", "bosnian"),
-    ("kk", "kazakh"),
-    ("sq", "albanian"),
-    ("sw", "swahili"),
-    ("gl", "galician"),
-    ("mr", "marathi"),
-    ("pa", "punjabi"),
-    ("si", "sinhala"),
-    ("km", "khmer"),
-    ("sn", "shona"),
-    ("yo", "yoruba"),
-    ("so", "somali"),
-    ("af", "afrikaans"),
-    ("oc", "occitan"),
-    ("ka", "georgian"),
-    ("be", "belarusian"),
-    ("tg", "tajik"),
-    ("sd", "sindhi"),
-    ("gu", "gujarati"),
-    ("am", "amharic"),
-    ("yi", "yiddish"),
-    ("lo", "lao"),
-    ("uz", "uzbek"),
-    ("fo", "faroese"),
-    ("ht", "haitian creole"),
-    ("ps", "pashto"),
-    ("tk", "turkmen"),
-    ("nn", "nynorsk"),
-    ("mt", "maltese"),
-    ("sa", "sanskrit"),
-    ("lb", "luxembourgish"),
-    ("my", "myanmar"),
-    ("bo", "tibetan"),
-    ("tl", "tagalog"),
-    ("mg", "malagasy"),
-    ("as", "assamese"),
-    ("tt", "tatar"),
-    ("haw", "hawaiian"),
-    ("ln", "lingala"),
-    ("ha", "hausa"),
-    ("ba", "bashkir"),
-    ("jw", "javanese"),
-    ("su", "sundanese"),
+//! Languages of the multilingual whisper models, in the order of their tokens.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LanguageInfo {
+    /// Code of the `<|code|>` token of the language.
+    pub code: &'static str,
+    pub english_name: &'static str,
+    /// Name of the language in the language itself.
+    pub native_name: &'static str,
+}
+
+const fn language(
+    code: &'static str,
+    english_name: &'static str,
+    native_name: &'static str,
+) -> LanguageInfo {
+    LanguageInfo {
+        code,
+        english_name,
+        native_name,
+    }
+}
+
+pub(crate) const LANGUAGES: [LanguageInfo; 99] = [
+    language("en", "English", "English"),
+    language("zh", "Chinese", "中文"),
+    language("de", "German", "Deutsch"),
+    language("es", "Spanish", "Español"),
+    language("ru", "Russian", "Русский"),
+    language("ko", "Korean", "한국어"),
+    language("fr", "French", "Français"),
+    language("ja", "Japanese", "日本語"),
+    language("pt", "Portuguese", "Português"),
+    language("tr", "Turkish", "Türkçe"),
+    language("pl", "Polish", "Polski"),
+    language("ca", "Catalan", "Català"),
+    language("nl", "Dutch", "Nederlands"),
+    language("ar", "Arabic", "العربية"),
+    language("sv", "Swedish", "Svenska"),
+    language("it", "Italian", "Italiano"),
+    language("id", "Indonesian", "Bahasa Indonesia"),
+    language("hi", "Hindi", "हिन्दी"),
+    language("fi", "Finnish", "Suomi"),
+    language("vi", "Vietnamese", "Tiếng Việt"),
+    language("he", "Hebrew", "עברית"),
+    language("uk", "Ukrainian", "Українська"),
+    language("el", "Greek", "Ελληνικά"),
+    language("ms", "Malay", "Bahasa Melayu"),
+    language("cs", "Czech", "Čeština"),
+    language("ro", "Romanian", "Română"),
+    language("da", "Danish", "Dansk"),
+    language("hu", "Hungarian", "Magyar"),
+    language("ta", "Tamil", "தமிழ்"),
+    language("no", "Norwegian", "Norsk"),
+    language("th", "Thai", "ไทย"),
+    language("ur", "Urdu", "اردو"),
+    language("hr", "Croatian", "Hrvatski"),
+    language("bg", "Bulgarian", "Български"),
+    language("lt", "Lithuanian", "Lietuvių"),
+    language("la", "Latin", "Latina"),
+    language("mi", "Maori", "Māori"),
+    language("ml", "Malayalam", "മലയാളം"),
+    language("cy", "Welsh", "Cymraeg"),
+    language("sk", "Slovak", "Slovenčina"),
+    language("te", "Telugu", "తెలుగు"),
+    language("fa", "Persian", "فارسی"),
+    language("lv", "Latvian", "Latviešu"),
+    language("bn", "Bengali", "বাংলা"),
+    language("sr", "Serbian", "Српски"),
+    language("az", "Azerbaijani", "Azərbaycanca"),
+    language("sl", "Slovenian", "Slovenščina"),
+    language("kn", "Kannada", "ಕನ್ನಡ"),
+    language("et", "Estonian", "Eesti"),
+    language("mk", "Macedonian", "Македонски"),
+    language("br", "Breton", "Brezhoneg"),
+    language("eu", "Basque", "Euskara"),
+    language("is", "Icelandic", "Íslenska"),
+    language("hy", "Armenian", "Հայերեն"),
+    language("ne", "Nepali", "नेपाली"),
+    language("mn", "Mongolian", "Монгол"),
+    language("bs", "Bosnian", "Bosanski"),
+    language("kk", "Kazakh", "Қазақ тілі"),
+    language("sq", "Albanian", "Shqip"),
+    language("sw", "Swahili", "Kiswahili"),
+    language("gl", "Galician", "Galego"),
+    language("mr", "Marathi", "मराठी"),
+    language("pa", "Punjabi", "ਪੰਜਾਬੀ"),
+    language("si", "Sinhala", "සිංහල"),
+    language("km", "Khmer", "ខ្មែរ"),
+    language("sn", "Shona", "chiShona"),
+    language("yo", "Yoruba", "Yorùbá"),
+    language("so", "Somali", "Soomaali"),
+    language("af", "Afrikaans", "Afrikaans"),
+    language("oc", "Occitan", "Occitan"),
+    language("ka", "Georgian", "ქართული"),
+    language("be", "Belarusian", "Беларуская"),
+    language("tg", "Tajik", "Тоҷикӣ"),
+    language("sd", "Sindhi", "سنڌي"),
+    language("gu", "Gujarati", "ગુજરાતી"),
+    language("am", "Amharic", "አማርኛ"),
+    language("yi", "Yiddish", "ייִדיש"),
+    language("lo", "Lao", "ລາວ"),
+    language("uz", "Uzbek", "Oʻzbekcha"),
+    language("fo", "Faroese", "Føroyskt"),
+    language("ht", "Haitian Creole", "Kreyòl ayisyen"),
+    language("ps", "Pashto", "پښتو"),
+    language("tk", "Turkmen", "Türkmençe"),
+    language("nn", "Nynorsk", "Nynorsk"),
+    language("mt", "Maltese", "Malti"),
+    language("sa", "Sanskrit", "संस्कृतम्"),
+    language("lb", "Luxembourgish", "Lëtzebuergesch"),
+    language("my", "Myanmar", "မြန်မာ"),
+    language("bo", "Tibetan", "བོད་སྐད"),
+    language("tl", "Tagalog", "Tagalog"),
+    language("mg", "Malagasy", "Malagasy"),
+    language("as", "Assamese", "অসমীয়া"),
+    language("tt", "Tatar", "Татар"),
+    language("haw", "Hawaiian", "ʻŌlelo Hawaiʻi"),
+    language("ln", "Lingala", "Lingála"),
+    language("ha", "Hausa", "Hausa"),
+    language("ba", "Bashkir", "Башҡорт"),
+    language("jw", "Javanese", "Basa Jawa"),
+    language("su", "Sundanese", "Basa Sunda"),
 ];
+
+/// Other names and codes of the languages, as accepted by openai/whisper and the BCP 47 tags.
+const ALIASES: &[(&str, &str)] = &[
+    ("mandarin", "zh"),
+    ("burmese", "my"),
+    ("valencian", "ca"),
+    ("flemish", "nl"),
+    ("haitian", "ht"),
+    ("letzeburgesch", "lb"),
+    ("pushto", "ps"),
+    ("panjabi", "pa"),
+    ("moldavian", "ro"),
+    ("moldovan", "ro"),
+    ("sinhalese", "si"),
+    ("castilian", "es"),
+    ("farsi", "fa"),
+    ("filipino", "tl"),
+    ("bokmål", "no"),
+    ("nb", "no"),
+    ("iw", "he"),
+    ("in", "id"),
+    ("ji", "yi"),
+    ("jv", "jw"),
+    ("fil", "tl"),
+];
+
+/// Languages supported by the multilingual models, in the order of their tokens.
+pub fn supported_languages() -> &'static [LanguageInfo] {
+    &LANGUAGES
+}
+
+/// Whether `code` is the code of a supported language, aliases are not accepted.
+pub fn is_supported(code: &str) -> bool {
+    LANGUAGES.iter().any(|l| l.code == code)
+}
+
+/// Code of the language named by `input`, matching case-insensitively the codes, the English
+/// and native names and the common aliases. Region subtags are ignored, e.g. `zh-CN`,
+/// `Mandarin` and `Chinese` all give `zh`.
+pub fn canonicalize(input: &str) -> Option<&'static str> {
+    let input = input.trim().to_lowercase();
+    let find = |name: &str| -> Option<&'static str> {
+        LANGUAGES
+            .iter()
+            .find(|l| {
+                l.code == name
+                    || l.english_name.to_lowercase() == name
+                    || l.native_name.to_lowercase() == name
+            })
+            .map(|l| l.code)
+            .or_else(|| {
+                ALIASES
+                    .iter()
+                    .find(|(alias, _)| *alias == name)
+                    .map(|(_, code)| *code)
+            })
+    };
+    find(&input).or_else(|| {
+        let (primary, region) = input.split_once(['-', '_'])?;
+        find(primary).filter(|_| !region.is_empty())
+    })
+}
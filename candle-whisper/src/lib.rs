@@ -1,19 +1,49 @@
-mod audio;
-mod languages;
+/// Formats a message and logs it at the given `Level` variant: `log_at!(logger, Debug, ...)`.
+macro_rules! log_at {
+    ($logger:expr, $level:ident, $($t:tt)*) => {
+        $logger.log($crate::logging::Level::$level, &format!($($t)*))
+    };
+}
 
+pub mod alignment;
+pub mod audio;
+pub mod biasing;
+pub mod builder;
+pub mod captions;
+pub mod chunked;
+pub mod confidence;
+pub mod consensus;
+pub mod detokenize;
+pub mod diagnostics;
+pub mod diff;
+pub mod encoder_cache;
+pub mod error;
+#[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+pub mod fetch;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fingerprint;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
+pub mod formats;
+pub mod half_model;
+pub mod hallucination;
+pub mod itn;
+pub mod languages;
+pub mod line_breaks;
+pub mod logging;
 pub mod logic;
-
-mod utils {
-    use wasm_bindgen::prelude::*;
-
-    #[wasm_bindgen]
-    extern "C" {
-        #[wasm_bindgen(js_namespace = console)]
-        pub fn log(s: &str);
-    }
-
-    #[macro_export]
-    macro_rules! console_log {
-        ($($t:tt)*) => ($crate::utils::log(&format_args!($($t)*).to_string()))
-    }
-}
+pub mod model_info;
+pub mod model_profile;
+pub mod overlap;
+pub mod paragraphs;
+pub mod preemption;
+pub mod profiles;
+pub mod prompt;
+pub mod segments;
+pub mod snapshot;
+pub mod speaker_change;
+pub mod text;
+pub mod timings;
+pub mod weights;
+pub mod yielder;
@@ -0,0 +1,89 @@
+//! Positions at which the text of a segment can be cut into caption lines, for the languages
+//! written with and without spaces between the words.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Punctuation ending a clause in the CJK languages, preferred as cut positions.
+pub const CJK_CLAUSE_MARKS: [char; 5] = ['、', '。', '！', '？', '，'];
+
+/// Characters that must not start a line in the CJK languages.
+const NO_LINE_START: &str = "、。，．！？：；）」』】〕〉》ー々ゃゅょっャュョッ…";
+
+/// Characters that must not end a line in the CJK languages.
+const NO_LINE_END: &str = "（「『【〔〈《";
+
+/// How a text is cut into lines, see [`BreakStrategy::for_language`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakStrategy {
+    /// Between the words separated by whitespace, the cuts falling before the whitespace.
+    Whitespace,
+    /// Between the ideographs and kana and around the runs of other scripts, which are kept
+    /// whole, never before a closing or after an opening punctuation mark.
+    Cjk,
+    /// Between the grapheme clusters, for the scripts without spaces between the words and
+    /// without a segmentation by the unicode rules.
+    Graphemes,
+}
+
+impl BreakStrategy {
+    /// Strategy of a Whisper language code, whitespace for the unknown and missing ones.
+    pub fn for_language(language: Option<&str>) -> Self {
+        match language {
+            Some("zh" | "ja" | "yue") => Self::Cjk,
+            Some("th" | "lo" | "km" | "my" | "bo") => Self::Graphemes,
+            _ => Self::Whitespace,
+        }
+    }
+}
+
+/// Byte indices strictly inside `text` at which it can be cut, in increasing order. Every
+/// index is a character and grapheme cluster boundary, so the combining sequences, emoji and
+/// surrogate pairs of the original UTF-16 are never split.
+///
+/// With [`BreakStrategy::Whitespace`] a text without spaces has no cut.
+pub fn break_points(text: &str, strategy: BreakStrategy) -> Vec<usize> {
+    let mut points: Vec<usize> = match strategy {
+        BreakStrategy::Whitespace => {
+            let mut previous_is_space = true;
+            text.char_indices()
+                .filter(|&(_, c)| {
+                    let cut = c.is_whitespace() && !previous_is_space;
+                    previous_is_space = c.is_whitespace();
+                    cut
+                })
+                .map(|(i, _)| i)
+                .collect()
+        }
+        BreakStrategy::Cjk => text
+            .split_word_bound_indices()
+            .map(|(i, _)| i)
+            .filter(|&i| {
+                let before = text[..i].chars().next_back();
+                let after = text[i..].chars().next();
+                // The whitespace stays at the start of the next line, as with the spaces.
+                !before.is_some_and(|c| c.is_whitespace() || NO_LINE_END.contains(c))
+                    && !after.is_some_and(|c| NO_LINE_START.contains(c))
+            })
+            .collect(),
+        BreakStrategy::Graphemes => text
+            .grapheme_indices(true)
+            .map(|(i, _)| i)
+            .filter(|&i| !text[..i].ends_with(char::is_whitespace))
+            .collect(),
+    };
+    points.retain(|&i| i > 0 && i < text.len());
+    points
+}
+
+/// Byte index of the cut of `text` closest to `index`, between the grapheme clusters for a
+/// text without spaces cut with [`BreakStrategy::Whitespace`], and 0 when it has none.
+pub fn closest_break(text: &str, index: usize, strategy: BreakStrategy) -> usize {
+    let mut points = break_points(text, strategy);
+    if points.is_empty() && strategy == BreakStrategy::Whitespace {
+        points = break_points(text, BreakStrategy::Graphemes);
+    }
+    points
+        .into_iter()
+        .min_by_key(|i| i.abs_diff(index))
+        .unwrap_or(0)
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Shapes, intermediate results and skipped windows.
+    Debug,
+    /// Loading steps.
+    Info,
+    /// Recoverable failures, e.g. a window that failed to decode.
+    Warn,
+    Error,
+}
+
+/// Destination of the log messages of the decoder and of its builder.
+pub trait Logger {
+    fn log(&self, level: Level, msg: &str);
+}
+
+/// The browser console on wasm, the `log` crate elsewhere.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultLogger;
+
+#[cfg(target_arch = "wasm32")]
+mod console {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = console)]
+        pub fn debug(s: &str);
+        #[wasm_bindgen(js_namespace = console)]
+        pub fn info(s: &str);
+        #[wasm_bindgen(js_namespace = console)]
+        pub fn warn(s: &str);
+        #[wasm_bindgen(js_namespace = console)]
+        pub fn error(s: &str);
+    }
+}
+
+impl Logger for DefaultLogger {
+    #[cfg(target_arch = "wasm32")]
+    fn log(&self, level: Level, msg: &str) {
+        let msg = format!("[RUST]: {msg}");
+        match level {
+            Level::Debug => console::debug(&msg),
+            Level::Info => console::info(&msg),
+            Level::Warn => console::warn(&msg),
+            Level::Error => console::error(&msg),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn log(&self, level: Level, msg: &str) {
+        let level = match level {
+            Level::Debug => log::Level::Debug,
+            Level::Info => log::Level::Info,
+            Level::Warn => log::Level::Warn,
+            Level::Error => log::Level::Error,
+        };
+        log::log!(level, "{msg}")
+    }
+}
+
+/// Drops every message.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopLogger;
+
+impl Logger for NoopLogger {
+    fn log(&self, _level: Level, _msg: &str) {}
+}
+
+/// Forwards the messages of at least `min_level` to `logger`.
+#[derive(Debug, Clone)]
+pub struct MinLevel<L> {
+    pub logger: L,
+    pub min_level: Level,
+}
+
+impl<L: Logger> Logger for MinLevel<L> {
+    fn log(&self, level: Level, msg: &str) {
+        if level >= self.min_level {
+            self.logger.log(level, msg)
+        }
+    }
+}
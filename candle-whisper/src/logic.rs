@@ -1,35 +1,623 @@
-use crate::{audio, console_log, languages::LANGUAGES};
+use crate::{
+    alignment::{self, AlignmentDecoder, WordTiming},
+    audio::{
+        self, AudioPreprocess, ChannelSelect, FormatCheck, FormatIssue, MelNormalization,
+        MelSpectrogram, VadOptions,
+    },
+    biasing::PhraseBooster,
+    builder::DecoderBuilder,
+    chunked::{self, ChunkOptions},
+    confidence::ConfidenceWeights,
+    consensus::{vote, HighAccuracyOptions, LowAgreementSpan},
+    detokenize::{decode_tokens, TokenText},
+    diagnostics::{
+        AttemptDiagnostics, FallbackReason, RunDiagnostics, WindowDiagnostics, WindowOutcome,
+    },
+    encoder_cache::{EncoderCache, EncoderCacheOptions, EncoderCacheStats},
+    error::{DecodeContext, StepFailure, WhisperError},
+    half_model,
+    hallucination::{self, filter_hallucinations, HallucinationOptions},
+    itn,
+    languages::{self, LANGUAGES},
+    logging::Logger,
+    model_info::ModelInfo,
+    overlap::{self, OverlapWord},
+    preemption::PreemptionControl,
+    profiles::{OptionProfiles, PartialDecodeOptions},
+    prompt::{PrefixIndex, PromptBuffer},
+    segments::{self, limit_length},
+    snapshot::{ConfigSnapshot, SkippedField, CRATE_VERSION},
+    speaker_change::{self, SpeakerChangeTracker},
+    text::{TextOptions, TextPostProcessor},
+    timings::{Clock, SystemClock, Timings, WarmupReport},
+    weights::WeightManifest,
+    yielder::{now_or_never, NoopYielder, Yielder},
+};
 
 use anyhow::Error as E;
-use rand::{distributions::Distribution, rngs::StdRng, SeedableRng};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
 use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Weak},
+};
 
-use candle_core::{safetensors::Load, Device, IndexOp, Tensor, D};
-use candle_nn::{ops::softmax, VarBuilder};
+use candle_core::{DType, Device, IndexOp, Tensor, D};
+use candle_nn::ops::{log_softmax, softmax};
 pub use candle_transformers::models::whisper::{self as m, Config};
 use tokenizers::Tokenizer;
 
+/// Model size in bytes taking about one second to transcribe a second of audio, a rough
+/// guess used by `Decoder::estimate_runtime` before any calibration.
+const BYTES_PER_REALTIME_FACTOR: f64 = 300e6;
+
+/// Vocabulary size from which a model is multilingual, following openai/whisper.
+pub const MULTILINGUAL_VOCAB_SIZE: usize = 51865;
+
+/// Number of sampled tokens between two checks of the time budgets.
+const BUDGET_CHECK_INTERVAL: usize = 8;
+
+/// Silence run through the model by `Decoder::warm_up`, in seconds.
+const WARMUP_SECONDS: f64 = 2.;
+
+/// Decoder steps run by `Decoder::warm_up`.
+const WARMUP_DECODER_STEPS: usize = 4;
+
+/// Token preceding the previous text passed as a prompt.
+const START_OF_PREV_TOKEN: &str = "<|startofprev|>";
+
+/// Relative mass by which the nucleus of `truncate_distribution` may fall short of `top_p`.
+const TOP_P_TOLERANCE: f64 = 1e-9;
+
+/// Total weight below which `sample_weights` finds nothing to sample.
+const MIN_SAMPLING_MASS: f64 = 1e-12;
+
+/// Files of a model, moved into the decoder by `Decoder::load` which releases each buffer once
+/// parsed, the weights while their tensors are materialized.
+///
+/// The buffers serialize as bytes in the formats that have them and as arrays of integers in
+/// JSON, both layouts are accepted when deserializing.
 #[derive(Serialize, Deserialize)]
 pub struct ModelData {
+    #[serde(with = "byte_buf")]
     pub weights: Vec<u8>,
+    #[serde(with = "byte_buf")]
     pub tokenizer: Vec<u8>,
+    /// Computed from the config when empty.
+    #[serde(with = "byte_buf")]
     pub mel_filters: Vec<u8>,
+    #[serde(with = "byte_buf")]
     pub config: Vec<u8>,
     pub quantized: bool,
     pub timestamps: bool,
     pub is_multilingual: bool,
     pub language: Option<String>,
     pub task: Option<String>,
+    /// `f32`, `f16` or `bf16`, overrides the dtype of the normal model.
+    #[serde(default)]
+    pub dtype: Option<String>,
+    /// Quantization such as `q8_0` or `q5_0` the safetensors weights are converted to on load,
+    /// see `DecoderBuilder::quantize_on_load`. Skipped when unset, keeping the layout of the
+    /// model data written before it existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantize_on_load: Option<String>,
+    /// Digests the safetensors weights are verified against on load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<WeightManifest>,
+}
+
+impl ModelData {
+    /// Model data taking ownership of the buffers, `Box<[u8]>` and `Vec<u8>` are moved without
+    /// a copy. The weights are quantized when they are a GGUF file and the model is multilingual
+    /// when the vocabulary of the config is, the model transcribes with timestamps in the
+    /// detected language. The other fields can be set afterwards.
+    pub fn from_parts(
+        weights: impl Into<Vec<u8>>,
+        tokenizer: impl Into<Vec<u8>>,
+        mel_filters: impl Into<Vec<u8>>,
+        config: impl Into<Vec<u8>>,
+    ) -> Self {
+        let (weights, config) = (weights.into(), config.into());
+        // An invalid config is reported by the loading.
+        let is_multilingual = serde_json::from_slice::<Config>(&config)
+            .is_ok_and(|config| config.vocab_size >= MULTILINGUAL_VOCAB_SIZE);
+        Self {
+            quantized: weights.starts_with(b"GGUF"),
+            weights,
+            tokenizer: tokenizer.into(),
+            mel_filters: mel_filters.into(),
+            config,
+            timestamps: true,
+            is_multilingual,
+            language: None,
+            task: None,
+            dtype: None,
+            quantize_on_load: None,
+            manifest: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecodeOptions {
+    pub hallucination: HallucinationOptions,
+    pub text: TextOptions,
+    pub preprocess: AudioPreprocess,
+    /// Reinterpret the samples of the WAV files that `audio::check_format` finds mislabeled:
+    /// resampled from the estimated rate, or with their bytes swapped. The mismatch is
+    /// otherwise only logged and reported in the diagnostics.
+    pub auto_correct_format: bool,
+    /// Compute the mel spectrogram in `f64`, see `audio::pcm_to_mel_precise`, for transcripts
+    /// identical between the native and the wasm builds. The mel computation takes 10 to 20%
+    /// longer, which is small next to the model.
+    pub precise_mel: bool,
+    /// Reference of the dynamic range clamp of the mel spectrogram, the global maximum by
+    /// default.
+    pub mel_normalization: MelNormalization,
+    /// Only decode the windows overlapping the speech regions found by the energy VAD, the
+    /// other windows are emitted as no-speech segments.
+    pub use_vad: bool,
+    /// Speech the energy VAD has to find in a window treated as silence for the speech to be
+    /// decoded on its own rather than dropped with the window. `None` drops the window.
+    pub min_speech_duration: Option<f64>,
+    pub vad: VadOptions,
+    /// Cut the silence before the first and after the last speech the energy VAD finds, on
+    /// which the model hallucinates, keeping `trim_silence_padding` seconds around the speech.
+    /// The audio without speech is left whole. The silence cut is reported in
+    /// `TranscriptionOutput::silence_trim`.
+    pub trim_silence: bool,
+    /// Seconds of the silence kept on both sides of the speech by `trim_silence`, 0.2 by
+    /// default.
+    pub trim_silence_padding: f64,
+    /// Whether the times of a transcription cut by `trim_silence` are relative to the audio
+    /// given or to the trimmed audio.
+    pub timestamp_origin: TimestampOrigin,
+    /// Temperatures tried in order until a decoding result is accepted.
+    pub temperatures: Vec<f64>,
+    /// Sample among the `top_k` most probable tokens only at the positive temperatures.
+    pub top_k: Option<usize>,
+    /// Sample among the most probable tokens whose probabilities add up to `top_p` only at the
+    /// positive temperatures, after `top_k`.
+    pub top_p: Option<f64>,
+    /// A result whose compression ratio is above this threshold is too repetitive and
+    /// triggers a fallback, `None` disables the check.
+    pub compression_ratio_threshold: Option<f64>,
+    /// A result whose average log probability is below this threshold triggers a fallback,
+    /// `None` disables the check. With `length_penalty` the length-normalized score is compared
+    /// instead.
+    pub logprob_threshold: Option<f64>,
+    /// Exponent of the GNMT length normalization of the scores ranking and accepting the
+    /// decoding results, see [`sequence_score`]. `None` uses the mean log probability.
+    pub length_penalty: Option<f64>,
+    /// A result whose no-speech probability is above this threshold while its average log
+    /// probability is below `logprob_threshold` is treated as silence.
+    pub no_speech_threshold: Option<f64>,
+    pub on_segment_error: SegmentErrorPolicy,
+    /// Measure the time spent in each stage, returned in `TranscriptionOutput::timings`.
+    pub collect_timings: bool,
+    /// Record what happened to every window, returned in `TranscriptionOutput::diagnostics`.
+    pub collect_diagnostics: bool,
+    pub language_detection: LanguageDetectionMode,
+    /// Split the segments into cues no longer than this many seconds.
+    pub max_segment_duration: Option<f64>,
+    /// Split the segments into cues no longer than this many characters.
+    pub max_segment_chars: Option<usize>,
+    /// Restricts the language detection to these language codes.
+    pub allowed_languages: Option<Vec<String>>,
+    /// Tokens suppressed on top of the `suppress_tokens` of the model config.
+    pub extra_suppress_tokens: Vec<u32>,
+    /// Tokens of the model config suppress list that are allowed to be sampled.
+    pub unsuppress_tokens: Vec<u32>,
+    /// Suppress the tokens of [`default_suppress_tokens`] when the `suppress_tokens` of the
+    /// model config are empty, as in some community conversions, whose models otherwise keep
+    /// writing `♪♪♪` and bracketed sound descriptions.
+    pub synthesize_suppress_list: bool,
+    /// Phrases to favour, e.g. product names, with the amount added to the logits of their
+    /// tokens, see [`PhraseBooster`]. The boost applies after the suppression and before
+    /// `top_k` and `top_p`, and disables `greedy_fast_path`.
+    pub boost_phrases: Vec<(String, f32)>,
+    /// Suppress the blank and end of text tokens at the first sampled position.
+    pub suppress_blank: bool,
+    /// Seconds from the start of the window the first timestamp of a window may claim at
+    /// most, so that the segments do not start long after their speech. The cap is lowered to
+    /// the audio left in the last window. 1.0 by default as in openai/whisper, `None` disables
+    /// it.
+    pub max_initial_timestamp: Option<f64>,
+    /// Decode every window several times and vote the tokens between the passes.
+    pub high_accuracy: Option<HighAccuracyOptions>,
+    /// Time the words of the segments from the cross-attention of the decoder, only
+    /// supported by the non-quantized models.
+    pub word_timestamps: bool,
+    /// Score the segments starting a window on how likely the voice changed from the previous
+    /// window, see the `speaker_change` module.
+    pub speaker_change_hints: bool,
+    /// Keep the encoder outputs to skip the encoder when the same audio is decoded again,
+    /// disabled by default.
+    pub encoder_cache: Option<EncoderCacheOptions>,
+    /// Weights of the statistics blended into `Segment::confidence`.
+    pub confidence: ConfidenceWeights,
+    /// Seconds by which consecutive windows overlap, so that the words cut by a window
+    /// boundary are decoded whole by the next window. The overlapping windows are stitched
+    /// where their words agree. Windows ending on a timestamp resume from it instead.
+    pub overlap_seconds: f64,
+    /// Tokens sampled per decoding attempt, at most and by default half the
+    /// `max_target_positions` of the model.
+    pub max_tokens_per_segment: Option<usize>,
+    /// Tokens sampled between two calls of the partial callback, see
+    /// `Decoder::set_on_partial`.
+    pub partial_interval_tokens: usize,
+    /// Tokens sampled between two awaits of the yielder of the async transcription, see
+    /// [`Decoder::run_pcm_async`].
+    pub yield_interval_tokens: usize,
+    /// Seconds a decoding attempt may take, checked every few sampled tokens.
+    pub max_decode_seconds_per_segment: Option<f64>,
+    /// Seconds the whole run may take, the windows decoded once it is exceeded are left empty.
+    pub max_total_seconds: Option<f64>,
+    /// Length of the windows fed to the encoder.
+    pub encoder_input: EncoderInput,
+    /// Decode the audio in independent overlapping chunks merged on their common tokens, the
+    /// long-form strategy of the distil-whisper models. `None` enables it for the models that
+    /// look distilled, see `chunked::is_distilled`.
+    pub chunked_long_form: Option<bool>,
+    /// Chunks of the chunked long-form strategy, which replace `overlap_seconds`.
+    pub chunking: ChunkOptions,
+    /// Prompt each window with the text of the previous ones, except in the chunked long-form
+    /// strategy whose chunks are independent. Needs a tokenizer with `<|startofprev|>`.
+    pub condition_on_previous_text: bool,
+    /// Tokens of previous text in the prompt, at most and by default half the
+    /// `max_target_positions` of the model minus one.
+    pub max_prompt_tokens: Option<usize>,
+    /// When the previous text ends inside a word, drop its last token from the prompt and
+    /// restrict the first text token sampled to the tokens starting with the same bytes, so
+    /// that the word is completed rather than repeated or mangled. Only the byte-level
+    /// tokenizers split the words. Enabled by default.
+    pub token_healing: bool,
+    /// Select the greedy tokens on the device, only copying the selected token and its log
+    /// probability to the host, when no logits processor needs the logits. Enabled by default.
+    pub greedy_fast_path: bool,
+    /// Return an empty output flagged `no_speech_detected`, without running the model, when
+    /// the `MelSpectrogram::speech_energy_ratio` of the audio is below this ratio.
+    pub min_speech_energy_ratio: Option<f32>,
+    /// Return an empty output flagged `too_short`, without running the model, for the audio
+    /// shorter than this duration in seconds, on which the model hallucinates. 0.1 by default.
+    pub min_duration: f64,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            hallucination: HallucinationOptions::default(),
+            text: TextOptions::default(),
+            preprocess: AudioPreprocess::default(),
+            auto_correct_format: false,
+            precise_mel: false,
+            mel_normalization: MelNormalization::default(),
+            use_vad: false,
+            min_speech_duration: Some(0.5),
+            vad: VadOptions::default(),
+            trim_silence: false,
+            trim_silence_padding: 0.2,
+            timestamp_origin: TimestampOrigin::default(),
+            temperatures: m::TEMPERATURES.to_vec(),
+            compression_ratio_threshold: Some(m::COMPRESSION_RATIO_THRESHOLD),
+            logprob_threshold: Some(m::LOGPROB_THRESHOLD),
+            length_penalty: None,
+            no_speech_threshold: Some(m::NO_SPEECH_THRESHOLD),
+            top_k: None,
+            top_p: None,
+            on_segment_error: SegmentErrorPolicy::default(),
+            collect_timings: false,
+            collect_diagnostics: false,
+            language_detection: LanguageDetectionMode::default(),
+            max_segment_duration: None,
+            max_segment_chars: None,
+            allowed_languages: None,
+            extra_suppress_tokens: vec![],
+            unsuppress_tokens: vec![],
+            synthesize_suppress_list: true,
+            boost_phrases: vec![],
+            suppress_blank: true,
+            max_initial_timestamp: Some(1.),
+            high_accuracy: None,
+            word_timestamps: false,
+            speaker_change_hints: false,
+            encoder_cache: None,
+            confidence: ConfidenceWeights::default(),
+            overlap_seconds: 0.,
+            max_tokens_per_segment: None,
+            partial_interval_tokens: 10,
+            yield_interval_tokens: 8,
+            max_decode_seconds_per_segment: None,
+            max_total_seconds: None,
+            encoder_input: EncoderInput::default(),
+            chunked_long_form: None,
+            chunking: ChunkOptions::default(),
+            condition_on_previous_text: false,
+            max_prompt_tokens: None,
+            token_healing: true,
+            greedy_fast_path: true,
+            min_speech_energy_ratio: None,
+            min_duration: 0.1,
+        }
+    }
+}
+
+impl DecodeOptions {
+    pub fn validate(&self) -> Result<(), WhisperError> {
+        if self.temperatures.is_empty() {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the temperature schedule cannot be empty".to_string(),
+            });
+        }
+        if let Some(t) = self
+            .temperatures
+            .iter()
+            .find(|t| !t.is_finite() || **t < 0.)
+        {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!("invalid temperature {t}"),
+            });
+        }
+        if let MelNormalization::Percentile(p) = self.mel_normalization {
+            if !(p > 0. && p <= 100.) {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("the mel normalization percentile {p} is not in (0, 100]"),
+                });
+            }
+        }
+        if self.top_k == Some(0) {
+            return Err(WhisperError::InvalidConfig {
+                reason: "top_k must be positive".to_string(),
+            });
+        }
+        if let Some(p) = self.top_p.filter(|p| !(*p > 0. && *p <= 1.)) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!("top_p {p} is not in (0, 1]"),
+            });
+        }
+        if let Some(penalty) = self.length_penalty.filter(|p| !p.is_finite() || *p < 0.) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!("length_penalty {penalty} must be a non-negative number"),
+            });
+        }
+        if let Some(allowed) = &self.allowed_languages {
+            if allowed.is_empty() {
+                return Err(WhisperError::InvalidConfig {
+                    reason: "the allowed languages cannot be empty".to_string(),
+                });
+            }
+            check_languages(allowed)?;
+        }
+        if let LanguageDetectionMode::Pinned(language) = &self.language_detection {
+            check_languages(std::slice::from_ref(language))?;
+        }
+        if let Some(seconds) = self.max_initial_timestamp.filter(|s| s.is_nan() || *s < 0.) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!("the maximum initial timestamp {seconds} cannot be negative"),
+            });
+        }
+        if self
+            .max_segment_duration
+            .is_some_and(|d| d.is_nan() || d <= 0.)
+            || self.max_segment_chars == Some(0)
+        {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the maximum segment length must be positive".to_string(),
+            });
+        }
+        if let Some(amplitude) = self.preprocess.dither {
+            if !(amplitude.is_finite() && amplitude > 0.) {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("dither amplitude {amplitude} must be positive"),
+                });
+            }
+        }
+        if let Some(cutoff_hz) = self.preprocess.highpass_hz {
+            if !(cutoff_hz > 0. && cutoff_hz < m::SAMPLE_RATE as f32 / 2.) {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("high-pass cutoff {cutoff_hz}Hz is out of range"),
+                });
+            }
+        }
+        if let Some(high_accuracy) = &self.high_accuracy {
+            if high_accuracy.temperatures.is_empty() {
+                return Err(WhisperError::InvalidConfig {
+                    reason: "the high accuracy mode needs at least one additional pass".to_string(),
+                });
+            }
+            if let Some(t) = high_accuracy
+                .temperatures
+                .iter()
+                .find(|t| !t.is_finite() || **t < 0.)
+            {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("invalid temperature {t}"),
+                });
+            }
+            let min_agreement = high_accuracy.min_agreement;
+            if !(min_agreement > 0. && min_agreement <= 1.) {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("minimum agreement {min_agreement} is not in (0, 1]"),
+                });
+            }
+        }
+        let max_overlap = (m::CHUNK_LENGTH / 2) as f64;
+        if !(0. ..max_overlap).contains(&self.overlap_seconds) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!(
+                    "window overlap of {}s is not in [0, {max_overlap})",
+                    self.overlap_seconds
+                ),
+            });
+        }
+        let ChunkOptions {
+            chunk_seconds,
+            overlap_seconds,
+        } = self.chunking;
+        if !(chunk_seconds > 0. && chunk_seconds <= m::CHUNK_LENGTH as f64) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!(
+                    "chunk length of {chunk_seconds}s is not in (0, {}]",
+                    m::CHUNK_LENGTH
+                ),
+            });
+        }
+        if !(0. ..chunk_seconds / 2.).contains(&overlap_seconds) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!(
+                    "chunk overlap of {overlap_seconds}s is not in [0, {})",
+                    chunk_seconds / 2.
+                ),
+            });
+        }
+        if self.max_tokens_per_segment == Some(0)
+            || self.max_prompt_tokens == Some(0)
+            || self.partial_interval_tokens == 0
+            || self.yield_interval_tokens == 0
+        {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the token budgets must be positive".to_string(),
+            });
+        }
+        if self
+            .min_speech_duration
+            .is_some_and(|d| d.is_nan() || d <= 0.)
+        {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the minimum speech duration must be positive".to_string(),
+            });
+        }
+        if let EncoderInput::Truncate(seconds) = self.encoder_input {
+            if !(seconds >= 0.02 && seconds <= m::CHUNK_LENGTH as f64) {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!(
+                        "encoder windows of {seconds}s are not in [0.02, {}]",
+                        m::CHUNK_LENGTH
+                    ),
+                });
+            }
+        }
+        if !(self.trim_silence_padding >= 0. && self.trim_silence_padding.is_finite()) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!(
+                    "silence trim padding {}s is invalid",
+                    self.trim_silence_padding
+                ),
+            });
+        }
+        if !(self.min_duration >= 0. && self.min_duration.is_finite()) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!("minimum duration {}s is invalid", self.min_duration),
+            });
+        }
+        if let Some(ratio) = self.min_speech_energy_ratio {
+            if !(0. ..=1.).contains(&ratio) {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("minimum speech energy ratio {ratio} is not in [0, 1]"),
+                });
+            }
+        }
+        let budgets = [self.max_decode_seconds_per_segment, self.max_total_seconds];
+        if budgets.iter().flatten().any(|s| s.is_nan() || *s <= 0.) {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the time budgets must be positive".to_string(),
+            });
+        }
+        if let Some(coeff) = self.preprocess.pre_emphasis {
+            if !(0. ..1.).contains(&coeff) {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("pre-emphasis coefficient {coeff} is not in [0, 1)"),
+                });
+            }
+        }
+        if let Some(agc) = &self.preprocess.agc {
+            agc.validate()?;
+        }
+        for (phrase, boost) in &self.boost_phrases {
+            if phrase.trim().is_empty() || !boost.is_finite() {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("invalid boost {boost} of the phrase {phrase:?}"),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the language names and aliases by their codes.
+    fn canonicalize_languages(&mut self) {
+        let languages = self.allowed_languages.iter_mut().flatten();
+        let pinned = match &mut self.language_detection {
+            LanguageDetectionMode::Pinned(language) => Some(language),
+            _ => None,
+        };
+        for language in languages.chain(pinned) {
+            if let Some(code) = languages::canonicalize(language) {
+                *language = code.to_string()
+            }
+        }
+    }
+
+    fn validate_tokens(&self, vocab_size: usize) -> Result<(), WhisperError> {
+        let tokens = self
+            .extra_suppress_tokens
+            .iter()
+            .chain(self.unsuppress_tokens.iter());
+        if let Some(token) = tokens.into_iter().find(|t| **t as usize >= vocab_size) {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!("token {token} is out of the vocabulary of size {vocab_size}"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether a window should be considered as silence, following openai/whisper this
+    /// requires both a high no-speech probability and a low average log probability.
+    fn is_silence(&self, dr: &DecodingResult) -> bool {
+        match (self.no_speech_threshold, self.logprob_threshold) {
+            (Some(no_speech), Some(logprob)) => dr.no_speech_prob > no_speech && dr.score < logprob,
+            _ => false,
+        }
+    }
+
+    /// Thresholds failed by a result that trigger a fallback, none for the silence.
+    fn fallback_reasons(&self, dr: &DecodingResult) -> Vec<FallbackReason> {
+        // Another attempt would most likely run out of budget as well.
+        if self.is_silence(dr) || dr.truncated {
+            return vec![];
+        }
+        let too_repetitive = self
+            .compression_ratio_threshold
+            .is_some_and(|threshold| dr.compression_ratio > threshold);
+        let too_unlikely = self
+            .logprob_threshold
+            .is_some_and(|threshold| dr.score < threshold);
+        [
+            (too_repetitive, FallbackReason::CompressionRatio),
+            (too_unlikely, FallbackReason::Logprob),
+        ]
+        .into_iter()
+        .filter_map(|(failed, reason)| failed.then_some(reason))
+        .collect()
+    }
 }
 
 pub enum Model {
     Normal(m::model::Whisper),
+    /// Normal model in `f16` or `bf16`, which the candle model does not run in.
+    Half(half_model::Whisper),
     Quantized(m::quantized_model::Whisper),
 }
 impl Model {
     pub fn config(&self) -> &Config {
         match self {
             Self::Normal(m) => &m.config,
+            Self::Half(m) => &m.config,
             Self::Quantized(m) => &m.config,
         }
     }
@@ -37,6 +625,7 @@ impl Model {
     pub fn encoder_forward(&mut self, x: &Tensor, flush: bool) -> candle_core::Result<Tensor> {
         match self {
             Self::Normal(m) => m.encoder.forward(x, flush),
+            Self::Half(m) => m.encoder.forward(x, flush),
             Self::Quantized(m) => m.encoder.forward(x, flush),
         }
     }
@@ -49,13 +638,24 @@ impl Model {
     ) -> candle_core::Result<Tensor> {
         match self {
             Self::Normal(m) => m.decoder.forward(x, xa, flush),
+            Self::Half(m) => m.decoder.forward(x, xa, flush),
             Self::Quantized(m) => m.decoder.forward(x, xa, flush),
         }
     }
 
+    pub fn reset_kv_cache(&mut self) {
+        match self {
+            Self::Normal(m) => m.reset_kv_cache(),
+            Self::Half(m) => m.reset_kv_cache(),
+            Self::Quantized(m) => m.reset_kv_cache(),
+        }
+    }
+
+    /// Logits as `f32` whatever the dtype of the model.
     pub fn decoder_final_linear(&self, x: &Tensor) -> candle_core::Result<Tensor> {
         match self {
-            Self::Normal(m) => m.decoder.final_linear(x),
+            Self::Normal(m) => m.decoder.final_linear(x)?.to_dtype(DType::F32),
+            Self::Half(m) => m.decoder.final_linear(x)?.to_dtype(DType::F32),
             Self::Quantized(m) => m.decoder.final_linear(x),
         }
     }
@@ -65,325 +665,4062 @@ impl Model {
 pub struct DecodingResult {
     pub tokens: Vec<u32>,
     pub text: String,
+    /// `text` after the post-processing configured in `DecodeOptions::text`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_clean: Option<String>,
     pub avg_logprob: f64,
+    /// Sum of the log probabilities of the sampled tokens, the end of text token excluded.
+    #[serde(default)]
+    pub sum_logprob: f64,
+    /// [`sequence_score`] of the sampled tokens with `DecodeOptions::length_penalty`, which
+    /// ranks the results and is compared to `DecodeOptions::logprob_threshold`. Their mean log
+    /// probability without a length penalty.
+    #[serde(default)]
+    pub score: f64,
     pub no_speech_prob: f64,
-    temperature: f64,
+    /// Temperature of the accepted decoding attempt.
+    pub temperature: f64,
+    /// `NaN` when not computed, serialized as `null`.
+    #[serde(deserialize_with = "f64_or_nan")]
     compression_ratio: f64,
+    /// Number of temperatures tried before this result was accepted.
+    #[serde(default)]
+    pub attempts: usize,
+    /// Whether the decoding stopped on a budget before the end of text token.
+    #[serde(default)]
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncation_reason: Option<TruncationReason>,
+    /// Probability of each of `tokens` when it was sampled, 1 for the prompt tokens. Empty when
+    /// the tokens do not come from a single decoding attempt, e.g. after a vote.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub token_probs: Vec<f32>,
+    /// Whether some tokens did not decode to valid text, their bytes being replaced by U+FFFD
+    /// in `text`.
+    #[serde(default)]
+    pub had_decode_errors: bool,
+    /// Indices in `tokens` of the tokens that did not decode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub decode_error_tokens: Vec<usize>,
+    /// Steps at which the sampling found no probability mass left, e.g. every token
+    /// suppressed, and took the argmax of the logits of the model instead.
+    #[serde(default)]
+    pub sampling_fallbacks: u32,
+}
+
+/// Budget of `DecodeOptions` that stopped a decoding attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationReason {
+    /// `max_tokens_per_segment`, or the context size of the model.
+    MaxTokens,
+    /// `max_decode_seconds_per_segment`.
+    SegmentTime,
+    /// `max_total_seconds`.
+    TotalTime,
+}
+
+impl DecodingResult {
+    /// Ratio of the size of the text to its compressed size, `NaN` when not computed.
+    pub fn compression_ratio(&self) -> f64 {
+        self.compression_ratio
+    }
+
+    fn no_speech() -> Self {
+        Self {
+            tokens: vec![],
+            text: String::new(),
+            text_clean: None,
+            avg_logprob: 0.0,
+            sum_logprob: 0.0,
+            score: 0.0,
+            no_speech_prob: 1.0,
+            temperature: 0.0,
+            compression_ratio: f64::NAN,
+            attempts: 0,
+            truncated: false,
+            truncation_reason: None,
+            token_probs: vec![],
+            had_decode_errors: false,
+            decode_error_tokens: vec![],
+            sampling_fallbacks: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Segment {
+    /// Position of the segment in the output of the run.
+    #[serde(default)]
+    pub id: usize,
     pub start: f64,
     pub duration: f64,
     pub dr: DecodingResult,
+    #[serde(default)]
+    pub hallucination_score: f64,
+    /// Score in `[0, 1]` blending the decoding statistics, see `ConfidenceWeights`.
+    #[serde(default)]
+    pub confidence: f32,
+    #[serde(default)]
+    pub no_speech: bool,
+    /// Error that prevented the window from being decoded, set on placeholder segments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Language the window was decoded in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Speaker label, left for callers to fill from a diarization.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,
+    /// Tokens the passes of the high accuracy mode disagreed on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub low_agreement_spans: Vec<LowAgreementSpan>,
+    /// Words with their times, when `word_timestamps` is set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<WordTiming>,
+    /// Number of previous text tokens the window was prompted with.
+    #[serde(default)]
+    pub prompt_tokens: usize,
+    /// Translation of the window with `Task::Both`, decoded from the same encoder output as
+    /// `dr`. Not merged nor trimmed with the transcription.
+    #[serde(default)]
+    pub translation: Option<DecodingResult>,
+    /// How much more the voice changed from the previous window than within the windows, set
+    /// with `speaker_change_hints` on the segments starting a window, 0 elsewhere.
+    #[serde(default)]
+    pub speaker_change_score: f32,
+}
+
+impl Segment {
+    fn new(start: f64, duration: f64, dr: DecodingResult) -> Self {
+        Self {
+            id: 0,
+            start,
+            duration,
+            dr,
+            hallucination_score: 0.0,
+            confidence: 0.0,
+            no_speech: false,
+            error: None,
+            language: None,
+            speaker: None,
+            low_agreement_spans: vec![],
+            words: vec![],
+            prompt_tokens: 0,
+            translation: None,
+            speaker_change_score: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LanguageDetectionMode {
+    /// Detect the language on the first window and use it for the whole file.
+    #[default]
+    Once,
+    /// Detect the language of every window, for audio switching between languages. This
+    /// costs an extra decoder pass per window.
+    PerSegment,
+    /// Use this language for every window.
+    Pinned(String),
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+/// Length of the mel windows fed to the encoder, which is trained on 30 seconds. The times of
+/// the segments always follow the audio, not the padding.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderInput {
+    /// The windows as cut from the spectrogram, shorter than 30 seconds when the decoded range
+    /// or the spectrogram ends before.
+    #[default]
+    Exact,
+    /// Every window padded with silence to 30 seconds, as the last window of the audio.
+    PadToFull,
+    /// Windows of at most this many seconds without padding, trading accuracy for the latency
+    /// of the short chunks.
+    Truncate(f64),
+}
+
+/// Origin of the times of a transcription whose audio was cut by
+/// `DecodeOptions::trim_silence`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampOrigin {
+    /// The start of the audio given, or of the range of `RunOptions`, the silence cut before
+    /// the speech being added back.
+    #[default]
+    Original,
+    /// The first sample kept by the trim.
+    Trimmed,
+}
+
+/// Silence cut from the audio by `DecodeOptions::trim_silence`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SilenceTrim {
+    /// Seconds cut before the speech.
+    pub leading: f64,
+    /// Seconds cut after the speech.
+    pub trailing: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentErrorPolicy {
+    /// Fail the whole run.
+    #[default]
+    Abort,
+    /// Drop the window and continue with the next one.
+    SkipAndContinue,
+    /// Emit a placeholder segment carrying the error and continue with the next one.
+    InsertPlaceholder,
+}
+
+/// A window that could not be decoded under a non-aborting [`SegmentErrorPolicy`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentFailure {
+    pub start: f64,
+    pub duration: f64,
+    pub error: String,
+    /// [`WhisperError::code`] of the error.
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub context: Option<DecodeContext>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum Task {
     Transcribe,
     Translate,
+    /// Transcribe and translate every window, the encoder running once for both. The
+    /// translations are returned in `Segment::translation`.
+    Both,
+}
+
+impl std::str::FromStr for Task {
+    type Err = E;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transcribe" => Ok(Self::Transcribe),
+            "translate" => Ok(Self::Translate),
+            "both" => Ok(Self::Both),
+            _ => anyhow::bail!("unknown task '{s}', expected 'transcribe', 'translate' or 'both'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadStage {
+    Tokenizer,
+    MelFilters,
+    Config,
+    Weights,
+    Done,
+}
+
+/// Reported by [`Decoder::load_with_progress`] after each loading step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadProgress {
+    pub stage: LoadStage,
+    pub tensors_loaded: usize,
+    pub tensors_total: usize,
+    /// Bytes of the tensors materialized so far, from their shapes and the dtype they are
+    /// loaded with. Never decreases, the last report has the size of all the tensors.
+    pub bytes_allocated: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    /// Size of the loaded tensors, see [`ModelInfo::materialized_bytes`].
+    pub parameter_bytes: usize,
+    /// Capacity of the cross-attention key/value cache of the decoder.
+    pub kv_cache_bytes: usize,
+    pub total_bytes: usize,
+}
+
+pub enum AudioInput {
+    /// Bytes of a 16kHz WAV file.
+    Wav(Vec<u8>),
+    /// A channel of a 16kHz WAV file, the channels of a file shared by several inputs are
+    /// transcribed as separate jobs without copying the file.
+    WavChannel(Rc<[u8]>, ChannelSelect),
+    /// 16kHz mono samples in `[-1, 1]`.
+    Pcm(Vec<f32>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchProgress {
+    pub file_index: usize,
+    pub files_total: usize,
+    /// Whether the file at `file_index` has been processed, successfully or not.
+    pub done: bool,
+}
+
+/// Options of a single transcription run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunOptions {
+    /// Start in seconds of the range of the audio to transcribe, segment start times stay
+    /// relative to the beginning of the whole audio.
+    pub start: Option<f64>,
+    /// End in seconds of the range of the audio to transcribe.
+    pub end: Option<f64>,
+    /// Sample the timestamp tokens and resume the windows from the last one, `None` keeps the
+    /// mode the decoder was loaded with.
+    pub timestamps: Option<bool>,
+    /// Channel of a multi-channel WAV file to transcribe, all the channels are mixed by
+    /// default. Ignored for the samples, which are mono.
+    pub channel: ChannelSelect,
 }
 
+impl RunOptions {
+    /// Returns the samples of `pcm` within the requested range, clamped to the audio duration,
+    /// along with the time offset of the first returned sample.
+    fn trim<'a>(&self, pcm: &'a [f32]) -> Result<(&'a [f32], f64), WhisperError> {
+        let range = self.range(pcm.len(), m::SAMPLE_RATE as f64)?;
+        let time_offset = range.start as f64 / m::SAMPLE_RATE as f64;
+        Ok((&pcm[range], time_offset))
+    }
+
+    /// Indices within `0..len` of the requested range, for items sampled at `rate` per second.
+    fn range(&self, len: usize, rate: f64) -> Result<std::ops::Range<usize>, WhisperError> {
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if start >= end {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!("range start {start}s must be before its end {end}s"),
+                });
+            }
+        }
+        let to_index = |t: f64| usize::min(len, (t.max(0.) * rate) as usize);
+        let start = self.start.map_or(0, to_index);
+        let end = self.end.map_or(len, to_index);
+        Ok(usize::min(start, end)..end)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguageProb {
+    pub language: String,
+    pub prob: f32,
+}
+
+/// Result of the language detection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguageDetection {
+    pub token: u32,
+    pub language: String,
+    /// Probabilities of the candidate languages, most likely first.
+    pub probs: Vec<LanguageProb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptionOutput {
+    pub task: Task,
+    pub language: Option<String>,
+    /// Probabilities of the candidate languages when the language was detected.
+    #[serde(default)]
+    pub language_probs: Vec<LanguageProb>,
+    pub segments: Vec<Segment>,
+    /// Windows that failed to decode and were skipped or replaced by a placeholder.
+    #[serde(default)]
+    pub failed_segments: Vec<SegmentFailure>,
+    /// Set when `DecodeOptions::collect_timings` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+    /// Set when `DecodeOptions::collect_diagnostics` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<RunDiagnostics>,
+    /// Configuration of the decoder, set with `DecodeOptions::collect_diagnostics`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<Box<ConfigSnapshot>>,
+    /// The audio was not decoded, its speech energy ratio being below
+    /// `DecodeOptions::min_speech_energy_ratio`.
+    #[serde(default)]
+    pub no_speech_detected: bool,
+    /// The audio was not decoded, being empty or shorter than `DecodeOptions::min_duration`.
+    #[serde(default)]
+    pub too_short: bool,
+    /// Silence cut by `DecodeOptions::trim_silence`, `None` when the audio was not trimmed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub silence_trim: Option<SilenceTrim>,
+}
+
+/// Ids of the special tokens driving the decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecialTokens {
+    pub sot: u32,
+    pub eot: u32,
+    /// Missing from the tokenizers of some English-only models, which do not use the task
+    /// tokens.
+    pub translate: Option<u32>,
+    pub transcribe: Option<u32>,
+    pub no_timestamps: u32,
+    pub no_speech: u32,
+    /// Missing from some converted tokenizers, the previous text is not passed without it.
+    pub start_of_prev: Option<u32>,
+    /// Id of the `<|0.00|>` timestamp token, the timestamp tokens follow it with a 20ms step.
+    pub timestamp_begin: u32,
+}
+
+impl SpecialTokens {
+    pub fn new(tokenizer: &Tokenizer) -> anyhow::Result<Self> {
+        let no_timestamps = token_id(tokenizer, m::NO_TIMESTAMPS_TOKEN)?;
+        let no_speech = m::NO_SPEECH_TOKENS
+            .iter()
+            .find_map(|token| token_id(tokenizer, token).ok())
+            .or_else(|| {
+                // The no-speech token precedes `<|notimestamps|>` in the whisper vocabularies.
+                let id = no_timestamps.checked_sub(1)?;
+                let token = tokenizer.id_to_token(id)?;
+                (token.starts_with("<|no") && token.ends_with("|>")).then_some(id)
+            });
+        let no_speech = match no_speech {
+            None => anyhow::bail!(
+                "unable to find a no-speech token, tried {} and the token preceding {}",
+                m::NO_SPEECH_TOKENS.join(", "),
+                m::NO_TIMESTAMPS_TOKEN
+            ),
+            Some(n) => n,
+        };
+        Ok(Self {
+            sot: token_id(tokenizer, m::SOT_TOKEN)?,
+            eot: token_id(tokenizer, m::EOT_TOKEN)?,
+            translate: token_id(tokenizer, m::TRANSLATE_TOKEN).ok(),
+            transcribe: token_id(tokenizer, m::TRANSCRIBE_TOKEN).ok(),
+            no_timestamps,
+            no_speech,
+            start_of_prev: token_id(tokenizer, START_OF_PREV_TOKEN).ok(),
+            timestamp_begin: no_timestamps + 1,
+        })
+    }
+
+    /// Special tokens of a shared tokenizer, derived once per tokenizer instance so that the
+    /// decoders built or swapped with the same tokenizer skip the derivation.
+    pub fn shared(tokenizer: &Arc<Tokenizer>) -> anyhow::Result<Self> {
+        thread_local! {
+            static DERIVED: RefCell<Vec<(Weak<Tokenizer>, SpecialTokens)>> =
+                const { RefCell::new(vec![]) };
+        }
+        let weak = Arc::downgrade(tokenizer);
+        let cached = DERIVED.with_borrow_mut(|derived| {
+            derived.retain(|(tokenizer, _)| tokenizer.strong_count() > 0);
+            derived
+                .iter()
+                .find(|(tokenizer, _)| tokenizer.ptr_eq(&weak))
+                .map(|(_, special_tokens)| *special_tokens)
+        });
+        if let Some(special_tokens) = cached {
+            return Ok(special_tokens);
+        }
+        let special_tokens = Self::new(tokenizer)?;
+        DERIVED.with_borrow_mut(|derived| derived.push((weak, special_tokens)));
+        Ok(special_tokens)
+    }
+}
+
+/// Accepted decoding of a window.
+struct DecodedWindow {
+    dr: DecodingResult,
+    /// Language the window was decoded in.
+    language: Option<String>,
+    low_agreement_spans: Vec<LowAgreementSpan>,
+    /// Word times relative to the start of the window.
+    words: Vec<WordTiming>,
+    translation: Option<DecodingResult>,
+    /// Audio features of every second of the window, with `speaker_change_hints`.
+    second_embeddings: Vec<Vec<f32>>,
+}
+
+/// State of a resumable transcription after a decoded window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Mel frame of the transcribed range the next window starts at.
+    pub seek_frame: usize,
+    /// Number of mel frames of the transcribed range.
+    pub n_frames: usize,
+    /// Windows decoded so far, before the hallucination filter and the cue splitting, with
+    /// times relative to the start of the transcribed range.
+    pub segments: Vec<Segment>,
+    pub failed_segments: Vec<SegmentFailure>,
+    pub detected_language: Option<LanguageDetection>,
+    /// Text tokens of the previous windows the next window is conditioned on, see
+    /// `DecodeOptions::condition_on_previous_text`.
+    #[serde(default)]
+    pub prompt_tokens: Vec<u32>,
+    /// Previous text of the translations of `Task::Both`, which are conditioned on their own.
+    #[serde(default)]
+    pub translation_prompt_tokens: Vec<u32>,
+    /// Last seconds and baseline distance the speaker change of the next window is measured
+    /// with, see `DecodeOptions::speaker_change_hints`.
+    #[serde(default)]
+    pub speaker_changes: SpeakerChangeTracker,
+    /// Seed of the sampling RNG for the next window, the RNG is reseeded at every checkpoint
+    /// so that a resumed run samples as an uninterrupted one.
+    pub rng_seed: u64,
+    /// Hash of the options and model the transcription was started with.
+    pub options_hash: u64,
+}
+
+/// End of a [`Decoder::run_preemptible`] run.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum RunOutcome {
+    Completed(TranscriptionOutput),
+    /// The run yielded to another job. [`Decoder::resume_preemptible`] continues it from the
+    /// checkpoint, the segments being the windows decoded so far with absolute times, for
+    /// display until the run completes.
+    Preempted(Checkpoint, Vec<Segment>),
+}
+
+/// Samples ready for the transcription, see `Decoder::prepare_pcm`.
+struct PreparedPcm {
+    mel: MelSpectrogram,
+    /// Speech regions when the VAD is used.
+    speech_regions: Option<Vec<(f64, f64)>>,
+    /// Time of the first sample kept, added to the times of the transcription.
+    time_offset: f64,
+    silence_trim: Option<SilenceTrim>,
+}
+
+/// Progress of a transcription over the windows of a spectrogram.
+struct RunState {
+    seek: usize,
+    segments: Vec<Segment>,
+    failures: Vec<SegmentFailure>,
+    /// End in seconds of the audio, the spectrogram being padded with silence past it.
+    audio_end: f64,
+}
+
+impl RunState {
+    fn new(audio_end: f64) -> Self {
+        Self {
+            seek: 0,
+            segments: vec![],
+            failures: vec![],
+            audio_end,
+        }
+    }
+}
+
+/// Window of a transcription pulled from a [`SegmentIter`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentEvent {
+    /// Segments decoded from the window, with absolute times. The hallucination filter, the
+    /// length limits and the ids are only applied to the segments of
+    /// [`SegmentIter::into_output`].
+    Decoded(Vec<Segment>),
+    /// Window without speech, or that could not be decoded under a non-aborting
+    /// [`SegmentErrorPolicy`], `failure` then being its error.
+    Skipped {
+        start: f64,
+        /// Seconds the transcription moved forward past the window.
+        duration: f64,
+        failure: Option<SegmentFailure>,
+    },
+}
+
+/// Transcription decoding one window, the fallback passes included, per call to `next`, see
+/// [`Decoder::segments_iter`]. Dropped before [`SegmentIter::into_output`], it resets the
+/// prompt and the caches of the decoder.
+pub struct SegmentIter<'a> {
+    decoder: &'a mut Decoder,
+    /// The spectrogram in the model dtype, `None` when it is too short to be transcribed.
+    mel: Option<Tensor>,
+    speech_regions: Option<&'a [(f64, f64)]>,
+    time_offset: f64,
+    /// Taken by [`SegmentIter::into_output`].
+    state: Option<RunState>,
+    /// Error of the setup of the run, yielded by the first call to `next`.
+    error: Option<WhisperError>,
+    done: bool,
+}
+
+impl<'a> SegmentIter<'a> {
+    fn new(
+        decoder: &'a mut Decoder,
+        mel: &Tensor,
+        opts: &RunOptions,
+        speech_regions: Option<&'a [(f64, f64)]>,
+        time_offset: f64,
+        audio_end: f64,
+    ) -> Self {
+        let mel = if audio_end < decoder.options.min_duration {
+            Ok(None)
+        } else {
+            decoder.begin_transcription(mel, opts)
+        };
+        let (mel, error) = match mel {
+            Ok(mel) => (mel, None),
+            Err(err) => (None, Some(err)),
+        };
+        Self {
+            decoder,
+            mel,
+            speech_regions,
+            time_offset,
+            state: Some(RunState::new(audio_end)),
+            error,
+            done: false,
+        }
+    }
+
+    fn failed(decoder: &'a mut Decoder, error: WhisperError) -> Self {
+        Self {
+            decoder,
+            mel: None,
+            speech_regions: None,
+            time_offset: 0.,
+            state: None,
+            error: Some(error),
+            done: false,
+        }
+    }
+
+    /// Output of the windows pulled so far, the whole transcription once the iterator is
+    /// exhausted, like [`Decoder::run_mel`] returns it.
+    pub fn into_output(mut self) -> TranscriptionOutput {
+        match (self.mel.is_some(), self.state.take()) {
+            (true, Some(state)) => self.decoder.finish_transcription(state, self.time_offset),
+            _ => self.decoder.too_short_output(),
+        }
+    }
+
+    /// Pulls the remaining windows, stopping at the first error.
+    fn run_to_end(mut self) -> Result<TranscriptionOutput, WhisperError> {
+        for event in self.by_ref() {
+            event?;
+        }
+        Ok(self.into_output())
+    }
+
+    /// Event of the window that moved the seek from `seek` and added the segments and the
+    /// failures from `first_segment` and `first_failure`.
+    fn event(
+        state: &RunState,
+        seek: usize,
+        first_segment: usize,
+        first_failure: usize,
+        time_offset: f64,
+    ) -> SegmentEvent {
+        let frames_per_sec = m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64;
+        let start = time_offset + seek as f64 / frames_per_sec;
+        let duration = state.seek.saturating_sub(seek) as f64 / frames_per_sec;
+        if let Some(failure) = state.failures[first_failure..].last() {
+            return SegmentEvent::Skipped {
+                start,
+                duration,
+                failure: Some(SegmentFailure {
+                    start: failure.start + time_offset,
+                    ..failure.clone()
+                }),
+            };
+        }
+        let decoded = &state.segments[first_segment..];
+        if !decoded.iter().any(segments::is_speech) {
+            return SegmentEvent::Skipped {
+                start,
+                duration,
+                failure: None,
+            };
+        }
+        SegmentEvent::Decoded(
+            decoded
+                .iter()
+                .map(|segment| Segment {
+                    start: segment.start + time_offset,
+                    ..segment.clone()
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Iterator for SegmentIter<'_> {
+    type Item = Result<SegmentEvent, WhisperError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            self.done = true;
+            return Some(Err(err));
+        }
+        if self.done {
+            return None;
+        }
+        let (Some(mel), Some(state)) = (self.mel.as_ref(), self.state.as_mut()) else {
+            self.done = true;
+            return None;
+        };
+        let (seek, first_segment, first_failure) =
+            (state.seek, state.segments.len(), state.failures.len());
+        let mut yielder = NoopYielder;
+        let window = self
+            .decoder
+            .run_window(mel, self.speech_regions, state, &mut yielder);
+        match now_or_never(window) {
+            Ok(true) => Some(Ok(Self::event(
+                state,
+                seek,
+                first_segment,
+                first_failure,
+                self.time_offset,
+            ))),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            // The window failed midway, the run cannot go on from its state.
+            Err(err) => {
+                self.done = true;
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+impl Drop for SegmentIter<'_> {
+    fn drop(&mut self) {
+        if self.state.is_some() {
+            self.decoder.reset_state();
+            self.decoder.timings = None;
+            self.decoder.diagnostics = None;
+        }
+    }
+}
+
+/// What a [`LogitsProcessor`] knows of the token being sampled.
+#[derive(Debug, Clone, Copy)]
+pub struct LogitsContext<'a> {
+    /// Tokens sampled so far in the window, the prompt excluded.
+    pub tokens: &'a [u32],
+    /// Index of the token being sampled.
+    pub step: usize,
+    /// Start of the window in seconds.
+    pub segment_start: f64,
+    /// Whether the timestamp tokens can be sampled.
+    pub timestamps: bool,
+}
+
+/// Hook editing the logits of every sampled token, after the suppression masks and before the
+/// token is selected, e.g. for constrained decoding.
+pub type LogitsProcessor = Box<dyn FnMut(&mut [f32], &LogitsContext)>;
+
+/// In-progress result of the window being decoded, for displaying the words as they are
+/// sampled. The segments of the window, once decoded, supersede its partial hypotheses.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartialHypothesis {
+    /// Start of the window in seconds.
+    pub segment_start: f64,
+    /// Tokens sampled so far, the prompt excluded.
+    pub tokens: Vec<u32>,
+    /// Text of the tokens up to the last complete character, a prefix of the text of the
+    /// decoding attempt once finished.
+    pub text: String,
+    /// Number of tokens sampled.
+    pub step: usize,
+    /// Temperature of the decoding attempt, a fallback starting a new hypothesis.
+    pub temperature: f64,
+}
+
+/// Callback receiving the [`PartialHypothesis`] of the window being decoded every
+/// `DecodeOptions::partial_interval_tokens` tokens.
+pub type PartialCallback = Box<dyn FnMut(PartialHypothesis)>;
+
 pub struct Decoder {
     model: Model,
+    /// Decoder exposing the cross-attention weights, `None` for quantized models.
+    alignment: Option<AlignmentDecoder>,
+    device: Device,
+    /// Dtype of the model inputs.
+    dtype: DType,
+    model_info: ModelInfo,
     rng: rand::rngs::StdRng,
+    /// Seed of `rng` set on load, recorded in the config snapshots.
+    seed: u64,
     task: Option<Task>,
     language: Option<String>,
+    /// Language detected on the first window of the current file.
+    detected_language: Option<LanguageDetection>,
     is_multilingual: bool,
+    /// Options as set, without the language profiles.
+    base_options: DecodeOptions,
+    /// Options in effect, the base options with the profile of `profile_language` applied.
+    options: DecodeOptions,
+    profiles: OptionProfiles,
+    /// Language whose profile is applied to `options`, `None` when they are the base options.
+    profile_language: Option<String>,
+    text_processor: TextPostProcessor,
+    clock: Box<dyn Clock>,
+    logger: Rc<dyn Logger>,
+    /// Timings of the current run, when collected.
+    timings: Option<Timings>,
+    /// Report of the warm-up, `None` until the decoder is warmed up.
+    warmup: Option<WarmupReport>,
+    /// Diagnostics of the current run, when collected.
+    diagnostics: Option<RunDiagnostics>,
+    encoder_cache: EncoderCache,
+    /// Encoder passes run since the model was loaded.
+    encoder_forwards: usize,
+    /// Seconds of processing per second of audio measured by `calibrate_runtime`.
+    realtime_factor: Option<f64>,
+    /// Clock time at which the current run exceeds `max_total_seconds`.
+    run_deadline_ms: Option<f64>,
+    /// Previous text of the current run, see `condition_on_previous_text`.
+    prompt: PromptBuffer,
+    /// Previous translations of the current run with `Task::Both`, swapped with `prompt`
+    /// while translating.
+    translation_prompt: PromptBuffer,
+    /// The translation pass of `Task::Both` is being decoded.
+    translating: bool,
+    speaker_changes: SpeakerChangeTracker,
+    /// Text tokens by bytes for the token healing, `None` when the tokenizer is not
+    /// byte-level.
+    prefix_index: Option<PrefixIndex>,
+    logits_processor: Option<LogitsProcessor>,
+    on_partial: Option<PartialCallback>,
+    /// Start in seconds of the window being decoded.
+    window_start: f64,
+    /// Seconds of audio in the window being decoded, the rest of the window being padding.
+    window_audio: f64,
     mel_filters: Vec<f32>,
+    /// FFT plan and buffers of the spectrograms, kept between the runs.
+    mel_context: RefCell<audio::MelContext<f32>>,
+    precise_mel_context: RefCell<audio::MelContext<f64>>,
+    /// Timestamps mode of the runs that do not choose one.
     timestamps: bool,
-    tokenizer: Tokenizer,
+    /// Timestamps mode of the current run.
+    run_timestamps: bool,
+    tokenizer: Arc<Tokenizer>,
     suppress_tokens: Tensor,
-    sot_token: u32,
-    transcribe_token: u32,
-    translate_token: u32,
-    eot_token: u32,
-    no_speech_token: u32,
-    no_timestamps_token: u32,
+    /// Mask applied at the first sampled position, `suppress_tokens` plus the blank tokens
+    /// when `suppress_blank` is set.
+    suppress_initial_tokens: Tensor,
+    /// Tokens of `" "`, suppressed with EOT at the first sampled position.
+    blank_tokens: Vec<u32>,
+    /// [`default_suppress_tokens`] of the tokenizer when the model config has no
+    /// `suppress_tokens`, empty otherwise.
+    synthesized_suppress_tokens: Vec<u32>,
+    /// Trie of `DecodeOptions::boost_phrases`.
+    booster: PhraseBooster,
+    special_tokens: SpecialTokens,
 }
 
 impl Decoder {
     #[allow(clippy::too_many_arguments)]
-    fn new(
+    pub(crate) fn new(
         model: Model,
-        tokenizer: Tokenizer,
+        alignment: Option<AlignmentDecoder>,
+        model_info: ModelInfo,
+        tokenizer: Arc<Tokenizer>,
         mel_filters: Vec<f32>,
-        device: &Device,
+        device: Device,
+        dtype: DType,
         task: Option<Task>,
         language: Option<String>,
         is_multilingual: bool,
         timestamps: bool,
+        seed: u64,
+        logger: Rc<dyn Logger>,
     ) -> anyhow::Result<Self> {
-        let suppress_tokens: Vec<f32> = (0..model.config().vocab_size as u32)
-            .map(|i| {
-                if model.config().suppress_tokens.contains(&i) {
-                    f32::NEG_INFINITY
-                } else {
-                    0f32
-                }
-            })
-            .collect();
-        let no_timestamps_token = token_id(&tokenizer, m::NO_TIMESTAMPS_TOKEN)?;
-        let suppress_tokens = Tensor::new(suppress_tokens.as_slice(), device)?;
-        let sot_token = token_id(&tokenizer, m::SOT_TOKEN)?;
-        let transcribe_token = token_id(&tokenizer, m::TRANSCRIBE_TOKEN)?;
-        let translate_token = token_id(&tokenizer, m::TRANSLATE_TOKEN)?;
-        let eot_token = token_id(&tokenizer, m::EOT_TOKEN)?;
-        let no_speech_token = m::NO_SPEECH_TOKENS
-            .iter()
-            .find_map(|token| token_id(&tokenizer, token).ok());
-        let no_speech_token = match no_speech_token {
-            None => anyhow::bail!("unable to find any non-speech token"),
-            Some(n) => n,
+        let vocab_size = model.config().vocab_size;
+        let is_multilingual = if is_multilingual && vocab_size < MULTILINGUAL_VOCAB_SIZE {
+            log_at!(
+                logger,
+                Info,
+                "English-only model with {vocab_size} tokens, not multilingual"
+            );
+            false
+        } else {
+            is_multilingual
         };
-        let seed = 299792458;
-        Ok(Self {
+        let suppress_tokens = Tensor::zeros(vocab_size, DType::F32, &device)?;
+        let special_tokens = SpecialTokens::shared(&tokenizer)?;
+        check_task(task, is_multilingual, &special_tokens)?;
+        let blank_tokens = tokenizer
+            .encode(" ", false)
+            .map_err(E::msg)?
+            .get_ids()
+            .to_vec();
+        let synthesized_suppress_tokens = if model.config().suppress_tokens.is_empty() {
+            let tokens = default_suppress_tokens(&tokenizer, &special_tokens);
+            log_at!(
+                logger,
+                Info,
+                "the model config has no suppress_tokens, {} synthesized from the tokenizer",
+                tokens.len()
+            );
+            tokens
+        } else {
+            vec![]
+        };
+        let max_prompt_tokens = model.config().max_target_positions / 2 - 1;
+        let prompt = PromptBuffer::for_tokenizer(max_prompt_tokens, special_tokens.eot, &tokenizer);
+        let prefix_index = PrefixIndex::for_tokenizer(&tokenizer, special_tokens.eot);
+        let mut decoder = Self {
             model,
+            alignment,
+            device,
+            dtype,
+            model_info,
             rng: StdRng::seed_from_u64(seed),
+            seed,
             tokenizer,
             mel_filters,
+            mel_context: RefCell::default(),
+            precise_mel_context: RefCell::default(),
             task,
             timestamps,
+            run_timestamps: timestamps,
             language,
+            detected_language: None,
             is_multilingual,
+            base_options: DecodeOptions::default(),
+            options: DecodeOptions::default(),
+            profiles: OptionProfiles::default(),
+            profile_language: None,
+            text_processor: TextPostProcessor::default(),
+            clock: Box::new(SystemClock),
+            logger,
+            timings: None,
+            warmup: None,
+            diagnostics: None,
+            encoder_cache: EncoderCache::default(),
+            encoder_forwards: 0,
+            realtime_factor: None,
+            run_deadline_ms: None,
+            translation_prompt: prompt.clone(),
+            translating: false,
+            speaker_changes: SpeakerChangeTracker::default(),
+            prompt,
+            prefix_index,
+            logits_processor: None,
+            on_partial: None,
+            window_start: 0.,
+            window_audio: m::CHUNK_LENGTH as f64,
+            suppress_initial_tokens: suppress_tokens.clone(),
             suppress_tokens,
-            sot_token,
-            transcribe_token,
-            translate_token,
-            eot_token,
-            no_speech_token,
-            no_timestamps_token,
-        })
+            blank_tokens,
+            synthesized_suppress_tokens,
+            booster: PhraseBooster::default(),
+            special_tokens,
+        };
+        decoder.update_suppress_tokens()?;
+        Ok(decoder)
     }
 
-    fn decode(&mut self, mel: &Tensor, t: f64) -> anyhow::Result<DecodingResult> {
-        let model = &mut self.model;
-        let language_token = match (self.is_multilingual, &self.language) {
-            (true, None) => Some(detect(model, &self.tokenizer, mel)?),
-            (false, None) => None,
-            (true, Some(language)) => {
-                match token_id(&self.tokenizer, &format!("<|{:?}|>", self.language)) {
-                    Ok(token_id) => Some(token_id),
-                    Err(_) => anyhow::bail!("language {language} is not supported"),
+    /// Rebuilds the suppression masks from the model config and the current options.
+    fn update_suppress_tokens(&mut self) -> candle_core::Result<()> {
+        let config = self.model.config();
+        let device = &self.device;
+        let config_suppress: &[u32] = if !config.suppress_tokens.is_empty() {
+            log_at!(
+                self.logger,
+                Debug,
+                "suppressing the {} tokens of the model config",
+                config.suppress_tokens.len()
+            );
+            &config.suppress_tokens
+        } else if self.options.synthesize_suppress_list {
+            log_at!(
+                self.logger,
+                Debug,
+                "suppressing the {} synthesized tokens",
+                self.synthesized_suppress_tokens.len()
+            );
+            &self.synthesized_suppress_tokens
+        } else {
+            log_at!(
+                self.logger,
+                Debug,
+                "the model config has no suppress_tokens, none suppressed"
+            );
+            &[]
+        };
+        let mask = build_suppression_mask(
+            config.vocab_size,
+            config_suppress,
+            &self.options.extra_suppress_tokens,
+            &self.options.unsuppress_tokens,
+        );
+        self.suppress_tokens = Tensor::new(mask.as_slice(), device)?;
+        self.suppress_initial_tokens = if self.options.suppress_blank {
+            let mut blank = self.blank_tokens.clone();
+            blank.push(self.special_tokens.eot);
+            let mut mask = mask;
+            for token in blank {
+                if let Some(v) = mask.get_mut(token as usize) {
+                    *v = f32::NEG_INFINITY
                 }
             }
-            (false, Some(_)) => {
-                anyhow::bail!("a language cannot be set for non-multilingual models")
-            }
+            Tensor::new(mask.as_slice(), device)?
+        } else {
+            self.suppress_tokens.clone()
         };
+        Ok(())
+    }
 
-        let audio_features = model.encoder_forward(mel, true)?;
-        let sample_len = model.config().max_target_positions / 2;
-        let mut sum_logprob = 0f64;
-        let mut no_speech_prob = f64::NAN;
-        let mut tokens = vec![self.sot_token];
-        if let Some(language_token) = language_token {
-            tokens.push(language_token);
-        }
-        match self.task {
-            None | Some(Task::Transcribe) => tokens.push(self.transcribe_token),
-            Some(Task::Translate) => tokens.push(self.translate_token),
-        }
-        if !self.timestamps {
-            tokens.push(self.no_timestamps_token);
+    /// Language set in the options or at load time.
+    fn pinned_language(&self) -> Option<String> {
+        match &self.options.language_detection {
+            LanguageDetectionMode::Pinned(language) => Some(language.clone()),
+            _ => self.language.clone(),
         }
-        for i in 0..sample_len {
-            let tokens_t = Tensor::new(tokens.as_slice(), mel.device())?;
-            let tokens_t = tokens_t.unsqueeze(0)?;
-            let ys = model.decoder_forward(&tokens_t, &audio_features, i == 0)?;
+    }
 
-            if i == 0 {
-                let logits = model.decoder_final_linear(&ys.i(..1)?)?.i(0)?.i(0)?;
-                no_speech_prob = softmax(&logits, 0)?
-                    .i(self.no_speech_token as usize)?
-                    .to_scalar::<f32>()? as f64;
+    /// Language token and code used to decode a window, detecting it when needed from the
+    /// encoder output of the window so that the encoder runs once per window.
+    fn language(&mut self, audio_features: &Tensor) -> anyhow::Result<Option<(u32, String)>> {
+        match (self.is_multilingual, self.pinned_language()) {
+            (true, None) => {
+                let allowed = self.options.allowed_languages.as_deref();
+                let detection = match (&self.options.language_detection, &self.detected_language) {
+                    (LanguageDetectionMode::Once, Some(detection)) => detection.clone(),
+                    _ => detect_from_features(
+                        &mut self.model,
+                        &self.tokenizer,
+                        audio_features,
+                        allowed,
+                    )?,
+                };
+                log_at!(
+                    self.logger,
+                    Debug,
+                    "language: {} <|{}|>",
+                    detection.token,
+                    detection.language
+                );
+                let language = Some((detection.token, detection.language.clone()));
+                if self.detected_language.is_none() {
+                    self.detected_language = Some(detection);
+                }
+                Ok(language)
             }
+            (false, None) => Ok(None),
+            (true, Some(language)) => match token_id(&self.tokenizer, &format!("<|{language}|>")) {
+                Ok(token_id) => Ok(Some((token_id, language))),
+                Err(_) => Err(WhisperError::LanguageNotSupported { lang: language }.into()),
+            },
+            (false, Some(language)) if language == "en" => Ok(None),
+            (false, Some(_)) => Err(WhisperError::InvalidConfig {
+                reason: "a language cannot be set for non-multilingual models".to_string(),
+            }
+            .into()),
+        }
+    }
 
-            let (_, seq_len, _) = ys.dims3()?;
-            let logits = model
-                .decoder_final_linear(&ys.i((..1, seq_len - 1..))?)?
-                .i(0)?
-                .i(0)?;
-
-            let logits = logits.broadcast_add(&self.suppress_tokens)?;
-            let next_token = if t > 0f64 {
-                let prs = softmax(&(&logits / t)?, 0)?;
-                let logits_v: Vec<f32> = prs.to_vec1()?;
-                let distr = rand::distributions::WeightedIndex::new(&logits_v)?;
-                distr.sample(&mut self.rng) as u32
-            } else {
-                let logits_v: Vec<f32> = logits.to_vec1()?;
-                logits_v
+    async fn decode(
+        &mut self,
+        audio_features: &Tensor,
+        language_token: Option<u32>,
+        t: f64,
+        yielder: &mut dyn Yielder,
+    ) -> anyhow::Result<DecodingResult> {
+        let decode_start = self.timer();
+        let (prefix, healing) = self.healed_prompt_prefix();
+        let eot = self.special_tokens.eot;
+        let mut tokens = self.sot_sequence(language_token);
+        let model = &mut self.model;
+        let sample_len = model.config().max_target_positions / 2;
+        let sample_len = self
+            .options
+            .max_tokens_per_segment
+            .map_or(sample_len, |max| usize::min(max, sample_len));
+        let segment_deadline_ms = self
+            .options
+            .max_decode_seconds_per_segment
+            .map(|seconds| self.clock.now_ms() + seconds * 1000.);
+        let mut truncation_reason = Some(TruncationReason::MaxTokens);
+        let mut sampling_fallbacks = 0;
+        let mut sum_logprob = 0f64;
+        let mut no_speech_prob = f64::NAN;
+        let prompt_len = tokens.len();
+        let mut token_probs = vec![1.; prompt_len];
+        let sot_index = prefix.len();
+        let timestamp_begin = self.special_tokens.timestamp_begin;
+        let initial_timestamp_mask = match build_initial_timestamp_mask(
+            model.config().vocab_size,
+            timestamp_begin,
+            self.options.max_initial_timestamp,
+            self.window_audio,
+        ) {
+            Some(mask) if self.run_timestamps => Some(Tensor::new(mask.as_slice(), &self.device)?),
+            _ => None,
+        };
+        // The timestamp tokens are sampled as the others, only the sampling and the hook need
+        // the logits on the host.
+        let fast_path = t <= 0f64
+            && self.logits_processor.is_none()
+            && healing.is_none()
+            && self.booster.is_empty()
+            && self.options.greedy_fast_path;
+        for i in 0..sample_len {
+            if i > 0 && i.is_multiple_of(self.options.yield_interval_tokens) {
+                yielder.yield_now().await;
+            }
+            let deadlines = [
+                (segment_deadline_ms, TruncationReason::SegmentTime),
+                (self.run_deadline_ms, TruncationReason::TotalTime),
+            ];
+            if i % BUDGET_CHECK_INTERVAL == 0 && deadlines.iter().any(|(d, _)| d.is_some()) {
+                let now = self.clock.now_ms();
+                let exceeded = deadlines
                     .iter()
-                    .enumerate()
-                    .max_by(|(_, u), (_, v)| u.total_cmp(v))
-                    .map(|(i, _)| i as u32)
-                    .unwrap()
+                    .find(|(deadline, _)| deadline.is_some_and(|deadline| now > deadline));
+                if let Some((_, reason)) = exceeded {
+                    truncation_reason = Some(*reason);
+                    break;
+                }
+            }
+            let mut step = || -> anyhow::Result<Option<(u32, f64)>> {
+                let input: Vec<u32> = prefix.iter().chain(&tokens).copied().collect();
+                let tokens_t = Tensor::new(input.as_slice(), &self.device)?;
+                let tokens_t = tokens_t.unsqueeze(0)?;
+                let ys = model.decoder_forward(&tokens_t, audio_features, i == 0)?;
+
+                if i == 0 {
+                    no_speech_prob = no_speech_probability(
+                        model,
+                        &ys,
+                        sot_index,
+                        self.special_tokens.no_speech,
+                    )?;
+                }
+
+                let (_, seq_len, _) = ys.dims3()?;
+                let raw_logits = model
+                    .decoder_final_linear(&ys.i((..1, seq_len - 1..))?)?
+                    .i(0)?
+                    .i(0)?;
+
+                let suppress_tokens = if i == 0 {
+                    &self.suppress_initial_tokens
+                } else {
+                    &self.suppress_tokens
+                };
+                let logits = raw_logits.broadcast_add(suppress_tokens)?;
+                // Until the first timestamp is sampled.
+                let logits = match &initial_timestamp_mask {
+                    Some(mask) if tokens[prompt_len..].iter().all(|&t| t < timestamp_begin) => {
+                        logits.broadcast_add(mask)?
+                    }
+                    _ => logits,
+                };
+                if fast_path {
+                    let next_token = logits.argmax(0)?.to_scalar::<u32>()?;
+                    let logprob = log_softmax(&logits, 0)?
+                        .i(next_token as usize)?
+                        .to_scalar::<f32>()?;
+                    // A NaN or +inf logit makes the log probability of the argmax NaN.
+                    if !logprob.is_finite() {
+                        return Ok(None);
+                    }
+                    return Ok(Some((next_token, (logprob as f64).exp())));
+                }
+                // The sampling works on the host so that the hook needs no extra copy.
+                let mut logits_v: Vec<f32> = logits.to_vec1()?;
+                self.booster.apply(&tokens[prompt_len..], &mut logits_v);
+                if let Some(candidates) = healing.as_deref() {
+                    if tokens[prompt_len..].iter().all(|&token| token >= eot) {
+                        restrict_text_tokens(&mut logits_v, candidates, eot);
+                    }
+                }
+                if let Some(processor) = self.logits_processor.as_mut() {
+                    let context = LogitsContext {
+                        tokens: &tokens[prompt_len..],
+                        step: i,
+                        segment_start: self.window_start,
+                        timestamps: self.run_timestamps,
+                    };
+                    processor(&mut logits_v, &context);
+                }
+                // Suppressed tokens are at -inf, NaN or +inf can only come from the model.
+                if logits_v.iter().any(|v| v.is_nan() || *v == f32::INFINITY) {
+                    return Ok(None);
+                }
+                let max = logits_v.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let next_token = if t > 0f64 {
+                    let mut weights: Vec<f64> = logits_v
+                        .iter()
+                        .map(|&v| ((v - max) as f64 / t).exp())
+                        .collect();
+                    let (top_k, top_p) = (self.options.top_k, self.options.top_p);
+                    match sample_weights(&mut weights, top_k, top_p, &mut self.rng) {
+                        Some(token) => token as u32,
+                        // Every token is suppressed, the probabilities are those of the model.
+                        None => {
+                            log_at!(self.logger, Debug, "nothing to sample at {t}, step {i}");
+                            sampling_fallbacks += 1;
+                            logits_v = raw_logits.to_vec1()?;
+                            argmax(&logits_v)
+                        }
+                    }
+                } else {
+                    argmax(&logits_v)
+                };
+                let max = logits_v.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let sum: f64 = logits_v.iter().map(|&v| ((v - max) as f64).exp()).sum();
+                let prob = ((logits_v[next_token as usize] - max) as f64).exp() / sum;
+                Ok(Some((next_token, prob)))
+            };
+            let (next_token, prob) = match step() {
+                Ok(Some(sampled)) => sampled,
+                result => {
+                    return Err(StepFailure {
+                        step: i,
+                        temperature: t,
+                        tokens: tokens[prompt_len..].to_vec(),
+                        source: result.err(),
+                    }
+                    .into())
+                }
             };
             tokens.push(next_token);
-            let prob = softmax(&logits, candle_core::D::Minus1)?
-                .i(next_token as usize)?
-                .to_scalar::<f32>()? as f64;
-            if next_token == self.eot_token || tokens.len() > model.config().max_target_positions {
+            token_probs.push(prob as f32);
+            let sampled = &tokens[prompt_len..];
+            if let Some(on_partial) = self.on_partial.as_mut().filter(|_| {
+                next_token != eot
+                    && sampled
+                        .len()
+                        .is_multiple_of(self.options.partial_interval_tokens)
+            }) {
+                on_partial(PartialHypothesis {
+                    segment_start: self.window_start,
+                    tokens: sampled.to_vec(),
+                    text: complete_text(&self.tokenizer, sampled, eot)?,
+                    step: sampled.len(),
+                    temperature: t,
+                });
+            }
+            if next_token == self.special_tokens.eot {
+                truncation_reason = None;
+                break;
+            }
+            if prefix.len() + tokens.len() > model.config().max_target_positions {
                 break;
             }
             sum_logprob += prob.ln();
         }
-        let text = self.tokenizer.decode(&tokens, true).map_err(E::msg)?;
+        let decode_ms = self.elapsed_ms(decode_start);
+        if let Some(timings) = self.timings.as_mut() {
+            timings.add_decode(fast_path, tokens.len() - prompt_len, decode_ms);
+        }
+        let TokenText {
+            text,
+            invalid_tokens,
+        } = self.token_text(&tokens);
         let avg_logprob = sum_logprob / tokens.len() as f64;
+        let score = sequence_score(
+            sum_logprob,
+            tokens.len() - prompt_len,
+            self.options.length_penalty,
+        );
+        if let Some(reason) = truncation_reason {
+            log_at!(self.logger, Debug, "decoding at {t} truncated: {reason:?}");
+        }
+        let compression_ratio = compression_ratio(&text);
 
         Ok(DecodingResult {
             tokens,
+            text_clean: self.text_processor.process(&text),
             text,
             avg_logprob,
+            sum_logprob,
+            score,
             no_speech_prob,
             temperature: t,
-            compression_ratio: f64::NAN,
+            compression_ratio,
+            attempts: 1,
+            truncated: truncation_reason.is_some(),
+            truncation_reason,
+            token_probs,
+            had_decode_errors: !invalid_tokens.is_empty(),
+            decode_error_tokens: invalid_tokens,
+            sampling_fallbacks,
         })
     }
 
-    fn decode_with_fallback(&mut self, segment: &Tensor) -> anyhow::Result<DecodingResult> {
-        for (i, &t) in m::TEMPERATURES.iter().enumerate() {
-            let dr: Result<DecodingResult, _> = self.decode(segment, t);
-            if i == m::TEMPERATURES.len() - 1 {
-                return dr;
+    /// Text of `tokens`, with the bytes that are not valid text replaced rather than failing,
+    /// see [`decode_tokens`].
+    fn token_text(&self, tokens: &[u32]) -> TokenText {
+        let token_text = decode_tokens(
+            &self.tokenizer,
+            self.prefix_index.as_ref(),
+            tokens,
+            self.special_tokens.eot,
+        );
+        if !token_text.invalid_tokens.is_empty() {
+            log_at!(
+                self.logger,
+                Warn,
+                "tokens {:?} do not decode to valid text",
+                token_text.invalid_tokens
+            );
+        }
+        token_text
+    }
+
+    /// Tokens preceding the sampled ones: `<|startoftranscript|>`, the language and task
+    /// tokens and `<|notimestamps|>` without timestamps.
+    fn sot_sequence(&self, language_token: Option<u32>) -> Vec<u32> {
+        let mut tokens = vec![self.special_tokens.sot];
+        tokens.extend(language_token);
+        // As in openai/whisper, English-only models are prompted without a task token.
+        if self.is_multilingual {
+            tokens.extend(match self.task {
+                Some(Task::Translate) => self.special_tokens.translate,
+                Some(Task::Both) if self.translating => self.special_tokens.translate,
+                None | Some(Task::Transcribe | Task::Both) => self.special_tokens.transcribe,
+            });
+        }
+        if !self.run_timestamps {
+            tokens.push(self.special_tokens.no_timestamps);
+        }
+        tokens
+    }
+
+    /// Decodes the encoded window at each of `temperatures`.
+    ///
+    /// With `fallback` the passes stop at the first result accepted by the thresholds, the
+    /// result of the last temperature being always accepted, and only that result is returned.
+    /// Otherwise the results of all the passes that succeeded are returned.
+    async fn run_passes(
+        &mut self,
+        audio_features: &Tensor,
+        language_token: Option<u32>,
+        temperatures: &[f64],
+        fallback: bool,
+        sampled_tokens: &mut usize,
+        yielder: &mut dyn Yielder,
+    ) -> anyhow::Result<Vec<DecodingResult>> {
+        let mut results = vec![];
+        for (i, &t) in temperatures.iter().enumerate() {
+            let last = i == temperatures.len() - 1;
+            let result = self
+                .decode(audio_features, language_token, t, &mut *yielder)
+                .await;
+            if fallback {
+                self.record_attempt(&result, t, language_token, last);
             }
-            match dr {
+            match result {
                 Ok(dr) => {
-                    let needs_fallback = dr.compression_ratio > m::COMPRESSION_RATIO_THRESHOLD
-                        || dr.avg_logprob < m::LOGPROB_THRESHOLD;
-                    if !needs_fallback || dr.no_speech_prob > m::NO_SPEECH_THRESHOLD {
-                        return Ok(dr);
+                    *sampled_tokens += dr.tokens.len() - self.sot_sequence(language_token).len();
+                    if !fallback {
+                        results.push(dr);
+                    } else if last || self.options.fallback_reasons(&dr).is_empty() {
+                        return Ok(vec![DecodingResult {
+                            attempts: i + 1,
+                            ..dr
+                        }]);
                     }
                 }
-                Err(err) => {
-                    console_log!("[RUST]: Error running at {t}: {err}")
+                Err(err) if fallback && last => return Err(err),
+                // Sampling at a higher temperature does not make the logits finite.
+                Err(err)
+                    if err
+                        .downcast_ref::<StepFailure>()
+                        .is_some_and(|failure| failure.source.is_none()) =>
+                {
+                    return Err(err)
                 }
+                Err(err) => log_at!(self.logger, Warn, "error running at {t}: {err}"),
             }
         }
-        unreachable!()
+        if fallback {
+            Err(anyhow::anyhow!("the temperature schedule is empty"))
+        } else {
+            Ok(results)
+        }
     }
 
-    fn run(&mut self, mel: &Tensor) -> anyhow::Result<Vec<Segment>> {
-        let (_, _, content_frames) = mel.dims3()?;
-        let mut seek = 0;
-        let mut segments = vec![];
-        while seek < content_frames {
-            let time_offset = (seek * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
-            let segment_size = usize::min(content_frames - seek, m::N_FRAMES);
-            let mel_segment = mel.narrow(2, seek, segment_size)?;
-            let segment_duration = (segment_size * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
-            let dr = self.decode_with_fallback(&mel_segment)?;
-            seek += segment_size;
-            if dr.no_speech_prob > m::NO_SPEECH_THRESHOLD && dr.avg_logprob < m::LOGPROB_THRESHOLD {
-                console_log!("[RUST]: skipping {seek} {dr:?}");
-                continue;
+    /// Adds a decoding attempt of the temperature schedule to the diagnostics of the window.
+    fn record_attempt(
+        &mut self,
+        result: &anyhow::Result<DecodingResult>,
+        temperature: f64,
+        language_token: Option<u32>,
+        last: bool,
+    ) {
+        // The diagnostics follow the transcription.
+        if self.diagnostics.is_none() || self.translating {
+            return;
+        }
+        let attempt = match result {
+            Ok(dr) => {
+                let fallback_reasons = self.options.fallback_reasons(dr);
+                AttemptDiagnostics {
+                    temperature,
+                    decode_steps: dr.tokens.len() - self.sot_sequence(language_token).len(),
+                    avg_logprob: dr.avg_logprob,
+                    no_speech_prob: dr.no_speech_prob,
+                    compression_ratio: dr.compression_ratio,
+                    accepted: last || fallback_reasons.is_empty(),
+                    fallback_reasons,
+                    sampling_fallbacks: dr.sampling_fallbacks,
+                    error: None,
+                }
+            }
+            Err(err) => AttemptDiagnostics {
+                temperature,
+                decode_steps: err
+                    .downcast_ref::<StepFailure>()
+                    .map_or(0, |failure| failure.step),
+                avg_logprob: f64::NAN,
+                no_speech_prob: f64::NAN,
+                compression_ratio: f64::NAN,
+                fallback_reasons: vec![],
+                accepted: false,
+                sampling_fallbacks: 0,
+                error: Some(format!("{err:#}")),
+            },
+        };
+        if let Some(window) = self.window_diagnostics() {
+            window.attempts.push(attempt);
+        }
+    }
+
+    /// Diagnostics of the window being decoded, when collected.
+    fn window_diagnostics(&mut self) -> Option<&mut WindowDiagnostics> {
+        self.diagnostics.as_mut()?.windows.last_mut()
+    }
+
+    /// Completes the diagnostics of the window being decoded with its outcome and kept result.
+    fn end_window_diagnostics(
+        &mut self,
+        outcome: WindowOutcome,
+        dr: Option<&DecodingResult>,
+        error: Option<String>,
+    ) {
+        if let Some(window) = self.window_diagnostics() {
+            window.outcome = outcome;
+            window.fallback = window.attempts.iter().any(|attempt| !attempt.accepted);
+            if let Some(dr) = dr {
+                window.no_speech_prob = dr.no_speech_prob;
+                window.avg_logprob = dr.avg_logprob;
+            }
+            window.error = error;
+        }
+    }
+
+    /// Runs the additional passes of the high accuracy mode and votes their tokens with the
+    /// accepted result, windows treated as silence are returned as is.
+    async fn vote_passes(
+        &mut self,
+        audio_features: &Tensor,
+        language_token: Option<u32>,
+        dr: DecodingResult,
+        sampled_tokens: &mut usize,
+        yielder: &mut dyn Yielder,
+    ) -> anyhow::Result<(DecodingResult, Vec<LowAgreementSpan>)> {
+        let high_accuracy = match &self.options.high_accuracy {
+            Some(high_accuracy) if !self.options.is_silence(&dr) => high_accuracy.clone(),
+            _ => return Ok((dr, vec![])),
+        };
+        let passes = self
+            .run_passes(
+                audio_features,
+                language_token,
+                &high_accuracy.temperatures,
+                false,
+                sampled_tokens,
+                yielder,
+            )
+            .await?;
+        if passes.is_empty() {
+            return Ok((dr, vec![]));
+        }
+        // The best scoring result is the reference of the vote, winning its ties, the accepted
+        // one on equal scores.
+        let attempts = dr.attempts;
+        let mut candidates: Vec<DecodingResult> = std::iter::once(dr).chain(passes).collect();
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let tokens: Vec<&[u32]> = candidates.iter().map(|c| c.tokens.as_slice()).collect();
+        let consensus = vote(&tokens);
+        let dr = DecodingResult {
+            attempts,
+            ..candidates.swap_remove(0)
+        };
+        let mut spans = vec![];
+        for (range, agreement) in consensus.low_agreement(high_accuracy.min_agreement) {
+            let text = self
+                .tokenizer
+                .decode(&consensus.tokens[range.clone()], true)
+                .map_err(E::msg)?;
+            // Disagreements on the timestamps only are not reported.
+            if !text.trim().is_empty() {
+                spans.push(LowAgreementSpan {
+                    start_token: range.start,
+                    end_token: range.end,
+                    text,
+                    agreement,
+                })
+            }
+        }
+        let TokenText {
+            text,
+            invalid_tokens,
+        } = self.token_text(&consensus.tokens);
+        let dr = DecodingResult {
+            tokens: consensus.tokens,
+            text_clean: self.text_processor.process(&text),
+            text,
+            token_probs: vec![],
+            had_decode_errors: !invalid_tokens.is_empty(),
+            decode_error_tokens: invalid_tokens,
+            ..dr
+        };
+        Ok((dr, spans))
+    }
+
+    /// Mel frames of a window of `window_frames` given to the encoder, fewer with
+    /// `EncoderInput::Truncate`.
+    fn truncated_window_frames(&self, window_frames: usize) -> usize {
+        match self.options.encoder_input {
+            EncoderInput::Truncate(seconds) => {
+                let frames = seconds * m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64;
+                usize::min(window_frames, frames as usize)
+            }
+            EncoderInput::Exact | EncoderInput::PadToFull => window_frames,
+        }
+    }
+
+    /// Input of the encoder for the `frames` mel frames of `mel` from `seek`.
+    fn encoder_window(
+        &self,
+        mel: &Tensor,
+        seek: usize,
+        frames: usize,
+    ) -> candle_core::Result<Tensor> {
+        match self.options.encoder_input {
+            EncoderInput::PadToFull => padded_window(mel, seek, frames),
+            EncoderInput::Exact | EncoderInput::Truncate(_) => mel.narrow(2, seek, frames),
+        }
+    }
+
+    fn encoder_forward(&mut self, mel: &Tensor) -> candle_core::Result<Tensor> {
+        self.encoder_forwards += 1;
+        self.model.encoder_forward(mel, true)
+    }
+
+    /// Encoder output of a mel window, from the encoder cache when enabled.
+    fn encode(&mut self, mel_segment: &Tensor) -> anyhow::Result<Tensor> {
+        if !self.encoder_cache.is_enabled() {
+            return Ok(self.encoder_forward(mel_segment)?);
+        }
+        let mel: Vec<f32> = mel_segment.flatten_all()?.to_dtype(DType::F32)?.to_vec1()?;
+        let info = &self.model_info;
+        let model = (
+            info.tensor_count,
+            info.parameter_bytes,
+            &info.quantization,
+            self.dtype.as_str(),
+        );
+        let key = EncoderCache::key(&mel, model);
+        if let Some(audio_features) = self.encoder_cache.get(key) {
+            return Ok(audio_features);
+        }
+        let audio_features = self.encoder_forward(mel_segment)?;
+        self.encoder_cache.insert(key, audio_features.clone());
+        Ok(audio_features)
+    }
+
+    /// Decodes a window, the encoder running once for all the passes.
+    async fn decode_with_fallback(
+        &mut self,
+        segment: &Tensor,
+        yielder: &mut dyn Yielder,
+    ) -> anyhow::Result<DecodedWindow> {
+        let encoder_start = self.timer();
+        let audio_features = self.encode(segment)?;
+        let encoder_ms = self.elapsed_ms(encoder_start);
+        let second_embeddings = if self.options.speaker_change_hints {
+            speaker_change::second_embeddings(&audio_features)?
+        } else {
+            vec![]
+        };
+        let (language_token, language) = self.language(&audio_features)?.unzip();
+        // The English-only models use the profile of English.
+        let profile_language = match &language {
+            None if !self.is_multilingual => Some("en"),
+            language => language.as_deref(),
+        };
+        self.apply_language_profile(profile_language)?;
+        if let Some(window) = self.diagnostics.as_mut().and_then(|d| d.windows.last_mut()) {
+            window.language.clone_from(&language);
+            window.no_speech_threshold = self.options.no_speech_threshold;
+            window.logprob_threshold = self.options.logprob_threshold;
+            window.compression_ratio_threshold = self.options.compression_ratio_threshold;
+        }
+        let decode_start = self.timer();
+        let mut sampled_tokens = 0;
+        let temperatures = self.options.temperatures.clone();
+        let result = match self
+            .run_passes(
+                &audio_features,
+                language_token,
+                &temperatures,
+                true,
+                &mut sampled_tokens,
+                &mut *yielder,
+            )
+            .await
+        {
+            Ok(mut passes) => {
+                let dr = passes.remove(0);
+                self.vote_passes(
+                    &audio_features,
+                    language_token,
+                    dr,
+                    &mut sampled_tokens,
+                    &mut *yielder,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        let translation = match self.task {
+            Some(Task::Both) if result.is_ok() => Some(
+                self.translate_window(
+                    &audio_features,
+                    language_token,
+                    &mut sampled_tokens,
+                    yielder,
+                )
+                .await,
+            ),
+            _ => None,
+        };
+        let decode_ms = self.elapsed_ms(decode_start);
+        if let Some(timings) = self.timings.as_mut() {
+            timings.add_window(encoder_ms, decode_ms, sampled_tokens)
+        }
+        let (dr, low_agreement_spans) = result?;
+        let translation = translation.transpose()?;
+        let words = match &self.alignment {
+            Some(alignment) if self.options.word_timestamps && !self.options.is_silence(&dr) => {
+                alignment.word_timings(
+                    &self.tokenizer,
+                    &self.special_tokens,
+                    &dr.tokens,
+                    &dr.token_probs,
+                    &audio_features,
+                    segment.dim(2)?,
+                )?
+            }
+            // Without cross-attention weights the times are estimated from the tokens.
+            None if self.options.word_timestamps && !self.options.is_silence(&dr) => {
+                alignment::heuristic_word_timings(
+                    &self.tokenizer,
+                    &self.special_tokens,
+                    &dr.tokens,
+                    &dr.token_probs,
+                    (segment.dim(2)? * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64,
+                )?
+            }
+            _ => vec![],
+        };
+        Ok(DecodedWindow {
+            dr,
+            language,
+            low_agreement_spans,
+            words,
+            translation,
+            second_embeddings,
+        })
+    }
+
+    /// Translation pass of `Task::Both` over the encoder output of a window, with its own
+    /// temperature fallback and previous text. The language is the one the window was
+    /// transcribed in, detected from the same encoder output.
+    async fn translate_window(
+        &mut self,
+        audio_features: &Tensor,
+        language_token: Option<u32>,
+        sampled_tokens: &mut usize,
+        yielder: &mut dyn Yielder,
+    ) -> anyhow::Result<DecodingResult> {
+        let temperatures = self.options.temperatures.clone();
+        std::mem::swap(&mut self.prompt, &mut self.translation_prompt);
+        self.translating = true;
+        let result = self
+            .run_passes(
+                audio_features,
+                language_token,
+                &temperatures,
+                true,
+                sampled_tokens,
+                yielder,
+            )
+            .await;
+        self.translating = false;
+        std::mem::swap(&mut self.prompt, &mut self.translation_prompt);
+        Ok(result?.remove(0))
+    }
+
+    /// Chunks of the chunked long-form strategy, when it is enabled or detected.
+    fn chunking(&self) -> Option<ChunkOptions> {
+        let enabled = self
+            .options
+            .chunked_long_form
+            .unwrap_or_else(|| chunked::is_distilled(self.model.config()));
+        enabled.then(|| self.options.chunking.clone())
+    }
+
+    fn max_prompt_tokens(&self) -> usize {
+        let max = self.model.config().max_target_positions / 2 - 1;
+        self.options
+            .max_prompt_tokens
+            .map_or(max, |tokens| usize::min(tokens, max))
+    }
+
+    /// Previous text the windows are prompted with, `<|startofprev|>` included. Empty when
+    /// not conditioning on the previous text.
+    fn prompt_prefix(&self) -> Vec<u32> {
+        let start_of_prev = match self.special_tokens.start_of_prev {
+            Some(token) if self.options.condition_on_previous_text => token,
+            _ => return vec![],
+        };
+        if self.prompt.is_empty() || self.chunking().is_some() {
+            return vec![];
+        }
+        let mut prefix = vec![start_of_prev];
+        prefix.extend_from_slice(self.prompt.as_prompt_tokens());
+        prefix
+    }
+
+    /// [`Decoder::prompt_prefix`] healed when the previous text ends inside a word, with the
+    /// tokens the first text token is then restricted to, see `DecodeOptions::token_healing`.
+    fn healed_prompt_prefix(&self) -> (Vec<u32>, Option<Vec<u32>>) {
+        let mut prefix = self.prompt_prefix();
+        let (index, &last) = match (&self.prefix_index, prefix.last()) {
+            (Some(index), Some(last)) if self.options.token_healing && prefix.len() > 1 => {
+                (index, last)
             }
-            let segment = Segment {
+            _ => return (prefix, None),
+        };
+        if !index.ends_inside_word(last) {
+            return (prefix, None);
+        }
+        let candidates = index.tokens_with_prefix(index.token_bytes(last)).to_vec();
+        prefix.pop();
+        // Only `<|startofprev|>` is left.
+        if prefix.len() == 1 {
+            prefix.clear();
+        }
+        (prefix, Some(candidates))
+    }
+
+    /// Current time when collecting timings.
+    fn timer(&self) -> Option<f64> {
+        self.options.collect_timings.then(|| self.clock.now_ms())
+    }
+
+    fn elapsed_ms(&self, start: Option<f64>) -> f64 {
+        start.map_or(0., |start| self.clock.now_ms() - start)
+    }
+
+    /// Decodes the window of `mel` starting at `state.seek`, returns `false` once the whole
+    /// spectrogram has been consumed.
+    async fn run_window(
+        &mut self,
+        mel: &Tensor,
+        speech_regions: Option<&[(f64, f64)]>,
+        state: &mut RunState,
+        yielder: &mut dyn Yielder,
+    ) -> anyhow::Result<bool> {
+        let (_, _, content_frames) = mel.dims3()?;
+        let RunState {
+            seek,
+            segments,
+            failures,
+            audio_end,
+        } = state;
+        let audio_end = *audio_end;
+        // The windows starting in the padding would only be filled with hallucinations.
+        let audio_frames = (audio_end * m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64).ceil();
+        let end_frame = usize::min(content_frames, audio_frames as usize);
+        if *seek >= end_frame {
+            return Ok(false);
+        }
+        let mut time_offset = (*seek * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+        let window_seek = *seek;
+        let chunking = self.chunking();
+        let window_frames = chunking.as_ref().map_or(m::N_FRAMES, |chunking| {
+            (chunking.chunk_seconds * m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64) as usize
+        });
+        let window_frames = self.truncated_window_frames(window_frames);
+        let segment_size = usize::min(content_frames - *seek, window_frames);
+        let mel_segment = self.encoder_window(mel, *seek, segment_size)?;
+        let segment_duration = (segment_size * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+        let window_end = time_offset + segment_duration;
+        if let Some(diagnostics) = self.diagnostics.as_mut() {
+            diagnostics.windows.push(WindowDiagnostics {
                 start: time_offset,
-                duration: segment_duration,
+                seek_frame: window_seek,
+                encoder_frames: mel_segment.dim(2)?,
+                no_speech_prob: f64::NAN,
+                avg_logprob: f64::NAN,
+                ..Default::default()
+            });
+        }
+        if let Some(regions) = speech_regions.filter(|_| self.options.use_vad) {
+            let segment_end = time_offset + segment_duration;
+            let has_speech = regions
+                .iter()
+                .any(|&(start, end)| start < segment_end && end > time_offset);
+            if !has_speech {
+                self.end_window_diagnostics(WindowOutcome::NoSpeechRegion, None, None);
+                *seek += segment_size;
+                clip_overlap(segments, time_offset);
+                segments.push(Segment {
+                    no_speech: true,
+                    ..Segment::new(time_offset, segment_duration, DecodingResult::no_speech())
+                });
+                return Ok(true);
+            }
+        }
+        let prompt_tokens = self.healed_prompt_prefix().0.len().saturating_sub(1);
+        self.window_start = time_offset;
+        self.window_audio = f64::min(segment_duration, audio_end - time_offset);
+        let DecodedWindow {
+            mut dr,
+            mut language,
+            mut low_agreement_spans,
+            mut words,
+            mut translation,
+            mut second_embeddings,
+        } = match self.decode_with_fallback(&mel_segment, &mut *yielder).await {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                let context = DecodeContext {
+                    segment_start: time_offset,
+                    seek_frame: *seek,
+                    quantized: matches!(self.model, Model::Quantized(_)),
+                    ..Default::default()
+                };
+                let err = WhisperError::decode(context, err);
+                self.end_window_diagnostics(WindowOutcome::Failed, None, Some(err.to_string()));
+                let policy = self.options.on_segment_error;
+                if policy == SegmentErrorPolicy::Abort {
+                    return Err(err.into());
+                }
+                log_at!(
+                    self.logger,
+                    Warn,
+                    "failed to decode segment at {time_offset}: {err}"
+                );
+                *seek += segment_size;
+                if policy == SegmentErrorPolicy::InsertPlaceholder {
+                    clip_overlap(segments, time_offset);
+                    segments.push(Segment {
+                        error: Some(err.to_string()),
+                        ..Segment::new(time_offset, segment_duration, DecodingResult::no_speech())
+                    });
+                }
+                failures.push(SegmentFailure {
+                    start: time_offset,
+                    duration: segment_duration,
+                    error: err.to_string(),
+                    code: err.code().to_string(),
+                    context: err.decode_context().cloned(),
+                });
+                return Ok(true);
+            }
+        };
+        // The chunks do not resume from the timestamps, they are merged on their tokens.
+        let consumed = if self.run_timestamps && chunking.is_none() {
+            timestamp_seek_advance(
+                &dr.tokens,
+                self.special_tokens.timestamp_begin,
+                self.special_tokens.eot,
+                segment_size,
+            )
+        } else {
+            segment_size
+        };
+        // Without a timestamp to resume from, the next window overlaps the end of this one.
+        let overlap_seconds = chunking
+            .as_ref()
+            .map_or(self.options.overlap_seconds, |chunking| {
+                chunking.overlap_seconds
+            });
+        let overlap_frames =
+            (overlap_seconds * m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64) as usize;
+        let overlapping =
+            overlap_frames > 0 && consumed == segment_size && *seek + segment_size < end_frame;
+        let mut segment_duration = (consumed * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+        *seek += if overlapping {
+            segment_size - overlap_frames
+        } else {
+            consumed
+        };
+        if self.options.is_silence(&dr) {
+            let span = speech_regions.and_then(|regions| {
+                let min_speech = self.options.min_speech_duration?;
+                speech_span(regions, time_offset, window_end, min_speech)
+            });
+            let recovered = match span {
+                Some((start, end)) => self.decode_speech_span(mel, start, end, yielder).await?,
+                None => None,
+            };
+            let Some(recovered) = recovered else {
+                self.end_window_diagnostics(WindowOutcome::Silence, Some(&dr), None);
+                log_at!(self.logger, Debug, "skipping {seek} {dr:?}");
+                return Ok(true);
+            };
+            log_at!(
+                self.logger,
+                Debug,
+                "recovered speech at {}s in a silent window",
+                recovered.0
+            );
+            // The rest of the window is silence, it is consumed whole.
+            *seek = window_seek + segment_size;
+            (time_offset, segment_duration) = (recovered.0, recovered.1);
+            DecodedWindow {
                 dr,
+                language,
+                low_agreement_spans,
+                words,
+                translation,
+                second_embeddings,
+            } = recovered.2;
+            self.end_window_diagnostics(WindowOutcome::RecoveredSpeech, Some(&dr), None);
+        } else {
+            self.end_window_diagnostics(WindowOutcome::Decoded, Some(&dr), None);
+        }
+        let speaker_change = (!second_embeddings.is_empty()).then(|| {
+            // Seconds from the start of the decoded audio to the next window.
+            let next_start = (*seek * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64 - time_offset;
+            self.speaker_changes
+                .push_window(&second_embeddings, self.window_audio, next_start)
+        });
+        if let Some((change, window)) = speaker_change
+            .as_ref()
+            .zip(self.diagnostics.as_mut().and_then(|d| d.windows.last_mut()))
+        {
+            window.speaker_change_distance = change.distance;
+            window
+                .within_window_distances
+                .clone_from(&change.within_distances);
+        }
+        let previous_text = segments.last().map(|segment| segment.dr.text.as_str());
+        let (_, hallucinated) =
+            hallucination::score(previous_text, &dr.text, &self.options.hallucination);
+        if hallucinated {
+            self.prompt.clear();
+        } else {
+            self.prompt.push_segment(&dr);
+        }
+        if let Some(translation) = &translation {
+            self.translation_prompt.push_segment(translation);
+        }
+        let words = words
+            .into_iter()
+            .filter(|word| word.start < segment_duration)
+            .map(|word| WordTiming {
+                start: time_offset + word.start,
+                end: time_offset + f64::min(word.end, segment_duration),
+                ..word
+            })
+            .collect();
+        let mut segment = Segment {
+            language,
+            low_agreement_spans,
+            words,
+            confidence: self.options.confidence.score(&dr),
+            prompt_tokens,
+            translation,
+            speaker_change_score: speaker_change.and_then(|change| change.score).unwrap_or(0.),
+            ..Segment::new(time_offset, segment_duration, dr)
+        };
+        if time_offset + segment_duration > audio_end {
+            self.trim_tail(&mut segment, audio_end)?;
+        }
+        match segments.last_mut() {
+            Some(previous)
+                if !previous.no_speech
+                    && previous.error.is_none()
+                    && previous.start + previous.duration > time_offset =>
+            {
+                if chunking.is_some() {
+                    self.merge_chunks(previous, &mut segment)?
+                } else {
+                    self.merge_overlap(previous, &mut segment)?
+                }
+            }
+            _ => clip_overlap(segments, time_offset),
+        }
+        segments.push(segment);
+        Ok(true)
+    }
+
+    /// Decodes the `[start, end)` range in seconds of a window treated as silence, the rest
+    /// of the window being replaced by silence. Returns the start and duration of the range
+    /// with its result, `None` when it is silence too.
+    async fn decode_speech_span(
+        &mut self,
+        mel: &Tensor,
+        start: f64,
+        end: f64,
+        yielder: &mut dyn Yielder,
+    ) -> anyhow::Result<Option<(f64, f64, DecodedWindow)>> {
+        let (_, _, content_frames) = mel.dims3()?;
+        let frames_per_second = m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64;
+        let start_frame = (start * frames_per_second) as usize;
+        let frames = ((end - start) * frames_per_second).ceil() as usize;
+        let frames = usize::min(frames, content_frames.saturating_sub(start_frame));
+        if frames == 0 {
+            return Ok(None);
+        }
+        let window = padded_window(mel, start_frame, frames)?;
+        let start = start_frame as f64 / frames_per_second;
+        self.window_start = start;
+        self.window_audio = frames as f64 / frames_per_second;
+        let decoded = match self.decode_with_fallback(&window, yielder).await {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                log_at!(
+                    self.logger,
+                    Warn,
+                    "failed to decode speech at {start}s: {err}"
+                );
+                return Ok(None);
+            }
+        };
+        if self.options.is_silence(&decoded.dr) {
+            return Ok(None);
+        }
+        Ok(Some((start, frames as f64 / frames_per_second, decoded)))
+    }
+
+    /// Words of the text tokens of `segment`, grouped like the word timestamps. Their times
+    /// are the word timestamps when available, estimated from the character counts otherwise.
+    fn overlap_words(&self, segment: &Segment) -> anyhow::Result<Vec<OverlapWord>> {
+        let tokens = &segment.dr.tokens;
+        let eot = self.special_tokens.eot;
+        let mut groups: Vec<std::ops::Range<usize>> = vec![];
+        for (i, &token) in tokens.iter().enumerate() {
+            if token >= eot {
+                continue;
+            }
+            let piece = self.tokenizer.decode(&[token], false).map_err(E::msg)?;
+            match groups.last_mut() {
+                Some(group) if !piece.starts_with(' ') => group.end = i + 1,
+                _ => groups.push(i..i + 1),
+            }
+        }
+        let mut texts = Vec::with_capacity(groups.len());
+        for group in groups.iter() {
+            let text_tokens: Vec<u32> = tokens[group.clone()]
+                .iter()
+                .copied()
+                .filter(|&t| t < eot)
+                .collect();
+            texts.push(self.tokenizer.decode(&text_tokens, false).map_err(E::msg)?);
+        }
+        let timed = segment.words.len() == groups.len();
+        let total_chars = texts
+            .iter()
+            .map(|t| t.chars().count())
+            .sum::<usize>()
+            .max(1) as f64;
+        let at = |chars: usize| segment.start + segment.duration * chars as f64 / total_chars;
+        let mut chars = 0;
+        let mut words = Vec::with_capacity(groups.len());
+        for (i, (text, tokens)) in texts.into_iter().zip(groups).enumerate() {
+            let start = at(chars);
+            chars += text.chars().count();
+            let (start, end) = match &segment.words[..] {
+                words if timed => (words[i].start, words[i].end),
+                _ => (start, at(chars)),
+            };
+            words.push(OverlapWord {
+                text,
+                tokens,
+                start,
+                end,
+            });
+        }
+        Ok(words)
+    }
+
+    /// Stitches the windows of `previous` and `next`, which overlap from the start of `next`,
+    /// so that the words of the overlap are kept once and the segments no longer overlap.
+    fn merge_overlap(&self, previous: &mut Segment, next: &mut Segment) -> anyhow::Result<()> {
+        let previous_words = self.overlap_words(previous)?;
+        let next_words = self.overlap_words(next)?;
+        let cut = overlap::find_cut(
+            &previous_words,
+            &next_words,
+            (next.start, previous.start + previous.duration),
+            previous.dr.score >= next.dr.score,
+        );
+        if let Some(word) = previous_words.get(cut.previous_words) {
+            let tokens = &previous.dr.tokens;
+            let end = match tokens.last() {
+                Some(&t) if t == self.special_tokens.eot => tokens.len() - 1,
+                _ => tokens.len(),
             };
-            segments.push(segment)
-        }
-        Ok(segments)
-    }
-
-    pub fn load(md: ModelData) -> anyhow::Result<Self> {
-        let device = Device::Cpu;
-        let tokenizer = Tokenizer::from_bytes(&md.tokenizer).map_err(E::msg)?;
-        let mel_filters = safetensors::tensor::SafeTensors::deserialize(&md.mel_filters)?;
-        let mel_filters = mel_filters.tensor("mel_80")?.load(&device)?;
-        console_log!("[RUST]: mel_filters shape '{:?}'", mel_filters.shape());
-
-        let mel_filters = mel_filters.flatten_all()?.to_vec1::<f32>()?;
-        let config: Config = serde_json::from_slice(&md.config)?;
-        let model = if md.quantized {
-            let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf_buffer(
-                &md.weights,
-                &device,
-            )?;
-            Model::Quantized(m::quantized_model::Whisper::load(&vb, config)?)
+            overlap::drop_tokens(previous, word.tokens.start..end);
+        }
+        if let (Some(first), Some(last)) = (next_words.first(), next_words[..cut.next_words].last())
+        {
+            overlap::drop_tokens(next, first.tokens.start..last.tokens.end);
+        }
+        if previous.words.len() == previous_words.len() {
+            previous.words.truncate(cut.previous_words);
         } else {
-            let vb = VarBuilder::from_buffered_safetensors(md.weights, m::DTYPE, &device)?;
-            Model::Normal(m::model::Whisper::load(&vb, config)?)
+            previous.words.retain(|w| w.start < cut.time);
+        }
+        if next.words.len() == next_words.len() {
+            next.words.drain(..cut.next_words);
+        } else {
+            next.words.retain(|w| w.start >= cut.time);
+        }
+        previous.duration = cut.time - previous.start;
+        next.duration = next.start + next.duration - cut.time;
+        next.start = cut.time;
+        self.decode_text(previous)?;
+        self.decode_text(next)
+    }
+
+    /// Stitches the chunks of `previous` and `next`, which overlap from the start of `next`, on
+    /// the longest common sequence of their text tokens. Without times on the tokens, the
+    /// segments are cut in the middle of the overlap.
+    fn merge_chunks(&self, previous: &mut Segment, next: &mut Segment) -> anyhow::Result<()> {
+        let eot = self.special_tokens.eot;
+        let text_positions = |segment: &Segment| -> Vec<usize> {
+            (0..segment.dr.tokens.len())
+                .filter(|&i| segment.dr.tokens[i] < eot)
+                .collect()
+        };
+        let (left, right) = (text_positions(previous), text_positions(next));
+        let (left_end, right_start) = {
+            let tokens = |segment: &Segment, positions: &[usize]| -> Vec<u32> {
+                positions.iter().map(|&i| segment.dr.tokens[i]).collect()
+            };
+            chunked::merge_point(&tokens(previous, &left), &tokens(next, &right))
         };
-        console_log!("[RUST]: model loaded");
+        if let (Some(&start), Some(&last)) = (left.get(left_end), left.last()) {
+            overlap::drop_tokens(previous, start..last + 1);
+        }
+        if right_start > 0 {
+            let end = right
+                .get(right_start)
+                .copied()
+                .unwrap_or(right[right.len() - 1] + 1);
+            overlap::drop_tokens(next, right[0]..end);
+        }
+        let time = (next.start + previous.start + previous.duration) / 2.;
+        previous.words.retain(|w| w.start < time);
+        next.words.retain(|w| w.start >= time);
+        previous.duration = time - previous.start;
+        next.duration = next.start + next.duration - time;
+        next.start = time;
+        self.decode_text(previous)?;
+        self.decode_text(next)
+    }
 
-        let task = match md.task.as_deref() {
-            Some("translate") => Some(Task::Translate),
-            _ => Some(Task::Transcribe),
+    /// Trims the segment of a window going past the end of the audio, which the model tends
+    /// to fill with repeated sentences or with text it hallucinates in the padding: the text
+    /// after a timestamp past the end and the repeated trailing sentences are dropped, and the
+    /// segment ends at its last timestamp or at the end of the audio.
+    fn trim_tail(&self, segment: &mut Segment, audio_end: f64) -> anyhow::Result<()> {
+        let timestamp_begin = self.special_tokens.timestamp_begin;
+        let step = (2 * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+        let tokens = &segment.dr.tokens;
+        let text_end = match tokens.last() {
+            Some(&t) if t == self.special_tokens.eot => tokens.len() - 1,
+            _ => tokens.len(),
         };
-        let decoder = Self::new(
-            model,
-            tokenizer,
-            mel_filters,
-            &device,
-            task,
-            md.language,
-            md.is_multilingual,
-            md.timestamps,
-        )?;
-        Ok(decoder)
+        let timestamp = |t: u32| segment.start + (t - timestamp_begin) as f64 * step;
+        if self.run_timestamps {
+            let past_end = tokens[..text_end]
+                .iter()
+                .position(|&t| t >= timestamp_begin && timestamp(t) >= audio_end + step);
+            let last_timestamp = tokens[..past_end.map_or(text_end, |i| i + 1)]
+                .iter()
+                .rev()
+                .find(|&&t| t > timestamp_begin)
+                .map(|&t| timestamp(t));
+            if let Some(end) = last_timestamp {
+                segment.duration = f64::min(segment.duration, end - segment.start);
+            }
+            if let Some(i) = past_end {
+                overlap::drop_tokens(segment, i + 1..text_end);
+            }
+        }
+        let words = self.overlap_words(segment)?;
+        let repeated = overlap::repeated_tail(&words);
+        if repeated > 0 {
+            let first = &words[words.len() - repeated];
+            let last = &words[words.len() - 1];
+            overlap::drop_tokens(segment, first.tokens.start..last.tokens.end);
+        }
+        segment.duration = f64::min(segment.duration, audio_end - segment.start);
+        segment.words.retain(|w| w.start < audio_end);
+        for word in segment.words.iter_mut() {
+            word.end = f64::min(word.end, audio_end)
+        }
+        self.decode_text(segment)
     }
 
-    pub fn convert_and_run(&mut self, wav_input: &[u8]) -> anyhow::Result<Vec<Segment>> {
-        let device = Device::Cpu;
-        let mut wav_input = std::io::Cursor::new(wav_input);
-        let wav_reader = hound::WavReader::new(&mut wav_input)?;
-        let spec = wav_reader.spec();
-        console_log!("[RUST]: wav data: {spec:?}");
+    /// Decodes the text of a segment again after its tokens changed.
+    fn decode_text(&self, segment: &mut Segment) -> anyhow::Result<()> {
+        let TokenText {
+            text,
+            invalid_tokens,
+        } = self.token_text(&segment.dr.tokens);
+        segment.dr.text = text;
+        segment.dr.had_decode_errors = !invalid_tokens.is_empty();
+        segment.dr.decode_error_tokens = invalid_tokens;
+        segment.dr.text_clean = self.text_processor.process(&segment.dr.text);
+        Ok(())
+    }
 
-        if spec.sample_rate != m::SAMPLE_RATE as u32 {
-            anyhow::bail!("wav file must have a {} sampling rate", m::SAMPLE_RATE);
+    fn finish_run(&self, state: RunState) -> (Vec<Segment>, Vec<SegmentFailure>) {
+        let RunState {
+            segments, failures, ..
+        } = state;
+        let mut segments = filter_hallucinations(segments, &self.options.hallucination);
+        if self.options.max_segment_duration.is_some() || self.options.max_segment_chars.is_some() {
+            segments = limit_length(
+                segments,
+                self.options.max_segment_duration,
+                self.options.max_segment_chars,
+            );
         }
-        let mut data = wav_reader.into_samples::<i16>().collect::<Vec<_>>();
-        data.truncate(data.len() / spec.channels as usize);
-        let mut pcm_data = Vec::with_capacity(data.len());
-        for d in data.into_iter() {
-            let d = d?;
-            pcm_data.push(d as f32 / 32768.)
+        for (id, segment) in segments.iter_mut().enumerate() {
+            segment.id = id;
+            if self.options.text.itn {
+                self.normalize_entities(segment)
+            }
         }
-        console_log!("[RUST]: pcm data loaded {}", pcm_data.len());
+        (segments, failures)
+    }
 
-        let mel = audio::pcm_to_mel(self.model.config(), &pcm_data, &self.mel_filters)?;
-        let mel_len = mel.len();
-        let n_mels = self.model.config().num_mel_bins;
-        let mel = Tensor::from_vec(mel, (1, n_mels, mel_len / n_mels), &device)?;
-        console_log!("[RUST]: loaded mel: {:?}", mel.dims());
-        let segments = self.run(&mel)?;
-        Ok(segments)
+    /// Runs the text post-processing again with the inverse text normalization of the language
+    /// of the segment, once the segments no longer change, and merges the words of the entities.
+    fn normalize_entities(&self, segment: &mut Segment) {
+        let language = match &segment.language {
+            Some(language) => Some(language.as_str()),
+            None if !self.is_multilingual => Some("en"),
+            None => None,
+        };
+        let Some(normalizer) = language.and_then(itn::normalizer) else {
+            return;
+        };
+        segment.dr.text_clean = self.text_processor.process_in(&segment.dr.text, language);
+        segment.words = itn::merge_words(normalizer, &segment.words);
     }
-}
 
-pub fn detect(model: &mut Model, tokenizer: &Tokenizer, mel: &Tensor) -> Result<u32, E> {
-    let (_bsize, _, seq_len) = mel.dims3()?;
-    let mel = mel.narrow(
-        2,
-        0,
-        usize::min(seq_len, model.config().max_source_positions),
-    )?;
-    let device = mel.device();
-    let language_token_ids = LANGUAGES
-        .iter()
-        .map(|(t, _)| token_id(tokenizer, &format!("<|{t}|>")))
-        .map(|e| e.map_err(E::msg))
-        .collect::<Result<Vec<_>, E>>()?;
-    let sot_token = token_id(tokenizer, m::SOT_TOKEN)?;
-    let audio_features = model.encoder_forward(&mel, true)?;
-    let tokens = Tensor::new(&[[sot_token]], device)?;
-    let language_token_ids = Tensor::new(language_token_ids.as_slice(), device)?;
-    let ys = model.decoder_forward(&tokens, &audio_features, true)?;
-    let logits = model.decoder_final_linear(&ys.i(..1)?)?.i(0)?.i(0)?;
-    let logits = logits.index_select(&language_token_ids, 0)?;
-    let probs = candle_nn::ops::softmax(&logits, D::Minus1)?;
-    let probs = probs.to_vec1::<f32>()?;
-    let mut probs = LANGUAGES.iter().zip(probs.iter()).collect::<Vec<_>>();
-    probs.sort_by(|(_, p1), (_, p2)| p2.total_cmp(p1));
-    let token = &format!("<|{}|>", probs[0].0 .0);
-    let language = token_id(tokenizer, token)?;
-    console_log!("[RUST]: language: {language} {token}");
-    Ok(language)
+    pub fn encode_text(&self, text: &str) -> Result<Vec<u32>, WhisperError> {
+        let encoding = self.tokenizer.encode(text, false)?;
+        Ok(encoding.get_ids().to_vec())
+    }
+
+    pub fn decode_tokens(
+        &self,
+        tokens: &[u32],
+        skip_special: bool,
+    ) -> Result<String, WhisperError> {
+        Ok(self.tokenizer.decode(tokens, skip_special)?)
+    }
+
+    pub fn special_tokens(&self) -> SpecialTokens {
+        self.special_tokens
+    }
+
+    /// The tokenizer, to be shared with other decoders through
+    /// [`DecoderBuilder::tokenizer_shared`].
+    pub fn tokenizer(&self) -> &Arc<Tokenizer> {
+        &self.tokenizer
+    }
+
+    /// Size of the tokenizer vocabulary, including the added special tokens.
+    pub fn vocab_size(&self) -> usize {
+        self.tokenizer.get_vocab_size(true)
+    }
+
+    pub fn config(&self) -> &Config {
+        self.model.config()
+    }
+
+    /// Device the model runs on.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Dtype the model computes in.
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.model_info
+    }
+
+    /// Estimate of the memory used by the model: the parameters plus the capacity of the
+    /// cross-attention key/value cache filled for each decoded window.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let config = self.model.config();
+        let parameter_bytes = self.model_info.materialized_bytes(self.dtype);
+        let kv_cache_bytes = config.decoder_layers
+            * 2
+            * config.max_source_positions
+            * config.d_model
+            * self.dtype.size_in_bytes();
+        MemoryUsage {
+            parameter_bytes,
+            kv_cache_bytes,
+            total_bytes: parameter_bytes + kv_cache_bytes,
+        }
+    }
+
+    /// Replaces the destination of the log messages.
+    pub fn set_logger(&mut self, logger: Rc<dyn Logger>) {
+        self.logger = logger;
+    }
+
+    /// Installs a hook applied to the logits of every sampled token, `None` removes it.
+    pub fn set_logits_processor(&mut self, processor: Option<LogitsProcessor>) {
+        self.logits_processor = processor;
+    }
+
+    /// Installs a callback receiving the in-progress text of the windows while they are decoded,
+    /// `None` removes it. It is not called by the language detection.
+    pub fn set_on_partial(&mut self, on_partial: Option<PartialCallback>) {
+        self.on_partial = on_partial;
+    }
+
+    /// Replaces the clock used to measure the timings.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Rough transcription time in seconds of `duration_secs` of audio, from the last
+    /// calibration or from the size of the model.
+    pub fn estimate_runtime(&self, duration_secs: f64) -> f64 {
+        let realtime_factor = self
+            .realtime_factor
+            .unwrap_or_else(|| self.model_info.parameter_bytes as f64 / BYTES_PER_REALTIME_FACTOR);
+        duration_secs * realtime_factor
+    }
+
+    /// Calibrates `estimate_runtime` from the timings of a run over `duration_secs` of audio.
+    pub fn calibrate_runtime(&mut self, timings: &Timings, duration_secs: f64) {
+        if duration_secs > 0. && timings.total_ms > 0. {
+            self.realtime_factor = Some(timings.total_ms / 1000. / duration_secs)
+        }
+    }
+
+    /// Changes the task of the next runs, the cached encoder outputs stay valid.
+    pub fn set_task(&mut self, task: Task) -> Result<(), WhisperError> {
+        check_task(Some(task), self.is_multilingual, &self.special_tokens)?;
+        self.task = Some(task);
+        Ok(())
+    }
+
+    /// Runs a short silence through the mel computation, the encoder and a few decoder
+    /// steps, so that the first transcription does not pay for the lazy allocations and the
+    /// first touch of the weights. The caches are reset afterwards and the RNG, the prompt and
+    /// the encoder cache are left untouched, the transcriptions are the same with or without
+    /// a warm-up. The calls after the first return at once.
+    pub fn warm_up(&mut self) -> Result<WarmupReport, WhisperError> {
+        if self.warmup.is_some() {
+            return Ok(WarmupReport {
+                already_warm: true,
+                ..Default::default()
+            });
+        }
+        let start = self.clock.now_ms();
+        let pcm = vec![0f32; (WARMUP_SECONDS * m::SAMPLE_RATE as f64) as usize];
+        let mel = self.mel_of(&pcm)?;
+        let frames = pcm.len() / m::HOP_LENGTH;
+        let mel = mel.tensor().narrow(2, 0, frames)?.to_dtype(self.dtype)?;
+        let mel_done = self.clock.now_ms();
+        // The encoder cache is bypassed, the silence would only evict real entries.
+        let audio_features = self.encoder_forward(&mel)?;
+        let encoder_done = self.clock.now_ms();
+        let language_token = match self.pinned_language() {
+            Some(language) if self.is_multilingual => {
+                token_id(&self.tokenizer, &format!("<|{language}|>")).ok()
+            }
+            _ => None,
+        };
+        let mut tokens = self.sot_sequence(language_token);
+        // Greedy steps, the RNG is not used.
+        for i in 0..WARMUP_DECODER_STEPS {
+            let tokens_t = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+            let ys = self
+                .model
+                .decoder_forward(&tokens_t, &audio_features, i == 0)?;
+            let (_, seq_len, _) = ys.dims3()?;
+            let logits = self
+                .model
+                .decoder_final_linear(&ys.i((..1, seq_len - 1..))?)?
+                .i(0)?
+                .i(0)?;
+            tokens.push(logits.argmax(0)?.to_scalar::<u32>()?);
+        }
+        self.model.reset_kv_cache();
+        let end = self.clock.now_ms();
+        let report = WarmupReport {
+            mel_ms: mel_done - start,
+            encoder_ms: encoder_done - mel_done,
+            decoder_ms: end - encoder_done,
+            total_ms: end - start,
+            already_warm: false,
+        };
+        log_at!(
+            self.logger,
+            Debug,
+            "warmed up in {:.0}ms: {report:?}",
+            report.total_ms
+        );
+        self.warmup = Some(report);
+        Ok(report)
+    }
+
+    /// Report of the first [`Decoder::warm_up`], `None` while the decoder is not warmed up.
+    pub fn warmup_report(&self) -> Option<WarmupReport> {
+        self.warmup
+    }
+
+    /// Drops the cached encoder outputs.
+    pub fn clear_encoder_cache(&mut self) {
+        self.encoder_cache.clear()
+    }
+
+    pub fn encoder_cache_stats(&self) -> EncoderCacheStats {
+        self.encoder_cache.stats()
+    }
+
+    /// Encoder passes run since the model was loaded, the warm-up included and the encoder
+    /// outputs found in the encoder cache excluded.
+    pub fn encoder_forwards(&self) -> usize {
+        self.encoder_forwards
+    }
+
+    /// Options as set, the language profiles are applied to them during the runs.
+    #[allow(clippy::misnamed_getters)]
+    pub fn options(&self) -> &DecodeOptions {
+        &self.base_options
+    }
+
+    /// Default options of the model, those it is loaded with unless options are given to the
+    /// builder, to start from when changing some of them.
+    pub fn profile_options(&self) -> DecodeOptions {
+        self.model_info
+            .profile
+            .options(self.is_multilingual, self.model_info.quantized)
+    }
+
+    pub fn set_options(&mut self, mut options: DecodeOptions) -> Result<(), WhisperError> {
+        options.validate()?;
+        options.canonicalize_languages();
+        options.validate_tokens(self.model.config().vocab_size)?;
+        self.text_processor = TextPostProcessor::new(&options.text)?;
+        self.encoder_cache
+            .set_options(options.encoder_cache.clone());
+        for (language, profile) in self.profiles.iter() {
+            check_profile(language, profile, &options)?;
+        }
+        self.booster = PhraseBooster::for_tokenizer(
+            &options.boost_phrases,
+            &self.tokenizer,
+            self.special_tokens.eot,
+        )?;
+        self.base_options = options.clone();
+        self.options = options;
+        self.profile_language = None;
+        self.prompt.set_max_tokens(self.max_prompt_tokens());
+        self.translation_prompt
+            .set_max_tokens(self.max_prompt_tokens());
+        self.update_suppress_tokens()?;
+        Ok(())
+    }
+
+    /// Everything the decoder is configured with, to reproduce its runs elsewhere, e.g. from a
+    /// bug report. See [`Decoder::apply_snapshot`].
+    pub fn config_snapshot(&self) -> ConfigSnapshot {
+        ConfigSnapshot {
+            crate_version: CRATE_VERSION.to_string(),
+            model: self.model_info.clone(),
+            options: self.base_options.clone(),
+            language_profiles: self.profiles.clone(),
+            task: self.task,
+            language: self.language.clone(),
+            timestamps: self.timestamps,
+            seed: self.seed,
+        }
+    }
+
+    /// Restores the configuration of a snapshot of this decoder or of another one, and returns
+    /// the fields that could not be restored, which keep their current values.
+    ///
+    /// The model is not restored: a snapshot of another model is reported under `model`, its
+    /// options being applied when valid for this one. The RNG is reseeded with the seed of the
+    /// snapshot, the next run sampling as the first run of the decoder snapshotted.
+    pub fn apply_snapshot(&mut self, snapshot: &ConfigSnapshot) -> Vec<SkippedField> {
+        let mut skipped = vec![];
+        if snapshot.crate_version != CRATE_VERSION {
+            skipped.push(SkippedField::new(
+                "crate_version",
+                format!("taken with {}, not {CRATE_VERSION}", snapshot.crate_version),
+            ));
+        }
+        let differences = snapshot.model_differences(&self.model_info);
+        if !differences.is_empty() {
+            skipped.push(SkippedField::new("model", differences.join(", ")));
+        }
+        // The current profiles may not fit the options of the snapshot.
+        let profiles = std::mem::take(&mut self.profiles);
+        if let Err(err) = self.set_options(snapshot.options.clone()) {
+            skipped.push(SkippedField::new("options", err));
+            self.profiles = profiles;
+        }
+        if let Err(err) = self.set_language_profiles(snapshot.language_profiles.clone()) {
+            skipped.push(SkippedField::new("language_profiles", err));
+        }
+        match snapshot.task {
+            Some(task) => {
+                if let Err(err) = self.set_task(task) {
+                    skipped.push(SkippedField::new("task", err));
+                }
+            }
+            None => self.task = None,
+        }
+        match &snapshot.language {
+            Some(_) if !self.is_multilingual => skipped.push(SkippedField::new(
+                "language",
+                "a language cannot be set for non-multilingual models",
+            )),
+            Some(language) if !languages::is_supported(language) => skipped.push(
+                SkippedField::new("language", format!("unsupported language {language}")),
+            ),
+            language => self.language = language.clone(),
+        }
+        self.timestamps = snapshot.timestamps;
+        self.seed = snapshot.seed;
+        self.rng = StdRng::seed_from_u64(snapshot.seed);
+        for field in &skipped {
+            log_at!(
+                self.logger,
+                Warn,
+                "snapshot field {} not restored: {}",
+                field.field,
+                field.reason
+            );
+        }
+        skipped
+    }
+
+    pub fn language_profiles(&self) -> &OptionProfiles {
+        &self.profiles
+    }
+
+    /// Sets the options overriding the base options when the language of the audio, pinned or
+    /// detected, is `language`, English for the English-only models. The profile is resolved
+    /// on every window, so that with [`LanguageDetectionMode::PerSegment`] each window uses
+    /// the profile of its language.
+    pub fn set_language_profile(
+        &mut self,
+        language: &str,
+        options: PartialDecodeOptions,
+    ) -> Result<(), WhisperError> {
+        let mut profiles = self.profiles.clone();
+        profiles.set(language, options)?;
+        self.set_language_profiles(profiles)
+    }
+
+    /// Replaces all the profiles, e.g. by a map deserialized from a JSON config.
+    pub fn set_language_profiles(&mut self, profiles: OptionProfiles) -> Result<(), WhisperError> {
+        let profiles = profiles.validated()?;
+        for (language, profile) in profiles.iter() {
+            check_profile(language, profile, &self.base_options)?;
+        }
+        self.profiles = profiles;
+        self.apply_language_profile(None)
+    }
+
+    /// Makes the options of `language` the ones in effect, the base options for `None` and the
+    /// languages without a profile.
+    fn apply_language_profile(&mut self, language: Option<&str>) -> Result<(), WhisperError> {
+        let language = language.filter(|language| self.profiles.get(language).is_some());
+        if language == self.profile_language.as_deref() {
+            return Ok(());
+        }
+        let options = self.profiles.resolve(&self.base_options, language);
+        self.text_processor = TextPostProcessor::new(&options.text)?;
+        self.options = options;
+        self.profile_language = language.map(str::to_string);
+        Ok(())
+    }
+
+    /// Loads a model from files on disk, memory-mapping the weights. Both safetensors and GGUF
+    /// weights are supported.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_from_paths(
+        weights: &std::path::Path,
+        tokenizer: &std::path::Path,
+        config: &std::path::Path,
+        mel_filters: Option<&std::path::Path>,
+        options: DecodeOptions,
+    ) -> Result<Self, WhisperError> {
+        let mut builder = DecoderBuilder::new()
+            .weights_file(weights)
+            .tokenizer_file(tokenizer)
+            .config_file(config);
+        if let Some(mel_filters) = mel_filters {
+            builder = builder.mel_filters_file(mel_filters);
+        }
+        let mut decoder = builder.build()?;
+        decoder.set_options(options)?;
+        Ok(decoder)
+    }
+
+    pub fn load(md: ModelData) -> Result<Self, WhisperError> {
+        Self::load_with_progress(md, &mut |_| {})
+    }
+
+    pub fn load_with_progress(
+        md: ModelData,
+        progress: &mut dyn FnMut(&LoadProgress),
+    ) -> Result<Self, WhisperError> {
+        DecoderBuilder::from(md).build_with_progress(progress)
+    }
+
+    /// Replaces the model, tokenizer and mel filters by the ones of `md`, loaded on the same
+    /// device, keeping the options, the clock, the logits processor, the partial hypotheses
+    /// callback and the state of the sampling RNG.
+    ///
+    /// The new model is fully loaded and the options are checked against it before anything is
+    /// replaced, on failure the current model stays usable. The encoder cache starts empty.
+    pub fn swap_model(&mut self, md: ModelData) -> Result<(), WhisperError> {
+        self.swap_model_with(DecoderBuilder::from(md))
+    }
+
+    /// [`Decoder::swap_model`] with the pieces of `builder`, e.g. to keep the current tokenizer
+    /// with `builder.tokenizer_shared(decoder.tokenizer().clone())`. The device and the logger
+    /// of the builder are replaced by the ones of the decoder.
+    pub fn swap_model_with(&mut self, builder: DecoderBuilder) -> Result<(), WhisperError> {
+        let mut decoder = builder
+            .device(self.device.clone())
+            .logger(self.logger.clone())
+            .build()?;
+        decoder.set_options(self.base_options.clone())?;
+        decoder.set_language_profiles(self.profiles.clone())?;
+        decoder.rng = self.rng.clone();
+        decoder.clock = std::mem::replace(&mut self.clock, Box::new(SystemClock));
+        decoder.logits_processor = self.logits_processor.take();
+        decoder.on_partial = std::mem::take(&mut self.on_partial);
+        *self = decoder;
+        Ok(())
+    }
+
+    /// Clears the per-file state: the detected language and the model caches.
+    fn reset_state(&mut self) {
+        self.detected_language = None;
+        self.prompt.clear();
+        self.translation_prompt.clear();
+        self.speaker_changes = SpeakerChangeTracker::default();
+        self.model.reset_kv_cache();
+    }
+
+    /// Transcribes several files in a row, a failure only affects the slot of the file that
+    /// caused it.
+    pub fn transcribe_batch(
+        &mut self,
+        inputs: &[AudioInput],
+    ) -> Vec<Result<TranscriptionOutput, WhisperError>> {
+        self.transcribe_batch_with_progress(inputs, &mut |_| {})
+    }
+
+    pub fn transcribe_batch_with_progress(
+        &mut self,
+        inputs: &[AudioInput],
+        progress: &mut dyn FnMut(&BatchProgress),
+    ) -> Vec<Result<TranscriptionOutput, WhisperError>> {
+        let opts = RunOptions::default();
+        let files_total = inputs.len();
+        let mut outputs = Vec::with_capacity(files_total);
+        for (file_index, input) in inputs.iter().enumerate() {
+            progress(&BatchProgress {
+                file_index,
+                files_total,
+                done: false,
+            });
+            let output = match input {
+                AudioInput::Wav(wav) => self.convert_and_run_with_options(wav, &opts),
+                AudioInput::WavChannel(wav, channel) => {
+                    let opts = RunOptions {
+                        channel: *channel,
+                        ..opts.clone()
+                    };
+                    self.convert_and_run_with_options(wav, &opts)
+                }
+                AudioInput::Pcm(pcm) => self.run_pcm(pcm, &opts),
+            };
+            if let Err(err) = &output {
+                log_at!(self.logger, Warn, "batch file {file_index} failed: {err}");
+                self.reset_state();
+            }
+            outputs.push(output);
+            progress(&BatchProgress {
+                file_index,
+                files_total,
+                done: true,
+            });
+        }
+        outputs
+    }
+
+    pub fn convert_and_run(
+        &mut self,
+        wav_input: &[u8],
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        self.convert_and_run_with_options(wav_input, &RunOptions::default())
+    }
+
+    pub fn convert_and_run_with_options(
+        &mut self,
+        wav_input: &[u8],
+        opts: &RunOptions,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        let start = self.timer();
+        let (pcm_data, format_check) = self.read_wav(wav_input, opts.channel)?;
+        let pcm_decode_ms = self.elapsed_ms(start);
+        let mut output = self.run_pcm(&pcm_data, opts)?;
+        if let Some(diagnostics) = output.diagnostics.as_mut() {
+            diagnostics.format_check = format_check;
+        }
+        if let Some(timings) = output.timings.as_mut() {
+            timings.pcm_decode_ms = pcm_decode_ms;
+            timings.total_ms = self.elapsed_ms(start);
+        }
+        Ok(output)
+    }
+
+    /// Transcribes 16kHz mono samples in `[-1, 1]`.
+    pub fn run_pcm(
+        &mut self,
+        pcm_data: &[f32],
+        opts: &RunOptions,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        let start = self.timer();
+        let Some(prepared) = self.prepare_pcm(pcm_data, opts)? else {
+            return Ok(self.too_short_output());
+        };
+        let mel = &prepared.mel;
+        if let Some(output) = self.no_speech_output(mel)? {
+            return Ok(output);
+        }
+        let mel_ms = self.elapsed_ms(start);
+        let mut output = self.transcribe_mel(
+            mel.tensor(),
+            opts,
+            prepared.speech_regions.as_deref(),
+            prepared.time_offset,
+            mel.duration(),
+        )?;
+        output.silence_trim = prepared.silence_trim;
+        if let Some(timings) = output.timings.as_mut() {
+            timings.mel_ms = mel_ms;
+            timings.total_ms = self.elapsed_ms(start);
+        }
+        Ok(output)
+    }
+
+    /// Transcribes the two tracks of a conversation recorded on separate devices, e.g. the host
+    /// and the guest of an interview, into a single transcript.
+    ///
+    /// Each track is transcribed on its own, its segments being tagged with the speaker `"A"`
+    /// or `"B"`. The segments of `track_b` are moved onto the timeline of `track_a` by the
+    /// offset of [`audio::align_tracks`], which fails beyond `max_offset_secs`, and the speech
+    /// segments of both are interleaved by start time, see [`segments::interleave`]. The
+    /// timings and diagnostics are those of `track_a`.
+    pub fn transcribe_dual_track(
+        &mut self,
+        track_a: &[f32],
+        track_b: &[f32],
+        max_offset_secs: f64,
+        opts: &RunOptions,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        let offset = audio::align_tracks(track_a, track_b, m::SAMPLE_RATE as u32, max_offset_secs)?;
+        log_at!(self.logger, Debug, "track B is {offset:.2}s behind track A");
+        let mut output = self.run_pcm(track_a, opts)?;
+        let mut output_b = self.run_pcm(track_b, opts)?;
+        for (segments, speaker) in [(&mut output.segments, "A"), (&mut output_b.segments, "B")] {
+            for segment in segments.iter_mut() {
+                segment.speaker = Some(speaker.to_string());
+            }
+        }
+        for failure in output_b.failed_segments.iter_mut() {
+            failure.start -= offset;
+        }
+        output.failed_segments.extend(output_b.failed_segments);
+        output.segments = segments::interleave(vec![
+            (0., std::mem::take(&mut output.segments)),
+            (-offset, output_b.segments),
+        ]);
+        output.no_speech_detected &= output_b.no_speech_detected;
+        output.too_short &= output_b.too_short;
+        Ok(output)
+    }
+
+    /// Same as [`Decoder::convert_and_run_with_options`], awaiting `yielder` before every
+    /// window and every `DecodeOptions::yield_interval_tokens` sampled tokens so that a
+    /// single-threaded caller can process its events.
+    pub async fn convert_and_run_async(
+        &mut self,
+        wav_input: &[u8],
+        opts: &RunOptions,
+        yielder: &mut dyn Yielder,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        let start = self.timer();
+        let (pcm_data, format_check) = self.read_wav(wav_input, opts.channel)?;
+        let pcm_decode_ms = self.elapsed_ms(start);
+        yielder.yield_now().await;
+        let mut output = self.run_pcm_async(&pcm_data, opts, yielder).await?;
+        if let Some(diagnostics) = output.diagnostics.as_mut() {
+            diagnostics.format_check = format_check;
+        }
+        if let Some(timings) = output.timings.as_mut() {
+            timings.pcm_decode_ms = pcm_decode_ms;
+            timings.total_ms = self.elapsed_ms(start);
+        }
+        Ok(output)
+    }
+
+    /// Same as [`Decoder::run_pcm`], awaiting `yielder` before every window and every
+    /// `DecodeOptions::yield_interval_tokens` sampled tokens.
+    pub async fn run_pcm_async(
+        &mut self,
+        pcm_data: &[f32],
+        opts: &RunOptions,
+        yielder: &mut dyn Yielder,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        let start = self.timer();
+        let Some(prepared) = self.prepare_pcm(pcm_data, opts)? else {
+            return Ok(self.too_short_output());
+        };
+        let mel = &prepared.mel;
+        if let Some(output) = self.no_speech_output(mel)? {
+            return Ok(output);
+        }
+        let mel_ms = self.elapsed_ms(start);
+        let Some(tensor) = self.begin_transcription(mel.tensor(), opts)? else {
+            return Ok(self.empty_output());
+        };
+        let mut state = RunState::new(mel.duration());
+        loop {
+            yielder.yield_now().await;
+            let regions = prepared.speech_regions.as_deref();
+            if !self
+                .run_window(&tensor, regions, &mut state, &mut *yielder)
+                .await?
+            {
+                break;
+            }
+        }
+        let mut output = self.finish_transcription(state, prepared.time_offset);
+        output.silence_trim = prepared.silence_trim;
+        if let Some(timings) = output.timings.as_mut() {
+            timings.mel_ms = mel_ms;
+            timings.total_ms = self.elapsed_ms(start);
+        }
+        Ok(output)
+    }
+
+    /// Trims and preprocesses the samples, returning their mel spectrogram, the speech
+    /// regions when the VAD is used and the time offset of the trimmed range. `None` when
+    /// the audio is too short to transcribe.
+    fn prepare_pcm(
+        &self,
+        pcm_data: &[f32],
+        opts: &RunOptions,
+    ) -> Result<Option<PreparedPcm>, WhisperError> {
+        let (pcm_data, mut time_offset) = opts.trim(pcm_data)?;
+        let duration = pcm_data.len() as f64 / m::SAMPLE_RATE as f64;
+        if pcm_data.len() < m::HOP_LENGTH || duration < self.options.min_duration {
+            log_at!(self.logger, Debug, "{duration}s of audio is too short");
+            return Ok(None);
+        }
+        let (pcm_data, silence_trim) = self.trim_silence(pcm_data, &mut time_offset);
+        // The dither is drawn from a copy of the generator, leaving the sampling untouched.
+        let pcm_data = self
+            .options
+            .preprocess
+            .apply_with_rng(pcm_data, &mut self.rng.clone());
+        let mel = self.mel_of(&pcm_data)?;
+        log_at!(self.logger, Debug, "loaded mel: {:?}", mel.tensor().dims());
+        let speech_regions = if self.options.use_vad || self.options.min_speech_duration.is_some() {
+            let regions =
+                audio::detect_speech_regions(&pcm_data, m::SAMPLE_RATE, &self.options.vad);
+            log_at!(self.logger, Debug, "speech regions: {regions:?}");
+            Some(regions)
+        } else {
+            None
+        };
+        Ok(Some(PreparedPcm {
+            mel,
+            speech_regions,
+            time_offset,
+            silence_trim,
+        }))
+    }
+
+    /// Cuts the silence around the speech when `DecodeOptions::trim_silence` is set, moving
+    /// `time_offset` to the origin of `DecodeOptions::timestamp_origin`.
+    fn trim_silence<'a>(
+        &self,
+        pcm_data: &'a [f32],
+        time_offset: &mut f64,
+    ) -> (&'a [f32], Option<SilenceTrim>) {
+        if !self.options.trim_silence {
+            return (pcm_data, None);
+        }
+        let range = audio::speech_range(
+            pcm_data,
+            m::SAMPLE_RATE,
+            &self.options.vad,
+            self.options.trim_silence_padding,
+        );
+        let Some(range) = range.filter(|range| range.len() >= m::HOP_LENGTH) else {
+            log_at!(
+                self.logger,
+                Debug,
+                "no speech found, the silence is not trimmed"
+            );
+            return (pcm_data, None);
+        };
+        let seconds = |samples: usize| samples as f64 / m::SAMPLE_RATE as f64;
+        let trim = SilenceTrim {
+            leading: seconds(range.start),
+            trailing: seconds(pcm_data.len() - range.end),
+        };
+        log_at!(
+            self.logger,
+            Debug,
+            "trimmed {:.2}s of silence before the speech and {:.2}s after",
+            trim.leading,
+            trim.trailing
+        );
+        *time_offset = match self.options.timestamp_origin {
+            TimestampOrigin::Original => *time_offset + trim.leading,
+            TimestampOrigin::Trimmed => 0.,
+        };
+        (&pcm_data[range], Some(trim))
+    }
+
+    /// Runs the encoder alone on the 30-second windows of 16kHz mono samples, the last window
+    /// being padded with silence. Returns the audio features of shape
+    /// `(windows, n_audio_ctx, d_model)` in `f32`, usable as audio embeddings.
+    ///
+    /// The decoder state is left untouched, the encoder outputs go through the encoder cache
+    /// when it is enabled.
+    pub fn encode_audio(&mut self, pcm_data: &[f32]) -> Result<Tensor, WhisperError> {
+        let config = self.model.config();
+        let (n_audio_ctx, d_model) = (config.max_source_positions, config.d_model);
+        if pcm_data.len() < m::HOP_LENGTH {
+            return Ok(Tensor::zeros(
+                (0, n_audio_ctx, d_model),
+                DType::F32,
+                &self.device,
+            )?);
+        }
+        let mel = self.compute_mel(pcm_data)?;
+        let mel = mel.tensor().to_dtype(self.dtype)?;
+        let n_frames = usize::min(mel.dim(2)?, pcm_data.len().div_ceil(m::HOP_LENGTH));
+        let mut windows = vec![];
+        for seek in (0..n_frames).step_by(m::N_FRAMES) {
+            let size = usize::min(n_frames - seek, m::N_FRAMES);
+            let window = mel
+                .narrow(2, seek, size)?
+                .pad_with_zeros(2, 0, m::N_FRAMES - size)?;
+            windows.push(self.encode(&window)?.to_dtype(DType::F32)?);
+        }
+        Ok(Tensor::cat(&windows, 0)?)
+    }
+
+    /// Probability that a short chunk of 16kHz mono samples holds speech, one minus the
+    /// probability of the no-speech token, from a single encoder pass over its first 30
+    /// seconds and a single decoder step from the SOT token. The chunk is fed to the encoder as
+    /// the windows of a transcription are, see `DecodeOptions::encoder_input`, so without
+    /// padding by default.
+    ///
+    /// The prompt and the language of the transcriptions are left untouched, the KV cache is
+    /// reset after the probe.
+    pub fn speech_probability(&mut self, pcm_data: &[f32]) -> Result<f32, WhisperError> {
+        if pcm_data.len() < m::HOP_LENGTH {
+            return Ok(0.);
+        }
+        let mel = self.compute_mel(pcm_data)?;
+        let mel = mel.tensor().to_dtype(self.dtype)?;
+        let frames = usize::min(
+            self.truncated_window_frames(m::N_FRAMES),
+            pcm_data.len().div_ceil(m::HOP_LENGTH),
+        );
+        let window = self.encoder_window(&mel, 0, frames)?;
+        let audio_features = self.encode(&window)?;
+        let sot = Tensor::new(&[self.special_tokens.sot], &self.device)?.unsqueeze(0)?;
+        let ys = self.model.decoder_forward(&sot, &audio_features, true);
+        let no_speech_prob = ys.and_then(|ys| {
+            no_speech_probability(&self.model, &ys, 0, self.special_tokens.no_speech)
+        });
+        self.model.reset_kv_cache();
+        Ok(1. - no_speech_prob? as f32)
+    }
+
+    /// Mean over time of the audio features of [`Decoder::encode_audio`], one vector of size
+    /// `d_model` per window: `(windows, d_model)`.
+    pub fn embedding(&mut self, pcm_data: &[f32]) -> Result<Tensor, WhisperError> {
+        Ok(self.encode_audio(pcm_data)?.mean(1)?)
+    }
+
+    /// [`Decoder::encode_audio`] as flat values along with their shape.
+    pub fn encode_audio_vec(
+        &mut self,
+        pcm_data: &[f32],
+    ) -> Result<(Vec<f32>, Vec<usize>), WhisperError> {
+        let features = self.encode_audio(pcm_data)?;
+        let shape = features.dims().to_vec();
+        Ok((features.flatten_all()?.to_vec1()?, shape))
+    }
+
+    /// Computes the mel spectrogram of 16kHz mono samples, after the configured preprocessing.
+    pub fn compute_mel(&self, pcm_data: &[f32]) -> Result<MelSpectrogram, WhisperError> {
+        self.mel_of(
+            &self
+                .options
+                .preprocess
+                .apply_with_rng(pcm_data, &mut self.rng.clone()),
+        )
+    }
+
+    /// Computes the mel spectrogram of a 16kHz WAV file, its channels mixed.
+    pub fn convert_to_mel(&self, wav_input: &[u8]) -> Result<MelSpectrogram, WhisperError> {
+        self.convert_channel_to_mel(wav_input, ChannelSelect::Mix)
+    }
+
+    /// Computes the mel spectrogram of a channel of a 16kHz WAV file.
+    pub fn convert_channel_to_mel(
+        &self,
+        wav_input: &[u8],
+        channel: ChannelSelect,
+    ) -> Result<MelSpectrogram, WhisperError> {
+        self.compute_mel(&self.read_wav(wav_input, channel)?.0)
+    }
+
+    /// Decodes the selected channel of a 16kHz WAV file into mono samples in `[-1, 1]`, checked
+    /// with `audio::check_format` and corrected with `DecodeOptions::auto_correct_format`.
+    fn read_wav(
+        &self,
+        wav_input: &[u8],
+        channel: ChannelSelect,
+    ) -> Result<(Vec<f32>, Option<FormatCheck>), WhisperError> {
+        let (spec, mut pcm_data) = audio::decode_wav(wav_input)?;
+        log_at!(self.logger, Debug, "wav data: {spec:?}");
+
+        if spec.sample_rate != m::SAMPLE_RATE as u32 {
+            return Err(WhisperError::unsupported_audio(format!(
+                "wav file must have a {} sampling rate",
+                m::SAMPLE_RATE
+            )));
+        }
+        let first_channel = ChannelSelect::Left.extract(&pcm_data, spec.channels)?;
+        let issue = audio::check_format(&first_channel, spec.sample_rate).map(|issue| {
+            let is_16_bit =
+                spec.sample_format == hound::SampleFormat::Int && spec.bits_per_sample == 16;
+            match issue {
+                FormatIssue::ByteSwapped if !is_16_bit => FormatIssue::NoiseLike,
+                issue => issue,
+            }
+        });
+        let corrected = self.options.auto_correct_format
+            && matches!(
+                issue,
+                Some(FormatIssue::ByteSwapped | FormatIssue::MislabeledRate { .. })
+            );
+        // The bytes are swapped before the channels are mixed.
+        if corrected && issue == Some(FormatIssue::ByteSwapped) {
+            pcm_data = audio::swap_bytes_16(&pcm_data);
+        }
+        let mut pcm_data = channel.extract(&pcm_data, spec.channels)?;
+        if let Some(FormatIssue::MislabeledRate { estimated_rate, .. }) =
+            issue.filter(|_| corrected)
+        {
+            pcm_data = audio::resample(&pcm_data, estimated_rate, m::SAMPLE_RATE as u32);
+        }
+        if let Some(issue) = issue {
+            log_at!(
+                self.logger,
+                Warn,
+                "{issue}, {}",
+                if corrected {
+                    "the samples were reinterpreted"
+                } else {
+                    "the transcript may be garbage"
+                }
+            );
+        }
+        log_at!(self.logger, Debug, "pcm data loaded {}", pcm_data.len());
+        Ok((
+            pcm_data,
+            issue.map(|issue| FormatCheck { issue, corrected }),
+        ))
+    }
+
+    fn mel_of(&self, pcm_data: &[f32]) -> Result<MelSpectrogram, WhisperError> {
+        let config = self.model.config();
+        let normalization = self.options.mel_normalization;
+        let mel = if self.options.precise_mel {
+            self.precise_mel_context.borrow_mut().pcm_to_mel_precise(
+                config,
+                pcm_data,
+                &self.mel_filters,
+                normalization,
+            )?
+        } else {
+            self.mel_context.borrow_mut().pcm_to_mel(
+                config,
+                pcm_data,
+                &self.mel_filters,
+                normalization,
+            )?
+        };
+        MelSpectrogram::new(
+            mel,
+            self.model.config().num_mel_bins,
+            pcm_data.len() as f64 / m::SAMPLE_RATE as f64,
+            &self.device,
+        )
+    }
+
+    /// Transcribes a spectrogram computed by [`Decoder::compute_mel`]. The VAD needs the
+    /// samples and is not applied.
+    pub fn run_mel(
+        &mut self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        let start = self.timer();
+        let mut output = self.segments_iter(mel, opts).run_to_end()?;
+        if let Some(timings) = output.timings.as_mut() {
+            timings.total_ms = self.elapsed_ms(start);
+        }
+        Ok(output)
+    }
+
+    /// Transcribes a spectrogram like [`Decoder::run_mel`], one window per call to `next` of
+    /// the iterator, for the callers pulling the segments at their own pace. The segments and
+    /// the failures of the transcription are those of [`SegmentIter::into_output`].
+    ///
+    /// A window that fails under a non-aborting [`SegmentErrorPolicy`] is skipped and the
+    /// next ones can still be pulled, while the first error of any other kind ends the
+    /// iterator.
+    pub fn segments_iter(&mut self, mel: &MelSpectrogram, opts: &RunOptions) -> SegmentIter<'_> {
+        match self.mel_range(mel, opts) {
+            Ok((tensor, time_offset, audio_end)) => {
+                SegmentIter::new(self, &tensor, opts, None, time_offset, audio_end)
+            }
+            Err(err) => SegmentIter::failed(self, err),
+        }
+    }
+
+    /// Checks the bins of `mel` and returns the range of it selected by `opts` on the device
+    /// of the model, with the time offset of the range and the end of the audio within it.
+    fn mel_range(
+        &self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+    ) -> Result<(Tensor, f64, f64), WhisperError> {
+        let num_mel_bins = self.model.config().num_mel_bins;
+        if mel.n_mels() != num_mel_bins {
+            return Err(WhisperError::InvalidConfig {
+                reason: format!(
+                    "mel spectrogram has {} bins but the model expects {num_mel_bins}",
+                    mel.n_mels()
+                ),
+            });
+        }
+        let frames_per_sec = (m::SAMPLE_RATE / m::HOP_LENGTH) as f64;
+        let range = opts.range(mel.n_frames(), frames_per_sec)?;
+        let time_offset = range.start as f64 / frames_per_sec;
+        let tensor = mel
+            .tensor()
+            .narrow(2, range.start, range.len())?
+            .to_device(&self.device)?;
+        let range_end = range.end as f64 / frames_per_sec;
+        let audio_end = f64::min(mel.duration(), range_end) - time_offset;
+        Ok((tensor, time_offset, audio_end))
+    }
+
+    /// Transcribes a spectrogram like [`Decoder::run_mel`], calling `on_checkpoint` after
+    /// every window with a checkpoint from which [`Decoder::resume`] can continue. Returning
+    /// `false` from `on_checkpoint` stops the run with [`WhisperError::Cancelled`].
+    pub fn run_resumable(
+        &mut self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+        on_checkpoint: &mut dyn FnMut(&Checkpoint) -> bool,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        self.run_from_checkpoint(mel, opts, None, on_checkpoint)
+    }
+
+    /// Continues a transcription from `checkpoint`. `mel` and `opts` must be the ones the
+    /// transcription was started with, the mel spectrogram being recomputed or restored with
+    /// [`MelSpectrogram::from_bytes`], and the decode options must not have changed.
+    pub fn resume(
+        &mut self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+        checkpoint: Checkpoint,
+        on_checkpoint: &mut dyn FnMut(&Checkpoint) -> bool,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        self.run_from_checkpoint(mel, opts, Some(checkpoint), on_checkpoint)
+    }
+
+    /// Transcribes a spectrogram like [`Decoder::run_mel`], yielding after the window being
+    /// decoded when `ctl` is raised. The decoder can then run other jobs before the preempted
+    /// one is resumed with [`Decoder::resume_preemptible`].
+    pub fn run_preemptible(
+        &mut self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+        ctl: &PreemptionControl,
+    ) -> Result<RunOutcome, WhisperError> {
+        self.run_until_preempted(mel, opts, None, ctl)
+    }
+
+    /// Continues a preempted transcription, see [`Decoder::resume`].
+    pub fn resume_preemptible(
+        &mut self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+        checkpoint: Checkpoint,
+        ctl: &PreemptionControl,
+    ) -> Result<RunOutcome, WhisperError> {
+        self.run_until_preempted(mel, opts, Some(checkpoint), ctl)
+    }
+
+    fn run_until_preempted(
+        &mut self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+        checkpoint: Option<Checkpoint>,
+        ctl: &PreemptionControl,
+    ) -> Result<RunOutcome, WhisperError> {
+        let mut preempted = None;
+        let mut on_checkpoint = |checkpoint: &Checkpoint| {
+            if ctl.take_request() {
+                preempted = Some(checkpoint.clone());
+            }
+            preempted.is_none()
+        };
+        let result = self.run_from_checkpoint(mel, opts, checkpoint, &mut on_checkpoint);
+        match (result, preempted) {
+            (Err(WhisperError::Cancelled), Some(checkpoint)) => {
+                let frames_per_sec = (m::SAMPLE_RATE / m::HOP_LENGTH) as f64;
+                let time_offset =
+                    opts.range(mel.n_frames(), frames_per_sec)?.start as f64 / frames_per_sec;
+                let mut segments = checkpoint.segments.clone();
+                for segment in segments.iter_mut() {
+                    segment.start += time_offset;
+                }
+                log_at!(
+                    self.logger,
+                    Debug,
+                    "preempted at frame {} of {}",
+                    checkpoint.seek_frame,
+                    checkpoint.n_frames
+                );
+                Ok(RunOutcome::Preempted(checkpoint, segments))
+            }
+            (result, _) => result.map(RunOutcome::Completed),
+        }
+    }
+
+    fn run_from_checkpoint(
+        &mut self,
+        mel: &MelSpectrogram,
+        opts: &RunOptions,
+        checkpoint: Option<Checkpoint>,
+        on_checkpoint: &mut dyn FnMut(&Checkpoint) -> bool,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        let start = self.timer();
+        let (tensor, time_offset, audio_end) = self.mel_range(mel, opts)?;
+        let n_frames = tensor.dim(2)?;
+        let options_hash = self.options_hash(opts)?;
+        if let Some(checkpoint) = &checkpoint {
+            if checkpoint.options_hash != options_hash {
+                return Err(WhisperError::InvalidConfig {
+                    reason: "the checkpoint was created with other options or another model"
+                        .to_string(),
+                });
+            }
+            if checkpoint.n_frames != n_frames {
+                return Err(WhisperError::InvalidConfig {
+                    reason: format!(
+                        "the checkpoint covers {} mel frames, not {n_frames}",
+                        checkpoint.n_frames
+                    ),
+                });
+            }
+        }
+        let Some(tensor) = self.begin_transcription(&tensor, opts)? else {
+            return Ok(self.empty_output());
+        };
+        let mut state = RunState::new(audio_end);
+        let mut rng_seed = match checkpoint {
+            Some(checkpoint) => {
+                state.seek = checkpoint.seek_frame;
+                state.segments = checkpoint.segments;
+                state.failures = checkpoint.failed_segments;
+                self.detected_language = checkpoint.detected_language;
+                self.prompt.set_tokens(checkpoint.prompt_tokens);
+                self.translation_prompt
+                    .set_tokens(checkpoint.translation_prompt_tokens);
+                self.speaker_changes = checkpoint.speaker_changes;
+                checkpoint.rng_seed
+            }
+            None => self.rng.gen(),
+        };
+        self.rng = StdRng::seed_from_u64(rng_seed);
+        while now_or_never(self.run_window(&tensor, None, &mut state, &mut NoopYielder))? {
+            rng_seed = self.rng.gen();
+            self.rng = StdRng::seed_from_u64(rng_seed);
+            let checkpoint = Checkpoint {
+                seek_frame: state.seek,
+                n_frames,
+                segments: state.segments.clone(),
+                failed_segments: state.failures.clone(),
+                detected_language: self.detected_language.clone(),
+                prompt_tokens: self.prompt.as_prompt_tokens().to_vec(),
+                translation_prompt_tokens: self.translation_prompt.as_prompt_tokens().to_vec(),
+                speaker_changes: self.speaker_changes.clone(),
+                rng_seed,
+                options_hash,
+            };
+            if !on_checkpoint(&checkpoint) {
+                return Err(WhisperError::Cancelled);
+            }
+        }
+        let mut output = self.finish_transcription(state, time_offset);
+        if let Some(timings) = output.timings.as_mut() {
+            timings.total_ms = self.elapsed_ms(start);
+        }
+        Ok(output)
+    }
+
+    /// FNV-1a hash of everything that affects the decoding of a run, stable across builds so
+    /// that checkpoints can be stored.
+    fn options_hash(&self, opts: &RunOptions) -> Result<u64, WhisperError> {
+        let info = &self.model_info;
+        let key = serde_json::to_vec(&(
+            &self.options,
+            opts,
+            self.task,
+            self.pinned_language(),
+            self.timestamps,
+            (info.tensor_count, info.parameter_bytes, &info.quantization),
+        ))
+        .map_err(anyhow::Error::from)?;
+        Ok(key.iter().fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+        }))
+    }
+
+    fn transcribe_mel(
+        &mut self,
+        mel: &Tensor,
+        opts: &RunOptions,
+        speech_regions: Option<&[(f64, f64)]>,
+        time_offset: f64,
+        audio_end: f64,
+    ) -> Result<TranscriptionOutput, WhisperError> {
+        SegmentIter::new(self, mel, opts, speech_regions, time_offset, audio_end).run_to_end()
+    }
+
+    /// Resets the per-file state, returns `mel` in the model dtype or `None` when it is empty.
+    fn begin_transcription(
+        &mut self,
+        mel: &Tensor,
+        opts: &RunOptions,
+    ) -> Result<Option<Tensor>, WhisperError> {
+        if mel.dim(2)? == 0 {
+            return Ok(None);
+        }
+        self.run_timestamps = opts.timestamps.unwrap_or(self.timestamps);
+        let mel = mel.to_dtype(self.dtype)?;
+        self.reset_state();
+        self.timings = self.options.collect_timings.then(Timings::default);
+        self.diagnostics = self.options.collect_diagnostics.then(|| RunDiagnostics {
+            mel_normalization: self.options.mel_normalization,
+            model_profile: self.model_info.profile,
+            ..Default::default()
+        });
+        self.run_deadline_ms = self
+            .options
+            .max_total_seconds
+            .map(|seconds| self.clock.now_ms() + seconds * 1000.);
+        Ok(Some(mel))
+    }
+
+    fn finish_transcription(&mut self, state: RunState, time_offset: f64) -> TranscriptionOutput {
+        let (mut segments, mut failed_segments) = self.finish_run(state);
+        for segment in segments.iter_mut() {
+            segment.start += time_offset;
+        }
+        for failure in failed_segments.iter_mut() {
+            failure.start += time_offset;
+        }
+        let detection = self.detected_language.take();
+        TranscriptionOutput {
+            task: self.task.unwrap_or(Task::Transcribe),
+            language: self
+                .pinned_language()
+                .or(detection.as_ref().map(|d| d.language.clone())),
+            language_probs: detection.map(|d| d.probs).unwrap_or_default(),
+            segments,
+            failed_segments,
+            timings: self.timings.take(),
+            config: self
+                .diagnostics
+                .is_some()
+                .then(|| Box::new(self.config_snapshot())),
+            diagnostics: self.diagnostics.take(),
+            no_speech_detected: false,
+            too_short: false,
+            silence_trim: None,
+        }
+    }
+
+    fn empty_output(&self) -> TranscriptionOutput {
+        TranscriptionOutput {
+            task: self.task.unwrap_or(Task::Transcribe),
+            language: self.pinned_language(),
+            language_probs: vec![],
+            segments: vec![],
+            failed_segments: vec![],
+            timings: None,
+            diagnostics: None,
+            config: None,
+            no_speech_detected: false,
+            too_short: false,
+            silence_trim: None,
+        }
+    }
+
+    /// Empty output flagged `too_short`.
+    fn too_short_output(&self) -> TranscriptionOutput {
+        TranscriptionOutput {
+            too_short: true,
+            ..self.empty_output()
+        }
+    }
+
+    /// Empty output flagged `no_speech_detected` when the mel of the audio has too little
+    /// speech energy to be worth decoding.
+    fn no_speech_output(
+        &self,
+        mel: &MelSpectrogram,
+    ) -> Result<Option<TranscriptionOutput>, WhisperError> {
+        let Some(min_ratio) = self.options.min_speech_energy_ratio else {
+            return Ok(None);
+        };
+        let ratio = mel.speech_energy_ratio()?;
+        if ratio >= min_ratio {
+            return Ok(None);
+        }
+        log_at!(
+            self.logger,
+            Debug,
+            "speech energy ratio {ratio} below {min_ratio}, skipping"
+        );
+        Ok(Some(TranscriptionOutput {
+            no_speech_detected: true,
+            ..self.empty_output()
+        }))
+    }
+}
+
+/// Serde of the byte buffers as bytes, like `serde_bytes`, the deserialization also accepting
+/// the arrays of integers of the JSON.
+mod byte_buf {
+    use serde::{
+        de::{SeqAccess, Visitor},
+        Deserializer, Serializer,
+    };
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+
+    struct ByteBufVisitor;
+
+    impl<'de> Visitor<'de> for ByteBufVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("bytes or an array of bytes")
+        }
+
+        fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_string<E>(self, s: String) -> Result<Vec<u8>, E> {
+            Ok(s.into_bytes())
+        }
+
+        fn visit_str<E>(self, s: &str) -> Result<Vec<u8>, E> {
+            Ok(s.as_bytes().to_vec())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+pub(crate) fn f64_or_nan<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN))
+}
+
+/// The `[start, end)` range in seconds of the speech detected in a window, `None` when the
+/// speech totals less than `min_speech` or the range covers the whole window, in which case
+/// decoding it again would not change the result.
+fn speech_span(
+    regions: &[(f64, f64)],
+    start: f64,
+    end: f64,
+    min_speech: f64,
+) -> Option<(f64, f64)> {
+    let speech = audio::clip_regions(regions, start, end);
+    let total: f64 = speech.iter().map(|(start, end)| end - start).sum();
+    let span = (speech.first()?.0, speech.last()?.1);
+    // Less than a second of silence around the speech.
+    let whole_window = (span.0 - start) + (end - span.1) < 1.;
+    (total >= min_speech && !whole_window).then_some(span)
+}
+
+/// Probability of the no-speech token at the position of the SOT token in the decoder output
+/// `ys`.
+fn no_speech_probability(
+    model: &Model,
+    ys: &Tensor,
+    sot_index: usize,
+    no_speech: u32,
+) -> candle_core::Result<f64> {
+    let logits = model
+        .decoder_final_linear(&ys.i((..1, sot_index..sot_index + 1))?)?
+        .i(0)?
+        .i(0)?;
+    Ok(softmax(&logits, 0)?
+        .i(no_speech as usize)?
+        .to_scalar::<f32>()? as f64)
+}
+
+/// `frames` mel frames of `mel` from `start`, padded with the lowest value of the window, i.e.
+/// silence, to the 30 seconds the encoder is trained on.
+fn padded_window(mel: &Tensor, start: usize, frames: usize) -> candle_core::Result<Tensor> {
+    let speech = mel.narrow(2, start, frames)?;
+    if frames >= m::N_FRAMES {
+        return speech.narrow(2, 0, m::N_FRAMES);
+    }
+    let (batch, n_mels, _) = speech.dims3()?;
+    let floor = speech
+        .flatten_all()?
+        .min(0)?
+        .to_dtype(DType::F64)?
+        .to_scalar::<f64>()?;
+    let padding = Tensor::ones(
+        (batch, n_mels, m::N_FRAMES - frames),
+        speech.dtype(),
+        speech.device(),
+    )?
+    .affine(0., floor)?;
+    Tensor::cat(&[&speech, &padding], 2)
+}
+
+/// Ends the last of `segments` at `time` when its window overlaps the next one.
+fn clip_overlap(segments: &mut [Segment], time: f64) {
+    if let Some(previous) = segments.last_mut() {
+        if previous.start + previous.duration > time {
+            previous.duration = f64::max(0., time - previous.start)
+        }
+    }
+}
+
+/// Number of mel frames consumed by a decoded window: when the tokens end with a pair of
+/// timestamp tokens the window is only consumed up to the first timestamp of that pair so that
+/// the speech following it is decoded again as part of the next window.
+fn timestamp_seek_advance(
+    tokens: &[u32],
+    timestamp_begin: u32,
+    eot_token: u32,
+    segment_size: usize,
+) -> usize {
+    // Each timestamp token accounts for 20ms, i.e. two mel frames.
+    let input_stride = 2;
+    let tokens = match tokens.last() {
+        Some(&t) if t == eot_token => &tokens[..tokens.len() - 1],
+        _ => tokens,
+    };
+    match tokens {
+        [.., t1, t2] if *t1 >= timestamp_begin && *t2 >= timestamp_begin => {
+            let advance = (t1 - timestamp_begin) as usize * input_stride;
+            if advance == 0 {
+                segment_size
+            } else {
+                usize::min(advance, segment_size)
+            }
+        }
+        _ => segment_size,
+    }
+}
+
+/// Score of a decoding result ranking it against the other candidates of a window, from the sum
+/// of the log probabilities of its `length` tokens.
+///
+/// Without `length_penalty` this is the mean log probability. With it the sum is divided by the
+/// GNMT length normalization `((5 + length) / 6) ^ length_penalty`, penalties above 1 favouring
+/// the longer results more than the mean does.
+pub fn sequence_score(sum_logprob: f64, length: usize, length_penalty: Option<f64>) -> f64 {
+    match length_penalty {
+        Some(penalty) => sum_logprob / ((5. + length as f64) / 6.).powf(penalty),
+        None => sum_logprob / length.max(1) as f64,
+    }
+}
+
+/// Ratio of the size of `text` in UTF-8 to its size compressed with zlib, as in openai/whisper.
+/// Repetition loops compress well and have a high ratio.
+pub fn compression_ratio(text: &str) -> f64 {
+    use std::io::Write;
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    // Writing to a vector does not fail.
+    let compressed = encoder
+        .write_all(text.as_bytes())
+        .and_then(|()| encoder.finish())
+        .expect("in-memory compression");
+    text.len() as f64 / compressed.len() as f64
+}
+
+/// Index of the largest logit.
+fn argmax(logits: &[f32]) -> u32 {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|(_, u), (_, v)| u.total_cmp(v))
+        .map(|(i, _)| i as u32)
+        .unwrap()
+}
+
+/// Index sampled from `weights` among the ones kept by `top_k` and `top_p`, the distribution
+/// being built over the kept indices only. The NaN, infinite and negative weights are zeroed
+/// first. `None` when less than [`MIN_SAMPLING_MASS`] is left to sample from.
+pub fn sample_weights(
+    weights: &mut [f64],
+    top_k: Option<usize>,
+    top_p: Option<f64>,
+    rng: &mut impl Rng,
+) -> Option<usize> {
+    for weight in weights.iter_mut() {
+        if !(weight.is_finite() && *weight > 0.) {
+            *weight = 0.;
+        }
+    }
+    let kept = truncated_indices(weights, top_k, top_p);
+    let mass: f64 = match &kept {
+        Some(kept) => kept.iter().map(|&i| weights[i]).sum(),
+        None => weights.iter().sum(),
+    };
+    if mass < MIN_SAMPLING_MASS {
+        return None;
+    }
+    match kept {
+        Some(kept) => {
+            let distr = WeightedIndex::new(kept.iter().map(|&i| weights[i])).ok()?;
+            Some(kept[distr.sample(rng)])
+        }
+        None => Some(WeightedIndex::new(&*weights).ok()?.sample(rng)),
+    }
+}
+
+/// Indices of the weights kept by `truncate_distribution`, `None` without truncation.
+fn truncated_indices(
+    weights: &[f64],
+    top_k: Option<usize>,
+    top_p: Option<f64>,
+) -> Option<Vec<usize>> {
+    if top_k.is_none() && top_p.is_none() {
+        return None;
+    }
+    let by_weight = |a: &usize, b: &usize| weights[*b].total_cmp(&weights[*a]);
+    let mut kept: Vec<usize> = match top_k {
+        Some(k) if k < weights.len() => {
+            let mut order: Vec<usize> = (0..weights.len()).collect();
+            // Only the boundary is placed, a full sort of the vocabulary is not needed.
+            order.select_nth_unstable_by(k.max(1) - 1, by_weight);
+            order.truncate(k.max(1));
+            order
+        }
+        _ => (0..weights.len()).filter(|&i| weights[i] > 0.).collect(),
+    };
+    if let Some(p) = top_p {
+        kept.sort_unstable_by(by_weight);
+        let total: f64 = kept.iter().map(|&i| weights[i]).sum();
+        let mut cumulative = 0.;
+        let mut len = kept.len();
+        for (n, &i) in kept.iter().enumerate() {
+            cumulative += weights[i];
+            if cumulative >= (p - TOP_P_TOLERANCE) * total {
+                len = n + 1;
+                break;
+            }
+        }
+        kept.truncate(len.max(1));
+    }
+    Some(kept)
+}
+
+/// Zeroes the weights outside of the `top_k` largest ones and then outside of the smallest set
+/// of largest ones holding a `top_p` share of the remaining mass, and normalizes the kept ones.
+/// At least the largest weight is kept.
+pub fn truncate_distribution(weights: &mut [f64], top_k: Option<usize>, top_p: Option<f64>) {
+    let Some(kept) = truncated_indices(weights, top_k, top_p) else {
+        return;
+    };
+    let total: f64 = kept.iter().map(|&i| weights[i]).sum();
+    let mut normalized = vec![0.; weights.len()];
+    for &i in &kept {
+        normalized[i] = weights[i] / total;
+    }
+    weights.copy_from_slice(&normalized);
+}
+
+/// Additive logits mask of size `vocab_size` applied until the first timestamp of a window is
+/// sampled, `-inf` for the timestamps after `max_initial_timestamp` seconds or after the
+/// `window_audio` seconds of audio of the window. `None` when every timestamp is allowed.
+pub fn build_initial_timestamp_mask(
+    vocab_size: usize,
+    timestamp_begin: u32,
+    max_initial_timestamp: Option<f64>,
+    window_audio: f64,
+) -> Option<Vec<f32>> {
+    let seconds = f64::min(max_initial_timestamp?, window_audio).max(0.);
+    let step = (2 * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+    let first_masked = timestamp_begin as usize + (seconds / step).round() as usize + 1;
+    if first_masked >= vocab_size {
+        return None;
+    }
+    let mut mask = vec![0f32; vocab_size];
+    mask[first_masked..].fill(f32::NEG_INFINITY);
+    Some(mask)
+}
+
+/// Additive logits mask of size `vocab_size`, `-inf` for the tokens of `config_suppress` and
+/// `extra` that are not in `allow`, 0 elsewhere. Out of vocabulary ids are ignored.
+pub fn build_suppression_mask(
+    vocab_size: usize,
+    config_suppress: &[u32],
+    extra: &[u32],
+    allow: &[u32],
+) -> Vec<f32> {
+    let mut mask = vec![0f32; vocab_size];
+    for &token in config_suppress.iter().chain(extra.iter()) {
+        if !allow.contains(&token) {
+            if let Some(v) = mask.get_mut(token as usize) {
+                *v = f32::NEG_INFINITY
+            }
+        }
+    }
+    mask
+}
+
+/// Symbols of the sound descriptions and annotations the whisper models learnt from the
+/// subtitles, see [`default_suppress_tokens`].
+const NON_SPEECH_SYMBOLS: &[&str] = &[
+    "\"",
+    "#",
+    "(",
+    ")",
+    "*",
+    "+",
+    "/",
+    ":",
+    ";",
+    "<",
+    "=",
+    ">",
+    "@",
+    "[",
+    "\\",
+    "]",
+    "^",
+    "_",
+    "`",
+    "{",
+    "|",
+    "}",
+    "~",
+    "「",
+    "」",
+    "『",
+    "』",
+    "<<",
+    ">>",
+    "<<<",
+    ">>>",
+    "--",
+    "---",
+    "-(",
+    "-[",
+    "('",
+    "(\"",
+    "((",
+    "))",
+    "(((",
+    ")))",
+    "[[",
+    "]]",
+    "{{",
+    "}}",
+    "♪♪",
+    "♪♪♪",
+];
+
+/// Musical symbols, whose first token is suppressed even when they take several.
+const MUSICAL_SYMBOLS: &[&str] = &["♩", "♪", "♫", "♬", "♭", "♮", "♯"];
+
+/// Suppress list of the models whose config has none, built from the tokenizer as
+/// openai/whisper builds its default one: the tokens of the non-speech symbols, alone or after
+/// a space, that take a single token, the first token of the musical symbols, the tokens of
+/// `" -"` and `" '"`, and the control tokens. The symbols the tokenizer cannot encode back, e.g.
+/// with its unknown token, are left out. Sorted.
+pub fn default_suppress_tokens(tokenizer: &Tokenizer, special_tokens: &SpecialTokens) -> Vec<u32> {
+    // Tokens of `text`, when they decode back to it.
+    let encode = |text: &str| {
+        let ids = tokenizer.encode(text, false).ok()?.get_ids().to_vec();
+        let decoded = tokenizer.decode(&ids, false).ok()?;
+        (!ids.is_empty() && decoded.trim() == text.trim()).then_some(ids)
+    };
+    // The space before a symbol may be a token of its own, which must not be suppressed.
+    let is_blank = |token: u32| {
+        tokenizer
+            .decode(&[token], false)
+            .map_or(true, |text| text.trim().is_empty())
+    };
+    let mut tokens = vec![];
+    for symbol in [" -", " '"] {
+        match encode(symbol) {
+            Some(ids) if !is_blank(ids[0]) => tokens.push(ids[0]),
+            _ => {}
+        }
+    }
+    for &symbol in NON_SPEECH_SYMBOLS.iter().chain(MUSICAL_SYMBOLS) {
+        let musical = MUSICAL_SYMBOLS.contains(&symbol);
+        for text in [symbol.to_string(), format!(" {symbol}")] {
+            match encode(&text) {
+                Some(ids) if (ids.len() == 1 || musical) && !is_blank(ids[0]) => {
+                    tokens.push(ids[0])
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens.extend(
+        [
+            Some(special_tokens.sot),
+            special_tokens.transcribe,
+            special_tokens.translate,
+            special_tokens.start_of_prev,
+            token_id(tokenizer, "<|startoflm|>").ok(),
+            Some(special_tokens.no_speech),
+        ]
+        .into_iter()
+        .flatten(),
+    );
+    tokens.sort_unstable();
+    tokens.dedup();
+    tokens
+}
+
+/// Checks that the model and its tokenizer support the task.
+fn check_task(
+    task: Option<Task>,
+    is_multilingual: bool,
+    special_tokens: &SpecialTokens,
+) -> Result<(), WhisperError> {
+    let missing = match task {
+        Some(Task::Translate | Task::Both) if !is_multilingual => {
+            return Err(WhisperError::InvalidConfig {
+                reason: "the translate task requires a multilingual model".to_string(),
+            })
+        }
+        _ if !is_multilingual => return Ok(()),
+        Some(Task::Translate | Task::Both) if special_tokens.translate.is_none() => {
+            m::TRANSLATE_TOKEN
+        }
+        None | Some(Task::Transcribe | Task::Both) if special_tokens.transcribe.is_none() => {
+            m::TRANSCRIBE_TOKEN
+        }
+        _ => return Ok(()),
+    };
+    Err(WhisperError::InvalidConfig {
+        reason: format!("the tokenizer of the multilingual model has no {missing} token"),
+    })
+}
+
+/// Text of the text tokens among `tokens` up to the last complete character, the last tokens
+/// of a byte-level tokenizer possibly ending inside a character.
+fn complete_text(tokenizer: &Tokenizer, tokens: &[u32], eot: u32) -> anyhow::Result<String> {
+    let mut text_tokens: Vec<u32> = tokens.iter().copied().filter(|&t| t < eot).collect();
+    // A character has at most 4 bytes, the replacement characters of the broken ones are
+    // dropped with the tokens they come from.
+    for _ in 0..4 {
+        let text = tokenizer.decode(&text_tokens, true).map_err(E::msg)?;
+        if !text.ends_with(char::REPLACEMENT_CHARACTER) {
+            return Ok(text);
+        }
+        text_tokens.pop();
+    }
+    let text = tokenizer.decode(&text_tokens, true).map_err(E::msg)?;
+    Ok(text
+        .trim_end_matches(char::REPLACEMENT_CHARACTER)
+        .to_string())
+}
+
+/// Masks the text tokens, below `eot`, other than `candidates`.
+fn restrict_text_tokens(logits: &mut [f32], candidates: &[u32], eot: u32) {
+    let kept: Vec<(usize, f32)> = candidates
+        .iter()
+        .filter_map(|&token| Some((token as usize, *logits.get(token as usize)?)))
+        .collect();
+    let text_tokens = usize::min(eot as usize, logits.len());
+    logits[..text_tokens].fill(f32::NEG_INFINITY);
+    for (token, logit) in kept {
+        logits[token] = logit;
+    }
+}
+
+/// Checks the options of a language profile applied to `base`.
+fn check_profile(
+    language: &str,
+    profile: &PartialDecodeOptions,
+    base: &DecodeOptions,
+) -> Result<(), WhisperError> {
+    profile
+        .apply_to(base)
+        .validate()
+        .map_err(|err| WhisperError::InvalidConfig {
+            reason: format!("profile of {language}: {err}"),
+        })
+}
+
+/// Checks that every code is one of the supported languages.
+fn check_languages(languages: &[String]) -> Result<(), WhisperError> {
+    match languages
+        .iter()
+        .find(|l| languages::canonicalize(l).is_none())
+    {
+        Some(lang) => Err(WhisperError::LanguageNotSupported { lang: lang.clone() }),
+        None => Ok(()),
+    }
+}
+
+/// Detects the spoken language from the first window of `mel`, running the encoder on it.
+/// When `allowed` is set only these languages are candidates, their probabilities are
+/// renormalized over the subset.
+pub fn detect(
+    model: &mut Model,
+    tokenizer: &Tokenizer,
+    mel: &Tensor,
+    allowed: Option<&[String]>,
+) -> Result<LanguageDetection, WhisperError> {
+    if let Some(allowed) = allowed {
+        check_languages(allowed)?;
+    }
+    let (_bsize, _, seq_len) = mel.dims3()?;
+    let mel = mel.narrow(
+        2,
+        0,
+        usize::min(seq_len, model.config().max_source_positions),
+    )?;
+    let audio_features = model.encoder_forward(&mel, true)?;
+    detect_from_features(model, tokenizer, &audio_features, allowed)
+}
+
+/// Detects the spoken language from the encoder output of a window.
+pub fn detect_from_features(
+    model: &mut Model,
+    tokenizer: &Tokenizer,
+    audio_features: &Tensor,
+    allowed: Option<&[String]>,
+) -> Result<LanguageDetection, WhisperError> {
+    if let Some(allowed) = allowed {
+        check_languages(allowed)?;
+    }
+    let device = audio_features.device();
+    let languages: Vec<&str> = LANGUAGES
+        .iter()
+        .map(|l| l.code)
+        .filter(|code| {
+            allowed.is_none_or(|allowed| {
+                allowed
+                    .iter()
+                    .any(|l| languages::canonicalize(l) == Some(*code))
+            })
+        })
+        .collect();
+    let language_token_ids = languages
+        .iter()
+        .map(|t| token_id(tokenizer, &format!("<|{t}|>")))
+        .map(|e| e.map_err(E::msg))
+        .collect::<Result<Vec<_>, E>>()?;
+    let sot_token = token_id(tokenizer, m::SOT_TOKEN)?;
+    let tokens = Tensor::new(&[[sot_token]], device)?;
+    let language_token_ids_t = Tensor::new(language_token_ids.as_slice(), device)?;
+    let ys = model.decoder_forward(&tokens, audio_features, true)?;
+    let logits = model.decoder_final_linear(&ys.i(..1)?)?.i(0)?.i(0)?;
+    let logits = logits.index_select(&language_token_ids_t, 0)?;
+    let probs = candle_nn::ops::softmax(&logits, D::Minus1)?;
+    let probs = probs.to_vec1::<f32>()?;
+    let mut ranked: Vec<(usize, f32)> = probs.into_iter().enumerate().collect();
+    ranked.sort_by(|(_, p1), (_, p2)| p2.total_cmp(p1));
+    let (best, _) = ranked[0];
+    let detection = LanguageDetection {
+        token: language_token_ids[best],
+        language: languages[best].to_string(),
+        probs: ranked
+            .into_iter()
+            .map(|(i, prob)| LanguageProb {
+                language: languages[i].to_string(),
+                prob,
+            })
+            .collect(),
+    };
+    Ok(detection)
 }
 
 pub fn token_id(tokenizer: &Tokenizer, token: &str) -> candle_core::Result<u32> {
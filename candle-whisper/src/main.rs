@@ -1,6 +1,41 @@
-use candle_whisper::logic::{Decoder as D, ModelData};
+use candle_whisper::{
+    audio::{self, MelSpectrogram},
+    error::WhisperError,
+    languages,
+    logic::{Checkpoint, DecodeOptions, Decoder as D, ModelData, RunOptions, RunOutcome, Task},
+    preemption::PreemptionControl,
+    snapshot::ConfigSnapshot,
+    timings::Timings,
+};
 use wasm_bindgen::prelude::*;
 
+/// The message of the thrown error is the JSON representation of the `WhisperError`, so that
+/// the worker can forward its `code` to the main thread.
+fn js_error(e: WhisperError) -> JsError {
+    JsError::new(&serde_json::to_string(&e).unwrap_or_else(|_| e.to_string()))
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Atomics, js_name = load)]
+    fn atomics_load(array: &JsValue, index: u32) -> i32;
+}
+
+/// `Int32Array` over a `SharedArrayBuffer` whose first element the main thread sets with
+/// `Atomics.store` to preempt the transcription.
+struct SharedFlag(JsValue);
+
+// SAFETY: the module is built without the atomics target feature, it only ever runs on the
+// thread of its worker, the JS side writing the flag through the shared buffer.
+unsafe impl Send for SharedFlag {}
+unsafe impl Sync for SharedFlag {}
+
+/// JSON array of the languages of the multilingual models, with their codes and names.
+#[wasm_bindgen(js_name = supportedLanguages)]
+pub fn supported_languages() -> Result<String, JsError> {
+    Ok(serde_json::to_string(languages::supported_languages())?)
+}
+
 #[wasm_bindgen]
 pub struct Decoder {
     decoder: D,
@@ -31,23 +66,272 @@ impl Decoder {
             timestamps,
             task,
             language,
+            dtype: None,
+            quantize_on_load: None,
+            manifest: None,
         });
 
         match decoder {
             Ok(decoder) => Ok(Self { decoder }),
-            Err(e) => Err(JsError::new(&e.to_string())),
+            Err(e) => Err(js_error(e)),
         }
     }
 
+    #[wasm_bindgen(js_name = modelInfo)]
+    pub fn model_info(&self) -> Result<String, JsError> {
+        let json = serde_json::to_string(self.decoder.model_info())?;
+        Ok(json)
+    }
+
+    /// Audio features of 16kHz mono samples, flattened from `(windows, n_audio_ctx, d_model)`
+    /// with one window per 30 seconds.
+    #[wasm_bindgen(js_name = encodeAudio)]
+    pub fn encode_audio(&mut self, pcm: Vec<f32>) -> Result<Vec<f32>, JsError> {
+        let (features, _) = self.decoder.encode_audio_vec(&pcm).map_err(js_error)?;
+        Ok(features)
+    }
+
+    /// Probability that a short chunk of 16kHz mono samples holds speech, without decoding it.
+    #[wasm_bindgen(js_name = speechProbability)]
+    pub fn speech_probability(&mut self, pcm: Vec<f32>) -> Result<f32, JsError> {
+        self.decoder.speech_probability(&pcm).map_err(js_error)
+    }
+
+    /// Mean-pooled audio features of 16kHz mono samples, flattened from `(windows, d_model)`.
+    pub fn embedding(&mut self, pcm: Vec<f32>) -> Result<Vec<f32>, JsError> {
+        let embedding = self.decoder.embedding(&pcm).map_err(js_error)?;
+        embedding
+            .flatten_all()
+            .and_then(|e| e.to_vec1())
+            .map_err(|e| js_error(e.into()))
+    }
+
+    /// Size of the vectors returned by `embedding`.
+    #[wasm_bindgen(js_name = embeddingSize)]
+    pub fn embedding_size(&self) -> usize {
+        self.decoder.config().d_model
+    }
+
+    #[wasm_bindgen(js_name = encodeText)]
+    pub fn encode_text(&self, text: &str) -> Result<Vec<u32>, JsError> {
+        self.decoder.encode_text(text).map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = decodeTokens)]
+    pub fn decode_tokens(&self, tokens: Vec<u32>, skip_special: bool) -> Result<String, JsError> {
+        self.decoder
+            .decode_tokens(&tokens, skip_special)
+            .map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = specialTokens)]
+    pub fn special_tokens(&self) -> Result<String, JsError> {
+        let json = serde_json::to_string(&self.decoder.special_tokens())?;
+        Ok(json)
+    }
+
+    #[wasm_bindgen(js_name = setOptions)]
+    pub fn set_options(&mut self, options: String) -> Result<(), JsError> {
+        let options: DecodeOptions = serde_json::from_str(&options)?;
+        self.decoder.set_options(options).map_err(js_error)
+    }
+
+    /// JSON of the configuration of the decoder, for the bug reports.
+    #[wasm_bindgen(js_name = configSnapshot)]
+    pub fn config_snapshot(&self) -> Result<String, JsError> {
+        let json = serde_json::to_string(&self.decoder.config_snapshot())?;
+        Ok(json)
+    }
+
+    /// Restores a configuration snapshot, returns the JSON of the fields not restored.
+    #[wasm_bindgen(js_name = applySnapshot)]
+    pub fn apply_snapshot(&mut self, snapshot: String) -> Result<String, JsError> {
+        let snapshot: ConfigSnapshot = serde_json::from_str(&snapshot)?;
+        let json = serde_json::to_string(&self.decoder.apply_snapshot(&snapshot))?;
+        Ok(json)
+    }
+
+    /// Replaces the model in place, keeping the options set on the decoder.
+    #[wasm_bindgen(js_name = swapModel)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn swap_model(
+        &mut self,
+        weights: Vec<u8>,
+        tokenizer: Vec<u8>,
+        mel_filters: Vec<u8>,
+        config: Vec<u8>,
+        quantized: bool,
+        is_multilingual: bool,
+        timestamps: bool,
+        task: Option<String>,
+        language: Option<String>,
+    ) -> Result<(), JsError> {
+        self.decoder
+            .swap_model(ModelData {
+                tokenizer,
+                mel_filters,
+                config,
+                quantized,
+                weights,
+                is_multilingual,
+                timestamps,
+                task,
+                language,
+                dtype: None,
+                quantize_on_load: None,
+                manifest: None,
+            })
+            .map_err(js_error)
+    }
+
+    /// Properties of the WAV file and the estimated transcription time in seconds.
+    #[wasm_bindgen(js_name = probeWav)]
+    pub fn probe_wav(&self, wav_input: Vec<u8>) -> Result<String, JsError> {
+        let info = audio::probe_wav(&wav_input).map_err(js_error)?;
+        let estimated_secs = self.decoder.estimate_runtime(info.duration_secs);
+        let json = serde_json::to_string(&serde_json::json!({
+            "info": info,
+            "estimated_secs": estimated_secs,
+        }))?;
+        Ok(json)
+    }
+
+    /// Calibrates the estimates of `probeWav` from the `timings` of a previous run.
+    #[wasm_bindgen(js_name = calibrateRuntime)]
+    pub fn calibrate_runtime(
+        &mut self,
+        timings: String,
+        duration_secs: f64,
+    ) -> Result<(), JsError> {
+        let timings: Timings = serde_json::from_str(&timings)?;
+        self.decoder.calibrate_runtime(&timings, duration_secs);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = setTask)]
+    pub fn set_task(&mut self, task: String) -> Result<(), JsError> {
+        let task = task.parse::<Task>().map_err(|e| {
+            js_error(WhisperError::InvalidConfig {
+                reason: e.to_string(),
+            })
+        })?;
+        self.decoder.set_task(task).map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = clearEncoderCache)]
+    pub fn clear_encoder_cache(&mut self) {
+        self.decoder.clear_encoder_cache()
+    }
+
     #[wasm_bindgen]
     pub fn decode(&mut self, wav_input: Vec<u8>) -> Result<String, JsError> {
-        let segments = self
+        let output = self.decoder.convert_and_run(&wav_input).map_err(js_error)?;
+        let json = serde_json::to_string(&output)?;
+        Ok(json)
+    }
+
+    #[wasm_bindgen(js_name = decodeWithOptions)]
+    pub fn decode_with_options(
+        &mut self,
+        wav_input: Vec<u8>,
+        options: String,
+    ) -> Result<String, JsError> {
+        let options: RunOptions = serde_json::from_str(&options)?;
+        let output = self
             .decoder
-            .convert_and_run(&wav_input)
-            .map_err(|e| JsError::new(&e.to_string()))?;
-        let json = serde_json::to_string(&segments)?;
+            .convert_and_run_with_options(&wav_input, &options)
+            .map_err(js_error)?;
+        let json = serde_json::to_string(&output)?;
         Ok(json)
     }
+
+    /// Mel spectrogram of the WAV file, serialized so that it can be cached.
+    #[wasm_bindgen(js_name = computeMel)]
+    pub fn compute_mel(&self, wav_input: Vec<u8>) -> Result<Vec<u8>, JsError> {
+        let mel = self.decoder.convert_to_mel(&wav_input).map_err(js_error)?;
+        mel.to_bytes().map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = decodeMel)]
+    pub fn decode_mel(&mut self, mel: Vec<u8>, options: String) -> Result<String, JsError> {
+        let options: RunOptions = serde_json::from_str(&options)?;
+        let mel = MelSpectrogram::from_bytes(&mel, self.decoder.device()).map_err(js_error)?;
+        let output = self.decoder.run_mel(&mel, &options).map_err(js_error)?;
+        let json = serde_json::to_string(&output)?;
+        Ok(json)
+    }
+
+    /// Decodes at most `max_windows` windows of `mel`, starting from `checkpoint` when given.
+    /// Returns `{"checkpoint": ...}` to pass to the next call while the transcription is not
+    /// over, `{"output": ...}` once it is.
+    #[wasm_bindgen(js_name = decodeMelResumable)]
+    pub fn decode_mel_resumable(
+        &mut self,
+        mel: Vec<u8>,
+        options: String,
+        checkpoint: Option<String>,
+        max_windows: u32,
+    ) -> Result<String, JsError> {
+        let options: RunOptions = serde_json::from_str(&options)?;
+        let mel = MelSpectrogram::from_bytes(&mel, self.decoder.device()).map_err(js_error)?;
+        let mut windows = 0;
+        let mut last = None;
+        let mut on_checkpoint = |checkpoint: &Checkpoint| {
+            windows += 1;
+            last = Some(checkpoint.clone());
+            windows < max_windows
+        };
+        let result = match checkpoint {
+            Some(checkpoint) => {
+                let checkpoint: Checkpoint = serde_json::from_str(&checkpoint)?;
+                self.decoder
+                    .resume(&mel, &options, checkpoint, &mut on_checkpoint)
+            }
+            None => self
+                .decoder
+                .run_resumable(&mel, &options, &mut on_checkpoint),
+        };
+        let json = match (result, last) {
+            (Err(WhisperError::Cancelled), Some(checkpoint)) => {
+                serde_json::json!({ "checkpoint": checkpoint })
+            }
+            (result, _) => serde_json::json!({ "output": result.map_err(js_error)? }),
+        };
+        Ok(serde_json::to_string(&json)?)
+    }
+
+    /// Decodes `mel` from `checkpoint` when given, until the transcription is over or the first
+    /// element of `flag`, an `Int32Array` over a `SharedArrayBuffer`, is set. Returns
+    /// `{"checkpoint": ..., "segments": [...]}` when preempted, the caller clearing the flag
+    /// before resuming, `{"output": ...}` once the transcription is over.
+    #[wasm_bindgen(js_name = decodeMelPreemptible)]
+    pub fn decode_mel_preemptible(
+        &mut self,
+        mel: Vec<u8>,
+        options: String,
+        checkpoint: Option<String>,
+        flag: JsValue,
+    ) -> Result<String, JsError> {
+        let options: RunOptions = serde_json::from_str(&options)?;
+        let mel = MelSpectrogram::from_bytes(&mel, self.decoder.device()).map_err(js_error)?;
+        let flag = SharedFlag(flag);
+        let ctl = PreemptionControl::with_external(move || atomics_load(&flag.0, 0) != 0);
+        let outcome = match checkpoint {
+            Some(checkpoint) => {
+                let checkpoint: Checkpoint = serde_json::from_str(&checkpoint)?;
+                self.decoder
+                    .resume_preemptible(&mel, &options, checkpoint, &ctl)
+            }
+            None => self.decoder.run_preemptible(&mel, &options, &ctl),
+        };
+        let json = match outcome.map_err(js_error)? {
+            RunOutcome::Preempted(checkpoint, segments) => {
+                serde_json::json!({ "checkpoint": checkpoint, "segments": segments })
+            }
+            RunOutcome::Completed(output) => serde_json::json!({ "output": output }),
+        };
+        Ok(serde_json::to_string(&json)?)
+    }
 }
 
 fn main() {}
@@ -0,0 +1,214 @@
+use crate::{logic::Config, model_profile::ModelProfile};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use candle_core::quantized::gguf_file;
+
+/// Summary of a weights file, obtained by reading its header only.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub quantized: bool,
+    /// Quantization type used by most of the weight matrices, e.g. `Q4_0` or `Q8_0`.
+    pub quantization: Option<String>,
+    pub tensor_count: usize,
+    /// Size in bytes of the tensor payloads as stored in the weights file.
+    pub parameter_bytes: usize,
+    /// Number of values of the tensors.
+    #[serde(default)]
+    pub parameter_count: usize,
+    /// Number of tensors per dtype.
+    pub dtypes: BTreeMap<String, usize>,
+    pub encoder_layers: usize,
+    pub decoder_layers: usize,
+    pub d_model: Option<usize>,
+    pub vocab_size: Option<usize>,
+    pub num_mel_bins: Option<usize>,
+    /// Size in bytes of the full-precision weights of a model quantized on load,
+    /// `parameter_bytes` being the size of the quantized tensors.
+    #[serde(default)]
+    pub source_parameter_bytes: Option<usize>,
+    /// Family the model was recognized as from its config, set on load.
+    #[serde(default)]
+    pub profile: ModelProfile,
+    /// `WeightManifest::digest` of the weights verified against a manifest on load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weights_sha256: Option<String>,
+}
+
+impl ModelInfo {
+    fn from_tensors<'a, I: Iterator<Item = (&'a str, String, &'a [usize], usize)>>(
+        tensors: I,
+        quantized: bool,
+    ) -> Self {
+        let mut info = Self {
+            quantized,
+            ..Default::default()
+        };
+        let mut matrix_dtypes = BTreeMap::<String, usize>::new();
+        let mut encoder_layers = std::collections::BTreeSet::new();
+        let mut decoder_layers = std::collections::BTreeSet::new();
+        for (name, dtype, dims, bytes) in tensors {
+            info.tensor_count += 1;
+            info.parameter_bytes += bytes;
+            info.parameter_count += dims.iter().product::<usize>();
+            *info.dtypes.entry(dtype.clone()).or_default() += 1;
+            if dims.len() == 2 {
+                *matrix_dtypes.entry(dtype).or_default() += 1;
+            }
+            if let Some(layer) = layer_index(name, "model.encoder.layers.") {
+                encoder_layers.insert(layer);
+            }
+            if let Some(layer) = layer_index(name, "model.decoder.layers.") {
+                decoder_layers.insert(layer);
+            }
+            match (name, dims) {
+                ("model.decoder.embed_tokens.weight", &[vocab_size, d_model]) => {
+                    info.vocab_size = Some(vocab_size);
+                    info.d_model = Some(d_model);
+                }
+                ("model.encoder.conv1.weight", &[_, num_mel_bins, _]) => {
+                    info.num_mel_bins = Some(num_mel_bins)
+                }
+                _ => {}
+            }
+        }
+        info.encoder_layers = encoder_layers.len();
+        info.decoder_layers = decoder_layers.len();
+        if quantized {
+            info.quantization = matrix_dtypes
+                .into_iter()
+                .max_by_key(|(_, count)| *count)
+                .map(|(dtype, _)| dtype);
+        }
+        info
+    }
+
+    pub fn from_gguf(weights: &[u8]) -> anyhow::Result<Self> {
+        Self::from_gguf_reader(&mut std::io::Cursor::new(weights), weights.len() as u64)
+    }
+
+    fn from_gguf_reader<R: std::io::Read + std::io::Seek>(
+        reader: &mut R,
+        len: u64,
+    ) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 4];
+        if reader.read_exact(&mut magic).is_err() || &magic != b"GGUF" {
+            anyhow::bail!("weights are not a GGUF file (bad magic)")
+        }
+        reader.seek(std::io::SeekFrom::Start(0))?;
+        let content = gguf_file::Content::read(reader)
+            .map_err(|e| anyhow::anyhow!("unable to read the GGUF header: {e}"))?;
+        for (name, tensor) in content.tensor_infos.iter() {
+            let dtype = tensor.ggml_dtype;
+            let size = tensor.shape.elem_count() / dtype.block_size() * dtype.type_size();
+            let end = content.tensor_data_offset + tensor.offset + size as u64;
+            if end > len {
+                anyhow::bail!(
+                    "GGUF tensor {name} ends at byte {end} but the file only has {len} bytes"
+                )
+            }
+        }
+        let tensors = content.tensor_infos.iter().map(|(name, tensor)| {
+            let dtype = tensor.ggml_dtype;
+            (
+                name.as_str(),
+                format!("{dtype:?}"),
+                tensor.shape.dims(),
+                tensor.shape.elem_count() / dtype.block_size() * dtype.type_size(),
+            )
+        });
+        Ok(Self::from_tensors(tensors, true))
+    }
+
+    pub fn from_safetensors(weights: &[u8]) -> anyhow::Result<Self> {
+        let st = safetensors::SafeTensors::deserialize(weights)
+            .map_err(|e| anyhow::anyhow!("unable to read the safetensors header: {e}"))?;
+        Ok(Self::from_safetensors_views(&st.tensors()))
+    }
+
+    /// Reads the header of a weights file, GGUF files are recognized by their `.gguf`
+    /// extension or their magic and safetensors files are memory-mapped.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let mut magic = [0u8; 4];
+        let is_gguf = path.extension().is_some_and(|ext| ext == "gguf")
+            || (std::io::Read::read_exact(&mut file, &mut magic).is_ok() && &magic == b"GGUF");
+        if is_gguf {
+            // The magic was read by the check.
+            std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(0))?;
+            let len = file.metadata()?.len();
+            return Self::from_gguf_reader(&mut file, len);
+        }
+        // Safety: the file is expected not to be modified while it is mapped.
+        let st = unsafe { candle_core::safetensors::MmapedSafetensors::new(path)? };
+        Ok(Self::from_safetensors_views(&st.tensors()))
+    }
+
+    /// Size in bytes of the tensors once loaded: the quantized tensors keep their storage, the
+    /// others are converted to `dtype`.
+    pub fn materialized_bytes(&self, dtype: candle_core::DType) -> usize {
+        if self.quantized {
+            self.parameter_bytes
+        } else {
+            self.parameter_count * dtype.size_in_bytes()
+        }
+    }
+
+    fn from_safetensors_views(tensors: &[(String, safetensors::tensor::TensorView<'_>)]) -> Self {
+        let tensors = tensors.iter().map(|(name, view)| {
+            (
+                name.as_str(),
+                format!("{:?}", view.dtype()),
+                view.shape(),
+                view.data().len(),
+            )
+        });
+        Self::from_tensors(tensors, false)
+    }
+
+    /// Checks that the dimensions found in the weights match the ones of `config`, reporting
+    /// every mismatch at once.
+    /// Inconsistencies between the weights and `config`.
+    pub fn config_mismatches(&self, config: &Config) -> Vec<String> {
+        let kind = if self.quantized {
+            "GGUF"
+        } else {
+            "safetensors"
+        };
+        let mut errors = vec![];
+        if self.encoder_layers != config.encoder_layers {
+            errors.push(format!(
+                "{kind} model has {} audio layers but config.json expects {}",
+                self.encoder_layers, config.encoder_layers
+            ))
+        }
+        if self.decoder_layers != config.decoder_layers {
+            errors.push(format!(
+                "{kind} model has {} text layers but config.json expects {}",
+                self.decoder_layers, config.decoder_layers
+            ))
+        }
+        let dims = [
+            ("d_model", self.d_model, config.d_model),
+            ("vocab_size", self.vocab_size, config.vocab_size),
+            ("num_mel_bins", self.num_mel_bins, config.num_mel_bins),
+        ];
+        for (name, found, expected) in dims {
+            match found {
+                Some(found) if found != expected => errors.push(format!(
+                    "{kind} model has {name} {found} but config.json expects {expected}"
+                )),
+                Some(_) => {}
+                None => errors.push(format!("{kind} model does not define {name}")),
+            }
+        }
+        errors
+    }
+}
+
+fn layer_index(name: &str, prefix: &str) -> Option<usize> {
+    let rest = name.strip_prefix(prefix)?;
+    rest.split('.').next()?.parse().ok()
+}
@@ -0,0 +1,109 @@
+//! Decode options tuned to the size of the model, recognized from its config: the small models
+//! afford more passes per window, the large ones fewer, and the distilled ones decode long-form
+//! audio in chunks.
+
+use crate::{
+    chunked::is_distilled,
+    consensus::HighAccuracyOptions,
+    logic::{Config, DecodeOptions, LanguageDetectionMode},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Temperatures of the fallback of the medium and large models, every pass costing seconds.
+const SHORT_TEMPERATURES: [f64; 3] = [0.0, 0.4, 0.8];
+
+/// Seconds a window of a medium or large model may take on wasm, where a window stuck
+/// repeating itself would freeze the page for minutes. The quantized models decode about twice
+/// as fast.
+const WASM_SEGMENT_SECONDS: f64 = 120.;
+const WASM_QUANTIZED_SEGMENT_SECONDS: f64 = 60.;
+
+/// Family of the whisper model, from the shape of its config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelProfile {
+    Tiny,
+    Base,
+    Small,
+    Medium,
+    /// large, large-v2 and large-v3, the latter with 128 mel bins.
+    Large,
+    /// Deep encoder and shallow decoder, the distil-whisper models and large-v3-turbo, see
+    /// `chunked::is_distilled`.
+    Distil,
+    /// Unknown shape, the default options.
+    #[default]
+    Generic,
+}
+
+impl ModelProfile {
+    pub fn classify(config: &Config) -> Self {
+        if is_distilled(config) {
+            return Self::Distil;
+        }
+        if config.encoder_layers != config.decoder_layers {
+            return Self::Generic;
+        }
+        match (config.d_model, config.encoder_layers) {
+            (384, 4) => Self::Tiny,
+            (512, 6) => Self::Base,
+            (768, 12) => Self::Small,
+            (1024, 24) => Self::Medium,
+            (1280, 32) => Self::Large,
+            _ => Self::Generic,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tiny => "tiny",
+            Self::Base => "base",
+            Self::Small => "small",
+            Self::Medium => "medium",
+            Self::Large => "large",
+            Self::Distil => "distil",
+            Self::Generic => "generic",
+        }
+    }
+
+    /// Default options of the model of `config`, the base options of a decoder loaded without
+    /// explicit options.
+    pub fn from_config(config: &Config, is_multilingual: bool, quantized: bool) -> DecodeOptions {
+        Self::classify(config).options(is_multilingual, quantized)
+    }
+
+    pub fn options(self, is_multilingual: bool, quantized: bool) -> DecodeOptions {
+        let defaults = DecodeOptions::default();
+        let wasm_budget = cfg!(target_arch = "wasm32").then_some(if quantized {
+            WASM_QUANTIZED_SEGMENT_SECONDS
+        } else {
+            WASM_SEGMENT_SECONDS
+        });
+        match self {
+            // The extra passes of the high accuracy mode are cheap, they stand in for a beam
+            // search.
+            Self::Tiny | Self::Base => DecodeOptions {
+                high_accuracy: Some(HighAccuracyOptions::default()),
+                ..defaults
+            },
+            Self::Medium | Self::Large => DecodeOptions {
+                temperatures: SHORT_TEMPERATURES.to_vec(),
+                max_decode_seconds_per_segment: wasm_budget,
+                // The large models detect the language reliably enough to follow the audio
+                // switching languages, for one decoder step per window.
+                language_detection: if is_multilingual && self == Self::Large {
+                    LanguageDetectionMode::PerSegment
+                } else {
+                    defaults.language_detection.clone()
+                },
+                ..defaults
+            },
+            Self::Distil => DecodeOptions {
+                chunked_long_form: Some(true),
+                ..defaults
+            },
+            Self::Small | Self::Generic => defaults,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::logic::Segment;
+
+use std::ops::Range;
+
+/// Word of a decoded window, with the range of its tokens in the tokens of the segment.
+#[derive(Debug, Clone)]
+pub struct OverlapWord {
+    pub text: String,
+    pub tokens: Range<usize>,
+    /// Start time in seconds.
+    pub start: f64,
+    /// End time in seconds.
+    pub end: f64,
+}
+
+/// Where two overlapping windows are stitched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlapCut {
+    /// Number of words of the previous window that are kept.
+    pub previous_words: usize,
+    /// Index of the first kept word of the next window.
+    pub next_words: usize,
+    /// Time the previous segment ends and the next one starts at.
+    pub time: f64,
+}
+
+/// Lowercase alphanumeric characters of `word`.
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Longest common subsequence of two word sequences, as pairs of indices. Words without any
+/// alphanumeric character never match.
+fn lcs(a: &[String], b: &[String]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len(), b.len());
+    let matches = |i: usize, j: usize| !a[i].is_empty() && a[i] == b[j];
+    let mut len = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            len[i][j] = if matches(i, j) {
+                len[i + 1][j + 1] + 1
+            } else {
+                usize::max(len[i + 1][j], len[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if matches(i, j) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if len[i + 1][j] >= len[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Finds where to stitch two windows overlapping over `overlap`, in seconds.
+///
+/// The words of both windows around the overlap are aligned on their normalized text. The
+/// matched words are kept from the previous window when `prefer_previous` is set, from the
+/// next one otherwise. The words of the previous window after the last match and of the next
+/// window before the first match are cut by the window boundaries and are taken from the other
+/// window. When no word matches, the windows are cut in the middle of the overlap.
+pub fn find_cut(
+    previous: &[OverlapWord],
+    next: &[OverlapWord],
+    overlap: (f64, f64),
+    prefer_previous: bool,
+) -> OverlapCut {
+    let (overlap_start, overlap_end) = overlap;
+    // The word times are estimates, the alignment looks one overlap length further.
+    let margin = overlap_end - overlap_start;
+    let first = previous
+        .iter()
+        .position(|w| w.end > overlap_start - margin)
+        .unwrap_or(previous.len());
+    let last = next
+        .iter()
+        .position(|w| w.start >= overlap_end + margin)
+        .unwrap_or(next.len());
+    let a: Vec<String> = previous[first..]
+        .iter()
+        .map(|w| normalize(&w.text))
+        .collect();
+    let b: Vec<String> = next[..last].iter().map(|w| normalize(&w.text)).collect();
+    let pairs = lcs(&a, &b);
+    let clamp = |time: f64| time.clamp(overlap_start, overlap_end);
+    let cut = match (pairs.first(), pairs.last(), prefer_previous) {
+        (_, Some(&(i, j)), true) => OverlapCut {
+            previous_words: first + i + 1,
+            next_words: j + 1,
+            time: previous[first + i].end,
+        },
+        (Some(&(i, j)), _, false) => OverlapCut {
+            previous_words: first + i,
+            next_words: j,
+            time: next[j].start,
+        },
+        _ => {
+            let time = (overlap_start + overlap_end) / 2.;
+            OverlapCut {
+                previous_words: previous.iter().take_while(|w| w.start < time).count(),
+                next_words: next.iter().take_while(|w| w.start < time).count(),
+                time,
+            }
+        }
+    };
+    OverlapCut {
+        time: clamp(cut.time),
+        ..cut
+    }
+}
+
+/// Removes the tokens in `range` from the tokens of `segment`, moving its low agreement spans
+/// accordingly. The text is left for the caller to decode again.
+pub fn drop_tokens(segment: &mut Segment, range: Range<usize>) {
+    if range.is_empty() {
+        return;
+    }
+    let removed = range.len();
+    let remap = |i: usize| {
+        if i <= range.start {
+            i
+        } else if i >= range.end {
+            i - removed
+        } else {
+            range.start
+        }
+    };
+    segment.low_agreement_spans.retain_mut(|span| {
+        span.start_token = remap(span.start_token);
+        span.end_token = remap(span.end_token);
+        span.start_token < span.end_token
+    });
+    if segment.dr.token_probs.len() == segment.dr.tokens.len() {
+        segment.dr.token_probs.drain(range.clone());
+    }
+    segment.dr.tokens.drain(range);
+}
+
+/// Number of trailing words forming sentences that repeat the sentence before them, e.g. 4
+/// for `Thank you. Thank you. Thank you.`.
+pub fn repeated_tail(words: &[OverlapWord]) -> usize {
+    let mut sentences: Vec<Range<usize>> = vec![];
+    let mut start = 0;
+    for (i, word) in words.iter().enumerate() {
+        if word.text.trim_end().ends_with(['.', '!', '?']) || i + 1 == words.len() {
+            sentences.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    let key = |sentence: &Range<usize>| -> Vec<String> {
+        words[sentence.clone()]
+            .iter()
+            .map(|w| normalize(&w.text))
+            .filter(|w| !w.is_empty())
+            .collect()
+    };
+    let mut kept = sentences.len();
+    while kept >= 2 {
+        let last = key(&sentences[kept - 1]);
+        if last.is_empty() || last != key(&sentences[kept - 2]) {
+            break;
+        }
+        kept -= 1;
+    }
+    sentences
+        .get(kept)
+        .map_or(0, |sentence| words.len() - sentence.start)
+}
@@ -0,0 +1,228 @@
+//! Paragraphs of a transcript, cut at the pauses, at the speaker changes and after a number of
+//! sentences, for reading a long transcript as text rather than as a list of segments.
+
+use crate::{logic::Segment, segments::is_speech};
+
+use serde::{Deserialize, Serialize};
+
+/// When a paragraph ends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParagraphOptions {
+    /// Seconds of silence between two segments, or two words when the segments have word
+    /// timings, that start a new paragraph.
+    pub min_gap_seconds: f64,
+    /// `Segment::speaker_change_score` above which a segment starts a new paragraph, see
+    /// `DecodeOptions::speaker_change_hints`.
+    pub speaker_change_threshold: Option<f32>,
+    /// Sentences after which a paragraph ends at the next sentence end, without a pause.
+    pub max_sentences: usize,
+}
+
+impl Default for ParagraphOptions {
+    fn default() -> Self {
+        Self {
+            min_gap_seconds: 2.0,
+            speaker_change_threshold: Some(1.5),
+            max_sentences: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Paragraph {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    /// Ids of the segments with text in the paragraph, a segment cut at a sentence end inside
+    /// it being in both paragraphs.
+    pub segment_ids: Vec<usize>,
+}
+
+/// Abbreviations of the Latin scripts whose dot does not end a sentence, lowercase without
+/// their last dot.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "mt", "vs", "e.g", "i.e", "cf", "fig",
+    "vol", "approx", "dept", "inc", "ltd", "jan", "feb", "mar", "apr", "jun", "jul", "aug", "sep",
+    "sept", "oct", "nov", "dec", "sra", "sres", "mme", "mlle", "hr", "fr", "bzw", "usw",
+];
+
+/// Punctuation ending a sentence in the CJK languages, without a space after it.
+const CJK_SENTENCE_MARKS: [char; 3] = ['。', '！', '？'];
+
+/// Closing quotes and brackets that may follow the punctuation ending a sentence.
+const CLOSING: &[char] = &['"', '\'', ')', ']', '»', '”', '’', '」', '』', '）', '】'];
+
+pub(crate) fn is_cjk(c: char) -> bool {
+    ('\u{3000}'..='\u{9fff}').contains(&c)
+        || ('\u{f900}'..='\u{faff}').contains(&c)
+        || ('\u{ff00}'..='\u{ffef}').contains(&c)
+}
+
+/// Whether the word ends a sentence: a terminal punctuation mark, possibly followed by closing
+/// quotes, that is not the dot of an abbreviation or of an initial.
+fn ends_sentence(word: &str) -> bool {
+    let word = word.trim_end_matches(CLOSING);
+    if word.ends_with(CJK_SENTENCE_MARKS) || word.ends_with(['!', '?', '…']) {
+        return true;
+    }
+    let Some(stem) = word.strip_suffix('.') else {
+        return false;
+    };
+    let stem = stem.trim_start_matches(|c: char| !c.is_alphanumeric());
+    let lowercase = stem.to_lowercase();
+    let is_initial = stem.chars().count() == 1 && stem.chars().all(char::is_uppercase);
+    !(stem.is_empty() || is_initial || ABBREVIATIONS.contains(&lowercase.as_str()))
+}
+
+/// Whether the last word of `text` ends a sentence.
+fn ends_with_sentence(text: &str) -> bool {
+    text.split_whitespace().last().is_some_and(ends_sentence)
+}
+
+/// Number of sentences ending in `text`.
+fn sentence_ends(text: &str) -> usize {
+    text.split_whitespace()
+        .map(|word| {
+            // The CJK sentences are not separated by spaces, a run of marks ends one sentence.
+            let cjk = word
+                .char_indices()
+                .filter(|&(i, c)| {
+                    CJK_SENTENCE_MARKS.contains(&c)
+                        && !word[i + c.len_utf8()..].starts_with(CJK_SENTENCE_MARKS)
+                })
+                .count();
+            let cjk_last = word.trim_end_matches(CLOSING).ends_with(CJK_SENTENCE_MARKS);
+            match (cjk_last, ends_sentence(word)) {
+                (false, true) => cjk + 1,
+                _ => cjk,
+            }
+        })
+        .sum()
+}
+
+/// Appends `text` to the paragraph, with a space unless both sides are CJK.
+fn push_text(paragraph: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    let joined = paragraph.ends_with(is_cjk) && text.starts_with(is_cjk);
+    if !paragraph.is_empty() && !joined {
+        paragraph.push(' ');
+    }
+    paragraph.push_str(text);
+}
+
+/// Piece of text the paragraphs are cut between: a word with its times when the segment has
+/// word timings, the whole segment otherwise.
+struct Unit<'a> {
+    text: &'a str,
+    start: f64,
+    end: f64,
+    segment_id: usize,
+    speaker_change_score: f32,
+}
+
+fn units(segments: &[Segment]) -> Vec<Unit<'_>> {
+    let mut units = vec![];
+    for segment in segments.iter().filter(|segment| is_speech(segment)) {
+        if segment.words.is_empty() {
+            units.push(Unit {
+                text: segment.dr.text_clean.as_deref().unwrap_or(&segment.dr.text),
+                start: segment.start,
+                end: segment.start + segment.duration,
+                segment_id: segment.id,
+                speaker_change_score: segment.speaker_change_score,
+            });
+            continue;
+        }
+        for (i, word) in segment.words.iter().enumerate() {
+            units.push(Unit {
+                text: &word.word,
+                start: word.start,
+                end: word.end,
+                segment_id: segment.id,
+                speaker_change_score: if i == 0 {
+                    segment.speaker_change_score
+                } else {
+                    0.
+                },
+            });
+        }
+    }
+    units
+}
+
+/// Groups the text of the speech segments, in the order given, into paragraphs.
+pub fn paragraphs(segments: &[Segment], options: &ParagraphOptions) -> Vec<Paragraph> {
+    let mut paragraphs: Vec<Paragraph> = vec![];
+    let mut sentences = 0;
+    let mut previous_end = None;
+    for unit in units(segments) {
+        if unit.text.trim().is_empty() {
+            continue;
+        }
+        let gap = previous_end.is_some_and(|end| unit.start - end > options.min_gap_seconds);
+        let speaker_change = options
+            .speaker_change_threshold
+            .is_some_and(|threshold| unit.speaker_change_score > threshold);
+        let full = sentences >= options.max_sentences.max(1);
+        match paragraphs.last_mut() {
+            Some(paragraph) if !(gap || speaker_change || full) => {
+                push_text(&mut paragraph.text, unit.text);
+                paragraph.end = f64::max(paragraph.end, unit.end);
+                if paragraph.segment_ids.last() != Some(&unit.segment_id) {
+                    paragraph.segment_ids.push(unit.segment_id);
+                }
+            }
+            _ => {
+                paragraphs.push(Paragraph {
+                    start: unit.start,
+                    end: unit.end,
+                    text: unit.text.trim().to_string(),
+                    segment_ids: vec![unit.segment_id],
+                });
+                sentences = 0;
+            }
+        }
+        sentences += sentence_ends(unit.text);
+        // The paragraph only ends after a sentence ends the unit.
+        if !ends_with_sentence(unit.text) {
+            sentences = sentences.min(options.max_sentences.max(1) - 1);
+        }
+        previous_end = Some(unit.end);
+    }
+    paragraphs
+}
+
+/// `MM:SS`, or `H:MM:SS` from an hour.
+fn clock_time(seconds: f64) -> String {
+    let s = seconds.max(0.).floor() as u64;
+    let (h, m, s) = (s / 3600, s / 60 % 60, s % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m:02}:{s:02}")
+    }
+}
+
+/// Markdown of the paragraphs separated by blank lines, each prefixed with its start time in
+/// bold as `**[12:34]**` with `include_timestamps`.
+pub fn to_markdown(paragraphs: &[Paragraph], include_timestamps: bool) -> String {
+    let blocks: Vec<String> = paragraphs
+        .iter()
+        .map(|paragraph| {
+            if include_timestamps {
+                format!("**[{}]** {}", clock_time(paragraph.start), paragraph.text)
+            } else {
+                paragraph.text.clone()
+            }
+        })
+        .collect();
+    let mut markdown = blocks.join("\n\n");
+    if !markdown.is_empty() {
+        markdown.push('\n');
+    }
+    markdown
+}
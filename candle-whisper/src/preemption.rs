@@ -0,0 +1,61 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Flag outside of the decoder, e.g. an `Int32Array` over a `SharedArrayBuffer` read with
+/// `Atomics.load` by the wasm bindings.
+type ExternalFlag = Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// Request to stop a [`crate::logic::Decoder::run_preemptible`] run at the next window so that
+/// the decoder can serve a more urgent job. The clones share the flag, which can be raised from
+/// another thread.
+#[derive(Clone, Default)]
+pub struct PreemptionControl {
+    requested: Arc<AtomicBool>,
+    external: Option<ExternalFlag>,
+}
+
+impl PreemptionControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Control also raised by `flag` returning `true`, which is polled after every window. The
+    /// run does not lower it, the caller does before resuming.
+    pub fn with_external(flag: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            requested: Arc::default(),
+            external: Some(Arc::new(flag)),
+        }
+    }
+
+    /// Asks the run to yield after the window being decoded.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Release)
+    }
+
+    /// Withdraws a request not yet seen by the run.
+    pub fn clear(&self) {
+        self.requested.store(false, Ordering::Release)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire) || self.external.as_ref().is_some_and(|flag| flag())
+    }
+
+    /// Whether the run should yield, lowering the flag so that the resumed run continues.
+    pub(crate) fn take_request(&self) -> bool {
+        self.requested.swap(false, Ordering::AcqRel)
+            || self.external.as_ref().is_some_and(|flag| flag())
+    }
+}
+
+impl std::fmt::Debug for PreemptionControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreemptionControl")
+            .field("requested", &self.is_requested())
+            .field("external", &self.external.is_some())
+            .finish()
+    }
+}
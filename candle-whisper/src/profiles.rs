@@ -0,0 +1,149 @@
+//! Options overriding the [`DecodeOptions`] for the audio in a given language, as the best
+//! thresholds differ between the languages.
+
+use crate::error::WhisperError;
+use crate::languages;
+use crate::logic::DecodeOptions;
+use crate::text::TextOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Options of a language profile, the ones set replace those of the base options and the
+/// others are kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PartialDecodeOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperatures: Option<Vec<f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression_ratio_threshold: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprob_threshold: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_speech_threshold: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_segment_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_segment_chars: Option<usize>,
+    /// Replaces the whole text cleanup, e.g. to enable the ITN for a single language.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<TextOptions>,
+}
+
+impl PartialDecodeOptions {
+    /// `base` with the options set in the profile replaced.
+    pub fn apply_to(&self, base: &DecodeOptions) -> DecodeOptions {
+        let mut options = base.clone();
+        if let Some(temperatures) = &self.temperatures {
+            options.temperatures = temperatures.clone();
+        }
+        if let Some(threshold) = self.compression_ratio_threshold {
+            options.compression_ratio_threshold = Some(threshold);
+        }
+        if let Some(threshold) = self.logprob_threshold {
+            options.logprob_threshold = Some(threshold);
+        }
+        if let Some(threshold) = self.no_speech_threshold {
+            options.no_speech_threshold = Some(threshold);
+        }
+        if let Some(duration) = self.max_segment_duration {
+            options.max_segment_duration = Some(duration);
+        }
+        if let Some(chars) = self.max_segment_chars {
+            options.max_segment_chars = Some(chars);
+        }
+        if let Some(text) = &self.text {
+            options.text = text.clone();
+        }
+        options
+    }
+
+    /// Checks the thresholds, the other options are checked with the base options they are
+    /// applied to.
+    pub fn validate(&self) -> Result<(), WhisperError> {
+        let out_of_range = |name: &str, value: f64| WhisperError::InvalidConfig {
+            reason: format!("{name} threshold {value} is out of range"),
+        };
+        if let Some(t) = self.no_speech_threshold.filter(|t| !(0. ..=1.).contains(t)) {
+            return Err(out_of_range("no-speech", t));
+        }
+        if let Some(t) = self.logprob_threshold.filter(|t| !t.is_finite() || *t > 0.) {
+            return Err(out_of_range("log probability", t));
+        }
+        if let Some(t) = self
+            .compression_ratio_threshold
+            .filter(|t| !t.is_finite() || *t <= 0.)
+        {
+            return Err(out_of_range("compression ratio", t));
+        }
+        Ok(())
+    }
+}
+
+/// Profiles keyed by language code, serialized as a JSON object such as
+/// `{"ja": {"no_speech_threshold": 0.4}}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OptionProfiles {
+    profiles: BTreeMap<String, PartialDecodeOptions>,
+}
+
+impl OptionProfiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the profile of `language`, a code or name accepted by
+    /// [`languages::canonicalize`], replacing its previous profile.
+    pub fn set(
+        &mut self,
+        language: &str,
+        options: PartialDecodeOptions,
+    ) -> Result<(), WhisperError> {
+        let code = languages::canonicalize(language).ok_or_else(|| {
+            WhisperError::LanguageNotSupported {
+                lang: language.to_string(),
+            }
+        })?;
+        options.validate()?;
+        self.profiles.insert(code.to_string(), options);
+        Ok(())
+    }
+
+    /// Profile of `language`, a code or name.
+    pub fn get(&self, language: &str) -> Option<&PartialDecodeOptions> {
+        self.profiles.get(languages::canonicalize(language)?)
+    }
+
+    pub fn remove(&mut self, language: &str) -> Option<PartialDecodeOptions> {
+        self.profiles.remove(languages::canonicalize(language)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+
+    /// Profiles by language code, in the order of the codes.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PartialDecodeOptions)> {
+        self.profiles
+            .iter()
+            .map(|(code, options)| (code.as_str(), options))
+    }
+
+    /// Checks the profiles of a deserialized map, canonicalizing their languages.
+    pub fn validated(self) -> Result<Self, WhisperError> {
+        let mut profiles = Self::new();
+        for (language, options) in self.profiles {
+            profiles.set(&language, options)?;
+        }
+        Ok(profiles)
+    }
+
+    /// Options of the audio in `language`, `base` when it has no profile.
+    pub fn resolve(&self, base: &DecodeOptions, language: Option<&str>) -> DecodeOptions {
+        match language.and_then(|language| self.get(language)) {
+            Some(profile) => profile.apply_to(base),
+            None => base.clone(),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Conditioning on the previous text: the text tokens of the previous windows are passed to the
+//! decoder after `<|startofprev|>`, within a budget that keeps room for the sampled tokens in
+//! the decoder context.
+
+use crate::logic::DecodingResult;
+
+use std::rc::Rc;
+use tokenizers::{DecoderWrapper, Tokenizer};
+
+/// Temperature above which a result is considered unreliable and the buffer is dropped, as the
+/// `prompt_reset_on_temperature` of openai/whisper.
+pub const RESET_TEMPERATURE: f64 = 0.5;
+
+/// The most recent text tokens of the previous windows.
+#[derive(Debug, Clone)]
+pub struct PromptBuffer {
+    tokens: Vec<u32>,
+    max_tokens: usize,
+    /// First special token, the tokens from it are not kept.
+    eot: u32,
+    /// Whether each token of the vocabulary starts with a character rather than inside one.
+    char_starts: Rc<[bool]>,
+}
+
+impl PromptBuffer {
+    /// Tokens without an entry in `char_starts` are considered to start a character.
+    pub fn new(max_tokens: usize, eot: u32, char_starts: Rc<[bool]>) -> Self {
+        Self {
+            tokens: vec![],
+            max_tokens,
+            eot,
+            char_starts,
+        }
+    }
+
+    pub fn for_tokenizer(max_tokens: usize, eot: u32, tokenizer: &Tokenizer) -> Self {
+        Self::new(max_tokens, eot, char_starts(tokenizer).into())
+    }
+
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    pub fn set_max_tokens(&mut self, max_tokens: usize) {
+        self.max_tokens = max_tokens;
+        self.truncate();
+    }
+
+    /// Appends the text tokens of a decoded window, or drops the buffer when the window was
+    /// decoded above [`RESET_TEMPERATURE`].
+    pub fn push_segment(&mut self, dr: &DecodingResult) {
+        if dr.temperature > RESET_TEMPERATURE {
+            self.clear();
+            return;
+        }
+        let eot = self.eot;
+        self.tokens
+            .extend(dr.tokens.iter().copied().filter(|&token| token < eot));
+        self.truncate();
+    }
+
+    pub fn clear(&mut self) {
+        self.tokens.clear()
+    }
+
+    /// Replaces the tokens, e.g. with those of a `Checkpoint`, keeping the most recent ones.
+    pub fn set_tokens(&mut self, tokens: Vec<u32>) {
+        self.tokens = tokens;
+        self.truncate();
+    }
+
+    /// Tokens to pass after `<|startofprev|>`, oldest first.
+    pub fn as_prompt_tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Drops the oldest tokens beyond `max_tokens`, and the tokens continuing a character cut
+    /// by the truncation so that the prompt decodes to valid UTF-8.
+    fn truncate(&mut self) {
+        let Some(mut start) = self.tokens.len().checked_sub(self.max_tokens) else {
+            return;
+        };
+        while start < self.tokens.len() && !self.starts_char(self.tokens[start]) {
+            start += 1;
+        }
+        self.tokens.drain(..start);
+    }
+
+    fn starts_char(&self, token: u32) -> bool {
+        self.char_starts
+            .get(token as usize)
+            .copied()
+            .unwrap_or(true)
+    }
+}
+
+/// Text tokens of a byte-level vocabulary sorted by their bytes, so that the tokens starting
+/// with given bytes form a contiguous range. Used to heal a prompt ending inside a word: its
+/// last token is dropped and the first text token sampled has to start with the same bytes.
+#[derive(Debug, Clone)]
+pub struct PrefixIndex {
+    /// Bytes of all the tokens, those of the token `id` at `offsets[id]..offsets[id + 1]`.
+    bytes: Vec<u8>,
+    offsets: Vec<u32>,
+    /// Token ids in the order of their bytes.
+    sorted: Vec<u32>,
+}
+
+impl PrefixIndex {
+    /// Index of the tokens below `eot`, `None` for the tokenizers that are not byte-level,
+    /// whose tokens do not split the words.
+    pub fn for_tokenizer(tokenizer: &Tokenizer, eot: u32) -> Option<Self> {
+        if !matches!(tokenizer.get_decoder(), Some(DecoderWrapper::ByteLevel(_))) {
+            return None;
+        }
+        let mut bytes = vec![];
+        let mut offsets = vec![0];
+        for id in 0..eot {
+            let token = tokenizer.id_to_token(id).unwrap_or_default();
+            bytes.extend(token.chars().filter_map(byte_level_byte));
+            offsets.push(bytes.len() as u32);
+        }
+        let mut index = Self {
+            bytes,
+            offsets,
+            sorted: (0..eot).collect(),
+        };
+        let mut sorted = std::mem::take(&mut index.sorted);
+        sorted.sort_by(|&a, &b| index.token_bytes(a).cmp(index.token_bytes(b)));
+        index.sorted = sorted;
+        Some(index)
+    }
+
+    /// Bytes of `token`, empty for the tokens outside of the index.
+    pub fn token_bytes(&self, token: u32) -> &[u8] {
+        let token = token as usize;
+        match (self.offsets.get(token), self.offsets.get(token + 1)) {
+            (Some(&start), Some(&end)) => &self.bytes[start as usize..end as usize],
+            _ => &[],
+        }
+    }
+
+    /// Tokens whose bytes start with `prefix`, in the order of their bytes.
+    pub fn tokens_with_prefix(&self, prefix: &[u8]) -> &[u32] {
+        let start = self
+            .sorted
+            .partition_point(|&token| self.token_bytes(token) < prefix);
+        let len = self.sorted[start..]
+            .partition_point(|&token| self.token_bytes(token).starts_with(prefix));
+        &self.sorted[start..start + len]
+    }
+
+    /// Whether the text of `token` stops inside a word, i.e. ends with a letter or a digit or
+    /// inside a character, rather than with a space or a punctuation mark.
+    pub fn ends_inside_word(&self, token: u32) -> bool {
+        let bytes = self.token_bytes(token);
+        match std::str::from_utf8(bytes) {
+            Ok(text) => text.chars().next_back().is_some_and(char::is_alphanumeric),
+            Err(err) => err.error_len().is_none(),
+        }
+    }
+}
+
+/// Whether each token of the vocabulary starts with a character, i.e. does not start with a
+/// UTF-8 continuation byte. Only byte-level tokenizers, as the whisper ones, can split the
+/// characters, all the tokens start a character for the others.
+pub fn char_starts(tokenizer: &Tokenizer) -> Vec<bool> {
+    let vocab_size = tokenizer.get_vocab_size(true) as u32;
+    let byte_level = matches!(tokenizer.get_decoder(), Some(DecoderWrapper::ByteLevel(_)));
+    (0..vocab_size)
+        .map(|id| {
+            let first_byte = tokenizer
+                .id_to_token(id)
+                .filter(|_| byte_level)
+                .and_then(|token| token.chars().next())
+                .and_then(byte_level_byte);
+            first_byte.is_none_or(|b| b & 0xc0 != 0x80)
+        })
+        .collect()
+}
+
+/// Byte represented by a character of the byte-level BPE alphabet of GPT-2: the printable
+/// bytes stand for themselves and the others are mapped in order from U+0100.
+fn byte_level_byte(c: char) -> Option<u8> {
+    let printable = |b: u32| matches!(b, 33..=126 | 161..=172 | 174..=255);
+    let c = c as u32;
+    if c < 256 {
+        return printable(c).then_some(c as u8);
+    }
+    (0..256)
+        .filter(|&b| !printable(b))
+        .nth((c - 256) as usize)
+        .map(|b| b as u8)
+}
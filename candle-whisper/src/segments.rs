@@ -0,0 +1,426 @@
+use crate::{
+    consensus::LowAgreementSpan,
+    line_breaks::{break_points, closest_break, BreakStrategy, CJK_CLAUSE_MARKS},
+    logic::Segment,
+};
+
+fn end(segment: &Segment) -> f64 {
+    segment.start + segment.duration
+}
+
+pub(crate) fn is_speech(segment: &Segment) -> bool {
+    !segment.no_speech && segment.error.is_none()
+}
+
+fn join_text(a: &str, b: &str) -> String {
+    if a.is_empty()
+        || b.is_empty()
+        || a.ends_with(char::is_whitespace)
+        || b.starts_with(char::is_whitespace)
+    {
+        format!("{a}{b}")
+    } else {
+        format!("{a} {b}")
+    }
+}
+
+fn renumber(segments: &mut [Segment]) {
+    for (id, segment) in segments.iter_mut().enumerate() {
+        segment.id = id
+    }
+}
+
+/// Joins consecutive speech segments of the same speaker separated by at most `max_gap_secs`.
+///
+/// The text and tokens are concatenated and `avg_logprob` is averaged weighted by the token
+/// counts. No-speech and placeholder segments are never merged, and the ids of the returned
+/// segments are renumbered sequentially.
+pub fn merge_adjacent(segments: Vec<Segment>, max_gap_secs: f64) -> Vec<Segment> {
+    let mut output: Vec<Segment> = Vec::with_capacity(segments.len());
+    for segment in segments.into_iter() {
+        let previous = match output.last_mut() {
+            Some(previous)
+                if is_speech(previous)
+                    && is_speech(&segment)
+                    && previous.speaker == segment.speaker
+                    && segment.start - end(previous) <= max_gap_secs =>
+            {
+                previous
+            }
+            _ => {
+                output.push(segment);
+                continue;
+            }
+        };
+        let segment_end = end(&segment);
+        let (n1, n2) = (previous.dr.tokens.len(), segment.dr.tokens.len());
+        let dr = &mut previous.dr;
+        if n1 + n2 > 0 {
+            dr.avg_logprob = (dr.avg_logprob * n1 as f64 + segment.dr.avg_logprob * n2 as f64)
+                / (n1 + n2) as f64;
+            dr.score = (dr.score * n1 as f64 + segment.dr.score * n2 as f64) / (n1 + n2) as f64;
+            previous.confidence = (previous.confidence * n1 as f32
+                + segment.confidence * n2 as f32)
+                / (n1 + n2) as f32;
+        }
+        dr.sum_logprob += segment.dr.sum_logprob;
+        dr.no_speech_prob = f64::min(dr.no_speech_prob, segment.dr.no_speech_prob);
+        dr.temperature = f64::max(dr.temperature, segment.dr.temperature);
+        dr.attempts = usize::max(dr.attempts, segment.dr.attempts);
+        dr.truncated |= segment.dr.truncated;
+        dr.truncation_reason = dr.truncation_reason.or(segment.dr.truncation_reason);
+        dr.text = join_text(&dr.text, &segment.dr.text);
+        dr.text_clean = match (&dr.text_clean, &segment.dr.text_clean) {
+            (Some(a), Some(b)) => Some(join_text(a, b)),
+            _ => None,
+        };
+        if dr.token_probs.len() == n1 && segment.dr.token_probs.len() == n2 {
+            dr.token_probs.extend(segment.dr.token_probs);
+        } else {
+            dr.token_probs.clear();
+        }
+        dr.tokens.extend(segment.dr.tokens);
+        previous
+            .low_agreement_spans
+            .extend(
+                segment
+                    .low_agreement_spans
+                    .into_iter()
+                    .map(|span| LowAgreementSpan {
+                        start_token: span.start_token + n1,
+                        end_token: span.end_token + n1,
+                        ..span
+                    }),
+            );
+        previous.words.extend(segment.words);
+        previous.duration = f64::max(end(previous), segment_end) - previous.start;
+        previous.hallucination_score =
+            f64::max(previous.hallucination_score, segment.hallucination_score);
+        if previous.language != segment.language {
+            previous.language = None
+        }
+    }
+    renumber(&mut output);
+    output
+}
+
+fn break_strategy(segment: &Segment) -> BreakStrategy {
+    BreakStrategy::for_language(segment.language.as_deref())
+}
+
+/// Splits `segment` at the given `(time, text byte index)` cuts, sorted and strictly inside
+/// the segment. The tokens are split proportionally to the time.
+fn split_segment(segment: &Segment, cuts: &[(f64, usize)]) -> Vec<Segment> {
+    let text = &segment.dr.text;
+    let tokens = &segment.dr.tokens;
+    let mut bounds = vec![(segment.start, 0)];
+    bounds.extend_from_slice(cuts);
+    bounds.push((end(segment), text.len()));
+    let mut token_start = 0;
+    bounds
+        .windows(2)
+        .enumerate()
+        .map(|(i, window)| {
+            let ((start, text_start), (end, text_end)) = (window[0], window[1]);
+            let token_end = if i == bounds.len() - 2 {
+                tokens.len()
+            } else {
+                let ratio = (end - segment.start) / segment.duration;
+                usize::max((ratio * tokens.len() as f64) as usize, token_start)
+            };
+            let mut part = segment.clone();
+            part.start = start;
+            part.duration = end - start;
+            part.dr.text = text[text_start..usize::max(text_start, text_end)].to_string();
+            part.dr.text_clean = None;
+            part.dr.tokens = tokens[token_start..token_end].to_vec();
+            part.dr.token_probs = match segment.dr.token_probs.len() == tokens.len() {
+                true => segment.dr.token_probs[token_start..token_end].to_vec(),
+                false => vec![],
+            };
+            part.low_agreement_spans = segment
+                .low_agreement_spans
+                .iter()
+                .filter(|span| span.start_token >= token_start && span.start_token < token_end)
+                .map(|span| LowAgreementSpan {
+                    start_token: span.start_token - token_start,
+                    end_token: usize::min(span.end_token, token_end) - token_start,
+                    ..span.clone()
+                })
+                .collect();
+            part.words = segment
+                .words
+                .iter()
+                .filter(|word| word.start >= start && word.start < end)
+                .cloned()
+                .collect();
+            token_start = token_end;
+            part
+        })
+        .collect()
+}
+
+/// Splits the segments at the given times in seconds, boundaries on a segment edge or outside
+/// of any segment are ignored.
+///
+/// The text is split at the word boundary closest to the proportional position of the split
+/// time, see [`BreakStrategy::for_language`] for the languages without spaces, and the tokens
+/// are split proportionally, the other decoding statistics are copied from the original
+/// segment and `text_clean` is dropped. The ids of the returned segments are
+/// renumbered sequentially.
+pub fn split_at(segments: Vec<Segment>, timestamps: &[f64]) -> Vec<Segment> {
+    let mut timestamps = timestamps.to_vec();
+    timestamps.sort_by(f64::total_cmp);
+    let mut output = Vec::with_capacity(segments.len());
+    for segment in segments.into_iter() {
+        if !is_speech(&segment) {
+            output.push(segment);
+            continue;
+        }
+        let text = &segment.dr.text;
+        let mut text_start = 0;
+        let cuts: Vec<(f64, usize)> = timestamps
+            .iter()
+            .copied()
+            .filter(|&t| t > segment.start && t < end(&segment))
+            .map(|t| {
+                let ratio = (t - segment.start) / segment.duration;
+                let index = (ratio * text.len() as f64) as usize;
+                let index = closest_break(text, index, break_strategy(&segment));
+                text_start = usize::max(index, text_start);
+                (t, text_start)
+            })
+            .collect();
+        if cuts.is_empty() {
+            output.push(segment);
+            continue;
+        }
+        output.extend(split_segment(&segment, &cuts));
+    }
+    renumber(&mut output);
+    output
+}
+
+fn ends_clause(text: &str) -> bool {
+    let text = text.trim_end();
+    text.ends_with(['.', ',', '!', '?', ';', ':']) || text.ends_with(CJK_CLAUSE_MARKS)
+}
+
+/// Ends of the timed words of `segment` in its text, as `(byte index, char index, end time)`,
+/// `None` when the segment has no words or they are not all found in order in its text.
+fn word_ends(segment: &Segment) -> Option<Vec<(usize, usize, f64)>> {
+    if segment.words.is_empty() {
+        return None;
+    }
+    let text = &segment.dr.text;
+    let (mut byte, mut chars, mut time) = (0, 0, segment.start);
+    segment
+        .words
+        .iter()
+        .map(|word| {
+            let word_text = word.word.trim();
+            let word_end = byte + text[byte..].find(word_text)? + word_text.len();
+            chars += text[byte..word_end].chars().count();
+            byte = word_end;
+            time = word.end.clamp(time, end(segment));
+            Some((byte, chars, time))
+        })
+        .collect()
+}
+
+/// Splits the speech segments longer than `max_duration` seconds or `max_chars` characters
+/// into cues satisfying both limits when possible, a single word exceeding them is kept whole.
+///
+/// The text is cut between words, preferring a cut after a punctuation mark. With word
+/// timings the cues are cut at the end of the timed words, otherwise the text is cut between
+/// the words or the characters of the languages without spaces as chosen by
+/// [`BreakStrategy::for_language`] from the language of the segment, and the time of the
+/// segment is allocated to the cues proportionally to their number of characters. Either way
+/// the cues tile the segment exactly. The decoding statistics are copied from the original
+/// segment. The ids of the returned segments are renumbered sequentially.
+pub fn limit_length(
+    segments: Vec<Segment>,
+    max_duration: Option<f64>,
+    max_chars: Option<usize>,
+) -> Vec<Segment> {
+    let mut output = Vec::with_capacity(segments.len());
+    for segment in segments.into_iter() {
+        let text = &segment.dr.text;
+        let total_chars = text.chars().count();
+        if !is_speech(&segment) || total_chars == 0 {
+            output.push(segment);
+            continue;
+        }
+        // Ends of the words with their leading whitespace, as `(byte index, char index, time)`.
+        let mut words = match word_ends(&segment) {
+            Some(mut words) => {
+                words.retain(|&(b, _, _)| b < text.len());
+                words
+            }
+            None => {
+                let secs_per_char = segment.duration / total_chars as f64;
+                let mut words = vec![];
+                let mut chars = 0;
+                let mut previous = 0;
+                for byte_index in break_points(text, break_strategy(&segment)) {
+                    chars += text[previous..byte_index].chars().count();
+                    words.push((
+                        byte_index,
+                        chars,
+                        segment.start + chars as f64 * secs_per_char,
+                    ));
+                    previous = byte_index;
+                }
+                words
+            }
+        };
+        words.push((text.len(), total_chars, end(&segment)));
+        let fits = |start: (usize, usize, f64), end: (usize, usize, f64)| {
+            max_duration.is_none_or(|max| end.2 - start.2 <= max)
+                && max_chars.is_none_or(|max| text[start.0..end.0].trim().chars().count() <= max)
+        };
+
+        let mut cuts: Vec<(usize, usize, f64)> = vec![];
+        let mut cue_start = (0, 0, segment.start);
+        let mut previous: Option<(usize, usize, f64)> = None;
+        for &word_end in words.iter() {
+            while let Some(previous_end) = previous.filter(|p| p.1 > cue_start.1) {
+                if fits(cue_start, word_end) {
+                    break;
+                }
+                // Prefer the last punctuation mark in the second half of the cue.
+                let cut = words
+                    .iter()
+                    .copied()
+                    .rev()
+                    .find(|&(b, c, _)| {
+                        c > cue_start.1
+                            && c <= previous_end.1
+                            && 2 * (c - cue_start.1) >= previous_end.1 - cue_start.1
+                            && ends_clause(&text[cue_start.0..b])
+                    })
+                    .unwrap_or(previous_end);
+                cuts.push(cut);
+                cue_start = cut;
+            }
+            previous = Some(word_end);
+        }
+        let mut cuts: Vec<(f64, usize)> = cuts
+            .into_iter()
+            .filter(|&(_, c, t)| c > 0 && c < total_chars && t > segment.start && t < end(&segment))
+            .map(|(b, _, t)| (t, b))
+            .collect();
+        // Words without duration would give empty cues, their text goes to the next cue.
+        cuts.dedup_by(|next, previous| next.0 <= previous.0);
+        if cuts.is_empty() {
+            output.push(segment);
+        } else {
+            output.extend(split_segment(&segment, &cuts));
+        }
+    }
+    renumber(&mut output);
+    output
+}
+
+/// Tolerance in seconds of the time comparisons, absorbing the rounding of the offsets.
+const TIME_EPSILON: f64 = 1e-6;
+
+/// Moves the segments and their words by `offset_secs`.
+pub fn shift(segments: &mut [Segment], offset_secs: f64) {
+    for segment in segments.iter_mut() {
+        segment.start += offset_secs;
+        for word in segment.words.iter_mut() {
+            word.start += offset_secs;
+            word.end += offset_secs;
+        }
+    }
+}
+
+/// Two consecutive segments of [`concat`] whose times are out of order or overlap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentOrderError {
+    /// Ids of the segments in the concatenated list.
+    pub previous: usize,
+    pub next: usize,
+    /// End of `previous` and start of `next` in seconds.
+    pub previous_end: f64,
+    pub next_start: f64,
+}
+
+impl std::fmt::Display for SegmentOrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "segment {} starts at {}s before the end of segment {} at {}s",
+            self.next, self.next_start, self.previous, self.previous_end
+        )
+    }
+}
+
+impl std::error::Error for SegmentOrderError {}
+
+/// Concatenates the segments of several transcriptions, each moved by its offset in seconds,
+/// e.g. the chapters of a book. The ids are renumbered sequentially.
+///
+/// Fails on the first pair of consecutive segments that overlap, times differing by less than
+/// a microsecond being considered equal.
+pub fn concat(parts: Vec<(f64, Vec<Segment>)>) -> Result<Vec<Segment>, SegmentOrderError> {
+    let mut output: Vec<Segment> = Vec::with_capacity(parts.iter().map(|(_, s)| s.len()).sum());
+    for (offset, mut segments) in parts.into_iter() {
+        shift(&mut segments, offset);
+        output.extend(segments);
+    }
+    renumber(&mut output);
+    for (id, pair) in output.windows(2).enumerate() {
+        if pair[1].start < end(&pair[0]) - TIME_EPSILON {
+            return Err(SegmentOrderError {
+                previous: id,
+                next: id + 1,
+                previous_end: end(&pair[0]),
+                next_start: pair[1].start,
+            });
+        }
+    }
+    Ok(output)
+}
+
+/// Interleaves the speech segments of several transcriptions of the same time span, e.g. the
+/// tracks of the speakers of a conversation, each moved by its offset in seconds.
+///
+/// Unlike [`concat`] the segments of different parts may overlap. They are ordered with
+/// [`start_order`] and their ids renumbered sequentially. The no-speech and placeholder
+/// segments, which would cover the speech of the other parts, are dropped.
+pub fn interleave(parts: Vec<(f64, Vec<Segment>)>) -> Vec<Segment> {
+    let mut output: Vec<Segment> = vec![];
+    for (offset, mut segments) in parts.into_iter() {
+        segments.retain(is_speech);
+        shift(&mut segments, offset);
+        output.extend(segments);
+    }
+    sort_by_start(&mut output);
+    renumber(&mut output);
+    output
+}
+
+/// Text of the speech segments joined with `separator`, the cleaned text being used when
+/// available. The whitespace around the texts is trimmed and the empty texts are skipped.
+pub fn full_text(segments: &[Segment], separator: &str) -> String {
+    let texts: Vec<&str> = segments
+        .iter()
+        .filter(|segment| is_speech(segment))
+        .map(|segment| segment.dr.text_clean.as_deref().unwrap_or(&segment.dr.text))
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .collect();
+    texts.join(separator)
+}
+
+/// Orders segments by start time, then by id.
+pub fn start_order(a: &Segment, b: &Segment) -> std::cmp::Ordering {
+    a.start.total_cmp(&b.start).then(a.id.cmp(&b.id))
+}
+
+/// Sorts the segments with [`start_order`].
+pub fn sort_by_start(segments: &mut [Segment]) {
+    segments.sort_by(start_order)
+}
@@ -0,0 +1,110 @@
+//! Everything a decoder is configured with, attached to the bug reports and to the transcripts
+//! collected with diagnostics so that the setup of a run can be reproduced elsewhere.
+
+use crate::{
+    logic::{DecodeOptions, Task},
+    model_info::ModelInfo,
+    profiles::OptionProfiles,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the crate, recorded in the snapshots.
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Configuration of a decoder, see `Decoder::config_snapshot`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigSnapshot {
+    /// Version of the crate that took the snapshot.
+    pub crate_version: String,
+    /// Model of the decoder, which a snapshot cannot restore.
+    pub model: ModelInfo,
+    /// Options as set, the preprocessing of the audio included.
+    pub options: DecodeOptions,
+    #[serde(default)]
+    pub language_profiles: OptionProfiles,
+    pub task: Option<Task>,
+    /// Language set on load, see `DecoderBuilder::language`.
+    pub language: Option<String>,
+    /// Timestamps mode of the runs that do not choose one.
+    pub timestamps: bool,
+    /// Seed of the sampling RNG set on load.
+    pub seed: u64,
+}
+
+/// Part of a snapshot that `Decoder::apply_snapshot` could not restore.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedField {
+    /// Field of [`ConfigSnapshot`].
+    pub field: String,
+    pub reason: String,
+}
+
+impl SkippedField {
+    pub(crate) fn new(field: &str, reason: impl ToString) -> Self {
+        Self {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl ConfigSnapshot {
+    /// How `model` differs from the model of the snapshot: its family, its shape, its
+    /// quantization and the checksum of its weights when both have one. Empty when they look
+    /// the same.
+    pub fn model_differences(&self, model: &ModelInfo) -> Vec<String> {
+        let snapshot = &self.model;
+        let checksums = match (&snapshot.weights_sha256, &model.weights_sha256) {
+            (Some(a), Some(b)) => (Some(a), Some(b)),
+            _ => (None, None),
+        };
+        let fields = [
+            (
+                "profile",
+                format!("{:?}", snapshot.profile),
+                format!("{:?}", model.profile),
+            ),
+            (
+                "d_model",
+                format!("{:?}", snapshot.d_model),
+                format!("{:?}", model.d_model),
+            ),
+            (
+                "encoder_layers",
+                snapshot.encoder_layers.to_string(),
+                model.encoder_layers.to_string(),
+            ),
+            (
+                "decoder_layers",
+                snapshot.decoder_layers.to_string(),
+                model.decoder_layers.to_string(),
+            ),
+            (
+                "vocab_size",
+                format!("{:?}", snapshot.vocab_size),
+                format!("{:?}", model.vocab_size),
+            ),
+            (
+                "num_mel_bins",
+                format!("{:?}", snapshot.num_mel_bins),
+                format!("{:?}", model.num_mel_bins),
+            ),
+            (
+                "quantization",
+                format!("{:?}", snapshot.quantization),
+                format!("{:?}", model.quantization),
+            ),
+            (
+                "weights_sha256",
+                format!("{:?}", checksums.0),
+                format!("{:?}", checksums.1),
+            ),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, expected, actual)| expected != actual)
+            .map(|(name, expected, actual)| format!("{name} is {actual}, not {expected}"))
+            .collect()
+    }
+}
@@ -0,0 +1,116 @@
+//! Hints that the voice changed between two windows, from the encoder outputs already computed
+//! for the decoding. The first seconds of a window are compared with the last seconds before
+//! it, and the distance is scaled by the usual distance between consecutive blocks of the same
+//! windows so that the scores compare across recordings.
+
+use candle_core::{DType, Tensor};
+use serde::{Deserialize, Serialize};
+
+/// Seconds of audio pooled on each side of a window boundary.
+pub const BLOCK_SECONDS: usize = 5;
+
+/// Encoder positions per second of audio, a position covering two 10ms mel frames.
+const POSITIONS_PER_SECOND: usize = 50;
+
+/// Mean audio features of every whole second of the `(1, positions, d_model)` encoder output of
+/// a window.
+pub fn second_embeddings(audio_features: &Tensor) -> candle_core::Result<Vec<Vec<f32>>> {
+    let (_, positions, d_model) = audio_features.dims3()?;
+    let seconds = positions / POSITIONS_PER_SECOND;
+    if seconds == 0 {
+        return Ok(vec![]);
+    }
+    audio_features
+        .narrow(1, 0, seconds * POSITIONS_PER_SECOND)?
+        .reshape((seconds, POSITIONS_PER_SECOND, d_model))?
+        .to_dtype(DType::F32)?
+        .mean(1)?
+        .to_vec2()
+}
+
+/// Mean of `vectors`, which must not be empty.
+fn mean(vectors: &[Vec<f32>]) -> Vec<f32> {
+    let mut sum = vec![0f32; vectors[0].len()];
+    for vector in vectors {
+        for (s, v) in sum.iter_mut().zip(vector) {
+            *s += v;
+        }
+    }
+    sum.iter().map(|s| s / vectors.len() as f32).collect()
+}
+
+/// `1 - cos(a, b)`, in `[0, 2]`, 0 when one of the vectors is null.
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms =
+        a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|y| y * y).sum::<f32>().sqrt();
+    if norms == 0. {
+        return 0.;
+    }
+    1. - dot / norms
+}
+
+/// Speaker change measured at the start of a window.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowChange {
+    /// Distance to the previous window scaled by the baseline, `None` for the first window of
+    /// the run and until the windows had consecutive blocks to set the baseline.
+    pub score: Option<f32>,
+    /// Raw distance between the last seconds before the window and its first seconds.
+    pub distance: Option<f32>,
+    /// Distances between the consecutive blocks of the window.
+    pub within_distances: Vec<f32>,
+}
+
+/// Embeddings carried from one window of a run to the next, kept in the checkpoints of the
+/// resumable runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpeakerChangeTracker {
+    /// Last seconds before the next window.
+    previous_tail: Option<Vec<f32>>,
+    within_sum: f64,
+    within_count: usize,
+}
+
+impl SpeakerChangeTracker {
+    /// Compares a window, of which `seconds` are the [`second_embeddings`] and `audio` the
+    /// seconds of audio, with the previous one. The next window starts `next_start` seconds
+    /// into this one.
+    pub fn push_window(
+        &mut self,
+        seconds: &[Vec<f32>],
+        audio: f64,
+        next_start: f64,
+    ) -> WindowChange {
+        let audio_seconds = (audio.round() as usize).min(seconds.len());
+        if audio_seconds == 0 {
+            self.previous_tail = None;
+            return WindowChange::default();
+        }
+        let seconds = &seconds[..audio_seconds];
+        let blocks: Vec<Vec<f32>> = seconds.chunks(BLOCK_SECONDS).map(mean).collect();
+        let within_distances: Vec<f32> = blocks
+            .windows(2)
+            .map(|pair| cosine_distance(&pair[0], &pair[1]))
+            .collect();
+        self.within_sum += within_distances.iter().map(|&d| d as f64).sum::<f64>();
+        self.within_count += within_distances.len();
+        let distance = self
+            .previous_tail
+            .as_ref()
+            .map(|tail| cosine_distance(tail, &blocks[0]));
+        let baseline = (self.within_count > 0).then(|| self.within_sum / self.within_count as f64);
+        let score = distance
+            .zip(baseline)
+            .map(|(distance, baseline)| (distance as f64 / baseline.max(f64::EPSILON)) as f32);
+        let tail_end = (next_start.round() as usize).clamp(1, audio_seconds);
+        self.previous_tail = Some(mean(
+            &seconds[tail_end.saturating_sub(BLOCK_SECONDS)..tail_end],
+        ));
+        WindowChange {
+            score,
+            distance,
+            within_distances,
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{error::WhisperError, itn};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replacement {
+    /// Regular expression, see the `regex` crate for the syntax.
+    pub pattern: String,
+    /// Replacement text, `$1` or `${name}` refer to the capture groups.
+    pub replacement: String,
+}
+
+/// Cleanup of the decoded text, stored in `text_clean` while `text` stays raw. Everything is
+/// disabled by default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextOptions {
+    /// Trim the text and collapse runs of whitespace into a single space.
+    pub collapse_whitespace: bool,
+    /// Apply the unicode NFC normalization.
+    pub nfc: bool,
+    /// Write the numbers, amounts, percentages and times with digits and symbols, e.g.
+    /// `$23.50` for `twenty three dollars and fifty cents`, see the `itn` module. Only applied
+    /// to the languages with rules, and merges the timed words of each rewritten entity.
+    pub itn: bool,
+    /// Capitalize the first letter of sentences written in a Latin script.
+    pub capitalize_sentences: bool,
+    /// Replacements applied in order after the other steps.
+    pub replacements: Vec<Replacement>,
+}
+
+impl TextOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.collapse_whitespace
+            || self.nfc
+            || self.itn
+            || self.capitalize_sentences
+            || !self.replacements.is_empty()
+    }
+}
+
+/// [`TextOptions`] with the replacement patterns compiled.
+#[derive(Debug, Clone, Default)]
+pub struct TextPostProcessor {
+    options: TextOptions,
+    replacements: Vec<(Regex, String)>,
+}
+
+impl TextPostProcessor {
+    pub fn new(options: &TextOptions) -> Result<Self, WhisperError> {
+        let replacements = options
+            .replacements
+            .iter()
+            .map(|r| match Regex::new(&r.pattern) {
+                Ok(regex) => Ok((regex, r.replacement.clone())),
+                Err(err) => Err(WhisperError::InvalidConfig {
+                    reason: format!("invalid replacement pattern {:?}: {err}", r.pattern),
+                }),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            options: options.clone(),
+            replacements,
+        })
+    }
+
+    /// Cleaned up text, `None` when no step is enabled. The inverse text normalization needs
+    /// the language of the text, see [`Self::process_in`].
+    pub fn process(&self, text: &str) -> Option<String> {
+        self.process_in(text, None)
+    }
+
+    /// Cleaned up text in `language`, `None` when no step is enabled.
+    pub fn process_in(&self, text: &str, language: Option<&str>) -> Option<String> {
+        if !self.options.is_enabled() {
+            return None;
+        }
+        let mut text = text.to_string();
+        if self.options.nfc {
+            let mut normalized = tokenizers::NormalizedString::from(text.as_str());
+            normalized.nfc();
+            text = normalized.get().to_string();
+        }
+        if self.options.collapse_whitespace {
+            text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        if let Some(normalizer) = language
+            .filter(|_| self.options.itn)
+            .and_then(itn::normalizer)
+        {
+            text = itn::normalize_text(normalizer, &text);
+        }
+        if self.options.capitalize_sentences {
+            text = capitalize_sentences(&text);
+        }
+        for (regex, replacement) in self.replacements.iter() {
+            text = regex.replace_all(&text, replacement.as_str()).into_owned();
+        }
+        Some(text)
+    }
+}
+
+fn is_latin(c: char) -> bool {
+    c.is_ascii_alphabetic() || ('\u{00c0}'..='\u{024f}').contains(&c)
+}
+
+/// Uppercases the first letter of the text and the letters following a sentence ending
+/// punctuation, possibly closing a quote or a parenthesis, and a space, for Latin letters
+/// only.
+pub fn capitalize_sentences(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut sentence_start = true;
+    let mut after_punctuation = false;
+    for c in text.chars() {
+        if sentence_start && c.is_alphabetic() {
+            if is_latin(c) {
+                output.extend(c.to_uppercase());
+            } else {
+                output.push(c);
+            }
+            sentence_start = false;
+            after_punctuation = false;
+            continue;
+        }
+        if matches!(c, '.' | '!' | '?') {
+            after_punctuation = true;
+        } else if after_punctuation && matches!(c, '"' | '\'' | ')' | '»' | '”') {
+            // Closing a quote or a parenthesis, the sentence still ends.
+        } else if c.is_whitespace() {
+            if after_punctuation {
+                sentence_start = true;
+            }
+        } else if !sentence_start || !matches!(c, '"' | '\'' | '(' | '¿' | '¡') {
+            after_punctuation = false;
+            sentence_start = false;
+        }
+        output.push(c);
+    }
+    output
+}
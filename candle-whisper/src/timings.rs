@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+/// Source of the time used by the timings, `std::time::Instant` is not available on wasm.
+pub trait Clock {
+    /// Milliseconds elapsed since an arbitrary origin.
+    fn now_ms(&self) -> f64;
+}
+
+/// `performance.now()` on wasm, `Instant` elsewhere.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(target_arch = "wasm32")]
+mod performance {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = performance)]
+        pub fn now() -> f64;
+    }
+}
+
+impl Clock for SystemClock {
+    #[cfg(target_arch = "wasm32")]
+    fn now_ms(&self) -> f64 {
+        performance::now()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now_ms(&self) -> f64 {
+        static ORIGIN: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        ORIGIN
+            .get_or_init(std::time::Instant::now)
+            .elapsed()
+            .as_secs_f64()
+            * 1000.
+    }
+}
+
+/// Time spent in the stages of a transcription, in milliseconds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Timings {
+    /// Decoding of the WAV file into samples.
+    pub pcm_decode_ms: f64,
+    /// Resampling to 16kHz, 0 as the audio must already be sampled at 16kHz.
+    pub resample_ms: f64,
+    /// Preprocessing and mel spectrogram computation.
+    pub mel_ms: f64,
+    /// Encoder pass of every decoded window.
+    pub encoder_ms: Vec<f64>,
+    /// Decoder loop of every decoded window, all the temperatures tried included.
+    pub decode_ms: Vec<f64>,
+    /// Tokens sampled per second by the decoder loop of every decoded window.
+    pub tokens_per_sec: Vec<f64>,
+    /// Decoding passes that selected the greedy tokens on the device.
+    #[serde(default)]
+    pub greedy_fast_path: PathThroughput,
+    /// Decoding passes that copied the logits to the host, to sample or run the logits
+    /// processor.
+    #[serde(default)]
+    pub host_path: PathThroughput,
+    pub total_ms: f64,
+}
+
+/// Time spent by [`crate::logic::Decoder::warm_up`] in each phase, in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct WarmupReport {
+    pub mel_ms: f64,
+    pub encoder_ms: f64,
+    /// Decoder steps on the encoded silence.
+    pub decoder_ms: f64,
+    pub total_ms: f64,
+    /// Whether the decoder had already been warmed up, nothing ran and the times are 0.
+    pub already_warm: bool,
+}
+
+/// Tokens sampled by the decoding passes of one path of the decoder loop.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PathThroughput {
+    pub passes: usize,
+    pub tokens: usize,
+    pub decode_ms: f64,
+    pub tokens_per_sec: f64,
+}
+
+impl Timings {
+    pub(crate) fn add_window(&mut self, encoder_ms: f64, decode_ms: f64, tokens: usize) {
+        self.encoder_ms.push(encoder_ms);
+        self.decode_ms.push(decode_ms);
+        self.tokens_per_sec.push(if decode_ms > 0. {
+            tokens as f64 * 1000. / decode_ms
+        } else {
+            0.
+        });
+    }
+
+    pub(crate) fn add_decode(&mut self, fast_path: bool, tokens: usize, decode_ms: f64) {
+        let path = if fast_path {
+            &mut self.greedy_fast_path
+        } else {
+            &mut self.host_path
+        };
+        path.passes += 1;
+        path.tokens += tokens;
+        path.decode_ms += decode_ms;
+        if path.decode_ms > 0. {
+            path.tokens_per_sec = path.tokens as f64 * 1000. / path.decode_ms;
+        }
+    }
+}
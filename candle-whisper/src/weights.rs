@@ -0,0 +1,276 @@
+//! Integrity checks of safetensors weights, so that a truncated or corrupted download fails with
+//! the offending tensor rather than with a shape error or a model producing garbage.
+//!
+//! The structure of the header is always checked on load. Model hosts can also publish a
+//! [`WeightManifest`] of the SHA-256 digest of every tensor, which the loading then verifies.
+
+use crate::{error::WhisperError, fingerprint::sha256};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Largest header accepted, the limit of the safetensors crate.
+const MAX_HEADER_BYTES: usize = 100_000_000;
+
+/// Key of the free-form metadata in the header, which is not a tensor.
+const METADATA_KEY: &str = "__metadata__";
+
+/// Digests of the tensors of a weights file, to be published along with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightManifest {
+    /// Size in bytes of the whole file.
+    pub total_bytes: usize,
+    /// Lowercase hexadecimal SHA-256 of the data of each tensor, by tensor name.
+    pub tensors: BTreeMap<String, String>,
+}
+
+impl WeightManifest {
+    /// Manifest of `weights`, whose structure is checked first.
+    pub fn from_weights(weights: &[u8]) -> Result<Self, WhisperError> {
+        let header = parse_header(weights)?;
+        let tensors = header
+            .tensors
+            .iter()
+            .map(|tensor| {
+                (
+                    tensor.name.clone(),
+                    hex(&sha256(header.data(weights, tensor))),
+                )
+            })
+            .collect();
+        Ok(Self {
+            total_bytes: weights.len(),
+            tensors,
+        })
+    }
+
+    /// Lowercase hexadecimal SHA-256 of the digests of the tensors in name order, a checksum of
+    /// the weights that does not depend on the layout of the file.
+    pub fn digest(&self) -> String {
+        let digests: Vec<u8> = self
+            .tensors
+            .iter()
+            .flat_map(|(name, digest)| [name.as_bytes(), b":", digest.as_bytes(), b"\n"])
+            .flatten()
+            .copied()
+            .collect();
+        hex(&sha256(&digests))
+    }
+}
+
+/// Summary of verified weights.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightReport {
+    pub tensor_count: usize,
+    /// Size in bytes of the header, its length prefix included.
+    pub header_bytes: usize,
+    /// Size in bytes of the tensor data.
+    pub data_bytes: usize,
+    /// Whether the digests of a manifest were compared.
+    pub digests_verified: bool,
+}
+
+/// Checks the structure of safetensors `weights`: the header length, the dtype of every tensor
+/// and its offsets, which must lie within the buffer, match its shape and not overlap another
+/// tensor. With a `manifest` the size of the buffer and the digest of every tensor are
+/// compared as well.
+///
+/// The tensors are checked in the order of their data, the error names the first offending one
+/// with the offset in `weights` at which the problem lies.
+pub fn verify_weights(
+    weights: &[u8],
+    manifest: Option<&WeightManifest>,
+) -> Result<WeightReport, WhisperError> {
+    let header = parse_header(weights)?;
+    let mut report = WeightReport {
+        tensor_count: header.tensors.len(),
+        header_bytes: header.data_start,
+        data_bytes: header.tensors.iter().map(|t| t.end - t.start).sum(),
+        digests_verified: false,
+    };
+    let Some(manifest) = manifest else {
+        return Ok(report);
+    };
+    if weights.len() != manifest.total_bytes {
+        return Err(corrupt(
+            None,
+            weights.len().min(manifest.total_bytes),
+            format!(
+                "the weights have {} bytes but the manifest expects {}",
+                weights.len(),
+                manifest.total_bytes
+            ),
+        ));
+    }
+    for tensor in &header.tensors {
+        let offset = header.data_start + tensor.start;
+        let Some(expected) = manifest.tensors.get(&tensor.name) else {
+            return Err(corrupt(
+                Some(&tensor.name),
+                offset,
+                "the tensor is not in the manifest",
+            ));
+        };
+        let digest = hex(&sha256(header.data(weights, tensor)));
+        if !digest.eq_ignore_ascii_case(expected) {
+            return Err(corrupt(
+                Some(&tensor.name),
+                offset,
+                format!("the data has digest {digest} but the manifest expects {expected}"),
+            ));
+        }
+    }
+    if let Some(name) = manifest
+        .tensors
+        .keys()
+        .find(|name| header.tensors.iter().all(|tensor| &tensor.name != *name))
+    {
+        return Err(corrupt(
+            Some(name),
+            8,
+            "the tensor is missing from the header",
+        ));
+    }
+    report.digests_verified = true;
+    Ok(report)
+}
+
+fn corrupt(tensor: Option<&str>, offset: usize, reason: impl ToString) -> WhisperError {
+    WhisperError::CorruptWeights {
+        tensor: tensor.map(str::to_string),
+        offset,
+        reason: reason.to_string(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Entry of a tensor in the header.
+#[derive(Deserialize)]
+struct TensorEntry {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: (usize, usize),
+}
+
+/// Checked tensor, its offsets being relative to the start of the data.
+struct HeaderTensor {
+    name: String,
+    start: usize,
+    end: usize,
+}
+
+struct Header {
+    /// Offset of the data in the buffer.
+    data_start: usize,
+    /// Tensors in the order of their data.
+    tensors: Vec<HeaderTensor>,
+}
+
+impl Header {
+    fn data<'a>(&self, weights: &'a [u8], tensor: &HeaderTensor) -> &'a [u8] {
+        &weights[self.data_start + tensor.start..self.data_start + tensor.end]
+    }
+}
+
+/// Size in bytes of an element of a safetensors dtype, `None` when the dtype is unknown.
+fn dtype_size(dtype: &str) -> Option<usize> {
+    let size = match dtype {
+        "BOOL" | "U8" | "I8" | "F8_E5M2" | "F8_E4M3" => 1,
+        "I16" | "U16" | "F16" | "BF16" => 2,
+        "I32" | "U32" | "F32" => 4,
+        "I64" | "U64" | "F64" => 8,
+        _ => return None,
+    };
+    Some(size)
+}
+
+fn parse_header(weights: &[u8]) -> Result<Header, WhisperError> {
+    let Some(length) = weights.get(..8) else {
+        return Err(corrupt(
+            None,
+            0,
+            format!("{} bytes are too few for a header", weights.len()),
+        ));
+    };
+    let length = u64::from_le_bytes(length.try_into().expect("8 bytes"));
+    if length > MAX_HEADER_BYTES as u64 || 8 + length > weights.len() as u64 {
+        return Err(corrupt(
+            None,
+            0,
+            format!(
+                "the header length {length} exceeds the {} bytes of the buffer or the limit of \
+                 {MAX_HEADER_BYTES} bytes",
+                weights.len()
+            ),
+        ));
+    }
+    let data_start = 8 + length as usize;
+    let entries: BTreeMap<String, serde_json::Value> =
+        serde_json::from_slice(&weights[8..data_start])
+            .map_err(|err| corrupt(None, 8, format!("invalid header: {err}")))?;
+    let data_len = weights.len() - data_start;
+    let mut tensors = vec![];
+    for (name, entry) in entries {
+        if name == METADATA_KEY {
+            continue;
+        }
+        let entry: TensorEntry = serde_json::from_value(entry)
+            .map_err(|err| corrupt(Some(&name), 8, format!("invalid header entry: {err}")))?;
+        let Some(size) = dtype_size(&entry.dtype) else {
+            return Err(corrupt(
+                Some(&name),
+                8,
+                format!("unknown dtype {}", entry.dtype),
+            ));
+        };
+        let (start, end) = entry.data_offsets;
+        let expected = entry
+            .shape
+            .iter()
+            .try_fold(size, |bytes, &dim| bytes.checked_mul(dim));
+        if start > end || expected != Some(end - start) {
+            return Err(corrupt(
+                Some(&name),
+                data_start.saturating_add(start),
+                format!(
+                    "the offsets {start}..{end} do not hold a {} tensor of shape {:?}",
+                    entry.dtype, entry.shape
+                ),
+            ));
+        }
+        tensors.push((name, start, end));
+    }
+    tensors.sort_by_key(|&(_, start, end)| (start, end));
+    let mut previous_end = 0;
+    for (name, start, end) in &tensors {
+        if *end > data_len {
+            return Err(corrupt(
+                Some(name),
+                data_start.saturating_add(*start),
+                format!(
+                    "the data ends at byte {} past the end of the {} bytes of the buffer",
+                    data_start.saturating_add(*end),
+                    weights.len()
+                ),
+            ));
+        }
+        if *start < previous_end {
+            return Err(corrupt(
+                Some(name),
+                data_start + start,
+                "the data overlaps the previous tensor",
+            ));
+        }
+        previous_end = *end;
+    }
+    Ok(Header {
+        data_start,
+        tensors: tensors
+            .into_iter()
+            .map(|(name, start, end)| HeaderTensor { name, start, end })
+            .collect(),
+    })
+}
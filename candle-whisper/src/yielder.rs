@@ -0,0 +1,31 @@
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    task::{Context, Poll, Waker},
+};
+
+/// Hook awaited by the async transcription before every window and every
+/// `DecodeOptions::yield_interval_tokens` sampled tokens, e.g. to give the browser event loop
+/// a chance to run. Implementations live with the runtime, the library only awaits them.
+pub trait Yielder {
+    fn yield_now(&mut self) -> Pin<Box<dyn Future<Output = ()> + '_>>;
+}
+
+/// Yielder returning immediately, for callers that do not need to yield.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopYielder;
+
+impl Yielder for NoopYielder {
+    fn yield_now(&mut self) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// Output of a future that only awaits [`NoopYielder`], which never suspends, so that the sync
+/// transcription shares the decoding of the async one.
+pub(crate) fn now_or_never<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("the sync transcription only awaits the no-op yielder"),
+    }
+}
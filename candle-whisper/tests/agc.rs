@@ -0,0 +1,112 @@
+use candle_whisper::audio::{automatic_gain_control, AgcOptions, AudioPreprocess};
+
+const SAMPLE_RATE: usize = 16000;
+
+fn rms(pcm: &[f32]) -> f32 {
+    (pcm.iter().map(|v| v * v).sum::<f32>() / pcm.len() as f32).sqrt()
+}
+
+fn db(v: f32) -> f32 {
+    20. * v.log10()
+}
+
+/// RMS in dB of the one-second windows of `pcm`.
+fn window_levels(pcm: &[f32]) -> Vec<f32> {
+    pcm.chunks(SAMPLE_RATE).map(|w| db(rms(w))).collect()
+}
+
+fn variance(values: &[f32]) -> f32 {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32
+}
+
+/// 440Hz sine whose peak amplitude ramps from -40dB to -6dB over `seconds`.
+fn ramp(seconds: usize) -> Vec<f32> {
+    let len = seconds * SAMPLE_RATE;
+    (0..len)
+        .map(|i| {
+            let level_db = -40. + 34. * i as f32 / len as f32;
+            let t = i as f32 / SAMPLE_RATE as f32;
+            10f32.powf(level_db / 20.) * (2. * std::f32::consts::PI * 440. * t).sin()
+        })
+        .collect()
+}
+
+/// Deterministic white noise in `[-amplitude, amplitude]`.
+fn noise(seconds: usize, amplitude: f32) -> Vec<f32> {
+    let mut state = 0x2545f4914f6cdd1du64;
+    (0..seconds * SAMPLE_RATE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            ((state >> 40) as f32 / (1u64 << 24) as f32 * 2. - 1.) * amplitude
+        })
+        .collect()
+}
+
+#[test]
+fn varying_levels_are_evened_out() {
+    let input = ramp(30);
+    let mut output = input.clone();
+    automatic_gain_control(&mut output, &AgcOptions::default());
+    let before = variance(&window_levels(&input));
+    // The first windows are skipped while the gain settles.
+    let after = variance(&window_levels(&output)[3..]);
+    assert!(before > 50., "variance before: {before}");
+    assert!(after < 4., "variance after: {after}");
+    let levels = window_levels(&output);
+    let mean = levels[3..].iter().sum::<f32>() / (levels.len() - 3) as f32;
+    assert!((mean + 20.).abs() < 3., "mean level: {mean}");
+}
+
+#[test]
+fn noise_is_not_boosted_past_the_max_gain() {
+    let input = noise(10, 1e-4);
+    let mut output = input.clone();
+    let options = AgcOptions {
+        max_gain_db: 12.,
+        ..Default::default()
+    };
+    automatic_gain_control(&mut output, &options);
+    let gain_db = db(rms(&output)) - db(rms(&input));
+    assert!(gain_db <= 12. + 1e-3, "gain: {gain_db}dB");
+    assert!(gain_db > 11., "gain: {gain_db}dB");
+}
+
+#[test]
+fn gain_changes_smoothly_between_windows() {
+    // A constant signal jumping from -40dB to -10dB, the gain is the ratio of the output to
+    // the input.
+    let input: Vec<f32> = (0..10 * SAMPLE_RATE)
+        .map(|i| if i < 5 * SAMPLE_RATE { 0.01 } else { 0.316 })
+        .collect();
+    let mut output = input.clone();
+    automatic_gain_control(&mut output, &AgcOptions::default());
+    let gains: Vec<f32> = output.iter().zip(&input).map(|(o, i)| o / i).collect();
+    let max_step = gains
+        .windows(2)
+        .map(|w| (w[1] - w[0]).abs())
+        .fold(0f32, f32::max);
+    assert!(max_step < 1e-3, "largest gain step: {max_step}");
+    assert!(output.iter().all(|v| v.abs() <= 1.));
+}
+
+#[test]
+fn agc_is_deterministic_and_part_of_the_preprocessing() {
+    let input = ramp(5);
+    let preprocess = AudioPreprocess {
+        agc: Some(AgcOptions::default()),
+        ..Default::default()
+    };
+    assert!(preprocess.is_enabled());
+    let a = preprocess.apply(&input).into_owned();
+    let b = preprocess.apply(&input).into_owned();
+    assert_eq!(a, b);
+    assert_ne!(a, input);
+    let invalid = AgcOptions {
+        attack_seconds: 0.,
+        ..Default::default()
+    };
+    assert!(invalid.validate().is_err());
+}
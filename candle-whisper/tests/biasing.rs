@@ -0,0 +1,86 @@
+use candle_whisper::{
+    biasing::PhraseBooster,
+    fixtures::{sine_pcm, tiny_model_data, tiny_tokenizer_json, TEXT_TOKENS},
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+use tokenizers::Tokenizer;
+
+fn boosts(booster: &mut PhraseBooster, sampled: &[u32]) -> Vec<(u32, f32)> {
+    booster.boosts(sampled).collect()
+}
+
+#[test]
+fn trie_matches_the_suffix_of_the_sampled_tokens() {
+    let mut booster = PhraseBooster::new(&[
+        (vec![1, 2, 3], 4.),
+        // Shares a prefix with the first phrase.
+        (vec![1, 2, 5], 2.),
+        // Inside the first phrase.
+        (vec![2, 3], 1.),
+        (vec![9], 0.),
+    ]);
+    assert_eq!(boosts(&mut booster, &[]), [(1, 4.), (2, 1.)]);
+    // The deepest match wins, its boost divided by the depth plus one.
+    assert_eq!(boosts(&mut booster, &[1]), [(1, 4.), (2, 2.)]);
+    assert_eq!(
+        boosts(&mut booster, &[7, 1, 2]),
+        [(1, 4.), (2, 1.), (3, 4. / 3.), (5, 2. / 3.)]
+    );
+    // A complete phrase has nothing left to boost.
+    assert_eq!(boosts(&mut booster, &[1, 2, 3]), [(1, 4.), (2, 1.)]);
+    assert_eq!(boosts(&mut booster, &[2]), [(1, 4.), (2, 1.), (3, 0.5)]);
+
+    let mut logits = vec![0.; 6];
+    logits[1] = f32::NEG_INFINITY;
+    booster.apply(&[1], &mut logits);
+    assert_eq!(logits, [0., f32::NEG_INFINITY, 2., 0., 0., 0.]);
+}
+
+#[test]
+fn phrases_are_tokenized_without_special_tokens() {
+    let tokenizer = Tokenizer::from_bytes(tiny_tokenizer_json()).unwrap();
+    let eot = TEXT_TOKENS.len() as u32;
+    let id = |word: &str| TEXT_TOKENS.iter().position(|t| *t == word).unwrap() as u32;
+    let phrases = [("sine wave".to_string(), 3.)];
+    let mut booster = PhraseBooster::for_tokenizer(&phrases, &tokenizer, eot).unwrap();
+    assert_eq!(boosts(&mut booster, &[id("a")]), [(id("sine"), 3.)]);
+    assert_eq!(
+        boosts(&mut booster, &[id("a"), id("sine")]),
+        [(id("sine"), 3.), (id("wave"), 1.5)]
+    );
+    let special = [("<|endoftext|>".to_string(), 3.)];
+    assert!(PhraseBooster::for_tokenizer(&special, &tokenizer, eot)
+        .unwrap()
+        .is_empty());
+}
+
+fn run(boost_phrases: Vec<(String, f32)>) -> Vec<Vec<u32>> {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            boost_phrases,
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(8., 440.), &RunOptions::default())
+        .unwrap();
+    output.segments.into_iter().map(|s| s.dr.tokens).collect()
+}
+
+#[test]
+fn boost_changes_the_transcript_and_zero_is_a_no_op() {
+    let plain = run(vec![]);
+    assert_eq!(run(vec![("sound".to_string(), 0.)]), plain);
+
+    let sound = TEXT_TOKENS.iter().position(|t| *t == "sound").unwrap() as u32;
+    assert!(!plain.concat().contains(&sound));
+    let boosted = run(vec![("sound".to_string(), 50.)]);
+    assert!(boosted.concat().contains(&sound), "{boosted:?}");
+
+    let invalid = DecodeOptions {
+        boost_phrases: vec![("sound".to_string(), f32::NAN)],
+        ..Default::default()
+    };
+    assert!(invalid.validate().is_err());
+}
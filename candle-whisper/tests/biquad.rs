@@ -0,0 +1,69 @@
+use candle_whisper::{audio::Biquad, fixtures::sine_pcm, logic::m};
+use std::f32::consts::FRAC_1_SQRT_2;
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|v| v * v).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Ratio of the RMS of `filter` applied to one second of `input` to the RMS of `input`, the
+/// first 100ms of transient left out.
+fn gain(mut filter: Biquad, input: &[f32]) -> f32 {
+    let mut output = input.to_vec();
+    filter.process(&mut output);
+    let settled = m::SAMPLE_RATE / 10;
+    rms(&output[settled..]) / rms(&input[settled..])
+}
+
+#[test]
+fn highpass_removes_dc_and_passes_speech() {
+    let highpass = || Biquad::highpass(m::SAMPLE_RATE, 80., FRAC_1_SQRT_2);
+    let dc = vec![0.5; m::SAMPLE_RATE];
+    assert!(gain(highpass(), &dc) < 1e-3, "{}", gain(highpass(), &dc));
+    let tone = sine_pcm(1., 1000.);
+    assert!((gain(highpass(), &tone) - 1.).abs() < 0.01);
+    // A Butterworth filter is 3dB down at its cutoff.
+    let cutoff = sine_pcm(1., 80.);
+    assert!((gain(highpass(), &cutoff) - FRAC_1_SQRT_2).abs() < 0.01);
+}
+
+#[test]
+fn lowpass_passes_dc_and_removes_high_frequencies() {
+    let lowpass = || Biquad::lowpass(m::SAMPLE_RATE, 1000., FRAC_1_SQRT_2);
+    let dc = vec![0.5; m::SAMPLE_RATE];
+    assert!((gain(lowpass(), &dc) - 1.).abs() < 1e-3);
+    // 12dB per octave, two octaves above the cutoff.
+    let tone = sine_pcm(1., 4000.);
+    assert!(
+        gain(lowpass(), &tone) < 1. / 16.,
+        "{}",
+        gain(lowpass(), &tone)
+    );
+}
+
+#[test]
+fn bandpass_has_unit_gain_at_its_center() {
+    let bandpass = || Biquad::bandpass(m::SAMPLE_RATE, 1000., 2.);
+    assert!((gain(bandpass(), &sine_pcm(1., 1000.)) - 1.).abs() < 0.01);
+    assert!(gain(bandpass(), &sine_pcm(1., 100.)) < 0.1);
+    assert!(gain(bandpass(), &vec![0.5; m::SAMPLE_RATE]) < 1e-3);
+}
+
+#[test]
+fn state_is_kept_across_chunks() {
+    let input = sine_pcm(0.5, 440.);
+    let mut whole = input.clone();
+    Biquad::highpass(m::SAMPLE_RATE, 80., FRAC_1_SQRT_2).process(&mut whole);
+
+    let mut filter = Biquad::highpass(m::SAMPLE_RATE, 80., FRAC_1_SQRT_2);
+    let mut chunked = input.clone();
+    for chunk in chunked.chunks_mut(1000) {
+        filter.process(chunk);
+    }
+    assert_eq!(chunked, whole);
+
+    // Once reset, the filter starts from silence again.
+    filter.reset();
+    let mut again = input;
+    filter.process(&mut again);
+    assert_eq!(again, whole);
+}
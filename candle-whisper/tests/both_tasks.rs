@@ -0,0 +1,97 @@
+use candle_whisper::{
+    builder::DecoderBuilder,
+    encoder_cache::EncoderCacheOptions,
+    fixtures::{large_vocab_model_data, sine_pcm, tiny_model_data},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, LogitsContext, RunOptions, Segment, Task},
+};
+use std::{cell::RefCell, rc::Rc};
+use tokenizers::Tokenizer;
+
+fn options() -> DecodeOptions {
+    DecodeOptions {
+        temperatures: vec![0.],
+        no_speech_threshold: None,
+        encoder_cache: Some(EncoderCacheOptions::default()),
+        hallucination: HallucinationOptions {
+            drop: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn both_tasks_share_the_encoder_output() {
+    let md = large_vocab_model_data();
+    let tokenizer = Tokenizer::from_bytes(&md.tokenizer).unwrap();
+    let transcribe = tokenizer.token_to_id("<|transcribe|>").unwrap();
+    let translate = tokenizer.token_to_id("<|translate|>").unwrap();
+    let mut decoder = DecoderBuilder::from(md).task(Task::Both).build().unwrap();
+    decoder.set_options(options()).unwrap();
+    let first_steps = Rc::new(RefCell::new(vec![]));
+    let recorded = first_steps.clone();
+    decoder.set_logits_processor(Some(Box::new(
+        move |_: &mut [f32], context: &LogitsContext| {
+            if context.step == 0 {
+                recorded.borrow_mut().push(context.segment_start);
+            }
+        },
+    )));
+    let output = decoder
+        .run_pcm(&sine_pcm(35., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.task, Task::Both);
+    assert_eq!(output.segments.len(), 2);
+
+    // One encoder forward per window.
+    let stats = decoder.encoder_cache_stats();
+    assert_eq!((stats.misses, stats.hits), (2, 0));
+    // Two decoder sequences per window, the transcription then the translation.
+    assert_eq!(*first_steps.borrow(), [0., 0., 30., 30.]);
+    for segment in &output.segments {
+        let translation = segment.translation.as_ref().unwrap();
+        let language = segment.dr.tokens[1];
+        assert_eq!(
+            segment.dr.tokens[..3],
+            [segment.dr.tokens[0], language, transcribe]
+        );
+        assert_eq!(
+            translation.tokens[..3],
+            [segment.dr.tokens[0], language, translate]
+        );
+    }
+}
+
+#[test]
+fn single_tasks_have_no_translation() {
+    let mut decoder = DecoderBuilder::from(large_vocab_model_data())
+        .task(Task::Translate)
+        .build()
+        .unwrap();
+    decoder.set_options(options()).unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert!(output.segments.iter().all(|s| s.translation.is_none()));
+
+    // The English-only models do not translate.
+    let built = DecoderBuilder::from(tiny_model_data())
+        .task(Task::Both)
+        .build();
+    assert!(built.is_err());
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    assert!(decoder.set_task(Task::Both).is_err());
+}
+
+#[test]
+fn segments_without_translation_still_deserialize() {
+    let json = r#"{"start": 0.0, "duration": 2.0, "dr": {"tokens": [1], "text": "hello",
+        "avg_logprob": -0.1, "no_speech_prob": 0.1, "temperature": 0.0,
+        "compression_ratio": 1.0}}"#;
+    let segment: Segment = serde_json::from_str(json).unwrap();
+    assert!(segment.translation.is_none());
+    let value = serde_json::to_value(&segment).unwrap();
+    assert!(value["translation"].is_null());
+    assert_eq!("both".parse::<Task>().unwrap(), Task::Both);
+}
@@ -0,0 +1,144 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_config, tiny_model_data, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, RunOptions, Segment, TruncationReason},
+    timings::Clock,
+};
+use std::{cell::Cell, rc::Rc};
+
+/// `<|startoftranscript|>` and `<|notimestamps|>`.
+const PROMPT_LEN: usize = 2;
+
+/// Clock moving forward by `step_ms` every time it is read.
+struct SteppingClock {
+    now_ms: Rc<Cell<f64>>,
+    step_ms: f64,
+}
+
+impl Clock for SteppingClock {
+    fn now_ms(&self) -> f64 {
+        self.now_ms.set(self.now_ms.get() + self.step_ms);
+        self.now_ms.get()
+    }
+}
+
+/// Decoder that never ends a window, its end of text token being suppressed.
+fn endless_decoder(options: DecodeOptions) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let eot = TEXT_TOKENS.len() as u32;
+    decoder
+        .set_options(DecodeOptions {
+            extra_suppress_tokens: vec![eot],
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                drop: false,
+                ..Default::default()
+            },
+            ..options
+        })
+        .unwrap();
+    decoder
+}
+
+fn sampled(segment: &Segment) -> usize {
+    segment.dr.tokens.len() - PROMPT_LEN
+}
+
+#[test]
+fn token_budget_truncates_without_fallback() {
+    let mut decoder = endless_decoder(DecodeOptions {
+        max_tokens_per_segment: Some(5),
+        ..Default::default()
+    });
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert!(!output.segments.is_empty());
+    for segment in output.segments.iter() {
+        assert_eq!(sampled(segment), 5);
+        assert!(segment.dr.truncated);
+        assert_eq!(
+            segment.dr.truncation_reason,
+            Some(TruncationReason::MaxTokens)
+        );
+        assert_eq!(segment.dr.attempts, 1);
+    }
+}
+
+#[test]
+fn token_budget_defaults_to_half_the_context() {
+    let mut decoder = endless_decoder(DecodeOptions::default());
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let segment = &output.segments[0];
+    assert_eq!(sampled(segment), tiny_config().max_target_positions / 2);
+    assert_eq!(
+        segment.dr.truncation_reason,
+        Some(TruncationReason::MaxTokens)
+    );
+}
+
+#[test]
+fn segment_time_budget_truncates_on_the_clock() {
+    let mut decoder = endless_decoder(DecodeOptions {
+        max_decode_seconds_per_segment: Some(0.15),
+        ..Default::default()
+    });
+    decoder.set_clock(Box::new(SteppingClock {
+        now_ms: Rc::default(),
+        step_ms: 100.,
+    }));
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let segment = &output.segments[0];
+    assert!(segment.dr.truncated);
+    assert_eq!(
+        segment.dr.truncation_reason,
+        Some(TruncationReason::SegmentTime)
+    );
+    let sampled = sampled(segment);
+    assert!(sampled > 0 && sampled < tiny_config().max_target_positions / 2);
+}
+
+#[test]
+fn total_time_budget_leaves_the_last_windows_empty() {
+    let mut decoder = endless_decoder(DecodeOptions {
+        max_total_seconds: Some(0.25),
+        ..Default::default()
+    });
+    decoder.set_clock(Box::new(SteppingClock {
+        now_ms: Rc::default(),
+        step_ms: 100.,
+    }));
+    let output = decoder
+        .run_pcm(&sine_pcm(35., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.segments.len(), 2);
+    assert!(output.failed_segments.is_empty());
+    let last = &output.segments[1];
+    assert_eq!(last.dr.truncation_reason, Some(TruncationReason::TotalTime));
+    assert_eq!(sampled(last), 0);
+}
+
+#[test]
+fn budgets_must_be_positive() {
+    let invalid = [
+        DecodeOptions {
+            max_tokens_per_segment: Some(0),
+            ..Default::default()
+        },
+        DecodeOptions {
+            max_decode_seconds_per_segment: Some(0.),
+            ..Default::default()
+        },
+        DecodeOptions {
+            max_total_seconds: Some(f64::NAN),
+            ..Default::default()
+        },
+    ];
+    for options in invalid {
+        assert!(options.validate().is_err());
+    }
+}
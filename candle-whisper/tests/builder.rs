@@ -0,0 +1,64 @@
+use candle_core::quantized::GgmlDType;
+use candle_whisper::{
+    builder::DecoderBuilder,
+    error::WhisperError,
+    fixtures::{tiny_config_json, tiny_model_data},
+    logic::Task,
+};
+
+fn invalid_config(builder: DecoderBuilder) -> String {
+    match builder.build() {
+        Err(WhisperError::InvalidConfig { reason }) => reason,
+        Err(err) => panic!("unexpected error {err}"),
+        Ok(_) => panic!("the build succeeded"),
+    }
+}
+
+#[test]
+fn missing_pieces_are_reported_together() {
+    assert_eq!(
+        invalid_config(DecoderBuilder::new()),
+        "missing model weights, missing tokenizer, missing config"
+    );
+    let reason = invalid_config(DecoderBuilder::new().config(tiny_config_json()));
+    assert_eq!(reason, "missing model weights, missing tokenizer");
+}
+
+#[test]
+fn several_weights_are_rejected() {
+    let data = tiny_model_data();
+    let builder = DecoderBuilder::from(tiny_model_data()).weights_gguf(data.weights);
+    assert_eq!(
+        invalid_config(builder),
+        "several model weights were provided"
+    );
+}
+
+#[test]
+fn language_and_translation_need_a_multilingual_model() {
+    let builder = DecoderBuilder::from(tiny_model_data()).language(Some("fr"));
+    assert_eq!(
+        invalid_config(builder),
+        "a language cannot be set for non-multilingual models"
+    );
+    let builder = DecoderBuilder::from(tiny_model_data())
+        .language(Some("french"))
+        .task(Task::Translate);
+    assert_eq!(
+        invalid_config(builder),
+        "a language cannot be set for non-multilingual models, \
+         the translate task requires a multilingual model"
+    );
+}
+
+#[test]
+fn normal_and_quantized_models_are_built() {
+    let decoder = DecoderBuilder::from(tiny_model_data()).build().unwrap();
+    assert!(!decoder.model_info().quantized);
+
+    let decoder = DecoderBuilder::from(tiny_model_data())
+        .quantize_on_load(Some(GgmlDType::Q8_0))
+        .build()
+        .unwrap();
+    assert!(decoder.model_info().quantized);
+}
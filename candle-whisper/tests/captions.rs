@@ -0,0 +1,111 @@
+use candle_whisper::{
+    captions::{SrtWriter, VttWriter},
+    fixtures::segment,
+    logic::Segment,
+};
+use serde_json::json;
+
+/// Segments as a streaming decoder emits them: an out-of-order pair, a silent window and an
+/// interim result revised once its cue was emitted.
+fn scripted() -> Vec<Segment> {
+    let mut silence = segment(3, 7.5, 2.5, "");
+    silence.no_speech = true;
+    vec![
+        segment(0, 0., 2.5, " Hello"),
+        segment(2, 5., 2.5, " how are you?"),
+        segment(1, 2.5, 2.5, " world."),
+        silence,
+        segment(4, 3601.25, 1.5, " Fine <thanks> & you"),
+        segment(2, 5., 2.75, " How are you?"),
+    ]
+}
+
+#[test]
+fn srt_cues_are_emitted_in_order_and_revised() {
+    let mut writer = SrtWriter::new();
+    let pushed: Vec<String> = scripted().iter().map(|s| writer.push(s)).collect();
+    assert_eq!(
+        pushed,
+        [
+            "",
+            "1\n00:00:00,000 --> 00:00:02,500\nHello\n\n",
+            "2\n00:00:02,500 --> 00:00:05,000\nworld.\n\n",
+            "",
+            "3\n00:00:05,000 --> 00:00:07,500\nhow are you?\n\n",
+            "3\n00:00:05,000 --> 00:00:07,750\nHow are you?\n\n",
+        ]
+    );
+    assert_eq!(
+        writer.finish(),
+        "4\n01:00:01,250 --> 01:00:02,750\nFine <thanks> & you\n\n"
+    );
+    assert_eq!(writer.finish(), "");
+}
+
+#[test]
+fn vtt_emits_the_header_first_and_escapes_the_text() {
+    let mut writer = VttWriter::with_max_reorder(0);
+    let pushed: Vec<String> = scripted().iter().map(|s| writer.push(s)).collect();
+    assert_eq!(
+        pushed,
+        [
+            "WEBVTT\n\n1\n00:00:00.000 --> 00:00:02.500\nHello\n\n",
+            "2\n00:00:05.000 --> 00:00:07.500\nhow are you?\n\n",
+            // Beyond the reorder window the late segment is emitted as it comes.
+            "3\n00:00:02.500 --> 00:00:05.000\nworld.\n\n",
+            "",
+            "4\n01:00:01.250 --> 01:00:02.750\nFine &lt;thanks&gt; &amp; you\n\n",
+            "2\n00:00:05.000 --> 00:00:07.750\nHow are you?\n\n",
+        ]
+    );
+    assert_eq!(writer.finish(), "");
+    assert_eq!(VttWriter::new().finish(), "WEBVTT\n\n");
+}
+
+#[test]
+fn pending_segments_are_revised_before_being_emitted() {
+    let mut writer = SrtWriter::with_max_reorder(2);
+    assert_eq!(writer.push(&segment(0, 0., 1., "interim")), "");
+    assert_eq!(writer.push(&segment(0, 0., 1.5, "final\n\n text")), "");
+    assert_eq!(writer.push(&segment(1, 1.5, 1., "next")), "");
+    assert_eq!(
+        writer.finish(),
+        "1\n00:00:00,000 --> 00:00:01,500\nfinal\ntext\n\n\
+         2\n00:00:01,500 --> 00:00:02,500\nnext\n\n"
+    );
+}
+
+/// Segments with word timings, in Latin and CJK scripts, and one without.
+fn with_words() -> Vec<Segment> {
+    let words = |words: &[(&str, f64, f64)]| {
+        let words: Vec<_> = words
+            .iter()
+            .map(|(word, start, end)| json!({ "word": word, "start": start, "end": end }))
+            .collect();
+        serde_json::from_value(json!(words)).unwrap()
+    };
+    let mut latin = segment(0, 1., 2., " Hello world.");
+    latin.words = words(&[(" Hello", 1., 1.4), (" world.", 1.5, 2.9)]);
+    let mut cjk = segment(1, 3., 1.5, "你好世界");
+    // The last word ends past the segment.
+    cjk.words = words(&[("你", 3., 3.3), ("好", 3.3, 3.7), ("世界", 3.7, 4.8)]);
+    vec![latin, cjk, segment(2, 5., 1., " a <b>")]
+}
+
+#[test]
+fn vtt_karaoke_cues_time_every_word() {
+    let mut writer = VttWriter::with_max_reorder(0).with_word_timestamps(true);
+    let mut vtt: String = with_words().iter().map(|s| writer.push(s)).collect();
+    vtt.push_str(&writer.finish());
+    assert_eq!(
+        vtt,
+        "WEBVTT\n\n\
+         1\n00:00:01.000 --> 00:00:03.000\nHello <00:00:01.500>world.\n\n\
+         2\n00:00:03.000 --> 00:00:04.500\n你<00:00:03.300>好<00:00:03.700>世界\n\n\
+         3\n00:00:05.000 --> 00:00:06.000\na &lt;b&gt;\n\n"
+    );
+
+    let mut writer = VttWriter::with_max_reorder(0);
+    let plain: String = with_words().iter().map(|s| writer.push(s)).collect();
+    assert!(plain.contains("\nHello world.\n") && plain.contains("\n你好世界\n"));
+}
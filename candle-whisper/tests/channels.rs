@@ -0,0 +1,116 @@
+use candle_whisper::{
+    audio::ChannelSelect,
+    error::WhisperError,
+    fixtures::{sine_wav, tiny_model_data},
+    logic::{AudioInput, Decoder, RunOptions},
+};
+use std::rc::Rc;
+
+/// 16-bit 16kHz stereo WAV file with broadband noise followed by as much silence on the left
+/// and silence on the right. The noise spreads its energy over the mel bands like speech.
+fn stereo_wav(seconds: f64) -> Vec<u8> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = std::io::Cursor::new(vec![]);
+    let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+    let frames = (seconds * 16000.) as usize;
+    let mut state = 1u32;
+    let left = (0..frames).map(|i| {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        match i < frames / 2 {
+            true => (state >> 8) as f32 / (1 << 24) as f32 - 0.5,
+            false => 0.,
+        }
+    });
+    for sample in left {
+        writer
+            .write_sample((sample * i16::MAX as f32) as i16)
+            .unwrap();
+        writer.write_sample(0i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    bytes.into_inner()
+}
+
+#[test]
+fn channels_are_extracted_from_the_interleaved_samples() {
+    let samples = [0.5, -0.5, 0.25, 0.75];
+    assert_eq!(ChannelSelect::Mix.extract(&samples, 2).unwrap(), [0., 0.5]);
+    assert_eq!(
+        ChannelSelect::Left.extract(&samples, 2).unwrap(),
+        [0.5, 0.25]
+    );
+    assert_eq!(
+        ChannelSelect::Right.extract(&samples, 2).unwrap(),
+        [-0.5, 0.75]
+    );
+    let err = ChannelSelect::Index(1).extract(&samples, 1).unwrap_err();
+    assert!(matches!(
+        &err,
+        WhisperError::InvalidConfig { reason }
+            if reason == "channel 1 is out of range, the audio has 1 channel"
+    ));
+    let err = ChannelSelect::Index(3).extract(&samples, 2).unwrap_err();
+    assert!(
+        err.to_string().contains("the audio has 2 channels"),
+        "{err}"
+    );
+    assert_eq!(ChannelSelect::Mix.extract(&samples, 1).unwrap(), samples);
+}
+
+#[test]
+fn selected_channel_drives_the_mel() {
+    let decoder = Decoder::load(tiny_model_data()).unwrap();
+    let wav = stereo_wav(2.);
+    let energy = |channel| {
+        decoder
+            .convert_channel_to_mel(&wav, channel)
+            .unwrap()
+            .speech_energy_ratio()
+            .unwrap()
+    };
+    let left = energy(ChannelSelect::Left);
+    assert!((0.4..0.6).contains(&left), "{left}");
+    assert_eq!(energy(ChannelSelect::Right), 0.);
+    assert_eq!(energy(ChannelSelect::Index(1)), 0.);
+    // The mix halves the tone instead of interleaving the channels.
+    let mix = decoder.convert_to_mel(&wav).unwrap();
+    assert!((mix.duration() - 2.).abs() < 1e-9);
+    assert!((mix.speech_energy_ratio().unwrap() - left).abs() < 0.05);
+
+    let err = decoder
+        .convert_channel_to_mel(&sine_wav(1., 440.), ChannelSelect::Right)
+        .unwrap_err();
+    assert!(matches!(err, WhisperError::InvalidConfig { .. }), "{err}");
+}
+
+#[test]
+fn channels_of_a_file_are_separate_batch_jobs() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let wav: Rc<[u8]> = stereo_wav(2.).into();
+    let outputs = decoder.transcribe_batch(&[
+        AudioInput::WavChannel(wav.clone(), ChannelSelect::Left),
+        AudioInput::WavChannel(wav.clone(), ChannelSelect::Index(5)),
+        AudioInput::WavChannel(wav, ChannelSelect::Right),
+    ]);
+    assert!(outputs[0].is_ok());
+    assert!(matches!(
+        outputs[1],
+        Err(WhisperError::InvalidConfig { .. })
+    ));
+    assert!(outputs[2].is_ok());
+
+    let opts = RunOptions {
+        channel: ChannelSelect::Left,
+        ..Default::default()
+    };
+    let left = decoder
+        .convert_and_run_with_options(&stereo_wav(2.), &opts)
+        .unwrap();
+    let first = outputs[0].as_ref().unwrap();
+    assert_eq!(left.segments.len(), first.segments.len());
+}
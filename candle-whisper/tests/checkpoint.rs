@@ -0,0 +1,225 @@
+use candle_whisper::{
+    audio::MelSpectrogram,
+    builder::DecoderBuilder,
+    error::WhisperError,
+    fixtures::{
+        english_only_model_data, large_vocab_model_data, sine_pcm, tiny_model_data, TEXT_TOKENS,
+    },
+    hallucination::HallucinationOptions,
+    logic::{Checkpoint, DecodeOptions, Decoder, RunOptions, Task, TranscriptionOutput},
+};
+
+fn decoder() -> Decoder {
+    Decoder::load(tiny_model_data()).unwrap()
+}
+
+/// Decoder conditioned on the previous text, which the tokenizer of the English-only model
+/// has the `<|startofprev|>` token for. Only the text tokens and EOT are sampled, and no
+/// window is considered a hallucination, which would clear the previous text.
+fn conditioned_decoder() -> Decoder {
+    let mut decoder = Decoder::load(english_only_model_data()).unwrap();
+    let eot = TEXT_TOKENS.len() as u32;
+    decoder
+        .set_options(DecodeOptions {
+            condition_on_previous_text: true,
+            extra_suppress_tokens: (eot + 1..decoder.config().vocab_size as u32).collect(),
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                similarity_threshold: 2.,
+                max_ngram_coverage: 1.,
+                blocklist: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+}
+
+/// Three windows.
+fn mel() -> MelSpectrogram {
+    decoder().compute_mel(&sine_pcm(70., 440.)).unwrap()
+}
+
+/// Uninterrupted transcription with the checkpoints of every window.
+fn uninterrupted(
+    decoder: fn() -> Decoder,
+    mel: &MelSpectrogram,
+) -> (TranscriptionOutput, Vec<Checkpoint>) {
+    let mut checkpoints = vec![];
+    let output = decoder()
+        .run_resumable(mel, &RunOptions::default(), &mut |checkpoint| {
+            checkpoints.push(checkpoint.clone());
+            true
+        })
+        .unwrap();
+    (output, checkpoints)
+}
+
+/// Checkpoint stored as JSON and read back.
+fn round_trip(checkpoint: &Checkpoint) -> Checkpoint {
+    let json = serde_json::to_string(checkpoint).unwrap();
+    let restored: Checkpoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+    restored
+}
+
+fn json(output: &TranscriptionOutput) -> serde_json::Value {
+    serde_json::to_value(output).unwrap()
+}
+
+#[test]
+fn resumed_run_matches_the_uninterrupted_one() {
+    let mel = mel();
+    let (expected, checkpoints) = uninterrupted(decoder, &mel);
+    assert_eq!(checkpoints.len(), 3);
+    assert_eq!(checkpoints[0].seek_frame, 3000);
+
+    // Stopped after the first window.
+    let mut stored = None;
+    let err = decoder()
+        .run_resumable(&mel, &RunOptions::default(), &mut |checkpoint| {
+            stored = Some(serde_json::to_string(checkpoint).unwrap());
+            false
+        })
+        .unwrap_err();
+    assert!(matches!(err, WhisperError::Cancelled), "{err:?}");
+    let checkpoint: Checkpoint = serde_json::from_str(&stored.unwrap()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&checkpoint).unwrap(),
+        serde_json::to_value(&checkpoints[0]).unwrap()
+    );
+
+    // Resumed by another decoder, the rest of the checkpoints are the same too.
+    let mut resumed_checkpoints = vec![];
+    let resumed = decoder()
+        .resume(
+            &mel,
+            &RunOptions::default(),
+            checkpoint,
+            &mut |checkpoint| {
+                resumed_checkpoints.push(round_trip(checkpoint));
+                true
+            },
+        )
+        .unwrap();
+    assert_eq!(json(&resumed), json(&expected));
+    assert_eq!(
+        serde_json::to_value(&resumed_checkpoints).unwrap(),
+        serde_json::to_value(&checkpoints[1..]).unwrap()
+    );
+}
+
+#[test]
+fn resumed_run_keeps_the_previous_text() {
+    let mel = mel();
+    let (expected, checkpoints) = uninterrupted(conditioned_decoder, &mel);
+    assert!(!checkpoints[0].prompt_tokens.is_empty());
+    let resumed = conditioned_decoder()
+        .resume(
+            &mel,
+            &RunOptions::default(),
+            round_trip(&checkpoints[0]),
+            &mut |_| true,
+        )
+        .unwrap();
+    assert_eq!(json(&resumed), json(&expected));
+}
+
+#[test]
+fn resumed_run_keeps_the_previous_translation() {
+    let decoder = || {
+        let mut decoder = DecoderBuilder::from(large_vocab_model_data())
+            .task(Task::Both)
+            .build()
+            .unwrap();
+        let eot = decoder.special_tokens().eot;
+        decoder
+            .set_options(DecodeOptions {
+                condition_on_previous_text: true,
+                extra_suppress_tokens: (eot + 1..decoder.config().vocab_size as u32).collect(),
+                temperatures: vec![0.],
+                no_speech_threshold: None,
+                max_tokens_per_segment: Some(4),
+                ..Default::default()
+            })
+            .unwrap();
+        decoder
+    };
+    // Two windows.
+    let mel = decoder().compute_mel(&sine_pcm(35., 440.)).unwrap();
+    let (expected, checkpoints) = uninterrupted(decoder, &mel);
+    assert!(!checkpoints[0].translation_prompt_tokens.is_empty());
+    let resumed = decoder()
+        .resume(
+            &mel,
+            &RunOptions::default(),
+            checkpoints[0].clone(),
+            &mut |_| true,
+        )
+        .unwrap();
+    assert_eq!(json(&resumed), json(&expected));
+}
+
+#[test]
+fn resuming_at_the_end_returns_the_segments() {
+    let mel = mel();
+    let (expected, checkpoints) = uninterrupted(decoder, &mel);
+    let last = round_trip(checkpoints.last().unwrap());
+    assert!(last.seek_frame >= last.n_frames);
+    let mut calls = 0;
+    let resumed = decoder()
+        .resume(&mel, &RunOptions::default(), last, &mut |_| {
+            calls += 1;
+            true
+        })
+        .unwrap();
+    assert_eq!(calls, 0);
+    assert_eq!(json(&resumed), json(&expected));
+}
+
+#[test]
+fn checkpoints_of_other_runs_are_rejected() {
+    let mel = mel();
+    let (_, checkpoints) = uninterrupted(decoder, &mel);
+    let reason = |result: Result<TranscriptionOutput, WhisperError>| match result {
+        Err(WhisperError::InvalidConfig { reason }) => reason,
+        other => panic!("unexpected {other:?}"),
+    };
+
+    let mut decoder = decoder();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            ..Default::default()
+        })
+        .unwrap();
+    let result = decoder.resume(
+        &mel,
+        &RunOptions::default(),
+        checkpoints[0].clone(),
+        &mut |_| true,
+    );
+    assert!(reason(result).contains("other options"));
+
+    // The run options count too.
+    let opts = RunOptions {
+        timestamps: Some(false),
+        ..Default::default()
+    };
+    let result = self::decoder().resume(&mel, &opts, checkpoints[0].clone(), &mut |_| true);
+    assert!(reason(result).contains("other options"));
+
+    let shorter = self::decoder().compute_mel(&sine_pcm(50., 440.)).unwrap();
+    let result = self::decoder().resume(
+        &shorter,
+        &RunOptions::default(),
+        checkpoints[0].clone(),
+        &mut |_| true,
+    );
+    let n_frames = checkpoints[0].n_frames;
+    assert!(
+        reason(result).contains(&format!("covers {n_frames} mel frames")),
+        "{n_frames}"
+    );
+}
@@ -0,0 +1,109 @@
+use candle_whisper::{
+    chunked::{is_distilled, merge_point, merge_sequences, ChunkOptions},
+    fixtures::{sine_pcm, tiny_config, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+
+#[test]
+fn chunks_are_merged_on_their_common_tokens() {
+    assert_eq!(merge_point(&[1, 2, 3, 4, 5, 6], &[4, 5, 6, 7, 8]), (4, 1));
+    assert_eq!(
+        merge_sequences(&[vec![1, 2, 3, 4, 5, 6], vec![4, 5, 6, 7, 8]]),
+        [1, 2, 3, 4, 5, 6, 7, 8]
+    );
+    // A token decoded differently in the overlap does not prevent the alignment.
+    assert_eq!(
+        merge_sequences(&[vec![1, 2, 3, 9, 5, 6], vec![3, 4, 5, 6, 7]]),
+        [1, 2, 3, 9, 5, 6, 7]
+    );
+    assert_eq!(
+        merge_sequences(&[
+            vec![10, 11, 12, 13, 14],
+            vec![12, 13, 14, 15, 16, 17],
+            vec![16, 17, 18, 19],
+        ]),
+        [10, 11, 12, 13, 14, 15, 16, 17, 18, 19]
+    );
+}
+
+#[test]
+fn chunks_without_common_tokens_are_concatenated() {
+    assert_eq!(merge_point(&[1, 2, 3], &[4, 5, 6]), (3, 0));
+    // A single matching token is not enough to align the chunks.
+    assert_eq!(
+        merge_sequences(&[vec![1, 2, 3], vec![3, 4]]),
+        [1, 2, 3, 3, 4]
+    );
+    assert_eq!(merge_sequences(&[vec![], vec![1, 2]]), [1, 2]);
+    assert_eq!(merge_sequences(&[vec![1, 2], vec![]]), [1, 2]);
+    assert!(merge_sequences(&[]).is_empty());
+}
+
+#[test]
+fn distilled_models_are_detected_from_their_layers() {
+    let with_layers = |encoder_layers, decoder_layers| {
+        let mut config = tiny_config();
+        config.encoder_layers = encoder_layers;
+        config.decoder_layers = decoder_layers;
+        is_distilled(&config)
+    };
+    // distil-large-v3, distil-medium.en and distil-small.en.
+    assert!(with_layers(32, 2));
+    assert!(with_layers(24, 2));
+    assert!(with_layers(12, 4));
+    // tiny, base and large-v3.
+    assert!(!with_layers(4, 4));
+    assert!(!with_layers(6, 6));
+    assert!(!with_layers(32, 32));
+    assert!(!is_distilled(&tiny_config()));
+}
+
+#[test]
+fn chunked_segments_do_not_overlap() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            chunked_long_form: Some(true),
+            chunking: ChunkOptions {
+                chunk_seconds: 10.,
+                overlap_seconds: 2.,
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(25., 440.), &RunOptions::default())
+        .unwrap();
+    let mut end = 0.;
+    for segment in output.segments.iter() {
+        assert!(segment.start >= end - 1e-9);
+        assert!(segment.duration <= 10.);
+        end = segment.start + segment.duration;
+    }
+    assert!(end <= 25. + 1e-9);
+}
+
+#[test]
+fn chunk_options_are_validated() {
+    let invalid = [
+        ChunkOptions {
+            chunk_seconds: 40.,
+            overlap_seconds: 5.,
+        },
+        ChunkOptions {
+            chunk_seconds: 10.,
+            overlap_seconds: 5.,
+        },
+        ChunkOptions {
+            chunk_seconds: 0.,
+            overlap_seconds: 0.,
+        },
+    ];
+    for chunking in invalid {
+        let options = DecodeOptions {
+            chunking,
+            ..Default::default()
+        };
+        assert!(options.validate().is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use candle_whisper::{
+    confidence::{Band, ConfidenceThresholds, ConfidenceWeights, DEFAULT_CONFIDENCE_THRESHOLDS},
+    fixtures::segment,
+    logic::{DecodingResult, Segment},
+};
+use serde_json::json;
+
+/// Segment of `confidence` accepted at the `attempts`-th temperature.
+fn scored(confidence: f32, attempts: usize) -> Segment {
+    let mut segment = segment(0, 0., 1., "hello");
+    segment.confidence = confidence;
+    segment.dr.attempts = attempts;
+    segment
+}
+
+fn band(confidence: f32, attempts: usize) -> Band {
+    scored(confidence, attempts).confidence_band(&DEFAULT_CONFIDENCE_THRESHOLDS)
+}
+
+fn result(avg_logprob: f64, no_speech_prob: f64, compression_ratio: Option<f64>) -> DecodingResult {
+    serde_json::from_value(json!({
+        "tokens": [],
+        "text": "hello",
+        "avg_logprob": avg_logprob,
+        "no_speech_prob": no_speech_prob,
+        "temperature": 0.,
+        "compression_ratio": compression_ratio,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn bands_start_at_their_thresholds() {
+    assert_eq!(band(1., 1), Band::High);
+    assert_eq!(band(0.7, 1), Band::High);
+    assert_eq!(band(0.699, 1), Band::Medium);
+    assert_eq!(band(0.4, 1), Band::Medium);
+    assert_eq!(band(0.399, 1), Band::Low);
+    assert_eq!(band(0., 1), Band::Low);
+
+    let thresholds = ConfidenceThresholds {
+        high: 0.9,
+        medium: 0.5,
+    };
+    assert_eq!(scored(0.8, 1).confidence_band(&thresholds), Band::Medium);
+    assert_eq!(scored(0.45, 1).confidence_band(&thresholds), Band::Low);
+}
+
+#[test]
+fn temperature_fallback_is_at_most_medium() {
+    assert_eq!(band(1., 2), Band::Medium);
+    assert_eq!(band(0.7, 3), Band::Medium);
+    // The lower bands are kept.
+    assert_eq!(band(0.5, 2), Band::Medium);
+    assert_eq!(band(0.1, 2), Band::Low);
+}
+
+#[test]
+fn no_speech_and_placeholder_segments_are_low() {
+    let mut silence = scored(1., 1);
+    silence.no_speech = true;
+    assert_eq!(
+        silence.confidence_band(&DEFAULT_CONFIDENCE_THRESHOLDS),
+        Band::Low
+    );
+
+    let mut placeholder = scored(1., 1);
+    placeholder.error = Some("decoding failed".to_string());
+    assert_eq!(
+        placeholder.confidence_band(&DEFAULT_CONFIDENCE_THRESHOLDS),
+        Band::Low
+    );
+}
+
+#[test]
+fn score_combines_the_decoding_statistics() {
+    let weights = ConfidenceWeights::default();
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+    assert!(close(weights.score(&result(-0.2, 0., None)), 0.8));
+    assert!(close(weights.score(&result(-1., 0., None)), 0.));
+    assert!(close(weights.score(&result(0., 0.4, None)), 0.8));
+    // Only the compression ratio above 2.4 lowers the score.
+    assert!(close(weights.score(&result(0., 0., Some(2.4))), 1.));
+    assert!(close(weights.score(&result(0., 0., Some(3.))), 0.7));
+    // Clamped to [0, 1].
+    assert_eq!(weights.score(&result(-3., 1., Some(9.))), 0.);
+    assert_eq!(weights.score(&result(0.5, 0., None)), 1.);
+}
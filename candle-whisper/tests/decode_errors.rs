@@ -0,0 +1,86 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::{sine_pcm, tiny_config, tiny_model_data, tiny_weights_with},
+    logic::{DecodeOptions, Decoder, ModelData, RunOptions, SegmentErrorPolicy},
+};
+
+/// `<|startoftranscript|>` and `<|notimestamps|>`.
+const PROMPT_LEN: usize = 2;
+
+/// Decoder whose positional embeddings are NaN from the position of the token sampled at
+/// `step`, so that the logits are not finite from the decoding of the token after it.
+fn nan_decoder(step: usize, on_segment_error: SegmentErrorPolicy) -> Decoder {
+    let config = tiny_config();
+    let d_model = config.d_model;
+    let weights = tiny_weights_with(&config, |name, values| {
+        if name == "model.decoder.embed_positions.weight" {
+            values[(PROMPT_LEN + step) * d_model..].fill(f32::NAN);
+        }
+    })
+    .unwrap();
+    let mut decoder = Decoder::load(ModelData {
+        weights,
+        ..tiny_model_data()
+    })
+    .unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            on_segment_error,
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+}
+
+/// The first tokens sampled for the test audio by the unmodified model.
+fn sampled_tokens() -> Vec<u32> {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    output.segments[0].dr.tokens[PROMPT_LEN..].to_vec()
+}
+
+#[test]
+fn non_finite_logits_report_the_failing_step() {
+    let tokens = sampled_tokens();
+    assert!(tokens.len() > 2);
+    let mut decoder = nan_decoder(1, SegmentErrorPolicy::Abort);
+    let err = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), "non_finite_logits");
+    let WhisperError::NonFiniteLogits { context } = &err else {
+        panic!("unexpected error {err}");
+    };
+    assert_eq!(context.segment_start, 0.);
+    assert_eq!(context.seek_frame, 0);
+    assert_eq!(context.step, Some(2));
+    // The temperature schedule is not continued after non finite logits.
+    assert_eq!(context.temperature, Some(0.));
+    assert_eq!(context.tokens_generated, 2);
+    assert_eq!(context.last_tokens, tokens[..2]);
+    assert!(!context.quantized);
+    assert!(err.to_string().contains("step 2 at temperature 0"));
+
+    let json = serde_json::to_value(&err).unwrap();
+    assert_eq!(json["code"], "non_finite_logits");
+    assert_eq!(json["context"]["step"], 2);
+    assert_eq!(json["context"]["tokens_generated"], 2);
+}
+
+#[test]
+fn skipped_windows_keep_the_error_context() {
+    let tokens = sampled_tokens();
+    let mut decoder = nan_decoder(0, SegmentErrorPolicy::SkipAndContinue);
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert!(output.segments.is_empty());
+    let failure = &output.failed_segments[0];
+    assert_eq!(failure.code, "non_finite_logits");
+    let context = failure.context.as_ref().unwrap();
+    assert_eq!(context.step, Some(1));
+    assert_eq!(context.last_tokens, tokens[..1]);
+}
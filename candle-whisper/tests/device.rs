@@ -0,0 +1,81 @@
+//! Decoding on a GPU, the tests return early when the device of the enabled feature is not
+//! available.
+#![cfg(any(feature = "cuda", feature = "metal"))]
+
+use candle_core::{quantized::GgmlDType, Device};
+use candle_whisper::{
+    builder::DecoderBuilder,
+    error::WhisperError,
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+
+#[cfg(feature = "cuda")]
+fn gpu() -> Option<Device> {
+    Device::new_cuda(0).ok()
+}
+
+#[cfg(all(feature = "metal", not(feature = "cuda")))]
+fn gpu() -> Option<Device> {
+    Device::new_metal(0).ok()
+}
+
+fn tokens(decoder: &mut Decoder) -> Vec<Vec<u32>> {
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap();
+    output
+        .segments
+        .into_iter()
+        .map(|segment| segment.dr.tokens)
+        .collect()
+}
+
+#[test]
+fn gpu_decodes_as_the_cpu() {
+    let Some(device) = gpu() else { return };
+    let mut cpu = Decoder::load(tiny_model_data()).unwrap();
+    let mut gpu = DecoderBuilder::from(tiny_model_data())
+        .device(device)
+        .build()
+        .unwrap();
+    let expected = tokens(&mut cpu);
+    assert!(!expected.is_empty());
+    assert_eq!(tokens(&mut gpu), expected);
+}
+
+#[cfg(feature = "cuda")]
+#[test]
+fn cuda_if_available_decodes_as_the_cpu() {
+    let mut cpu = Decoder::load(tiny_model_data()).unwrap();
+    let mut decoder = DecoderBuilder::from(tiny_model_data())
+        .cuda_if_available(0)
+        .build()
+        .unwrap();
+    assert_eq!(tokens(&mut decoder), tokens(&mut cpu));
+}
+
+#[test]
+fn quantized_models_are_rejected_on_the_gpu() {
+    let Some(device) = gpu() else { return };
+    let err = DecoderBuilder::from(tiny_model_data())
+        .quantize_on_load(Some(GgmlDType::Q8_0))
+        .device(device.clone())
+        .build()
+        .err()
+        .unwrap();
+    match err {
+        WhisperError::InvalidConfig { reason } => assert_eq!(
+            reason,
+            format!("quantized models only run on the CPU, not on {device:?}")
+        ),
+        err => panic!("unexpected error {err}"),
+    }
+}
@@ -0,0 +1,199 @@
+use candle_whisper::{
+    diagnostics::{FallbackReason, WindowOutcome},
+    fixtures::{sine_pcm, tiny_model_data, TEXT_TOKENS},
+    logic::{compression_ratio, DecodeOptions, Decoder, LogitsContext, RunOptions},
+};
+use std::{cell::Cell, rc::Rc};
+
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+const HELLO: u32 = 1;
+
+fn decoder(options: DecodeOptions) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            collect_diagnostics: true,
+            ..options
+        })
+        .unwrap();
+    decoder
+}
+
+/// Decoder whose first attempt of every window samples from uniform logits, failing the log
+/// probability threshold, and whose next attempts are forced to `hello`.
+fn fallback_decoder() -> Decoder {
+    let mut decoder = decoder(DecodeOptions {
+        temperatures: vec![0., 0.2],
+        logprob_threshold: Some(-1.),
+        no_speech_threshold: None,
+        ..Default::default()
+    });
+    let attempts = Rc::new(Cell::new(0));
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.step == 0 {
+                attempts.set(attempts.get() + 1);
+            }
+            let forced = if context.step == 0 { HELLO } else { EOT };
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if attempts.get() == 1 && context.step == 0 {
+                    if logit.is_finite() {
+                        *logit = 0.;
+                    }
+                } else if token as u32 != forced {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        },
+    )));
+    decoder
+}
+
+#[test]
+fn diagnostics_are_only_collected_on_request() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert!(output.diagnostics.is_none());
+    let json = serde_json::to_value(&output).unwrap();
+    assert!(json.get("diagnostics").is_none());
+}
+
+#[test]
+fn diagnostics_record_a_forced_fallback() {
+    let mut decoder = fallback_decoder();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let diagnostics = output.diagnostics.unwrap();
+    assert_eq!(diagnostics.windows.len(), 1);
+    let window = &diagnostics.windows[0];
+    assert_eq!(window.outcome, WindowOutcome::Decoded);
+    assert_eq!((window.start, window.seek_frame), (0., 0));
+    assert!(window.fallback);
+    assert!(window.error.is_none());
+
+    let [rejected, kept] = &window.attempts[..] else {
+        panic!("expected two attempts, got {:?}", window.attempts);
+    };
+    assert_eq!(rejected.temperature, 0.);
+    assert!(!rejected.accepted);
+    assert!(rejected.avg_logprob < -1.);
+    assert_eq!(rejected.fallback_reasons, [FallbackReason::Logprob]);
+    assert_eq!(kept.temperature, 0.2);
+    assert!(kept.accepted);
+    assert!(kept.fallback_reasons.is_empty());
+    assert_eq!(kept.decode_steps, rejected.decode_steps);
+    assert!(kept.decode_steps > 0);
+
+    // The window reports the kept result, the one of the segment.
+    let segment = &output.segments[0];
+    assert_eq!(window.avg_logprob, kept.avg_logprob);
+    assert_eq!(window.avg_logprob, segment.dr.avg_logprob);
+    assert_eq!(window.no_speech_prob, segment.dr.no_speech_prob);
+    assert_eq!(segment.dr.text.trim(), "hello");
+}
+
+#[test]
+fn diagnostics_record_a_forced_skip() {
+    // Every result is above the no-speech threshold and below the log probability one.
+    let mut decoder = decoder(DecodeOptions {
+        temperatures: vec![0., 0.2],
+        no_speech_threshold: Some(0.),
+        logprob_threshold: Some(1.),
+        use_vad: false,
+        ..Default::default()
+    });
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert!(output.segments.is_empty());
+    let diagnostics = output.diagnostics.unwrap();
+    assert_eq!(diagnostics.windows.len(), 1);
+    let window = &diagnostics.windows[0];
+    assert_eq!(window.outcome, WindowOutcome::Silence);
+    assert!(!window.fallback);
+    // The silence is not retried at a higher temperature.
+    assert_eq!(window.attempts.len(), 1);
+    assert!(window.attempts[0].accepted);
+    assert!(window.attempts[0].fallback_reasons.is_empty());
+    assert_eq!(window.no_speech_prob, window.attempts[0].no_speech_prob);
+}
+
+#[test]
+fn diagnostics_serialize_with_stable_names() {
+    let mut decoder = fallback_decoder();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let json = serde_json::to_value(&output).unwrap();
+    assert_eq!(json["diagnostics"]["mel_normalization"], "global_max");
+    let window = &json["diagnostics"]["windows"][0];
+    assert_eq!(window["outcome"], "decoded");
+    assert_eq!(window["fallback"], true);
+    for field in ["start", "seek_frame", "no_speech_prob", "avg_logprob"] {
+        assert!(window.get(field).is_some(), "missing {field}");
+    }
+    assert!(window.get("error").is_none());
+    let attempt = &window["attempts"][0];
+    assert_eq!(attempt["fallback_reasons"], serde_json::json!(["logprob"]));
+    assert_eq!(attempt["accepted"], false);
+    for field in [
+        "temperature",
+        "decode_steps",
+        "avg_logprob",
+        "no_speech_prob",
+    ] {
+        assert!(attempt.get(field).is_some(), "missing {field}");
+    }
+    assert!(attempt["compression_ratio"].is_f64());
+}
+
+#[test]
+fn compression_ratio_follows_zlib() {
+    assert!(compression_ratio(" hello world") < 1.);
+    let looping = " hello".repeat(50);
+    // 300 bytes compressed to a header, one word, a back-reference and the checksum.
+    assert!(compression_ratio(&looping) > 9.);
+}
+
+#[test]
+fn repetition_loop_falls_back() {
+    let mut decoder = decoder(DecodeOptions {
+        temperatures: vec![0., 0.2],
+        logprob_threshold: None,
+        no_speech_threshold: None,
+        // The context of the tiny model is too short for the default threshold.
+        compression_ratio_threshold: Some(1.5),
+        ..Default::default()
+    });
+    // `hello` repeated until the end of text token by the first attempt, said once by the next.
+    let attempts = Rc::new(Cell::new(0));
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.step == 0 {
+                attempts.set(attempts.get() + 1);
+            }
+            let repeats = if attempts.get() == 1 { 9 } else { 1 };
+            let forced = if context.step < repeats { HELLO } else { EOT };
+            logits.fill(f32::NEG_INFINITY);
+            logits[forced as usize] = 0.;
+        },
+    )));
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let window = &output.diagnostics.unwrap().windows[0];
+    let [rejected, kept] = &window.attempts[..] else {
+        panic!("expected two attempts, got {:?}", window.attempts);
+    };
+    assert!(rejected.compression_ratio > 1.5);
+    assert_eq!(
+        rejected.fallback_reasons,
+        [FallbackReason::CompressionRatio]
+    );
+    assert!(kept.accepted);
+    assert!(kept.compression_ratio < 1.);
+    assert_eq!(output.segments[0].dr.text.trim(), "hello");
+}
@@ -0,0 +1,99 @@
+use candle_whisper::{
+    diff::{diff_segments, DiffOptions, DiffStats, EditKind},
+    fixtures::segment,
+    logic::Segment,
+};
+
+fn transcript() -> Vec<Segment> {
+    vec![
+        segment(0, 0., 4., " The quick brown fox"),
+        segment(1, 4., 4., " jumps over the dog."),
+    ]
+}
+
+#[test]
+fn identical_transcripts_have_no_errors() {
+    let a = transcript();
+    let diff = diff_segments(&a, &a, &DiffOptions::default());
+    assert!(diff.is_identical());
+    assert_eq!(diff.groups.len(), 2);
+    assert_eq!(
+        diff.stats,
+        DiffStats {
+            reference_words: 8,
+            ..Default::default()
+        }
+    );
+
+    // Case and punctuation only differ when they are compared.
+    let b = vec![
+        segment(0, 0., 4., " the Quick brown fox"),
+        segment(1, 4., 4., " jumps over the dog"),
+    ];
+    assert!(diff_segments(&a, &b, &DiffOptions::default()).is_identical());
+    let strict = DiffOptions {
+        ignore_case: false,
+        ignore_punctuation: false,
+    };
+    let diff = diff_segments(&a, &b, &strict);
+    assert_eq!(diff.stats.substitutions, 3);
+    assert_eq!(diff.stats.wer, 3. / 8.);
+}
+
+#[test]
+fn single_substitution_is_located() {
+    let a = transcript();
+    let b = vec![
+        segment(0, 0., 4., " The quick brown box"),
+        segment(1, 4., 4., " jumps over the dog."),
+    ];
+    let diff = diff_segments(&a, &b, &DiffOptions::default());
+    assert_eq!(diff.stats.substitutions, 1);
+    assert_eq!((diff.stats.insertions, diff.stats.deletions), (0, 0));
+    assert_eq!(diff.stats.wer, 1. / 8.);
+    let spans = &diff.groups[0].spans;
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].kind, EditKind::Replace);
+    assert_eq!(spans[0].a_words, ["fox"]);
+    assert_eq!(spans[0].b_words, ["box"]);
+    // The last of the 4 words of the segment.
+    assert_eq!((spans[0].start, spans[0].end), (3., 4.));
+    assert!(diff.groups[1].spans.is_empty());
+
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(json["groups"][0]["spans"][0]["kind"], "replace");
+}
+
+#[test]
+fn segments_with_other_boundaries_are_compared_as_a_whole() {
+    let a = transcript();
+    let b = vec![
+        segment(0, 0., 2.5, " The quick"),
+        segment(1, 2.5, 3., " brown fox jumps over"),
+        segment(2, 5.5, 2.5, " the lazy dog."),
+        segment(3, 9., 1., " Thanks."),
+    ];
+    let diff = diff_segments(&a, &b, &DiffOptions::default());
+    assert_eq!(diff.groups.len(), 2);
+    let group = &diff.groups[0];
+    assert_eq!(group.a_segments, [0, 1]);
+    assert_eq!(group.b_segments, [0, 1, 2]);
+    assert_eq!((group.start, group.end), (0., 8.));
+    assert_eq!(group.spans.len(), 1);
+    assert_eq!(group.spans[0].kind, EditKind::Insert);
+    assert_eq!(group.spans[0].b_words, ["lazy"]);
+
+    // Speech only in `b` is inserted.
+    let extra = &diff.groups[1];
+    assert!(extra.a_segments.is_empty());
+    assert_eq!(extra.b_segments, [3]);
+    assert_eq!(extra.stats.insertions, 1);
+    assert_eq!(diff.stats.insertions, 2);
+    assert_eq!(diff.stats.wer, 2. / 8.);
+
+    // The other way around the words are deleted.
+    let diff = diff_segments(&b, &a, &DiffOptions::default());
+    assert_eq!(diff.stats.deletions, 2);
+    assert_eq!(diff.stats.reference_words, 10);
+    assert_eq!(diff.groups[1].spans[0].kind, EditKind::Delete);
+}
@@ -0,0 +1,126 @@
+use candle_whisper::{
+    audio::{AudioPreprocess, DEFAULT_DITHER},
+    builder::DecoderBuilder,
+    fixtures::tiny_model_data,
+    logic::{DecodeOptions, Decoder},
+};
+use std::collections::BTreeMap;
+
+const SAMPLE_RATE: usize = 16000;
+
+/// Sine of peak `levels` 16-bit quantization steps, rounded to the steps as a quiet recording
+/// converted from integers.
+fn quantized_sine(seconds: usize, levels: f32) -> Vec<f32> {
+    (0..seconds * SAMPLE_RATE)
+        .map(|i| {
+            let v =
+                levels * (2. * std::f32::consts::PI * 440. * i as f32 / SAMPLE_RATE as f32).sin();
+            v.round() / 32768.
+        })
+        .collect()
+}
+
+/// Values of the mel bins over the frames of the `seconds` of audio, without the padding.
+fn mel_values(decoder: &Decoder, pcm: &[f32], seconds: usize) -> Vec<f32> {
+    let mel = decoder.compute_mel(pcm).unwrap();
+    let rows: Vec<Vec<f32>> = mel.tensor().squeeze(0).unwrap().to_vec2().unwrap();
+    rows.iter()
+        .flat_map(|row| &row[..seconds * 100])
+        .copied()
+        .collect()
+}
+
+fn decoder(dither: Option<f32>) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            preprocess: AudioPreprocess {
+                dither,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+}
+
+/// Counts of the values in bins of 0.05.
+fn histogram(values: &[f32]) -> BTreeMap<i32, usize> {
+    let mut histogram = BTreeMap::new();
+    for v in values {
+        *histogram.entry((v / 0.05).floor() as i32).or_insert(0) += 1;
+    }
+    histogram
+}
+
+#[test]
+fn dither_fills_the_gaps_of_a_quantized_sine() {
+    // 15 levels, a 4-bit signal.
+    let pcm = quantized_sine(5, 7.);
+    let plain = histogram(&mel_values(&decoder(None), &pcm, 5));
+    let dithered = histogram(&mel_values(&decoder(Some(DEFAULT_DITHER)), &pcm, 5));
+    let floor = *plain.keys().next().unwrap();
+    assert_eq!(floor, *dithered.keys().next().unwrap());
+    // Without the dither, the error of the quantization leaves a tenth of the bins at the floor
+    // of the dynamic range.
+    assert!(
+        plain[&floor] > 5 * dithered[&floor],
+        "{plain:?} {dithered:?}"
+    );
+    // The tone itself is unchanged.
+    let top = |h: &BTreeMap<i32, usize>| {
+        h.iter()
+            .rev()
+            .take(3)
+            .map(|(&k, &n)| (k, n))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(top(&plain), top(&dithered));
+
+    // The dither follows the seed of the decoder.
+    let pcm = quantized_sine(1, 7.);
+    let dithered = mel_values(&decoder(Some(DEFAULT_DITHER)), &pcm, 1);
+    assert_eq!(
+        mel_values(&decoder(Some(DEFAULT_DITHER)), &pcm, 1),
+        dithered
+    );
+    let mut reseeded = DecoderBuilder::from(tiny_model_data())
+        .seed(1)
+        .build()
+        .unwrap();
+    reseeded
+        .set_options(decoder(Some(DEFAULT_DITHER)).options().clone())
+        .unwrap();
+    assert_ne!(mel_values(&reseeded, &pcm, 1), dithered);
+}
+
+#[test]
+fn constant_input_gives_the_mel_of_silence() {
+    let decoder = decoder(None);
+    let silence = mel_values(&decoder, &vec![0.; 3 * SAMPLE_RATE], 3);
+    assert!(silence.iter().all(|&v| v == silence[0]));
+    // A DC offset, alone or with noise below one quantization step.
+    let offset = mel_values(&decoder, &vec![0.25; 3 * SAMPLE_RATE], 3);
+    assert_eq!(offset, silence);
+    let dithered = AudioPreprocess {
+        dither: Some(DEFAULT_DITHER),
+        ..Default::default()
+    }
+    .apply(&vec![0.25; 3 * SAMPLE_RATE])
+    .into_owned();
+    assert_eq!(mel_values(&decoder, &dithered, 3), silence);
+    // A quiet tone is kept.
+    let tone = mel_values(&decoder, &quantized_sine(3, 7.), 3);
+    assert_ne!(tone, silence);
+
+    assert!(Decoder::load(tiny_model_data())
+        .unwrap()
+        .set_options(DecodeOptions {
+            preprocess: AudioPreprocess {
+                dither: Some(-1.),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .is_err());
+}
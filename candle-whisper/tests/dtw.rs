@@ -0,0 +1,86 @@
+use candle_whisper::alignment::{dtw, median_filter};
+
+/// Frames each token is attended over, consecutive and of different lengths.
+const TOKEN_FRAMES: [std::ops::Range<usize>; 4] = [0..2, 2..7, 7..8, 8..12];
+const N_FRAMES: usize = 12;
+
+/// Normalized cross-attention of each token over the frames: high on the frames of the token,
+/// low elsewhere.
+fn attention(token_frames: &[std::ops::Range<usize>], n_frames: usize) -> Vec<Vec<f32>> {
+    token_frames
+        .iter()
+        .map(|frames| {
+            (0..n_frames)
+                .map(|frame| if frames.contains(&frame) { 1. } else { -0.5 })
+                .collect()
+        })
+        .collect()
+}
+
+/// Cost of the alignment as computed from the attention weights, their opposite.
+fn cost(attention: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    attention
+        .iter()
+        .map(|row| row.iter().map(|w| -w).collect())
+        .collect()
+}
+
+/// Frame each token starts at, where the path moves to its row.
+fn jumps(path: &[(usize, usize)]) -> Vec<usize> {
+    let mut jumps = vec![];
+    let mut previous_row = None;
+    for &(row, frame) in path {
+        if previous_row != Some(row) {
+            jumps.push(frame);
+            previous_row = Some(row);
+        }
+    }
+    jumps
+}
+
+#[test]
+fn path_follows_the_attended_frames() {
+    let path = dtw(&cost(&attention(&TOKEN_FRAMES, N_FRAMES)));
+    let expected: Vec<(usize, usize)> = TOKEN_FRAMES
+        .iter()
+        .enumerate()
+        .flat_map(|(token, frames)| frames.clone().map(move |frame| (token, frame)))
+        .collect();
+    assert_eq!(path, expected);
+    assert_eq!(jumps(&path), [0, 2, 7, 8]);
+}
+
+#[test]
+fn path_is_monotone_from_corner_to_corner() {
+    // More tokens than frames, some rows share a frame.
+    let cost = vec![vec![0.; 3]; 5];
+    let path = dtw(&cost);
+    assert_eq!(path.first(), Some(&(0, 0)));
+    assert_eq!(path.last(), Some(&(4, 2)));
+    for step in path.windows(2) {
+        let ((i0, j0), (i1, j1)) = (step[0], step[1]);
+        assert!(
+            i1 - i0 <= 1 && j1 - j0 <= 1 && (i1, j1) != (i0, j0),
+            "{path:?}"
+        );
+    }
+    assert!(dtw(&[]).is_empty());
+    assert!(dtw(&[vec![]]).is_empty());
+}
+
+#[test]
+fn median_filter_smooths_a_dropout() {
+    let token_frames = [0..8, 8..16, 16..24];
+    let mut attention = attention(&token_frames, 24);
+    // A frame of the second token attended to by the third.
+    attention[1][12] = -0.5;
+    attention[2][12] = 1.;
+
+    let filtered: Vec<Vec<f32>> = attention.iter().map(|row| median_filter(row, 7)).collect();
+    assert_eq!((filtered[1][12], filtered[2][12]), (1., -0.5));
+    let path = dtw(&cost(&filtered));
+    assert!(path.contains(&(1, 12)), "{path:?}");
+    assert_eq!(jumps(&path)[..2], [0, 8]);
+    // Rows not longer than half the width are left as is.
+    assert_eq!(median_filter(&[3., 1., 2.], 7), [3., 1., 2.]);
+}
@@ -0,0 +1,70 @@
+use candle_core::DType;
+use candle_whisper::{
+    builder::DecoderBuilder,
+    error::WhisperError,
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, ModelData, RunOptions, TranscriptionOutput},
+};
+
+fn transcribe(decoder: &mut Decoder) -> TranscriptionOutput {
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap()
+}
+
+#[test]
+fn f16_model_transcribes_as_the_f32_one() {
+    let mut f32_model = DecoderBuilder::from(tiny_model_data())
+        .dtype(DType::F32)
+        .build()
+        .unwrap();
+    let expected = transcribe(&mut f32_model);
+    assert!(!expected.segments.is_empty());
+    assert!(expected
+        .segments
+        .iter()
+        .all(|segment| segment.dr.avg_logprob.is_finite() && segment.dr.avg_logprob < 0.));
+    let text = |output: &TranscriptionOutput| -> Vec<String> {
+        output.segments.iter().map(|s| s.dr.text.clone()).collect()
+    };
+    let mut f16_model = DecoderBuilder::from(tiny_model_data())
+        .dtype(DType::F16)
+        .build()
+        .unwrap();
+    assert_eq!(text(&transcribe(&mut f16_model)), text(&expected));
+}
+
+#[test]
+fn unsupported_dtypes_are_rejected() {
+    for (dtype, expected) in [
+        (DType::U32, "unsupported dtype U32"),
+        (DType::BF16, "unsupported dtype BF16 on Cpu"),
+    ] {
+        let err = DecoderBuilder::from(tiny_model_data())
+            .dtype(dtype)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            WhisperError::InvalidConfig { reason } if reason == expected
+        ));
+    }
+
+    let data = ModelData {
+        dtype: Some("f64".to_string()),
+        ..tiny_model_data()
+    };
+    let err = Decoder::load(data).err().unwrap();
+    assert!(matches!(
+        err,
+        WhisperError::InvalidConfig { reason } if reason == "unknown dtype f64"
+    ));
+}
@@ -0,0 +1,127 @@
+use candle_whisper::{
+    audio::align_tracks,
+    fixtures::{self, tiny_model_data},
+    logic::{Decoder, RunOptions, Segment},
+    segments::interleave,
+};
+
+const SAMPLE_RATE: u32 = 16000;
+
+/// Noise in bursts of irregular lengths, like the syllables of speech.
+fn bursts(seconds: f64, seed: u64) -> Vec<f32> {
+    let mut state = seed;
+    let mut next = move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 33) as f32 / (1u64 << 31) as f32
+    };
+    let len = (seconds * SAMPLE_RATE as f64) as usize;
+    let mut pcm = Vec::with_capacity(len);
+    let mut on = false;
+    while pcm.len() < len {
+        let burst = ((0.1 + 0.6 * next()) * SAMPLE_RATE as f32) as usize;
+        for _ in 0..burst.min(len - pcm.len()) {
+            pcm.push(if on { 0.3 * (2. * next() - 1.) } else { 0. });
+        }
+        on = !on;
+    }
+    pcm
+}
+
+/// `pcm` heard `delay` seconds later, of the same length.
+fn delayed(pcm: &[f32], delay: f64) -> Vec<f32> {
+    let shift = (delay * SAMPLE_RATE as f64) as usize;
+    let mut out = vec![0.; shift];
+    out.extend_from_slice(&pcm[..pcm.len() - shift]);
+    out
+}
+
+#[test]
+fn offset_of_a_delayed_copy_is_found() {
+    let a = bursts(20., 1);
+    let hop = 160. / SAMPLE_RATE as f64;
+    let offset = align_tracks(&a, &delayed(&a, 1.234), SAMPLE_RATE, 2.).unwrap();
+    assert!((offset - 1.234).abs() <= hop, "{offset}");
+    // The quieter copy of the other device, the first track starting later.
+    let b: Vec<f32> = delayed(&a, 0.5).iter().map(|v| 0.2 * v).collect();
+    let offset = align_tracks(&b, &a, SAMPLE_RATE, 2.).unwrap();
+    assert!((offset + 0.5).abs() <= hop, "{offset}");
+    assert!(align_tracks(&a, &a, SAMPLE_RATE, 0.).unwrap().abs() <= hop);
+
+    let err = align_tracks(&a, &delayed(&a, 3.), SAMPLE_RATE, 1.).unwrap_err();
+    assert_eq!(err.code(), "unsupported_audio");
+    // A silent track is not aligned.
+    assert_eq!(align_tracks(&a, &[0.; 16000], SAMPLE_RATE, 1.).unwrap(), 0.);
+}
+
+fn segment(id: usize, start: f64, text: &str, no_speech: bool) -> Segment {
+    Segment {
+        no_speech,
+        ..fixtures::segment(id, start, 1., text)
+    }
+}
+
+#[test]
+fn tracks_are_interleaved_by_start() {
+    let a = vec![
+        segment(0, 0., "hello", false),
+        segment(1, 1., "", true),
+        segment(2, 4., "bye", false),
+    ];
+    let b = vec![
+        segment(0, 2., "hi", false),
+        segment(1, 3.5, "see you", false),
+    ];
+    let merged = interleave(vec![(0., a), (-0.5, b)]);
+    let texts: Vec<(usize, f64, &str)> = merged
+        .iter()
+        .map(|s| (s.id, s.start, s.dr.text.as_str()))
+        .collect();
+    assert_eq!(
+        texts,
+        [
+            (0, 0., "hello"),
+            (1, 1.5, "hi"),
+            (2, 3., "see you"),
+            (3, 4., "bye")
+        ]
+    );
+}
+
+#[test]
+fn dual_track_transcript_tags_and_aligns_the_speakers() {
+    let a = bursts(12., 2);
+    let b: Vec<f32> = delayed(&a, 0.75).iter().map(|v| 0.5 * v).collect();
+    let opts = RunOptions::default();
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let solo_b = decoder.run_pcm(&b, &opts).unwrap();
+    let output = decoder.transcribe_dual_track(&a, &b, 1., &opts).unwrap();
+
+    let speakers: Vec<&str> = output
+        .segments
+        .iter()
+        .filter_map(|s| s.speaker.as_deref())
+        .collect();
+    assert_eq!(speakers.len(), output.segments.len());
+    assert!(speakers.contains(&"A") && speakers.contains(&"B"));
+    assert!(output.segments.windows(2).all(|w| w[0].start <= w[1].start));
+    assert!(output.segments.iter().enumerate().all(|(i, s)| s.id == i));
+
+    let starts_b: Vec<f64> = output
+        .segments
+        .iter()
+        .filter(|s| s.speaker.as_deref() == Some("B"))
+        .map(|s| s.start)
+        .collect();
+    let expected: Vec<f64> = solo_b
+        .segments
+        .iter()
+        .filter(|s| !s.no_speech && s.error.is_none())
+        .map(|s| s.start - 0.75)
+        .collect();
+    assert_eq!(starts_b.len(), expected.len());
+    for (start, expected) in starts_b.iter().zip(&expected) {
+        assert!((start - expected).abs() <= 0.01, "{start} {expected}");
+    }
+}
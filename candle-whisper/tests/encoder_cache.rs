@@ -0,0 +1,101 @@
+use candle_whisper::{
+    encoder_cache::{EncoderCacheOptions, EncoderCacheStats},
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+
+fn decoder(encoder_cache: EncoderCacheOptions) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            encoder_cache: Some(encoder_cache),
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+}
+
+/// Transcribes a single window of a tone at `frequency`, returning the tokens and the number of
+/// encoder passes it took.
+fn transcribe(decoder: &mut Decoder, frequency: f64) -> (Vec<Vec<u32>>, usize) {
+    let before = decoder.encoder_forwards();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., frequency), &RunOptions::default())
+        .unwrap();
+    let tokens = output.segments.into_iter().map(|s| s.dr.tokens).collect();
+    (tokens, decoder.encoder_forwards() - before)
+}
+
+fn counts(stats: EncoderCacheStats) -> (usize, usize, usize) {
+    (stats.entries, stats.hits, stats.misses)
+}
+
+#[test]
+fn repeated_windows_skip_the_encoder() {
+    let mut decoder = decoder(EncoderCacheOptions::default());
+    let (first, forwards) = transcribe(&mut decoder, 440.);
+    assert_eq!(forwards, 1);
+    assert_eq!(counts(decoder.encoder_cache_stats()), (1, 0, 1));
+
+    let (second, forwards) = transcribe(&mut decoder, 440.);
+    assert_eq!(forwards, 0);
+    assert_eq!(second, first);
+    assert_eq!(counts(decoder.encoder_cache_stats()), (1, 1, 1));
+
+    // Another window misses.
+    assert_eq!(transcribe(&mut decoder, 880.).1, 1);
+    assert_eq!(counts(decoder.encoder_cache_stats()), (2, 1, 2));
+
+    decoder.clear_encoder_cache();
+    assert_eq!(decoder.encoder_cache_stats().entries, 0);
+    assert_eq!(transcribe(&mut decoder, 440.).1, 1);
+}
+
+#[test]
+fn least_recently_used_window_is_evicted() {
+    let mut decoder = decoder(EncoderCacheOptions {
+        max_entries: 2,
+        ..Default::default()
+    });
+    assert_eq!(transcribe(&mut decoder, 440.).1, 1);
+    assert_eq!(transcribe(&mut decoder, 880.).1, 1);
+    // Used again, 440 Hz is now the most recent entry.
+    assert_eq!(transcribe(&mut decoder, 440.).1, 0);
+    assert_eq!(transcribe(&mut decoder, 1320.).1, 1);
+    assert_eq!(decoder.encoder_cache_stats().entries, 2);
+
+    assert_eq!(transcribe(&mut decoder, 440.).1, 0);
+    assert_eq!(transcribe(&mut decoder, 880.).1, 1);
+    assert_eq!(counts(decoder.encoder_cache_stats()), (2, 2, 4));
+}
+
+#[test]
+fn cache_is_bounded_by_its_size() {
+    let mut first = decoder(EncoderCacheOptions::default());
+    transcribe(&mut first, 440.);
+    let entry_bytes = first.encoder_cache_stats().bytes;
+    assert!(entry_bytes > 0);
+
+    let mut decoder = decoder(EncoderCacheOptions {
+        max_bytes: entry_bytes,
+        ..Default::default()
+    });
+    transcribe(&mut decoder, 440.);
+    transcribe(&mut decoder, 880.);
+    let stats = decoder.encoder_cache_stats();
+    assert_eq!((stats.entries, stats.bytes), (1, entry_bytes));
+    assert_eq!(transcribe(&mut decoder, 880.).1, 0);
+    assert_eq!(transcribe(&mut decoder, 440.).1, 1);
+
+    // Disabling the cache drops its entries.
+    decoder
+        .set_options(DecodeOptions {
+            encoder_cache: None,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(decoder.encoder_cache_stats().entries, 0);
+    assert_eq!(transcribe(&mut decoder, 880.).1, 1);
+}
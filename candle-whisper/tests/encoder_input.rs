@@ -0,0 +1,67 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{m, DecodeOptions, Decoder, EncoderInput, RunOptions, TranscriptionOutput},
+};
+
+/// Transcribes the first 7 seconds of a 10-second spectrogram.
+fn run(encoder_input: EncoderInput) -> TranscriptionOutput {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            encoder_input,
+            collect_diagnostics: true,
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    let mel = decoder.compute_mel(&sine_pcm(10., 440.)).unwrap();
+    let opts = RunOptions {
+        end: Some(7.),
+        ..Default::default()
+    };
+    decoder.run_mel(&mel, &opts).unwrap()
+}
+
+fn encoder_frames(output: &TranscriptionOutput) -> Vec<usize> {
+    let windows = &output.diagnostics.as_ref().unwrap().windows;
+    windows.iter().map(|w| w.encoder_frames).collect()
+}
+
+fn times(output: &TranscriptionOutput) -> Vec<(f64, f64)> {
+    output
+        .segments
+        .iter()
+        .map(|s| (s.start, s.start + s.duration))
+        .collect()
+}
+
+#[test]
+fn encoder_windows_follow_the_mode() {
+    let exact = run(EncoderInput::Exact);
+    assert_eq!(encoder_frames(&exact), [700]);
+    let full = run(EncoderInput::PadToFull);
+    assert_eq!(encoder_frames(&full), [m::N_FRAMES]);
+    let truncated = run(EncoderInput::Truncate(5.));
+    assert_eq!(encoder_frames(&truncated), [500, 200]);
+    let starts: Vec<f64> = truncated.segments.iter().map(|s| s.start).collect();
+    assert_eq!(starts, [0., 5.]);
+
+    // The padding does not move the segments past the audio.
+    assert_eq!(times(&full), times(&exact));
+    assert_eq!(times(&full), [(0., 7.)]);
+}
+
+#[test]
+fn truncated_windows_must_fit_the_encoder() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    for seconds in [0., 31., f64::NAN] {
+        let options = DecodeOptions {
+            encoder_input: EncoderInput::Truncate(seconds),
+            ..Default::default()
+        };
+        assert!(decoder.set_options(options).is_err(), "{seconds}");
+    }
+    let json = serde_json::to_value(EncoderInput::Truncate(5.)).unwrap();
+    assert_eq!(json, serde_json::json!({ "truncate": 5.0 }));
+}
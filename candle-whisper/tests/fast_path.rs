@@ -0,0 +1,64 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, RunOptions, TranscriptionOutput},
+};
+
+fn run(greedy_fast_path: bool, set_processor: bool) -> TranscriptionOutput {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            greedy_fast_path,
+            temperatures: vec![0.],
+            collect_timings: true,
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                drop: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    if set_processor {
+        decoder.set_logits_processor(Some(Box::new(|_, _| {})));
+    }
+    decoder
+        .run_pcm(&sine_pcm(20., 440.), &RunOptions::default())
+        .unwrap()
+}
+
+#[test]
+fn fast_path_matches_the_host_path() {
+    let fast = run(true, false);
+    let host = run(false, false);
+    assert_eq!(fast.segments.len(), host.segments.len());
+    assert!(!fast.segments.is_empty());
+    for (fast, host) in fast.segments.iter().zip(&host.segments) {
+        assert_eq!(fast.dr.tokens, host.dr.tokens);
+        assert!(
+            (fast.dr.avg_logprob - host.dr.avg_logprob).abs() < 1e-4,
+            "{} {}",
+            fast.dr.avg_logprob,
+            host.dr.avg_logprob
+        );
+        for (a, b) in fast.dr.token_probs.iter().zip(&host.dr.token_probs) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn timings_report_the_path_of_every_pass() {
+    let fast = run(true, false).timings.unwrap();
+    assert!(fast.greedy_fast_path.passes > 0 && fast.greedy_fast_path.tokens > 0);
+    assert_eq!(fast.host_path.passes, 0);
+
+    let host = run(false, false).timings.unwrap();
+    assert_eq!(host.greedy_fast_path.passes, 0);
+    assert_eq!(host.host_path.tokens, fast.greedy_fast_path.tokens);
+
+    // A logits processor needs the logits on the host.
+    let hooked = run(true, true).timings.unwrap();
+    assert_eq!(hooked.greedy_fast_path.passes, 0);
+    assert_eq!(hooked.host_path.passes, fast.greedy_fast_path.passes);
+}
@@ -0,0 +1,223 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use candle_whisper::{
+    error::WhisperError,
+    fetch::{fetch_model, fetch_model_with_progress, FetchProgress, ModelVariant},
+    fixtures::tiny_model_data,
+    logic::Decoder,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Repo of the files of the tiny model, answering the ranged requests.
+#[derive(Default)]
+struct Repo {
+    files: HashMap<String, Vec<u8>>,
+    gets: AtomicUsize,
+    /// `Range` headers received.
+    ranges: Mutex<Vec<String>>,
+    /// Whether the next download of the weights is cut in the middle.
+    interrupt: AtomicBool,
+}
+
+fn etag(file: &str) -> String {
+    format!("\"{file}-1\"")
+}
+
+async fn serve(
+    State(repo): State<Arc<Repo>>,
+    method: Method,
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(data) = repo.files.get(&file) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if method == Method::HEAD {
+        let headers = [
+            (header::CONTENT_LENGTH, data.len().to_string()),
+            (header::ETAG, etag(&file)),
+        ];
+        return (headers, Body::empty()).into_response();
+    }
+    repo.gets.fetch_add(1, Ordering::SeqCst);
+    let mut start = 0;
+    if let Some(range) = headers.get(header::RANGE) {
+        let range = range.to_str().unwrap().to_string();
+        let if_range = headers.get(header::IF_RANGE).map(|v| v.to_str().unwrap());
+        if if_range.is_none_or(|tag| tag == etag(&file)) {
+            let offset = range.strip_prefix("bytes=").unwrap().trim_end_matches('-');
+            start = offset.parse().unwrap();
+        }
+        repo.ranges.lock().unwrap().push(range);
+    }
+    let body = data[start..].to_vec();
+    let length = body.len();
+    let body = if file == "model.safetensors" && repo.interrupt.swap(false, Ordering::SeqCst) {
+        let half = body[..length / 2].to_vec();
+        Body::from_stream(futures_util::stream::iter([
+            Ok(half),
+            Err(std::io::Error::other("connection lost")),
+        ]))
+    } else {
+        Body::from(body)
+    };
+    let mut response = Response::builder()
+        .header(header::CONTENT_LENGTH, length)
+        .header(header::ETAG, etag(&file));
+    if start > 0 {
+        let range = format!("bytes {start}-{}/{}", data.len() - 1, data.len());
+        response = response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, range);
+    }
+    response.body(body).unwrap()
+}
+
+/// Serves the tiny model, with its mel filters when `mel_filters`, returning the repo URL.
+async fn start_server(mel_filters: bool) -> (String, Arc<Repo>) {
+    let data = tiny_model_data();
+    let mut files = HashMap::from([
+        ("model.safetensors".to_string(), data.weights),
+        ("config.json".to_string(), data.config),
+        ("tokenizer.json".to_string(), data.tokenizer),
+    ]);
+    if mel_filters {
+        files.insert("mel_filters.safetensors".to_string(), data.mel_filters);
+    }
+    let repo = Arc::new(Repo {
+        files,
+        ..Default::default()
+    });
+    let app = Router::new()
+        .route("/org/model/resolve/main/:file", get(serve))
+        .with_state(repo.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/org/model", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, repo)
+}
+
+/// Empty cache directory of a test.
+fn cache_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "candle-whisper-fetch-{}-{test}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test]
+async fn files_are_fetched_once_then_read_from_the_cache() {
+    let (url, repo) = start_server(true).await;
+    let cache = cache_dir("cache_hit");
+    let mut reports: Vec<FetchProgress> = vec![];
+    let data = fetch_model_with_progress(&url, ModelVariant::Safetensors, &cache, &mut |p| {
+        reports.push(p.clone())
+    })
+    .await
+    .unwrap();
+    let expected = tiny_model_data();
+    assert_eq!(data.weights, expected.weights);
+    assert_eq!(data.config, expected.config);
+    assert_eq!(data.tokenizer, expected.tokenizer);
+    assert_eq!(data.mel_filters, expected.mel_filters);
+    assert!(!data.quantized);
+    assert_eq!(repo.gets.load(Ordering::SeqCst), 4);
+    let last = reports
+        .iter()
+        .rfind(|p| p.file == "model.safetensors")
+        .unwrap();
+    assert!(!last.cached);
+    assert_eq!(last.bytes_downloaded, expected.weights.len() as u64);
+    assert_eq!(last.bytes_total, expected.weights.len() as u64);
+    Decoder::load(data).unwrap();
+
+    let mut reports: Vec<FetchProgress> = vec![];
+    let data = fetch_model_with_progress(&url, ModelVariant::Safetensors, &cache, &mut |p| {
+        reports.push(p.clone())
+    })
+    .await
+    .unwrap();
+    assert_eq!(data.weights, expected.weights);
+    assert_eq!(repo.gets.load(Ordering::SeqCst), 4);
+    assert_eq!(reports.len(), 4);
+    assert!(reports.iter().all(|p| p.cached));
+    std::fs::remove_dir_all(cache).unwrap();
+}
+
+#[tokio::test]
+async fn interrupted_download_is_resumed() {
+    let (url, repo) = start_server(true).await;
+    repo.interrupt.store(true, Ordering::SeqCst);
+    let cache = cache_dir("resume");
+    let err = fetch_model(&url, ModelVariant::Safetensors, &cache)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), "model_load");
+
+    let data = fetch_model(&url, ModelVariant::Safetensors, &cache)
+        .await
+        .unwrap();
+    let weights = tiny_model_data().weights;
+    assert_eq!(data.weights, weights);
+    let ranges = repo.ranges.lock().unwrap().clone();
+    assert_eq!(ranges.len(), 1);
+    let offset: usize = ranges[0]
+        .strip_prefix("bytes=")
+        .and_then(|range| range.strip_suffix('-'))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(offset > 0 && offset < weights.len());
+    std::fs::remove_dir_all(cache).unwrap();
+}
+
+#[tokio::test]
+async fn missing_mel_filters_are_computed_on_load() {
+    let (url, _) = start_server(false).await;
+    let cache = cache_dir("no_mel_filters");
+    let data = fetch_model(&url, ModelVariant::Safetensors, &cache)
+        .await
+        .unwrap();
+    assert!(data.mel_filters.is_empty());
+    Decoder::load(data).unwrap();
+    std::fs::remove_dir_all(cache).unwrap();
+}
+
+#[test]
+fn gguf_variants_have_the_names_of_the_quantized_repos() {
+    let variant = |model: &str, quantization: &str| ModelVariant::Gguf {
+        model: model.to_string(),
+        quantization: quantization.to_string(),
+    };
+    assert_eq!(
+        variant("tiny.en", "q80").file_names().unwrap(),
+        [
+            "model-tiny-en-q80.gguf",
+            "config-tiny-en.json",
+            "tokenizer-tiny-en.json"
+        ]
+    );
+    assert!(matches!(
+        variant("tiny", "q7").file_names(),
+        Err(WhisperError::InvalidConfig { .. })
+    ));
+    assert!(matches!(
+        variant("../tiny", "q80").file_names(),
+        Err(WhisperError::InvalidConfig { .. })
+    ));
+}
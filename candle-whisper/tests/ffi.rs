@@ -0,0 +1,110 @@
+use candle_whisper::{
+    ffi::{
+        whisper_decoder_free, whisper_decoder_new, whisper_decoder_set_option,
+        whisper_decoder_transcribe_wav, whisper_string_free, WHISPER_ERROR,
+        WHISPER_INVALID_ARGUMENT, WHISPER_OK,
+    },
+    fixtures::{sine_wav, tiny_model_data},
+    logic::Decoder,
+};
+use serde_json::Value;
+use std::{
+    ffi::{c_char, CStr, CString},
+    ptr,
+};
+
+/// Takes the ownership of a string returned through the C ABI.
+fn take_string(s: *mut c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let string = unsafe { CStr::from_ptr(s) }.to_str().unwrap().to_string();
+    unsafe { whisper_string_free(s) };
+    Some(string)
+}
+
+fn new_decoder() -> *mut Decoder {
+    let model_data = serde_json::to_vec(&tiny_model_data()).unwrap();
+    let mut err = ptr::null_mut();
+    let decoder = unsafe { whisper_decoder_new(model_data.as_ptr(), model_data.len(), &mut err) };
+    assert!(!decoder.is_null());
+    assert_eq!(take_string(err), None);
+    decoder
+}
+
+/// Status and error JSON of setting an option.
+fn set_option(decoder: *mut Decoder, key: &str, value: &str) -> (i32, Option<Value>) {
+    let (key, value) = (CString::new(key).unwrap(), CString::new(value).unwrap());
+    let mut err = ptr::null_mut();
+    let status =
+        unsafe { whisper_decoder_set_option(decoder, key.as_ptr(), value.as_ptr(), &mut err) };
+    let err = take_string(err).map(|err| serde_json::from_str(&err).unwrap());
+    (status, err)
+}
+
+#[test]
+fn transcripts_round_trip_through_the_c_abi() {
+    let decoder = new_decoder();
+    assert_eq!(
+        set_option(decoder, "temperatures", "[0.0]"),
+        (WHISPER_OK, None)
+    );
+    assert_eq!(
+        set_option(decoder, "collect_timings", "true"),
+        (WHISPER_OK, None)
+    );
+    assert_eq!(
+        set_option(decoder, "vad.min_rms", "0.01"),
+        (WHISPER_OK, None)
+    );
+
+    let wav = sine_wav(5., 440.);
+    let (mut json, mut err) = (ptr::null_mut(), ptr::null_mut());
+    let status = unsafe {
+        whisper_decoder_transcribe_wav(decoder, wav.as_ptr(), wav.len(), &mut json, &mut err)
+    };
+    assert_eq!(status, WHISPER_OK);
+    assert_eq!(take_string(err), None);
+    let output: Value = serde_json::from_str(&take_string(json).unwrap()).unwrap();
+    assert!(!output["segments"].as_array().unwrap().is_empty());
+    assert!(output["timings"].is_object());
+    unsafe { whisper_decoder_free(decoder) };
+}
+
+#[test]
+fn failures_are_reported_as_status_and_json() {
+    let decoder = new_decoder();
+    let (status, err) = set_option(decoder, "no_such_option", "1");
+    assert_eq!(status, WHISPER_ERROR);
+    assert_eq!(err.unwrap()["code"], "invalid_config");
+    let (status, err) = set_option(decoder, "temperatures", "\"hot\"");
+    assert_eq!(status, WHISPER_ERROR);
+    assert!(err.unwrap()["message"]
+        .as_str()
+        .unwrap()
+        .contains("temperatures"));
+
+    let wav = b"not a wav file";
+    let (mut json, mut err) = (ptr::null_mut(), ptr::null_mut());
+    let status = unsafe {
+        whisper_decoder_transcribe_wav(decoder, wav.as_ptr(), wav.len(), &mut json, &mut err)
+    };
+    assert_eq!(status, WHISPER_ERROR);
+    assert!(json.is_null());
+    let json_err: Value = serde_json::from_str(&take_string(err).unwrap()).unwrap();
+    assert_eq!(json_err["code"], "unsupported_audio");
+
+    let status = unsafe {
+        whisper_decoder_transcribe_wav(ptr::null_mut(), wav.as_ptr(), 1, &mut json, &mut err)
+    };
+    assert_eq!(status, WHISPER_INVALID_ARGUMENT);
+    assert!(take_string(err).unwrap().contains("null decoder"));
+    unsafe { whisper_decoder_free(decoder) };
+
+    let mut err = ptr::null_mut();
+    let decoder = unsafe { whisper_decoder_new(b"{}".as_ptr(), 2, &mut err) };
+    assert!(decoder.is_null());
+    let err: Value = serde_json::from_str(&take_string(err).unwrap()).unwrap();
+    assert_eq!(err["code"], "invalid_config");
+    unsafe { whisper_decoder_free(ptr::null_mut()) };
+}
@@ -0,0 +1,62 @@
+use candle_whisper::audio::{fft, FftPlan};
+
+/// Direct DFT in `f64` as `(re, im)` pairs.
+fn reference_dft(inp: &[f64]) -> Vec<(f64, f64)> {
+    let n = inp.len();
+    (0..n)
+        .map(|k| {
+            inp.iter().enumerate().fold((0., 0.), |(re, im), (j, x)| {
+                let angle = std::f64::consts::TAU * (k * j % n) as f64 / n as f64;
+                (re + x * angle.cos(), im - x * angle.sin())
+            })
+        })
+        .collect()
+}
+
+fn signal(n: usize) -> Vec<f64> {
+    (0..n)
+        .map(|i| (i as f64 * 0.37).sin() + 0.5 * (i as f64 * 2.1).cos() + (i % 7) as f64 * 0.1)
+        .collect()
+}
+
+#[test]
+fn every_size_matches_the_direct_dft() {
+    for n in [
+        1, 2, 3, 8, 12, 15, 17, 25, 100, 400, 401, 509, 512, 1000, 1009,
+    ] {
+        let inp = signal(n);
+        let out = fft(&inp);
+        assert_eq!(out.len(), 2 * n);
+        let scale = inp.iter().map(|x| x.abs()).sum::<f64>();
+        for (k, &(re, im)) in reference_dft(&inp).iter().enumerate() {
+            let error = f64::max((out[2 * k] - re).abs(), (out[2 * k + 1] - im).abs());
+            assert!(error < 1e-9 * scale, "size {n} bin {k}: error {error}");
+        }
+    }
+}
+
+#[test]
+fn f32_transforms_stay_accurate() {
+    for n in [400, 401, 1009] {
+        let inp = signal(n);
+        let inp32: Vec<f32> = inp.iter().map(|&x| x as f32).collect();
+        let out = FftPlan::new(n).transform(&inp32);
+        let scale = inp.iter().map(|x| x.abs()).sum::<f64>();
+        for (k, &(re, im)) in reference_dft(&inp).iter().enumerate() {
+            let error = f64::max(
+                (out[2 * k] as f64 - re).abs(),
+                (out[2 * k + 1] as f64 - im).abs(),
+            );
+            assert!(error < 1e-5 * scale, "size {n} bin {k}: error {error}");
+        }
+    }
+}
+
+#[test]
+fn a_plan_transforms_several_inputs() {
+    let plan = FftPlan::new(400);
+    for shift in 0..3 {
+        let inp: Vec<f64> = signal(400 + shift)[shift..].to_vec();
+        assert_eq!(plan.transform(&inp), fft(&inp));
+    }
+}
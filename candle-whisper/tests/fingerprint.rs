@@ -0,0 +1,120 @@
+use candle_whisper::{
+    fingerprint::similarity,
+    fixtures::{sine_wav, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+use std::f32::consts::PI;
+
+const SAMPLE_RATE: usize = 16000;
+
+/// Harmonics of a gliding 120Hz voice, in syllables of a sixth of a second separated by pauses
+/// as long.
+fn speech_like(seconds: usize, gain: f32) -> Vec<f32> {
+    (0..seconds * SAMPLE_RATE)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = (2. * PI * 3. * t).sin().max(0.);
+            let f0 = 120. + 30. * (2. * PI * 0.5 * t).sin();
+            let voice: f32 = (1..12)
+                .map(|h| (2. * PI * f0 * h as f32 * t).sin() / h as f32)
+                .sum();
+            0.2 * gain * envelope * voice
+        })
+        .collect()
+}
+
+/// Deterministic white noise in `[-amplitude, amplitude]`.
+fn noise(seconds: usize, amplitude: f32) -> Vec<f32> {
+    let mut state = 0x2545f4914f6cdd1du64;
+    (0..seconds * SAMPLE_RATE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            ((state >> 40) as f32 / (1u64 << 24) as f32 * 2. - 1.) * amplitude
+        })
+        .collect()
+}
+
+#[test]
+fn same_audio_has_the_same_fingerprint() {
+    let decoder = Decoder::load(tiny_model_data()).unwrap();
+    let a = decoder.compute_mel(&speech_like(5, 1.)).unwrap();
+    let b = decoder.compute_mel(&speech_like(5, 1.)).unwrap();
+    assert_eq!(a.fingerprint().unwrap(), b.fingerprint().unwrap());
+    let other = decoder.compute_mel(&speech_like(4, 1.)).unwrap();
+    assert_ne!(a.fingerprint().unwrap(), other.fingerprint().unwrap());
+
+    // SHA-256 of the empty perceptual hash of silence.
+    let silence = decoder.compute_mel(&vec![0.; SAMPLE_RATE]).unwrap();
+    assert_eq!(silence.perceptual_hash().unwrap(), [0; 32]);
+    let digest: String = silence
+        .fingerprint()
+        .unwrap()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    assert_eq!(
+        digest,
+        "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925"
+    );
+}
+
+#[test]
+fn similarity_ignores_the_gain() {
+    let decoder = Decoder::load(tiny_model_data()).unwrap();
+    let mel = |pcm: Vec<f32>| decoder.compute_mel(&pcm).unwrap();
+    let speech = mel(speech_like(5, 1.));
+    let quieter = mel(speech_like(5, 0.3));
+    assert!(similarity(&speech, &quieter).unwrap() > 0.9);
+    let noise = mel(noise(5, 0.3));
+    let score = similarity(&speech, &noise).unwrap();
+    assert!(score < 0.6, "similarity {score}");
+}
+
+#[test]
+fn speech_energy_ratio_separates_silence_from_speech() {
+    let decoder = Decoder::load(tiny_model_data()).unwrap();
+    let ratio = |pcm: Vec<f32>| {
+        decoder
+            .compute_mel(&pcm)
+            .unwrap()
+            .speech_energy_ratio()
+            .unwrap()
+    };
+    assert_eq!(ratio(vec![0.; 5 * SAMPLE_RATE]), 0.);
+    assert_eq!(ratio(noise(5, 0.001)), 0.);
+    let speech = ratio(speech_like(5, 1.));
+    assert!(speech > 0.2 && speech < 0.5, "ratio {speech}");
+    assert_eq!(ratio(noise(5, 0.3)), 1.);
+}
+
+#[test]
+fn silent_uploads_are_not_decoded() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            min_speech_energy_ratio: Some(0.1),
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&vec![0.; 5 * SAMPLE_RATE], &RunOptions::default())
+        .unwrap();
+    assert!(output.no_speech_detected);
+    assert!(output.segments.is_empty());
+
+    let output = decoder
+        .run_pcm(&speech_like(3, 1.), &RunOptions::default())
+        .unwrap();
+    assert!(!output.no_speech_detected);
+    assert!(!output.segments.is_empty());
+    let output = decoder.convert_and_run(&sine_wav(1., 440.)).unwrap();
+    assert!(output.no_speech_detected);
+
+    let invalid = DecodeOptions {
+        min_speech_energy_ratio: Some(1.5),
+        ..Default::default()
+    };
+    assert!(invalid.validate().is_err());
+}
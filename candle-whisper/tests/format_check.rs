@@ -0,0 +1,132 @@
+use candle_whisper::{
+    audio::{check_format, resample, swap_bytes_16, FormatCheck, FormatIssue},
+    fixtures::tiny_model_data,
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const RATE: u32 = 16000;
+
+/// Voiced sound at `RATE`: the harmonics of 150 Hz up to 4 kHz with white noise at -40 dBFS.
+fn voice(seconds: f64) -> Vec<f32> {
+    let mut rng = StdRng::seed_from_u64(7);
+    let len = (seconds * RATE as f64) as usize;
+    (0..len)
+        .map(|i| {
+            let t = i as f64 / RATE as f64;
+            let harmonics: f64 = (1..=26)
+                .map(|k| 0.2 / k as f64 * (std::f64::consts::TAU * 150. * k as f64 * t).sin())
+                .sum();
+            (harmonics + rng.gen_range(-0.017..0.017)) as f32
+        })
+        .collect()
+}
+
+fn quantized(samples: &[f32]) -> Vec<f32> {
+    samples
+        .iter()
+        .map(|&x| (x * 32768.).round() / 32768.)
+        .collect()
+}
+
+fn wav(samples: &[f32]) -> Vec<u8> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = std::io::Cursor::new(vec![]);
+    let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+    for &sample in samples {
+        writer
+            .write_sample((sample * 32768.).round() as i16)
+            .unwrap();
+    }
+    writer.finalize().unwrap();
+    bytes.into_inner()
+}
+
+#[test]
+fn correct_audio_passes() {
+    assert_eq!(check_format(&voice(3.), RATE), None);
+    assert_eq!(check_format(&vec![0.; 48000], RATE), None);
+    assert_eq!(check_format(&voice(0.01), RATE), None);
+    let tone: Vec<f32> = (0..3 * RATE)
+        .map(|i| 0.5 * (std::f32::consts::TAU * 440. * i as f32 / RATE as f32).sin())
+        .collect();
+    assert_eq!(check_format(&tone, RATE), None);
+}
+
+#[test]
+fn audio_of_a_higher_rate_is_mislabeled() {
+    // Recorded at 48 kHz, declared as 16 kHz.
+    let samples = resample(&voice(3.), RATE, 48000);
+    assert_eq!(
+        check_format(&samples, RATE),
+        Some(FormatIssue::MislabeledRate {
+            declared_rate: RATE,
+            estimated_rate: 48000
+        })
+    );
+}
+
+#[test]
+fn swapped_bytes_are_recognized() {
+    let samples = quantized(&voice(3.));
+    let swapped = swap_bytes_16(&samples);
+    assert_eq!(check_format(&swapped, RATE), Some(FormatIssue::ByteSwapped));
+    assert_eq!(swap_bytes_16(&swapped), samples);
+
+    let mut rng = StdRng::seed_from_u64(3);
+    let noise: Vec<f32> = (0..48000).map(|_| rng.gen_range(-1.0..1.0)).collect();
+    assert_eq!(check_format(&noise, RATE), Some(FormatIssue::NoiseLike));
+}
+
+#[test]
+fn resampling_keeps_the_tones() {
+    let sine = |rate: u32, seconds: f64| -> Vec<f32> {
+        let len = (seconds * rate as f64) as usize;
+        (0..len)
+            .map(|i| (0.5 * (std::f64::consts::TAU * 1000. * i as f64 / rate as f64).sin()) as f32)
+            .collect()
+    };
+    for (from, to) in [(48000, RATE), (RATE, 44100)] {
+        let resampled = resample(&sine(from, 1.), from, to);
+        let expected = sine(to, 1.);
+        assert_eq!(resampled.len(), expected.len());
+        // The edges lack the samples before and after.
+        let middle = expected.len() / 10..expected.len() * 9 / 10;
+        for (a, b) in resampled[middle.clone()].iter().zip(&expected[middle]) {
+            assert!((a - b).abs() < 1e-2, "{a} {b} from {from} to {to}");
+        }
+    }
+}
+
+#[test]
+fn mislabeled_files_are_reported_and_corrected() {
+    let file = wav(&resample(&voice(4.), RATE, 48000));
+    for auto_correct_format in [false, true] {
+        let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+        decoder
+            .set_options(DecodeOptions {
+                auto_correct_format,
+                collect_diagnostics: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let output = decoder
+            .convert_and_run_with_options(&file, &RunOptions::default())
+            .unwrap();
+        assert_eq!(
+            output.diagnostics.unwrap().format_check,
+            Some(FormatCheck {
+                issue: FormatIssue::MislabeledRate {
+                    declared_rate: RATE,
+                    estimated_rate: 48000
+                },
+                corrected: auto_correct_format
+            })
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use candle_whisper::{
+    fixtures::segment,
+    hallucination::{
+        filter_hallucinations, ngram_coverage, score, similarity, HallucinationOptions,
+    },
+    logic::Segment,
+};
+
+fn scores(segments: &[Segment]) -> Vec<f64> {
+    segments.iter().map(|s| s.hallucination_score).collect()
+}
+
+fn texts(segments: &[Segment]) -> Vec<&str> {
+    segments.iter().map(|s| s.dr.text.as_str()).collect()
+}
+
+fn flagged(text: &str, opts: &HallucinationOptions) -> bool {
+    score(None, text, opts).1
+}
+
+#[test]
+fn known_phrases_are_flagged() {
+    let opts = HallucinationOptions::default();
+    for text in [
+        " Thanks for watching!",
+        " THANK YOU FOR WATCHING.",
+        " Please subscribe",
+        " you",
+        " ♪",
+    ] {
+        assert_eq!(score(None, text, &opts), (1., true), "{text:?}");
+    }
+    // Only the whole text matches, not a sentence containing the phrase.
+    let sentence = " Thanks for watching my talk on Rust.";
+    assert!(score(None, sentence, &opts).0 < 1.);
+    assert!(!flagged(sentence, &opts));
+    let opts = HallucinationOptions {
+        blocklist: vec!["Like and share".to_string()],
+        ..Default::default()
+    };
+    assert!(flagged(" like, and share!", &opts));
+    assert!(!flagged(" Thanks for watching!", &opts));
+}
+
+#[test]
+fn repeated_ngrams_are_flagged() {
+    let looping = " I love you. I love you. I love you. I love you.";
+    assert_eq!(ngram_coverage(looping, 3), 1.);
+    assert!(flagged(looping, &HallucinationOptions::default()));
+
+    let sentence = " The quick brown fox jumps over the lazy dog near the river bank.";
+    assert_eq!(ngram_coverage(sentence, 3), 0.);
+    assert!(!flagged(sentence, &HallucinationOptions::default()));
+    // A phrase said twice in a longer sentence stays under the coverage threshold.
+    let twice = " We went to the park, then we went to the park again with friends today.";
+    let coverage = ngram_coverage(twice, 3);
+    assert!(coverage > 0. && coverage <= 0.8, "{coverage}");
+    assert!(!flagged(twice, &HallucinationOptions::default()));
+    // Too short for two n-grams.
+    assert_eq!(ngram_coverage(" a b c a b", 3), 0.);
+}
+
+#[test]
+fn near_duplicates_of_the_previous_segment_are_flagged() {
+    assert_eq!(similarity(" Hello, world!", "hello world"), 1.);
+    assert_eq!(similarity("", ""), 1.);
+    assert_eq!(similarity("abc", "xyz"), 0.);
+    let opts = HallucinationOptions::default();
+    let previous = Some(" See you tomorrow.");
+    assert_eq!(score(previous, " See you tomorrow!", &opts), (1., true));
+    assert!(!score(previous, " See you on Monday.", &opts).1);
+}
+
+#[test]
+fn flagged_segments_are_scored_or_dropped() {
+    let mut silence = segment(2, 4., 2., " Thanks for watching!");
+    silence.no_speech = true;
+    let segments = vec![
+        segment(0, 0., 2., " Welcome to the show."),
+        segment(1, 2., 2., " Welcome to the show."),
+        silence,
+        segment(3, 6., 2., " Today we talk about birds."),
+        segment(4, 8., 2., " Thanks for watching!"),
+    ];
+    let scored = filter_hallucinations(segments.clone(), &HallucinationOptions::default());
+    assert_eq!(scored.len(), 5);
+    let scores = scores(&scored);
+    assert_eq!(scores[0], 0.);
+    assert_eq!(scores[1], 1.);
+    // The segments without speech are left alone.
+    assert_eq!(scores[2], 0.);
+    assert!(scores[3] < 0.5, "{scores:?}");
+    assert_eq!(scores[4], 1.);
+
+    let opts = HallucinationOptions {
+        drop: true,
+        ..Default::default()
+    };
+    let kept = filter_hallucinations(segments, &opts);
+    assert_eq!(
+        texts(&kept),
+        [
+            " Welcome to the show.",
+            " Thanks for watching!",
+            " Today we talk about birds."
+        ]
+    );
+    assert!(kept[1].no_speech);
+}
+
+#[test]
+fn nothing_is_flagged_in_a_normal_transcript() {
+    let segments = vec![
+        segment(0, 0., 3., " Good morning everyone."),
+        segment(1, 3., 3., " Thank you all for coming to the meeting."),
+        segment(2, 6., 3., " Let's start with the budget."),
+    ];
+    let opts = HallucinationOptions {
+        drop: true,
+        ..Default::default()
+    };
+    assert_eq!(filter_hallucinations(segments, &opts).len(), 3);
+}
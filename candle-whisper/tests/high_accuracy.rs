@@ -0,0 +1,81 @@
+use candle_whisper::{
+    consensus::HighAccuracyOptions,
+    fixtures::{sine_pcm, tiny_model_data, TEXT_TOKENS},
+    logic::{DecodeOptions, Decoder, LogitsContext, RunOptions, Segment},
+};
+use std::{cell::Cell, rc::Rc};
+
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+/// Tokens sampled by each pass: the accepted greedy decoding then the two additional passes,
+/// disagreeing on the second and third tokens.
+const PASSES: [[u32; 4]; 3] = [[1, 2, 3, 4], [1, 5, 6, 4], [1, 2, 6, 4]];
+
+/// Single segment of a high accuracy run over the scripted passes, with the number of passes
+/// that were run.
+fn run(min_agreement: f64) -> (Segment, usize) {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            high_accuracy: Some(HighAccuracyOptions {
+                temperatures: vec![0.2, 0.4],
+                min_agreement,
+            }),
+            compression_ratio_threshold: None,
+            logprob_threshold: None,
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    let passes = Rc::new(Cell::new(0));
+    let counter = passes.clone();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.step == 0 {
+                counter.set(counter.get() + 1);
+            }
+            let forced = PASSES[counter.get() - 1]
+                .get(context.step)
+                .copied()
+                .unwrap_or(EOT);
+            logits.fill(f32::NEG_INFINITY);
+            logits[forced as usize] = 0.;
+        },
+    )));
+    let opts = RunOptions {
+        timestamps: Some(false),
+        ..Default::default()
+    };
+    let mut output = decoder.run_pcm(&sine_pcm(5., 440.), &opts).unwrap();
+    assert_eq!(output.segments.len(), 1);
+    (output.segments.remove(0), passes.get())
+}
+
+#[test]
+fn passes_are_voted_token_by_token() {
+    let (segment, passes) = run(1.);
+    assert_eq!(passes, 3);
+    // `<|startoftranscript|>` and `<|notimestamps|>` precede the voted tokens.
+    let text: Vec<u32> = segment.dr.tokens[2..]
+        .iter()
+        .copied()
+        .filter(|&token| token < EOT)
+        .collect();
+    assert_eq!(text, [1, 2, 6, 4]);
+    assert_eq!(segment.dr.text.trim(), "hello world a sound");
+
+    let [span] = segment.low_agreement_spans.as_slice() else {
+        panic!("{:?}", segment.low_agreement_spans)
+    };
+    assert_eq!((span.start_token, span.end_token), (3, 5));
+    assert_eq!(span.text.trim(), "world a");
+    assert!((span.agreement - 2. / 3.).abs() < 1e-12);
+}
+
+#[test]
+fn majorities_above_the_minimum_agreement_are_not_reported() {
+    let (segment, _) = run(0.6);
+    assert!(segment.dr.text.contains("world a"));
+    assert!(segment.low_agreement_spans.is_empty());
+}
@@ -0,0 +1,117 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_config, tiny_model_data, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{build_initial_timestamp_mask, DecodeOptions, Decoder, LogitsContext, RunOptions},
+};
+use std::{cell::RefCell, rc::Rc};
+
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+const TIMESTAMP_BEGIN: u32 = EOT + 6;
+const HELLO: u32 = 1;
+
+/// Timestamp token of `seconds`.
+fn ts(seconds: f64) -> u32 {
+    TIMESTAMP_BEGIN + (seconds / 0.02).round() as u32
+}
+
+/// Last timestamp token allowed by a mask.
+fn last_allowed(mask: &[f32]) -> u32 {
+    mask.iter().rposition(|v| v.is_finite()).unwrap() as u32
+}
+
+#[test]
+fn mask_forbids_the_late_timestamps() {
+    let vocab_size = tiny_config().vocab_size;
+    let mask = |max, window_audio| {
+        build_initial_timestamp_mask(vocab_size, TIMESTAMP_BEGIN, max, window_audio)
+    };
+    assert!(mask(None, 30.).is_none());
+    assert!(mask(None, 5.).is_none());
+    // Past the window.
+    assert!(mask(Some(30.), 30.).is_none());
+    assert!(mask(Some(45.), 30.).is_none());
+
+    let one_second = mask(Some(1.), 30.).unwrap();
+    assert_eq!(one_second.len(), vocab_size);
+    assert_eq!(last_allowed(&one_second), ts(1.));
+    assert!(one_second[..=ts(1.) as usize].iter().all(|&v| v == 0.));
+    assert_eq!(last_allowed(&mask(Some(0.), 30.).unwrap()), ts(0.));
+    assert_eq!(last_allowed(&mask(Some(2.51), 30.).unwrap()), ts(2.5));
+    // Lowered to the audio of the last window.
+    assert_eq!(last_allowed(&mask(Some(45.), 7.).unwrap()), ts(7.));
+    assert_eq!(last_allowed(&mask(Some(1.), 0.5).unwrap()), ts(0.5));
+}
+
+/// First tokens sampled in each window of 10 seconds of audio by a model preferring the latest
+/// timestamps, then sampling `hello` and the end of text.
+fn first_tokens(max_initial_timestamp: Option<f64>) -> Vec<u32> {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            max_initial_timestamp,
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                drop: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let first = Rc::new(RefCell::new(vec![]));
+    let recorded = first.clone();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            match context.step {
+                0 => {
+                    for (token, logit) in logits.iter_mut().enumerate() {
+                        let token = token as u32;
+                        if token < TIMESTAMP_BEGIN {
+                            *logit = f32::NEG_INFINITY;
+                        } else if logit.is_finite() {
+                            *logit = (token - TIMESTAMP_BEGIN) as f32;
+                        }
+                    }
+                    return;
+                }
+                1 => recorded.borrow_mut().push(context.tokens[0]),
+                _ => {}
+            }
+            let forced = if context.step == 1 { HELLO } else { EOT };
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if token as u32 != forced {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        },
+    )));
+    let opts = RunOptions {
+        timestamps: Some(true),
+        ..Default::default()
+    };
+    decoder.run_pcm(&sine_pcm(10., 440.), &opts).unwrap();
+    let first = first.borrow().clone();
+    first
+}
+
+#[test]
+fn first_timestamp_never_exceeds_the_cap() {
+    assert_eq!(first_tokens(Some(1.)), [ts(1.)]);
+    assert_eq!(first_tokens(Some(0.)), [ts(0.)]);
+    // Lowered to the 10 seconds of audio.
+    assert_eq!(first_tokens(Some(25.)), [ts(10.)]);
+    // Without the cap the model claims the speech starts at the end of the window.
+    assert_eq!(first_tokens(None), [ts(30.)]);
+}
+
+#[test]
+fn negative_caps_are_rejected() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    for max_initial_timestamp in [-1., f64::NAN] {
+        let options = DecodeOptions {
+            max_initial_timestamp: Some(max_initial_timestamp),
+            ..Default::default()
+        };
+        assert!(decoder.set_options(options).is_err());
+    }
+}
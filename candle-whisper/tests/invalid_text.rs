@@ -0,0 +1,153 @@
+use candle_whisper::{
+    detokenize::{decode_tokens, strip_special_token_text},
+    fixtures::{sine_pcm, tiny_model_data, tiny_tokenizer_json, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, LogitsContext, ModelData, RunOptions, Segment},
+    prompt::PrefixIndex,
+};
+use serde_json::json;
+use tokenizers::Tokenizer;
+
+/// Byte-level text tokens replacing the ones of [`TEXT_TOKENS`], `é` being `Ã` followed by `©`.
+const BYTE_LEVEL_TOKENS: [&str; 11] = [
+    "Ġcaf", "Ã", "©", "Ġ", "ä", "¸", "Ń", "Ġhello", "Ġworld", ".", "a",
+];
+const CAF: u32 = 0;
+/// The first byte of `é`, not valid on its own.
+const A_TILDE: u32 = 1;
+const COPYRIGHT: u32 = 2;
+const HELLO: u32 = 7;
+const WORLD: u32 = 8;
+const PERIOD: u32 = 9;
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+/// [`tiny_tokenizer_json`] made byte-level with the text tokens of [`BYTE_LEVEL_TOKENS`].
+fn byte_level_tokenizer_json() -> Vec<u8> {
+    let mut tokenizer: serde_json::Value = serde_json::from_slice(&tiny_tokenizer_json()).unwrap();
+    let mut vocab: serde_json::Map<_, _> = tokenizer["model"]["vocab"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .filter(|(_, id)| id.as_u64().unwrap() >= EOT as u64)
+        .map(|(token, id)| (token.clone(), id.clone()))
+        .collect();
+    for (id, token) in BYTE_LEVEL_TOKENS.iter().enumerate() {
+        vocab.insert(token.to_string(), json!(id));
+    }
+    let byte_level =
+        json!({ "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true });
+    tokenizer["pre_tokenizer"] = byte_level.clone();
+    tokenizer["decoder"] = byte_level;
+    tokenizer["model"] = json!({
+        "type": "BPE",
+        "dropout": null,
+        "unk_token": null,
+        "continuing_subword_prefix": null,
+        "end_of_word_suffix": null,
+        "fuse_unk": false,
+        "vocab": vocab,
+        "merges": [],
+    });
+    serde_json::to_vec(&tokenizer).unwrap()
+}
+
+/// `tokenizer` with its added tokens not marked as special, their text being kept by the
+/// decoding.
+fn without_special_flags(tokenizer: Vec<u8>) -> Vec<u8> {
+    let mut tokenizer: serde_json::Value = serde_json::from_slice(&tokenizer).unwrap();
+    for token in tokenizer["added_tokens"].as_array_mut().unwrap() {
+        token["special"] = json!(false);
+    }
+    serde_json::to_vec(&tokenizer).unwrap()
+}
+
+/// Decodes 10 seconds of audio forced to `script`.
+fn run(tokenizer: Vec<u8>, script: &'static [u32]) -> Segment {
+    let data = ModelData {
+        tokenizer,
+        ..tiny_model_data()
+    };
+    let mut decoder = Decoder::load(data).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            no_speech_threshold: None,
+            compression_ratio_threshold: None,
+            hallucination: HallucinationOptions {
+                similarity_threshold: 2.,
+                max_ngram_coverage: 1.,
+                blocklist: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            let forced = script.get(context.step).copied().unwrap_or(EOT);
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if token as u32 != forced {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        },
+    )));
+    let mut output = decoder
+        .run_pcm(&sine_pcm(10., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.segments.len(), 1);
+    output.segments.remove(0)
+}
+
+#[test]
+fn broken_characters_are_replaced_and_flagged() {
+    let segment = run(
+        byte_level_tokenizer_json(),
+        &[CAF, A_TILDE, HELLO, WORLD, PERIOD],
+    );
+    let dr = &segment.dr;
+    assert_eq!(dr.text, " caf\u{FFFD} hello world.");
+    assert!(dr.had_decode_errors);
+    assert_eq!(dr.decode_error_tokens.len(), 1);
+    assert_eq!(dr.tokens[dr.decode_error_tokens[0]], A_TILDE);
+
+    let segment = run(
+        byte_level_tokenizer_json(),
+        &[CAF, A_TILDE, COPYRIGHT, PERIOD],
+    );
+    assert_eq!(segment.dr.text, " café.");
+    assert!(!segment.dr.had_decode_errors);
+    assert!(segment.dr.decode_error_tokens.is_empty());
+}
+
+#[test]
+fn invalid_bytes_are_located_in_the_tokens() {
+    let tokenizer = Tokenizer::from_bytes(byte_level_tokenizer_json()).unwrap();
+    let index = PrefixIndex::for_tokenizer(&tokenizer, EOT).unwrap();
+    for index in [Some(&index), None] {
+        // `©` without its first byte, then `Ã` cut by the end of the text.
+        let tokens = [HELLO, COPYRIGHT, WORLD, A_TILDE, EOT];
+        let decoded = decode_tokens(&tokenizer, index, &tokens, EOT);
+        assert_eq!(decoded.text, " hello\u{FFFD} world\u{FFFD}");
+        assert_eq!(decoded.invalid_tokens, [1, 3]);
+
+        let decoded = decode_tokens(&tokenizer, index, &[CAF, A_TILDE, COPYRIGHT, EOT], EOT);
+        assert_eq!(decoded.text, " café");
+        assert!(decoded.invalid_tokens.is_empty());
+    }
+}
+
+#[test]
+fn special_token_text_is_stripped() {
+    assert_eq!(
+        strip_special_token_text("<|startoftranscript|><|en|> hello<|0.00|> world"),
+        " hello world"
+    );
+    // Text that only looks like special tokens partially is kept.
+    assert_eq!(strip_special_token_text("a <| b |> c"), "a <| b |> c");
+
+    let expected = run(tiny_tokenizer_json(), &[1, 2]).dr.text;
+    let segment = run(without_special_flags(tiny_tokenizer_json()), &[1, 2]);
+    assert!(!expected.is_empty());
+    assert_eq!(segment.dr.text.trim(), expected.trim());
+    assert!(!segment.dr.had_decode_errors);
+}
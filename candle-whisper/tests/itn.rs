@@ -0,0 +1,175 @@
+use candle_whisper::{
+    alignment::{TimingSource, WordTiming},
+    itn::{merge_words, normalize_text, normalizer, English},
+    text::{TextOptions, TextPostProcessor},
+};
+
+fn check(pairs: &[(&str, &str)]) {
+    for (input, expected) in pairs {
+        assert_eq!(
+            normalize_text(&English, input),
+            *expected,
+            "input: {input:?}"
+        );
+    }
+}
+
+#[test]
+fn cardinals() {
+    check(&[
+        ("I counted twenty three birds", "I counted 23 birds"),
+        ("twenty-three", "23"),
+        ("one hundred", "100"),
+        ("one hundred and five people", "105 people"),
+        ("two hundred fifty six", "256"),
+        ("a hundred and fifty", "150"),
+        ("a thousand cuts", "1000 cuts"),
+        ("fifteen hundred", "1500"),
+        ("two thousand twenty four", "2024"),
+        ("forty two thousand", "42,000"),
+        ("three million five hundred thousand", "3,500,000"),
+        ("ten", "10"),
+        ("Eleven players.", "11 players."),
+        ("three point one four", "3.14"),
+        ("zero point five", "0.5"),
+    ]);
+}
+
+#[test]
+fn ordinals() {
+    check(&[
+        ("the twenty first century", "the 21st century"),
+        ("twenty-second", "22nd"),
+        ("the tenth time", "the 10th time"),
+        ("eleventh, twelfth and thirteenth", "11th, 12th and 13th"),
+        ("one hundred and third", "103rd"),
+        ("the fortieth anniversary", "the 40th anniversary"),
+        ("the one thousandth visitor", "the 1000th visitor"),
+    ]);
+}
+
+#[test]
+fn years() {
+    check(&[
+        ("in nineteen ninety nine", "in 1999"),
+        ("twenty twenty four", "2024"),
+        ("nineteen oh five", "1905"),
+    ]);
+}
+
+#[test]
+fn money() {
+    check(&[
+        ("twenty three dollars and fifty cents", "$23.50"),
+        ("It costs five dollars.", "It costs $5."),
+        ("one dollar and one cent", "$1.01"),
+        ("ten dollars five cents", "$10.05"),
+        ("a hundred dollars", "$100"),
+        ("two million dollars", "$2,000,000"),
+        ("fifty cents", "50¢"),
+        ("twelve euros", "€12"),
+        ("23 dollars", "$23"),
+        ("ten dollars and some change", "$10 and some change"),
+    ]);
+}
+
+#[test]
+fn percentages() {
+    check(&[
+        ("five percent", "5%"),
+        ("up by twelve per cent", "up by 12%"),
+        ("three point five percent", "3.5%"),
+        ("40 percent", "40%"),
+        ("one hundred percent sure", "100% sure"),
+    ]);
+}
+
+#[test]
+fn times() {
+    check(&[
+        ("three thirty pm", "3:30 PM"),
+        ("at seven oh five a.m. we left", "at 7:05 AM we left"),
+        ("It starts at nine p.m.", "It starts at 9 PM."),
+        ("ten o'clock", "10:00"),
+        ("eleven o’clock pm", "11:00 PM"),
+        ("twelve forty five a m", "12:45 AM"),
+        ("six fifteen PM", "6:15 PM"),
+    ]);
+}
+
+#[test]
+fn unrecognized_patterns_are_left_untouched() {
+    check(&[
+        ("", ""),
+        ("hello world", "hello world"),
+        ("one of the two", "one of the two"),
+        ("at first, he was second", "at first, he was second"),
+        ("twelve thirty", "twelve thirty"),
+        ("one two three", "one two three"),
+        ("five pounds", "five pounds"),
+        ("I am here", "I am here"),
+        ("a dollar", "a dollar"),
+        ("hundred", "hundred"),
+        ("and one", "and one"),
+        ("a quarter past three", "a quarter past three"),
+        ("  spaced   out  ", "  spaced   out  "),
+        ("— «quoted» —", "— «quoted» —"),
+    ]);
+}
+
+#[test]
+fn punctuation_around_entities_is_kept() {
+    check(&[
+        ("(twenty dollars)", "($20)"),
+        ("\"fifty percent,\" she said", "\"50%,\" she said"),
+        ("It was twenty three.", "It was 23."),
+        ("twenty, three", "20, three"),
+        ("thirteen o'clock", "13 o'clock"),
+    ]);
+}
+
+#[test]
+fn words_of_an_entity_are_merged() {
+    let words: Vec<WordTiming> = [" It", " costs", " twenty", " three", " dollars", " today."]
+        .iter()
+        .enumerate()
+        .map(|(i, word)| WordTiming {
+            word: word.to_string(),
+            start: i as f64,
+            end: i as f64 + 0.5,
+            timing_source: TimingSource::Attention,
+            probability: None,
+        })
+        .collect();
+    let merged = merge_words(&English, &words);
+    let texts: Vec<&str> = merged.iter().map(|w| w.word.as_str()).collect();
+    assert_eq!(texts, [" It", " costs", " $23", " today."]);
+    assert_eq!((merged[2].start, merged[2].end), (2., 4.5));
+    assert_eq!((merged[3].start, merged[3].end), (5., 5.5));
+}
+
+#[test]
+fn post_processor_only_normalizes_supported_languages() {
+    let options = TextOptions {
+        itn: true,
+        collapse_whitespace: true,
+        capitalize_sentences: true,
+        ..Default::default()
+    };
+    let processor = TextPostProcessor::new(&options).unwrap();
+    let text = " twenty  percent of   the time.";
+    assert_eq!(
+        processor.process_in(text, Some("en")).as_deref(),
+        Some("20% of the time.")
+    );
+    assert_eq!(
+        processor.process_in(text, Some("fr")).as_deref(),
+        Some("Twenty percent of the time.")
+    );
+    assert_eq!(
+        processor.process(text).as_deref(),
+        Some("Twenty percent of the time.")
+    );
+    assert!(normalizer("en").is_some());
+    assert!(normalizer("de").is_none());
+}
@@ -0,0 +1,154 @@
+use candle_whisper::{
+    fixtures::{self, sine_pcm, tiny_model_data},
+    formats::jsonl::{from_reader, JsonlErrorKind, JsonlRecord, SegmentJsonlWriter},
+    logic::{DecodeOptions, Decoder, RunOptions, Segment},
+};
+
+fn segment(id: usize, start: f64, text: &str, no_speech: bool) -> Segment {
+    let mut segment = fixtures::segment(id, start, 2., text);
+    segment.dr.tokens = vec![1, 2];
+    segment.no_speech = no_speech;
+    segment
+}
+
+#[test]
+fn interleaved_segments_round_trip() {
+    let mut writer = SegmentJsonlWriter::new(vec![]);
+    for (id, no_speech) in [false, true, false, true].into_iter().enumerate() {
+        writer
+            .push(&segment(
+                id,
+                2. * id as f64,
+                &format!(" text {id}"),
+                no_speech,
+            ))
+            .unwrap();
+    }
+    let bytes = writer.into_inner();
+    let text = String::from_utf8(bytes.clone()).unwrap();
+    assert_eq!(text.lines().count(), 4);
+    for line in text.lines() {
+        let value: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(value["v"], 1);
+    }
+    assert!(text
+        .lines()
+        .nth(1)
+        .unwrap()
+        .contains(r#""type":"no_speech""#));
+
+    let records: Vec<JsonlRecord> = from_reader(bytes.as_slice())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(records.len(), 4);
+    for (id, record) in records.into_iter().enumerate() {
+        match record {
+            JsonlRecord::Segment(segment) => {
+                assert_eq!(id % 2, 0);
+                assert_eq!(segment.id, id);
+                assert_eq!(segment.dr.text, format!(" text {id}"));
+                assert_eq!(segment.dr.tokens, [1, 2]);
+                assert!(segment.dr.compression_ratio().is_nan());
+            }
+            JsonlRecord::NoSpeech {
+                id: no_speech_id,
+                start,
+                duration,
+            } => {
+                assert_eq!(id % 2, 1);
+                assert_eq!((no_speech_id, start, duration), (id, 2. * id as f64, 2.));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn truncated_last_line_is_reported_with_its_offset() {
+    let mut writer = SegmentJsonlWriter::new(vec![]);
+    for id in 0..3 {
+        writer
+            .push(&segment(id, 2. * id as f64, " text", false))
+            .unwrap();
+    }
+    let mut bytes = writer.into_inner();
+    let last_line = bytes[..bytes.len() - 1]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .unwrap()
+        + 1;
+    bytes.truncate(last_line + 20);
+
+    let results: Vec<_> = from_reader(bytes.as_slice()).collect();
+    assert_eq!(results.len(), 3);
+    assert!(results[..2].iter().all(Result::is_ok));
+    let err = results[2].as_ref().unwrap_err();
+    assert_eq!(err.kind, JsonlErrorKind::Truncated);
+    assert_eq!((err.line, err.offset), (3, last_line));
+}
+
+#[test]
+fn unknown_major_versions_are_rejected() {
+    let lines = concat!(
+        r#"{"v":2,"type":"no_speech","id":0,"start":0.0,"duration":1.0}"#,
+        "\n",
+        r#"{"type":"no_speech","id":1,"start":1.0,"duration":1.0}"#,
+        "\n\n",
+        r#"{"v":1,"type":"no_speech","id":2,"start":2.0,"duration":1.0}"#,
+        "\n",
+        r#"{"v":1,"type":"subtitle"}"#,
+        "\n",
+    );
+    let results: Vec<_> = from_reader(lines.as_bytes()).collect();
+    assert_eq!(results.len(), 4);
+    let kind = |i: usize| results[i].as_ref().unwrap_err().kind;
+    assert_eq!(kind(0), JsonlErrorKind::UnsupportedVersion);
+    assert_eq!(kind(1), JsonlErrorKind::UnsupportedVersion);
+    // The lines after an error are still read, the blank line skipped.
+    assert!(matches!(
+        results[2],
+        Ok(JsonlRecord::NoSpeech { id: 2, .. })
+    ));
+    assert_eq!(kind(3), JsonlErrorKind::InvalidRecord);
+    assert_eq!(results[3].as_ref().unwrap_err().line, 5);
+}
+
+#[test]
+fn transcription_streams_with_a_summary() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            collect_diagnostics: true,
+            collect_timings: true,
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap();
+    let bytes = SegmentJsonlWriter::new(vec![])
+        .write_output(&output, 12.)
+        .unwrap();
+    let records: Vec<JsonlRecord> = from_reader(bytes.as_slice())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let windows = output.diagnostics.as_ref().unwrap().windows.len();
+    assert_eq!(records.len(), output.segments.len() + windows + 1);
+    match records.last().unwrap() {
+        JsonlRecord::Summary(summary) => {
+            assert_eq!(summary.duration, 12.);
+            assert_eq!(summary.segments, output.segments.len());
+            assert_eq!(summary.language, output.language);
+            assert!(summary.timings.is_some());
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    let diagnostics: Vec<_> = records
+        .iter()
+        .filter_map(|record| match record {
+            JsonlRecord::Diagnostics(window) => Some(window.outcome),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(diagnostics.len(), windows);
+}
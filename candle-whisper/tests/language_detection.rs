@@ -0,0 +1,174 @@
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_whisper::{
+    fixtures::{
+        large_vocab_model_data, multilingual_tokenizer_json, sine_pcm, tiny_config,
+        tiny_weights_with,
+    },
+    hallucination::HallucinationOptions,
+    logic::{
+        detect_from_features, m, Config, DecodeOptions, Decoder, LanguageDetectionMode, Model,
+        ModelData, RunOptions, TranscriptionOutput,
+    },
+};
+use tokenizers::Tokenizer;
+
+/// [`large_vocab_model_data`] with its cross-attention amplified, so that the language
+/// detected among `mi` and `haw` follows the audio: `mi` on a high tone, `haw` on a low one.
+fn code_switch_model_data() -> ModelData {
+    let md = large_vocab_model_data();
+    let config: Config = serde_json::from_slice(&md.config).unwrap();
+    let weights = tiny_weights_with(&config, |name, values| {
+        if name.contains("encoder_attn.out_proj.weight") {
+            values.iter_mut().for_each(|v| *v *= 300.)
+        }
+    })
+    .unwrap();
+    ModelData { weights, ..md }
+}
+
+/// A 30 seconds window of high tone then a window of low tone.
+fn high_then_low() -> Vec<f32> {
+    let mut pcm = sine_pcm(30., 6000.);
+    pcm.extend(sine_pcm(30., 100.));
+    pcm
+}
+
+fn run(decoder: &mut Decoder, language_detection: LanguageDetectionMode) -> TranscriptionOutput {
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            max_tokens_per_segment: Some(1),
+            allowed_languages: Some(vec!["mi".to_string(), "haw".to_string()]),
+            language_detection,
+            hallucination: HallucinationOptions {
+                drop: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+        .run_pcm(&high_then_low(), &RunOptions::default())
+        .unwrap()
+}
+
+/// Language of every segment, checked against the language token of its prompt.
+fn languages(output: &TranscriptionOutput, tokenizer: &Tokenizer) -> Vec<String> {
+    output
+        .segments
+        .iter()
+        .map(|segment| {
+            let language = segment.language.clone().unwrap();
+            let token = tokenizer.token_to_id(&format!("<|{language}|>")).unwrap();
+            assert_eq!(segment.dr.tokens[1], token);
+            language
+        })
+        .collect()
+}
+
+#[test]
+fn every_window_is_detected_per_segment() {
+    let md = code_switch_model_data();
+    let tokenizer = Tokenizer::from_bytes(&md.tokenizer).unwrap();
+    let mut decoder = Decoder::load(md).unwrap();
+
+    let output = run(&mut decoder, LanguageDetectionMode::PerSegment);
+    assert_eq!(languages(&output, &tokenizer), ["mi", "haw"]);
+    // The language of the output is the one of the first window.
+    assert_eq!(output.language.as_deref(), Some("mi"));
+
+    let output = run(&mut decoder, LanguageDetectionMode::Once);
+    assert_eq!(languages(&output, &tokenizer), ["mi", "mi"]);
+
+    let output = run(
+        &mut decoder,
+        LanguageDetectionMode::Pinned("haw".to_string()),
+    );
+    assert_eq!(languages(&output, &tokenizer), ["haw", "haw"]);
+}
+
+#[test]
+fn encoder_runs_once_per_window_when_detecting() {
+    let mut decoder = Decoder::load(large_vocab_model_data()).unwrap();
+    decoder.warm_up().unwrap();
+    assert_eq!(decoder.encoder_forwards(), 1);
+    for mode in [
+        LanguageDetectionMode::Once,
+        LanguageDetectionMode::PerSegment,
+    ] {
+        let before = decoder.encoder_forwards();
+        let output = run(&mut decoder, mode);
+        assert!(output.language.is_some());
+        assert_eq!(output.segments.len(), 2);
+        // The detection reuses the encoder output of the window it is decoded with.
+        assert_eq!(decoder.encoder_forwards() - before, 2);
+    }
+}
+
+/// Model whose logits after the SOT token are `logits` for the given tokens and 0 for the
+/// others, whatever the audio: its final layer norm outputs the first unit vector, which the
+/// first column of the token embedding maps to the logits.
+fn scripted_model(logits: &[(u32, f32)]) -> Model {
+    let config = Config {
+        vocab_size: tokenizer().get_vocab_size(true) + 1501,
+        ..tiny_config()
+    };
+    let d_model = config.d_model;
+    let weights = tiny_weights_with(&config, |name, values| match name {
+        "model.decoder.layer_norm.weight" => values.fill(0.),
+        "model.decoder.layer_norm.bias" => values[0] = 1.,
+        "model.decoder.embed_tokens.weight" => {
+            values.iter_mut().step_by(d_model).for_each(|v| *v = 0.);
+            for &(token, logit) in logits {
+                values[token as usize * d_model] = logit
+            }
+        }
+        _ => {}
+    })
+    .unwrap();
+    let vb = VarBuilder::from_buffered_safetensors(weights, DType::F32, &Device::Cpu).unwrap();
+    Model::Normal(m::model::Whisper::load(&vb, config).unwrap())
+}
+
+fn tokenizer() -> Tokenizer {
+    Tokenizer::from_bytes(multilingual_tokenizer_json()).unwrap()
+}
+
+#[test]
+fn allowed_languages_are_ranked_among_themselves() {
+    let tokenizer = tokenizer();
+    let token = |language: &str| tokenizer.token_to_id(&format!("<|{language}|>")).unwrap();
+    // English has the highest logit but is not allowed.
+    let mut model = scripted_model(&[
+        (token("en"), 10.),
+        (token("fr"), 2.),
+        (token("de"), 1.),
+        (token("es"), 0.),
+    ]);
+    let audio_features =
+        Tensor::zeros((1, 10, tiny_config().d_model), DType::F32, &Device::Cpu).unwrap();
+
+    let detection = detect_from_features(&mut model, &tokenizer, &audio_features, None).unwrap();
+    assert_eq!(detection.language, "en");
+
+    let allowed = ["es", "german", "fr"].map(String::from);
+    let detection =
+        detect_from_features(&mut model, &tokenizer, &audio_features, Some(&allowed)).unwrap();
+    assert_eq!(detection.language, "fr");
+    assert_eq!(detection.token, token("fr"));
+    let ranked: Vec<&str> = detection
+        .probs
+        .iter()
+        .map(|p| p.language.as_str())
+        .collect();
+    assert_eq!(ranked, ["fr", "de", "es"]);
+    let total: f32 = detection.probs.iter().map(|p| p.prob).sum();
+    assert!((total - 1.).abs() < 1e-6, "{total}");
+    // The softmax of the logits of the allowed languages only.
+    let norm = 2f32.exp() + 1f32.exp() + 1.;
+    for (prob, logit) in detection.probs.iter().zip([2f32, 1., 0.]) {
+        assert!((prob.prob - logit.exp() / norm).abs() < 1e-5, "{prob:?}");
+    }
+}
@@ -0,0 +1,144 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+    profiles::{OptionProfiles, PartialDecodeOptions},
+};
+
+fn base_options() -> DecodeOptions {
+    DecodeOptions {
+        collect_diagnostics: true,
+        no_speech_threshold: Some(0.6),
+        logprob_threshold: Some(-1.),
+        ..Default::default()
+    }
+}
+
+fn no_speech_profile(threshold: f64) -> PartialDecodeOptions {
+    PartialDecodeOptions {
+        no_speech_threshold: Some(threshold),
+        ..Default::default()
+    }
+}
+
+fn decoder() -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder.set_options(base_options()).unwrap();
+    decoder
+        .set_language_profile("ja", no_speech_profile(0.3))
+        .unwrap();
+    decoder
+}
+
+/// Thresholds used for the first window of a run.
+fn thresholds(decoder: &mut Decoder) -> (Option<f64>, Option<f64>) {
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let window = output.diagnostics.unwrap().windows.remove(0);
+    (window.no_speech_threshold, window.logprob_threshold)
+}
+
+#[test]
+fn profiles_resolve_by_language() {
+    let mut profiles = OptionProfiles::new();
+    profiles.set("Japanese", no_speech_profile(0.3)).unwrap();
+    let base = base_options();
+    let ja = profiles.resolve(&base, Some("ja"));
+    assert_eq!(ja.no_speech_threshold, Some(0.3));
+    assert_eq!(ja.logprob_threshold, Some(-1.));
+    for language in [Some("en"), None] {
+        let options = profiles.resolve(&base, language);
+        assert_eq!(options.no_speech_threshold, Some(0.6));
+    }
+}
+
+#[test]
+fn runs_use_the_profile_of_their_language() {
+    // The tiny model is English-only, its audio is in English.
+    let mut decoder = decoder();
+    assert_eq!(thresholds(&mut decoder), (Some(0.6), Some(-1.)));
+
+    decoder
+        .set_language_profile(
+            "en",
+            PartialDecodeOptions {
+                no_speech_threshold: Some(0.4),
+                logprob_threshold: Some(-0.5),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(thresholds(&mut decoder), (Some(0.4), Some(-0.5)));
+    // The profile does not leak into the options as set.
+    assert_eq!(decoder.options().no_speech_threshold, Some(0.6));
+
+    decoder.set_options(base_options()).unwrap();
+    assert_eq!(thresholds(&mut decoder), (Some(0.4), Some(-0.5)));
+}
+
+#[test]
+fn profile_map_round_trips_through_json() {
+    let json = r#"{
+        "Japanese": {"no_speech_threshold": 0.3, "text": {"itn": true}},
+        "de-DE": {"max_segment_duration": 12.0}
+    }"#;
+    let profiles: OptionProfiles = serde_json::from_str(json).unwrap();
+    let mut decoder = decoder();
+    decoder.set_language_profiles(profiles).unwrap();
+
+    let profiles = decoder.language_profiles();
+    assert_eq!(
+        profiles.iter().map(|(code, _)| code).collect::<Vec<_>>(),
+        ["de", "ja"]
+    );
+    assert!(profiles.get("ja").unwrap().text.as_ref().unwrap().itn);
+    let json = serde_json::to_value(profiles).unwrap();
+    assert_eq!(
+        json["de"],
+        serde_json::json!({"max_segment_duration": 12.0})
+    );
+    let back: OptionProfiles = serde_json::from_value(json).unwrap();
+    assert_eq!(back.get("german").unwrap().max_segment_duration, Some(12.));
+}
+
+#[test]
+fn invalid_profiles_are_rejected_when_set() {
+    let mut decoder = decoder();
+    let err = decoder
+        .set_language_profile("klingon", PartialDecodeOptions::default())
+        .unwrap_err();
+    assert!(matches!(err, WhisperError::LanguageNotSupported { .. }));
+
+    let invalid = [
+        PartialDecodeOptions {
+            no_speech_threshold: Some(1.5),
+            ..Default::default()
+        },
+        PartialDecodeOptions {
+            logprob_threshold: Some(f64::NAN),
+            ..Default::default()
+        },
+        PartialDecodeOptions {
+            temperatures: Some(vec![]),
+            ..Default::default()
+        },
+        PartialDecodeOptions {
+            max_segment_chars: Some(0),
+            ..Default::default()
+        },
+    ];
+    for profile in invalid {
+        let err = decoder.set_language_profile("de", profile).unwrap_err();
+        assert!(matches!(err, WhisperError::InvalidConfig { .. }), "{err}");
+    }
+    assert!(decoder.language_profiles().get("de").is_none());
+    assert_eq!(
+        decoder
+            .language_profiles()
+            .get("ja")
+            .unwrap()
+            .no_speech_threshold,
+        Some(0.3)
+    );
+}
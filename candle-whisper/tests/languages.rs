@@ -0,0 +1,112 @@
+use candle_whisper::{
+    builder::DecoderBuilder,
+    fixtures::{multilingual_tokenizer_json, tiny_model_data},
+    languages::{canonicalize, is_supported, supported_languages},
+    logic::{DecodeOptions, Decoder, LanguageDetectionMode},
+};
+use std::collections::HashSet;
+use tokenizers::Tokenizer;
+
+#[test]
+fn aliases_and_names_resolve_to_codes() {
+    let cases = [
+        ("en", "en"),
+        ("English", "en"),
+        ("ENGLISH", "en"),
+        (" en-US ", "en"),
+        ("Mandarin", "zh"),
+        ("zh-CN", "zh"),
+        ("zh_TW", "zh"),
+        ("Chinese", "zh"),
+        ("中文", "zh"),
+        ("Deutsch", "de"),
+        ("pt-BR", "pt"),
+        ("Español", "es"),
+        ("castilian", "es"),
+        ("Haitian Creole", "ht"),
+        ("haitian", "ht"),
+        ("Burmese", "my"),
+        ("Farsi", "fa"),
+        ("nb", "no"),
+        ("iw", "he"),
+        ("jv", "jw"),
+        ("Hawaiian", "haw"),
+    ];
+    for (input, code) in cases {
+        assert_eq!(canonicalize(input), Some(code), "input: {input:?}");
+    }
+}
+
+#[test]
+fn unknown_languages_are_rejected() {
+    for input in ["", "xx", "klingon", "en-", "english language", "<|en|>"] {
+        assert_eq!(canonicalize(input), None, "input: {input:?}");
+    }
+    assert!(is_supported("en"));
+    assert!(!is_supported("English"));
+    assert!(!is_supported("EN"));
+}
+
+#[test]
+fn language_table_is_deduplicated_and_matches_the_tokenizer() {
+    let languages = supported_languages();
+    let codes: HashSet<_> = languages.iter().map(|l| l.code).collect();
+    let names: HashSet<_> = languages.iter().map(|l| l.english_name).collect();
+    assert_eq!(codes.len(), languages.len());
+    assert_eq!(names.len(), languages.len());
+
+    let tokenizer = Tokenizer::from_bytes(multilingual_tokenizer_json()).unwrap();
+    let first = tokenizer.token_to_id("<|en|>").unwrap();
+    for (i, language) in languages.iter().enumerate() {
+        let token = tokenizer.token_to_id(&format!("<|{}|>", language.code));
+        assert_eq!(token, Some(first + i as u32), "language {}", language.code);
+        assert_eq!(canonicalize(language.code), Some(language.code));
+        assert_eq!(canonicalize(language.english_name), Some(language.code));
+        assert_eq!(canonicalize(language.native_name), Some(language.code));
+    }
+}
+
+#[test]
+fn language_list_serializes_for_the_frontend() {
+    let json = serde_json::to_value(supported_languages()).unwrap();
+    assert_eq!(
+        json[0],
+        serde_json::json!({"code": "en", "english_name": "English", "native_name": "English"})
+    );
+}
+
+#[test]
+fn options_and_builder_accept_language_names() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            allowed_languages: Some(vec!["English".to_string(), "fr-FR".to_string()]),
+            language_detection: LanguageDetectionMode::Pinned("German".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(
+        decoder.options().allowed_languages.as_deref(),
+        Some(&["en".to_string(), "fr".to_string()][..])
+    );
+    assert_eq!(
+        decoder.options().language_detection,
+        LanguageDetectionMode::Pinned("de".to_string())
+    );
+    let invalid = DecodeOptions {
+        allowed_languages: Some(vec!["klingon".to_string()]),
+        ..Default::default()
+    };
+    assert!(invalid.validate().is_err());
+
+    let data = tiny_model_data();
+    let builder = |language| {
+        DecoderBuilder::new()
+            .weights_safetensors(data.weights.clone())
+            .tokenizer(multilingual_tokenizer_json())
+            .config(data.config.clone())
+            .language(Some(language))
+    };
+    let err = builder("klingon").build().err().unwrap();
+    assert!(err.to_string().contains("unsupported language klingon"));
+}
@@ -0,0 +1,115 @@
+use candle_whisper::{
+    consensus::HighAccuracyOptions,
+    fixtures::{sine_pcm, tiny_model_data, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{sequence_score, DecodeOptions, Decoder, LogitsContext, RunOptions},
+};
+
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-12, "{actual} != {expected}");
+}
+
+#[test]
+fn scores_are_normalized_by_the_length() {
+    // The mean without a penalty.
+    assert_close(sequence_score(-6., 4, None), -1.5);
+    assert_close(sequence_score(-6., 0, None), -6.);
+    // ((5 + 7) / 6) ^ 1 = 2.
+    assert_close(sequence_score(-6., 7, Some(1.)), -3.);
+    // ((5 + 7) / 6) ^ 2 = 4.
+    assert_close(sequence_score(-6., 7, Some(2.)), -1.5);
+    // ((5 + 4) / 6) ^ 3 = 3.375.
+    assert_close(sequence_score(-6.75, 4, Some(3.)), -2.);
+    // No normalization at all.
+    assert_close(sequence_score(-6., 7, Some(0.)), -6.);
+}
+
+#[test]
+fn negative_penalties_are_rejected() {
+    for length_penalty in [-1., f64::NAN] {
+        let options = DecodeOptions {
+            length_penalty: Some(length_penalty),
+            ..Default::default()
+        };
+        assert!(options.validate().is_err(), "{length_penalty}");
+    }
+}
+
+/// Logits making `token` the most probable, with probability `prob`.
+fn force(logits: &mut [f32], token: u32, prob: f32) {
+    for logit in logits.iter_mut() {
+        *logit = f32::NEG_INFINITY;
+    }
+    logits[token as usize] = 0.;
+    if prob < 1. {
+        // A less probable token holding the rest.
+        logits[(token as usize + 1) % TEXT_TOKENS.len()] = ((1. - prob) / prob).ln();
+    }
+}
+
+/// Tokens of the single window of a high accuracy run whose accepted greedy decoding samples
+/// a token at probability 0.6, while the additional pass samples 4 tokens at 0.6^(3/4) each.
+fn run(length_penalty: Option<f64>) -> Vec<u32> {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            length_penalty,
+            temperatures: vec![0.],
+            high_accuracy: Some(HighAccuracyOptions {
+                temperatures: vec![0.],
+                ..Default::default()
+            }),
+            compression_ratio_threshold: None,
+            logprob_threshold: None,
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                similarity_threshold: 2.,
+                max_ngram_coverage: 1.,
+                blocklist: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let mut passes = 0;
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.step == 0 {
+                passes += 1;
+            }
+            match (passes, context.step) {
+                (1, 0) => force(logits, 1, 0.6),
+                (2, step @ 0..=3) => force(logits, step as u32 + 1, 0.6f32.powf(0.75)),
+                _ => force(logits, EOT, 1.),
+            }
+        },
+    )));
+    let opts = RunOptions {
+        timestamps: Some(false),
+        ..Default::default()
+    };
+    let output = decoder.run_pcm(&sine_pcm(12., 440.), &opts).unwrap();
+    assert_eq!(output.segments.len(), 1);
+    let dr = &output.segments[0].dr;
+    // The score counts the sampled tokens, not `<|startoftranscript|>` and `<|notimestamps|>`.
+    assert_close(
+        dr.score,
+        sequence_score(dr.sum_logprob, dr.tokens.len() - 2, length_penalty),
+    );
+    dr.tokens
+        .iter()
+        .copied()
+        .filter(|&token| token < EOT)
+        .collect()
+}
+
+#[test]
+fn length_penalty_prefers_the_longer_candidate() {
+    // The mean log probability favours the single token, ln(0.6) over 2 tokens with the end
+    // of text being above 4 ln(0.6^(3/4)) = 3 ln(0.6) over 5.
+    assert_eq!(run(None), [1]);
+    // ((5 + 2) / 6) ^ 4 = 1.85 and ((5 + 5) / 6) ^ 4 = 7.72, ln(0.6) / 1.85 < 3 ln(0.6) / 7.72.
+    assert_eq!(run(Some(4.)), [1, 2, 3, 4]);
+}
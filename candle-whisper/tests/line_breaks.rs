@@ -0,0 +1,141 @@
+use candle_whisper::{
+    fixtures,
+    line_breaks::{break_points, closest_break, BreakStrategy},
+    logic::Segment,
+    segments::{limit_length, split_at},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+fn segment(text: &str, duration: f64, language: &str) -> Segment {
+    Segment {
+        language: Some(language.to_string()),
+        ..fixtures::segment(0, 0., duration, text)
+    }
+}
+
+/// Checks that every cut is a character and grapheme boundary and returns the pieces.
+fn pieces(text: &str, strategy: BreakStrategy) -> Vec<&str> {
+    let points = break_points(text, strategy);
+    let graphemes: Vec<usize> = text.grapheme_indices(true).map(|(i, _)| i).collect();
+    let mut start = 0;
+    let mut pieces = vec![];
+    for &point in points.iter().chain([text.len()].iter()) {
+        assert!(point > start, "{points:?} not increasing in {text:?}");
+        assert!(text.is_char_boundary(point));
+        assert!(point == text.len() || graphemes.contains(&point));
+        pieces.push(&text[start..point]);
+        start = point;
+    }
+    pieces
+}
+
+const JAPANESE: &str = "今日はGitHubでRust 1.75を試しました。「速い」と思います！";
+
+#[test]
+fn japanese_keeps_latin_words_and_punctuation_attached() {
+    let pieces = pieces(JAPANESE, BreakStrategy::for_language(Some("ja")));
+    assert!(pieces.len() > 10, "{pieces:?}");
+    assert!(pieces.contains(&"GitHub"));
+    assert!(pieces.contains(&"Rust"));
+    for piece in pieces.iter().skip(1) {
+        assert!(!piece.starts_with(['。', '！', '」']), "{pieces:?}");
+    }
+    for piece in pieces.iter() {
+        assert!(!piece.ends_with('「'), "{pieces:?}");
+        assert!(!piece.ends_with(' '), "{pieces:?}");
+    }
+    assert_eq!(pieces.concat(), JAPANESE);
+}
+
+#[test]
+fn emoji_and_combining_sequences_are_never_split() {
+    let family = "👨\u{200d}👩\u{200d}👧";
+    let text = format!("{family}🎉e\u{301}🇯🇵 x");
+    for strategy in [BreakStrategy::Cjk, BreakStrategy::Graphemes] {
+        let pieces = pieces(&text, strategy);
+        assert!(pieces.contains(&family), "{pieces:?}");
+        assert!(
+            !pieces.iter().any(|p| p.starts_with('\u{301}')),
+            "{pieces:?}"
+        );
+        assert!(!pieces.contains(&"🇯"), "{pieces:?}");
+    }
+    let text = "家族👨\u{200d}👩\u{200d}👧で行きました。";
+    assert!(pieces(text, BreakStrategy::Cjk).contains(&family));
+}
+
+#[test]
+fn thai_is_cut_between_graphemes() {
+    let text = "สวัสดีครับ ยินดีที่ได้รู้จัก";
+    let strategy = BreakStrategy::for_language(Some("th"));
+    assert_eq!(strategy, BreakStrategy::Graphemes);
+    let pieces = pieces(text, strategy);
+    assert_eq!(pieces.len(), text.graphemes(true).count() - 1);
+    assert!(pieces.contains(&"วั"), "{pieces:?}");
+    assert!(pieces.contains(&" ยิ"), "{pieces:?}");
+}
+
+#[test]
+fn whitespace_languages_keep_their_words() {
+    let strategy = BreakStrategy::for_language(Some("en"));
+    assert_eq!(pieces(" a  b c", strategy), [" a", "  b", " c"]);
+    assert_eq!(break_points("日本語", strategy), Vec::<usize>::new());
+    assert_eq!(BreakStrategy::for_language(None), BreakStrategy::Whitespace);
+    // Without spaces the closest cut falls between the graphemes.
+    assert_eq!(closest_break("ae\u{301}b", 2, strategy), 1);
+}
+
+#[test]
+fn every_index_is_a_valid_cut() {
+    let text = "👍🏽漢字かなカタカナ、ok🇫🇷。ก้";
+    for strategy in [
+        BreakStrategy::Whitespace,
+        BreakStrategy::Cjk,
+        BreakStrategy::Graphemes,
+    ] {
+        pieces(text, strategy);
+        for index in 0..=text.len() + 2 {
+            let cut = closest_break(text, index, strategy);
+            let _ = (&text[..cut], &text[cut..]);
+        }
+    }
+}
+
+#[test]
+fn japanese_segments_are_limited_at_punctuation() {
+    let text = "今日はRustを試しました。とても速いと思います。明日も続けます。";
+    let cues = limit_length(vec![segment(text, 10., "ja")], None, Some(14));
+    let texts: Vec<&str> = cues.iter().map(|c| c.dr.text.as_str()).collect();
+    assert_eq!(
+        texts,
+        [
+            "今日はRustを試しました。",
+            "とても速いと思います。",
+            "明日も続けます。"
+        ]
+    );
+    let end = cues.last().map(|c| c.start + c.duration).unwrap();
+    assert!((end - 10.).abs() < 1e-9);
+
+    // Without punctuation the cues still fit and the words stay whole.
+    let text = "日本語の文章をGitHubで書いてRustで読みます".repeat(2);
+    let cues = limit_length(vec![segment(&text, 10., "ja")], None, Some(10));
+    for cue in cues.iter() {
+        assert!(cue.dr.text.chars().count() <= 10, "{:?}", cue.dr.text);
+    }
+    assert!(cues.iter().any(|c| c.dr.text.contains("GitHub")));
+    let joined: String = cues.iter().map(|c| c.dr.text.as_str()).collect();
+    assert_eq!(joined, text);
+
+    let english = limit_length(vec![segment(text.as_str(), 10., "en")], None, Some(10));
+    assert_eq!(english.len(), 1);
+}
+
+#[test]
+fn split_at_cuts_cjk_between_graphemes() {
+    let text = "家族👨\u{200d}👩\u{200d}👧で行きました";
+    let parts = split_at(vec![segment(text, 4., "zh")], &[1.2]);
+    assert_eq!(parts.len(), 2);
+    assert_eq!(format!("{}{}", parts[0].dr.text, parts[1].dr.text), text);
+    assert!(!parts[1].dr.text.starts_with('\u{200d}'));
+}
@@ -0,0 +1,100 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+use std::path::PathBuf;
+
+/// Temporary directory named after the test and the process, holding the files of the tiny
+/// model, removed on drop.
+struct ModelDir(PathBuf);
+
+impl ModelDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "candle-whisper-paths-{}-{name}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let md = tiny_model_data();
+        for (file, bytes) in [
+            ("model.safetensors", &md.weights),
+            ("tokenizer.json", &md.tokenizer),
+            ("config.json", &md.config),
+            ("melfilters.safetensors", &md.mel_filters),
+        ] {
+            std::fs::write(dir.join(file), bytes).unwrap();
+        }
+        Self(dir)
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+
+    fn load(&self, mel_filters: Option<&str>) -> Decoder {
+        let mel_filters = mel_filters.map(|file| self.path(file));
+        Decoder::load_from_paths(
+            &self.path("model.safetensors"),
+            &self.path("tokenizer.json"),
+            &self.path("config.json"),
+            mel_filters.as_deref(),
+            options(),
+        )
+        .unwrap()
+    }
+}
+
+impl Drop for ModelDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn options() -> DecodeOptions {
+    DecodeOptions {
+        temperatures: vec![0.],
+        no_speech_threshold: None,
+        ..Default::default()
+    }
+}
+
+/// Tokens of the segments of a transcription of a sine wave.
+fn transcribe(decoder: &mut Decoder) -> Vec<Vec<u32>> {
+    let opts = RunOptions {
+        timestamps: Some(false),
+        ..Default::default()
+    };
+    let output = decoder.run_pcm(&sine_pcm(35., 440.), &opts).unwrap();
+    output.segments.into_iter().map(|s| s.dr.tokens).collect()
+}
+
+#[test]
+fn mapped_weights_transcribe_like_the_buffer() {
+    let mut from_buffer = Decoder::load(tiny_model_data()).unwrap();
+    from_buffer.set_options(options()).unwrap();
+    let expected = transcribe(&mut from_buffer);
+    assert_eq!(expected.len(), 2);
+
+    let dir = ModelDir::new("mapped");
+    assert_eq!(
+        transcribe(&mut dir.load(Some("melfilters.safetensors"))),
+        expected
+    );
+    // The filterbank computed in place of the file matches the fixture.
+    assert_eq!(transcribe(&mut dir.load(None)), expected);
+}
+
+#[test]
+fn missing_files_are_reported() {
+    let dir = ModelDir::new("missing");
+    let err = Decoder::load_from_paths(
+        &dir.path("absent.safetensors"),
+        &dir.path("tokenizer.json"),
+        &dir.path("config.json"),
+        None,
+        options(),
+    )
+    .err()
+    .unwrap();
+    assert!(err.to_string().contains("absent.safetensors"), "{err}");
+}
@@ -0,0 +1,81 @@
+//! Single test, the allocator counts the allocations of every thread.
+
+use candle_whisper::{
+    fixtures::tiny_model_data,
+    logic::{Decoder, LoadProgress, LoadStage},
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Allocator tracking the peak of the allocated bytes.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(current, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() {
+            let current = CURRENT.fetch_add(new_size - layout.size(), Ordering::Relaxed) + new_size
+                - layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        } else {
+            CURRENT.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+#[test]
+fn loading_releases_the_weights_as_it_goes() {
+    // Initializes the lazy statics of the loading.
+    drop(Decoder::load(tiny_model_data()).unwrap());
+    let md = tiny_model_data();
+    let weights = md.weights.len();
+    let largest = safetensors::SafeTensors::deserialize(&md.weights)
+        .unwrap()
+        .tensors()
+        .iter()
+        .map(|(_, view)| view.data().len())
+        .max()
+        .unwrap();
+    let before = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let mut weights_peak = 0;
+    let decoder = Decoder::load_with_progress(md, &mut |p: &LoadProgress| {
+        if p.stage == LoadStage::Weights {
+            weights_peak = PEAK.load(Ordering::Relaxed) - before;
+        }
+    })
+    .unwrap();
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    // The f32 tensors take as much as the weights, a copy of the buffer would add as much.
+    assert!(
+        peak < weights * 3 / 2,
+        "peak of {peak} bytes loading {weights} bytes of weights"
+    );
+    // While the weights are materialized, the tensors only coexist with the part of the buffer
+    // not yet released: an eighth of the weights and the tensor being materialized.
+    assert!(
+        weights_peak < weights / 8 + 2 * largest,
+        "peak of {weights_peak} bytes materializing {weights} bytes of weights, the largest \
+         tensor taking {largest}"
+    );
+    drop(decoder);
+}
@@ -0,0 +1,84 @@
+use candle_core::{quantized::GgmlDType, DType};
+use candle_whisper::{
+    builder::DecoderBuilder,
+    fixtures::tiny_model_data,
+    logic::{LoadProgress, LoadStage},
+    model_info::ModelInfo,
+};
+
+fn load(builder: DecoderBuilder) -> (Vec<LoadProgress>, usize) {
+    let mut reports = vec![];
+    let decoder = builder
+        .build_with_progress(&mut |p| reports.push(p.clone()))
+        .unwrap();
+    (reports, decoder.memory_usage().parameter_bytes)
+}
+
+/// Checks the order of the stages and that the counters never decrease.
+fn assert_monotonic(reports: &[LoadProgress]) {
+    let stages: Vec<LoadStage> = reports.iter().map(|p| p.stage).collect();
+    assert_eq!(
+        stages[..3],
+        [
+            LoadStage::Tokenizer,
+            LoadStage::Config,
+            LoadStage::MelFilters
+        ]
+    );
+    assert_eq!(stages.last(), Some(&LoadStage::Done));
+    for pair in reports.windows(2) {
+        assert!(
+            pair[1].bytes_allocated >= pair[0].bytes_allocated,
+            "{reports:?}"
+        );
+        if pair[0].stage == LoadStage::Weights {
+            assert!(
+                pair[1].tensors_loaded >= pair[0].tensors_loaded,
+                "{reports:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn progress_reports_the_materialized_tensors() {
+    let md = tiny_model_data();
+    let info = ModelInfo::from_safetensors(&md.weights).unwrap();
+    let (reports, memory) = load(DecoderBuilder::from(tiny_model_data()));
+    assert_monotonic(&reports);
+    let weights: Vec<&LoadProgress> = reports
+        .iter()
+        .filter(|p| p.stage == LoadStage::Weights)
+        .collect();
+    // The start of the stage, then one report per tensor.
+    assert_eq!(weights.len(), info.tensor_count + 1);
+    assert!(weights.iter().all(|p| p.tensors_total == info.tensor_count));
+    let done = reports.last().unwrap();
+    assert_eq!(done.tensors_loaded, info.tensor_count);
+    // The f32 weights are materialized as they are stored.
+    assert_eq!(done.bytes_allocated, info.parameter_bytes);
+    assert_eq!(done.bytes_allocated, info.parameter_count * 4);
+    assert_eq!(memory, done.bytes_allocated);
+}
+
+#[test]
+fn converted_tensors_are_counted_in_their_dtype() {
+    let info = ModelInfo::from_safetensors(&tiny_model_data().weights).unwrap();
+    let builder = DecoderBuilder::from(tiny_model_data()).dtype(DType::F16);
+    let (reports, memory) = load(builder);
+    assert_monotonic(&reports);
+    let done = reports.last().unwrap();
+    assert_eq!(done.bytes_allocated, info.parameter_bytes / 2);
+    assert_eq!(memory, done.bytes_allocated);
+}
+
+#[test]
+fn quantized_tensors_are_counted_once() {
+    let info = ModelInfo::from_safetensors(&tiny_model_data().weights).unwrap();
+    let builder = DecoderBuilder::from(tiny_model_data()).quantize_on_load(Some(GgmlDType::Q8_0));
+    let (reports, memory) = load(builder);
+    assert_monotonic(&reports);
+    let done = reports.last().unwrap();
+    assert!(done.bytes_allocated < info.parameter_bytes);
+    assert_eq!(memory, done.bytes_allocated);
+}
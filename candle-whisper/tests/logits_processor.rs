@@ -0,0 +1,85 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{Decoder, LogitsContext, RunOptions},
+};
+use std::{cell::RefCell, rc::Rc};
+
+/// `<|startoftranscript|>` and `<|notimestamps|>`.
+const PROMPT_LEN: usize = 2;
+
+/// A call of the processor.
+#[derive(Debug, Clone, Copy)]
+struct Call {
+    step: usize,
+    generated: usize,
+    segment_start: f64,
+    timestamps: bool,
+}
+
+/// Logits of the first sampled token, and the calls of the processor.
+fn first_logits(decoder: &mut Decoder) -> (Vec<f32>, Vec<Call>) {
+    let first = Rc::new(RefCell::new(vec![]));
+    let contexts = Rc::new(RefCell::new(vec![]));
+    let (first_hook, contexts_hook) = (first.clone(), contexts.clone());
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.step == 0 && first_hook.borrow().is_empty() {
+                *first_hook.borrow_mut() = logits.to_vec();
+            }
+            contexts_hook.borrow_mut().push(Call {
+                step: context.step,
+                generated: context.tokens.len(),
+                segment_start: context.segment_start,
+                timestamps: context.timestamps,
+            });
+        },
+    )));
+    decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let first = first.borrow().clone();
+    let contexts = contexts.borrow().clone();
+    (first, contexts)
+}
+
+#[test]
+fn processor_sees_every_step() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let (_, calls) = first_logits(&mut decoder);
+    assert!(!calls.is_empty());
+    for (i, call) in calls.iter().enumerate() {
+        assert_eq!(call.step, i);
+        assert_eq!(call.generated, i);
+        assert_eq!(call.segment_start, 0.);
+        assert!(!call.timestamps);
+    }
+}
+
+#[test]
+fn boosted_token_wins_a_close_race() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let (logits, _) = first_logits(&mut decoder);
+    let mut ranked: Vec<usize> = (0..logits.len()).collect();
+    ranked.sort_by(|&a, &b| logits[b].total_cmp(&logits[a]));
+    let (best, runner_up) = (ranked[0], ranked[1]);
+    let gap = logits[best] - logits[runner_up];
+    assert!(gap.is_finite());
+
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.step == 0 {
+                logits[runner_up] += gap + 0.01;
+            }
+        },
+    )));
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.segments[0].dr.tokens[PROMPT_LEN], runner_up as u32);
+
+    decoder.set_logits_processor(None);
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.segments[0].dr.tokens[PROMPT_LEN], best as u32);
+}
@@ -0,0 +1,275 @@
+use candle_whisper::{
+    audio::{self, MelNormalization::GlobalMax},
+    fixtures::{sine_pcm, tiny_config},
+    logic::m,
+};
+
+/// `(bin, frame, value)` of the log-mel spectrogram of one second of a 440Hz sine wave at half
+/// the full scale, the values of the silent padding being clamped 8 below the maximum.
+const GOLDEN_440HZ: &[(usize, usize, f32)] = &[
+    (8, 0, -0.48653865),
+    (9, 0, 1.2346487),
+    (10, 0, 1.4239955),
+    (11, 0, 1.5134612),
+    (12, 0, 1.368781),
+    (8, 50, -0.48653865),
+    (9, 50, 1.2346487),
+    (10, 50, 1.4239955),
+    (11, 50, 1.5134612),
+    (12, 50, 1.368781),
+    (14, 99, 1.0099968),
+    (11, 100, -0.48653865),
+    (0, 2999, -0.48653865),
+];
+
+#[test]
+fn pcm_to_mel_matches_golden_values() {
+    let config = tiny_config();
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, config.num_mel_bins);
+    let mel = audio::pcm_to_mel(&config, &sine_pcm(1., 440.), &filters, GlobalMax).unwrap();
+    let n_frames = mel.len() / config.num_mel_bins;
+    assert_eq!(n_frames, m::N_FRAMES);
+    for &(bin, frame, expected) in GOLDEN_440HZ {
+        let value = mel[bin * n_frames + frame];
+        assert!(
+            (value - expected).abs() < 1e-4,
+            "mel[{bin}, {frame}] = {value}, expected {expected}"
+        );
+    }
+}
+
+#[test]
+fn mel_filters_match_golden_values() {
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, 80);
+    assert_eq!(filters.len(), 80 * (m::N_FFT / 2 + 1));
+    for (index, expected) in [(1, 0.024862595), (202, 0.001990822), (203, 0.022871772)] {
+        assert!((filters[index] - expected).abs() < 1e-6);
+    }
+}
+
+/// Log-mel spectrogram of `pcm` computed with the direct DFT in `f64`, as in `pcm_to_mel`.
+fn reference_log_mel(pcm: &[f32], filters: &[f32], n_mels: usize) -> Vec<f64> {
+    let n_fft = m::N_FFT;
+    let n_bins = n_fft / 2 + 1;
+    let hann: Vec<f64> = (0..n_fft)
+        .map(|i| 0.5 * (1. - (std::f64::consts::TAU * i as f64 / n_fft as f64).cos()))
+        .collect();
+    let n_frames = m::N_FRAMES;
+    let mut mel = vec![-10f64; n_mels * n_frames];
+    // The frames past the audio are silent.
+    for frame in 0..pcm.len().div_ceil(m::HOP_LENGTH) {
+        let offset = frame * m::HOP_LENGTH;
+        let windowed: Vec<f64> = (0..n_fft)
+            .map(|j| hann[j] * pcm.get(offset + j).map_or(0., |&x| x as f64))
+            .collect();
+        let power: Vec<f64> = (0..n_bins)
+            .map(|k| {
+                let (re, im) = windowed
+                    .iter()
+                    .enumerate()
+                    .fold((0., 0.), |(re, im), (j, x)| {
+                        let angle = std::f64::consts::TAU * (k * j % n_fft) as f64 / n_fft as f64;
+                        (re + x * angle.cos(), im - x * angle.sin())
+                    });
+                // The negative frequencies are folded onto the positive ones.
+                let fold = if k == 0 || k == n_fft / 2 { 1. } else { 2. };
+                fold * (re * re + im * im)
+            })
+            .collect();
+        for bin in 0..n_mels {
+            let sum: f64 = (0..n_bins)
+                .map(|k| power[k] * filters[bin * n_bins + k] as f64)
+                .sum();
+            mel[bin * n_frames + frame] = sum.max(1e-10).log10();
+        }
+    }
+    let max = mel.iter().copied().fold(f64::MIN, f64::max) - 8.;
+    mel.iter().map(|&v| v.max(max) / 4. + 1.).collect()
+}
+
+#[test]
+fn pcm_to_mel_matches_the_direct_dft() {
+    // N_FFT is 400, not a power of two.
+    let config = tiny_config();
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, config.num_mel_bins);
+    let pcm: Vec<f32> = (0..m::SAMPLE_RATE / 4)
+        .map(|i| {
+            let t = i as f32 / m::SAMPLE_RATE as f32;
+            0.3 * (std::f32::consts::TAU * (200. + 3000. * t) * t).sin()
+                + 0.1 * (std::f32::consts::TAU * 2500. * t).sin()
+                + 0.05 * ((i * 7919 % 1000) as f32 / 500. - 1.)
+        })
+        .collect();
+    let mel = audio::pcm_to_mel(&config, &pcm, &filters, GlobalMax).unwrap();
+    let expected = reference_log_mel(&pcm, &filters, config.num_mel_bins);
+    assert_eq!(mel.len(), expected.len());
+    for (i, (&value, &expected)) in mel.iter().zip(&expected).enumerate() {
+        assert!(
+            (value as f64 - expected).abs() < 1e-3,
+            "mel[{i}] = {value}, expected {expected}"
+        );
+    }
+}
+
+/// FNV-1a hash of the bits of the mel spectrogram computed in `f64` of [`noise_pcm`].
+const PRECISE_MEL_HASH: u64 = 0xb2fb2043b0b741d3;
+
+/// Half a second of pseudo-random samples in `[-0.5, 0.5)`.
+fn noise_pcm() -> Vec<f32> {
+    let mut state = 0x2545f4914f6cdd1du64;
+    (0..m::SAMPLE_RATE / 2)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        })
+        .collect()
+}
+
+fn fnv1a(values: &[f32]) -> u64 {
+    values
+        .iter()
+        .flat_map(|v| v.to_bits().to_le_bytes())
+        .fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[test]
+fn precise_mel_is_reproducible() {
+    let config = tiny_config();
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, config.num_mel_bins);
+    let pcm = noise_pcm();
+    let precise = audio::pcm_to_mel_precise(&config, &pcm, &filters, GlobalMax).unwrap();
+    assert_eq!(fnv1a(&precise), PRECISE_MEL_HASH, "{:#x}", fnv1a(&precise));
+
+    let mel = audio::pcm_to_mel(&config, &pcm, &filters, GlobalMax).unwrap();
+    let max_error = mel
+        .iter()
+        .zip(&precise)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0f32, f32::max);
+    assert!(max_error < 1e-5, "max error {max_error}");
+}
+
+#[test]
+fn reused_mel_context_matches_fresh_spectrograms() {
+    let config = tiny_config();
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, config.num_mel_bins);
+    let mut context = audio::MelContext::new();
+    let mut precise_context = audio::MelContext::new();
+    // A long input first, the shorter ones reusing larger buffers.
+    for pcm in [sine_pcm(35., 440.), noise_pcm(), sine_pcm(1., 1000.)] {
+        let fresh = audio::pcm_to_mel(&config, &pcm, &filters, GlobalMax).unwrap();
+        let reused = context
+            .pcm_to_mel(&config, &pcm, &filters, GlobalMax)
+            .unwrap();
+        assert_eq!(fresh, reused);
+
+        let fresh = audio::pcm_to_mel_precise(&config, &pcm, &filters, GlobalMax).unwrap();
+        let reused = precise_context
+            .pcm_to_mel_precise(&config, &pcm, &filters, GlobalMax)
+            .unwrap();
+        assert_eq!(fresh, reused);
+    }
+}
+
+/// `seconds` of quiet tones across the speech band, with a full-scale click of 20ms at 1s.
+fn click_over_quiet_speech_band(seconds: f64) -> Vec<f32> {
+    let n = (seconds * m::SAMPLE_RATE as f64) as usize;
+    let click = m::SAMPLE_RATE..m::SAMPLE_RATE + m::SAMPLE_RATE / 50;
+    (0..n)
+        .map(|i| {
+            let t = i as f32 / m::SAMPLE_RATE as f32;
+            let tones: f32 = [300., 700., 1100., 1900., 2700.]
+                .iter()
+                .map(|hz| (std::f32::consts::TAU * hz * t).sin())
+                .sum();
+            let click = if click.contains(&i) {
+                if i % 2 == 0 {
+                    1.
+                } else {
+                    -1.
+                }
+            } else {
+                0.
+            };
+            1e-5 * tones + click
+        })
+        .collect()
+}
+
+/// Variance of the normalized mel values above which the tones are told apart from the floor.
+const MIN_VARIANCE: f32 = 0.01;
+
+/// Variance of the values of `frames` of `mel`.
+fn variance(mel: &[f32], n_mels: usize, frames: std::ops::Range<usize>) -> f32 {
+    let n_len = mel.len() / n_mels;
+    let values: Vec<f32> = (0..n_mels)
+        .flat_map(|j| &mel[j * n_len + frames.start..j * n_len + frames.end])
+        .copied()
+        .collect();
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32
+}
+
+#[test]
+fn percentile_normalization_survives_a_click() {
+    use audio::MelNormalization::{PerWindow, Percentile};
+
+    let config = tiny_config();
+    let n_mels = config.num_mel_bins;
+    let filters = audio::mel_filters(m::SAMPLE_RATE, m::N_FFT, n_mels);
+    // The frames of the tones from 2 seconds, after the click.
+    let frames_per_second = m::SAMPLE_RATE / m::HOP_LENGTH;
+    let tones = 2 * frames_per_second..10 * frames_per_second;
+    let pcm = click_over_quiet_speech_band(10.);
+    let global = audio::pcm_to_mel(&config, &pcm, &filters, GlobalMax).unwrap();
+    let percentile = audio::pcm_to_mel(&config, &pcm, &filters, Percentile(99.5)).unwrap();
+    // The click is 80dB above the tones, which end up in the floor.
+    assert!(variance(&global, n_mels, tones.clone()) < 1e-4);
+    assert!(variance(&percentile, n_mels, tones) > MIN_VARIANCE);
+
+    // The audio ends 10 seconds into the second window.
+    let second_window = m::N_FRAMES..m::N_FRAMES + 10 * frames_per_second;
+    let pcm = click_over_quiet_speech_band(40.);
+    let global = audio::pcm_to_mel(&config, &pcm, &filters, GlobalMax).unwrap();
+    let per_window = audio::pcm_to_mel(&config, &pcm, &filters, PerWindow).unwrap();
+    assert!(variance(&global, n_mels, second_window.clone()) < 1e-4);
+    assert!(variance(&per_window, n_mels, second_window) > MIN_VARIANCE);
+    // The window with the click is normalized as with the global maximum.
+    let n_len = global.len() / n_mels;
+    assert_eq!(global[..m::N_FRAMES], per_window[..m::N_FRAMES]);
+    assert_eq!(global[n_len..n_len + 10], per_window[n_len..n_len + 10]);
+}
+
+#[test]
+fn spectrogram_bytes_round_trip() {
+    let device = candle_core::Device::Cpu;
+    let data: Vec<f32> = (0..80 * 7).map(|i| i as f32 * 0.25 - 3.).collect();
+    let mel = audio::MelSpectrogram::new(data.clone(), 80, 0.07, &device).unwrap();
+    let bytes = mel.to_bytes().unwrap();
+    assert_eq!(&bytes[..4], b"WMEL");
+    assert_eq!(bytes.len(), 24 + data.len() * 4);
+
+    let read = audio::MelSpectrogram::from_bytes(&bytes, &device).unwrap();
+    assert_eq!((read.n_mels(), read.n_frames()), (80, 7));
+    assert_eq!(read.duration(), 0.07);
+    let values = read
+        .tensor()
+        .flatten_all()
+        .unwrap()
+        .to_vec1::<f32>()
+        .unwrap();
+    assert_eq!(values, data);
+
+    // Truncated data, a bad magic and sizes whose product overflows on 32-bit targets.
+    assert!(audio::MelSpectrogram::from_bytes(&bytes[..bytes.len() - 4], &device).is_err());
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+    assert!(audio::MelSpectrogram::from_bytes(&bad_magic, &device).is_err());
+    let mut huge = bytes;
+    huge[8..16].fill(0xff);
+    assert!(audio::MelSpectrogram::from_bytes(&huge, &device).is_err());
+}
@@ -0,0 +1,58 @@
+use candle_whisper::{
+    fixtures::{tiny_config_json, tiny_model_data, tiny_tokenizer_json},
+    logic::ModelData,
+};
+use serde_json::json;
+
+#[test]
+fn json_layout_is_unchanged() {
+    let md = ModelData {
+        weights: vec![1, 2, 255],
+        tokenizer: vec![],
+        mel_filters: vec![0],
+        config: b"{}".to_vec(),
+        language: Some("fr".to_string()),
+        ..tiny_model_data()
+    };
+    let old = json!({
+        "weights": [1, 2, 255],
+        "tokenizer": [],
+        "mel_filters": [0],
+        "config": [123, 125],
+        "quantized": false,
+        "timestamps": false,
+        "is_multilingual": false,
+        "language": "fr",
+        "task": null,
+        "dtype": null,
+    });
+    assert_eq!(serde_json::to_value(&md).unwrap(), old);
+    let parsed: ModelData = serde_json::from_value(old).unwrap();
+    assert_eq!(parsed.weights, [1, 2, 255]);
+    assert_eq!(parsed.config, b"{}");
+    assert!(parsed.tokenizer.is_empty());
+    // Written before the dtype existed.
+    let parsed: ModelData = serde_json::from_str(
+        r#"{"weights":[7],"tokenizer":[],"mel_filters":[],"config":[],"quantized":true,
+        "timestamps":true,"is_multilingual":true,"language":null,"task":"translate"}"#,
+    )
+    .unwrap();
+    assert_eq!(parsed.weights, [7]);
+    assert!(parsed.dtype.is_none());
+    assert!(serde_json::from_str::<ModelData>(r#"{"weights":[256]}"#).is_err());
+}
+
+#[test]
+fn parts_are_moved_without_a_copy() {
+    let weights: Box<[u8]> = tiny_model_data().weights.into_boxed_slice();
+    let weights_ptr = weights.as_ptr();
+    let tokenizer = tiny_tokenizer_json();
+    let tokenizer_ptr = tokenizer.as_ptr();
+    let md = ModelData::from_parts(weights, tokenizer, vec![], tiny_config_json());
+    assert_eq!(md.weights.as_ptr(), weights_ptr);
+    assert_eq!(md.tokenizer.as_ptr(), tokenizer_ptr);
+    assert!(!md.quantized);
+    assert!(!md.is_multilingual);
+    assert!(md.timestamps);
+    assert!(ModelData::from_parts(*b"GGUF\x03\0\0\0", vec![], vec![], vec![]).quantized);
+}
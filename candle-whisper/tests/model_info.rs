@@ -0,0 +1,70 @@
+use candle_core::{
+    quantized::{gguf_file, GgmlDType, QTensor},
+    Device, Tensor,
+};
+use candle_whisper::{
+    fixtures::{tiny_config, tiny_weights},
+    model_info::ModelInfo,
+};
+
+/// Path of a file of the temporary directory named after the test and the process.
+fn temp_file(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "candle-whisper-model-info-{}-{name}",
+        std::process::id()
+    ))
+}
+
+/// GGUF file of a Q8_0 matrix and an F32 bias.
+fn gguf_bytes() -> Vec<u8> {
+    let device = Device::Cpu;
+    let weight = Tensor::arange(0f32, 64. * 32., &device)
+        .unwrap()
+        .reshape((64, 32))
+        .unwrap();
+    let bias = Tensor::zeros(64, candle_core::DType::F32, &device).unwrap();
+    let weight = QTensor::quantize(&weight, GgmlDType::Q8_0).unwrap();
+    let bias = QTensor::quantize(&bias, GgmlDType::F32).unwrap();
+    let mut gguf = std::io::Cursor::new(vec![]);
+    gguf_file::write(
+        &mut gguf,
+        &[],
+        &[("layer.weight", &weight), ("layer.bias", &bias)],
+    )
+    .unwrap();
+    gguf.into_inner()
+}
+
+#[test]
+fn gguf_is_recognized_by_its_magic() {
+    let bytes = gguf_bytes();
+    // Without the `.gguf` extension the magic is read before the header.
+    let path = temp_file("weights.bin");
+    std::fs::write(&path, &bytes).unwrap();
+    let info = ModelInfo::from_file(&path);
+    std::fs::remove_file(&path).unwrap();
+    let info = info.unwrap();
+    assert!(info.quantized);
+    assert_eq!(info.tensor_count, 2);
+    assert_eq!(info.quantization.as_deref(), Some("Q8_0"));
+    assert_eq!(info.parameter_count, 64 * 32 + 64);
+    assert_eq!(
+        info.parameter_bytes,
+        ModelInfo::from_gguf(&bytes).unwrap().parameter_bytes
+    );
+}
+
+#[test]
+fn safetensors_file_matches_the_bytes() {
+    let weights = tiny_weights(&tiny_config()).unwrap();
+    let path = temp_file("model.safetensors");
+    std::fs::write(&path, &weights).unwrap();
+    let info = ModelInfo::from_file(&path);
+    std::fs::remove_file(&path).unwrap();
+    let info = info.unwrap();
+    let expected = ModelInfo::from_safetensors(&weights).unwrap();
+    assert!(!info.quantized);
+    assert_eq!(info.tensor_count, expected.tensor_count);
+    assert_eq!(info.parameter_bytes, expected.parameter_bytes);
+    assert_eq!(info.d_model, Some(tiny_config().d_model));
+}
@@ -0,0 +1,136 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::{
+        large_vocab_model_data, multilingual_model_data, tiny_config, tiny_model_data, tiny_weights,
+    },
+    logic::{Config, Decoder, ModelData},
+};
+
+/// Issues reported when loading `md`.
+fn issues(md: ModelData) -> Vec<String> {
+    match Decoder::load(md) {
+        Err(WhisperError::ModelMismatch { issues }) => issues,
+        Err(err) => panic!("unexpected error {err:?}"),
+        Ok(_) => panic!("loaded"),
+    }
+}
+
+/// [`tiny_model_data`] with the weights of its config edited by `edit`.
+fn with_weights_of(edit: impl Fn(&mut Config)) -> ModelData {
+    let mut config = tiny_config();
+    edit(&mut config);
+    ModelData {
+        weights: tiny_weights(&config).unwrap(),
+        ..tiny_model_data()
+    }
+}
+
+#[test]
+fn weights_of_another_vocabulary_are_rejected() {
+    let md = ModelData {
+        weights: multilingual_model_data().weights,
+        ..tiny_model_data()
+    };
+    let vocab_size = tiny_config().vocab_size;
+    let [issue] = issues(md).try_into().unwrap();
+    assert!(
+        issue.contains("has vocab_size") && issue.ends_with(&format!("expects {vocab_size}")),
+        "{issue}"
+    );
+}
+
+#[test]
+fn weights_of_other_dimensions_are_rejected() {
+    let [issue] = issues(with_weights_of(|config| config.num_mel_bins = 128))
+        .try_into()
+        .unwrap();
+    assert_eq!(
+        issue,
+        "safetensors model has num_mel_bins 128 but config.json expects 80"
+    );
+
+    let issues = issues(with_weights_of(|config| config.d_model = 128));
+    assert!(
+        issues
+            .contains(&"safetensors model has d_model 128 but config.json expects 64".to_string()),
+        "{issues:?}"
+    );
+}
+
+#[test]
+fn weights_with_other_block_counts_are_rejected() {
+    let issues = issues(with_weights_of(|config| {
+        config.encoder_layers = 3;
+        config.decoder_layers = 1;
+    }));
+    assert_eq!(
+        issues,
+        [
+            "safetensors model has 3 audio layers but config.json expects 2",
+            "safetensors model has 1 text layers but config.json expects 2",
+        ]
+    );
+}
+
+#[test]
+fn tokenizer_of_another_vocabulary_is_rejected() {
+    // More special tokens, `<|notimestamps|>` has another id.
+    let md = ModelData {
+        tokenizer: multilingual_model_data().tokenizer,
+        ..tiny_model_data()
+    };
+    let [issue] = issues(md).try_into().unwrap();
+    assert!(
+        issue.starts_with("tokenizer implies a vocab_size"),
+        "{issue}"
+    );
+
+    // More tokens than the model has embeddings for.
+    let md = ModelData {
+        tokenizer: large_vocab_model_data().tokenizer,
+        ..tiny_model_data()
+    };
+    let issues = issues(md);
+    assert_eq!(issues.len(), 2, "{issues:?}");
+    assert!(issues[0].starts_with("tokenizer has "), "{issues:?}");
+    assert!(
+        issues[1].starts_with("tokenizer implies a vocab_size"),
+        "{issues:?}"
+    );
+}
+
+#[test]
+fn multilingual_model_needs_language_tokens() {
+    let md = large_vocab_model_data();
+    // Renamed, the token keeps its id.
+    let tokenizer = String::from_utf8(md.tokenizer)
+        .unwrap()
+        .replace("<|en|>", "<|english|>");
+    let md = ModelData {
+        tokenizer: tokenizer.into_bytes(),
+        ..md
+    };
+    assert_eq!(
+        issues(md),
+        ["the model is multilingual but the tokenizer has no language tokens"]
+    );
+}
+
+#[test]
+fn mismatches_are_reported_together() {
+    let md = ModelData {
+        tokenizer: multilingual_model_data().tokenizer,
+        ..with_weights_of(|config| config.num_mel_bins = 128)
+    };
+    let err = Decoder::load(md).err().unwrap();
+    assert_eq!(err.code(), "model_mismatch");
+    let message = err.to_string();
+    assert!(
+        message.starts_with("the model files do not match: "),
+        "{message}"
+    );
+    assert!(
+        message.contains("expects 80, tokenizer implies"),
+        "{message}"
+    );
+}
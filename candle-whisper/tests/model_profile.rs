@@ -0,0 +1,173 @@
+use candle_whisper::{
+    builder::DecoderBuilder,
+    fixtures::{sine_pcm, tiny_config_json, tiny_model_data, tiny_weights},
+    logic::{Config, DecodeOptions, Decoder, LanguageDetectionMode, ModelData, RunOptions},
+    model_profile::ModelProfile,
+};
+use serde_json::json;
+
+/// The fields of the `config.json` of a whisper checkpoint of the Hugging Face hub that the
+/// model reads, with the other fields of those files.
+fn hub_config(
+    d_model: usize,
+    encoder_layers: usize,
+    decoder_layers: usize,
+    heads: usize,
+    vocab_size: usize,
+    num_mel_bins: usize,
+) -> Config {
+    serde_json::from_value(json!({
+        "_name_or_path": "openai/whisper",
+        "activation_function": "gelu",
+        "architectures": ["WhisperForConditionalGeneration"],
+        "d_model": d_model,
+        "decoder_attention_heads": heads,
+        "decoder_ffn_dim": 4 * d_model,
+        "decoder_layers": decoder_layers,
+        "decoder_start_token_id": 50258,
+        "encoder_attention_heads": heads,
+        "encoder_ffn_dim": 4 * d_model,
+        "encoder_layers": encoder_layers,
+        "max_source_positions": 1500,
+        "max_target_positions": 448,
+        "model_type": "whisper",
+        "num_hidden_layers": encoder_layers,
+        "num_mel_bins": num_mel_bins,
+        "scale_embedding": false,
+        "suppress_tokens": [1, 2, 7, 8, 9, 10, 14, 25, 26, 27, 28, 29, 31, 58, 59, 60, 61, 62],
+        "torch_dtype": "float32",
+        "vocab_size": vocab_size,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn hub_configs_are_classified() {
+    let configs = [
+        (
+            "tiny.en",
+            hub_config(384, 4, 4, 6, 51864, 80),
+            ModelProfile::Tiny,
+        ),
+        (
+            "base",
+            hub_config(512, 6, 6, 8, 51865, 80),
+            ModelProfile::Base,
+        ),
+        (
+            "small",
+            hub_config(768, 12, 12, 12, 51865, 80),
+            ModelProfile::Small,
+        ),
+        (
+            "medium.en",
+            hub_config(1024, 24, 24, 16, 51864, 80),
+            ModelProfile::Medium,
+        ),
+        (
+            "large-v2",
+            hub_config(1280, 32, 32, 20, 51865, 80),
+            ModelProfile::Large,
+        ),
+        (
+            "large-v3",
+            hub_config(1280, 32, 32, 20, 51866, 128),
+            ModelProfile::Large,
+        ),
+        (
+            "distil-large-v3",
+            hub_config(1280, 32, 2, 20, 51866, 128),
+            ModelProfile::Distil,
+        ),
+        (
+            "distil-small.en",
+            hub_config(768, 12, 4, 12, 51864, 80),
+            ModelProfile::Distil,
+        ),
+        (
+            "large-v3-turbo",
+            hub_config(1280, 32, 4, 20, 51866, 128),
+            ModelProfile::Distil,
+        ),
+        (
+            "custom",
+            hub_config(640, 8, 8, 10, 51865, 80),
+            ModelProfile::Generic,
+        ),
+    ];
+    for (name, config, profile) in &configs {
+        assert_eq!(ModelProfile::classify(config), *profile, "{name}");
+    }
+
+    let tiny = ModelProfile::from_config(&configs[0].1, false, false);
+    assert!(tiny.high_accuracy.is_some());
+    let large = ModelProfile::from_config(&configs[5].1, true, true);
+    assert!(large.temperatures.len() < DecodeOptions::default().temperatures.len());
+    assert_eq!(large.language_detection, LanguageDetectionMode::PerSegment);
+    assert!(large.high_accuracy.is_none());
+    // Not on wasm.
+    assert_eq!(large.max_decode_seconds_per_segment, None);
+    let distil = ModelProfile::from_config(&configs[6].1, true, false);
+    assert_eq!(distil.chunked_long_form, Some(true));
+    let generic = ModelProfile::from_config(&configs[9].1, true, false);
+    assert_eq!(
+        serde_json::to_value(generic).unwrap(),
+        serde_json::to_value(DecodeOptions::default()).unwrap()
+    );
+}
+
+/// [`tiny_model_data`] with the width and depth of the whisper tiny model.
+fn tiny_shaped_model_data() -> ModelData {
+    let mut config: serde_json::Value = serde_json::from_slice(&tiny_config_json()).unwrap();
+    config["d_model"] = json!(384);
+    config["encoder_attention_heads"] = json!(6);
+    config["decoder_attention_heads"] = json!(6);
+    config["encoder_layers"] = json!(4);
+    config["decoder_layers"] = json!(4);
+    let config = serde_json::to_vec(&config).unwrap();
+    ModelData {
+        weights: tiny_weights(&serde_json::from_slice(&config).unwrap()).unwrap(),
+        config,
+        ..tiny_model_data()
+    }
+}
+
+#[test]
+fn load_applies_the_profile_unless_options_are_given() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    assert_eq!(decoder.model_info().profile, ModelProfile::Generic);
+    assert_eq!(
+        serde_json::to_value(decoder.options()).unwrap(),
+        serde_json::to_value(DecodeOptions::default()).unwrap()
+    );
+    decoder
+        .set_options(DecodeOptions {
+            collect_diagnostics: true,
+            ..decoder.profile_options()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(2., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(
+        output.diagnostics.unwrap().model_profile,
+        ModelProfile::Generic
+    );
+
+    let decoder = Decoder::load(tiny_shaped_model_data()).unwrap();
+    assert_eq!(decoder.model_info().profile, ModelProfile::Tiny);
+    let info = serde_json::to_value(decoder.model_info()).unwrap();
+    assert_eq!(info["profile"], "tiny");
+    assert!(decoder.options().high_accuracy.is_some());
+
+    let explicit = DecodeOptions {
+        temperatures: vec![0.],
+        ..Default::default()
+    };
+    let decoder = DecoderBuilder::from(tiny_shaped_model_data())
+        .options(Some(explicit))
+        .build()
+        .unwrap();
+    assert!(decoder.options().high_accuracy.is_none());
+    assert_eq!(decoder.options().temperatures, [0.]);
+}
@@ -0,0 +1,64 @@
+use candle_whisper::overlap::{find_cut, repeated_tail, OverlapWord};
+
+/// Words of `text` evenly spread over `start..end`.
+fn words(text: &str, start: f64, end: f64) -> Vec<OverlapWord> {
+    let texts: Vec<&str> = text.split(' ').collect();
+    let step = (end - start) / texts.len() as f64;
+    texts
+        .iter()
+        .enumerate()
+        .map(|(i, text)| OverlapWord {
+            text: text.to_string(),
+            tokens: i..i + 1,
+            start: start + i as f64 * step,
+            end: start + (i + 1) as f64 * step,
+        })
+        .collect()
+}
+
+fn stitch(previous: &[OverlapWord], next: &[OverlapWord], prefer_previous: bool) -> String {
+    let cut = find_cut(previous, next, (25., 30.), prefer_previous);
+    previous[..cut.previous_words]
+        .iter()
+        .chain(next[cut.next_words..].iter())
+        .map(|w| w.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn overlapping_windows_are_stitched_without_duplication_or_truncation() {
+    // The previous window ends in the middle of "boundary", the next one starts in the middle
+    // of "the".
+    let previous = words(
+        "a b c d e f g h i j k l m n o p q r s t u v w x the quick brown fox jumps bound",
+        0.,
+        30.,
+    );
+    let next = words("e quick brown fox jumps boundary words follow", 25., 33.);
+    let expected = "a b c d e f g h i j k l m n o p q r s t u v w x the quick brown fox jumps \
+                    boundary words follow";
+    assert_eq!(stitch(&previous, &next, true), expected);
+    assert_eq!(stitch(&previous, &next, false), expected);
+}
+
+#[test]
+fn windows_without_common_words_are_cut_in_the_middle() {
+    let previous = words("one two three four five six", 0., 30.);
+    let next = words("seven eight nine", 25., 40.);
+    let cut = find_cut(&previous, &next, (25., 30.), true);
+    assert_eq!(cut.time, 27.5);
+    assert_eq!(cut.previous_words, 6);
+    assert_eq!(cut.next_words, 1);
+}
+
+#[test]
+fn repeated_trailing_sentences_are_counted() {
+    assert_eq!(
+        repeated_tail(&words("Hi there. Thank you. Thank you. thank you!", 0., 1.)),
+        4
+    );
+    assert_eq!(repeated_tail(&words("Thank you. Hi there.", 0., 1.)), 0);
+    assert_eq!(repeated_tail(&words(". .", 0., 1.)), 0);
+    assert_eq!(repeated_tail(&[]), 0);
+}
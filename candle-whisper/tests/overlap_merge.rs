@@ -0,0 +1,181 @@
+use candle_whisper::{
+    fixtures::{tiny_model_data, SPECIAL_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, LogitsContext, ModelData, RunOptions, TranscriptionOutput},
+};
+use serde_json::json;
+
+/// Byte-level text tokens, the words carrying their leading space as in the whisper
+/// vocabularies, ` boundary` being split over two tokens.
+const TEXT_TOKENS: [&str; 11] = [
+    "Ġhello", "Ġworld", "Ġthe", "Ġsound", "Ġof", "Ġa", "Ġsine", "Ġwave", "Ġbound", "ary", ".",
+];
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+const HELLO: u32 = 0;
+const WORLD: u32 = 1;
+const THE: u32 = 2;
+const SOUND: u32 = 3;
+const OF: u32 = 4;
+const A: u32 = 5;
+const SINE: u32 = 6;
+const WAVE: u32 = 7;
+const BOUND: u32 = 8;
+const ARY: u32 = 9;
+
+/// [`tiny_model_data`] with a byte-level tokenizer of [`TEXT_TOKENS`], of the same size.
+fn model_data() -> ModelData {
+    let mut vocab = json!({});
+    for (id, token) in TEXT_TOKENS.iter().chain(SPECIAL_TOKENS).enumerate() {
+        vocab[token] = json!(id);
+    }
+    let added_tokens: Vec<_> = SPECIAL_TOKENS
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            json!({
+                "id": TEXT_TOKENS.len() + i,
+                "content": token,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            })
+        })
+        .collect();
+    let byte_level =
+        json!({ "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true });
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": byte_level,
+        "post_processor": null,
+        "decoder": byte_level,
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "vocab": vocab,
+            "merges": [],
+        },
+    });
+    ModelData {
+        tokenizer: serde_json::to_vec(&tokenizer).unwrap(),
+        ..tiny_model_data()
+    }
+}
+
+/// 40 seconds decoded in windows overlapping by 5 seconds: the first window ends in the middle
+/// of ` boundary`, the second one starts with the word before it. The tokens of the first
+/// window are sampled at probability `first_prob`, those of the second one at 1.
+fn run(first_prob: f32) -> TranscriptionOutput {
+    let mut decoder = Decoder::load(model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            overlap_seconds: 5.,
+            no_speech_threshold: None,
+            compression_ratio_threshold: None,
+            logprob_threshold: None,
+            hallucination: HallucinationOptions {
+                drop: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            let (script, prob): (&[u32], f32) = if context.segment_start < 1. {
+                (
+                    &[HELLO, WORLD, THE, SOUND, OF, A, SINE, WAVE, BOUND],
+                    first_prob,
+                )
+            } else {
+                (&[WAVE, BOUND, ARY, HELLO, WORLD, THE], 1.)
+            };
+            logits.fill(f32::NEG_INFINITY);
+            match script.get(context.step) {
+                Some(&token) => {
+                    logits[token as usize] = 0.;
+                    if prob < 1. {
+                        // The period holds the rest.
+                        logits[10] = ((1. - prob) / prob).ln();
+                    }
+                }
+                None => logits[EOT as usize] = 0.,
+            }
+        },
+    )));
+    let pcm: Vec<f32> = (0..40 * 16000)
+        .map(|i| (i as f32 * 0.05).sin() * 0.5)
+        .collect();
+    let opts = RunOptions {
+        timestamps: Some(false),
+        ..Default::default()
+    };
+    decoder.run_pcm(&pcm, &opts).unwrap()
+}
+
+fn texts(output: &TranscriptionOutput) -> Vec<&str> {
+    output
+        .segments
+        .iter()
+        .map(|segment| segment.dr.text.trim())
+        .collect()
+}
+
+#[test]
+fn boundary_word_is_kept_whole_and_once() {
+    let output = run(1.);
+    let texts = texts(&output);
+    assert_eq!(
+        texts,
+        [
+            "hello world the sound of a sine wave",
+            "boundary hello world the"
+        ]
+    );
+    // The windows are cut after the last common word, the tokens follow the text.
+    let [first, second] = &output.segments[..] else {
+        panic!("{:?}", output.segments)
+    };
+    assert!(!first.dr.tokens.contains(&BOUND));
+    assert_eq!(
+        second
+            .dr
+            .tokens
+            .iter()
+            .filter(|&&t| t < EOT)
+            .collect::<Vec<_>>(),
+        [&BOUND, &ARY, &HELLO, &WORLD, &THE]
+    );
+
+    // Increasing times without overlap, the cut falling within the overlap.
+    assert_eq!(first.start, 0.);
+    let cut = first.start + first.duration;
+    assert!((25. ..=30.).contains(&cut), "{cut}");
+    assert!((second.start - cut).abs() < 1e-9);
+    assert!(second.start + second.duration <= 40. + 1e-9);
+}
+
+#[test]
+fn common_words_are_kept_from_the_more_probable_window() {
+    let output = run(0.6);
+    assert_eq!(
+        texts(&output),
+        [
+            "hello world the sound of a sine",
+            "wave boundary hello world the"
+        ]
+    );
+    let cut = output.segments[1].start;
+    assert!((25. ..=30.).contains(&cut), "{cut}");
+    assert!((output.segments[0].duration - cut).abs() < 1e-9);
+}
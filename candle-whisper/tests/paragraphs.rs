@@ -0,0 +1,143 @@
+use candle_whisper::{
+    alignment::WordTiming,
+    fixtures,
+    logic::Segment,
+    paragraphs::{paragraphs, to_markdown, Paragraph, ParagraphOptions},
+};
+
+fn segment(id: usize, start: f64, end: f64, text: &str) -> Segment {
+    fixtures::segment(id, start, end - start, text)
+}
+
+/// Segments following each other without pauses.
+fn contiguous(texts: &[&str]) -> Vec<Segment> {
+    texts
+        .iter()
+        .enumerate()
+        .map(|(id, text)| segment(id, 2. * id as f64, 2. * (id + 1) as f64, text))
+        .collect()
+}
+
+fn texts(paragraphs: &[Paragraph]) -> Vec<&str> {
+    paragraphs.iter().map(|p| p.text.as_str()).collect()
+}
+
+#[test]
+fn pauses_and_speaker_changes_start_paragraphs() {
+    let mut segments = vec![
+        segment(0, 0., 2., " Hello there."),
+        segment(1, 2.5, 4., " How are you"),
+        segment(2, 8., 10., " Fine, thanks."),
+        segment(3, 10., 12., " And you?"),
+        segment(4, 12., 14., " Great."),
+    ];
+    segments[4].speaker_change_score = 3.;
+    let split = paragraphs(&segments, &ParagraphOptions::default());
+    assert_eq!(
+        texts(&split),
+        [
+            "Hello there. How are you",
+            "Fine, thanks. And you?",
+            "Great."
+        ]
+    );
+    assert_eq!(split[0].segment_ids, [0, 1]);
+    assert_eq!((split[1].start, split[1].end), (8., 12.));
+    assert_eq!(
+        to_markdown(&split[..2], true),
+        "**[00:00]** Hello there. How are you\n\n**[00:08]** Fine, thanks. And you?\n"
+    );
+    assert_eq!(to_markdown(&split[2..], false), "Great.\n");
+
+    let options = ParagraphOptions {
+        min_gap_seconds: 5.,
+        speaker_change_threshold: None,
+        ..Default::default()
+    };
+    assert_eq!(paragraphs(&segments, &options).len(), 1);
+    let late = [segment(0, 3725., 3730., " Still here.")];
+    assert_eq!(
+        to_markdown(&paragraphs(&late, &options), true),
+        "**[1:02:05]** Still here.\n"
+    );
+}
+
+#[test]
+fn sentence_count_splits_without_pauses() {
+    let options = ParagraphOptions {
+        max_sentences: 3,
+        ..Default::default()
+    };
+    let segments = contiguous(&[
+        " One.",
+        " Two!",
+        " I met Dr.",
+        " Smith and J.",
+        " Doe, e.g. at the station.",
+        " Four. Five. Six",
+        " continues.",
+        " Seven?",
+    ]);
+    let split = paragraphs(&segments, &options);
+    assert_eq!(
+        texts(&split),
+        [
+            "One. Two! I met Dr. Smith and J. Doe, e.g. at the station.",
+            "Four. Five. Six continues.",
+            "Seven?"
+        ]
+    );
+    assert_eq!(split[1].segment_ids, [5, 6]);
+
+    // With word timings, the paragraphs are cut inside the segments.
+    let mut segment = segment(0, 0., 6., " Yes. No. Maybe. Fine.");
+    segment.words = ["Yes.", "No.", "Maybe.", "Fine."]
+        .iter()
+        .enumerate()
+        .map(|(i, word)| WordTiming {
+            word: format!(" {word}"),
+            start: i as f64,
+            end: i as f64 + 0.5,
+            timing_source: Default::default(),
+            probability: None,
+        })
+        .collect();
+    let options = ParagraphOptions {
+        max_sentences: 2,
+        ..Default::default()
+    };
+    let split = paragraphs(&[segment], &options);
+    assert_eq!(texts(&split), ["Yes. No.", "Maybe. Fine."]);
+    assert_eq!((split[1].start, split[1].end), (2., 3.5));
+    assert_eq!(split[1].segment_ids, [0]);
+}
+
+#[test]
+fn cjk_sentences_are_counted_and_joined_without_spaces() {
+    let options = ParagraphOptions {
+        max_sentences: 2,
+        ..Default::default()
+    };
+    let segments = contiguous(&[
+        "今日は晴れです。",
+        "明日は「雨」です！",
+        "そうですか？",
+        "はい。",
+    ]);
+    let split = paragraphs(&segments, &options);
+    assert_eq!(
+        texts(&split),
+        ["今日は晴れです。明日は「雨」です！", "そうですか？はい。"]
+    );
+    // A run of marks ends one sentence.
+    let segments = contiguous(&["本当？！それは。", "よかった。"]);
+    assert_eq!(
+        texts(&paragraphs(&segments, &options)),
+        ["本当？！それは。", "よかった。"]
+    );
+    let segments = contiguous(&["本当？！", "それは", "よかった。"]);
+    assert_eq!(
+        texts(&paragraphs(&segments, &options)),
+        ["本当？！それはよかった。"]
+    );
+}
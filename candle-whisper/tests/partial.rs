@@ -0,0 +1,126 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data, tiny_tokenizer_json, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, LogitsContext, ModelData, PartialHypothesis, RunOptions},
+};
+use serde_json::json;
+use std::{cell::RefCell, rc::Rc};
+
+/// Byte-level text tokens replacing the ones of [`TEXT_TOKENS`], `é` being `Ã` followed by `©`
+/// and `中` being `ä`, `¸` and `Ń`.
+const BYTE_LEVEL_TOKENS: [&str; 11] = [
+    "Ġcaf", "Ã", "©", "Ġ", "ä", "¸", "Ń", "Ġhello", "Ġworld", ".", "a",
+];
+/// ` café 中 hello world.`, within the 12 tokens sampled at most by the tiny model.
+const SCRIPT: [u32; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+/// [`tiny_tokenizer_json`] made byte-level with the text tokens of [`BYTE_LEVEL_TOKENS`].
+fn byte_level_tokenizer_json() -> Vec<u8> {
+    let mut tokenizer: serde_json::Value = serde_json::from_slice(&tiny_tokenizer_json()).unwrap();
+    let mut vocab: serde_json::Map<_, _> = tokenizer["model"]["vocab"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .filter(|(_, id)| id.as_u64().unwrap() >= EOT as u64)
+        .map(|(token, id)| (token.clone(), id.clone()))
+        .collect();
+    for (id, token) in BYTE_LEVEL_TOKENS.iter().enumerate() {
+        vocab.insert(token.to_string(), json!(id));
+    }
+    let byte_level =
+        json!({ "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true });
+    tokenizer["pre_tokenizer"] = byte_level.clone();
+    tokenizer["decoder"] = byte_level;
+    tokenizer["model"] = json!({
+        "type": "BPE",
+        "dropout": null,
+        "unk_token": null,
+        "continuing_subword_prefix": null,
+        "end_of_word_suffix": null,
+        "fuse_unk": false,
+        "vocab": vocab,
+        "merges": [],
+    });
+    serde_json::to_vec(&tokenizer).unwrap()
+}
+
+/// Decodes 10 seconds of audio forced to [`SCRIPT`], returns the partial hypotheses and the
+/// final text.
+fn run(partial_interval_tokens: usize) -> (Vec<PartialHypothesis>, String) {
+    let data = ModelData {
+        tokenizer: byte_level_tokenizer_json(),
+        ..tiny_model_data()
+    };
+    let mut decoder = Decoder::load(data).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            partial_interval_tokens,
+            no_speech_threshold: None,
+            compression_ratio_threshold: None,
+            hallucination: HallucinationOptions {
+                similarity_threshold: 2.,
+                max_ngram_coverage: 1.,
+                blocklist: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    decoder.set_logits_processor(Some(Box::new(
+        |logits: &mut [f32], context: &LogitsContext| {
+            let forced = SCRIPT.get(context.step).copied().unwrap_or(EOT);
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if token as u32 != forced {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        },
+    )));
+    let partials = Rc::new(RefCell::new(vec![]));
+    let received = partials.clone();
+    decoder.set_on_partial(Some(Box::new(move |partial| {
+        received.borrow_mut().push(partial)
+    })));
+    let output = decoder
+        .run_pcm(&sine_pcm(10., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.segments.len(), 1);
+    let text = output.segments[0].dr.text.clone();
+    let partials = partials.borrow().clone();
+    (partials, text)
+}
+
+#[test]
+fn partials_follow_the_interval() {
+    let (partials, text) = run(5);
+    assert_eq!(text, " café 中 hello world.");
+    let steps: Vec<usize> = partials.iter().map(|p| p.step).collect();
+    assert_eq!(steps, [5, 10]);
+    for partial in &partials {
+        assert_eq!(partial.segment_start, 0.);
+        assert_eq!(partial.tokens, SCRIPT[..partial.step]);
+        assert_eq!(partial.temperature, 0.);
+    }
+    // The 5 first tokens end inside `中`.
+    assert_eq!(partials[0].text, " café ");
+    assert_eq!(partials[1].text, text);
+
+    // The final token is never reported as a partial.
+    let (partials, _) = run(SCRIPT.len() + 1);
+    assert!(partials.is_empty());
+}
+
+#[test]
+fn partial_texts_are_valid_prefixes_of_the_final_text() {
+    let (partials, text) = run(1);
+    assert_eq!(partials.len(), SCRIPT.len());
+    let mut previous = String::new();
+    for partial in &partials {
+        assert!(!partial.text.contains(char::REPLACEMENT_CHARACTER));
+        assert!(text.starts_with(&partial.text), "{:?}", partial.text);
+        assert!(partial.text.starts_with(&previous), "{:?}", partial.text);
+        previous = partial.text.clone();
+    }
+    assert_eq!(previous, text);
+}
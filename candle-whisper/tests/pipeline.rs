@@ -0,0 +1,90 @@
+use candle_whisper::{
+    builder::DecoderBuilder,
+    fixtures::{sine_pcm, sine_wav, tiny_config, tiny_model_data, CapturingLogger},
+    logging::Level,
+    logic::{m, Decoder, Segment, TranscriptionOutput},
+};
+use std::rc::Rc;
+
+#[test]
+fn tiny_model_transcribes_a_sine_wave() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let output = decoder.convert_and_run(&sine_wav(5., 440.)).unwrap();
+    let max_tokens = tiny_config().max_target_positions + 1;
+    let mut end = 0.;
+    for segment in output.segments.iter() {
+        assert!(segment.dr.tokens.len() <= max_tokens);
+        assert!(segment.start >= end && segment.duration > 0.);
+        end = segment.start + segment.duration;
+    }
+    assert!(end <= m::CHUNK_LENGTH as f64);
+
+    let json = serde_json::to_string(&output).unwrap();
+    let parsed: TranscriptionOutput = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.segments.len(), output.segments.len());
+    for (a, b) in parsed.segments.iter().zip(output.segments.iter()) {
+        assert_eq!(a.dr.tokens, b.dr.tokens);
+        assert_eq!(a.dr.text, b.dr.text);
+        assert_eq!(a.start, b.start);
+    }
+}
+
+#[test]
+fn tiny_model_runs_are_deterministic() {
+    let wav = sine_wav(3., 220.);
+    let mut a = Decoder::load(tiny_model_data()).unwrap();
+    let mut b = Decoder::load(tiny_model_data()).unwrap();
+    let tokens = |output: TranscriptionOutput| -> Vec<Vec<u32>> {
+        output
+            .segments
+            .into_iter()
+            .map(|s: Segment| s.dr.tokens)
+            .collect()
+    };
+    assert_eq!(
+        tokens(a.convert_and_run(&wav).unwrap()),
+        tokens(b.convert_and_run(&wav).unwrap())
+    );
+}
+
+#[test]
+fn encoder_features_have_one_window_per_30_seconds() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let config = tiny_config();
+    let features = decoder.encode_audio(&sine_pcm(35., 440.)).unwrap();
+    assert_eq!(
+        features.dims(),
+        &[2, config.max_source_positions, config.d_model]
+    );
+    let embedding = decoder.embedding(&sine_pcm(10., 440.)).unwrap();
+    assert_eq!(embedding.dims(), &[1, config.d_model]);
+}
+
+#[test]
+fn messages_go_to_the_configured_logger() {
+    let logger = CapturingLogger::default();
+    let mut decoder = DecoderBuilder::from(tiny_model_data())
+        .logger(Rc::new(logger.clone()))
+        .build()
+        .unwrap();
+    assert!(logger.contains(Level::Info, "model loaded"));
+    decoder.convert_and_run(&sine_wav(1., 440.)).unwrap();
+    assert!(logger.contains(Level::Debug, "loaded mel: [1, 80, 3000]"));
+    assert!(logger
+        .messages()
+        .iter()
+        .all(|(level, _)| *level < Level::Warn));
+}
+
+#[test]
+fn segments_stop_at_the_end_of_the_audio() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let output = decoder.convert_and_run(&sine_wav(35., 440.)).unwrap();
+    for segment in output.segments.iter() {
+        assert!(segment.start < 35.);
+        assert!(segment.start + segment.duration <= 35. + 1e-6);
+    }
+    if let Some(last) = output.segments.iter().find(|s| s.start >= 30.) {
+        assert!((last.duration - 5.).abs() < 0.1, "{}", last.duration);
+    }
+}
@@ -0,0 +1,91 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{Decoder, RunOptions, RunOutcome, TranscriptionOutput},
+    preemption::PreemptionControl,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Frames of a 30-second window.
+const N_FRAMES: usize = 3000;
+
+fn completed(outcome: RunOutcome) -> TranscriptionOutput {
+    match outcome {
+        RunOutcome::Completed(output) => output,
+        RunOutcome::Preempted(checkpoint, _) => panic!("preempted at {}", checkpoint.seek_frame),
+    }
+}
+
+#[test]
+fn preempted_run_resumes_after_another_job() {
+    // Five windows.
+    let pcm = sine_pcm(140., 440.);
+    let opts = RunOptions::default();
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let mel = decoder.compute_mel(&pcm).unwrap();
+    let uninterrupted = completed(
+        decoder
+            .run_preemptible(&mel, &opts, &PreemptionControl::new())
+            .unwrap(),
+    );
+    assert!(uninterrupted.segments.len() >= 5);
+
+    // The flag is polled after every window, it is raised at the second.
+    let polls = Arc::new(AtomicUsize::new(0));
+    let ctl = PreemptionControl::with_external({
+        let polls = polls.clone();
+        move || polls.fetch_add(1, Ordering::SeqCst) + 1 == 2
+    });
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let (checkpoint, segments) = match decoder.run_preemptible(&mel, &opts, &ctl).unwrap() {
+        RunOutcome::Preempted(checkpoint, segments) => (checkpoint, segments),
+        RunOutcome::Completed(_) => panic!("not preempted"),
+    };
+    assert_eq!(checkpoint.seek_frame, 2 * N_FRAMES);
+    assert_eq!(segments.len(), checkpoint.segments.len());
+    assert!(segments.iter().all(|segment| segment.start < 60.));
+    assert_eq!(segments[0].dr.tokens, uninterrupted.segments[0].dr.tokens);
+
+    // The interactive job runs on the same decoder.
+    let interactive = decoder
+        .run_pcm(&sine_pcm(10., 880.), &RunOptions::default())
+        .unwrap();
+    assert!(!interactive.segments.is_empty());
+
+    let resumed = completed(
+        decoder
+            .resume_preemptible(&mel, &opts, checkpoint, &ctl)
+            .unwrap(),
+    );
+    assert_eq!(
+        serde_json::to_value(&resumed).unwrap(),
+        serde_json::to_value(&uninterrupted).unwrap()
+    );
+}
+
+#[test]
+fn control_is_raised_from_another_thread() {
+    let ctl = PreemptionControl::new();
+    let remote = ctl.clone();
+    std::thread::spawn(move || remote.request()).join().unwrap();
+    assert!(ctl.is_requested());
+
+    // A request made before the run stops it after its first window and is consumed.
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let mel = decoder.compute_mel(&sine_pcm(40., 440.)).unwrap();
+    let opts = RunOptions::default();
+    let checkpoint = match decoder.run_preemptible(&mel, &opts, &ctl).unwrap() {
+        RunOutcome::Preempted(checkpoint, _) => checkpoint,
+        RunOutcome::Completed(_) => panic!("not preempted"),
+    };
+    assert_eq!(checkpoint.seek_frame, N_FRAMES);
+    assert!(!ctl.is_requested());
+    let output = completed(
+        decoder
+            .resume_preemptible(&mel, &opts, checkpoint, &ctl)
+            .unwrap(),
+    );
+    assert!(!output.segments.is_empty());
+}
@@ -0,0 +1,118 @@
+use candle_whisper::{
+    audio::{normalize_peak, normalize_rms, pre_emphasis, AudioPreprocess, Normalization},
+    fixtures::sine_pcm,
+};
+
+fn mean(pcm: &[f32]) -> f32 {
+    pcm.iter().sum::<f32>() / pcm.len() as f32
+}
+
+fn rms(pcm: &[f32]) -> f32 {
+    (pcm.iter().map(|v| v * v).sum::<f32>() / pcm.len() as f32).sqrt()
+}
+
+fn peak(pcm: &[f32]) -> f32 {
+    pcm.iter().fold(0f32, |max, v| f32::max(max, v.abs()))
+}
+
+fn dbfs(v: f32) -> f32 {
+    20. * v.log10()
+}
+
+#[test]
+fn disabled_preprocessing_borrows_the_samples() {
+    let pcm = sine_pcm(0.1, 440.);
+    let preprocess = AudioPreprocess::default();
+    assert!(!preprocess.is_enabled());
+    assert!(matches!(
+        preprocess.apply(&pcm),
+        std::borrow::Cow::Borrowed(_)
+    ));
+}
+
+#[test]
+fn dc_offset_is_removed() {
+    let tone = sine_pcm(1., 440.);
+    let biased: Vec<f32> = tone.iter().map(|v| v * 0.5 + 0.3).collect();
+    assert!((mean(&biased) - 0.3).abs() < 1e-3);
+    let preprocess = AudioPreprocess {
+        remove_dc_offset: true,
+        ..Default::default()
+    };
+    let centered = preprocess.apply(&biased);
+    assert!(mean(&centered).abs() < 1e-6, "{}", mean(&centered));
+    // Only the offset is removed.
+    for (centered, tone) in centered.iter().zip(&tone) {
+        assert!((centered - tone * 0.5).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn peak_normalization_reaches_the_target() {
+    let quiet: Vec<f32> = sine_pcm(1., 440.).iter().map(|v| v * 0.02).collect();
+    let preprocess = AudioPreprocess {
+        normalization: Some(Normalization::Peak),
+        target_dbfs: -6.,
+        ..Default::default()
+    };
+    let normalized = preprocess.apply(&quiet);
+    assert!((dbfs(peak(&normalized)) - -6.).abs() < 0.01);
+
+    // A target above the ceiling is limited to it.
+    let preprocess = AudioPreprocess {
+        target_dbfs: 0.,
+        ceiling_dbfs: -1.,
+        ..preprocess
+    };
+    let normalized = preprocess.apply(&quiet);
+    assert!((dbfs(peak(&normalized)) - -1.).abs() < 0.01);
+
+    // Silence is left as is.
+    let mut silence = vec![0.; 100];
+    normalize_peak(&mut silence, -3.);
+    assert!(silence.iter().all(|&v| v == 0.));
+}
+
+#[test]
+fn rms_normalization_is_limited_by_the_ceiling() {
+    let tone: Vec<f32> = sine_pcm(1., 440.).iter().map(|v| v * 0.02).collect();
+    let preprocess = AudioPreprocess {
+        normalization: Some(Normalization::Rms),
+        target_dbfs: -20.,
+        ceiling_dbfs: -1.,
+        ..Default::default()
+    };
+    let normalized = preprocess.apply(&tone);
+    assert!((dbfs(rms(&normalized)) - -20.).abs() < 0.01);
+
+    // A click makes the peak reach the ceiling before the RMS reaches the target.
+    let mut clicked = tone.clone();
+    clicked[100] = 0.5;
+    let mut normalized = clicked.clone();
+    normalize_rms(&mut normalized, -20., -1.);
+    assert!((dbfs(peak(&normalized)) - -1.).abs() < 0.01);
+    assert!(dbfs(rms(&normalized)) < -20.5);
+
+    let mut silence = vec![0.; 100];
+    normalize_rms(&mut silence, -20., -1.);
+    assert!(silence.iter().all(|&v| v == 0.));
+}
+
+#[test]
+fn pre_emphasis_follows_its_difference_equation() {
+    let mut pcm = vec![1., 0.5, -0.25, 0., 2.];
+    pre_emphasis(&mut pcm, 0.97);
+    let expected = [1., 0.5 - 0.97, -0.25 - 0.97 * 0.5, 0.97 * 0.25, 2.];
+    for (v, expected) in pcm.iter().zip(expected) {
+        assert!((v - expected).abs() < 1e-6, "{pcm:?}");
+    }
+
+    // Through the preprocessing, the DC is almost removed.
+    let preprocess = AudioPreprocess {
+        pre_emphasis: Some(0.97),
+        ..Default::default()
+    };
+    let dc = preprocess.apply(&[0.5; 100]);
+    assert_eq!(dc[0], 0.5);
+    assert!(dc[1..].iter().all(|v| (v - 0.015).abs() < 1e-6));
+}
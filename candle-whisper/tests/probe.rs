@@ -0,0 +1,178 @@
+use candle_whisper::{
+    audio::{probe_wav, AudioInfo},
+    error::WhisperError,
+    fixtures::{fmt_chunk, riff_wav, sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+    timings::Timings,
+};
+
+/// WAV file written by hound of `frames` frames of `channels` channels, the samples given by
+/// `sample` from the frame and channel indices.
+fn wav(spec: hound::WavSpec, frames: usize, sample: impl Fn(usize, usize) -> f32) -> Vec<u8> {
+    let mut bytes = std::io::Cursor::new(vec![]);
+    let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+    for i in 0..frames {
+        for channel in 0..spec.channels as usize {
+            let v = sample(i, channel);
+            match spec.sample_format {
+                hound::SampleFormat::Float => writer.write_sample(v).unwrap(),
+                hound::SampleFormat::Int => {
+                    let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                    let v = (v * full_scale).clamp(-full_scale, full_scale - 1.);
+                    writer.write_sample(v as i32).unwrap()
+                }
+            }
+        }
+    }
+    writer.finalize().unwrap();
+    bytes.into_inner()
+}
+
+fn spec(channels: u16, sample_rate: u32, bits: u16, format: hound::SampleFormat) -> hound::WavSpec {
+    hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: bits,
+        sample_format: format,
+    }
+}
+
+fn unsupported(result: Result<AudioInfo, WhisperError>) -> String {
+    match result {
+        Err(WhisperError::UnsupportedAudio { reason }) => reason,
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+#[test]
+fn header_and_levels_are_reported() {
+    // A half scale stereo tone with two samples clipped on the right channel.
+    let bytes = wav(
+        spec(2, 22050, 16, hound::SampleFormat::Int),
+        44100,
+        |i, channel| match (channel, i) {
+            (1, 100 | 200) => 1.,
+            _ => 0.5 * (i as f32 * 0.1).sin().signum(),
+        },
+    );
+    let info = probe_wav(&bytes).unwrap();
+    assert_eq!(
+        (info.sample_rate, info.channels, info.frames),
+        (22050, 2, 44100)
+    );
+    assert_eq!(
+        (info.bits_per_sample, info.sample_format.as_str()),
+        (16, "int")
+    );
+    assert_eq!(info.duration_secs, 2.);
+    assert!((info.peak_dbfs - 20. * (32767f32 / 32768.).log10()).abs() < 1e-4);
+    assert_eq!(info.clipped_fraction, 2. / 88200.);
+
+    let bytes = wav(
+        spec(1, 16000, 32, hound::SampleFormat::Float),
+        8000,
+        |_, _| 0.25,
+    );
+    let info = probe_wav(&bytes).unwrap();
+    assert_eq!(
+        (info.bits_per_sample, info.sample_format.as_str()),
+        (32, "float")
+    );
+    assert_eq!(info.duration_secs, 0.5);
+    assert!((info.peak_dbfs - 20. * 0.25f32.log10()).abs() < 1e-4);
+    assert_eq!(info.clipped_fraction, 0.);
+
+    let bytes = wav(spec(1, 8000, 24, hound::SampleFormat::Int), 800, |_, _| 0.);
+    let info = probe_wav(&bytes).unwrap();
+    assert_eq!((info.bits_per_sample, info.frames), (24, 800));
+    assert_eq!(info.peak_dbfs, f32::NEG_INFINITY);
+}
+
+#[test]
+fn unusual_chunk_orders_are_probed() {
+    let samples: Vec<u8> = sine_pcm(1., 440.)
+        .iter()
+        .flat_map(|v| ((v * 32767.) as i16).to_le_bytes())
+        .collect();
+    let fmt = fmt_chunk(1, 1, 16000, 16);
+    for chunks in [
+        vec![(b"fmt ", &fmt[..]), (b"data", &samples[..])],
+        // Metadata first, then the samples before their format.
+        vec![
+            (b"LIST", &b"INFOISFT"[..]),
+            (b"data", &samples[..]),
+            (b"fmt ", &fmt[..]),
+        ],
+        vec![
+            (b"fmt ", &fmt[..]),
+            (b"fact", &[0u8; 5][..]),
+            (b"data", &samples[..]),
+        ],
+    ] {
+        let info = probe_wav(&riff_wav(&chunks)).unwrap();
+        assert_eq!(
+            (info.sample_rate, info.channels, info.frames),
+            (16000, 1, 16000)
+        );
+        assert!(
+            (info.peak_dbfs - 20. * 0.5f32.log10()).abs() < 0.01,
+            "{info:?}"
+        );
+    }
+}
+
+#[test]
+fn empty_and_malformed_files_are_rejected() {
+    assert_eq!(unsupported(probe_wav(&[])), "the file is empty");
+    let no_samples = wav(spec(1, 16000, 16, hound::SampleFormat::Int), 0, |_, _| 0.);
+    assert_eq!(
+        unsupported(probe_wav(&no_samples)),
+        "the file has no samples"
+    );
+    assert_eq!(
+        unsupported(probe_wav(b"ID3\x04 not a wav file")),
+        "not a RIFF/WAVE file"
+    );
+    let no_data = riff_wav(&[(b"fmt ", &fmt_chunk(1, 1, 16000, 16))]);
+    assert_eq!(unsupported(probe_wav(&no_data)), "no data chunk");
+    let truncated_fmt = riff_wav(&[(b"fmt ", &[1, 0, 1, 0]), (b"data", &[0; 4])]);
+    assert_eq!(
+        unsupported(probe_wav(&truncated_fmt)),
+        "truncated fmt chunk"
+    );
+}
+
+#[test]
+fn runtime_estimate_is_calibrated_from_the_timings() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    // Before any calibration, from the size of the weights.
+    let uncalibrated = decoder.estimate_runtime(60.);
+    assert!(uncalibrated > 0.);
+    assert_eq!(decoder.estimate_runtime(120.), 2. * uncalibrated);
+
+    let timings = Timings {
+        total_ms: 2000.,
+        ..Default::default()
+    };
+    decoder.calibrate_runtime(&timings, 10.);
+    assert_eq!(decoder.estimate_runtime(60.), 12.);
+    // Timings without duration or of no audio leave the calibration as is.
+    decoder.calibrate_runtime(&Timings::default(), 10.);
+    decoder.calibrate_runtime(&timings, 0.);
+    assert_eq!(decoder.estimate_runtime(60.), 12.);
+
+    // From the timings of an actual run.
+    decoder
+        .set_options(DecodeOptions {
+            collect_timings: true,
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    let timings = output.timings.unwrap();
+    decoder.calibrate_runtime(&timings, 5.);
+    let expected = timings.total_ms / 1000. * 6.;
+    assert!((decoder.estimate_runtime(30.) - expected).abs() < 1e-9);
+}
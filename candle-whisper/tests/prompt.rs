@@ -0,0 +1,128 @@
+use candle_whisper::{
+    fixtures::{multilingual_model_data, sine_pcm, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{Config, DecodeOptions, Decoder, DecodingResult, RunOptions},
+    prompt::{char_starts, PromptBuffer},
+};
+use serde_json::json;
+use tokenizers::Tokenizer;
+
+const EOT: u32 = 100;
+
+fn result(tokens: &[u32], temperature: f64) -> DecodingResult {
+    serde_json::from_value(json!({
+        "tokens": tokens,
+        "text": "",
+        "avg_logprob": 0.,
+        "no_speech_prob": 0.,
+        "temperature": temperature,
+        "compression_ratio": null,
+    }))
+    .unwrap()
+}
+
+fn buffer(max_tokens: usize) -> PromptBuffer {
+    PromptBuffer::new(max_tokens, EOT, vec![true; EOT as usize].into())
+}
+
+#[test]
+fn oldest_tokens_are_dropped_on_overflow() {
+    let mut prompt = buffer(5);
+    prompt.push_segment(&result(&[101, 1, 2, 3, EOT], 0.));
+    assert_eq!(prompt.as_prompt_tokens(), [1, 2, 3]);
+    prompt.push_segment(&result(&[101, 4, 5, 6, EOT], 0.2));
+    assert_eq!(prompt.as_prompt_tokens(), [2, 3, 4, 5, 6]);
+    prompt.set_max_tokens(2);
+    assert_eq!(prompt.as_prompt_tokens(), [5, 6]);
+    // A window longer than the budget only keeps its end.
+    prompt.push_segment(&result(&[7, 8, 9, 10], 0.));
+    assert_eq!(prompt.as_prompt_tokens(), [9, 10]);
+}
+
+#[test]
+fn high_temperature_results_reset_the_prompt() {
+    let mut prompt = buffer(10);
+    prompt.push_segment(&result(&[1, 2, 3], 0.4));
+    prompt.push_segment(&result(&[4, 5, 6], 0.5));
+    assert_eq!(prompt.len(), 6);
+    prompt.push_segment(&result(&[7, 8, 9], 0.6));
+    assert!(prompt.is_empty());
+    prompt.push_segment(&result(&[7], 0.));
+    assert_eq!(prompt.as_prompt_tokens(), [7]);
+}
+
+/// Byte-level BPE tokenizer whose tokens are single bytes, `é` being `Ã` followed by `©`.
+fn byte_level_tokenizer() -> Tokenizer {
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": { "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true },
+        "post_processor": null,
+        "decoder": { "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true },
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "vocab": { "a": 0, "Ã": 1, "©": 2, "Ġ": 3, "ä": 4, "¸": 5, "Ń": 6 },
+            "merges": [],
+        },
+    });
+    Tokenizer::from_bytes(serde_json::to_vec(&tokenizer).unwrap()).unwrap()
+}
+
+#[test]
+fn truncation_does_not_split_characters() {
+    let tokenizer = byte_level_tokenizer();
+    let starts = char_starts(&tokenizer);
+    // `Ń` stands for the non printable byte 0xad.
+    assert_eq!(starts, [true, true, false, true, true, false, false]);
+
+    // "aé 中" is `a Ã © Ġ ä ¸ Ń`.
+    let tokens = [0, 1, 2, 3, 4, 5, 6];
+    assert_eq!(tokenizer.decode(&tokens, false).unwrap(), "aé 中");
+    for (max_tokens, expected) in [(6, "é 中"), (5, " 中"), (4, " 中"), (2, ""), (1, "")] {
+        let mut prompt = PromptBuffer::new(max_tokens, 7, starts.clone().into());
+        prompt.push_segment(&result(&tokens, 0.));
+        let text = tokenizer.decode(prompt.as_prompt_tokens(), false).unwrap();
+        assert_eq!(text, expected, "max tokens {max_tokens}");
+    }
+}
+
+#[test]
+fn windows_record_the_length_of_their_prompt() {
+    let data = multilingual_model_data();
+    let vocab_size = serde_json::from_slice::<Config>(&data.config)
+        .unwrap()
+        .vocab_size as u32;
+    let mut decoder = Decoder::load(data).unwrap();
+    // Only the text tokens and EOT can be sampled.
+    let eot = TEXT_TOKENS.len() as u32;
+    decoder
+        .set_options(DecodeOptions {
+            condition_on_previous_text: true,
+            max_prompt_tokens: Some(4),
+            extra_suppress_tokens: (eot + 1..vocab_size).collect(),
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                similarity_threshold: 2.,
+                max_ngram_coverage: 1.,
+                blocklist: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(35., 440.), &RunOptions::default())
+        .unwrap();
+    let prompts: Vec<usize> = output.segments.iter().map(|s| s.prompt_tokens).collect();
+    assert_eq!(prompts.len(), 2);
+    assert_eq!(prompts[0], 0);
+    assert!(prompts[1] > 0 && prompts[1] <= 4, "prompts {prompts:?}");
+}
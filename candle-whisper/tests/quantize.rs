@@ -0,0 +1,63 @@
+use candle_core::quantized::GgmlDType;
+use candle_whisper::{
+    builder::DecoderBuilder,
+    error::WhisperError,
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, ModelData, RunOptions},
+};
+
+fn tokens(decoder: &mut Decoder) -> Vec<Vec<u32>> {
+    decoder
+        .set_options(DecodeOptions {
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap();
+    output
+        .segments
+        .into_iter()
+        .map(|segment| segment.dr.tokens)
+        .collect()
+}
+
+#[test]
+fn q8_0_model_transcribes_as_the_f32_one() {
+    let mut f32_model = Decoder::load(tiny_model_data()).unwrap();
+    let mut q8_model = DecoderBuilder::from(tiny_model_data())
+        .quantize_on_load(Some(GgmlDType::Q8_0))
+        .build()
+        .unwrap();
+    let expected = tokens(&mut f32_model);
+    assert!(!expected.is_empty());
+    assert_eq!(tokens(&mut q8_model), expected);
+
+    let source = f32_model.model_info();
+    let info = q8_model.model_info();
+    assert!(!source.quantized && info.quantized);
+    assert_eq!(info.quantization.as_deref(), Some("Q8_0"));
+    assert_eq!(info.tensor_count, source.tensor_count);
+    assert_eq!(info.source_parameter_bytes, Some(source.parameter_bytes));
+    assert!(info.parameter_bytes < source.parameter_bytes);
+}
+
+#[test]
+fn quantization_is_read_from_the_model_data() {
+    let data = ModelData {
+        quantize_on_load: Some("q5_0".to_string()),
+        ..tiny_model_data()
+    };
+    let decoder = Decoder::load(data).unwrap();
+    assert_eq!(decoder.model_info().quantization.as_deref(), Some("Q5_0"));
+
+    for quantization in ["q9_0", "f32"] {
+        let data = ModelData {
+            quantize_on_load: Some(quantization.to_string()),
+            ..tiny_model_data()
+        };
+        let err = Decoder::load(data).err().unwrap();
+        assert!(matches!(err, WhisperError::InvalidConfig { .. }), "{err}");
+    }
+}
@@ -0,0 +1,72 @@
+//! Single test, the allocator counts the allocations of every thread.
+
+use candle_core::quantized::GgmlDType;
+use candle_whisper::{builder::DecoderBuilder, fixtures::tiny_model_data};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Allocator tracking the peak of the allocated bytes.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(current, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() {
+            let current = CURRENT.fetch_add(new_size - layout.size(), Ordering::Relaxed) + new_size
+                - layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        } else {
+            CURRENT.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+#[test]
+fn quantizing_releases_the_weights_as_it_goes() {
+    let builder =
+        || DecoderBuilder::from(tiny_model_data()).quantize_on_load(Some(GgmlDType::Q8_0));
+    // Initializes the lazy statics of the loading.
+    drop(builder().build().unwrap());
+    let builder = builder();
+    let md = tiny_model_data();
+    let weights = md.weights.len();
+    let largest = safetensors::SafeTensors::deserialize(&md.weights)
+        .unwrap()
+        .tensors()
+        .iter()
+        .map(|(_, view)| view.data().len())
+        .max()
+        .unwrap();
+    drop(md);
+    let before = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let decoder = builder.build().unwrap();
+    // The weights buffer, allocated before, is part of the peak.
+    let peak = PEAK.load(Ordering::Relaxed) - before + weights;
+    // Reading the quantized tensors back from the GGUF buffer briefly takes twice their size,
+    // which for the tiny model whose embedding stays in f32 is more than the largest tensor.
+    assert!(
+        peak < weights + 2 * largest,
+        "peak of {peak} bytes quantizing {weights} bytes of weights"
+    );
+    drop(decoder);
+}
@@ -0,0 +1,142 @@
+use candle_whisper::{
+    audio::{decode_wav, read_riff},
+    error::WhisperError,
+    fixtures::{fmt_chunk, riff_wav},
+};
+
+/// 16-bit samples of the values of `SAMPLES`.
+const SAMPLES: [f32; 6] = [0., 0.5, -0.5, 0.25, -1., 0.75];
+
+fn int16(samples: &[f32]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|v| ((v * 32768.) as i16).to_le_bytes())
+        .collect()
+}
+
+/// Body of a `WAVE_FORMAT_EXTENSIBLE` `fmt ` chunk whose sub-format is `tag`.
+fn extensible_fmt(tag: u16, channels: u16, bits_per_sample: u16) -> Vec<u8> {
+    let mut fmt = fmt_chunk(0xfffe, channels, 16000, bits_per_sample);
+    fmt.extend_from_slice(&22u16.to_le_bytes());
+    // Valid bits and channel mask.
+    fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+    fmt.extend_from_slice(&0u32.to_le_bytes());
+    // The GUID of the sub-format, starting with its tag.
+    fmt.extend_from_slice(&tag.to_le_bytes());
+    fmt.extend_from_slice(&[
+        0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+    ]);
+    fmt
+}
+
+fn unsupported(bytes: &[u8]) -> String {
+    match read_riff(bytes) {
+        Err(WhisperError::UnsupportedAudio { reason }) => reason,
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+fn assert_samples(bytes: &[u8], channels: u16, expected: &[f32]) {
+    let (spec, samples) = read_riff(bytes).unwrap();
+    assert_eq!((spec.channels, spec.sample_rate), (channels, 16000));
+    assert_eq!(samples, expected);
+}
+
+#[test]
+fn chunks_are_found_in_any_order() {
+    let fmt = fmt_chunk(1, 1, 16000, 16);
+    let data = int16(&SAMPLES);
+    for chunks in [
+        [
+            (b"fmt ", &fmt[..]),
+            (b"data", &data[..]),
+            (b"LIST", &b"INFO"[..]),
+        ],
+        [
+            (b"LIST", &b"INFO"[..]),
+            (b"fmt ", &fmt[..]),
+            (b"data", &data[..]),
+        ],
+        [
+            (b"data", &data[..]),
+            (b"junk", &[0; 7][..]),
+            (b"fmt ", &fmt[..]),
+        ],
+    ] {
+        let bytes = riff_wav(&chunks);
+        assert_samples(&bytes, 1, &SAMPLES);
+        // hound reads the files it accepts the same.
+        assert_eq!(decode_wav(&bytes).unwrap().1, SAMPLES);
+    }
+}
+
+#[test]
+fn extensible_formats_use_their_sub_format() {
+    let data = int16(&SAMPLES);
+    let bytes = riff_wav(&[(b"fmt ", &extensible_fmt(1, 2, 16)), (b"data", &data)]);
+    assert_samples(&bytes, 2, &SAMPLES);
+
+    let floats: Vec<u8> = SAMPLES.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let bytes = riff_wav(&[(b"fmt ", &extensible_fmt(3, 1, 32)), (b"data", &floats)]);
+    let (spec, samples) = read_riff(&bytes).unwrap();
+    assert_eq!(spec.sample_format, hound::SampleFormat::Float);
+    assert_eq!(samples, SAMPLES);
+}
+
+#[test]
+fn odd_chunks_are_read_with_or_without_padding() {
+    let fmt = fmt_chunk(1, 1, 16000, 16);
+    let data = int16(&SAMPLES);
+    let padded = riff_wav(&[
+        (b"junk", &[1, 2, 3][..]),
+        (b"fmt ", &fmt[..]),
+        (b"data", &data[..]),
+    ]);
+    assert_samples(&padded, 1, &SAMPLES);
+
+    // The same file with the padding byte after `junk` missing.
+    let mut unpadded = padded.clone();
+    unpadded.remove(12 + 8 + 3);
+    assert_samples(&unpadded, 1, &SAMPLES);
+
+    // An odd-sized data chunk of 8-bit samples, padded at the end of the file.
+    let bytes = riff_wav(&[
+        (b"fmt ", &fmt_chunk(1, 1, 16000, 8)),
+        (b"data", &[128, 192, 64]),
+    ]);
+    assert_samples(&bytes, 1, &[0., 0.5, -0.5]);
+}
+
+#[test]
+fn data_sizes_past_the_end_are_clamped() {
+    let mut bytes = riff_wav(&[
+        (b"fmt ", &fmt_chunk(1, 1, 16000, 16)),
+        (b"data", &int16(&SAMPLES)),
+    ]);
+    // Written by a streaming encoder that never came back to the header.
+    bytes[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_samples(&bytes, 1, &SAMPLES);
+    // A partial last frame is dropped.
+    bytes.push(0x7f);
+    assert_samples(&bytes, 1, &SAMPLES);
+}
+
+#[test]
+fn invalid_formats_are_rejected() {
+    let data = int16(&SAMPLES);
+    // Neither a block alignment nor a sample size.
+    let mut fmt = fmt_chunk(1, 1, 16000, 0);
+    fmt[12..14].copy_from_slice(&0u16.to_le_bytes());
+    let bytes = riff_wav(&[(b"fmt ", &fmt), (b"data", &data)]);
+    assert_eq!(unsupported(&bytes), "invalid fmt chunk");
+
+    let bytes = riff_wav(&[(b"fmt ", &fmt_chunk(1, 0, 16000, 16)), (b"data", &data)]);
+    assert_eq!(unsupported(&bytes), "invalid fmt chunk");
+    let bytes = riff_wav(&[(b"fmt ", &fmt_chunk(2, 1, 16000, 4)), (b"data", &data)]);
+    assert_eq!(
+        unsupported(&bytes),
+        "unsupported format 0x2 with 4 bits per sample"
+    );
+    assert_eq!(unsupported(&riff_wav(&[(b"data", &data)])), "no fmt chunk");
+    assert_eq!(unsupported(b"RIFF\0\0\0\0WAV"), "not a RIFF/WAVE file");
+}
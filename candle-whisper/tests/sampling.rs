@@ -0,0 +1,197 @@
+use candle_whisper::{
+    builder::DecoderBuilder,
+    fixtures::{sine_pcm, tiny_model_data},
+    hallucination::HallucinationOptions,
+    logic::{
+        sample_weights, truncate_distribution, DecodeOptions, Decoder, LogitsContext, RunOptions,
+    },
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+fn truncated(weights: &[f64], top_k: Option<usize>, top_p: Option<f64>) -> Vec<f64> {
+    let mut weights = weights.to_vec();
+    truncate_distribution(&mut weights, top_k, top_p);
+    weights
+}
+
+fn assert_close(actual: &[f64], expected: &[f64]) {
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < 1e-12, "{actual:?} != {expected:?}");
+    }
+}
+
+#[test]
+fn nucleus_ending_on_a_cumulative_boundary_is_kept_whole() {
+    let weights = [0.5, 0.25, 0.125, 0.125];
+    assert_close(
+        &truncated(&weights, None, Some(0.75)),
+        &[2. / 3., 1. / 3., 0., 0.],
+    );
+    // Just past the boundary the next token is needed.
+    let past = truncated(&weights, None, Some(0.76));
+    assert_eq!(past.iter().filter(|&&w| w > 0.).count(), 3);
+    // The weights do not need to be normalized.
+    assert_close(
+        &truncated(&[4., 2., 1., 1.], None, Some(0.75)),
+        &[2. / 3., 1. / 3., 0., 0.],
+    );
+    // The most probable token is always kept.
+    assert_close(&truncated(&weights, None, Some(1e-6)), &[1., 0., 0., 0.]);
+    // Zero weights, the suppressed tokens, stay out of the full nucleus.
+    assert_close(&truncated(&[0.5, 0., 0.5], None, Some(1.)), &[0.5, 0., 0.5]);
+}
+
+#[test]
+fn top_k_larger_than_the_vocabulary_keeps_everything() {
+    let weights = [0.1, 0.4, 0.2, 0.3];
+    assert_close(&truncated(&weights, Some(10), None), &weights);
+    assert_close(&truncated(&weights, Some(4), None), &weights);
+    assert_close(
+        &truncated(&weights, Some(2), None),
+        &[0., 4. / 7., 0., 3. / 7.],
+    );
+    assert_close(&truncated(&weights, Some(1), None), &[0., 1., 0., 0.]);
+    // Nothing set leaves the weights untouched.
+    assert_eq!(truncated(&[3., 1.], None, None), [3., 1.]);
+}
+
+#[test]
+fn top_p_applies_to_the_top_k_tokens() {
+    let weights = [0.1, 0.4, 0.2, 0.3];
+    // The 3 largest weights hold 0.9, 75% of which is reached by 0.4 and 0.3.
+    assert_close(
+        &truncated(&weights, Some(3), Some(0.75)),
+        &[0., 4. / 7., 0., 3. / 7.],
+    );
+    // Without `top_k` the nucleus needs 0.2 as well.
+    assert_close(
+        &truncated(&weights, None, Some(0.75)),
+        &[0., 4. / 9., 2. / 9., 3. / 9.],
+    );
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    for (top_k, top_p) in [(Some(0), None), (None, Some(0.)), (None, Some(1.5))] {
+        let options = DecodeOptions {
+            top_k,
+            top_p,
+            ..Default::default()
+        };
+        assert!(options.validate().is_err(), "{top_k:?} {top_p:?}");
+    }
+}
+
+fn options(top_k: Option<usize>, top_p: Option<f64>, temperature: f64) -> DecodeOptions {
+    DecodeOptions {
+        temperatures: vec![temperature],
+        top_k,
+        top_p,
+        compression_ratio_threshold: None,
+        logprob_threshold: None,
+        no_speech_threshold: None,
+        hallucination: HallucinationOptions {
+            similarity_threshold: 2.,
+            max_ngram_coverage: 1.,
+            blocklist: vec![],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn tokens(decoder: &mut Decoder) -> Vec<Vec<u32>> {
+    let output = decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap();
+    output
+        .segments
+        .into_iter()
+        .map(|segment| segment.dr.tokens)
+        .collect()
+}
+
+#[test]
+fn sampling_is_deterministic_with_a_fixed_seed() {
+    let run = |top_k, top_p, temperature| {
+        let mut decoder = DecoderBuilder::from(tiny_model_data())
+            .seed(7)
+            .build()
+            .unwrap();
+        decoder
+            .set_options(options(top_k, top_p, temperature))
+            .unwrap();
+        tokens(&mut decoder)
+    };
+    let sampled = run(Some(3), Some(0.9), 1.);
+    assert!(!sampled.is_empty());
+    assert_eq!(run(Some(3), Some(0.9), 1.), sampled);
+    // A single candidate leaves nothing to sample.
+    assert_eq!(run(Some(1), None, 1.), run(None, None, 0.));
+}
+
+#[test]
+fn empty_distributions_sample_nothing() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut nan = vec![f64::NAN; 8];
+    assert_eq!(sample_weights(&mut nan, None, None, &mut rng), None);
+    assert_eq!(sample_weights(&mut nan, Some(3), Some(0.9), &mut rng), None);
+    assert!(nan.iter().all(|&w| w == 0.));
+    // The mass left after the truncation is what counts.
+    let mut zero = vec![0., 0., 0., -1., f64::NAN];
+    assert_eq!(sample_weights(&mut zero, Some(2), None, &mut rng), None);
+    assert_eq!(sample_weights(&mut zero, None, Some(0.5), &mut rng), None);
+
+    // The NaN and negative weights are never sampled.
+    for _ in 0..100 {
+        let mut weights = [f64::NAN, 0.2, -1., 0.5, 0.3, f64::INFINITY];
+        let token = sample_weights(&mut weights, None, None, &mut rng).unwrap();
+        assert!([1, 3, 4].contains(&token), "{token}");
+        let mut weights = [f64::NAN, 0.2, -1., 0.5, 0.3];
+        let token = sample_weights(&mut weights, Some(2), None, &mut rng).unwrap();
+        assert!([3, 4].contains(&token), "{token}");
+    }
+    let mut weights = [0.1, 0.4, 0.2, 0.3];
+    assert_eq!(
+        sample_weights(&mut weights, Some(1), None, &mut rng),
+        Some(1)
+    );
+}
+
+#[test]
+fn suppressing_every_token_falls_back_to_the_argmax() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            collect_diagnostics: true,
+            ..options(Some(5), None, 1.)
+        })
+        .unwrap();
+    let run = |decoder: &mut Decoder| {
+        decoder
+            .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+            .unwrap()
+    };
+    let output = run(&mut decoder);
+    let attempts = &output.diagnostics.unwrap().windows[0].attempts;
+    assert_eq!(attempts[0].sampling_fallbacks, 0);
+    assert_eq!(output.segments[0].dr.sampling_fallbacks, 0);
+
+    // At the second step only.
+    decoder.set_logits_processor(Some(Box::new(
+        |logits: &mut [f32], context: &LogitsContext| {
+            if context.step == 1 {
+                logits.fill(f32::NEG_INFINITY);
+            }
+        },
+    )));
+    let output = run(&mut decoder);
+    let attempts = &output.diagnostics.unwrap().windows[0].attempts;
+    assert!(attempts[0].decode_steps > 1);
+    assert!(attempts[0].error.is_none());
+    assert_eq!(attempts[0].sampling_fallbacks, 1);
+    let dr = &output.segments[0].dr;
+    assert_eq!(dr.sampling_fallbacks, 1);
+    assert!(dr.avg_logprob.is_finite());
+}
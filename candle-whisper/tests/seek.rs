@@ -0,0 +1,104 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data, SPECIAL_TOKENS, TEXT_TOKENS},
+    logic::{DecodeOptions, Decoder, LogitsContext, RunOptions, TranscriptionOutput},
+};
+
+const HELLO: u32 = 1;
+const WORLD: u32 = 2;
+const SOUND: u32 = 4;
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+const TIMESTAMP_BEGIN: u32 = (TEXT_TOKENS.len() + SPECIAL_TOKENS.len()) as u32;
+
+/// Timestamp token of `seconds` within the window.
+fn ts(seconds: f64) -> u32 {
+    TIMESTAMP_BEGIN + (seconds / 0.02).round() as u32
+}
+
+/// Transcribes `seconds` of audio with timestamps, the window starting at each of the
+/// `scripts` times sampling its tokens.
+fn run_scripted(seconds: f64, scripts: Vec<(f64, Vec<u32>)>) -> TranscriptionOutput {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            collect_diagnostics: true,
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            logprob_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            let (_, script) = scripts
+                .iter()
+                .find(|(start, _)| (start - context.segment_start).abs() < 1e-6)
+                .unwrap_or_else(|| panic!("no script at {}s", context.segment_start));
+            let token = script.get(context.step).copied().unwrap_or(EOT);
+            logits.fill(f32::NEG_INFINITY);
+            logits[token as usize] = 0.;
+        },
+    )));
+    let opts = RunOptions {
+        timestamps: Some(true),
+        ..Default::default()
+    };
+    decoder.run_pcm(&sine_pcm(seconds, 440.), &opts).unwrap()
+}
+
+fn seek_frames(output: &TranscriptionOutput) -> Vec<usize> {
+    let windows = &output.diagnostics.as_ref().unwrap().windows;
+    windows.iter().map(|w| w.seek_frame).collect()
+}
+
+#[test]
+fn seek_resumes_from_the_last_timestamp_pair() {
+    let output = run_scripted(
+        40.,
+        vec![
+            // Consumed up to the pair, the next window starting there.
+            (0., vec![ts(0.), HELLO, WORLD, ts(12.), ts(12.)]),
+            (12., vec![ts(0.), SOUND, ts(6.), ts(6.)]),
+            (18., vec![ts(0.), HELLO, ts(2.)]),
+        ],
+    );
+    // The last window, ending with a single timestamp, is consumed whole: no window follows
+    // it although the audio goes on 22s after its start.
+    assert_eq!(seek_frames(&output), [0, 1200, 1800]);
+    let times: Vec<(f64, f64)> = output
+        .segments
+        .iter()
+        .map(|s| (s.start, s.duration))
+        .collect();
+    assert_eq!(times[0], (0., 12.));
+    assert_eq!(times[1], (12., 6.));
+    assert_eq!(times[2].0, 18.);
+}
+
+#[test]
+fn single_timestamp_ending_consumes_the_window() {
+    let output = run_scripted(
+        45.,
+        vec![
+            (0., vec![ts(0.), HELLO, WORLD, ts(10.)]),
+            (30., vec![ts(0.), SOUND, ts(5.)]),
+        ],
+    );
+    assert_eq!(seek_frames(&output), [0, 3000]);
+    assert_eq!(
+        (output.segments[0].start, output.segments[0].duration),
+        (0., 30.)
+    );
+}
+
+#[test]
+fn pair_at_the_window_start_does_not_stall() {
+    // A pair at 0 would not advance, the whole window is consumed instead.
+    let output = run_scripted(
+        35.,
+        vec![
+            (0., vec![ts(0.), ts(0.)]),
+            (30., vec![ts(0.), HELLO, ts(1.)]),
+        ],
+    );
+    assert_eq!(seek_frames(&output), [0, 3000]);
+}
@@ -0,0 +1,106 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_config, tiny_model_data, tiny_weights_with},
+    logic::{
+        DecodeOptions, Decoder, ModelData, RunOptions, Segment, SegmentErrorPolicy, SegmentEvent,
+    },
+};
+
+#[test]
+fn pulled_windows_match_the_batch_run() {
+    let pcm = sine_pcm(40., 440.);
+    let opts = RunOptions::default();
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let mel = decoder.compute_mel(&pcm).unwrap();
+    let batch = decoder.run_mel(&mel, &opts).unwrap();
+
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let mut segments = decoder.segments_iter(&mel, &opts);
+    let mut decoded = vec![];
+    let mut windows = 0;
+    for event in segments.by_ref() {
+        windows += 1;
+        if let SegmentEvent::Decoded(window) = event.unwrap() {
+            decoded.extend(window);
+        }
+    }
+    assert!(windows >= 2);
+    // Exhausted for good.
+    assert!(segments.next().is_none());
+    assert!(segments.next().is_none());
+    let output = segments.into_output();
+    assert_eq!(
+        serde_json::to_value(&output).unwrap(),
+        serde_json::to_value(&batch).unwrap()
+    );
+    let tokens = |segments: &[Segment]| {
+        segments
+            .iter()
+            .map(|segment| (segment.start, segment.dr.tokens.clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(tokens(&decoded), tokens(&batch.segments));
+}
+
+#[test]
+fn dropped_iterator_leaves_the_decoder_reusable() {
+    let opts = RunOptions::default();
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let short = decoder.compute_mel(&sine_pcm(10., 880.)).unwrap();
+    let expected = decoder.run_mel(&short, &opts).unwrap();
+
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let long = decoder.compute_mel(&sine_pcm(70., 440.)).unwrap();
+    let first = decoder.segments_iter(&long, &opts).next().unwrap().unwrap();
+    assert!(matches!(first, SegmentEvent::Decoded(_)));
+    let output = decoder.run_mel(&short, &opts).unwrap();
+    assert_eq!(output.segments.len(), expected.segments.len());
+    for (segment, expected) in output.segments.iter().zip(&expected.segments) {
+        assert_eq!(segment.dr.tokens, expected.dr.tokens);
+        assert_eq!(segment.prompt_tokens, expected.prompt_tokens);
+    }
+}
+
+#[test]
+fn failed_windows_are_skipped_and_pulling_goes_on() {
+    // The positional embeddings are NaN from the first sampled token, every window fails.
+    let config = tiny_config();
+    let d_model = config.d_model;
+    let weights = tiny_weights_with(&config, |name, values| {
+        if name == "model.decoder.embed_positions.weight" {
+            values[2 * d_model..].fill(f32::NAN);
+        }
+    })
+    .unwrap();
+    let mut decoder = Decoder::load(ModelData {
+        weights,
+        ..tiny_model_data()
+    })
+    .unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            on_segment_error: SegmentErrorPolicy::SkipAndContinue,
+            ..Default::default()
+        })
+        .unwrap();
+    let mel = decoder.compute_mel(&sine_pcm(40., 440.)).unwrap();
+    let mut segments = decoder.segments_iter(&mel, &RunOptions::default());
+    let mut starts = vec![];
+    for event in segments.by_ref() {
+        match event.unwrap() {
+            SegmentEvent::Skipped {
+                start,
+                failure: Some(failure),
+                ..
+            } => {
+                assert_eq!(failure.code, "non_finite_logits");
+                assert_eq!(failure.start, start);
+                starts.push(start);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+    assert_eq!(starts, [0., 30.]);
+    let output = segments.into_output();
+    assert!(output.segments.is_empty());
+    assert_eq!(output.failed_segments.len(), 2);
+}
@@ -0,0 +1,335 @@
+use candle_whisper::{
+    alignment::WordTiming,
+    fixtures,
+    logic::Segment,
+    segments::{
+        concat, full_text, interleave, limit_length, merge_adjacent, shift, sort_by_start,
+        split_at, SegmentOrderError,
+    },
+};
+
+/// [`fixtures::segment`] of a single word.
+fn segment(id: usize, start: f64, duration: f64, text: &str) -> Segment {
+    let word = WordTiming {
+        word: text.to_string(),
+        start,
+        end: start + duration,
+        timing_source: Default::default(),
+        probability: None,
+    };
+    Segment {
+        words: vec![word],
+        ..fixtures::segment(id, start, duration, text)
+    }
+}
+
+#[test]
+fn shift_moves_segments_and_words() {
+    let mut segments = vec![segment(0, 1., 2., "a"), segment(1, 3., 1., "b")];
+    shift(&mut segments, 10.);
+    assert_eq!(segments[0].start, 11.);
+    assert_eq!(segments[1].start, 13.);
+    assert_eq!(segments[1].duration, 1.);
+    assert_eq!(
+        (segments[1].words[0].start, segments[1].words[0].end),
+        (13., 14.)
+    );
+}
+
+#[test]
+fn parts_are_offset_and_renumbered() {
+    let parts = vec![
+        (
+            0.,
+            vec![segment(0, 0., 5., "one"), segment(1, 5., 5., "two")],
+        ),
+        (60., vec![]),
+        (10., vec![segment(0, 0., 3., "three")]),
+        (20., vec![segment(0, 1., 3., "four")]),
+    ];
+    let segments = concat(parts).unwrap();
+    let summary: Vec<(usize, f64)> = segments.iter().map(|s| (s.id, s.start)).collect();
+    assert_eq!(summary, [(0, 0.), (1, 5.), (2, 10.), (3, 21.)]);
+    assert!(concat(vec![]).unwrap().is_empty());
+    assert!(concat(vec![(5., vec![]), (0., vec![])]).unwrap().is_empty());
+}
+
+#[test]
+fn overlapping_parts_are_reported() {
+    let parts = vec![
+        (
+            0.,
+            vec![segment(0, 0., 5., "one"), segment(1, 5., 5., "two")],
+        ),
+        (9., vec![segment(0, 0., 3., "three")]),
+    ];
+    assert_eq!(
+        concat(parts).unwrap_err(),
+        SegmentOrderError {
+            previous: 1,
+            next: 2,
+            previous_end: 10.,
+            next_start: 9.,
+        }
+    );
+    // Segments out of order within a part.
+    let parts = vec![(0., vec![segment(0, 4., 1., "b"), segment(1, 0., 1., "a")])];
+    let err = concat(parts).unwrap_err();
+    assert_eq!((err.previous, err.next), (0, 1));
+    assert!(err.to_string().contains("segment 1 starts at 0s"));
+}
+
+#[test]
+fn offsets_do_not_accumulate_rounding_errors() {
+    // Hundreds of parts of a single 0.1s segment, each ending where the next starts.
+    let parts: Vec<(f64, Vec<Segment>)> = (0..500)
+        .map(|i| (i as f64 * 0.1, vec![segment(0, 0., 0.1, "x")]))
+        .collect();
+    let segments = concat(parts).unwrap();
+    assert_eq!(segments.len(), 500);
+    let last = &segments[499];
+    assert!((last.start + last.duration - 50.).abs() < 1e-9);
+    assert_eq!(last.id, 499);
+}
+
+#[test]
+fn full_text_skips_silence_and_normalizes_the_joins() {
+    let mut silent = segment(2, 4., 1., " noise ");
+    silent.no_speech = true;
+    let mut cleaned = segment(4, 6., 1., " raw text");
+    cleaned.dr.text_clean = Some("Clean text.".to_string());
+    let segments = vec![
+        segment(0, 0., 2., " Hello there. "),
+        segment(1, 2., 2., "   "),
+        silent,
+        segment(3, 5., 1., "\nGeneral Kenobi!"),
+        cleaned,
+    ];
+    assert_eq!(
+        full_text(&segments, " "),
+        "Hello there. General Kenobi! Clean text."
+    );
+    assert_eq!(
+        full_text(&segments, "\n"),
+        "Hello there.\nGeneral Kenobi!\nClean text."
+    );
+    assert_eq!(full_text(&[], " "), "");
+}
+
+#[test]
+fn sorting_is_stable_on_ids() {
+    let mut segments = vec![
+        segment(3, 2., 1., "d"),
+        segment(1, 0., 1., "b"),
+        segment(2, 2., 1., "c"),
+        segment(0, 0., 1., "a"),
+    ];
+    sort_by_start(&mut segments);
+    let texts: Vec<&str> = segments.iter().map(|s| s.dr.text.as_str()).collect();
+    assert_eq!(texts, ["a", "b", "c", "d"]);
+}
+
+/// [`segment`] of `speaker` with `n_tokens` tokens of log probability `avg_logprob`.
+fn spoken(
+    id: usize,
+    start: f64,
+    duration: f64,
+    text: &str,
+    speaker: &str,
+    n_tokens: u32,
+    avg_logprob: f64,
+) -> Segment {
+    let mut segment = segment(id, start, duration, text);
+    segment.speaker = Some(speaker.to_string());
+    segment.dr.tokens = (0..n_tokens).collect();
+    segment.dr.avg_logprob = avg_logprob;
+    segment
+}
+
+#[test]
+fn adjacent_segments_of_a_speaker_are_merged() {
+    let mut silence = segment(3, 4.5, 1., "");
+    silence.no_speech = true;
+    let segments = vec![
+        spoken(0, 0., 1., " Hello", "A", 1, -0.1),
+        spoken(1, 1.2, 1., "there.", "A", 3, -0.5),
+        spoken(2, 2.3, 2., " Hi!", "B", 2, -0.2),
+        silence,
+        spoken(4, 5.5, 1., " Bye.", "B", 1, -0.3),
+        // Past the gap.
+        spoken(5, 7., 1., " Again.", "B", 1, -0.3),
+    ];
+    let merged = merge_adjacent(segments, 0.25);
+    let summary: Vec<(usize, &str, f64, f64)> = merged
+        .iter()
+        .map(|s| (s.id, s.dr.text.as_str(), s.start, s.duration))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (0, " Hello there.", 0., 2.2),
+            (1, " Hi!", 2.3, 2.),
+            (2, "", 4.5, 1.),
+            (3, " Bye.", 5.5, 1.),
+            (4, " Again.", 7., 1.),
+        ]
+    );
+    let first = &merged[0];
+    assert_eq!(first.dr.tokens, [0, 0, 1, 2]);
+    // Weighted by the token counts.
+    assert!((first.dr.avg_logprob - -0.4).abs() < 1e-9);
+    assert_eq!(first.words.len(), 2);
+
+    // Further apart with a wider gap.
+    let wide = merge_adjacent(
+        vec![
+            spoken(0, 0., 1., " One.", "B", 1, -0.1),
+            spoken(1, 3., 1., " Two.", "B", 1, -0.1),
+        ],
+        5.,
+    );
+    assert_eq!(wide.len(), 1);
+    assert_eq!(wide[0].dr.text, " One. Two.");
+    assert_eq!(wide[0].duration, 4.);
+}
+
+#[test]
+fn segments_are_split_at_word_boundaries() {
+    let mut long = spoken(0, 0., 4., " one two three four", "A", 8, -0.2);
+    long.dr.text_clean = Some("One two three four".to_string());
+    let segments = vec![segment(0, 4., 1., " five"), long];
+    // 5s is the start of the next segment and 9s outside of every segment.
+    let parts = split_at(segments, &[9., 2., 4.]);
+    let summary: Vec<(usize, &str, f64, f64, usize)> = parts
+        .iter()
+        .map(|s| {
+            (
+                s.id,
+                s.dr.text.as_str(),
+                s.start,
+                s.duration,
+                s.dr.tokens.len(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (0, " five", 4., 1., 0),
+            (1, " one two", 0., 2., 4),
+            (2, " three four", 2., 2., 4),
+        ]
+    );
+    assert!(parts[1].dr.text_clean.is_none());
+    assert_eq!(parts[1].speaker.as_deref(), Some("A"));
+    assert_eq!(parts[2].dr.avg_logprob, -0.2);
+}
+
+#[test]
+fn tracks_are_interleaved_by_start() {
+    let mut silence = segment(1, 3., 1., "");
+    silence.no_speech = true;
+    let a = vec![
+        spoken(0, 0., 2., " A one", "A", 1, -0.1),
+        silence,
+        spoken(2, 5., 1., " A two", "A", 1, -0.1),
+    ];
+    let b = vec![spoken(0, 0., 2., " B one", "B", 1, -0.1)];
+    let segments = interleave(vec![(0., a), (1., b)]);
+    let summary: Vec<(usize, &str, f64)> = segments
+        .iter()
+        .map(|s| (s.id, s.dr.text.as_str(), s.start))
+        .collect();
+    assert_eq!(
+        summary,
+        [(0, " A one", 0.), (1, " B one", 1.), (2, " A two", 5.)]
+    );
+}
+
+/// 28 seconds monologue of 8 sentences of 7 words, a word every half second, each word
+/// lasting 0.4s.
+fn monologue() -> Segment {
+    const WORDS: &[&str] = &["the", "sound", "of", "a", "sine", "wave", "hello"];
+    let mut text = String::new();
+    let mut words = vec![];
+    for i in 0..56 {
+        let word = match i % 7 {
+            6 => format!(" {}.", WORDS[i % 7]),
+            _ => format!(" {}", WORDS[i % 7]),
+        };
+        text.push_str(&word);
+        let start = i as f64 * 0.5;
+        words.push(WordTiming {
+            word,
+            start,
+            end: start + 0.4,
+            timing_source: Default::default(),
+            probability: None,
+        });
+    }
+    Segment {
+        words,
+        ..fixtures::segment(0, 0., 28., &text)
+    }
+}
+
+/// Checks that the cues tile `[0, 28)`, last at most 5 seconds, and keep the text.
+fn assert_cues(cues: &[Segment], text: &str) {
+    assert!(cues.len() >= 6, "{} cues", cues.len());
+    assert_eq!(cues[0].start, 0.);
+    for pair in cues.windows(2) {
+        assert!((pair[0].start + pair[0].duration - pair[1].start).abs() < 1e-9);
+    }
+    let last = cues.last().unwrap();
+    assert!((last.start + last.duration - 28.).abs() < 1e-9);
+    for cue in cues {
+        assert!(
+            cue.duration <= 5. + 1e-9,
+            "{} lasts {}",
+            cue.dr.text,
+            cue.duration
+        );
+    }
+    let joined: String = cues.iter().map(|c| c.dr.text.as_str()).collect();
+    assert_eq!(joined, text);
+    let ids: Vec<usize> = cues.iter().map(|c| c.id).collect();
+    assert_eq!(ids, (0..cues.len()).collect::<Vec<_>>());
+}
+
+#[test]
+fn long_segments_are_cut_at_word_ends() {
+    let segment = monologue();
+    let text = segment.dr.text.clone();
+    let word_ends: Vec<f64> = segment.words.iter().map(|w| w.end).collect();
+    let cues = limit_length(vec![segment], Some(5.), None);
+    assert_cues(&cues, &text);
+    // One cue per sentence, each ending at the end of its last word.
+    assert_eq!(cues.len(), 8);
+    for cue in &cues[..7] {
+        assert!(cue.dr.text.ends_with('.'), "{:?}", cue.dr.text);
+        let cut = cue.start + cue.duration;
+        assert!(
+            word_ends.iter().any(|&end| (end - cut).abs() < 1e-9),
+            "{cut}"
+        );
+        // The words of the cue are the words of its text.
+        let words: String = cue.words.iter().map(|w| w.word.as_str()).collect();
+        assert_eq!(words, cue.dr.text);
+    }
+}
+
+#[test]
+fn segments_without_words_are_cut_by_characters() {
+    let segment = Segment {
+        words: vec![],
+        ..monologue()
+    };
+    let text = segment.dr.text.clone();
+    let cues = limit_length(vec![segment], Some(5.), None);
+    assert_cues(&cues, &text);
+
+    // The characters limit applies as well.
+    let cues = limit_length(vec![monologue()], Some(5.), Some(20));
+    assert_cues(&cues, &text);
+    assert!(cues.iter().all(|c| c.dr.text.trim().chars().count() <= 20));
+}
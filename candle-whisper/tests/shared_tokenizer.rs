@@ -0,0 +1,59 @@
+use candle_whisper::{
+    builder::{parse_tokenizer, DecoderBuilder},
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{Decoder, RunOptions},
+};
+use std::sync::Arc;
+
+fn transcribe(decoder: &mut Decoder) -> Vec<(Vec<u32>, String)> {
+    decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap()
+        .segments
+        .into_iter()
+        .map(|segment| (segment.dr.tokens, segment.dr.text))
+        .collect()
+}
+
+#[test]
+fn decoders_share_one_parsed_tokenizer() {
+    let tokenizer = parse_tokenizer(&tiny_model_data().tokenizer).unwrap();
+    let build = || {
+        DecoderBuilder::from(tiny_model_data())
+            .tokenizer_shared(tokenizer.clone())
+            .build()
+            .unwrap()
+    };
+    let mut first = build();
+    let mut second = build();
+    assert!(Arc::ptr_eq(first.tokenizer(), &tokenizer));
+    assert!(Arc::ptr_eq(first.tokenizer(), second.tokenizer()));
+    assert_eq!(Arc::strong_count(&tokenizer), 3);
+
+    let mut from_bytes = Decoder::load(tiny_model_data()).unwrap();
+    assert!(!Arc::ptr_eq(from_bytes.tokenizer(), &tokenizer));
+    assert_eq!(first.special_tokens(), from_bytes.special_tokens());
+    let expected = transcribe(&mut from_bytes);
+    assert!(!expected.is_empty());
+    assert_eq!(transcribe(&mut first), expected);
+    assert_eq!(transcribe(&mut second), expected);
+}
+
+#[test]
+fn swapping_the_model_keeps_the_shared_tokenizer() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let tokenizer = decoder.tokenizer().clone();
+    let special_tokens = decoder.special_tokens();
+    decoder
+        .swap_model_with(
+            DecoderBuilder::from(tiny_model_data()).tokenizer_shared(tokenizer.clone()),
+        )
+        .unwrap();
+    assert!(Arc::ptr_eq(decoder.tokenizer(), &tokenizer));
+    assert_eq!(decoder.special_tokens(), special_tokens);
+
+    // Swapping with the bytes parses a new tokenizer.
+    decoder.swap_model(tiny_model_data()).unwrap();
+    assert!(!Arc::ptr_eq(decoder.tokenizer(), &tokenizer));
+    assert_eq!(Arc::strong_count(&tokenizer), 1);
+}
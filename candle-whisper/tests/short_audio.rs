@@ -0,0 +1,85 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, sine_wav, tiny_model_data},
+    logic::{m, DecodeOptions, Decoder, RunOptions, TranscriptionOutput},
+};
+
+fn run(decoder: &mut Decoder, seconds: f64) -> TranscriptionOutput {
+    decoder
+        .run_pcm(&sine_pcm(seconds, 440.), &RunOptions::default())
+        .unwrap()
+}
+
+fn assert_within_audio(output: &TranscriptionOutput, seconds: f64) {
+    for segment in output.segments.iter() {
+        assert!(
+            segment.start + segment.duration <= seconds + 1e-6,
+            "segment {segment:?} ends after {seconds}s"
+        );
+    }
+}
+
+#[test]
+fn mel_is_padded_to_the_next_window_once() {
+    let decoder = Decoder::load(tiny_model_data()).unwrap();
+    for (seconds, frames) in [
+        (0., m::N_FRAMES),
+        (0.05, m::N_FRAMES),
+        (2., m::N_FRAMES),
+        (29.9, m::N_FRAMES),
+        (30., m::N_FRAMES),
+        (30.5, 2 * m::N_FRAMES),
+    ] {
+        let mel = decoder.compute_mel(&sine_pcm(seconds, 440.)).unwrap();
+        assert_eq!(mel.n_frames(), frames, "{seconds}s");
+        assert!((mel.duration() - seconds).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn empty_and_very_short_audio_is_not_decoded() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    for seconds in [0., 0.05] {
+        let output = run(&mut decoder, seconds);
+        assert!(output.too_short, "{seconds}s");
+        assert!(output.segments.is_empty());
+    }
+    let output = decoder.convert_and_run(&sine_wav(0., 440.)).unwrap();
+    assert!(output.too_short);
+    let json = serde_json::to_value(&output).unwrap();
+    assert_eq!(json["too_short"], true);
+
+    let mel = decoder.compute_mel(&sine_pcm(0.05, 440.)).unwrap();
+    let output = decoder.run_mel(&mel, &RunOptions::default()).unwrap();
+    assert!(output.too_short);
+    assert!(output.segments.is_empty());
+}
+
+#[test]
+fn min_duration_is_configurable() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            min_duration: 0.,
+            ..Default::default()
+        })
+        .unwrap();
+    let output = run(&mut decoder, 0.05);
+    assert!(!output.too_short);
+    assert_within_audio(&output, 0.05);
+
+    let invalid = DecodeOptions {
+        min_duration: f64::NAN,
+        ..Default::default()
+    };
+    assert!(decoder.set_options(invalid).is_err());
+}
+
+#[test]
+fn short_clips_are_decoded_within_the_audio() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    for seconds in [2., 29.9] {
+        let output = run(&mut decoder, seconds);
+        assert!(!output.too_short);
+        assert_within_audio(&output, seconds);
+    }
+}
@@ -0,0 +1,121 @@
+use candle_whisper::{
+    audio::AudioPreprocess,
+    builder::DecoderBuilder,
+    fixtures::{large_vocab_model_data, sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, ModelData, RunOptions, Task, TranscriptionOutput},
+    profiles::PartialDecodeOptions,
+    snapshot::{ConfigSnapshot, CRATE_VERSION},
+    weights::WeightManifest,
+};
+
+fn options() -> DecodeOptions {
+    DecodeOptions {
+        temperatures: vec![0.6],
+        top_k: Some(5),
+        collect_diagnostics: true,
+        preprocess: AudioPreprocess {
+            dither: Some(1e-3),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn with_manifest(md: ModelData) -> ModelData {
+    ModelData {
+        manifest: Some(WeightManifest::from_weights(&md.weights).unwrap()),
+        ..md
+    }
+}
+
+fn transcribe(decoder: &mut Decoder) -> TranscriptionOutput {
+    decoder
+        .run_pcm(&sine_pcm(8., 440.), &RunOptions::default())
+        .unwrap()
+}
+
+fn segments_json(output: &TranscriptionOutput) -> serde_json::Value {
+    serde_json::to_value(&output.segments).unwrap()
+}
+
+#[test]
+fn snapshot_reproduces_a_run_on_another_decoder() {
+    let mut decoder = DecoderBuilder::from(with_manifest(tiny_model_data()))
+        .seed(42)
+        .timestamps(false)
+        .build()
+        .unwrap();
+    decoder.set_options(options()).unwrap();
+    decoder
+        .set_language_profile(
+            "en",
+            PartialDecodeOptions {
+                no_speech_threshold: Some(0.3),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    decoder.set_task(Task::Transcribe).unwrap();
+    let snapshot = decoder.config_snapshot();
+    assert_eq!(snapshot.crate_version, CRATE_VERSION);
+    assert_eq!(snapshot.seed, 42);
+    assert!(snapshot.model.weights_sha256.is_some());
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let output = transcribe(&mut decoder);
+
+    // The diagnostics carry the snapshot.
+    assert_eq!(
+        serde_json::to_string(&output.config.as_deref().unwrap()).unwrap(),
+        json
+    );
+
+    let mut other = Decoder::load(with_manifest(tiny_model_data())).unwrap();
+    let restored: ConfigSnapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(other.apply_snapshot(&restored), []);
+    assert_eq!(
+        serde_json::to_string(&other.config_snapshot()).unwrap(),
+        json
+    );
+    assert_eq!(
+        segments_json(&transcribe(&mut other)),
+        segments_json(&output)
+    );
+}
+
+#[test]
+fn fields_that_do_not_fit_the_model_are_reported() {
+    let mut multilingual = DecoderBuilder::from(large_vocab_model_data())
+        .language(Some("fr"))
+        .build()
+        .unwrap();
+    multilingual.set_options(options()).unwrap();
+    let snapshot = multilingual.config_snapshot();
+
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let before = transcribe(&mut decoder);
+    assert!(before.config.is_none());
+    let skipped = decoder.apply_snapshot(&snapshot);
+    let fields: Vec<&str> = skipped.iter().map(|s| s.field.as_str()).collect();
+    assert_eq!(fields, ["model", "language"]);
+    assert!(
+        skipped[0].reason.contains("vocab_size"),
+        "{}",
+        skipped[0].reason
+    );
+    // The options fit, they are applied.
+    assert_eq!(decoder.options().top_k, Some(5));
+    let output = transcribe(&mut decoder);
+    let config = output.config.unwrap();
+    assert_eq!(config.language, None);
+    assert_eq!(config.options.top_k, Some(5));
+
+    // Checksums are compared when both models have one.
+    let mut snapshot = Decoder::load(with_manifest(tiny_model_data()))
+        .unwrap()
+        .config_snapshot();
+    let checked = Decoder::load(with_manifest(tiny_model_data())).unwrap();
+    assert!(snapshot.model_differences(checked.model_info()).is_empty());
+    snapshot.model.weights_sha256 = Some("0".repeat(64));
+    assert_eq!(snapshot.model_differences(checked.model_info()).len(), 1);
+    assert!(snapshot.model_differences(decoder.model_info()).is_empty());
+}
@@ -0,0 +1,150 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{Checkpoint, DecodeOptions, Decoder, LogitsContext, RunOptions, TranscriptionOutput},
+    speaker_change::{cosine_distance, SpeakerChangeTracker},
+};
+
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+/// Decoder of each window to a single token.
+fn decoder(speaker_change_hints: bool) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            speaker_change_hints,
+            collect_diagnostics: true,
+            no_speech_threshold: None,
+            compression_ratio_threshold: None,
+            hallucination: HallucinationOptions {
+                similarity_threshold: 2.,
+                max_ngram_coverage: 1.,
+                blocklist: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    decoder.set_logits_processor(Some(Box::new(
+        |logits: &mut [f32], context: &LogitsContext| {
+            let forced = if context.step == 0 { 1 } else { EOT };
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if token as u32 != forced {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        },
+    )));
+    decoder
+}
+
+/// 30 seconds of a 440Hz tone followed by 30 seconds of a 3kHz one.
+fn pcm() -> Vec<f32> {
+    let mut pcm = sine_pcm(30., 440.);
+    pcm.extend(sine_pcm(30., 3000.));
+    pcm
+}
+
+fn opts() -> RunOptions {
+    RunOptions {
+        timestamps: Some(false),
+        ..Default::default()
+    }
+}
+
+fn run(speaker_change_hints: bool) -> TranscriptionOutput {
+    decoder(speaker_change_hints)
+        .run_pcm(&pcm(), &opts())
+        .unwrap()
+}
+
+#[test]
+fn change_of_content_between_windows_scores_high() {
+    let output = run(true);
+    assert_eq!(output.segments.len(), 2);
+    assert_eq!(output.segments[0].speaker_change_score, 0.);
+    let windows = output.diagnostics.unwrap().windows;
+    assert_eq!(windows.len(), 2);
+    assert!(windows[0].speaker_change_distance.is_none());
+    let boundary = windows[1].speaker_change_distance.unwrap();
+    let within: Vec<f32> = windows
+        .iter()
+        .flat_map(|window| window.within_window_distances.iter().copied())
+        .collect();
+    // 6 blocks of 5 seconds per window.
+    assert_eq!(within.len(), 10);
+    let max_within = within.iter().copied().fold(0f32, f32::max);
+    assert!(boundary > 1.5 * max_within, "{boundary} {within:?}");
+    assert!(output.segments[1].speaker_change_score > 1.5);
+}
+
+#[test]
+fn hints_are_off_by_default() {
+    let output = run(false);
+    assert!(output
+        .segments
+        .iter()
+        .all(|segment| segment.speaker_change_score == 0.));
+    let windows = output.diagnostics.unwrap().windows;
+    assert!(windows
+        .iter()
+        .all(|window| window.speaker_change_distance.is_none()
+            && window.within_window_distances.is_empty()));
+}
+
+#[test]
+fn scores_are_scaled_by_the_within_window_distances() {
+    let first = [1., 0.];
+    let second = [0.8, 0.6];
+    assert!((cosine_distance(&first, &second) - 0.2).abs() < 1e-6);
+    assert_eq!(cosine_distance(&first, &[0., 0.]), 0.);
+
+    // 10 seconds per window, the second block of each leaning towards the next window.
+    let window = |a: [f32; 2], b: [f32; 2]| -> Vec<Vec<f32>> {
+        [vec![a.to_vec(); 5], vec![b.to_vec(); 5]].concat()
+    };
+    let mut tracker = SpeakerChangeTracker::default();
+    let change = tracker.push_window(&window(first, second), 10., 10.);
+    assert_eq!(change.score, None);
+    assert_eq!(change.distance, None);
+    assert_eq!(change.within_distances.len(), 1);
+    // From `second` to its opposite, 20 times the baseline averaging 0.2 and 0.
+    let change = tracker.push_window(&window([-0.8, -0.6], [-0.8, -0.6]), 10., 10.);
+    assert!((change.distance.unwrap() - 2.).abs() < 1e-6);
+    assert!(
+        (change.score.unwrap() - 2. / 0.1).abs() < 1e-3,
+        "{change:?}"
+    );
+}
+
+#[test]
+fn resumed_run_scores_as_the_uninterrupted_one() {
+    let expected = run(true);
+    let mut decoder = decoder(true);
+    let mel = decoder.compute_mel(&pcm()).unwrap();
+    let mut stored = None;
+    decoder
+        .run_resumable(&mel, &opts(), &mut |checkpoint| {
+            stored = Some(serde_json::to_string(checkpoint).unwrap());
+            false
+        })
+        .unwrap_err();
+    let checkpoint: Checkpoint = serde_json::from_str(&stored.unwrap()).unwrap();
+    let output = self::decoder(true)
+        .resume(&mel, &opts(), checkpoint, &mut |_| true)
+        .unwrap();
+    let scores = |output: &TranscriptionOutput| -> Vec<f32> {
+        output
+            .segments
+            .iter()
+            .map(|segment| segment.speaker_change_score)
+            .collect()
+    };
+    let (resumed, expected) = (scores(&output), scores(&expected));
+    assert_eq!(resumed.len(), 2);
+    assert!(expected[1] > 1.5);
+    assert!(
+        (resumed[1] - expected[1]).abs() < 1e-5,
+        "{resumed:?} {expected:?}"
+    );
+}
@@ -0,0 +1,101 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, EncoderInput, RunOptions, TranscriptionOutput},
+};
+use std::f32::consts::PI;
+
+const SAMPLE_RATE: usize = 16000;
+
+/// Harmonics of a gliding 120Hz voice, in syllables of a sixth of a second separated by pauses
+/// as long.
+fn speech_like(seconds: usize) -> Vec<f32> {
+    (0..seconds * SAMPLE_RATE)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = (2. * PI * 3. * t).sin().max(0.);
+            let f0 = 120. + 30. * (2. * PI * 0.5 * t).sin();
+            let voice: f32 = (1..12)
+                .map(|h| (2. * PI * f0 * h as f32 * t).sin() / h as f32)
+                .sum();
+            0.2 * envelope * voice
+        })
+        .collect()
+}
+
+/// Deterministic white noise in `[-amplitude, amplitude]`.
+fn noise(seconds: usize, amplitude: f32) -> Vec<f32> {
+    let mut state = 0x2545f4914f6cdd1du64;
+    (0..seconds * SAMPLE_RATE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            ((state >> 40) as f32 / (1u64 << 24) as f32 * 2. - 1.) * amplitude
+        })
+        .collect()
+}
+
+fn decoder(encoder_input: EncoderInput) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            encoder_input,
+            collect_diagnostics: true,
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+}
+
+#[test]
+fn probe_ranks_the_chunks_as_the_full_decoding() {
+    for encoder_input in [EncoderInput::Exact, EncoderInput::PadToFull] {
+        let mut decoder = decoder(encoder_input);
+        let chunks = [speech_like(3), noise(3, 0.3), vec![0.; 3 * SAMPLE_RATE]];
+        let mut probes = vec![];
+        for chunk in &chunks {
+            let probe = decoder.speech_probability(chunk).unwrap();
+            let output = decoder.run_pcm(chunk, &RunOptions::default()).unwrap();
+            let no_speech_prob = output.diagnostics.unwrap().windows[0].no_speech_prob;
+            assert!(
+                (probe as f64 - (1. - no_speech_prob)).abs() < 1e-4,
+                "{probe} {no_speech_prob}"
+            );
+            probes.push(probe);
+        }
+        // The pseudo-random weights of the tiny model do not tell speech from noise, only both
+        // from silence.
+        let [speech, noise, silence] = probes[..] else {
+            unreachable!()
+        };
+        assert!(silence < speech && silence < noise, "{probes:?}");
+        assert!(probes.iter().all(|p| (0. ..=1.).contains(p)));
+    }
+    let mut decoder = decoder(EncoderInput::Exact);
+    assert_eq!(decoder.speech_probability(&[0.; 100]).unwrap(), 0.);
+}
+
+fn transcribe(decoder: &mut Decoder) -> TranscriptionOutput {
+    let mut output = decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap();
+    output.diagnostics = None;
+    output
+}
+
+#[test]
+fn probes_leave_the_transcriptions_unchanged() {
+    let expected = serde_json::to_value(transcribe(&mut decoder(EncoderInput::Exact))).unwrap();
+    let mut decoder = decoder(EncoderInput::Exact);
+    decoder.speech_probability(&speech_like(2)).unwrap();
+    assert_eq!(
+        serde_json::to_value(transcribe(&mut decoder)).unwrap(),
+        expected
+    );
+    decoder.speech_probability(&noise(1, 0.3)).unwrap();
+    decoder.speech_probability(&speech_like(5)).unwrap();
+    assert_eq!(
+        serde_json::to_value(transcribe(&mut decoder)).unwrap(),
+        expected
+    );
+}
@@ -0,0 +1,74 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data, TEXT_TOKENS},
+    logic::{DecodeOptions, Decoder, LogitsContext, RunOptions},
+};
+
+const SAMPLE_RATE: usize = 16000;
+
+/// 30 seconds of silence with a tone burst of `burst` seconds at 10 seconds.
+fn burst_pcm(burst: f64) -> Vec<f32> {
+    let mut pcm = vec![0.; 30 * SAMPLE_RATE];
+    let tone = sine_pcm(burst, 440.);
+    pcm[10 * SAMPLE_RATE..10 * SAMPLE_RATE + tone.len()].copy_from_slice(&tone);
+    pcm
+}
+
+/// Decoder treating the full windows as silence, their logits being flattened so that the
+/// sampled tokens are unlikely, while the windows starting later confidently decode
+/// `hello hello hello`.
+fn decoder(min_speech_duration: Option<f64>) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            no_speech_threshold: Some(0.),
+            min_speech_duration,
+            ..Default::default()
+        })
+        .unwrap();
+    let hello = 1;
+    let eot = TEXT_TOKENS.len();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.segment_start == 0. {
+                logits
+                    .iter_mut()
+                    .filter(|v| v.is_finite())
+                    .for_each(|v| *v = 0.);
+            } else {
+                let token = if context.step < 3 { hello } else { eot };
+                logits[token] += 100.;
+            }
+        },
+    )));
+    decoder
+}
+
+#[test]
+fn speech_in_a_silent_window_is_recovered() {
+    let mut decoder = decoder(Some(0.5));
+    let output = decoder
+        .run_pcm(&burst_pcm(1.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.segments.len(), 1);
+    let segment = &output.segments[0];
+    assert_eq!(segment.dr.text, "hello hello hello");
+    // The speech region with the margins of the VAD.
+    assert!(
+        (segment.start - 9.7).abs() < 0.05,
+        "start {}",
+        segment.start
+    );
+    let end = segment.start + segment.duration;
+    assert!((end - 11.3).abs() < 0.05, "end {end}");
+}
+
+#[test]
+fn windows_with_too_little_speech_are_dropped() {
+    for min_speech_duration in [Some(2.), None] {
+        let mut decoder = decoder(min_speech_duration);
+        let output = decoder
+            .run_pcm(&burst_pcm(1.), &RunOptions::default())
+            .unwrap();
+        assert!(output.segments.is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+use candle_whisper::{
+    builder::DecoderBuilder,
+    error::WhisperError,
+    fixtures::{sine_pcm, tiny_config_json, tiny_model_data, CapturingLogger, TEXT_TOKENS},
+    logging::Level,
+    logic::{
+        build_suppression_mask, default_suppress_tokens, DecodeOptions, Decoder, LogitsContext,
+        ModelData, RunOptions, SpecialTokens,
+    },
+};
+use serde_json::json;
+use std::{cell::RefCell, rc::Rc};
+use tokenizers::Tokenizer;
+
+/// Byte-level tokenizer of ` hello`, ` -`, `(`, `[`, `♪`, `♪♪` and their pieces, and of the
+/// bytes of `♫`, with the special tokens.
+fn tokenizer() -> Tokenizer {
+    let text = [
+        "Ġ",
+        "h",
+        "e",
+        "l",
+        "o",
+        "-",
+        "(",
+        "[",
+        "â",
+        "Ļ",
+        "ª",
+        "«",
+        "âĻ",
+        "âĻª",
+        "âĻªâĻª",
+        "Ġ-",
+        "Ġh",
+        "Ġhe",
+        "Ġhel",
+        "Ġhell",
+        "Ġhello",
+    ];
+    let merges = [
+        "â Ļ",
+        "âĻ ª",
+        "âĻª âĻª",
+        "Ġ -",
+        "Ġ h",
+        "Ġh e",
+        "Ġhe l",
+        "Ġhel l",
+        "Ġhell o",
+    ];
+    let special = [
+        "<|endoftext|>",
+        "<|startoftranscript|>",
+        "<|transcribe|>",
+        "<|nospeech|>",
+        "<|notimestamps|>",
+    ];
+    let mut vocab = json!({});
+    for (id, token) in text.iter().enumerate() {
+        vocab[token] = json!(id);
+    }
+    let added_tokens: Vec<_> = special
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let id = text.len() + i;
+            vocab[token] = json!(id);
+            json!({
+                "id": id,
+                "content": token,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            })
+        })
+        .collect();
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": { "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true },
+        "post_processor": null,
+        "decoder": { "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true },
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "vocab": vocab,
+            "merges": merges,
+        },
+    });
+    Tokenizer::from_bytes(serde_json::to_vec(&tokenizer).unwrap()).unwrap()
+}
+
+#[test]
+fn default_list_has_the_symbols_and_the_control_tokens() {
+    let tokenizer = tokenizer();
+    let special_tokens = SpecialTokens::new(&tokenizer).unwrap();
+    let id = |token: &str| tokenizer.token_to_id(token).unwrap();
+    let mut expected = vec![
+        id("Ġ-"),
+        id("("),
+        id("["),
+        id("âĻª"),
+        id("âĻªâĻª"),
+        // First token of `♫`, the vocabulary having no token of the whole symbol.
+        id("âĻ"),
+        id("<|startoftranscript|>"),
+        id("<|transcribe|>"),
+        id("<|nospeech|>"),
+    ];
+    expected.sort_unstable();
+    assert_eq!(
+        default_suppress_tokens(&tokenizer, &special_tokens),
+        expected
+    );
+}
+
+/// Tiny model data whose config has the `suppress_tokens`.
+fn model_data_with_config_list(suppress_tokens: &[u32]) -> ModelData {
+    let mut config: serde_json::Value = serde_json::from_slice(&tiny_config_json()).unwrap();
+    config["suppress_tokens"] = json!(suppress_tokens);
+    ModelData {
+        config: serde_json::to_vec(&config).unwrap(),
+        ..tiny_model_data()
+    }
+}
+
+#[test]
+fn empty_config_list_is_synthesized_on_load() {
+    let logger = CapturingLogger::default();
+    DecoderBuilder::from(tiny_model_data())
+        .logger(Rc::new(logger.clone()))
+        .build()
+        .unwrap();
+    assert!(logger.contains(Level::Info, "no suppress_tokens"));
+    assert!(logger.contains(Level::Debug, "synthesized tokens"));
+}
+
+#[test]
+fn config_list_takes_precedence() {
+    let logger = CapturingLogger::default();
+    DecoderBuilder::from(model_data_with_config_list(&[1, 2]))
+        .logger(Rc::new(logger.clone()))
+        .build()
+        .unwrap();
+    assert!(!logger.contains(Level::Info, "no suppress_tokens"));
+    assert!(logger.contains(Level::Debug, "suppressing the 2 tokens of the model config"));
+}
+
+#[test]
+fn synthesis_can_be_turned_off() {
+    let logger = CapturingLogger::default();
+    DecoderBuilder::from(tiny_model_data())
+        .options(Some(DecodeOptions {
+            synthesize_suppress_list: false,
+            ..Default::default()
+        }))
+        .logger(Rc::new(logger.clone()))
+        .build()
+        .unwrap();
+    assert!(logger.contains(Level::Debug, "none suppressed"));
+}
+
+#[test]
+fn mask_suppresses_the_listed_tokens() {
+    let mask = build_suppression_mask(8, &[1, 2], &[3, 5], &[2, 5]);
+    let suppressed: Vec<usize> = (0..8).filter(|&i| mask[i] == f32::NEG_INFINITY).collect();
+    assert_eq!(suppressed, [1, 3]);
+    assert!(mask.iter().all(|&v| v == 0. || v == f32::NEG_INFINITY));
+    // The out of vocabulary ids are ignored.
+    assert_eq!(
+        build_suppression_mask(4, &[1, 9], &[100], &[]),
+        [0., f32::NEG_INFINITY, 0., 0.]
+    );
+}
+
+const HELLO: u32 = 1;
+const WORLD: u32 = 2;
+const THE: u32 = 3;
+const SOUND: u32 = 4;
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+const TIMESTAMP_BEGIN: u32 = EOT + 6;
+
+/// Suppressed tokens at the first two steps of the first window, the model config suppressing
+/// `world` and `sound`, the first step sampling `the` or the first timestamp.
+fn suppressed_tokens(options: DecodeOptions, timestamps: bool) -> [Vec<u32>; 2] {
+    let mut decoder = Decoder::load(model_data_with_config_list(&[WORLD, SOUND])).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            ..options
+        })
+        .unwrap();
+    let steps: Rc<RefCell<Vec<Vec<u32>>>> = Rc::new(RefCell::new(vec![]));
+    let recorded = steps.clone();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            if context.step < 2 {
+                let suppressed = (0..logits.len() as u32)
+                    .filter(|&token| logits[token as usize] == f32::NEG_INFINITY)
+                    .collect();
+                recorded.borrow_mut().push(suppressed);
+            }
+            let forced = match context.step {
+                0 if timestamps => TIMESTAMP_BEGIN,
+                0 => THE,
+                _ => EOT,
+            };
+            logits.fill(f32::NEG_INFINITY);
+            logits[forced as usize] = 0.;
+        },
+    )));
+    let opts = RunOptions {
+        timestamps: Some(timestamps),
+        ..Default::default()
+    };
+    decoder.run_pcm(&sine_pcm(2., 440.), &opts).unwrap();
+    let steps = steps.take();
+    [steps[0].clone(), steps[1].clone()]
+}
+
+#[test]
+fn decoder_applies_the_suppression_options() {
+    let options = DecodeOptions {
+        extra_suppress_tokens: vec![HELLO],
+        unsuppress_tokens: vec![SOUND],
+        ..Default::default()
+    };
+    let [first, second] = suppressed_tokens(options.clone(), false);
+    let text =
+        |tokens: &[u32]| -> Vec<u32> { tokens.iter().copied().filter(|&t| t < EOT).collect() };
+    assert_eq!(text(&first), [HELLO, WORLD]);
+    assert_eq!(text(&second), [HELLO, WORLD]);
+    // The end of text is suppressed at the first step only.
+    assert!(first.contains(&EOT));
+    assert!(!second.contains(&EOT));
+
+    let [first, _] = suppressed_tokens(
+        DecodeOptions {
+            suppress_blank: false,
+            ..options
+        },
+        false,
+    );
+    assert!(!first.contains(&EOT));
+}
+
+#[test]
+fn decoder_masks_the_late_initial_timestamps() {
+    let options = DecodeOptions {
+        max_initial_timestamp: Some(1.),
+        ..Default::default()
+    };
+    let [first, second] = suppressed_tokens(options, true);
+    let last_allowed_timestamp = (TIMESTAMP_BEGIN..)
+        .take_while(|token| !first.contains(token))
+        .last();
+    // 1 second at 20ms per timestamp token.
+    assert_eq!(last_allowed_timestamp, Some(TIMESTAMP_BEGIN + 50));
+    assert!(first.contains(&(TIMESTAMP_BEGIN + 51)));
+    // Lifted once a timestamp is sampled.
+    assert!(!second.contains(&(TIMESTAMP_BEGIN + 51)));
+}
+
+#[test]
+fn out_of_vocabulary_options_are_rejected() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    for options in [
+        DecodeOptions {
+            extra_suppress_tokens: vec![100_000],
+            ..Default::default()
+        },
+        DecodeOptions {
+            unsuppress_tokens: vec![100_000],
+            ..Default::default()
+        },
+    ] {
+        let err = decoder.set_options(options).unwrap_err();
+        assert!(matches!(err, WhisperError::InvalidConfig { .. }), "{err:?}");
+    }
+}
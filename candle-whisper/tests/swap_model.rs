@@ -0,0 +1,99 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::{multilingual_model_data, sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, LogitsContext, ModelData, PartialHypothesis, RunOptions},
+};
+use std::{cell::Cell, rc::Rc};
+
+fn options() -> DecodeOptions {
+    DecodeOptions {
+        temperatures: vec![0.],
+        no_speech_threshold: None,
+        max_tokens_per_segment: Some(2),
+        max_prompt_tokens: Some(4),
+        ..Default::default()
+    }
+}
+
+fn transcribe(decoder: &mut Decoder) -> Vec<Vec<u32>> {
+    decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap()
+        .segments
+        .into_iter()
+        .map(|segment| segment.dr.tokens)
+        .collect()
+}
+
+#[test]
+fn options_persist_across_a_swap() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder.set_options(options()).unwrap();
+    let before = serde_json::to_value(decoder.options()).unwrap();
+    let expected = transcribe(&mut decoder);
+
+    decoder.swap_model(multilingual_model_data()).unwrap();
+    assert_eq!(serde_json::to_value(decoder.options()).unwrap(), before);
+
+    // Back to the first model, the transcription is the same as before the swaps.
+    decoder.swap_model(tiny_model_data()).unwrap();
+    assert_eq!(serde_json::to_value(decoder.options()).unwrap(), before);
+    assert_eq!(transcribe(&mut decoder), expected);
+}
+
+#[test]
+fn failed_swap_keeps_the_current_model() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder.set_options(options()).unwrap();
+    let expected = transcribe(&mut decoder);
+    let model_info = serde_json::to_value(decoder.model_info()).unwrap();
+
+    let md = tiny_model_data();
+    let mut weights = md.weights;
+    weights.truncate(weights.len() / 2);
+    let err = decoder.swap_model(ModelData { weights, ..md }).unwrap_err();
+    assert!(
+        matches!(err, WhisperError::CorruptWeights { .. }),
+        "{err:?}"
+    );
+
+    assert_eq!(
+        serde_json::to_value(decoder.model_info()).unwrap(),
+        model_info
+    );
+    assert_eq!(
+        serde_json::to_value(decoder.options()).unwrap(),
+        serde_json::to_value(options()).unwrap()
+    );
+    assert_eq!(transcribe(&mut decoder), expected);
+}
+
+#[test]
+fn hooks_survive_a_swap() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            partial_interval_tokens: 1,
+            max_tokens_per_segment: None,
+            ..options()
+        })
+        .unwrap();
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    decoder.set_logits_processor(Some(Box::new(move |_: &mut [f32], _: &LogitsContext| {
+        counter.set(counter.get() + 1)
+    })));
+    let partials = Rc::new(Cell::new(0));
+    let counter = partials.clone();
+    decoder.set_on_partial(Some(Box::new(move |_: PartialHypothesis| {
+        counter.set(counter.get() + 1)
+    })));
+    transcribe(&mut decoder);
+    let (calls_before, partials_before) = (calls.get(), partials.get());
+    assert!(calls_before > 0 && partials_before > 0);
+
+    decoder.swap_model(tiny_model_data()).unwrap();
+    transcribe(&mut decoder);
+    assert_eq!(calls.get(), 2 * calls_before);
+    assert_eq!(partials.get(), 2 * partials_before);
+}
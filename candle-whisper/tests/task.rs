@@ -0,0 +1,117 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::{
+        english_only_model_data, large_vocab_model_data, multilingual_model_data, sine_pcm,
+        tiny_model_data,
+    },
+    logic::{Decoder, ModelData, RunOptions, Task},
+};
+use tokenizers::Tokenizer;
+
+fn with_task(md: ModelData, task: &str) -> ModelData {
+    ModelData {
+        task: Some(task.to_string()),
+        ..md
+    }
+}
+
+/// Reason of the `InvalidConfig` error of loading `md`.
+fn load_error(md: ModelData) -> String {
+    match Decoder::load(md) {
+        Err(WhisperError::InvalidConfig { reason }) => reason,
+        Err(err) => panic!("unexpected error {err:?}"),
+        Ok(_) => panic!("loaded"),
+    }
+}
+
+#[test]
+fn multilingual_model_translates() {
+    let md = with_task(large_vocab_model_data(), "translate");
+    let translate = Tokenizer::from_bytes(&md.tokenizer)
+        .unwrap()
+        .token_to_id("<|translate|>")
+        .unwrap();
+    let mut decoder = Decoder::load(md).unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.task, Task::Translate);
+    assert!(output.language.is_some());
+    // `<|startoftranscript|>`, the detected language then the task.
+    assert_eq!(output.segments[0].dr.tokens[2], translate);
+}
+
+#[test]
+fn english_only_model_rejects_translate() {
+    let reason = load_error(with_task(tiny_model_data(), "translate"));
+    assert!(reason.contains("requires a multilingual model"), "{reason}");
+
+    // Flagged multilingual but with the vocabulary of an English-only model.
+    let md = ModelData {
+        is_multilingual: true,
+        ..with_task(multilingual_model_data(), "translate")
+    };
+    let reason = load_error(md);
+    assert!(reason.contains("requires a multilingual model"), "{reason}");
+
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    assert!(decoder.set_task(Task::Translate).is_err());
+    let output = decoder
+        .run_pcm(&sine_pcm(2., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.task, Task::Transcribe);
+    assert_eq!(output.language, None);
+}
+
+#[test]
+fn english_only_tokenizer_needs_no_task_tokens() {
+    let md = english_only_model_data();
+    let tokenizer = Tokenizer::from_bytes(&md.tokenizer).unwrap();
+    assert_eq!(tokenizer.token_to_id("<|translate|>"), None);
+    assert_eq!(tokenizer.token_to_id("<|transcribe|>"), None);
+
+    let reason = load_error(with_task(md, "translate"));
+    assert!(reason.contains("requires a multilingual model"), "{reason}");
+
+    // English-only models are prompted without a task token.
+    let md = with_task(english_only_model_data(), "transcribe");
+    let mut decoder = Decoder::load(md).unwrap();
+    assert!(decoder.set_task(Task::Translate).is_err());
+    let output = decoder
+        .run_pcm(&sine_pcm(2., 440.), &RunOptions::default())
+        .unwrap();
+    assert_eq!(output.task, Task::Transcribe);
+    let sot = tokenizer.token_to_id("<|startoftranscript|>").unwrap();
+    let no_timestamps = tokenizer.token_to_id("<|notimestamps|>").unwrap();
+    assert_eq!(output.segments[0].dr.tokens[..2], [sot, no_timestamps]);
+}
+
+/// [`large_vocab_model_data`] with `<|translate|>` renamed, keeping its id.
+fn without_translate_token() -> ModelData {
+    let md = large_vocab_model_data();
+    let tokenizer = String::from_utf8(md.tokenizer)
+        .unwrap()
+        .replace("<|translate|>", "<|translation|>");
+    ModelData {
+        tokenizer: tokenizer.into_bytes(),
+        ..md
+    }
+}
+
+#[test]
+fn multilingual_tokenizer_without_translate_token_is_rejected() {
+    let reason = load_error(with_task(without_translate_token(), "translate"));
+    assert!(reason.contains("has no <|translate|> token"), "{reason}");
+
+    let mut decoder = Decoder::load(without_translate_token()).unwrap();
+    let err = decoder.set_task(Task::Translate).unwrap_err();
+    assert!(err.to_string().contains("<|translate|>"), "{err}");
+}
+
+#[test]
+fn unknown_task_is_rejected() {
+    let reason = load_error(with_task(tiny_model_data(), "trnaslate"));
+    assert!(reason.contains("unknown task 'trnaslate'"), "{reason}");
+    assert!("Translate".parse::<Task>().is_err());
+    assert_eq!("transcribe".parse::<Task>().unwrap(), Task::Transcribe);
+}
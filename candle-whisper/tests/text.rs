@@ -0,0 +1,129 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::tiny_model_data,
+    logic::{DecodeOptions, Decoder},
+    text::{capitalize_sentences, Replacement, TextOptions, TextPostProcessor},
+};
+
+fn replacement(pattern: &str, replacement: &str) -> Replacement {
+    Replacement {
+        pattern: pattern.to_string(),
+        replacement: replacement.to_string(),
+    }
+}
+
+fn all_steps(replacements: Vec<Replacement>) -> TextPostProcessor {
+    TextPostProcessor::new(&TextOptions {
+        collapse_whitespace: true,
+        nfc: true,
+        capitalize_sentences: true,
+        replacements,
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+#[test]
+fn disabled_processor_keeps_no_clean_text() {
+    let processor = TextPostProcessor::new(&TextOptions::default()).unwrap();
+    assert_eq!(processor.process(" raw  text"), None);
+}
+
+#[test]
+fn whitespace_is_trimmed_and_collapsed() {
+    let processor = TextPostProcessor::new(&TextOptions {
+        collapse_whitespace: true,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        processor.process("  hello \t  world\n ").as_deref(),
+        Some("hello world")
+    );
+}
+
+#[test]
+fn text_is_normalized_to_nfc() {
+    let processor = TextPostProcessor::new(&TextOptions {
+        nfc: true,
+        ..Default::default()
+    })
+    .unwrap();
+    // `e` followed by a combining acute accent.
+    assert_eq!(
+        processor.process("caf\u{0065}\u{0301}").as_deref(),
+        Some("caf\u{00e9}")
+    );
+}
+
+#[test]
+fn sentences_are_capitalized_in_latin_scripts_only() {
+    assert_eq!(
+        capitalize_sentences("hello. how are you? fine! (really.) \"yes.\" élan"),
+        "Hello. How are you? Fine! (Really.) \"Yes.\" Élan"
+    );
+    // No sentence break without a space.
+    assert_eq!(capitalize_sentences("v1.two"), "V1.two");
+    // CJK and Cyrillic letters have no case to change, or are left alone.
+    assert_eq!(capitalize_sentences("你好。 世界"), "你好。 世界");
+    assert_eq!(capitalize_sentences("привет. мир"), "привет. мир");
+    // A sentence starting with a CJK character is not capitalized further on.
+    assert_eq!(capitalize_sentences("日本 tokyo"), "日本 tokyo");
+}
+
+#[test]
+fn replacements_apply_in_order_after_the_other_steps() {
+    let processor = all_steps(vec![
+        replacement(r"\bk8s\b", "kubernetes"),
+        replacement(r"\bkubernetes\b", "Kubernetes"),
+        replacement(r"(\d+) percent", "$1%"),
+    ]);
+    assert_eq!(
+        processor
+            .process(" we run  k8s. 50 percent of it")
+            .as_deref(),
+        Some("We run Kubernetes. 50% of it")
+    );
+    // Reversed, the first pattern does not see the output of the second.
+    let reversed = all_steps(vec![
+        replacement(r"\bkubernetes\b", "Kubernetes"),
+        replacement(r"\bk8s\b", "kubernetes"),
+    ]);
+    assert_eq!(
+        reversed.process("we run k8s").as_deref(),
+        Some("We run kubernetes")
+    );
+}
+
+#[test]
+fn processing_is_idempotent() {
+    let processor = all_steps(vec![replacement(r"\bk8s\b", "Kubernetes")]);
+    for text in [
+        "  hello   world. the sound of k8s ",
+        " 你好，世界。  mixed 文本. ok",
+        "cafe\u{0301}. déjà vu",
+    ] {
+        let once = processor.process(text).unwrap();
+        assert_eq!(processor.process(&once).unwrap(), once, "{text:?}");
+    }
+}
+
+#[test]
+fn invalid_patterns_fail_when_the_options_are_set() {
+    let options = TextOptions {
+        replacements: vec![replacement("(unclosed", "x")],
+        ..Default::default()
+    };
+    assert!(matches!(
+        TextPostProcessor::new(&options),
+        Err(WhisperError::InvalidConfig { .. })
+    ));
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let err = decoder
+        .set_options(DecodeOptions {
+            text: options,
+            ..Default::default()
+        })
+        .unwrap_err();
+    assert!(err.to_string().contains("(unclosed"), "{err}");
+}
@@ -0,0 +1,103 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{m, DecodeOptions, Decoder, RunOptions, TranscriptionOutput},
+};
+
+fn decoder() -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    decoder
+}
+
+fn range(start: Option<f64>, end: Option<f64>) -> RunOptions {
+    RunOptions {
+        start,
+        end,
+        ..Default::default()
+    }
+}
+
+/// Start, duration and text of the segments.
+fn summary(output: &TranscriptionOutput) -> Vec<(f64, f64, String)> {
+    output
+        .segments
+        .iter()
+        .map(|s| (s.start, s.duration, s.dr.text.clone()))
+        .collect()
+}
+
+#[test]
+fn range_segments_start_at_the_range() {
+    // A chirp, so that every range of it sounds different.
+    let pcm: Vec<f32> = (0..25 * m::SAMPLE_RATE)
+        .map(|i| {
+            let t = i as f32 / m::SAMPLE_RATE as f32;
+            0.5 * (2. * std::f32::consts::PI * (200. + 20. * t) * t).sin()
+        })
+        .collect();
+    let output = decoder()
+        .run_pcm(&pcm, &range(Some(10.), Some(20.)))
+        .unwrap();
+    assert!(!output.segments.is_empty());
+    assert_eq!(output.segments[0].start, 10.);
+    for segment in &output.segments {
+        assert!(segment.start >= 10. && segment.start + segment.duration <= 20. + 1e-9);
+    }
+
+    // The same as transcribing the samples of the range, shifted by its start.
+    let slice = &pcm[10 * m::SAMPLE_RATE..20 * m::SAMPLE_RATE];
+    let expected = decoder().run_pcm(slice, &RunOptions::default()).unwrap();
+    let shifted: Vec<(f64, f64, String)> = summary(&expected)
+        .into_iter()
+        .map(|(start, duration, text)| (start + 10., duration, text))
+        .collect();
+    assert_eq!(summary(&output), shifted);
+
+    // The spectrogram is narrowed to the frames of the range.
+    let mut decoder = decoder();
+    let mel = decoder.compute_mel(&pcm).unwrap();
+    let from_mel = decoder.run_mel(&mel, &range(Some(10.), Some(20.))).unwrap();
+    assert_eq!(from_mel.segments[0].start, 10.);
+    assert_eq!(summary(&from_mel).len(), summary(&output).len());
+}
+
+#[test]
+fn range_end_is_clamped_to_the_audio() {
+    let pcm = sine_pcm(12., 440.);
+    let clamped = decoder()
+        .run_pcm(&pcm, &range(Some(4.), Some(1000.)))
+        .unwrap();
+    let open = decoder().run_pcm(&pcm, &range(Some(4.), None)).unwrap();
+    assert_eq!(summary(&clamped), summary(&open));
+    assert_eq!(open.segments[0].start, 4.);
+    let last = open.segments.last().unwrap();
+    assert!(last.start + last.duration <= 12. + 1e-9);
+}
+
+#[test]
+fn empty_ranges_are_rejected() {
+    let pcm = sine_pcm(5., 440.);
+    for (start, end) in [(3., 2.), (2., 2.)] {
+        let err = decoder()
+            .run_pcm(&pcm, &range(Some(start), Some(end)))
+            .unwrap_err();
+        assert!(matches!(err, WhisperError::InvalidConfig { .. }), "{err:?}");
+    }
+    let mut decoder = decoder();
+    let mel = decoder.compute_mel(&pcm).unwrap();
+    let err = decoder
+        .run_mel(&mel, &range(Some(3.), Some(1.)))
+        .unwrap_err();
+    assert!(matches!(err, WhisperError::InvalidConfig { .. }), "{err:?}");
+
+    // A range past the end of the audio has nothing to transcribe.
+    let output = decoder.run_pcm(&pcm, &range(Some(10.), Some(20.))).unwrap();
+    assert!(output.segments.is_empty());
+}
@@ -0,0 +1,95 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, LogitsContext, RunOptions, TranscriptionOutput},
+};
+
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+const SOT: u32 = EOT + 1;
+const NO_TIMESTAMPS: u32 = EOT + 5;
+const HELLO: u32 = 1;
+
+/// Timestamp token of `seconds`.
+fn ts(seconds: f64) -> u32 {
+    NO_TIMESTAMPS + 1 + (seconds / 0.02).round() as u32
+}
+
+/// Decoder forced to sample `<|0.00|> hello <|10.00|><|10.00|>` in every window.
+fn scripted_decoder() -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                drop: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let script = [ts(0.), HELLO, ts(10.), ts(10.), EOT];
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            let forced = script[usize::min(context.step, script.len() - 1)];
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if token as u32 != forced {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        },
+    )));
+    decoder
+}
+
+fn run(decoder: &mut Decoder, timestamps: Option<bool>) -> TranscriptionOutput {
+    let opts = RunOptions {
+        timestamps,
+        ..Default::default()
+    };
+    decoder.run_pcm(&sine_pcm(25., 440.), &opts).unwrap()
+}
+
+fn starts(output: &TranscriptionOutput) -> Vec<f64> {
+    output.segments.iter().map(|s| s.start).collect()
+}
+
+#[test]
+fn timestamps_mode_is_chosen_per_run() {
+    let mut decoder = scripted_decoder();
+    let on = run(&mut decoder, Some(true));
+    let off = run(&mut decoder, Some(false));
+
+    // Only the prompt differs, by `<|notimestamps|>`.
+    let mut expected = on.segments[0].dr.tokens.clone();
+    assert_eq!(expected[0], SOT);
+    expected.insert(1, NO_TIMESTAMPS);
+    assert_eq!(off.segments[0].dr.tokens, expected);
+
+    // The windows resume from the last timestamp pair only with the timestamps.
+    assert_eq!(starts(&on), [0., 10., 20.]);
+    assert_eq!(starts(&off), [0.]);
+    assert!(on.segments[0].duration <= 10. + 1e-9);
+    assert!(off.segments[0].duration > 20.);
+}
+
+#[test]
+fn runs_do_not_leak_their_mode() {
+    let mut decoder = scripted_decoder();
+    let first = run(&mut decoder, Some(false));
+    let on = run(&mut decoder, Some(true));
+    let last = run(&mut decoder, None);
+    let tokens = |output: &TranscriptionOutput| -> Vec<Vec<u32>> {
+        output
+            .segments
+            .iter()
+            .map(|s| s.dr.tokens.clone())
+            .collect()
+    };
+    // The tiny model is loaded without timestamps.
+    assert_eq!(tokens(&first), tokens(&last));
+    assert_eq!(starts(&first), starts(&last));
+    let fresh = run(&mut scripted_decoder(), Some(true));
+    assert_eq!(tokens(&on), tokens(&fresh));
+    assert_eq!(starts(&on), starts(&fresh));
+}
@@ -0,0 +1,85 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, sine_wav, tiny_model_data, TEXT_TOKENS},
+    logic::{DecodeOptions, Decoder, LogitsContext, RunOptions},
+    timings::{Clock, Timings},
+};
+use std::{cell::Cell, rc::Rc};
+
+const HELLO: u32 = 1;
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+/// Clock only moving when the test advances it.
+struct ManualClock(Rc<Cell<f64>>);
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> f64 {
+        self.0.get()
+    }
+}
+
+/// Decoder whose every decoder step takes 10ms, sampling `hello` three times then the end of
+/// text in every window.
+fn decoder() -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            collect_timings: true,
+            ..Default::default()
+        })
+        .unwrap();
+    let now = Rc::new(Cell::new(1000.));
+    decoder.set_clock(Box::new(ManualClock(now.clone())));
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            now.set(now.get() + 10.);
+            let forced = if context.step < 3 { HELLO } else { EOT };
+            logits.fill(f32::NEG_INFINITY);
+            logits[forced as usize] = 0.;
+        },
+    )));
+    decoder
+}
+
+fn assert_windows(timings: &Timings) {
+    // The other stages take no time on the clock.
+    assert_eq!(timings.pcm_decode_ms, 0.);
+    assert_eq!(timings.mel_ms, 0.);
+    assert_eq!(timings.encoder_ms, [0., 0.]);
+    // Four steps of 10ms per window.
+    assert_eq!(timings.decode_ms, [40., 40.]);
+    assert_eq!(timings.tokens_per_sec, [100., 100.]);
+    assert_eq!(timings.total_ms, 80.);
+    // The logits processor runs on the host.
+    let host = &timings.host_path;
+    assert_eq!((host.passes, host.tokens, host.decode_ms), (2, 8, 80.));
+    assert_eq!(host.tokens_per_sec, 100.);
+    assert_eq!(timings.greedy_fast_path.passes, 0);
+}
+
+#[test]
+fn stages_are_timed_on_the_clock() {
+    let output = decoder()
+        .run_pcm(&sine_pcm(35., 440.), &RunOptions::default())
+        .unwrap();
+    assert_windows(output.timings.as_ref().unwrap());
+
+    let output = decoder().convert_and_run(&sine_wav(35., 440.)).unwrap();
+    assert_windows(output.timings.as_ref().unwrap());
+}
+
+#[test]
+fn timings_are_only_collected_on_request() {
+    let mut decoder = decoder();
+    decoder
+        .set_options(DecodeOptions {
+            collect_timings: false,
+            ..Default::default()
+        })
+        .unwrap();
+    let output = decoder
+        .run_pcm(&sine_pcm(5., 440.), &RunOptions::default())
+        .unwrap();
+    assert!(output.timings.is_none());
+}
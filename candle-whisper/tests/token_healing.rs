@@ -0,0 +1,149 @@
+use candle_whisper::{
+    fixtures::{multilingual_model_data, multilingual_tokenizer_json, sine_pcm, TEXT_TOKENS},
+    hallucination::HallucinationOptions,
+    logic::{DecodeOptions, Decoder, LogitsContext, ModelData, RunOptions, Segment},
+    prompt::PrefixIndex,
+};
+use serde_json::json;
+use std::{cell::RefCell, rc::Rc};
+use tokenizers::Tokenizer;
+
+/// Byte-level text tokens replacing the ones of [`TEXT_TOKENS`], with the same ids.
+const BYTE_LEVEL_TOKENS: [&str; 11] = [
+    "Ġhello", "Ġworld", "Ġthe", "Ġsound", "Ġof", "Ġa", "Ġsi", "Ġsine", "ne", "Ġwave", ".",
+];
+const THE: u32 = 2;
+const SI: u32 = 6;
+const SINE: u32 = 7;
+const WAVE: u32 = 9;
+const PERIOD: u32 = 10;
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+/// [`multilingual_tokenizer_json`] made byte-level with the text tokens of
+/// [`BYTE_LEVEL_TOKENS`].
+fn byte_level_tokenizer_json() -> Vec<u8> {
+    let mut tokenizer: serde_json::Value =
+        serde_json::from_slice(&multilingual_tokenizer_json()).unwrap();
+    let mut vocab: serde_json::Map<_, _> = tokenizer["model"]["vocab"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .filter(|(_, id)| id.as_u64().unwrap() >= EOT as u64)
+        .map(|(token, id)| (token.clone(), id.clone()))
+        .collect();
+    for (id, token) in BYTE_LEVEL_TOKENS.iter().enumerate() {
+        vocab.insert(token.to_string(), json!(id));
+    }
+    let byte_level =
+        json!({ "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true });
+    tokenizer["pre_tokenizer"] = byte_level.clone();
+    tokenizer["decoder"] = byte_level;
+    tokenizer["model"] = json!({
+        "type": "BPE",
+        "dropout": null,
+        "unk_token": null,
+        "continuing_subword_prefix": null,
+        "end_of_word_suffix": null,
+        "fuse_unk": false,
+        "vocab": vocab,
+        "merges": [],
+    });
+    serde_json::to_vec(&tokenizer).unwrap()
+}
+
+/// Text tokens whose logits are finite at the first step of every window, keyed by the start
+/// of the window.
+type FirstSteps = Rc<RefCell<Vec<(f64, Vec<u32>)>>>;
+
+/// Runs two windows with the previous text, the first one forced to `first_window` and the
+/// second one to a single text token sampled among the allowed ones.
+fn run(first_window: &'static [u32]) -> (Vec<Segment>, Vec<(f64, Vec<u32>)>) {
+    let data = ModelData {
+        tokenizer: byte_level_tokenizer_json(),
+        ..multilingual_model_data()
+    };
+    let mut decoder = Decoder::load(data).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            condition_on_previous_text: true,
+            no_speech_threshold: None,
+            hallucination: HallucinationOptions {
+                similarity_threshold: 2.,
+                max_ngram_coverage: 1.,
+                blocklist: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let first_steps = FirstSteps::default();
+    let recorded = first_steps.clone();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            let step = context.step;
+            if step == 0 {
+                let allowed = (0..EOT).filter(|&t| logits[t as usize].is_finite());
+                recorded
+                    .borrow_mut()
+                    .push((context.segment_start, allowed.collect()));
+            }
+            let forced = if context.segment_start == 0. {
+                first_window.get(step).copied().unwrap_or(EOT)
+            } else if step == 0 {
+                // The best allowed text token.
+                return logits[EOT as usize..].fill(f32::NEG_INFINITY);
+            } else {
+                EOT
+            };
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if token as u32 != forced {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        },
+    )));
+    let output = decoder
+        .run_pcm(&sine_pcm(35., 440.), &RunOptions::default())
+        .unwrap();
+    let first_steps = first_steps.borrow().clone();
+    (output.segments, first_steps)
+}
+
+#[test]
+fn prefix_index_finds_the_continuations() {
+    let tokenizer = Tokenizer::from_bytes(byte_level_tokenizer_json()).unwrap();
+    let index = PrefixIndex::for_tokenizer(&tokenizer, EOT).unwrap();
+    assert_eq!(index.token_bytes(SINE), b" sine");
+    assert_eq!(index.tokens_with_prefix(b" si"), [SI, SINE]);
+    assert_eq!(index.tokens_with_prefix(b" s").len(), 3);
+    assert!(index.tokens_with_prefix(b" x").is_empty());
+    assert!(index.ends_inside_word(SI));
+    assert!(!index.ends_inside_word(PERIOD));
+
+    // The word-level tokenizers never split the words.
+    let word_level = Tokenizer::from_bytes(multilingual_tokenizer_json()).unwrap();
+    assert!(PrefixIndex::for_tokenizer(&word_level, EOT).is_none());
+}
+
+#[test]
+fn prompt_ending_inside_a_word_is_healed() {
+    let (segments, first_steps) = run(&[THE, SI]);
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].dr.tokens[2..], [THE, SI, EOT]);
+    // ` si` is dropped from the prompt and has to be continued.
+    assert_eq!(segments[1].prompt_tokens, 1);
+    let (start, allowed) = &first_steps[1];
+    assert!(*start > 0.);
+    assert_eq!(allowed, &[SI, SINE]);
+    let healed = segments[1].dr.tokens[2];
+    assert!([SI, SINE].contains(&healed), "{healed}");
+}
+
+#[test]
+fn prompt_ending_cleanly_is_untouched() {
+    let (segments, first_steps) = run(&[THE, WAVE, PERIOD]);
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[1].prompt_tokens, 3);
+    let (_, allowed) = &first_steps[1];
+    assert_eq!(allowed.len(), BYTE_LEVEL_TOKENS.len());
+}
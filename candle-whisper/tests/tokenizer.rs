@@ -0,0 +1,154 @@
+use candle_whisper::{
+    fixtures::{
+        multilingual_model_data, tiny_config_json, tiny_model_data, tiny_weights, SPECIAL_TOKENS,
+    },
+    logic::{Decoder, ModelData, SpecialTokens},
+};
+use serde_json::json;
+
+/// Characters standing for the bytes in the byte-level vocabularies, the printable ones standing
+/// for themselves.
+fn byte_chars() -> Vec<char> {
+    let printable = |b: u8| matches!(b, b'!'..=b'~' | 0xa1..=0xac | 0xae..=0xff);
+    let mut unprintable = 0;
+    (0..=255u8)
+        .map(|b| {
+            if printable(b) {
+                b as char
+            } else {
+                unprintable += 1;
+                char::from_u32(255 + unprintable).unwrap()
+            }
+        })
+        .collect()
+}
+
+/// [`tiny_model_data`] with a byte-level tokenizer of the 256 bytes, without merges, so that
+/// any text is encoded.
+fn byte_level_model_data() -> ModelData {
+    let text_tokens: Vec<String> = byte_chars().into_iter().map(String::from).collect();
+    let mut vocab = json!({});
+    for (id, token) in text_tokens
+        .iter()
+        .map(String::as_str)
+        .chain(SPECIAL_TOKENS.iter().copied())
+        .enumerate()
+    {
+        vocab[token] = json!(id);
+    }
+    let added_tokens: Vec<_> = SPECIAL_TOKENS
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            json!({
+                "id": text_tokens.len() + i,
+                "content": token,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            })
+        })
+        .collect();
+    let byte_level =
+        json!({ "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true });
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": byte_level,
+        "post_processor": null,
+        "decoder": byte_level,
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "vocab": vocab,
+            "merges": [],
+        },
+    });
+    let mut config: serde_json::Value = serde_json::from_slice(&tiny_config_json()).unwrap();
+    config["vocab_size"] = json!(256 + SPECIAL_TOKENS.len() + 1501);
+    let config = serde_json::to_vec(&config).unwrap();
+    let weights = tiny_weights(&serde_json::from_slice(&config).unwrap()).unwrap();
+    ModelData {
+        weights,
+        tokenizer: serde_json::to_vec(&tokenizer).unwrap(),
+        config,
+        ..tiny_model_data()
+    }
+}
+
+#[test]
+fn multilingual_text_round_trips() {
+    let decoder = Decoder::load(byte_level_model_data()).unwrap();
+    for text in [
+        "hello world",
+        "Grüße aus Köln, ça va ?",
+        "Привет, мир",
+        "你好，世界",
+        "こんにちは 🎵",
+    ] {
+        let tokens = decoder.encode_text(text).unwrap();
+        // One token per byte without merges.
+        assert_eq!(tokens.len(), text.len());
+        assert!(tokens.iter().all(|&token| token < 256));
+        assert_eq!(decoder.decode_tokens(&tokens, true).unwrap(), text);
+    }
+
+    let eot = decoder.special_tokens().eot;
+    let mut tokens = decoder.encode_text("Köln").unwrap();
+    tokens.push(eot);
+    assert_eq!(decoder.decode_tokens(&tokens, true).unwrap(), "Köln");
+    assert_eq!(
+        decoder.decode_tokens(&tokens, false).unwrap(),
+        "Köln<|endoftext|>"
+    );
+}
+
+#[test]
+fn special_tokens_have_the_ids_of_the_tokenizer() {
+    let decoder = Decoder::load(tiny_model_data()).unwrap();
+    let special_tokens = decoder.special_tokens();
+    assert_eq!(
+        special_tokens,
+        SpecialTokens {
+            sot: 12,
+            eot: 11,
+            translate: Some(13),
+            transcribe: Some(14),
+            no_timestamps: 16,
+            no_speech: 15,
+            start_of_prev: None,
+            timestamp_begin: 17,
+        }
+    );
+    assert_eq!(
+        special_tokens.timestamp_begin,
+        special_tokens.no_timestamps + 1
+    );
+    assert_eq!(decoder.vocab_size(), 17);
+
+    // The 99 language tokens follow `<|startoftranscript|>`.
+    let decoder = Decoder::load(multilingual_model_data()).unwrap();
+    assert_eq!(
+        decoder.special_tokens(),
+        SpecialTokens {
+            sot: 12,
+            eot: 11,
+            translate: Some(112),
+            transcribe: Some(113),
+            no_timestamps: 117,
+            no_speech: 116,
+            start_of_prev: Some(115),
+            timestamp_begin: 118,
+        }
+    );
+    assert_eq!(decoder.vocab_size(), 118);
+}
@@ -0,0 +1,73 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{m, DecodeOptions, Decoder, RunOptions, TimestampOrigin, TranscriptionOutput},
+};
+
+/// 5 seconds of silence, a 3-second tone and 5 seconds of silence.
+fn clip() -> Vec<f32> {
+    let silence = vec![0.; 5 * m::SAMPLE_RATE];
+    [silence.clone(), sine_pcm(3., 440.), silence].concat()
+}
+
+fn run(pcm: &[f32], trim_silence: bool, timestamp_origin: TimestampOrigin) -> TranscriptionOutput {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            trim_silence,
+            timestamp_origin,
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    decoder.run_pcm(pcm, &RunOptions::default()).unwrap()
+}
+
+/// Mel frames of the audio transcribed, the segments of the tiny model spanning it.
+fn audio_frames(output: &TranscriptionOutput) -> usize {
+    let seconds: f64 = output.segments.iter().map(|s| s.duration).sum();
+    (seconds * m::SAMPLE_RATE as f64 / m::HOP_LENGTH as f64).round() as usize
+}
+
+#[test]
+fn silence_around_the_speech_is_trimmed() {
+    let untrimmed = run(&clip(), false, TimestampOrigin::Original);
+    assert!(untrimmed.silence_trim.is_none());
+    assert_eq!(audio_frames(&untrimmed), 1300);
+
+    let original = run(&clip(), true, TimestampOrigin::Original);
+    let trim = original.silence_trim.unwrap();
+    assert!((trim.leading - 4.8).abs() < 0.1, "{trim:?}");
+    assert!((trim.trailing - 4.8).abs() < 0.1, "{trim:?}");
+    // The tone and its padding.
+    let frames = audio_frames(&original);
+    assert!((330..=360).contains(&frames), "{frames} frames");
+    let start = original.segments[0].start;
+    assert!((4.7..=5.).contains(&start), "first segment at {start}s");
+
+    let trimmed = run(&clip(), true, TimestampOrigin::Trimmed);
+    assert_eq!(trimmed.silence_trim, Some(trim));
+    assert_eq!(audio_frames(&trimmed), frames);
+    let start = trimmed.segments[0].start;
+    assert!(start < 0.1, "first segment at {start}s");
+}
+
+#[test]
+fn audio_without_speech_is_not_trimmed() {
+    let output = run(
+        &vec![0.; 8 * m::SAMPLE_RATE],
+        true,
+        TimestampOrigin::Original,
+    );
+    assert!(output.silence_trim.is_none());
+}
+
+#[test]
+fn negative_padding_is_rejected() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let options = DecodeOptions {
+        trim_silence_padding: -0.1,
+        ..Default::default()
+    };
+    assert!(decoder.set_options(options).is_err());
+}
@@ -0,0 +1,131 @@
+use candle_whisper::{
+    audio::{detect_speech_regions, VadOptions},
+    diagnostics::WindowOutcome,
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{m, DecodeOptions, Decoder, RunOptions},
+};
+
+const SAMPLE_RATE: usize = m::SAMPLE_RATE;
+
+fn silence(seconds: f64) -> Vec<f32> {
+    vec![0.; (seconds * SAMPLE_RATE as f64) as usize]
+}
+
+fn scaled(pcm: Vec<f32>, gain: f32) -> Vec<f32> {
+    pcm.into_iter().map(|v| v * gain).collect()
+}
+
+/// Checks that `regions` match `expected` within a frame and the hangover.
+fn assert_regions(regions: &[(f64, f64)], expected: &[(f64, f64)]) {
+    assert_eq!(regions.len(), expected.len(), "{regions:?}");
+    for (&(start, end), &(expected_start, expected_end)) in regions.iter().zip(expected) {
+        assert!((start - expected_start).abs() < 0.05, "{regions:?}");
+        assert!((end - expected_end).abs() < 0.05, "{regions:?}");
+    }
+}
+
+#[test]
+fn tone_between_silences_is_one_region() {
+    let pcm = [silence(2.), sine_pcm(1., 440.), silence(2.)].concat();
+    let regions = detect_speech_regions(&pcm, SAMPLE_RATE, &VadOptions::default());
+    // Widened by the 0.3s margin.
+    assert_regions(&regions, &[(1.7, 3.3)]);
+
+    let opts = VadOptions {
+        margin_secs: 0.,
+        ..Default::default()
+    };
+    let regions = detect_speech_regions(&pcm, SAMPLE_RATE, &opts);
+    assert_regions(&regions, &[(2., 3.)]);
+}
+
+#[test]
+fn bursts_are_separate_regions_unless_close() {
+    let opts = VadOptions {
+        margin_secs: 0.,
+        ..Default::default()
+    };
+    let pcm = [
+        silence(1.),
+        sine_pcm(0.5, 440.),
+        silence(3.),
+        sine_pcm(0.5, 440.),
+        silence(1.),
+    ]
+    .concat();
+    let regions = detect_speech_regions(&pcm, SAMPLE_RATE, &opts);
+    assert_regions(&regions, &[(1., 1.5), (4.5, 5.)]);
+
+    // A pause shorter than the hangover does not close the region.
+    let pcm = [
+        silence(1.),
+        sine_pcm(0.5, 440.),
+        silence(0.2),
+        sine_pcm(0.5, 440.),
+        silence(1.),
+    ]
+    .concat();
+    let regions = detect_speech_regions(&pcm, SAMPLE_RATE, &opts);
+    assert_regions(&regions, &[(1., 2.2)]);
+
+    // Too short to be speech.
+    let pcm = [silence(1.), sine_pcm(0.05, 440.), silence(1.)].concat();
+    assert!(detect_speech_regions(&pcm, SAMPLE_RATE, &opts).is_empty());
+}
+
+#[test]
+fn quiet_recordings_use_their_noise_floor() {
+    let opts = VadOptions {
+        margin_secs: 0.,
+        ..Default::default()
+    };
+    // A faint hum under a quiet tone, both far below the levels of a normal recording.
+    let hum = scaled(sine_pcm(5., 100.), 0.001);
+    let tone = [silence(2.), scaled(sine_pcm(1., 440.), 0.02), silence(2.)].concat();
+    let pcm: Vec<f32> = hum.iter().zip(&tone).map(|(a, b)| a + b).collect();
+    let regions = detect_speech_regions(&pcm, SAMPLE_RATE, &opts);
+    assert_regions(&regions, &[(2., 3.)]);
+}
+
+#[test]
+fn silence_and_noise_have_no_speech() {
+    let opts = VadOptions::default();
+    assert!(detect_speech_regions(&[], SAMPLE_RATE, &opts).is_empty());
+    assert!(detect_speech_regions(&silence(3.), SAMPLE_RATE, &opts).is_empty());
+    // Loud noise crosses zero too often to be voiced speech.
+    let mut state = 1u32;
+    let noise: Vec<f32> = (0..3 * SAMPLE_RATE)
+        .map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+        })
+        .collect();
+    let pcm = [silence(1.), noise, silence(1.)].concat();
+    assert!(detect_speech_regions(&pcm, SAMPLE_RATE, &opts).is_empty());
+}
+
+#[test]
+fn windows_without_speech_are_not_decoded() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            use_vad: true,
+            collect_diagnostics: true,
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            ..Default::default()
+        })
+        .unwrap();
+    // Speech in the first window only.
+    let pcm = [silence(10.), sine_pcm(5., 440.), silence(45.)].concat();
+    let output = decoder.run_pcm(&pcm, &RunOptions::default()).unwrap();
+    let windows = &output.diagnostics.as_ref().unwrap().windows;
+    assert_eq!(windows.len(), 2);
+    assert_ne!(windows[0].outcome, WindowOutcome::NoSpeechRegion);
+    assert_eq!(windows[1].outcome, WindowOutcome::NoSpeechRegion);
+    assert!(windows[1].attempts.is_empty());
+    // The gap is still on the timeline.
+    let last = output.segments.last().unwrap();
+    assert!(last.no_speech);
+    assert_eq!((last.start, last.start + last.duration), (30., 60.));
+}
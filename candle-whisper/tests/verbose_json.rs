@@ -0,0 +1,78 @@
+use candle_whisper::{
+    fixtures, formats::verbose_json::to_verbose_json, logic::TranscriptionOutput,
+};
+use serde_json::json;
+
+fn segment(
+    id: usize,
+    start: f64,
+    duration: f64,
+    text: &str,
+    words: serde_json::Value,
+) -> serde_json::Value {
+    let mut segment = fixtures::segment(id, start, duration, text);
+    segment.dr.tokens = vec![1, 2];
+    segment.dr.avg_logprob = -0.25;
+    segment.dr.no_speech_prob = 0.5;
+    segment.words = serde_json::from_value(words).unwrap();
+    serde_json::to_value(segment).unwrap()
+}
+
+#[test]
+fn segments_carry_their_words() {
+    let mut silence = segment(2, 4.5, 0.5, "", json!([]));
+    silence["no_speech"] = json!(true);
+    let output: TranscriptionOutput = serde_json::from_value(json!({
+        "task": "transcribe",
+        "language": "zh",
+        "segments": [
+            segment(0, 0., 2., " Hello world.", json!([
+                { "word": " Hello", "start": 0., "end": 0.5, "probability": 0.75 },
+                { "word": " world.", "start": 0.5, "end": 2.5, "probability": 0.5 },
+            ])),
+            segment(1, 2., 2.5, "你好", json!([
+                { "word": "你", "start": 1.5, "end": 3., "probability": 0.25 },
+                { "word": "好", "start": 3., "end": 4. },
+            ])),
+            silence,
+            segment(3, 5., 1., " Bye.", json!([])),
+        ],
+    }))
+    .unwrap();
+    let verbose = to_verbose_json(&output, 6.);
+    let segment = |id: usize, seek: usize, start: f64, end: f64, text: &str| {
+        json!({
+            "id": id,
+            "seek": seek,
+            "start": start,
+            "end": end,
+            "text": text,
+            "tokens": [1, 2],
+            "temperature": 0.,
+            "avg_logprob": -0.25,
+            "compression_ratio": null,
+            "no_speech_prob": 0.5,
+        })
+    };
+    let mut latin = segment(0, 0, 0., 2., " Hello world.");
+    // Clamped to the segment.
+    latin["words"] = json!([
+        { "word": " Hello", "start": 0., "end": 0.5, "probability": 0.75 },
+        { "word": " world.", "start": 0.5, "end": 2., "probability": 0.5 },
+    ]);
+    let mut cjk = segment(1, 200, 2., 4.5, "你好");
+    cjk["words"] = json!([
+        { "word": "你", "start": 2., "end": 3., "probability": 0.25 },
+        { "word": "好", "start": 3., "end": 4. },
+    ]);
+    assert_eq!(
+        serde_json::to_value(&verbose).unwrap(),
+        json!({
+            "task": "transcribe",
+            "language": "zh",
+            "duration": 6.,
+            "text": " Hello world.你好 Bye.",
+            "segments": [latin, cjk, segment(3, 500, 5., 6., " Bye.")],
+        })
+    );
+}
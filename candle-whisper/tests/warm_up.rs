@@ -0,0 +1,66 @@
+use candle_whisper::{
+    builder::DecoderBuilder,
+    encoder_cache::EncoderCacheOptions,
+    fixtures::{sine_pcm, tiny_model_data},
+    logic::{DecodeOptions, Decoder, RunOptions},
+};
+
+fn options() -> DecodeOptions {
+    // Sampling at a positive temperature makes the tokens depend on the state of the RNG.
+    DecodeOptions {
+        temperatures: vec![1.],
+        condition_on_previous_text: true,
+        no_speech_threshold: None,
+        encoder_cache: Some(EncoderCacheOptions::default()),
+        ..Default::default()
+    }
+}
+
+fn tokens(decoder: &mut Decoder) -> Vec<Vec<u32>> {
+    let output = decoder
+        .run_pcm(&sine_pcm(12., 440.), &RunOptions::default())
+        .unwrap();
+    output
+        .segments
+        .into_iter()
+        .map(|segment| segment.dr.tokens)
+        .collect()
+}
+
+#[test]
+fn transcriptions_are_the_same_after_a_warm_up() {
+    let mut cold = Decoder::load(tiny_model_data()).unwrap();
+    cold.set_options(options()).unwrap();
+    let mut warm = DecoderBuilder::from(tiny_model_data())
+        .warm_up_on_load(true)
+        .build()
+        .unwrap();
+    warm.set_options(options()).unwrap();
+    assert!(cold.warmup_report().is_none());
+    assert!(warm.warmup_report().is_some());
+
+    // Twice, the second run also depending on the state left by the first.
+    for _ in 0..2 {
+        let expected = tokens(&mut cold);
+        assert!(!expected.is_empty());
+        assert_eq!(tokens(&mut warm), expected);
+    }
+}
+
+#[test]
+fn warm_up_runs_once() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder.set_options(options()).unwrap();
+    let first = decoder.warm_up().unwrap();
+    assert!(!first.already_warm);
+    assert!(first.total_ms > 0.);
+    assert!(first.total_ms >= first.encoder_ms + first.decoder_ms);
+    // The silence is not cached.
+    let stats = decoder.encoder_cache_stats();
+    assert_eq!((stats.entries, stats.misses), (0, 0));
+
+    let second = decoder.warm_up().unwrap();
+    assert!(second.already_warm);
+    assert_eq!(second.total_ms, 0.);
+    assert_eq!(decoder.warmup_report(), Some(first));
+}
@@ -0,0 +1,134 @@
+use candle_whisper::{
+    error::WhisperError,
+    fixtures::tiny_model_data,
+    logic::{Decoder, ModelData},
+    weights::{verify_weights, WeightManifest},
+};
+use serde_json::json;
+
+/// Tensor and offset named by a `CorruptWeights` error.
+fn corruption(result: Result<impl std::fmt::Debug, WhisperError>) -> (Option<String>, usize) {
+    match result {
+        Err(WhisperError::CorruptWeights { tensor, offset, .. }) => (tensor, offset),
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+/// Name and data offsets in the buffer of the tensor stored last in `weights`.
+fn last_tensor(weights: &[u8]) -> (String, usize, usize) {
+    let (header_len, metadata) = safetensors::SafeTensors::read_metadata(weights).unwrap();
+    let (name, info) = metadata
+        .tensors()
+        .into_iter()
+        .max_by_key(|(_, info)| info.data_offsets.0)
+        .unwrap();
+    let (start, end) = info.data_offsets;
+    (name, 8 + header_len + start, 8 + header_len + end)
+}
+
+fn load(weights: Vec<u8>, manifest: Option<WeightManifest>) -> Result<Decoder, WhisperError> {
+    Decoder::load(ModelData {
+        weights,
+        manifest,
+        ..tiny_model_data()
+    })
+}
+
+#[test]
+fn bit_flipped_tensor_fails_the_digest_check() {
+    let weights = tiny_model_data().weights;
+    let manifest = WeightManifest::from_weights(&weights).unwrap();
+    assert_eq!(manifest.total_bytes, weights.len());
+    let report = verify_weights(&weights, Some(&manifest)).unwrap();
+    assert!(report.digests_verified);
+    assert_eq!(report.tensor_count, manifest.tensors.len());
+    assert_eq!(report.header_bytes + report.data_bytes, weights.len());
+    assert!(load(weights.clone(), Some(manifest.clone())).is_ok());
+
+    let (name, start, _) = last_tensor(&weights);
+    let mut flipped = weights;
+    flipped[start + 1] ^= 0x10;
+    // The structure is intact, only the digest tells.
+    assert!(!verify_weights(&flipped, None).unwrap().digests_verified);
+    assert_eq!(
+        corruption(verify_weights(&flipped, Some(&manifest))),
+        (Some(name.clone()), start)
+    );
+    let err = load(flipped, Some(manifest)).err().unwrap();
+    assert_eq!(err.code(), "corrupt_weights");
+    assert!(err.to_string().contains(&name), "{err}");
+}
+
+#[test]
+fn truncated_buffer_names_the_last_tensor() {
+    let mut weights = tiny_model_data().weights;
+    let (name, start, end) = last_tensor(&weights);
+    assert_eq!(end, weights.len());
+    weights.pop();
+    assert_eq!(
+        corruption(verify_weights(&weights, None)),
+        (Some(name), start)
+    );
+    // The structural check does not need a manifest.
+    let err = load(weights.clone(), None).err().unwrap();
+    assert_eq!(err.code(), "corrupt_weights");
+    let json = serde_json::to_value(&err).unwrap();
+    assert_eq!(json["offset"], start);
+
+    weights.truncate(4);
+    assert_eq!(corruption(verify_weights(&weights, None)), (None, 0));
+}
+
+/// Safetensors buffer of `header` followed by `data_bytes` zeros.
+fn safetensors(header: serde_json::Value, data_bytes: usize) -> (Vec<u8>, usize) {
+    let header = serde_json::to_vec(&header).unwrap();
+    let mut weights = (header.len() as u64).to_le_bytes().to_vec();
+    weights.extend(header);
+    let data_start = weights.len();
+    weights.resize(data_start + data_bytes, 0);
+    (weights, data_start)
+}
+
+#[test]
+fn header_offsets_must_lie_within_the_buffer() {
+    let tensor = |dtype: &str, len: usize, start: usize| json!({"dtype": dtype, "shape": [len], "data_offsets": [start, start + 4 * len]});
+    let (weights, _) = safetensors(
+        json!({
+            "__metadata__": {"format": "pt"},
+            "a": tensor("F32", 2, 0),
+            "b": tensor("F32", 4, 8),
+        }),
+        24,
+    );
+    let report = verify_weights(&weights, None).unwrap();
+    assert_eq!((report.tensor_count, report.data_bytes), (2, 24));
+
+    // `b` declares 16 bytes from byte 8 of 16 bytes of data.
+    let (weights, data_start) = safetensors(
+        json!({"a": tensor("F32", 2, 0), "b": tensor("F32", 4, 8)}),
+        16,
+    );
+    assert_eq!(
+        corruption(verify_weights(&weights, None)),
+        (Some("b".to_string()), data_start + 8)
+    );
+
+    // `b` starts in the data of `a`.
+    let (weights, data_start) = safetensors(
+        json!({"a": tensor("F32", 2, 0), "b": tensor("F32", 4, 4)}),
+        24,
+    );
+    assert_eq!(
+        corruption(verify_weights(&weights, None)),
+        (Some("b".to_string()), data_start + 4)
+    );
+
+    let (weights, _) = safetensors(json!({"a": tensor("F128", 2, 0)}), 8);
+    assert_eq!(corruption(verify_weights(&weights, None)).0.unwrap(), "a");
+    // The offsets do not hold the shape.
+    let (weights, _) = safetensors(
+        json!({"a": {"dtype": "F32", "shape": [3], "data_offsets": [0, 8]}}),
+        8,
+    );
+    assert_eq!(corruption(verify_weights(&weights, None)).0.unwrap(), "a");
+}
@@ -0,0 +1,191 @@
+use candle_whisper::{
+    alignment::{heuristic_word_timings, TimingSource, WordTiming},
+    logic::SpecialTokens,
+};
+use serde_json::json;
+use tokenizers::Tokenizer;
+
+const SOT: u32 = 5;
+const EOT: u32 = 4;
+const NO_TIMESTAMPS: u32 = 7;
+const HELLO: u32 = 0;
+const WORLD: u32 = 1;
+const FOO: u32 = 2;
+const BAR: u32 = 3;
+
+/// Byte-level tokenizer of ` hello`, ` world`, ` foo` and `bar` with the special tokens.
+fn tokenizer() -> Tokenizer {
+    tokenizer_with(&["Ġhello", "Ġworld", "Ġfoo", "bar"])
+}
+
+/// Byte-level tokenizer of the `text` tokens, in the byte-level alphabet, followed by the
+/// special tokens.
+fn tokenizer_with(text: &[&str]) -> Tokenizer {
+    let special = [
+        "<|endoftext|>",
+        "<|startoftranscript|>",
+        "<|nospeech|>",
+        "<|notimestamps|>",
+    ];
+    let mut vocab = json!({});
+    for (id, token) in text.iter().enumerate() {
+        vocab[token] = json!(id);
+    }
+    let added_tokens: Vec<_> = special
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let id = text.len() + i;
+            vocab[token] = json!(id);
+            json!({
+                "id": id,
+                "content": token,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            })
+        })
+        .collect();
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": { "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true },
+        "post_processor": null,
+        "decoder": { "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true },
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "vocab": vocab,
+            "merges": [],
+        },
+    });
+    Tokenizer::from_bytes(serde_json::to_vec(&tokenizer).unwrap()).unwrap()
+}
+
+/// Timestamp token of `seconds`.
+fn ts(seconds: f64) -> u32 {
+    NO_TIMESTAMPS + 1 + (seconds / 0.02).round() as u32
+}
+
+fn timings(tokens: &[u32], probs: &[f32], duration: f64) -> Vec<WordTiming> {
+    let tokenizer = tokenizer();
+    let special_tokens = SpecialTokens::new(&tokenizer).unwrap();
+    assert_eq!(special_tokens.timestamp_begin, ts(0.));
+    heuristic_word_timings(&tokenizer, &special_tokens, tokens, probs, duration).unwrap()
+}
+
+fn assert_monotone(words: &[WordTiming], duration: f64) {
+    let mut time = 0.;
+    for word in words {
+        assert!(word.start >= time && word.end >= word.start, "{words:?}");
+        time = word.end;
+    }
+    assert!(time <= duration + 1e-9);
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+#[test]
+fn duration_is_distributed_by_probability() {
+    let tokens = [SOT, NO_TIMESTAMPS, HELLO, WORLD, FOO, BAR, EOT];
+    let probs = [1., 1., 0.9, 0.3, 0.9, 0.9, 0.8];
+    let words = timings(&tokens, &probs, 10.);
+    let texts: Vec<&str> = words.iter().map(|w| w.word.as_str()).collect();
+    assert_eq!(texts, [" hello", " world", " foobar"]);
+    assert_monotone(&words, 10.);
+    let bounds: Vec<(f64, f64)> = words.iter().map(|w| (w.start, w.end)).collect();
+    for ((start, end), expected) in bounds.iter().zip([(0., 3.), (3., 4.), (4., 10.)]) {
+        assert!(
+            close(*start, expected.0) && close(*end, expected.1),
+            "{bounds:?}"
+        );
+    }
+    assert!(words
+        .iter()
+        .all(|w| w.timing_source == TimingSource::Heuristic));
+
+    // Without aligned probabilities the tokens share the time evenly.
+    let words = timings(&tokens, &[], 8.);
+    assert!(close(words[0].end, 2.) && close(words[1].end, 4.));
+    assert!(close(words[2].end, 8.));
+}
+
+#[test]
+fn interior_timestamps_anchor_the_words() {
+    let tokens = [
+        SOT,
+        ts(0.),
+        HELLO,
+        WORLD,
+        ts(2.),
+        ts(2.),
+        FOO,
+        ts(5.),
+        ts(6.),
+        FOO,
+        BAR,
+        EOT,
+    ];
+    let probs = vec![1.; tokens.len()];
+    let words = timings(&tokens, &probs, 30.);
+    assert_monotone(&words, 30.);
+    assert_eq!(words.len(), 4);
+    assert!(close(words[0].start, 0.) && close(words[1].end, 2.));
+    assert_eq!(words[1].timing_source, TimingSource::Heuristic);
+    // Alone between its timestamps.
+    assert!(close(words[2].start, 2.) && close(words[2].end, 5.));
+    assert_eq!(words[2].timing_source, TimingSource::TimestampTokens);
+    // After the last timestamp the word extends to the end of the window.
+    assert!(close(words[3].start, 6.) && close(words[3].end, 30.));
+    assert_eq!(words[3].timing_source, TimingSource::Heuristic);
+}
+
+#[test]
+fn timestamps_going_backwards_or_outside_are_clamped() {
+    let tokens = [ts(3.), HELLO, ts(1.), WORLD, ts(25.), FOO, EOT];
+    let words = timings(&tokens, &[], 10.);
+    assert_monotone(&words, 10.);
+    assert_eq!(words.len(), 3);
+    assert!(close(words[0].start, 3.) && close(words[0].end, 3.));
+    assert!(close(words[1].start, 3.) && close(words[1].end, 10.));
+    assert!(close(words[2].start, 10.) && close(words[2].end, 10.));
+    assert!(timings(&[SOT, NO_TIMESTAMPS, EOT], &[], 10.).is_empty());
+}
+
+#[test]
+fn cjk_characters_are_words_with_their_mean_probability() {
+    // ` hello`, the two tokens of `你`, `好` and `世界` in one token.
+    let tokenizer = tokenizer_with(&["Ġhello", "ä½", "ł", "å¥½", "ä¸ĸçķĮ"]);
+    let special_tokens = SpecialTokens::new(&tokenizer).unwrap();
+    let tokens = [0, 1, 2, 3, 4, special_tokens.eot];
+    let probs = [0.9, 0.2, 0.4, 0.8, 0.6, 1.];
+    let words = heuristic_word_timings(&tokenizer, &special_tokens, &tokens, &probs, 5.).unwrap();
+    let words: Vec<(&str, f32)> = words
+        .iter()
+        .map(|word| (word.word.as_str(), word.probability.unwrap()))
+        .collect();
+    assert_eq!(words.len(), 4);
+    for ((word, probability), (expected, expected_probability)) in
+        words
+            .into_iter()
+            .zip([(" hello", 0.9), ("你", 0.3), ("好", 0.8), ("世界", 0.6)])
+    {
+        assert_eq!(word, expected);
+        assert!((probability - expected_probability).abs() < 1e-6);
+    }
+
+    // Without aligned probabilities.
+    let words = heuristic_word_timings(&tokenizer, &special_tokens, &tokens, &[], 5.).unwrap();
+    assert!(words.iter().all(|word| word.probability.is_none()));
+}
@@ -0,0 +1,83 @@
+use candle_whisper::{
+    fixtures::{sine_pcm, tiny_model_data, TEXT_TOKENS},
+    logic::{DecodeOptions, Decoder, LogitsContext, RunOptions},
+    yielder::Yielder,
+};
+use std::{future::Future, pin::Pin};
+
+const HELLO: u32 = 1;
+const EOT: u32 = TEXT_TOKENS.len() as u32;
+
+/// Yielder counting its awaits, each suspending the transcription once.
+#[derive(Default)]
+struct CountingYielder {
+    yields: usize,
+}
+
+impl Yielder for CountingYielder {
+    fn yield_now(&mut self) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+        self.yields += 1;
+        Box::pin(tokio::task::yield_now())
+    }
+}
+
+/// Decoder sampling `tokens` times `hello` then the end of text in every window.
+fn decoder(tokens: usize) -> Decoder {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    decoder
+        .set_options(DecodeOptions {
+            temperatures: vec![0.],
+            no_speech_threshold: None,
+            logprob_threshold: None,
+            compression_ratio_threshold: None,
+            yield_interval_tokens: 3,
+            ..Default::default()
+        })
+        .unwrap();
+    decoder.set_logits_processor(Some(Box::new(
+        move |logits: &mut [f32], context: &LogitsContext| {
+            let forced = if context.step < tokens { HELLO } else { EOT };
+            logits.fill(f32::NEG_INFINITY);
+            logits[forced as usize] = 0.;
+        },
+    )));
+    decoder
+}
+
+#[tokio::test]
+async fn yields_every_few_decoder_steps() {
+    let pcm = sine_pcm(45., 440.);
+    let expected = decoder(7).run_pcm(&pcm, &RunOptions::default()).unwrap();
+    assert_eq!(expected.segments.len(), 2);
+
+    let mut yielder = CountingYielder::default();
+    let output = decoder(7)
+        .run_pcm_async(&pcm, &RunOptions::default(), &mut yielder)
+        .await
+        .unwrap();
+    // Once before every window and before the end of the audio is found, then before the
+    // steps 3 and 6 of the 8 steps of every window.
+    assert_eq!(yielder.yields, 3 + 2 * 2);
+    let texts = |output: &candle_whisper::logic::TranscriptionOutput| -> Vec<String> {
+        output.segments.iter().map(|s| s.dr.text.clone()).collect()
+    };
+    assert_eq!(texts(&output), texts(&expected));
+
+    // The windows of a single step only yield before them.
+    let mut yielder = CountingYielder::default();
+    decoder(0)
+        .run_pcm_async(&pcm, &RunOptions::default(), &mut yielder)
+        .await
+        .unwrap();
+    assert_eq!(yielder.yields, 3);
+}
+
+#[test]
+fn yield_interval_must_be_positive() {
+    let mut decoder = Decoder::load(tiny_model_data()).unwrap();
+    let options = DecodeOptions {
+        yield_interval_tokens: 0,
+        ..Default::default()
+    };
+    assert!(decoder.set_options(options).is_err());
+}
@@ -35,9 +35,16 @@ self.addEventListener("message", async function (event) {
   const audio = new Uint8Array(
     await (await fetch(event.data.audioSrc)).arrayBuffer()
   );
-  const data = instance.decode(audio);
-  self.postMessage({
-    status: "complete",
-    output: JSON.parse(data),
-  });
+  try {
+    const data = instance.decode(audio);
+    self.postMessage({
+      status: "complete",
+      output: JSON.parse(data),
+    });
+  } catch (e) {
+    self.postMessage({
+      status: "error",
+      error: JSON.parse(e.message),
+    });
+  }
 });
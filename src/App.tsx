@@ -28,9 +28,9 @@ function App() {
         if (!audioSrc) return;
         worker.current.postMessage({ audioSrc })
     }
-    const onMessage = ({ data: {status, output} }: MessageEvent<{status: string; output: {dr:{text: string}}[]}>) => {
+    const onMessage = ({ data: {status, output} }: MessageEvent<{status: string; output: {task: string; language: string | null; segments: {dr:{text: string}}[]}}>) => {
         if (status === "complete") {
-            const text = output.map(_ => _.dr.text).join(" ");
+            const text = output.segments.map(_ => _.dr.text).join(" ");
             setText(text);
         }
     }